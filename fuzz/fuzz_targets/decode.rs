@@ -0,0 +1,33 @@
+// Decodes arbitrary words. The decoder must not panic, and words it rejects have to raise an
+// illegal-instruction exception when executed, so the decoder and the cpu agree on what's valid.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ruscv::cpu::Cpu;
+use ruscv::disasm;
+
+const TRAP_HANDLER: u32 = 0x100;
+const ILLEGAL_INSTRUCTION: u32 = 2;
+
+fuzz_target!(|data: &[u8]| {
+    for (_, raw_inst, inst, text) in disasm::iter(data, 0) {
+        // an all-zero word marks the end of the program instead
+        if inst.is_ok() || raw_inst == 0 {
+            continue;
+        }
+        let mut cpu = Cpu::new(false);
+        cpu.set_quiet();
+        cpu.disable_idle_sleep();
+        cpu.load(raw_inst.to_le_bytes().to_vec());
+        // with a trap handler the exception is taken instead of stopping the emulation
+        cpu.csrs.mtvec = TRAP_HANDLER;
+        let result = cpu.step();
+        assert!(
+            matches!(result, Ok(None))
+                && cpu.csrs.mcause == ILLEGAL_INSTRUCTION
+                && cpu.csrs.mtval == raw_inst,
+            "{text} was rejected by the decoder but executed as {result:?} (mcause {})",
+            cpu.csrs.mcause
+        );
+    }
+});
@@ -0,0 +1,20 @@
+// Runs arbitrary bytes as a program on the default machine. Whatever the program does, the
+// emulator must neither panic nor hang, every run stops at the cycle limit at the latest.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ruscv::cpu::Cpu;
+
+// enough to get through loops and trap handlers, few enough for thousands of runs per second
+const CYCLE_LIMIT: usize = 100_000;
+
+fuzz_target!(|program: &[u8]| {
+    let mut cpu = Cpu::new(false);
+    cpu.set_quiet();
+    cpu.set_cycle_limit(CYCLE_LIMIT);
+    // wfi only skips time on the device clocks, sleeping would turn a short program into a hang
+    cpu.disable_idle_sleep();
+    // the console's output is dropped, use -close_fd_mask=1 to silence write syscalls as well
+    let _output = cpu.mem.capture_output();
+    let _ = cpu.run(program.to_vec());
+});
@@ -0,0 +1,145 @@
+// Log of the loads and stores the program makes to a few regions of interest, e.g. the
+// descriptors and buffers a driver shares with a dma engine. The memory records the accesses to
+// the regions and the cpu writes them with the cycle, hart and pc of the accessing instruction.
+use crate::memory::Size;
+
+use std::io::{self, Write};
+use std::ops::Range;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    // a header and one line per access: cycle,hart,pc,access,address,size,value
+    Csv,
+    // one json object per line
+    Json,
+}
+
+impl LogFormat {
+    // json for .json and .jsonl files, csv otherwise
+    pub fn from_path(path: &str) -> Self {
+        if path.ends_with(".json") || path.ends_with(".jsonl") {
+            LogFormat::Json
+        } else {
+            LogFormat::Csv
+        }
+    }
+}
+
+// a load or store of the program, the value is zero-extended from the accessed size
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LoggedAccess {
+    pub address: u32,
+    pub size: Size,
+    pub write: bool,
+    pub value: u32,
+}
+
+// The regions accessed are recorded until the cpu takes them.
+pub struct AccessRecorder {
+    regions: Vec<Range<u32>>,
+    accesses: Vec<LoggedAccess>,
+}
+
+impl AccessRecorder {
+    pub fn new(regions: Vec<Range<u32>>) -> Self {
+        AccessRecorder {
+            regions,
+            accesses: Vec::new(),
+        }
+    }
+
+    // records the access if any of its bytes is in a region
+    pub fn record(&mut self, address: u32, size: Size, write: bool, value: u32) {
+        let end = address as u64 + size as u64;
+        let overlaps =
+            |range: &Range<u32>| (address as u64) < range.end as u64 && end > range.start as u64;
+        if self.regions.iter().any(overlaps) {
+            let mask = u32::MAX >> (32 - 8 * size as u32);
+            self.accesses.push(LoggedAccess {
+                address,
+                size,
+                write,
+                value: value & mask,
+            });
+        }
+    }
+
+    pub fn drain(&mut self) -> impl Iterator<Item = LoggedAccess> + '_ {
+        self.accesses.drain(..)
+    }
+}
+
+pub struct AccessLog {
+    format: LogFormat,
+    out: Box<dyn Write>,
+}
+
+impl AccessLog {
+    pub fn new(mut out: Box<dyn Write>, format: LogFormat) -> io::Result<Self> {
+        if format == LogFormat::Csv {
+            writeln!(out, "cycle,hart,pc,access,address,size,value")?;
+        }
+        Ok(AccessLog { format, out })
+    }
+
+    pub fn write(
+        &mut self,
+        cycle: usize,
+        hart: usize,
+        pc: u32,
+        access: &LoggedAccess,
+    ) -> io::Result<()> {
+        let kind = if access.write { "w" } else { "r" };
+        let (address, size, value) = (access.address, access.size as u32, access.value);
+        match self.format {
+            LogFormat::Csv => writeln!(
+                self.out,
+                "{cycle},{hart},{pc:#x},{kind},{address:#x},{size},{value:#x}"
+            ),
+            LogFormat::Json => writeln!(
+                self.out,
+                r#"{{"cycle":{cycle},"hart":{hart},"pc":{pc},"access":"{kind}","address":{address},"size":{size},"value":{value}}}"#
+            ),
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::Capture;
+
+    #[test]
+    fn regions_and_formats() {
+        let mut recorder = AccessRecorder::new(vec![0x100..0x108, 0x200..0x204]);
+        recorder.record(0xfc, Size::Word, true, 1);
+        recorder.record(0xfe, Size::Word, true, 0x1234_5678);
+        recorder.record(0x107, Size::Byte, false, 0xffff_ff80);
+        recorder.record(0x108, Size::Byte, false, 0);
+        recorder.record(0x202, Size::HalfWord, true, 0xabcd);
+        let accesses: Vec<_> = recorder.drain().collect();
+        assert_eq!(accesses.len(), 3);
+        assert_eq!(accesses[1].value, 0x80);
+
+        let csv = Capture::new();
+        let mut log = AccessLog::new(Box::new(csv.clone()), LogFormat::Csv).unwrap();
+        log.write(7, 0, 0x40, &accesses[0]).unwrap();
+        assert_eq!(
+            csv.text(),
+            "cycle,hart,pc,access,address,size,value\n7,0,0x40,w,0xfe,4,0x12345678\n"
+        );
+        let json = Capture::new();
+        let mut log = AccessLog::new(Box::new(json.clone()), LogFormat::Json).unwrap();
+        log.write(8, 1, 0x44, &accesses[1]).unwrap();
+        assert_eq!(
+            json.text(),
+            "{\"cycle\":8,\"hart\":1,\"pc\":68,\"access\":\"r\",\"address\":263,\"size\":1,\"value\":128}\n"
+        );
+        assert_eq!(LogFormat::from_path("dma.jsonl"), LogFormat::Json);
+        assert_eq!(LogFormat::from_path("dma.csv"), LogFormat::Csv);
+    }
+}
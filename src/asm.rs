@@ -0,0 +1,1307 @@
+// A two-pass assembler for the instructions the emulator implements, in the syntax of the gnu
+// assembler, so that small programs run without a cross-compiler. The first pass assigns addresses
+// to the labels, the second encodes the statements. The output is a flat image, sections are laid
+// out in the order they appear in the source.
+use crate::csr::*;
+use crate::error::Error;
+use crate::inst_format::*;
+use crate::regs::Reg;
+use crate::vector::{VL, VLENB, VTYPE};
+
+use std::collections::HashMap;
+
+const OP: u32 = 0b0110011;
+const OP_IMM: u32 = 0b0010011;
+const LOAD: u32 = 0b0000011;
+const STORE: u32 = 0b0100011;
+const BRANCH: u32 = 0b1100011;
+const JAL: u32 = 0b1101111;
+const JALR: u32 = 0b1100111;
+const LUI: u32 = 0b0110111;
+const AUIPC: u32 = 0b0010111;
+const SYSTEM: u32 = 0b1110011;
+const AMO: u32 = 0b0101111;
+const MISC_MEM: u32 = 0b0001111;
+
+// addi x0, x0, 0, also pads code
+const NOP: u32 = 0x0000_0013;
+
+// name, funct3 and funct7
+#[rustfmt::skip]
+const R_OPS: &[(&str, usize, usize)] = &[
+    ("add", 0, 0x00), ("sub", 0, 0x20), ("sll", 1, 0x00), ("slt", 2, 0x00), ("sltu", 3, 0x00),
+    ("xor", 4, 0x00), ("srl", 5, 0x00), ("sra", 5, 0x20), ("or", 6, 0x00), ("and", 7, 0x00),
+    ("mul", 0, 0x01), ("mulh", 1, 0x01), ("mulhsu", 2, 0x01), ("mulhu", 3, 0x01),
+    ("div", 4, 0x01), ("divu", 5, 0x01), ("rem", 6, 0x01), ("remu", 7, 0x01),
+    ("czero.eqz", 5, 0x07), ("czero.nez", 7, 0x07),
+    ("andn", 7, 0x20), ("orn", 6, 0x20), ("xnor", 4, 0x20), ("rol", 1, 0x30), ("ror", 5, 0x30),
+    ("pack", 4, 0x04), ("packh", 7, 0x04), ("xperm4", 2, 0x14), ("xperm8", 4, 0x14),
+];
+
+#[rustfmt::skip]
+const I_OPS: &[(&str, usize)] = &[
+    ("addi", 0), ("slti", 2), ("sltiu", 3), ("xori", 4), ("ori", 6), ("andi", 7),
+];
+
+// name, funct3 and the bits of the immediate above the shift amount
+#[rustfmt::skip]
+const SHIFT_OPS: &[(&str, usize, u32)] = &[
+    ("slli", 1, 0x00), ("srli", 5, 0x00), ("srai", 5, 0x20), ("rori", 5, 0x30),
+];
+
+// bit permutations, encoded as shifts with a fixed immediate
+#[rustfmt::skip]
+const UNARY_OPS: &[(&str, usize, u32)] = &[
+    ("brev8", 5, 0x687), ("rev8", 5, 0x698), ("zip", 1, 0x08f), ("unzip", 5, 0x08f),
+];
+
+const LOADS: &[(&str, usize)] = &[("lb", 0), ("lh", 1), ("lw", 2), ("lbu", 4), ("lhu", 5)];
+
+const STORES: &[(&str, usize)] = &[("sb", 0), ("sh", 1), ("sw", 2)];
+
+#[rustfmt::skip]
+const BRANCHES: &[(&str, usize)] = &[
+    ("beq", 0), ("bne", 1), ("blt", 4), ("bge", 5), ("bltu", 6), ("bgeu", 7),
+];
+
+#[rustfmt::skip]
+const CSR_OPS: &[(&str, usize)] = &[
+    ("csrrw", 1), ("csrrs", 2), ("csrrc", 3), ("csrrwi", 5), ("csrrsi", 6), ("csrrci", 7),
+];
+
+// name and funct5, the ordering suffixes set the lowest bits of funct7
+#[rustfmt::skip]
+const AMO_OPS: &[(&str, usize)] = &[
+    ("lr.w", 0b00010), ("sc.w", 0b00011), ("amoswap.w", 0b00001), ("amoadd.w", 0b00000),
+    ("amoxor.w", 0b00100), ("amoand.w", 0b01100), ("amoor.w", 0b01000), ("amomin.w", 0b10000),
+    ("amomax.w", 0b10100), ("amominu.w", 0b11000), ("amomaxu.w", 0b11100),
+];
+
+// name and the lower bits of funct7, the byte select goes into the upper two
+#[rustfmt::skip]
+const AES_OPS: &[(&str, usize)] = &[
+    ("aes32esi", 0b10001), ("aes32esmi", 0b10011), ("aes32dsi", 0b10101), ("aes32dsmi", 0b10111),
+];
+
+// instructions without operands
+#[rustfmt::skip]
+const FIXED: &[(&str, u32)] = &[
+    ("ecall", 0x0000_0073), ("ebreak", 0x0010_0073), ("mret", 0x3020_0073), ("wfi", 0x1050_0073),
+    ("sret", 0x1020_0073), ("sfence.vma", 0x1200_0073),
+    ("fence.i", 0x0000_100f), ("fence.tso", 0x8330_000f), ("pause", 0x0100_000f), ("nop", NOP),
+];
+
+#[rustfmt::skip]
+const CSR_NAMES: &[(&str, u16)] = &[
+    ("mstatus", MSTATUS), ("misa", MISA), ("mie", MIE), ("mtvec", MTVEC),
+    ("mstatush", MSTATUSH), ("mcountinhibit", MCOUNTINHIBIT), ("mscratch", MSCRATCH),
+    ("mepc", MEPC), ("mcause", MCAUSE), ("mtval", MTVAL), ("mip", MIP),
+    ("mvendorid", MVENDORID), ("marchid", MARCHID), ("mimpid", MIMPID), ("mhartid", MHARTID),
+    ("mcycle", MCYCLE), ("minstret", MINSTRET), ("mcycleh", MCYCLEH), ("minstreth", MINSTRETH),
+    ("medeleg", MEDELEG), ("mideleg", MIDELEG), ("mcounteren", MCOUNTEREN),
+    ("menvcfg", MENVCFG), ("menvcfgh", MENVCFGH), ("pmpcfg0", PMPCFG0), ("pmpaddr0", PMPADDR0),
+    ("sstatus", SSTATUS), ("sie", SIE), ("stvec", STVEC), ("scounteren", SCOUNTEREN),
+    ("sscratch", SSCRATCH), ("sepc", SEPC), ("scause", SCAUSE), ("stval", STVAL), ("sip", SIP),
+    ("stimecmp", STIMECMP), ("stimecmph", STIMECMPH), ("satp", SATP),
+    ("cycle", CYCLE), ("time", TIME), ("instret", INSTRET),
+    ("cycleh", CYCLEH), ("timeh", TIMEH), ("instreth", INSTRETH),
+    ("vl", VL), ("vtype", VTYPE), ("vlenb", VLENB),
+];
+
+// number of the csr with the given name, also used by the debugger expressions
+pub(crate) fn csr_number(name: &str) -> Option<u16> {
+    CSR_NAMES
+        .iter()
+        .find(|(csr, _)| *csr == name)
+        .map(|&(_, number)| number)
+}
+
+// directives that don't emit anything
+const IGNORED: &[&str] = &[
+    ".globl",
+    ".global",
+    ".local",
+    ".weak",
+    ".type",
+    ".size",
+    ".file",
+    ".ident",
+    ".option",
+    ".attribute",
+];
+
+pub struct Assembly {
+    // the image to be loaded at the base address
+    pub bytes: Vec<u8>,
+    // addresses of the labels and values of the .equ symbols
+    pub symbols: HashMap<String, u32>,
+}
+
+// Assembles the source into an image that is loaded at `base`.
+pub fn assemble(source: &str, base: u32) -> Result<Assembly, Error> {
+    let mut statements = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        let statement = parse_line(line).map_err(|e| Error::Assembly(i + 1, e))?;
+        statements.push((i + 1, statement));
+    }
+    let mut asm = Assembler {
+        symbols: HashMap::new(),
+        locals: HashMap::new(),
+        code: true,
+    };
+
+    // the first pass only needs the size of each statement
+    let mut layout = Vec::with_capacity(statements.len());
+    let mut address = base;
+    for (index, (line, statement)) in statements.iter().enumerate() {
+        let size = asm
+            .define(statement, index, address)
+            .map_err(|e| Error::Assembly(*line, e))?;
+        layout.push((address, size));
+        address = address.checked_add(size).ok_or(Error::Assembly(
+            *line,
+            "program exceeds the address space".into(),
+        ))?;
+    }
+
+    asm.code = true;
+    let mut bytes = Vec::new();
+    for (index, ((line, statement), &(address, size))) in statements.iter().zip(&layout).enumerate()
+    {
+        let data = asm
+            .emit(statement, index, address, size)
+            .map_err(|e| Error::Assembly(*line, e))?;
+        debug_assert_eq!(data.len(), size as usize);
+        bytes.extend(data);
+    }
+    let symbols = asm
+        .symbols
+        .into_iter()
+        .map(|(name, value)| (name, value.value as u32))
+        .collect();
+    Ok(Assembly { bytes, symbols })
+}
+
+// the labels defined on a line and the instruction or directive with its operands
+#[derive(Default)]
+struct Statement {
+    labels: Vec<String>,
+    mnemonic: String,
+    operands: Vec<String>,
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '$')
+}
+
+// removes a comment, which starts at a # outside of string and character literals
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some(_), _) if escaped => escaped = false,
+            (Some(_), '\\') => escaped = true,
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => (),
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return &line[..i],
+            (None, _) => (),
+        }
+    }
+    line
+}
+
+// splits the operands at commas outside of parentheses and literals
+fn split_operands(operands: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let (mut depth, mut quote, mut escaped) = (0, None, false);
+    for c in operands.chars() {
+        match (quote, c) {
+            (Some(_), _) if escaped => escaped = false,
+            (Some(_), '\\') => escaped = true,
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => (),
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                parts.push(current.trim().to_string());
+                current.clear();
+                continue;
+            }
+            (None, _) => (),
+        }
+        current.push(c);
+    }
+    if !current.trim().is_empty() || !parts.is_empty() {
+        parts.push(current.trim().to_string());
+    }
+    parts
+}
+
+fn parse_line(line: &str) -> Result<Statement, String> {
+    let mut rest = strip_comment(line).trim();
+    let mut statement = Statement::default();
+    while let Some((label, after)) = rest.split_once(':') {
+        if label.is_empty() || !label.chars().all(is_symbol_char) {
+            break;
+        }
+        statement.labels.push(label.to_string());
+        rest = after.trim_start();
+    }
+    if rest.is_empty() {
+        return Ok(statement);
+    }
+    let (mnemonic, operands) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    statement.mnemonic = mnemonic.to_ascii_lowercase();
+    statement.operands = split_operands(operands);
+    if statement.operands.iter().any(String::is_empty) {
+        return Err("empty operand".to_string());
+    }
+    Ok(statement)
+}
+
+// the contents of a string literal with its escape sequences resolved
+fn parse_string(literal: &str) -> Result<Vec<u8>, String> {
+    let inner = literal
+        .strip_prefix('"')
+        .and_then(|literal| literal.strip_suffix('"'))
+        .ok_or_else(|| format!("expected a string, found '{literal}'"))?;
+    let mut bytes = Vec::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        bytes.push(match chars.next() {
+            Some('n') => b'\n',
+            Some('t') => b'\t',
+            Some('r') => b'\r',
+            Some('0') => 0,
+            Some('\\') => b'\\',
+            Some('"') => b'"',
+            Some('\'') => b'\'',
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                u8::from_str_radix(&hex, 16).map_err(|_| format!("invalid escape '\\x{hex}'"))?
+            }
+            other => return Err(format!("invalid escape in {literal}: {other:?}")),
+        });
+    }
+    Ok(bytes)
+}
+
+// Value of an expression. Addresses of labels are only known relative to where the program is
+// placed, branches to them are encoded as offsets while plain numbers are offsets already.
+#[derive(Clone, Copy)]
+struct Value {
+    value: i64,
+    address: bool,
+}
+
+impl Value {
+    fn constant(value: i64) -> Self {
+        Value {
+            value,
+            address: false,
+        }
+    }
+}
+
+// the sign-extended lower 12 bits and the upper 20 bits, which add up to the value
+fn split_hi_lo(value: i64) -> (u32, i64) {
+    let lo = ((value & 0xfff) ^ 0x800) - 0x800;
+    (((value - lo) >> 12) as u32 & 0xf_ffff, lo)
+}
+
+struct Assembler {
+    // labels and .equ symbols defined so far
+    symbols: HashMap<String, Value>,
+    // numeric labels like `1:` by name, with the index of the defining statement and the address
+    locals: HashMap<String, Vec<(usize, u32)>>,
+    // whether the current section holds code, which is aligned with nops
+    code: bool,
+}
+
+impl Assembler {
+    // first pass: defines the statement's labels and symbols and returns its size
+    fn define(&mut self, statement: &Statement, index: usize, address: u32) -> Result<u32, String> {
+        for label in &statement.labels {
+            if label.chars().all(|c| c.is_ascii_digit()) {
+                let locals = self.locals.entry(label.clone()).or_default();
+                locals.push((index, address));
+            } else if self.symbols.contains_key(label) {
+                return Err(format!("symbol '{label}' is already defined"));
+            } else {
+                let value = Value {
+                    value: address as i64,
+                    address: true,
+                };
+                self.symbols.insert(label.clone(), value);
+            }
+        }
+        let ops = &statement.operands;
+        let mnemonic = statement.mnemonic.as_str();
+        if mnemonic == ".equ" || mnemonic == ".set" {
+            let [name, expr] = &ops[..] else {
+                return Err(format!("{mnemonic} takes a name and a value"));
+            };
+            let value = self.eval(expr, index, address)?;
+            self.symbols.insert(name.clone(), value);
+            return Ok(0);
+        }
+        self.section(mnemonic, ops);
+        let size = match mnemonic {
+            "" => 0,
+            ".byte" => ops.len() as u32,
+            ".half" | ".short" | ".2byte" => 2 * ops.len() as u32,
+            ".word" | ".long" | ".4byte" => 4 * ops.len() as u32,
+            ".ascii" | ".asciz" | ".string" => {
+                let terminator = (mnemonic != ".ascii") as usize;
+                let mut size = 0;
+                for literal in ops {
+                    size += parse_string(literal)?.len() + terminator;
+                }
+                size as u32
+            }
+            ".zero" | ".space" | ".skip" => {
+                let size = ops.first().ok_or("missing size")?;
+                let size = self.eval(size, index, address)?.value;
+                u32::try_from(size).map_err(|_| format!("invalid size {size}"))?
+            }
+            ".align" | ".p2align" | ".balign" => self.alignment(mnemonic, ops, index, address)?,
+            "li" => {
+                // a single instruction unless the value needs both halves, unknown values (like
+                // labels defined later) always get two
+                let value = ops.get(1).map(|expr| self.eval(expr, index, address));
+                match value {
+                    Some(Ok(value)) if Self::short_li(value.value) => 4,
+                    _ => 8,
+                }
+            }
+            "la" | "lla" | "call" | "tail" => 8,
+            directive if directive.starts_with('.') => {
+                if IGNORED.contains(&directive) || self.is_section(directive) {
+                    0
+                } else {
+                    return Err(format!("unknown directive '{directive}'"));
+                }
+            }
+            _ => 4,
+        };
+        Ok(size)
+    }
+
+    // second pass: the bytes of the statement
+    fn emit(
+        &mut self,
+        statement: &Statement,
+        index: usize,
+        address: u32,
+        size: u32,
+    ) -> Result<Vec<u8>, String> {
+        let ops = &statement.operands;
+        let mnemonic = statement.mnemonic.as_str();
+        self.section(mnemonic, ops);
+        let mut bytes = Vec::with_capacity(size as usize);
+        match mnemonic {
+            ".byte" | ".half" | ".short" | ".2byte" | ".word" | ".long" | ".4byte" => {
+                let width = size as usize / ops.len().max(1);
+                for expr in ops {
+                    let value = self.eval(expr, index, address)?.value;
+                    let bits = 8 * width as u32;
+                    if bits < 64 && (value >= 1 << bits || value < -(1 << (bits - 1))) {
+                        return Err(format!("{value} doesn't fit into {width} bytes"));
+                    }
+                    bytes.extend_from_slice(&(value as u32).to_le_bytes()[..width]);
+                }
+            }
+            ".ascii" | ".asciz" | ".string" => {
+                for literal in ops {
+                    bytes.extend(parse_string(literal)?);
+                    if mnemonic != ".ascii" {
+                        bytes.push(0);
+                    }
+                }
+            }
+            ".zero" | ".space" | ".skip" => {
+                let fill = match ops.get(1) {
+                    Some(fill) => self.eval(fill, index, address)?.value as u8,
+                    None => 0,
+                };
+                bytes.resize(size as usize, fill);
+            }
+            ".align" | ".p2align" | ".balign" => {
+                // code is padded with nops where they fit
+                if self.code && address.is_multiple_of(4) && size.is_multiple_of(4) {
+                    for _ in 0..size / 4 {
+                        bytes.extend_from_slice(&NOP.to_le_bytes());
+                    }
+                } else {
+                    bytes.resize(size as usize, 0);
+                }
+            }
+            directive if directive.starts_with('.') || directive.is_empty() => (),
+            _ => {
+                if !address.is_multiple_of(4) {
+                    return Err(format!("instruction at unaligned address {address:#x}"));
+                }
+                let context = Context {
+                    asm: self,
+                    index,
+                    pc: address,
+                };
+                for word in context.encode(mnemonic, ops, size)? {
+                    bytes.extend_from_slice(&word.to_le_bytes());
+                }
+            }
+        }
+        Ok(bytes)
+    }
+
+    // padding to the alignment, .align is a power of two like .p2align on risc-v
+    fn alignment(
+        &self,
+        mnemonic: &str,
+        ops: &[String],
+        index: usize,
+        address: u32,
+    ) -> Result<u32, String> {
+        let expr = ops.first().ok_or("missing alignment")?;
+        let value = self.eval(expr, index, address)?.value;
+        let alignment = match mnemonic {
+            ".balign" if value > 0 && (value as u64).is_power_of_two() => value as u64,
+            ".balign" => return Err(format!("alignment {value} isn't a power of two")),
+            _ if (0..32).contains(&value) => 1 << value,
+            _ => return Err(format!("invalid alignment {value}")),
+        };
+        Ok(((address as u64).next_multiple_of(alignment) - address as u64) as u32)
+    }
+
+    fn is_section(&self, directive: &str) -> bool {
+        matches!(
+            directive,
+            ".text" | ".data" | ".bss" | ".rodata" | ".section"
+        )
+    }
+
+    // tracks whether the statement switched to a code or data section
+    fn section(&mut self, mnemonic: &str, ops: &[String]) {
+        match mnemonic {
+            ".text" => self.code = true,
+            ".data" | ".bss" | ".rodata" => self.code = false,
+            ".section" => {
+                let name = ops.first().map_or("", String::as_str);
+                self.code = name.starts_with(".text");
+            }
+            _ => (),
+        }
+    }
+
+    // whether li needs only one instruction for the value
+    fn short_li(value: i64) -> bool {
+        (-2048..2048).contains(&value) || value & 0xfff == 0
+    }
+
+    fn eval(&self, expr: &str, index: usize, pc: u32) -> Result<Value, String> {
+        let mut parser = Parser {
+            asm: self,
+            text: expr,
+            pos: 0,
+            index,
+            pc,
+        };
+        let value = parser.expr(0)?;
+        parser.skip_whitespace();
+        if parser.pos != expr.len() {
+            return Err(format!("unexpected '{}' in '{expr}'", &expr[parser.pos..]));
+        }
+        Ok(value)
+    }
+
+    fn symbol(&self, name: &str, index: usize) -> Result<Value, String> {
+        // numeric labels are referenced as 1b (the closest one before) or 1f (after)
+        let local = name
+            .strip_suffix(['b', 'f'])
+            .filter(|digits| !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()));
+        if let Some(digits) = local {
+            let definitions = self.locals.get(digits).map_or(&[][..], Vec::as_slice);
+            let found = if name.ends_with('b') {
+                definitions.iter().rev().find(|(i, _)| *i <= index)
+            } else {
+                definitions.iter().find(|(i, _)| *i > index)
+            };
+            return match found {
+                Some(&(_, address)) => Ok(Value {
+                    value: address as i64,
+                    address: true,
+                }),
+                None => Err(format!("undefined label '{name}'")),
+            };
+        }
+        self.symbols
+            .get(name)
+            .copied()
+            .ok_or_else(|| format!("undefined symbol '{name}'"))
+    }
+}
+
+// binary operators by precedence, higher binds tighter
+const OPERATORS: &[(&str, u8)] = &[
+    ("<<", 4),
+    (">>", 4),
+    ("|", 1),
+    ("^", 2),
+    ("&", 3),
+    ("+", 5),
+    ("-", 5),
+    ("*", 6),
+    ("/", 6),
+    ("%", 6),
+];
+
+// precedence climbing over the expression syntax of the gnu assembler
+struct Parser<'a> {
+    asm: &'a Assembler,
+    text: &'a str,
+    pos: usize,
+    // statement the expression belongs to, for numeric labels
+    index: usize,
+    // address of the statement, the value of `.`
+    pc: u32,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        let found = self.rest().starts_with(token);
+        if found {
+            self.pos += token.len();
+        }
+        found
+    }
+
+    fn expr(&mut self, min_precedence: u8) -> Result<Value, String> {
+        let mut lhs = self.unary()?;
+        loop {
+            self.skip_whitespace();
+            let rest = self.rest();
+            let Some(&(op, precedence)) = OPERATORS
+                .iter()
+                .find(|(op, precedence)| rest.starts_with(op) && *precedence > min_precedence)
+            else {
+                return Ok(lhs);
+            };
+            self.pos += op.len();
+            let rhs = self.expr(precedence)?;
+            lhs = Self::apply(op, lhs, rhs)?;
+        }
+    }
+
+    // addresses can only be offset, or subtracted from each other to get a distance
+    fn apply(op: &str, lhs: Value, rhs: Value) -> Result<Value, String> {
+        let (a, b) = (lhs.value, rhs.value);
+        let address = match op {
+            "+" if !(lhs.address && rhs.address) => lhs.address || rhs.address,
+            "-" if !rhs.address || lhs.address => lhs.address && !rhs.address,
+            _ if !lhs.address && !rhs.address => false,
+            _ => return Err(format!("'{op}' can't be applied to an address")),
+        };
+        let value = match op {
+            "+" => a.wrapping_add(b),
+            "-" => a.wrapping_sub(b),
+            "*" => a.wrapping_mul(b),
+            "/" | "%" if b == 0 => return Err("division by zero".to_string()),
+            "/" => a / b,
+            "%" => a % b,
+            "<<" => a.wrapping_shl(b as u32),
+            ">>" => a.wrapping_shr(b as u32),
+            "&" => a & b,
+            "|" => a | b,
+            _ => a ^ b,
+        };
+        Ok(Value { value, address })
+    }
+
+    fn unary(&mut self) -> Result<Value, String> {
+        if self.eat("-") {
+            let value = self.unary()?;
+            return Ok(Value::constant(value.value.wrapping_neg()));
+        }
+        if self.eat("~") {
+            return Ok(Value::constant(!self.unary()?.value));
+        }
+        if self.eat("+") {
+            return self.unary();
+        }
+        if self.eat("(") {
+            let value = self.expr(0)?;
+            return match self.eat(")") {
+                true => Ok(value),
+                false => Err(format!("missing ')' in '{}'", self.text)),
+            };
+        }
+        for (name, hi) in [("%hi(", true), ("%lo(", false)] {
+            if self.eat(name) {
+                let value = self.expr(0)?.value;
+                if !self.eat(")") {
+                    return Err(format!("missing ')' in '{}'", self.text));
+                }
+                let (upper, lower) = split_hi_lo(value);
+                return Ok(Value::constant(if hi { upper as i64 } else { lower }));
+            }
+        }
+        self.skip_whitespace();
+        let rest = self.rest();
+        if let Some(literal) = rest.strip_prefix('\'') {
+            // the closing quote, an escaped one doesn't end the literal
+            let mut escaped = false;
+            let end = literal
+                .char_indices()
+                .find(|&(_, c)| {
+                    let closing = c == '\'' && !escaped;
+                    escaped = c == '\\' && !escaped;
+                    closing
+                })
+                .map(|(end, _)| end)
+                .ok_or_else(|| format!("unterminated character in '{}'", self.text))?;
+            let bytes = parse_string(&format!("\"{}\"", &literal[..end]))?;
+            let [byte] = bytes[..] else {
+                return Err(format!("invalid character '{}'", &literal[..end]));
+            };
+            self.pos += end + 2;
+            return Ok(Value::constant(byte as i64));
+        }
+        let len = rest.find(|c| !is_symbol_char(c)).unwrap_or(rest.len());
+        let token = &rest[..len];
+        self.pos += len;
+        if token.is_empty() {
+            return Err(format!("expected a value in '{}'", self.text));
+        }
+        if token == "." {
+            return Ok(Value {
+                value: self.pc as i64,
+                address: true,
+            });
+        }
+        if token.starts_with(|c: char| c.is_ascii_digit()) {
+            let number = match token.get(..2) {
+                Some("0x" | "0X") => i64::from_str_radix(&token[2..], 16),
+                Some("0b" | "0B") => i64::from_str_radix(&token[2..], 2),
+                _ => token.parse(),
+            };
+            if let Ok(number) = number {
+                return Ok(Value::constant(number));
+            }
+        }
+        self.asm.symbol(token, self.index)
+    }
+}
+
+// a statement being encoded
+struct Context<'a> {
+    asm: &'a Assembler,
+    index: usize,
+    pc: u32,
+}
+
+impl Context<'_> {
+    fn value(&self, expr: &str) -> Result<Value, String> {
+        self.asm.eval(expr, self.index, self.pc)
+    }
+
+    fn reg(&self, name: &str) -> Result<usize, String> {
+        Reg::from_name(name)
+            .map(Reg::index)
+            .ok_or_else(|| format!("expected a register, found '{name}'"))
+    }
+
+    fn immediate(&self, expr: &str, range: std::ops::Range<i64>) -> Result<u32, String> {
+        let value = self.value(expr)?.value;
+        if !range.contains(&value) {
+            return Err(format!("immediate {value} out of range {range:?}"));
+        }
+        Ok(value as u32)
+    }
+
+    // offset to a label, plain numbers are taken as offsets already
+    fn offset(&self, expr: &str, bits: u32) -> Result<u32, String> {
+        let value = self.value(expr)?;
+        let offset = match value.address {
+            true => value.value - self.pc as i64,
+            false => value.value,
+        };
+        let limit = 1 << (bits - 1);
+        if offset % 2 != 0 || !(-limit..limit).contains(&offset) {
+            return Err(format!("target {expr} out of reach (offset {offset})"));
+        }
+        Ok(offset as u32)
+    }
+
+    // `offset(reg)`, the offset can be omitted
+    fn memory(&self, operand: &str) -> Result<(u32, usize), String> {
+        let open = operand
+            .rfind('(')
+            .filter(|_| operand.ends_with(')'))
+            .ok_or_else(|| format!("expected offset(register), found '{operand}'"))?;
+        let rs1 = self.reg(operand[open + 1..operand.len() - 1].trim())?;
+        let offset = operand[..open].trim();
+        let imm = match offset {
+            "" => 0,
+            offset => self.immediate(offset, -2048..2048)?,
+        };
+        Ok((imm, rs1))
+    }
+
+    fn csr(&self, name: &str) -> Result<u32, String> {
+        match csr_number(name) {
+            Some(number) => Ok(number as u32),
+            None => self.immediate(name, 0..4096),
+        }
+    }
+
+    fn fence_set(&self, set: &str) -> Result<u32, String> {
+        set.chars().try_fold(0, |bits, c| match c {
+            'i' => Ok(bits | 8),
+            'o' => Ok(bits | 4),
+            'r' => Ok(bits | 2),
+            'w' => Ok(bits | 1),
+            _ => Err(format!("invalid fence operand '{set}'")),
+        })
+    }
+
+    fn encode(&self, mnemonic: &str, ops: &[String], size: u32) -> Result<Vec<u32>, String> {
+        let count = |n: usize| match ops.len() == n {
+            true => Ok(()),
+            false => Err(format!("'{mnemonic}' takes {n} operands")),
+        };
+        let find =
+            |table: &[(&str, usize)]| table.iter().find(|op| op.0 == mnemonic).map(|op| op.1);
+        let word = |word| Ok(vec![word]);
+
+        // without operands sfence.vma is the same as with x0 for both
+        if mnemonic == "sfence.vma" && ops.len() == 2 {
+            let (rs1, rs2) = (self.reg(&ops[0])?, self.reg(&ops[1])?);
+            return word(
+                RFormat {
+                    rd: 0,
+                    funct3: 0,
+                    rs1,
+                    rs2,
+                    funct7: 0b0001001,
+                }
+                .encode(SYSTEM),
+            );
+        }
+        if let Some(&(_, raw_inst)) = FIXED.iter().find(|op| op.0 == mnemonic) {
+            count(0)?;
+            return word(raw_inst);
+        }
+        if let Some(&(_, funct3, funct7)) = R_OPS.iter().find(|op| op.0 == mnemonic) {
+            count(3)?;
+            let (rd, rs1, rs2) = (self.reg(&ops[0])?, self.reg(&ops[1])?, self.reg(&ops[2])?);
+            return word(
+                RFormat {
+                    rd,
+                    funct3,
+                    rs1,
+                    rs2,
+                    funct7,
+                }
+                .encode(OP),
+            );
+        }
+        if let Some(funct3) = find(I_OPS) {
+            count(3)?;
+            let (rd, rs1) = (self.reg(&ops[0])?, self.reg(&ops[1])?);
+            let imm = self.immediate(&ops[2], -2048..2048)?;
+            return word(
+                IFormat {
+                    rd,
+                    funct3,
+                    rs1,
+                    imm,
+                }
+                .encode(OP_IMM),
+            );
+        }
+        if let Some(&(_, funct3, upper)) = SHIFT_OPS.iter().find(|op| op.0 == mnemonic) {
+            count(3)?;
+            let (rd, rs1) = (self.reg(&ops[0])?, self.reg(&ops[1])?);
+            let imm = upper << 5 | self.immediate(&ops[2], 0..32)?;
+            return word(
+                IFormat {
+                    rd,
+                    funct3,
+                    rs1,
+                    imm,
+                }
+                .encode(OP_IMM),
+            );
+        }
+        if let Some(&(_, funct3, imm)) = UNARY_OPS.iter().find(|op| op.0 == mnemonic) {
+            count(2)?;
+            let (rd, rs1) = (self.reg(&ops[0])?, self.reg(&ops[1])?);
+            return word(
+                IFormat {
+                    rd,
+                    funct3,
+                    rs1,
+                    imm,
+                }
+                .encode(OP_IMM),
+            );
+        }
+        if let Some(funct3) = find(LOADS) {
+            count(2)?;
+            let rd = self.reg(&ops[0])?;
+            let (imm, rs1) = self.memory(&ops[1])?;
+            return word(
+                IFormat {
+                    rd,
+                    funct3,
+                    rs1,
+                    imm,
+                }
+                .encode(LOAD),
+            );
+        }
+        if let Some(funct3) = find(STORES) {
+            count(2)?;
+            let rs2 = self.reg(&ops[0])?;
+            let (imm, rs1) = self.memory(&ops[1])?;
+            return word(
+                SFormat {
+                    funct3,
+                    rs1,
+                    rs2,
+                    imm,
+                }
+                .encode(STORE),
+            );
+        }
+        if let Some(funct3) = find(BRANCHES) {
+            count(3)?;
+            let (rs1, rs2) = (self.reg(&ops[0])?, self.reg(&ops[1])?);
+            let imm = self.offset(&ops[2], 13)?;
+            return word(
+                BFormat {
+                    funct3,
+                    rs1,
+                    rs2,
+                    imm,
+                }
+                .encode(BRANCH),
+            );
+        }
+        if let Some(funct3) = find(CSR_OPS) {
+            count(3)?;
+            let rd = self.reg(&ops[0])?;
+            let imm = self.csr(&ops[1])?;
+            let rs1 = match funct3 >= 5 {
+                true => self.immediate(&ops[2], 0..32)? as usize,
+                false => self.reg(&ops[2])?,
+            };
+            return word(
+                IFormat {
+                    rd,
+                    funct3,
+                    rs1,
+                    imm,
+                }
+                .encode(SYSTEM),
+            );
+        }
+        if let Some(funct7) = find(AES_OPS) {
+            count(4)?;
+            let (rd, rs1, rs2) = (self.reg(&ops[0])?, self.reg(&ops[1])?, self.reg(&ops[2])?);
+            let bs = self.immediate(&ops[3], 0..4)? as usize;
+            let funct7 = bs << 5 | funct7;
+            return word(
+                RFormat {
+                    rd,
+                    funct3: 0,
+                    rs1,
+                    rs2,
+                    funct7,
+                }
+                .encode(OP),
+            );
+        }
+        let (name, ordering) = match mnemonic.rsplit_once('.') {
+            Some((name, "aq")) => (name, 0b10),
+            Some((name, "rl")) => (name, 0b01),
+            Some((name, "aqrl")) => (name, 0b11),
+            _ => (mnemonic, 0),
+        };
+        if let Some(&(_, funct5)) = AMO_OPS.iter().find(|op| op.0 == name) {
+            let lr = name == "lr.w";
+            count(if lr { 2 } else { 3 })?;
+            let rd = self.reg(&ops[0])?;
+            let rs2 = if lr { 0 } else { self.reg(&ops[1])? };
+            let (offset, rs1) = self.memory(&ops[ops.len() - 1])?;
+            if offset != 0 {
+                return Err(format!("'{mnemonic}' takes no offset"));
+            }
+            let funct7 = funct5 << 2 | ordering;
+            return word(
+                RFormat {
+                    rd,
+                    funct3: 2,
+                    rs1,
+                    rs2,
+                    funct7,
+                }
+                .encode(AMO),
+            );
+        }
+
+        match mnemonic {
+            "fence" => {
+                let (pred, succ) = match ops {
+                    [] => (0b1111, 0b1111),
+                    [pred, succ] => (self.fence_set(pred)?, self.fence_set(succ)?),
+                    _ => return Err("'fence' takes 0 or 2 operands".to_string()),
+                };
+                let imm = pred << 4 | succ;
+                word(
+                    IFormat {
+                        rd: 0,
+                        funct3: 0,
+                        rs1: 0,
+                        imm,
+                    }
+                    .encode(MISC_MEM),
+                )
+            }
+            "jal" => {
+                let (rd, target) = match ops {
+                    [target] => (Reg::Ra.index(), target),
+                    [rd, target] => (self.reg(rd)?, target),
+                    _ => return Err("'jal' takes 1 or 2 operands".to_string()),
+                };
+                let imm = self.offset(target, 21)?;
+                word(JFormat { rd, imm }.encode(JAL))
+            }
+            "jalr" => {
+                let (rd, imm, rs1) = match ops {
+                    [rs1] => (Reg::Ra.index(), 0, self.reg(rs1)?),
+                    [rd, rs1] if Reg::from_name(rs1).is_some() => {
+                        (self.reg(rd)?, 0, self.reg(rs1)?)
+                    }
+                    [rd, memory] => {
+                        let (imm, rs1) = self.memory(memory)?;
+                        (self.reg(rd)?, imm, rs1)
+                    }
+                    [rd, rs1, imm] => (
+                        self.reg(rd)?,
+                        self.immediate(imm, -2048..2048)?,
+                        self.reg(rs1)?,
+                    ),
+                    _ => return Err("'jalr' takes 1 to 3 operands".to_string()),
+                };
+                word(
+                    IFormat {
+                        rd,
+                        funct3: 0,
+                        rs1,
+                        imm,
+                    }
+                    .encode(JALR),
+                )
+            }
+            "lui" | "auipc" => {
+                count(2)?;
+                let rd = self.reg(&ops[0])?;
+                let imm = self.immediate(&ops[1], -0x8_0000..0x10_0000)?;
+                let opcode = if mnemonic == "lui" { LUI } else { AUIPC };
+                word(UFormat { rd, imm }.encode(opcode))
+            }
+            "li" => {
+                count(2)?;
+                let rd = self.reg(&ops[0])?;
+                let value = self.immediate(&ops[1], -(1 << 31)..1 << 32)? as i32 as i64;
+                let (hi, lo) = split_hi_lo(value);
+                let lui = UFormat { rd, imm: hi }.encode(LUI);
+                let addi = |rs1| {
+                    IFormat {
+                        rd,
+                        funct3: 0,
+                        rs1,
+                        imm: lo as u32,
+                    }
+                    .encode(OP_IMM)
+                };
+                match size {
+                    8 => Ok(vec![lui, addi(rd)]),
+                    _ if (-2048..2048).contains(&value) => word(addi(0)),
+                    _ if lo == 0 => word(lui),
+                    // the value changed since the first pass, e.g. by a later .set
+                    _ => Err(format!("li of {value} needs two instructions")),
+                }
+            }
+            // pc-relative pairs of auipc and an instruction adding the lower bits
+            "la" | "lla" | "call" | "tail" => {
+                let (rd, target) = match (mnemonic, ops) {
+                    ("la" | "lla", [rd, target]) => (self.reg(rd)?, target),
+                    ("call", [target]) => (Reg::Ra.index(), target),
+                    ("tail", [target]) => (Reg::T1.index(), target),
+                    _ => return Err(format!("wrong number of operands for '{mnemonic}'")),
+                };
+                let target = self.value(target)?;
+                let offset = match target.address {
+                    true => target.value - self.pc as i64,
+                    false => target.value.wrapping_sub(self.pc as i64) as i32 as i64,
+                };
+                let (hi, lo) = split_hi_lo(offset);
+                let auipc = UFormat { rd, imm: hi }.encode(AUIPC);
+                let second = match mnemonic {
+                    "call" => IFormat {
+                        rd,
+                        funct3: 0,
+                        rs1: rd,
+                        imm: lo as u32,
+                    }
+                    .encode(JALR),
+                    "tail" => IFormat {
+                        rd: 0,
+                        funct3: 0,
+                        rs1: rd,
+                        imm: lo as u32,
+                    }
+                    .encode(JALR),
+                    _ => IFormat {
+                        rd,
+                        funct3: 0,
+                        rs1: rd,
+                        imm: lo as u32,
+                    }
+                    .encode(OP_IMM),
+                };
+                Ok(vec![auipc, second])
+            }
+            _ => match alias(mnemonic, ops) {
+                Some((mnemonic, ops)) => self.encode(mnemonic, &ops, size),
+                None => Err(format!("unknown instruction '{mnemonic}'")),
+            },
+        }
+    }
+}
+
+// pseudo-instructions that stand for a single instruction
+fn alias(mnemonic: &str, ops: &[String]) -> Option<(&'static str, Vec<String>)> {
+    let ops: Vec<&str> = ops.iter().map(String::as_str).collect();
+    let (name, operands): (&str, Vec<&str>) = match (mnemonic, &ops[..]) {
+        ("mv", &[rd, rs]) => ("addi", vec![rd, rs, "0"]),
+        ("not", &[rd, rs]) => ("xori", vec![rd, rs, "-1"]),
+        ("neg", &[rd, rs]) => ("sub", vec![rd, "zero", rs]),
+        ("seqz", &[rd, rs]) => ("sltiu", vec![rd, rs, "1"]),
+        ("snez", &[rd, rs]) => ("sltu", vec![rd, "zero", rs]),
+        ("sltz", &[rd, rs]) => ("slt", vec![rd, rs, "zero"]),
+        ("sgtz", &[rd, rs]) => ("slt", vec![rd, "zero", rs]),
+        ("zext.b", &[rd, rs]) => ("andi", vec![rd, rs, "255"]),
+        ("beqz", &[rs, target]) => ("beq", vec![rs, "zero", target]),
+        ("bnez", &[rs, target]) => ("bne", vec![rs, "zero", target]),
+        ("blez", &[rs, target]) => ("bge", vec!["zero", rs, target]),
+        ("bgez", &[rs, target]) => ("bge", vec![rs, "zero", target]),
+        ("bltz", &[rs, target]) => ("blt", vec![rs, "zero", target]),
+        ("bgtz", &[rs, target]) => ("blt", vec!["zero", rs, target]),
+        ("bgt", &[a, b, target]) => ("blt", vec![b, a, target]),
+        ("ble", &[a, b, target]) => ("bge", vec![b, a, target]),
+        ("bgtu", &[a, b, target]) => ("bltu", vec![b, a, target]),
+        ("bleu", &[a, b, target]) => ("bgeu", vec![b, a, target]),
+        ("j", &[target]) => ("jal", vec!["zero", target]),
+        ("jr", &[rs]) => ("jalr", vec!["zero", rs]),
+        ("ret", &[]) => ("jalr", vec!["zero", "ra"]),
+        ("csrr", &[rd, csr]) => ("csrrs", vec![rd, csr, "zero"]),
+        ("csrw", &[csr, rs]) => ("csrrw", vec!["zero", csr, rs]),
+        ("csrs", &[csr, rs]) => ("csrrs", vec!["zero", csr, rs]),
+        ("csrc", &[csr, rs]) => ("csrrc", vec!["zero", csr, rs]),
+        ("csrwi", &[csr, imm]) => ("csrrwi", vec!["zero", csr, imm]),
+        ("csrsi", &[csr, imm]) => ("csrrsi", vec!["zero", csr, imm]),
+        ("csrci", &[csr, imm]) => ("csrrci", vec!["zero", csr, imm]),
+        ("rdcycle", &[rd]) => ("csrrs", vec![rd, "cycle", "zero"]),
+        ("rdcycleh", &[rd]) => ("csrrs", vec![rd, "cycleh", "zero"]),
+        ("rdtime", &[rd]) => ("csrrs", vec![rd, "time", "zero"]),
+        ("rdtimeh", &[rd]) => ("csrrs", vec![rd, "timeh", "zero"]),
+        ("rdinstret", &[rd]) => ("csrrs", vec![rd, "instret", "zero"]),
+        ("rdinstreth", &[rd]) => ("csrrs", vec![rd, "instreth", "zero"]),
+        _ => return None,
+    };
+    Some((name, operands.into_iter().map(str::to_string).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inst::Inst;
+    use proptest::prelude::*;
+
+    fn words(source: &str) -> Vec<u32> {
+        let assembly = assemble(source, 0).unwrap_or_else(|e| panic!("{e:?}"));
+        assembly
+            .bytes
+            .chunks(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect()
+    }
+
+    fn error(source: &str) -> String {
+        match assemble(source, 0) {
+            Err(Error::Assembly(line, message)) => format!("{line}: {message}"),
+            Err(e) => panic!("{e:?}"),
+            Ok(_) => panic!("'{source}' assembled"),
+        }
+    }
+
+    #[test]
+    fn encodings() {
+        // the encodings are the ones used by the hand-assembled tests of the cpu
+        let source = "
+            addi t0, x0, 5
+            add zero, a0, a1
+            lw t1, 0(t0)
+            sw t1, 0x10(a0)
+            sb t1, (t0)
+            lui a0, 0x80001
+            csrw mtvec, a1
+            csrsi mstatus, 8
+            csrr s0, mcause
+            srai a0, a0, 3
+            rev8 a0, a0
+            lr.w.aq a0, (a1)
+            amoadd.w a0, a2, (a1)
+            fence
+            ecall
+            ebreak
+            pause
+        ";
+        assert_eq!(
+            words(source),
+            [
+                0x00500293, 0x00b50033, 0x0002a303, 0x00652823, 0x00628023, 0x80001537, 0x30559073,
+                0x30046073, 0x34202473, 0x40355513, 0x69855513, 0x1405a52f, 0x00c5a52f, 0x0ff0000f,
+                0x00000073, 0x00100073, 0x0100000f,
+            ]
+        );
+    }
+
+    #[test]
+    fn labels_and_branches() {
+        let source = "
+            start:
+                j end           # forward
+            1:  addi a0, a0, -1
+                bnez a0, 1b
+                beq a0, a1, 8   # numbers are offsets
+                jal start
+            end: ret
+        ";
+        assert_eq!(
+            words(source),
+            [0x0140006f, 0xfff50513, 0xfe051ee3, 0x00b50463, 0xff1ff0ef, 0x00008067]
+        );
+    }
+
+    #[test]
+    fn pseudo_instructions() {
+        let assembly = assemble(
+            "
+            li a0, 42
+            li a1, 0x12345fff
+            li a2, 0x1000
+            li a3, later
+            la a4, data
+            call func
+            func: ret
+            .equ later, 7
+            .data
+            data: .word 1, -1, data
+            .byte 'a', '\\n'
+            .asciz \"hi\"
+            .align 2
+            end:
+            ",
+            0x8000_0000,
+        )
+        .unwrap_or_else(|e| panic!("{e:?}"));
+        let words: Vec<u32> = assembly
+            .bytes
+            .chunks(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect();
+        assert_eq!(
+            words[..12],
+            [
+                0x02a00513, // li a0, 42
+                0x123465b7, 0xfff58593, // li a1, 0x12345fff
+                0x00001637, // li a2, 0x1000
+                0x000006b7, 0x00768693, // li a3, later (defined later, so two instructions)
+                0x00000717, 0x01470713, // la a4, data
+                0x00000097, 0x008080e7, // call func
+                0x00008067, // ret
+                1,
+            ]
+        );
+        assert_eq!(words[12..14], [u32::MAX, 0x8000_002c]);
+        assert_eq!(assembly.bytes[56..61], *b"a\nhi\0");
+        assert_eq!(assembly.symbols["data"], 0x8000_002c);
+        assert_eq!(assembly.symbols["end"], 0x8000_0040);
+        assert_eq!(assembly.symbols["later"], 7);
+    }
+
+    // the disassembly is accepted by the assembler, except for fences which are all printed alike
+    proptest! {
+        #[test]
+        fn disassembly_round_trip(
+            bits in any::<u32>(),
+            opcode in prop::sample::select(vec![
+                OP, OP_IMM, LOAD, STORE, BRANCH, JAL, JALR, LUI, AUIPC, SYSTEM, AMO,
+            ]),
+        ) {
+            let raw_inst = bits & !0x7f | opcode;
+            let Ok(inst) = crate::decode(raw_inst) else {
+                return Ok(());
+            };
+            if matches!(inst, Inst::Fence | Inst::Vector(_)) {
+                return Ok(());
+            }
+            prop_assert_eq!(words(&inst.to_string()), [raw_inst], "{}", inst);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error("nop\nfoo a0"), "2: unknown instruction 'foo'");
+        assert_eq!(
+            error("addi a0, a0, 2048"),
+            "1: immediate 2048 out of range -2048..2048"
+        );
+        assert_eq!(error("add a0, a1"), "1: 'add' takes 3 operands");
+        assert_eq!(error("lw a0, 0(q0)"), "1: expected a register, found 'q0'");
+        assert_eq!(error("j nowhere"), "1: undefined symbol 'nowhere'");
+        assert_eq!(error("a: nop\na: nop"), "2: symbol 'a' is already defined");
+        assert_eq!(
+            error(".byte 1\nnop"),
+            "2: instruction at unaligned address 0x1"
+        );
+        assert_eq!(error(".foo"), "1: unknown directive '.foo'");
+        assert_eq!(error("li a0, '"), "1: unterminated character in '''");
+        assert_eq!(error(".word\t'"), "1: unterminated character in '''");
+        assert_eq!(error("li a0, 'é'"), "1: invalid character 'é'");
+        assert_eq!(error("li a0, ''"), "1: invalid character ''");
+    }
+}
@@ -0,0 +1,209 @@
+// Storage behind the machine's ram. Memory only sees byte ranges, so ram can live on the heap, in
+// a mapped host file or in copy-on-write layers over a frozen snapshot without the bus caring.
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::rc::Rc;
+
+// granularity at which the copy-on-write layer copies the contents of its base
+pub const PAGE_SIZE: usize = 4096;
+
+pub trait MemoryBackend {
+    fn size(&self) -> usize;
+    // borrowed where the backend keeps the range contiguously, callers stay inside of size()
+    fn read(&self, range: Range<usize>) -> Cow<'_, [u8]>;
+    fn write(&mut self, offset: usize, data: &[u8]);
+    // A copy of the contents that diverges from this one on writes of either side, if the backend
+    // can make one without copying. Others are frozen into a snapshot by the memory.
+    fn fork(&mut self) -> Option<Box<dyn MemoryBackend>> {
+        None
+    }
+}
+
+// ram allocated on the heap
+pub struct VecBackend(Vec<u8>);
+
+impl VecBackend {
+    pub fn new(size: usize) -> Self {
+        VecBackend(vec![0; size])
+    }
+}
+
+impl MemoryBackend for VecBackend {
+    fn size(&self) -> usize {
+        self.0.len()
+    }
+    fn read(&self, range: Range<usize>) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.0[range])
+    }
+    fn write(&mut self, offset: usize, data: &[u8]) {
+        self.0[offset..offset + data.len()].copy_from_slice(data);
+    }
+}
+
+// Ram mapped from a host file. Pages are only allocated once they're touched, so even ram sizes
+// close to the address space are cheap, and the contents persist across runs.
+#[cfg(feature = "mmap")]
+pub struct FileBackend {
+    map: memmap2::MmapMut,
+}
+
+#[cfg(feature = "mmap")]
+impl FileBackend {
+    // Creates the file if needed and grows it to `size` bytes, longer files are only partially
+    // mapped.
+    pub fn open(path: &std::path::Path, size: usize) -> std::io::Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        if file.metadata()?.len() < size as u64 {
+            file.set_len(size as u64)?;
+        }
+        // the file must not be changed by another process while it's mapped
+        let map = unsafe { memmap2::MmapOptions::new().len(size).map_mut(&file)? };
+        Ok(FileBackend { map })
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl MemoryBackend for FileBackend {
+    fn size(&self) -> usize {
+        self.map.len()
+    }
+    fn read(&self, range: Range<usize>) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.map[range])
+    }
+    fn write(&mut self, offset: usize, data: &[u8]) {
+        self.map[offset..offset + data.len()].copy_from_slice(data);
+    }
+}
+
+// Layer over a frozen snapshot that copies a page on its first write, so any number of forks
+// can share the snapshot and only pay for the pages they change.
+pub struct CowBackend {
+    base: Rc<dyn MemoryBackend>,
+    // copied pages by index
+    pages: HashMap<usize, Box<[u8]>>,
+}
+
+impl CowBackend {
+    pub fn new(base: Rc<dyn MemoryBackend>) -> Self {
+        CowBackend {
+            base,
+            pages: HashMap::new(),
+        }
+    }
+
+    // number of pages that differ from the snapshot
+    pub fn copied_pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    fn page_range(&self, page: usize) -> Range<usize> {
+        let start = page * PAGE_SIZE;
+        start..(start + PAGE_SIZE).min(self.base.size())
+    }
+}
+
+impl MemoryBackend for CowBackend {
+    fn size(&self) -> usize {
+        self.base.size()
+    }
+    fn read(&self, range: Range<usize>) -> Cow<'_, [u8]> {
+        let page = range.start / PAGE_SIZE;
+        if range.end <= (page + 1) * PAGE_SIZE {
+            return match self.pages.get(&page) {
+                Some(data) => {
+                    let offset = page * PAGE_SIZE;
+                    Cow::Borrowed(&data[range.start - offset..range.end - offset])
+                }
+                None => self.base.read(range),
+            };
+        }
+        // accesses crossing pages are assembled from the pieces
+        let mut bytes = Vec::with_capacity(range.len());
+        let mut start = range.start;
+        while start < range.end {
+            let end = ((start / PAGE_SIZE + 1) * PAGE_SIZE).min(range.end);
+            bytes.extend_from_slice(&self.read(start..end));
+            start = end;
+        }
+        Cow::Owned(bytes)
+    }
+    fn write(&mut self, offset: usize, data: &[u8]) {
+        let mut written = 0;
+        while written < data.len() {
+            let address = offset + written;
+            let page = address / PAGE_SIZE;
+            let range = self.page_range(page);
+            let base = &self.base;
+            let copy = self
+                .pages
+                .entry(page)
+                .or_insert_with(|| base.read(range.clone()).into_owned().into_boxed_slice());
+            let start = address - range.start;
+            let len = (range.end - address).min(data.len() - written);
+            copy[start..start + len].copy_from_slice(&data[written..written + len]);
+            written += len;
+        }
+    }
+    fn fork(&mut self) -> Option<Box<dyn MemoryBackend>> {
+        // the changed pages become part of a new snapshot shared by both sides
+        if !self.pages.is_empty() {
+            let layer = CowBackend {
+                base: self.base.clone(),
+                pages: std::mem::take(&mut self.pages),
+            };
+            self.base = Rc::new(layer);
+        }
+        Some(Box::new(CowBackend::new(self.base.clone())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copy_on_write() {
+        let mut ram = VecBackend::new(3 * PAGE_SIZE);
+        ram.write(PAGE_SIZE - 2, &[1, 2, 3, 4]);
+        let base: Rc<dyn MemoryBackend> = Rc::new(ram);
+        let mut fork = CowBackend::new(base.clone());
+        assert_eq!(fork.read(PAGE_SIZE - 2..PAGE_SIZE + 2)[..], [1, 2, 3, 4]);
+
+        // a write crossing into the next page copies both
+        fork.write(PAGE_SIZE - 1, &[5, 6]);
+        assert_eq!(fork.copied_pages(), 2);
+        assert_eq!(fork.read(PAGE_SIZE - 2..PAGE_SIZE + 2)[..], [1, 5, 6, 4]);
+        assert_eq!(base.read(PAGE_SIZE - 2..PAGE_SIZE + 2)[..], [1, 2, 3, 4]);
+
+        // forks don't see each other's writes
+        let mut other = fork.fork().unwrap();
+        assert_eq!(fork.copied_pages(), 0);
+        other.write(0, &[9]);
+        fork.write(0, &[7]);
+        assert_eq!(other.read(0..2)[..], [9, 0]);
+        assert_eq!(fork.read(PAGE_SIZE - 1..PAGE_SIZE + 1)[..], [5, 6]);
+        assert_eq!(fork.read(0..1)[..], [7]);
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn file_backed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ram.img");
+        let mut ram = FileBackend::open(&path, 2 * PAGE_SIZE).unwrap();
+        ram.write(PAGE_SIZE, b"ram");
+        drop(ram);
+        let ram = FileBackend::open(&path, 2 * PAGE_SIZE).unwrap();
+        assert_eq!(ram.read(PAGE_SIZE..PAGE_SIZE + 3)[..], *b"ram");
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().len(),
+            2 * PAGE_SIZE as u64
+        );
+    }
+}
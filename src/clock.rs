@@ -0,0 +1,106 @@
+// The time seen by the program through mtime, the time csr and the time syscalls. Virtual time
+// advances with the executed cycles at the timebase frequency, so runs are reproducible. Host time
+// follows the host's clocks, the program then sees real time pass however fast it is emulated.
+use crate::fdt::TIMEBASE_FREQUENCY;
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TimeSource {
+    Virtual,
+    Host,
+}
+
+impl TimeSource {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "virtual" => Some(TimeSource::Virtual),
+            "host" => Some(TimeSource::Host),
+            _ => None,
+        }
+    }
+}
+
+// timebase ticks in the duration
+pub fn ticks(duration: Duration) -> u64 {
+    (duration.as_nanos() * TIMEBASE_FREQUENCY as u128 / 1_000_000_000) as u64
+}
+
+#[derive(Clone)]
+pub struct Clock {
+    source: TimeSource,
+    // when the emulation started, the origin of host time
+    start: Instant,
+    // cycles executed so far, the origin of virtual time
+    cycles: u64,
+}
+
+impl Clock {
+    pub fn new(source: TimeSource) -> Self {
+        Clock {
+            source,
+            start: Instant::now(),
+            cycles: 0,
+        }
+    }
+
+    pub fn source(&self) -> TimeSource {
+        self.source
+    }
+
+    pub fn start(&self) -> Instant {
+        self.start
+    }
+
+    pub fn advance(&mut self, cycles: u64) {
+        self.cycles = self.cycles.wrapping_add(cycles);
+    }
+
+    // time since the emulation started, in ticks of the timebase
+    pub fn now(&self) -> u64 {
+        match self.source {
+            TimeSource::Virtual => self.cycles,
+            TimeSource::Host => ticks(self.start.elapsed()),
+        }
+    }
+
+    // time since the emulation started
+    pub fn monotonic(&self) -> Duration {
+        match self.source {
+            TimeSource::Virtual => {
+                Duration::from_nanos(self.cycles * 1_000_000_000 / TIMEBASE_FREQUENCY as u64)
+            }
+            TimeSource::Host => self.start.elapsed(),
+        }
+    }
+
+    // Time since the unix epoch. Virtual time starts at the epoch, so that the program doesn't
+    // see the date of the run.
+    pub fn realtime(&self) -> Duration {
+        match self.source {
+            TimeSource::Virtual => self.monotonic(),
+            TimeSource::Host => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn virtual_time() {
+        let mut clock = Clock::new(TimeSource::Virtual);
+        clock.advance(TIMEBASE_FREQUENCY as u64 * 3 / 2);
+        assert_eq!(clock.now(), TIMEBASE_FREQUENCY as u64 * 3 / 2);
+        assert_eq!(clock.monotonic(), Duration::from_millis(1500));
+        assert_eq!(clock.realtime(), Duration::from_millis(1500));
+
+        let mut clock = Clock::new(TimeSource::Host);
+        clock.advance(1_000_000);
+        assert!(clock.monotonic() < Duration::from_secs(1));
+        assert!(clock.realtime() > Duration::from_secs(1_600_000_000));
+    }
+}
@@ -0,0 +1,553 @@
+// Control socket through which external tools like test frameworks or guis drive the running
+// program. Clients connect over tcp and exchange json-rpc 2.0 messages, one per line:
+//   --> {"jsonrpc":"2.0","id":1,"method":"read_memory","params":{"address":4096,"length":4}}
+//   <-- {"jsonrpc":"2.0","id":1,"result":{"data":"13050000"}}
+// Methods and their params:
+//   pause, resume                      stops or continues the program, replies with the status
+//   status                             state (running or paused), pc, hart, cycles, instructions
+//   step {count}                       executes instructions of the paused program (default: 1)
+//   read_registers {hart}              the pc and x0-x31 of the hart (default: the current one)
+//   write_register {name, value, hart} a register by name like a0 or x10, the pc or a csr
+//   read_memory {address, length}      ram as hex string, device registers aren't read
+//   write_memory {address, data}       writes the hex string to ram
+//   inject_interrupt {source | cause}  pulses a plic interrupt line or sets a software mip bit
+//   stats                              the status plus the executed instructions per second
+// Numbers may also be given as expressions, e.g. "sym(\"buf\") + 8". Requests are handled
+// between two instructions, the program keeps running while no client sends any.
+use crate::cpu::{Cpu, StopReason};
+use crate::csr::MIP_HARDWARE;
+use crate::error::Error;
+use crate::expr::Expr;
+use crate::json::Json;
+use crate::regs::Reg;
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::time::Instant;
+
+// json-rpc error codes
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+
+// largest read_memory reply, in bytes of memory
+const MAX_READ: u64 = 1 << 20;
+
+// a line received from a client and where its reply goes
+struct Request {
+    line: String,
+    reply: Sender<String>,
+}
+
+// Opens the control socket at the address and runs the loaded program until it stops, answering
+// requests in between.
+pub fn serve(cpu: &mut Cpu, address: &str) -> Result<StopReason, Error> {
+    let result = session(cpu, address);
+    cpu.finish(result)
+}
+
+fn session(cpu: &mut Cpu, address: &str) -> Result<StopReason, Error> {
+    let listener = TcpListener::bind(address).map_err(Error::Control)?;
+    eprintln!(
+        "control socket on {}",
+        listener.local_addr().map_err(Error::Control)?
+    );
+    let mut control = Control::new();
+    let requests = listen(listener, control.pending.clone(), cpu.stop_handle());
+    loop {
+        if control.paused || control.pending.load(Ordering::SeqCst) > 0 {
+            // the acceptor keeps a sender, so this waits for the next request
+            let Ok(request) = requests.recv() else {
+                control.paused = false;
+                continue;
+            };
+            control.pending.fetch_sub(1, Ordering::SeqCst);
+            if let Some(result) = control.answer(cpu, request) {
+                return result;
+            }
+            continue;
+        }
+        // Clients stopped the program for requests that were answered while it was paused. The
+        // flag is cleared before the counter is checked again, a client counts its request before
+        // raising the flag, so none goes unnoticed.
+        cpu.stop_handle().store(false, Ordering::SeqCst);
+        if control.pending.load(Ordering::SeqCst) > 0 {
+            continue;
+        }
+        match cpu.run_until_stop() {
+            Ok(StopReason::HostRequest) => {
+                // someone else asked the emulation to stop, e.g. a ctrl-c handler
+                if control.pending.load(Ordering::SeqCst) == 0 {
+                    return Ok(StopReason::HostRequest);
+                }
+            }
+            // breakpoints pause the program until a client resumes it
+            Ok(StopReason::Debug(stop)) => {
+                control.paused = true;
+                control.stop = Some(format!("{stop:?}"));
+            }
+            result => return result,
+        }
+    }
+}
+
+// accepts clients on a thread of their own, each client is served by another thread
+fn listen(
+    listener: TcpListener,
+    pending: Arc<AtomicUsize>,
+    stop: Arc<AtomicBool>,
+) -> Receiver<Request> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let (sender, pending, stop) = (sender.clone(), pending.clone(), stop.clone());
+            std::thread::spawn(move || serve_client(stream, sender, pending, stop));
+        }
+    });
+    receiver
+}
+
+fn serve_client(
+    stream: TcpStream,
+    requests: Sender<Request>,
+    pending: Arc<AtomicUsize>,
+    stop: Arc<AtomicBool>,
+) {
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        // the request is counted before the running program is interrupted to answer it
+        pending.fetch_add(1, Ordering::SeqCst);
+        stop.store(true, Ordering::SeqCst);
+        let (reply, replies) = mpsc::channel();
+        if requests.send(Request { line, reply }).is_err() {
+            return;
+        }
+        let Ok(reply) = replies.recv() else {
+            return;
+        };
+        if writeln!(writer, "{reply}").is_err() {
+            return;
+        }
+    }
+}
+
+struct Control {
+    paused: bool,
+    // why the program paused on its own, e.g. at a breakpoint
+    stop: Option<String>,
+    // when the session started, for the execution speed
+    start: Instant,
+    // requests clients sent that weren't received yet
+    pending: Arc<AtomicUsize>,
+}
+
+type Reply = Result<Json, (i32, String)>;
+
+fn invalid(message: impl Into<String>) -> (i32, String) {
+    (INVALID_PARAMS, message.into())
+}
+
+impl Control {
+    fn new() -> Self {
+        Control {
+            paused: false,
+            stop: None,
+            start: Instant::now(),
+            pending: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    // Answers the request, returns the result of the run if a step finished the program.
+    fn answer(&mut self, cpu: &mut Cpu, request: Request) -> Option<Result<StopReason, Error>> {
+        let mut finished = None;
+        let (id, reply) = match Json::parse(&request.line) {
+            Err(e) => (Json::Null, Err((PARSE_ERROR, e))),
+            Ok(message) => {
+                let id = message.get("id").cloned().unwrap_or(Json::Null);
+                let params = message.get("params").cloned().unwrap_or(Json::Null);
+                let reply = match message.get("method").and_then(Json::as_str) {
+                    Some(method) => self.call(cpu, method, &params, &mut finished),
+                    None => Err((INVALID_REQUEST, "missing method".to_string())),
+                };
+                (id, reply)
+            }
+        };
+        let body = match reply {
+            Ok(result) => ("result", result),
+            Err((code, message)) => (
+                "error",
+                Json::object([
+                    ("code", Json::Number(code as f64)),
+                    ("message", Json::String(message)),
+                ]),
+            ),
+        };
+        let reply = Json::object([("jsonrpc", "2.0".into()), ("id", id), body]);
+        // the client may have disconnected in the meantime
+        let _ = request.reply.send(reply.to_string());
+        finished
+    }
+
+    fn call(
+        &mut self,
+        cpu: &mut Cpu,
+        method: &str,
+        params: &Json,
+        finished: &mut Option<Result<StopReason, Error>>,
+    ) -> Reply {
+        match method {
+            "pause" => {
+                self.paused = true;
+                self.stop = None;
+                Ok(self.status(cpu))
+            }
+            "resume" => {
+                self.paused = false;
+                self.stop = None;
+                Ok(self.status(cpu))
+            }
+            "status" => Ok(self.status(cpu)),
+            "step" => {
+                if !self.paused {
+                    return Err(invalid("the program has to be paused to step"));
+                }
+                let mut count = optional_number(cpu, params, "count")?.unwrap_or(1);
+                // the session checks for requests before the program runs again
+                cpu.stop_handle().store(false, Ordering::SeqCst);
+                while count > 0 {
+                    match cpu.step() {
+                        Ok(None) => count -= 1,
+                        // stops raised by clients are answered once the steps are done
+                        Ok(Some(StopReason::HostRequest))
+                            if self.pending.load(Ordering::SeqCst) > 0 => {}
+                        Ok(Some(StopReason::Debug(stop))) => {
+                            self.stop = Some(format!("{stop:?}"));
+                            break;
+                        }
+                        result => {
+                            let result = result.map(|reason| reason.unwrap());
+                            let status = finished_status(&result);
+                            *finished = Some(result);
+                            return Ok(status);
+                        }
+                    }
+                }
+                Ok(self.status(cpu))
+            }
+            "read_registers" => on_hart(cpu, params, |cpu| {
+                let regs = (0..32).map(|n| cpu.regs.read(n).into()).collect();
+                Ok(Json::object([
+                    ("pc", cpu.pc.get().into()),
+                    ("x", Json::Array(regs)),
+                ]))
+            }),
+            "write_register" => {
+                let name = params
+                    .get("name")
+                    .and_then(Json::as_str)
+                    .ok_or(invalid("missing name"))?;
+                let value = number(cpu, params, "value")? as u32;
+                on_hart(cpu, params, |cpu| {
+                    write_register(cpu, name, value)?;
+                    Ok(Json::Null)
+                })
+            }
+            "read_memory" => {
+                let address = number(cpu, params, "address")? as u32;
+                let length = number(cpu, params, "length")?;
+                if length > MAX_READ {
+                    return Err(invalid(format!("can read at most {MAX_READ} bytes")));
+                }
+                let bytes = cpu.mem.peek(address, length as usize);
+                if (bytes.len() as u64) < length {
+                    return Err(invalid(format!("{address:#x} isn't in ram")));
+                }
+                let data = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+                Ok(Json::object([("data", Json::String(data))]))
+            }
+            "write_memory" => {
+                let address = number(cpu, params, "address")? as u32;
+                let data = params
+                    .get("data")
+                    .and_then(Json::as_str)
+                    .ok_or(invalid("missing data"))?;
+                let bytes = parse_hex(data).ok_or(invalid("data isn't a hex string"))?;
+                if cpu.mem.peek(address, bytes.len()).len() < bytes.len() {
+                    return Err(invalid(format!("{address:#x} isn't in ram")));
+                }
+                cpu.mem.write_bytes(address, &bytes);
+                Ok(Json::Null)
+            }
+            "inject_interrupt" => {
+                if let Some(source) = optional_number(cpu, params, "source")? {
+                    if !(1..32).contains(&source) {
+                        return Err(invalid("interrupt sources are 1 to 31"));
+                    }
+                    cpu.mem.inject_irq(source as u32);
+                } else if let Some(cause) = optional_number(cpu, params, "cause")? {
+                    let bit = 1u32.checked_shl(cause as u32).filter(|_| cause < 32);
+                    match bit {
+                        Some(bit) if bit & MIP_HARDWARE == 0 => cpu.csrs.mip |= bit,
+                        _ => {
+                            return Err(invalid(format!(
+                                "mip bit {cause} isn't writable, inject a source instead"
+                            )))
+                        }
+                    }
+                } else {
+                    return Err(invalid("expected source or cause"));
+                }
+                Ok(Json::Null)
+            }
+            "stats" => {
+                let mut stats = self.status(cpu);
+                let seconds = self.start.elapsed().as_secs_f64();
+                if let Json::Object(members) = &mut stats {
+                    members.push(("harts".to_string(), (cpu.harts() as u64).into()));
+                    members.push((
+                        "instructions_per_second".to_string(),
+                        Json::Number((cpu.retired() as f64 / seconds).round()),
+                    ));
+                }
+                Ok(stats)
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method '{method}'"))),
+        }
+    }
+
+    fn status(&self, cpu: &Cpu) -> Json {
+        let state = if self.paused { "paused" } else { "running" };
+        let mut members = vec![
+            ("state", state.into()),
+            ("pc", cpu.pc.get().into()),
+            ("hart", (cpu.hart() as u64).into()),
+            ("cycles", cpu.cycles().into()),
+            ("instructions", cpu.retired().into()),
+        ];
+        if let Some(stop) = &self.stop {
+            members.push(("stop", stop.as_str().into()));
+        }
+        Json::object(members)
+    }
+}
+
+// the reply to a step that ended the program
+pub(crate) fn finished_status(result: &Result<StopReason, Error>) -> Json {
+    match result {
+        Ok(StopReason::Exit(code)) => Json::object([
+            ("state", "exited".into()),
+            ("exit_code", (*code as u32).into()),
+        ]),
+        Ok(reason) => Json::object([
+            ("state", "stopped".into()),
+            ("reason", format!("{reason:?}").as_str().into()),
+        ]),
+        Err(e) => Json::object([
+            ("state", "failed".into()),
+            ("error", format!("{e:?}").as_str().into()),
+        ]),
+    }
+}
+
+// a number given as json number or as expression
+pub(crate) fn optional_number(
+    cpu: &Cpu,
+    params: &Json,
+    key: &str,
+) -> Result<Option<u64>, (i32, String)> {
+    match params.get(key) {
+        None => Ok(None),
+        Some(Json::String(text)) => {
+            let value = Expr::parse(text)
+                .and_then(|expr| expr.eval(cpu))
+                .map_err(|e| invalid(format!("{key}: {e}")))?;
+            Ok(Some(value as u32 as u64))
+        }
+        Some(value) => value
+            .as_u64()
+            .map(Some)
+            .ok_or(invalid(format!("{key} isn't a number"))),
+    }
+}
+
+fn number(cpu: &Cpu, params: &Json, key: &str) -> Result<u64, (i32, String)> {
+    optional_number(cpu, params, key)?.ok_or(invalid(format!("missing {key}")))
+}
+
+// runs f on the hart given in the params, the current hart stays the one executing
+fn on_hart(cpu: &mut Cpu, params: &Json, f: impl FnOnce(&mut Cpu) -> Reply) -> Reply {
+    let running = cpu.hart();
+    let hart = optional_number(cpu, params, "hart")?.unwrap_or(running as u64);
+    if !cpu.select_hart(hart as usize) {
+        return Err(invalid(format!("there is no hart {hart}")));
+    }
+    let reply = f(cpu);
+    cpu.select_hart(running);
+    reply
+}
+
+fn write_register(cpu: &mut Cpu, name: &str, value: u32) -> Result<(), (i32, String)> {
+    if name == "pc" {
+        cpu.pc.set(value);
+    } else if let Some(reg) = Reg::from_name(name) {
+        cpu.regs.set(reg, value);
+    } else {
+        crate::asm::csr_number(name)
+            .and_then(|csr| cpu.write_csr(csr, value))
+            .ok_or(invalid(format!("unknown or read-only register '{name}'")))?;
+    }
+    Ok(())
+}
+
+pub(crate) fn parse_hex(data: &str) -> Option<Vec<u8>> {
+    if !data.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..data.len() / 2)
+        .map(|i| u8::from_str_radix(data.get(i * 2..i * 2 + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(control: &mut Control, cpu: &mut Cpu, line: &str) -> Json {
+        let (reply, replies) = mpsc::channel();
+        let line = line.to_string();
+        assert!(control.answer(cpu, Request { line, reply }).is_none());
+        Json::parse(&replies.recv().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn methods() {
+        let mut cpu = Cpu::new(false);
+        cpu.load(
+            crate::asm::assemble("li a0, 5\nloop:\naddi a0, a0, 1\nj loop", 0)
+                .unwrap()
+                .bytes,
+        );
+        let mut control = Control::new();
+        let mut call = |cpu: &mut Cpu, line: &str| {
+            let reply = request(&mut control, cpu, line);
+            assert_eq!(reply.get("jsonrpc").and_then(Json::as_str), Some("2.0"));
+            reply
+        };
+
+        let reply = call(&mut cpu, r#"{"jsonrpc":"2.0","id":1,"method":"step"}"#);
+        assert_eq!(reply.get("id"), Some(&Json::Number(1.0)));
+        let error = reply.get("error").unwrap();
+        assert_eq!(
+            error.get("code"),
+            Some(&Json::Number(INVALID_PARAMS as f64))
+        );
+
+        let reply = call(&mut cpu, r#"{"jsonrpc":"2.0","id":2,"method":"pause"}"#);
+        let result = reply.get("result").unwrap();
+        assert_eq!(result.get("state").and_then(Json::as_str), Some("paused"));
+        let reply = call(
+            &mut cpu,
+            r#"{"jsonrpc":"2.0","id":3,"method":"step","params":{"count":3}}"#,
+        );
+        let result = reply.get("result").unwrap();
+        assert_eq!(result.get("instructions").and_then(Json::as_u64), Some(3));
+
+        let reply = call(
+            &mut cpu,
+            r#"{"jsonrpc":"2.0","id":4,"method":"read_registers"}"#,
+        );
+        let Some(Json::Array(x)) = reply.get("result").and_then(|result| result.get("x")) else {
+            panic!("no registers in {reply}");
+        };
+        assert_eq!(x[10], Json::Number(6.0));
+
+        call(
+            &mut cpu,
+            r#"{"jsonrpc":"2.0","id":5,"method":"write_register","params":{"name":"mscratch","value":"a0 * 2"}}"#,
+        );
+        call(
+            &mut cpu,
+            r#"{"jsonrpc":"2.0","id":6,"method":"write_memory","params":{"address":256,"data":"cafe"}}"#,
+        );
+        let reply = call(
+            &mut cpu,
+            r#"{"jsonrpc":"2.0","id":7,"method":"read_memory","params":{"address":"0x100","length":2}}"#,
+        );
+        let result = reply.get("result").unwrap();
+        assert_eq!(result.get("data").and_then(Json::as_str), Some("cafe"));
+        assert_eq!(cpu.csrs.mscratch, 12);
+
+        let reply = call(
+            &mut cpu,
+            r#"{"jsonrpc":"2.0","id":8,"method":"inject_interrupt","params":{"cause":1}}"#,
+        );
+        assert_eq!(reply.get("result"), Some(&Json::Null));
+        assert_eq!(cpu.csrs.mip, 2);
+        let reply = call(
+            &mut cpu,
+            r#"{"jsonrpc":"2.0","id":9,"method":"inject_interrupt","params":{"cause":11}}"#,
+        );
+        assert!(reply.get("error").is_some());
+
+        let reply = call(&mut cpu, r#"{"jsonrpc":"2.0","id":10,"method":"reboot"}"#);
+        let error = reply.get("error").unwrap();
+        assert_eq!(
+            error.get("code"),
+            Some(&Json::Number(METHOD_NOT_FOUND as f64))
+        );
+        let reply = call(&mut cpu, "{not json");
+        assert_eq!(reply.get("id"), Some(&Json::Null));
+    }
+
+    #[test]
+    fn socket_session() {
+        let mut cpu = Cpu::new(false);
+        cpu.load(crate::asm::assemble("loop:\nj loop", 0).unwrap().bytes);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+        let client = std::thread::spawn(move || {
+            let stream = loop {
+                if let Ok(stream) = TcpStream::connect(address) {
+                    break stream;
+                }
+                std::thread::yield_now();
+            };
+            let mut lines = BufReader::new(stream.try_clone().unwrap()).lines();
+            let mut call = |line: &str| {
+                writeln!(&stream, "{line}").unwrap();
+                Json::parse(&lines.next().unwrap().unwrap()).unwrap()
+            };
+            let status = call(r#"{"jsonrpc":"2.0","id":1,"method":"status"}"#);
+            let result = status.get("result").unwrap();
+            assert_eq!(result.get("state").and_then(Json::as_str), Some("running"));
+            call(r#"{"jsonrpc":"2.0","id":2,"method":"pause"}"#);
+            // a program that never exits is ended by jumping to an exit ecall
+            call(
+                r#"{"jsonrpc":"2.0","id":3,"method":"write_memory","params":{"address":64,"data":"9308d00573000000"}}"#,
+            );
+            call(
+                r#"{"jsonrpc":"2.0","id":4,"method":"write_register","params":{"name":"a0","value":3}}"#,
+            );
+            call(
+                r#"{"jsonrpc":"2.0","id":5,"method":"write_register","params":{"name":"pc","value":64}}"#,
+            );
+            call(r#"{"jsonrpc":"2.0","id":6,"method":"resume"}"#);
+        });
+        let result = session(&mut cpu, &address.to_string());
+        client.join().unwrap();
+        assert_eq!(result.ok(), Some(StopReason::Exit(3)));
+    }
+}
@@ -0,0 +1,253 @@
+// Post-mortem dumps in the ELF core format of riscv32 linux, so that both `ruscv debug --core`
+// and gdb can inspect a crashed program offline. Every hart gets an NT_PRSTATUS note with its
+// pc and registers, the hart that stopped comes first. Ram is stored as PT_LOAD segments,
+// leaving out pages that are all zeros.
+use crate::cpu::{Cpu, StopReason};
+use crate::elf::ELF_MAGIC;
+use crate::error::Error;
+use crate::gdb;
+
+const ET_CORE: u16 = 4;
+const EM_RISCV: u16 = 243;
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const PF_RW: u32 = 6;
+const NT_PRSTATUS: u32 = 1;
+
+const EHDR_SIZE: usize = 52;
+const PHDR_SIZE: usize = 32;
+const PAGE_SIZE: usize = 4096;
+// layout of the 32-bit elf_prstatus: signal info, then the pid and at 72 the pc and x1-x31
+const PRSTATUS_SIZE: usize = 204;
+const PRSTATUS_PID: usize = 24;
+const PRSTATUS_REGS: usize = 72;
+const SIGSEGV: u8 = 11;
+
+// the signal the dump reports for the end of the run, like a linux kernel would send it
+pub fn signal(result: &Result<StopReason, Error>) -> u8 {
+    match result {
+        Ok(reason) => gdb::signal(reason),
+        Err(_) => SIGSEGV,
+    }
+}
+
+pub fn write(cpu: &mut Cpu, signal: u8) -> Vec<u8> {
+    let running = cpu.hart();
+    let harts = std::iter::once(running).chain((0..cpu.harts()).filter(|&id| id != running));
+    let mut notes = Vec::new();
+    for id in harts {
+        cpu.select_hart(id);
+        let mut status = vec![0; PRSTATUS_SIZE];
+        status[0..4].copy_from_slice(&(signal as u32).to_le_bytes());
+        status[12..14].copy_from_slice(&(signal as u16).to_le_bytes());
+        status[PRSTATUS_PID..PRSTATUS_PID + 4].copy_from_slice(&(id as u32 + 1).to_le_bytes());
+        status[PRSTATUS_REGS..PRSTATUS_REGS + 4].copy_from_slice(&cpu.pc.get().to_le_bytes());
+        for n in 1..32 {
+            let offset = PRSTATUS_REGS + n * 4;
+            status[offset..offset + 4].copy_from_slice(&cpu.regs.read(n).to_le_bytes());
+        }
+        notes.extend_from_slice(&5u32.to_le_bytes());
+        notes.extend_from_slice(&(PRSTATUS_SIZE as u32).to_le_bytes());
+        notes.extend_from_slice(&NT_PRSTATUS.to_le_bytes());
+        notes.extend_from_slice(b"CORE\0\0\0\0");
+        notes.extend_from_slice(&status);
+    }
+    cpu.select_hart(running);
+
+    let base = cpu.mem.ram_base();
+    let ram = cpu.mem.peek(base, cpu.mem.ram_size());
+    let segments = nonzero_runs(&ram);
+    let mut phdrs = Vec::new();
+    let mut offset = EHDR_SIZE + (segments.len() + 1) * PHDR_SIZE;
+    push_phdr(&mut phdrs, PT_NOTE, offset, 0, notes.len(), 0);
+    offset += notes.len();
+    for range in &segments {
+        push_phdr(
+            &mut phdrs,
+            PT_LOAD,
+            offset,
+            base + range.start as u32,
+            range.len(),
+            PF_RW,
+        );
+        offset += range.len();
+    }
+
+    let mut core = Vec::with_capacity(offset);
+    core.extend_from_slice(&ELF_MAGIC);
+    // 32-bit, little-endian, version 1
+    core.extend_from_slice(&[1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    core.extend_from_slice(&ET_CORE.to_le_bytes());
+    core.extend_from_slice(&EM_RISCV.to_le_bytes());
+    core.extend_from_slice(&1u32.to_le_bytes());
+    // no entry point and no section headers
+    core.extend_from_slice(&0u32.to_le_bytes());
+    core.extend_from_slice(&(EHDR_SIZE as u32).to_le_bytes());
+    core.extend_from_slice(&0u32.to_le_bytes());
+    core.extend_from_slice(&0u32.to_le_bytes());
+    core.extend_from_slice(&(EHDR_SIZE as u16).to_le_bytes());
+    core.extend_from_slice(&(PHDR_SIZE as u16).to_le_bytes());
+    core.extend_from_slice(&(segments.len() as u16 + 1).to_le_bytes());
+    core.extend_from_slice(&[0; 6]);
+    core.extend_from_slice(&phdrs);
+    core.extend_from_slice(&notes);
+    for range in segments {
+        core.extend_from_slice(&ram[range]);
+    }
+    core
+}
+
+// ranges of whole pages of the image that contain a non-zero byte, neighbours are merged
+fn nonzero_runs(image: &[u8]) -> Vec<std::ops::Range<usize>> {
+    let mut runs: Vec<std::ops::Range<usize>> = Vec::new();
+    for (index, page) in image.chunks(PAGE_SIZE).enumerate() {
+        if page.iter().all(|&byte| byte == 0) {
+            continue;
+        }
+        let start = index * PAGE_SIZE;
+        match runs.last_mut() {
+            Some(run) if run.end == start => run.end += page.len(),
+            _ => runs.push(start..start + page.len()),
+        }
+    }
+    runs
+}
+
+fn push_phdr(phdrs: &mut Vec<u8>, kind: u32, offset: usize, address: u32, size: usize, flags: u32) {
+    for field in [
+        kind,
+        offset as u32,
+        address,
+        address,
+        size as u32,
+        size as u32,
+        flags,
+    ] {
+        phdrs.extend_from_slice(&field.to_le_bytes());
+    }
+    // alignment
+    phdrs.extend_from_slice(&(if kind == PT_LOAD { PAGE_SIZE as u32 } else { 4 }).to_le_bytes());
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> Result<u16, Error> {
+    bytes
+        .get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes(b.try_into().unwrap()))
+        .ok_or(Error::InvalidElf("truncated core file"))
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Result<u32, Error> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+        .ok_or(Error::InvalidElf("truncated core file"))
+}
+
+fn slice(bytes: &[u8], offset: usize, len: usize) -> Result<&[u8], Error> {
+    bytes
+        .get(offset..offset.saturating_add(len))
+        .ok_or(Error::InvalidElf("core segment outside of file"))
+}
+
+// Restores the memory and the harts' registers of a core written by `write`. The running hart
+// becomes the one that stopped.
+pub fn load(cpu: &mut Cpu, bytes: &[u8]) -> Result<(), Error> {
+    if !bytes.starts_with(&ELF_MAGIC) || bytes.get(4..6) != Some(&[1, 1]) {
+        return Err(Error::InvalidElf("not a 32-bit little-endian elf"));
+    }
+    if u16_at(bytes, 16)? != ET_CORE || u16_at(bytes, 18)? != EM_RISCV {
+        return Err(Error::InvalidElf("not a risc-v core file"));
+    }
+    let phoff = u32_at(bytes, 28)? as usize;
+    let phentsize = u16_at(bytes, 42)? as usize;
+    let mut harts = Vec::new();
+    for i in 0..u16_at(bytes, 44)? as usize {
+        let header = phoff + i * phentsize;
+        let offset = u32_at(bytes, header + 4)? as usize;
+        let address = u32_at(bytes, header + 8)?;
+        let data = slice(bytes, offset, u32_at(bytes, header + 16)? as usize)?;
+        match u32_at(bytes, header)? {
+            PT_LOAD => {
+                let end = address as u64 + data.len() as u64;
+                if address < cpu.mem.ram_base() || end > cpu.mem.ram_end() {
+                    return Err(Error::InvalidElf("core segment outside of ram"));
+                }
+                cpu.mem.write_bytes(address, data);
+            }
+            PT_NOTE => harts.extend(prstatus_notes(data)?),
+            _ => (),
+        }
+    }
+    for (id, regs) in harts.iter().rev() {
+        if !cpu.select_hart(*id) {
+            return Err(Error::InvalidElf("core has more harts than the machine"));
+        }
+        cpu.pc.set(regs[0]);
+        for (n, &value) in regs.iter().enumerate().skip(1) {
+            cpu.regs.write(n, value);
+        }
+    }
+    Ok(())
+}
+
+// the hart ids and the pc followed by x1-x31 of all NT_PRSTATUS notes
+fn prstatus_notes(mut notes: &[u8]) -> Result<Vec<(usize, [u32; 32])>, Error> {
+    let mut harts = Vec::new();
+    while notes.len() >= 12 {
+        let name_size = u32_at(notes, 0)? as usize;
+        let desc_size = u32_at(notes, 4)? as usize;
+        let desc_start = 12 + name_size.next_multiple_of(4);
+        let desc = slice(notes, desc_start, desc_size)?;
+        if u32_at(notes, 8)? == NT_PRSTATUS && desc_size >= PRSTATUS_REGS + 128 {
+            let id = u32_at(desc, PRSTATUS_PID)?.saturating_sub(1) as usize;
+            let mut regs = [0; 32];
+            for (n, reg) in regs.iter_mut().enumerate() {
+                *reg = u32_at(desc, PRSTATUS_REGS + n * 4)?;
+            }
+            harts.push((id, regs));
+        }
+        notes = &notes[(desc_start + desc_size.next_multiple_of(4)).min(notes.len())..];
+    }
+    Ok(harts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regs::Reg;
+
+    #[test]
+    fn core_round_trip() {
+        let mut cpu = Cpu::new(false);
+        cpu.set_harts(2);
+        cpu.load(vec![0x13, 0, 0, 0]);
+        let base = cpu.mem.ram_base();
+        cpu.mem.write_bytes(base + 0x2000, &[1, 2, 3, 4]);
+        cpu.regs.set(Reg::A0, 0x1234);
+        cpu.pc.set(base + 0x10);
+        cpu.select_hart(1);
+        cpu.regs.set(Reg::Sp, 0x4242);
+        let core = write(&mut cpu, SIGSEGV);
+        assert_eq!(cpu.hart(), 1);
+        // the notes and the two non-zero pages, the rest of ram is left out
+        assert_eq!(u16_at(&core, 44).unwrap(), 3);
+        assert!(core.len() < 3 * PAGE_SIZE);
+
+        let mut restored = Cpu::new(false);
+        restored.set_harts(2);
+        restored.load(Vec::new());
+        load(&mut restored, &core).unwrap();
+        assert_eq!(restored.hart(), 1);
+        assert_eq!(restored.regs.get(Reg::Sp), 0x4242);
+        assert_eq!(&*restored.mem.peek(base + 0x2000, 4), &[1, 2, 3, 4]);
+        assert_eq!(&*restored.mem.peek(base, 4), &[0x13, 0, 0, 0]);
+        restored.select_hart(0);
+        assert_eq!(restored.regs.get(Reg::A0), 0x1234);
+        assert_eq!(restored.pc.get(), base + 0x10);
+
+        let mut single = Cpu::new(false);
+        single.load(Vec::new());
+        assert!(load(&mut single, &core).is_err());
+        assert!(load(&mut single, &core[..40]).is_err());
+    }
+}
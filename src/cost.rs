@@ -0,0 +1,177 @@
+// Abstract cost model, e.g. the energy of every instruction on the target chip. The costs are
+// read from a flat table mapping mnemonics to numbers, either a json object
+// (`{"lw": 2.5, "mul": 4}`) or toml key-value pairs (`lw = 2.5`). A mnemonic without an entry
+// falls back to its prefix before the last dot (amoswap.w.aq, amoswap.w, amoswap), the `*` entry
+// sets the cost of instructions that aren't listed at all (default: 0).
+use crate::decode::decode;
+use crate::graph;
+use crate::listing::Image;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+pub struct CostTable {
+    costs: HashMap<String, f64>,
+    default: f64,
+}
+
+impl CostTable {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let json = text.trim_start().starts_with('{');
+        let entries: Vec<&str> = if json {
+            let object = text
+                .trim()
+                .strip_prefix('{')
+                .and_then(|t| t.strip_suffix('}'));
+            object
+                .ok_or("unterminated json object")?
+                .split(',')
+                .collect()
+        } else {
+            text.lines()
+                .map(|line| line.split('#').next().unwrap_or_default())
+                .collect()
+        };
+        let separator = if json { ':' } else { '=' };
+
+        let mut table = CostTable {
+            costs: HashMap::new(),
+            default: 0.0,
+        };
+        for entry in entries.iter().map(|entry| entry.trim()) {
+            if entry.is_empty() {
+                continue;
+            }
+            let Some((key, value)) = entry.split_once(separator) else {
+                return Err(format!(
+                    "expected '<mnemonic> {separator} <cost>', got '{entry}'"
+                ));
+            };
+            let key = key.trim();
+            let key = key
+                .strip_prefix('"')
+                .and_then(|key| key.strip_suffix('"'))
+                .unwrap_or(key);
+            let Ok(cost) = value.trim().parse() else {
+                return Err(format!("invalid cost '{}' of '{key}'", value.trim()));
+            };
+            if key == "*" {
+                table.default = cost;
+            } else {
+                table.costs.insert(key.to_lowercase(), cost);
+            }
+        }
+        Ok(table)
+    }
+
+    pub fn cost(&self, mnemonic: &str) -> f64 {
+        let mut name = mnemonic;
+        loop {
+            if let Some(&cost) = self.costs.get(name) {
+                return cost;
+            }
+            match name.rsplit_once('.') {
+                Some((prefix, _)) => name = prefix,
+                None => return self.default,
+            }
+        }
+    }
+}
+
+pub struct FunctionCost {
+    pub name: String,
+    // executed instructions
+    pub instructions: u64,
+    pub cost: f64,
+}
+
+// Accumulates the cost of the executed instructions (`counts` per address) per function, the most
+// expensive function first. Instructions are attributed to the closest entry point, function
+// symbol or call target before them, instructions outside the image aren't counted.
+pub fn profile(table: &CostTable, image: &Image, counts: &HashMap<u32, u64>) -> Vec<FunctionCost> {
+    let graph = graph::build(image);
+    let mut functions: BTreeMap<Option<u32>, FunctionCost> = BTreeMap::new();
+    for (&pc, &count) in counts {
+        let Some(Ok(inst)) = image.word(pc).map(decode) else {
+            continue;
+        };
+        let text = inst.to_string();
+        let mnemonic = text.split(' ').next().unwrap_or_default();
+        let function = graph.function(pc);
+        let entry = functions.entry(function).or_insert_with(|| FunctionCost {
+            name: function.map_or("?".to_string(), |f| graph::Graph::name(image, f)),
+            instructions: 0,
+            cost: 0.0,
+        });
+        entry.instructions += count;
+        entry.cost += table.cost(mnemonic) * count as f64;
+    }
+    let mut profile: Vec<_> = functions.into_values().collect();
+    profile.sort_by(|a, b| b.cost.total_cmp(&a.cost));
+    profile
+}
+
+pub fn report(profile: &[FunctionCost]) -> String {
+    let total: f64 = profile.iter().map(|function| function.cost).sum();
+    let mut out = format!("cost profile, {total} in total:\n");
+    let _ = writeln!(
+        out,
+        "{:>14} {:>7} {:>14}  function",
+        "cost", "%", "instructions"
+    );
+    for function in profile {
+        let share = function.cost / total.max(f64::MIN_POSITIVE) * 100.0;
+        let _ = writeln!(
+            out,
+            "{:>14.1} {share:>6.1}% {:>14}  {}",
+            function.cost, function.instructions, function.name
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_tables() {
+        let json = CostTable::parse(r#"{"addi": 1, "amoswap.w": 4.5, "*": 2}"#).unwrap();
+        let toml =
+            CostTable::parse("# energy in pJ\naddi = 1\n\"amoswap.w\" = 4.5 # atomic\n\"*\" = 2\n")
+                .unwrap();
+        for table in [json, toml] {
+            assert_eq!(table.cost("addi"), 1.0);
+            assert_eq!(table.cost("amoswap.w.aq"), 4.5);
+            assert_eq!(table.cost("mul"), 2.0);
+        }
+        assert!(CostTable::parse("{\"addi\": 1").is_err());
+        assert!(CostTable::parse("addi = one").is_err());
+    }
+
+    #[test]
+    fn cost_per_function() {
+        let bytes: Vec<u8> = [
+            0x00300513u32, // addi a0, zero, 3
+            0x010000ef,    // jal ra, 16
+            0xfff50513,    // addi a0, a0, -1
+            0xfe051ee3,    // bne a0, zero, -4
+            0x00008067,    // jalr zero, 0(ra)
+            0x00158593,    // addi a1, a1, 1
+            0x00008067,    // jalr zero, 0(ra)
+        ]
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .collect();
+        let image = Image::raw(&bytes, 0);
+        let counts = HashMap::from([(0, 1), (4, 1), (8, 3), (12, 3), (16, 1), (20, 1), (24, 1)]);
+        let table = CostTable::parse("addi = 1\nbne = 2\njal = 5\njalr = 5").unwrap();
+
+        let profile = profile(&table, &image, &counts);
+        let costs: Vec<_> = profile
+            .iter()
+            .map(|function| (function.name.as_str(), function.instructions, function.cost))
+            .collect();
+        assert_eq!(costs, [("0x0", 9, 20.0), ("0x14", 2, 6.0)]);
+    }
+}
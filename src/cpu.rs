@@ -1,406 +1,3220 @@
+use crate::access_log::AccessLog;
+use crate::clock::{Clock, TimeSource};
+use crate::csr::*;
+use crate::decode::decode;
+use crate::devices::{
+    BootRom, Capture, Device, Gpio, GpioPins, MappedFile, Tracepoint, BOOTROM_BASE, MAX_HARTS,
+};
+use crate::elf::Elf;
+use crate::env::Env;
 use crate::error::*;
-use crate::get_bits;
-use crate::inst::*;
-use crate::inst_format::*;
+use crate::expr::{self, Expr};
+use crate::fdt::{self, TIMEBASE_FREQUENCY};
+use crate::fs::FileSystem;
+use crate::hart::{HartConfig, HartState};
+use crate::history::{InstHistory, RegHistory};
+use crate::inst::{Hint, Inst};
+use crate::malloc::Allocations;
 use crate::memory::*;
+use crate::mmu;
 use crate::pc::*;
+use crate::progress::Progress;
 use crate::regs::*;
+use crate::rng::Rng;
+use crate::sbi::{self, Sbi};
+use crate::scheduler::Scheduler;
+use crate::stack::{self, Link, StackUsage};
+use crate::state_hash::{HashMode, StateHash};
+use crate::syscall::{self, Heap, Syscall};
+use crate::throttle::Throttle;
+use crate::trace::{TraceFilter, TraceWriter};
+use crate::trap::{Exception, INTERRUPT};
+use crate::trigger::{Access, Triggers};
+use crate::vector::{VectorUnit, DEFAULT_VLEN};
+use crate::watch::{DebugStop, Watchpoints};
 
-enum ProgState {
-    Continue,
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+const PAGE_SIZE: u64 = 4096;
+
+// stack size of each hart if no --stack-size is given, the stacks of the harts are stacked below
+// each other at the end of ram
+const HART_STACK_SIZE: u32 = 0x1000;
+
+// upper bound for a single host sleep while waiting, so that input from stdin is noticed quickly
+const MAX_IDLE_CYCLES: u64 = TIMEBASE_FREQUENCY as u64 / 100;
+
+// isa string reported to the guest
+pub const ISA: &str = "rv32ima_zicond_zbkb_zbkx_zknd_zkne_zve32x";
+
+// Why the emulation stopped, returned by `run` and `step`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StopReason {
+    // the program exited through the exit syscall, an sbi shutdown, the sifive_test finisher or
+    // tohost
     Exit(u8),
+    // ebreak or a trigger fired while no trap handler was installed, holds the breakpoint address
+    Break(u32),
+    // exception raised while no trap handler was installed
+    Trap(Exception),
+    // the cycle limit was reached
+    Limit(usize),
+    // the host asked the emulation to stop through the stop handle
+    HostRequest,
+    // the instructions given to `run_for` are used up, the program can be resumed
+    Yield,
+    // a breakpoint or watchpoint of an attached debugger, the instruction didn't execute yet
+    Debug(DebugStop),
+}
+
+impl StopReason {
+    // exiting is the only way a program finishes successfully, everything else is an error
+    pub fn into_result(self) -> Result<u8, Error> {
+        match self {
+            StopReason::Exit(code) => Ok(code),
+            StopReason::Break(address) => Err(Error::Trap(Exception::Breakpoint(address))),
+            StopReason::Trap(exception) => Err(Error::Trap(exception)),
+            StopReason::Limit(cycles) => Err(Error::CycleLimit(cycles)),
+            StopReason::HostRequest | StopReason::Yield | StopReason::Debug(_) => {
+                Err(Error::Stopped)
+            }
+        }
+    }
 }
 
+// called once the program stopped, e.g. to dump memory or write statistics
+pub type ExitHook = Box<dyn FnMut(&mut Cpu, &StopReason)>;
+
+// called with the pc of every hint instruction before it executes as a nop
+pub type HintHook = Box<dyn FnMut(&mut Cpu, u32, Hint)>;
+
+// runs on the host instead of a guest function and returns the function's result
+pub type Stub = Box<dyn FnMut(&mut Cpu) -> u32>;
+
 pub struct Cpu {
     pub pc: ProgramCounter,
     pub regs: Registers,
     pub mem: Memory,
+    pub csrs: Csrs,
+    pub vector: VectorUnit,
+    pub triggers: Triggers,
+    // breakpoints and watchpoints of an attached debugger, shared by all harts
+    pub watchpoints: Watchpoints,
+    // hart and address of the instruction a debugger stopped at, it doesn't stop again once resumed
+    debug_stop: Option<(usize, u32)>,
     print_debug: bool,
+    // limits the debug output and the trace to instructions at matching addresses
+    pub trace_filter: TraceFilter,
+    // trace of the executed instructions and their register writes
+    trace: Option<TraceWriter>,
+    // signature of the run, the hash of the commit stream so far in commit mode
+    state_hash: Option<(HashMode, StateHash)>,
+    // heartbeat printed every few million instructions
+    progress: Option<Progress>,
+    // caps the instructions executed per second
+    throttle: Option<Throttle>,
+    // instructions retired since the program started, unlike minstret not writable by the guest
+    retired: u64,
+    // how often the instruction at each address was fetched, for execution-count annotations
+    exec_counts: Option<HashMap<u32, u64>>,
+    // tracing stays disabled until the pc first reaches this address
+    run_to: Option<u32>,
+    // instruction fetched in the current cycle, if any
+    fetched: Option<u32>,
+    // whether a device tree is placed in memory and passed in a1 at reset
+    pass_dtb: bool,
+    // entry point of the loaded program: the elf's entry or the start of ram
+    entry: u32,
+    // entry point set by the host, overrides the program's
+    reset_pc: Option<u32>,
+    // whether execution starts in the boot rom instead of directly at the entry point
+    bootrom: bool,
+    // whether the harts start with big-endian data accesses (mstatush.MBE)
+    big_endian: bool,
+    // set if ecalls are handled by the built-in sbi firmware
+    pub sbi: Option<Sbi>,
+    // program break managed by the brk syscall
+    pub heap: Heap,
+    // base and size of the files mapped with map_file, file descriptor 3 refers to the first one
+    pub mapped_files: Vec<(u32, u32)>,
+    // host files the program can open
+    pub files: FileSystem,
+    // randomness handed to the program through getrandom and /dev/urandom, seeded for replays
+    pub entropy: Rng,
+    // address reserved by the last lr.w, consumed by sc.w
+    pub reservation: Option<u32>,
+    // set by ecalls that stop the emulation, like the exit syscall
+    stop: Option<StopReason>,
+    // set by ecalls that reset the machine once the instruction completed
+    reset_requested: bool,
+    // set by the host to stop the emulation from another thread
+    stop_requested: Arc<AtomicBool>,
+    exit_hooks: Vec<ExitHook>,
+    hint_hooks: Vec<HintHook>,
+    // host functions replacing the guest functions at their addresses
+    stubs: HashMap<u32, Stub>,
+    // addresses of the loaded program's symbols, for the debugger expressions
+    pub symbols: HashMap<String, u32>,
+    // expressions printed whenever their value changes, with the last value
+    watch_exprs: Vec<(Expr, Option<Result<i64, String>>)>,
+    // cycles executed since the program started
+    cycles: usize,
+    // time reported by the time csr and the time syscalls
+    pub clock: Clock,
+    // gives ecalls their meaning, linux syscalls by default
+    env: Env,
+    // unknown syscalls of programs without trap handler stop the emulation instead of being ignored
+    strict_syscalls: bool,
+    // prints every syscall made by the program
+    strace: bool,
+    // receives what the program writes to stdout and stderr or the sbi console instead of the host
+    pub console: Option<Capture>,
+    // encodings executed as nops that were already reported, set in pedantic mode
+    pedantic: Option<HashSet<u32>>,
+    // csv log of the tracepoints hit by the program
+    tracepoints: Option<Box<dyn Write>>,
+    // loads and stores to the regions of interest
+    access_log: Option<AccessLog>,
+    // lowest sp and deepest function stacks of each hart
+    stack_usage: Option<HashMap<usize, StackUsage>>,
+    // calls into the program's allocator, observed once heap usage is reported
+    allocations: Option<Allocations>,
+    // the host's side of the gpio pins, once the gpio block is mapped
+    gpio: Option<GpioPins>,
+    // size of the stack, a guard page is placed below it if set
+    stack_size: Option<u32>,
+    // stops programs that would otherwise run forever
+    cycle_limit: Option<usize>,
+    // recently executed instructions, printed when the emulation fails
+    pub inst_history: InstHistory,
+    // recent register writes, printed when the emulation fails
+    pub reg_history: Option<RegHistory>,
+    // suppresses the register dump once the program finished
+    quiet: bool,
+    // set by wfi, the hart doesn't execute instructions until an interrupt is pending
+    pub waiting: bool,
+    // whether waiting harts sleep the host until the next device event or just skip the time
+    idle_sleep: bool,
+    // the running hart doesn't execute until it receives a software interrupt
+    parked: bool,
+    // boot parameters, one entry per hart
+    hart_configs: Vec<HartConfig>,
+    // state of the harts that aren't running, the slot of the running hart is unused
+    harts: Vec<HartState>,
+    // id of the running hart
+    hart: usize,
+    // picks the hart that executes in each cycle
+    scheduler: Scheduler,
 }
 
 impl Cpu {
     pub fn new(print_debug: bool) -> Self {
         Cpu {
             print_debug,
+            trace_filter: TraceFilter::new(),
+            trace: None,
+            state_hash: None,
+            progress: None,
+            throttle: None,
+            retired: 0,
+            exec_counts: None,
+            run_to: None,
+            fetched: None,
             pc: ProgramCounter::new(),
             regs: Registers::new(),
             mem: Memory::new(),
+            csrs: Csrs::new(),
+            vector: VectorUnit::new(DEFAULT_VLEN),
+            triggers: Triggers::new(),
+            watchpoints: Watchpoints::new(),
+            debug_stop: None,
+            pass_dtb: false,
+            entry: 0,
+            reset_pc: None,
+            bootrom: false,
+            big_endian: false,
+            sbi: None,
+            heap: Heap::new(0),
+            mapped_files: Vec::new(),
+            files: FileSystem::new(),
+            entropy: Rng::new(0),
+            reservation: None,
+            stop: None,
+            reset_requested: false,
+            stop_requested: Arc::new(AtomicBool::new(false)),
+            exit_hooks: Vec::new(),
+            hint_hooks: Vec::new(),
+            stubs: HashMap::new(),
+            symbols: HashMap::new(),
+            watch_exprs: Vec::new(),
+            cycles: 0,
+            clock: Clock::new(TimeSource::Virtual),
+            env: Env::Linux,
+            strict_syscalls: false,
+            strace: false,
+            console: None,
+            pedantic: None,
+            tracepoints: None,
+            access_log: None,
+            stack_usage: None,
+            allocations: None,
+            gpio: None,
+            stack_size: None,
+            cycle_limit: None,
+            inst_history: InstHistory::new(),
+            reg_history: None,
+            quiet: false,
+            waiting: false,
+            idle_sleep: true,
+            parked: false,
+            hart_configs: vec![HartConfig::default()],
+            harts: Vec::new(),
+            hart: 0,
+            scheduler: Scheduler::round_robin(1),
+        }
+    }
+
+    // Copy of the machine that can run on independently, e.g. to explore many inputs from a common
+    // snapshot. Ram is shared copy-on-write, so forking is cheap no matter how large it is. Host-side
+    // tooling like traces, statistics, debugger state and exit hooks stays with the original. None if
+    // a device is tied to a host resource, like the flash file or the network tunnel, or if stubs
+    // are registered, as their host state can't be copied.
+    pub fn fork(&mut self) -> Option<Cpu> {
+        if !self.stubs.is_empty() {
+            return None;
+        }
+        Some(Cpu {
+            print_debug: false,
+            trace_filter: TraceFilter::new(),
+            trace: None,
+            state_hash: None,
+            progress: None,
+            throttle: None,
+            retired: self.retired,
+            exec_counts: None,
+            run_to: None,
+            fetched: None,
+            pc: self.pc.clone(),
+            regs: self.regs.clone(),
+            mem: self.mem.fork()?,
+            csrs: self.csrs.clone(),
+            vector: self.vector.clone(),
+            triggers: self.triggers.clone(),
+            watchpoints: Watchpoints::new(),
+            debug_stop: None,
+            pass_dtb: self.pass_dtb,
+            entry: self.entry,
+            reset_pc: self.reset_pc,
+            bootrom: self.bootrom,
+            big_endian: self.big_endian,
+            sbi: self.sbi.as_ref().map(Sbi::fork),
+            heap: self.heap.clone(),
+            mapped_files: self.mapped_files.clone(),
+            files: self.files.fork(),
+            entropy: self.entropy.clone(),
+            reservation: self.reservation,
+            stop: None,
+            reset_requested: false,
+            stop_requested: Arc::new(AtomicBool::new(false)),
+            exit_hooks: Vec::new(),
+            hint_hooks: Vec::new(),
+            stubs: HashMap::new(),
+            symbols: self.symbols.clone(),
+            watch_exprs: Vec::new(),
+            cycles: self.cycles,
+            clock: self.clock.clone(),
+            env: self.env,
+            strict_syscalls: self.strict_syscalls,
+            strace: false,
+            console: self.console.clone(),
+            pedantic: None,
+            tracepoints: None,
+            access_log: None,
+            stack_usage: None,
+            allocations: None,
+            gpio: None,
+            stack_size: self.stack_size,
+            cycle_limit: self.cycle_limit,
+            inst_history: InstHistory::new(),
+            reg_history: None,
+            quiet: self.quiet,
+            waiting: self.waiting,
+            idle_sleep: self.idle_sleep,
+            parked: self.parked,
+            hart_configs: self.hart_configs.clone(),
+            harts: self.harts.clone(),
+            hart: self.hart,
+            scheduler: self.scheduler.clone(),
+        })
+    }
+
+    // Handles ecalls as sbi calls, so supervisor-mode kernels can run without separate firmware.
+    pub fn enable_sbi(&mut self) {
+        self.set_env(Env::Sbi);
+    }
+
+    // Selects the environment that handles ecalls. The htif environment is enabled with
+    // `enable_htif`, which also needs the address of the tohost word.
+    pub fn set_env(&mut self, env: Env) {
+        self.env = env;
+        self.sbi = (env == Env::Sbi).then(Sbi::new);
+    }
+
+    pub fn env(&self) -> Env {
+        self.env
+    }
+
+    pub fn enable_dtb(&mut self) {
+        self.pass_dtb = true;
+    }
+
+    // starts the harts at the address instead of the loaded program's entry point
+    pub fn set_reset_pc(&mut self, address: u32) {
+        self.reset_pc = Some(address);
+    }
+
+    fn reset_pc(&self) -> u32 {
+        self.reset_pc.unwrap_or(self.entry)
+    }
+
+    pub fn enable_bootrom(&mut self) {
+        self.bootrom = true;
+    }
+
+    pub fn set_big_endian(&mut self) {
+        self.big_endian = true;
+    }
+
+    // Terminates once the program writes to the tohost word like the riscv-tests environment does.
+    pub fn enable_htif(&mut self, tohost: u32) {
+        self.set_env(Env::Htif);
+        self.mem.set_tohost(tohost);
+    }
+
+    // Stops at syscalls that aren't emulated with `Error::UnimplementedSyscall`, unless the
+    // program handles them itself.
+    pub fn enable_strict_syscalls(&mut self) {
+        self.strict_syscalls = true;
+    }
+
+    // prints the syscalls with their arguments and results to stderr, like strace
+    pub fn enable_strace(&mut self) {
+        self.strace = true;
+    }
+
+    // Reports architecturally defined instructions that are executed as nops, like fences and
+    // hints, the first time each encoding executes.
+    pub fn enable_pedantic(&mut self) {
+        self.pedantic.get_or_insert_with(HashSet::new);
+    }
+
+    pub fn set_stack_size(&mut self, bytes: u32) {
+        self.stack_size = Some(bytes);
+    }
+
+    // Maps the contents of a host file read-only at the given address, the program can also
+    // look up the mapping with the mmap syscall.
+    pub fn map_file(&mut self, address: u32, data: Vec<u8>) -> Result<(), Error> {
+        let file = MappedFile::new(address, data);
+        if self.mem.overlaps(address, file.size()) {
+            return Err(Error::MappingOverlap(address));
+        }
+        self.mapped_files.push((address, file.size()));
+        self.mem.add_device(Box::new(file));
+        Ok(())
+    }
+
+    // Emulates `count` harts sharing the memory, the scheduler decides which one executes next.
+    pub fn set_harts(&mut self, count: usize) {
+        self.hart_configs
+            .resize(count.clamp(1, MAX_HARTS), HartConfig::default());
+    }
+
+    pub fn harts(&self) -> usize {
+        self.hart_configs.len()
+    }
+
+    pub fn set_scheduler(&mut self, scheduler: Scheduler) {
+        self.scheduler = scheduler;
+    }
+
+    pub fn configure_hart(&mut self, id: usize, config: HartConfig) {
+        self.hart_configs[id] = config;
+    }
+
+    pub fn set_cycle_limit(&mut self, cycles: usize) {
+        self.cycle_limit = Some(cycles);
+    }
+
+    // records the last `writes` register writes
+    pub fn enable_reg_history(&mut self, writes: usize) {
+        self.regs.enable_change_log();
+        self.reg_history = Some(RegHistory::new(writes));
+    }
+
+    pub fn enable_trace(&mut self, trace: TraceWriter) {
+        self.regs.enable_change_log();
+        self.trace = Some(trace);
+    }
+
+    pub fn enable_state_hash(&mut self, mode: HashMode) {
+        if mode == HashMode::Commit {
+            self.regs.enable_change_log();
+        }
+        self.state_hash = Some((mode, StateHash::new()));
+    }
+
+    // The signature of the run so far, the final state is hashed when this is called. None unless
+    // enabled.
+    pub fn state_hash(&self) -> Option<u64> {
+        let (mode, mut hash) = self.state_hash?;
+        if mode == HashMode::Final {
+            for id in 0..self.harts() {
+                let (pc, regs) = match self.harts.get(id).filter(|_| id != self.hart) {
+                    Some(state) => (&state.pc, &state.regs),
+                    None => (&self.pc, &self.regs),
+                };
+                hash.write_u32(pc.get());
+                (1..32).for_each(|n| hash.write_u32(regs.read(n)));
+            }
+            hash.write(&self.mem.peek(self.mem.ram_base(), self.mem.ram_size()));
+        }
+        Some(hash.value())
+    }
+
+    // Runs at full speed without debug output or trace until the pc first reaches the address.
+    pub fn set_run_to(&mut self, address: u32) {
+        self.run_to = Some(address);
+    }
+
+    // Maps the tracepoint register and logs every write to it as `cycle,hart,id,a0`.
+    pub fn enable_tracepoints(&mut self, mut log: Box<dyn Write>) -> std::io::Result<()> {
+        writeln!(log, "cycle,hart,id,a0")?;
+        self.tracepoints = Some(log);
+        self.mem.add_device(Box::new(Tracepoint::new()));
+        Ok(())
+    }
+
+    pub fn enable_stack_usage(&mut self) {
+        self.stack_usage = Some(HashMap::new());
+    }
+
+    // the stack usage of every hart that ran, None unless enabled
+    pub fn stack_report(&self) -> Option<String> {
+        let usages = self.stack_usage.as_ref()?;
+        let mut harts: Vec<_> = usages.iter().collect();
+        harts.sort_by_key(|&(&hart, _)| hart);
+        let reports = harts
+            .iter()
+            .map(|(&hart, usage)| usage.report(hart, &self.symbols));
+        Some(reports.collect())
+    }
+
+    pub fn enable_heap_usage(&mut self) {
+        let mut allocations = Allocations::new();
+        allocations.set_symbols(&self.symbols);
+        self.allocations = Some(allocations);
+    }
+
+    // The program break and its peak, and the allocations of each callsite if the program has a
+    // known allocator. None unless enabled.
+    pub fn heap_report(&self) -> Option<String> {
+        let allocations = self.allocations.as_ref()?;
+        let mut report = self.heap.report();
+        if allocations.found() {
+            report.push_str(&allocations.report(&self.symbols));
+        }
+        Some(report)
+    }
+
+    // Logs the loads and stores that touch the regions with the cycle, hart and pc.
+    pub fn enable_access_log(&mut self, log: AccessLog, regions: Vec<Range<u32>>) {
+        self.mem.enable_access_log(regions);
+        self.access_log = Some(log);
+    }
+
+    // Maps the gpio block, changes of the output pins are logged as `cycle,pin,level` if a log is
+    // given. The returned pins let the host drive the inputs.
+    pub fn enable_gpio(&mut self, mut log: Option<Box<dyn Write>>) -> std::io::Result<GpioPins> {
+        if let Some(log) = log.as_mut() {
+            writeln!(log, "cycle,pin,level")?;
+        }
+        let gpio = Gpio::new(log);
+        let pins = gpio.pins();
+        self.gpio = Some(pins.clone());
+        self.mem.add_device(Box::new(gpio));
+        Ok(pins)
+    }
+
+    pub fn gpio(&self) -> Option<&GpioPins> {
+        self.gpio.as_ref()
+    }
+
+    // limits the emulation to the given millions of instructions per second
+    pub fn enable_throttle(&mut self, mips: f64) {
+        self.throttle = Some(Throttle::new(mips));
+    }
+
+    // reports progress every `interval` retired instructions
+    pub fn enable_progress(&mut self, interval: u64) {
+        self.progress = Some(Progress::new(interval));
+    }
+
+    // Registers a hook that is called once `run` stopped, hooks are called in the order they were
+    // registered.
+    pub fn on_exit(&mut self, hook: impl FnMut(&mut Cpu, &StopReason) + 'static) {
+        self.exit_hooks.push(Box::new(hook));
+    }
+
+    // Registers a hook that observes the hint instructions the program executes, e.g. to collect
+    // prefetch addresses. Hooks are called in the order they were registered.
+    pub fn on_hint(&mut self, hook: impl FnMut(&mut Cpu, u32, Hint) + 'static) {
+        self.hint_hooks.push(Box::new(hook));
+    }
+
+    // Replaces the guest function at the address with a host function, e.g. to fake rand() or a
+    // hal's sensor reads when unit-testing firmware. Once the pc reaches the address the stub runs
+    // instead, it can read the arguments from a0.. and access memory, its result is returned in a0
+    // and execution continues at ra as if the function returned.
+    pub fn stub(&mut self, address: u32, stub: impl FnMut(&mut Cpu) -> u32 + 'static) {
+        self.stubs.insert(address, Box::new(stub));
+    }
+
+    // stubs the function named by a symbol of the loaded program, false if there is no such symbol
+    pub fn stub_symbol(&mut self, name: &str, stub: impl FnMut(&mut Cpu) -> u32 + 'static) -> bool {
+        match self.symbols.get(name) {
+            Some(&address) => {
+                self.stub(address, stub);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Setting the returned flag stops the emulation with `StopReason::HostRequest` at the next
+    // cycle, e.g. from a ctrl-c handler.
+    pub fn stop_handle(&self) -> Arc<AtomicBool> {
+        self.stop_requested.clone()
+    }
+
+    // stops the emulation once the current instruction completed
+    pub fn request_stop(&mut self, reason: StopReason) {
+        self.stop = Some(reason);
+    }
+
+    // counts how often every instruction is executed
+    pub fn enable_exec_counts(&mut self) {
+        self.exec_counts = Some(HashMap::new());
+    }
+
+    pub fn exec_counts(&self) -> Option<&HashMap<u32, u64>> {
+        self.exec_counts.as_ref()
+    }
+
+    pub fn retired(&self) -> u64 {
+        self.retired
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles as u64
+    }
+
+    // Redirects everything the program prints, through syscalls, sbi calls or the console devices,
+    // into the returned buffer instead of the host's stdout and stderr.
+    pub fn capture_output(&mut self) -> Capture {
+        let capture = self.mem.capture_output();
+        self.console = Some(capture.clone());
+        capture
+    }
+
+    // Waiting harts skip ahead to the next device event without sleeping the host, the time only
+    // passes on the device clocks. For fuzzers and servers, where wfi mustn't block the thread.
+    pub fn disable_idle_sleep(&mut self) {
+        self.idle_sleep = false;
+    }
+
+    pub fn set_quiet(&mut self) {
+        self.quiet = true;
+    }
+
+    // Selects whether the program sees virtual or host time, devices already in memory follow
+    // the selection. Time starts over at 0.
+    pub fn set_time_source(&mut self, source: TimeSource) {
+        self.clock = Clock::new(source);
+        if source == TimeSource::Host {
+            self.mem.follow_host_time(self.clock.start());
+        }
+    }
+
+    // the vector csrs are kept by the vector unit and the debug csrs by the trigger module
+    pub fn read_csr(&self, csr: u16) -> Option<u32> {
+        if csr == TIME || csr == TIMEH {
+            let time = self.clock.now();
+            Some(if csr == TIME { time } else { time >> 32 } as u32)
+        } else if VectorUnit::is_csr(csr) {
+            self.vector.read_csr(csr)
+        } else if Triggers::is_csr(csr) {
+            self.triggers.read_csr(csr)
+        } else {
+            self.csrs.read(csr)
+        }
+    }
+
+    pub fn write_csr(&mut self, csr: u16, value: u32) -> Option<()> {
+        if VectorUnit::is_csr(csr) {
+            self.vector.write_csr(csr, value)
+        } else if Triggers::is_csr(csr) {
+            self.triggers.write_csr(csr, value)
+        } else {
+            self.csrs.write(csr, value)
         }
     }
 
-    pub fn run(&mut self, program: Vec<u8>) -> Result<u8, Error> {
-        self.mem.load_program(program);
+    // Translates the virtual address of an access by the running hart to a physical one.
+    pub fn translate(&mut self, address: u32, access: Access) -> Result<u32, Exception> {
+        mmu::translate(&mut self.mem, &self.csrs, address, access)
+    }
+
+    // Like `translate` for all bytes of the access. Misaligned accesses crossing into a page that
+    // doesn't follow the first one physically raise a misaligned exception instead.
+    pub(crate) fn translate_access(
+        &mut self,
+        address: u32,
+        size: Size,
+        access: Access,
+    ) -> Result<u32, Exception> {
+        let physical = self.translate(address, access)?;
+        let last = address.wrapping_add(size as u32 - 1);
+        if !mmu::enabled(&self.csrs, access) || last >> 12 == address >> 12 {
+            return Ok(physical);
+        }
+        if self.translate(last, access)? != physical.wrapping_add(size as u32 - 1) {
+            return Err(match access {
+                Access::Load => Exception::LoadAddressMisaligned(address),
+                _ => Exception::StoreAddressMisaligned(address),
+            });
+        }
+        Ok(physical)
+    }
+
+    // loads and stores of the program at virtual addresses, faults report the virtual address
+    pub fn load_virtual(
+        &mut self,
+        size: Size,
+        address: u32,
+        is_unsigned: bool,
+    ) -> Result<u32, Exception> {
+        let physical = self.translate_access(address, size, Access::Load)?;
+        self.mem
+            .load(size, physical, is_unsigned)
+            .map_err(|_| Exception::LoadAccessFault(address))
+    }
+
+    pub fn store_virtual(&mut self, size: Size, address: u32, value: u32) -> Result<(), Exception> {
+        let physical = self.translate_access(address, size, Access::Store)?;
+        self.mem
+            .store(size, physical, value)
+            .map_err(|_| Exception::StoreAccessFault(address))
+    }
+
+    // Places the device tree at the end of memory and returns its address.
+    // The stack starts right below it.
+    fn place_dtb(&mut self) -> u32 {
+        let blob = fdt::machine_fdt(&self.mem, ISA, self.harts() as u32);
+        // keep the stack pointer 16-byte aligned as required by the calling convention
+        let address = (self.mem.ram_end() - blob.len() as u64) as u32 & !0xf;
+        self.mem.write_bytes(address, &blob);
+        self.regs.set(Reg::Sp, address);
+        address
+    }
+
+    // Makes the page below the lowest page of the stack inaccessible, so that overflowing the
+    // stack faults instead of overwriting data. No guard is placed if the stack doesn't fit.
+    fn place_stack_guard(&mut self, size: u32) {
+        let top = self.regs.get(Reg::Sp) as u64;
+        let bottom = top.saturating_sub(size as u64) & !(PAGE_SIZE - 1);
+        if bottom >= self.mem.ram_base() as u64 + PAGE_SIZE {
+            let bottom = bottom as u32;
+            self.mem.set_guard(bottom - PAGE_SIZE as u32..bottom);
+        }
+    }
+
+    // Sets up the state the loaded program expects at reset. As per the riscv boot convention
+    // a0 holds the hartid and a1 the address of the device tree, either set by the boot rom
+    // or directly if there is none.
+    fn power_on(&mut self) {
+        self.regs.set(Reg::Sp, self.mem.ram_end() as u32);
+        let dtb = if self.pass_dtb { self.place_dtb() } else { 0 };
+        // the harts' stacks are placed below each other, the guard page is below the lowest one
+        let stack_top = self.regs.get(Reg::Sp);
+        let stack_size = self.stack_size.unwrap_or(HART_STACK_SIZE);
+        if let Some(size) = self.stack_size {
+            self.place_stack_guard(size.saturating_mul(self.harts() as u32));
+        }
+        // after a reset the boot rom is still mapped
+        if self.bootrom && !self.mem.overlaps(BOOTROM_BASE, 1) {
+            self.mem
+                .add_device(Box::new(BootRom::new(self.reset_pc(), dtb)));
+        }
+        if self.harts() > 1 {
+            self.mem.enable_store_log();
+            let vlen = self.vector.vlen();
+            let logged = self.reg_history.is_some()
+                || self.trace.is_some()
+                || matches!(self.state_hash, Some((HashMode::Commit, _)));
+            self.harts = (0..self.harts())
+                .map(|id| {
+                    let mut state = HartState::new(id as u32, vlen);
+                    if logged {
+                        state.regs.enable_change_log();
+                    }
+                    state
+                })
+                .collect();
+        }
+        // hart 0 is set up last, so that it is the one running when execution starts
+        for id in (0..self.harts()).rev() {
+            self.switch_hart(id);
+            let sp = stack_top.wrapping_sub(id as u32 * stack_size);
+            self.reset_hart(id, sp, dtb);
+        }
+    }
+
+    fn reset_hart(&mut self, id: usize, sp: u32, dtb: u32) {
+        let config = self.hart_configs[id];
+        self.parked = config.parked;
+        if self.big_endian {
+            self.csrs.mstatush |= MSTATUSH_MBE;
+        }
+        // The built-in sbi hands over to the kernel in supervisor mode, delegating all exceptions
+        // but its own ecalls and the supervisor interrupts. Like with OpenSBI only hart 0 starts,
+        // the others wait for hart_start.
+        if self.sbi.is_some() {
+            self.csrs.write(MEDELEG, !(1 << 9)).unwrap();
+            self.csrs.write(MIDELEG, MIP_SUPERVISOR).unwrap();
+            self.csrs.write(MCOUNTEREN, u32::MAX).unwrap();
+            self.csrs.mstatus =
+                (self.csrs.mstatus & !MSTATUS_MPP) | (Mode::Supervisor as u32) << 11;
+            self.parked |= id != 0;
+        }
+        self.regs.set(Reg::Sp, config.sp.unwrap_or(sp));
+        match config.entry {
+            // the boot rom enters the program in the mode in mpp
+            None if self.bootrom => self.pc.set(BOOTROM_BASE),
+            entry => {
+                if self.pass_dtb || id != 0 {
+                    self.regs.set(Reg::A0, id as u32);
+                    self.regs.set(Reg::A1, dtb);
+                }
+                if self.sbi.is_some() {
+                    self.csrs.mode = Mode::Supervisor;
+                }
+                self.pc.set(entry.unwrap_or(self.reset_pc()));
+            }
+        }
+    }
+
+    // id of the hart that executed last
+    pub fn hart(&self) -> usize {
+        self.hart
+    }
+
+    // Makes the hart's registers and csrs the ones accessed through the cpu, e.g. for a debugger.
+    // Returns false if there is no such hart.
+    pub fn select_hart(&mut self, id: usize) -> bool {
+        if id >= self.harts() {
+            return false;
+        }
+        self.switch_hart(id);
+        true
+    }
+
+    // Runs f with hart `id` as the running hart and switches back afterwards, e.g. for the sbi to
+    // start another hart. None if there is no such hart.
+    pub(crate) fn with_hart<T>(&mut self, id: usize, f: impl FnOnce(&mut Cpu) -> T) -> Option<T> {
+        if id >= self.harts() {
+            return None;
+        }
+        let running = self.hart;
+        self.switch_hart(id);
+        let result = f(self);
+        self.switch_hart(running);
+        Some(result)
+    }
+
+    // whether the running hart is parked, the sbi's stopped harts are parked
+    pub(crate) fn parked(&self) -> bool {
+        self.parked
+    }
+
+    pub(crate) fn set_parked(&mut self, parked: bool) {
+        self.parked = parked;
+    }
+
+    // Swaps the architectural state of the running hart with the stored state of hart `id`.
+    fn switch_hart(&mut self, id: usize) {
+        if id == self.hart {
+            return;
+        }
+        self.swap_state(self.hart);
+        self.swap_state(id);
+        self.hart = id;
+    }
+
+    fn swap_state(&mut self, id: usize) {
+        let state = &mut self.harts[id];
+        std::mem::swap(&mut self.pc, &mut state.pc);
+        std::mem::swap(&mut self.regs, &mut state.regs);
+        std::mem::swap(&mut self.csrs, &mut state.csrs);
+        std::mem::swap(&mut self.vector, &mut state.vector);
+        std::mem::swap(&mut self.triggers, &mut state.triggers);
+        std::mem::swap(&mut self.reservation, &mut state.reservation);
+        std::mem::swap(&mut self.waiting, &mut state.waiting);
+        std::mem::swap(&mut self.parked, &mut state.parked);
+    }
+
+    // Stores of the running hart invalidate the reservations other harts hold on the stored
+    // words, so that their sc fails like it would on hardware. With a single hart the log is left
+    // to whoever enabled it, e.g. a playground session collecting the written memory.
+    fn invalidate_reservations(&mut self) {
+        if self.harts() == 1 {
+            return;
+        }
+        for address in self.mem.drain_stores() {
+            for (id, state) in self.harts.iter_mut().enumerate() {
+                if id != self.hart && state.reservation == Some(address) {
+                    state.reservation = None;
+                }
+            }
+        }
+    }
+
+    // whether all other harts are parked or waiting without a pending interrupt
+    fn others_blocked(&self) -> bool {
+        self.harts
+            .iter()
+            .enumerate()
+            .filter(|&(id, _)| id != self.hart)
+            .all(|(id, state)| !state.runnable(self.mem.interrupts(id)))
+    }
+
+    // Loads a raw binary to the start of ram and resets the harts, so that it can be executed with
+    // `step`.
+    pub fn load(&mut self, program: Vec<u8>) {
+        self.heap = Heap::new(self.mem.ram_base().wrapping_add(program.len() as u32));
+        self.entry = self.mem.ram_base();
+        self.mem.load_program(program);
+        self.start();
+    }
+
+    // Copies the segments of the executable into ram and resets the harts to start at its entry
+    // point, unless the host set another reset pc.
+    pub fn load_elf(&mut self, elf: &Elf) -> Result<(), Error> {
+        for segment in &elf.segments {
+            let end = segment.address as u64 + segment.mem_size as u64;
+            if segment.address < self.mem.ram_base() || end > self.mem.ram_end() {
+                return Err(Error::InvalidElf("segment outside of ram"));
+            }
+            let mut data = segment.data.clone();
+            data.resize(segment.mem_size.max(data.len() as u32) as usize, 0);
+            self.mem.write_bytes(segment.address, &data);
+        }
+        let end = elf
+            .segments
+            .iter()
+            .map(|segment| segment.address.saturating_add(segment.mem_size))
+            .max();
+        self.heap = Heap::new(end.unwrap_or(self.mem.ram_base()));
+        self.entry = elf.entry;
+        self.symbols = elf
+            .symbols()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect();
+        if let Some(progress) = self.progress.as_mut() {
+            progress.set_symbols(elf);
+        }
+        if let Some(allocations) = self.allocations.as_mut() {
+            allocations.set_symbols(&self.symbols);
+        }
+        self.start();
+        Ok(())
+    }
+
+    fn start(&mut self) {
+        self.power_on();
+        // the initial register values aren't part of the history
+        self.regs.drain_changes().for_each(drop);
+    }
+
+    // Resets the machine without reloading the program: the harts' registers, csrs and pc, the
+    // devices and the program break return to their power-on state. Memory keeps its contents,
+    // breakpoints and watchpoints stay set and the cycle and instruction counts keep running.
+    pub fn reset(&mut self) {
+        self.switch_hart(0);
+        for n in 1..32 {
+            self.regs.write(n, 0);
+        }
+        self.csrs = Csrs::new();
+        self.vector = VectorUnit::new(self.vector.vlen());
+        self.triggers = Triggers::new();
+        self.reservation = None;
+        self.waiting = false;
+        self.stop = None;
+        self.reset_requested = false;
+        self.heap.reset();
+        if let Some(allocations) = self.allocations.as_mut() {
+            allocations.reset();
+        }
+        if let Some(sbi) = self.sbi.as_mut() {
+            sbi.clear_timers();
+        }
+        self.mem.reset_devices();
+        self.start();
+    }
+
+    // resets the machine once the current instruction completed, e.g. for a reboot ecall
+    pub fn request_reset(&mut self) {
+        self.reset_requested = true;
+    }
+
+    pub fn run(&mut self, program: Vec<u8>) -> Result<StopReason, Error> {
+        self.load(program);
+        self.run_loaded()
+    }
+
+    pub fn run_elf(&mut self, elf: &Elf) -> Result<StopReason, Error> {
+        self.load_elf(elf)?;
+        self.run_loaded()
+    }
+
+    // runs the program loaded with `load` or `load_elf` until it stops
+    pub fn run_loaded(&mut self) -> Result<StopReason, Error> {
+        let result = self.run_until_stop();
+        self.finish(result)
+    }
+
+    // Executes cycles until the emulation stops, without finishing the run like `run_loaded`.
+    pub fn run_until_stop(&mut self) -> Result<StopReason, Error> {
+        loop {
+            match self.step() {
+                Ok(None) => (),
+                Ok(Some(reason)) => return Ok(reason),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // Executes at most `instructions` cycles and returns `StopReason::Yield` if the program is
+    // still running afterwards. The next call continues exactly where this one stopped, so that
+    // a host can time-slice many cpus on one thread deterministically:
+    //     while cpus.iter_mut().any(|cpu| cpu.run_for(1000) == Ok(StopReason::Yield)) {}
+    // Like `step` it doesn't call the exit hooks, `finish` completes the run.
+    pub fn run_for(&mut self, instructions: u64) -> Result<StopReason, Error> {
+        for _ in 0..instructions {
+            if let Some(reason) = self.step()? {
+                return Ok(reason);
+            }
+        }
+        Ok(StopReason::Yield)
+    }
+
+    // Completes a run that stopped with the result: finishes the trace and calls the exit hooks.
+    pub fn finish(&mut self, result: Result<StopReason, Error>) -> Result<StopReason, Error> {
+        // compressed traces are only readable once they are finished
+        let finished = self
+            .trace
+            .as_mut()
+            .map_or(Ok(()), TraceWriter::finish)
+            .and(self.tracepoints.as_mut().map_or(Ok(()), |log| log.flush()))
+            .and(self.access_log.as_mut().map_or(Ok(()), AccessLog::flush));
+        let reason = result?;
+        finished.map_err(Error::TraceIo)?;
+        let mut hooks = std::mem::take(&mut self.exit_hooks);
+        for hook in hooks.iter_mut() {
+            hook(self, &reason);
+        }
+        self.exit_hooks = hooks;
+        Ok(reason)
+    }
+
+    // Executes a single cycle of the loaded program and returns why the emulation stopped, if it
+    // did. Unlike `run` it doesn't finish the trace file or call the exit hooks.
+    pub fn step(&mut self) -> Result<Option<StopReason>, Error> {
+        let cycle = self.cycles;
+        if self.cycle_limit.is_some_and(|limit| cycle >= limit) {
+            self.dump_state(cycle);
+            self.print_history();
+            return Ok(Some(StopReason::Limit(cycle)));
+        }
+        if self.stop_requested.swap(false, Ordering::Relaxed) {
+            return Ok(Some(StopReason::HostRequest));
+        }
+        self.cycles += 1;
+        if self.harts() > 1 {
+            let hart = self.scheduler.next(self.hart, self.harts());
+            self.switch_hart(hart);
+        }
+        let pc = self.pc.get();
+        if self.run_to == Some(pc) {
+            self.run_to = None;
+        }
+        let result = self.emulate_cycle();
+        self.record_cycle(cycle, pc)?;
+        if !self.watch_exprs.is_empty() {
+            self.update_watch_exprs(pc);
+        }
+        if let Some(id) = self.mem.take_tracepoint() {
+            if let Some(log) = self.tracepoints.as_mut() {
+                let a0 = self.regs.get(Reg::A0);
+                writeln!(log, "{cycle},{},{id},{a0:#x}", self.hart).map_err(Error::TraceIo)?;
+            }
+        }
+        if let Some(log) = self.access_log.as_mut() {
+            for access in self.mem.drain_accesses() {
+                log.write(cycle, self.hart, pc, &access)
+                    .map_err(Error::TraceIo)?;
+            }
+        }
+        if let Some(progress) = self.progress.as_mut() {
+            progress.update(self.retired, self.pc.get());
+        }
+        if let Some(throttle) = self.throttle.as_mut() {
+            throttle.update(self.retired);
+        }
+        match result {
+            // the debugger shows the state itself
+            Ok(Some(StopReason::Debug(_))) => (),
+            Ok(Some(reason)) => {
+                self.dump_state(cycle);
+                if !matches!(reason, StopReason::Exit(_)) {
+                    self.print_history();
+                }
+            }
+            Err(_) => {
+                self.dump_state(cycle);
+                self.print_history();
+            }
+            Ok(None) if self.tracing(pc) => self.dump_state(cycle),
+            Ok(None) => (),
+        }
+        result
+    }
+
+    // prints the value of the expression after the first instruction and whenever it changes
+    pub fn add_watch_expr(&mut self, expr: Expr) {
+        self.watch_exprs.push((expr, None));
+    }
+
+    fn update_watch_exprs(&mut self, pc: u32) {
+        let mut watch_exprs = std::mem::take(&mut self.watch_exprs);
+        for (expr, last) in &mut watch_exprs {
+            let value = expr.eval(self);
+            if last.as_ref() == Some(&value) {
+                continue;
+            }
+            let shown = match &value {
+                Ok(value) => expr::format_value(*value),
+                Err(e) => format!("<{e}>"),
+            };
+            match last {
+                Some(_) => eprintln!("watch {expr} = {shown} after pc {pc:#x}"),
+                None => eprintln!("watch {expr} = {shown}"),
+            }
+            *last = Some(value);
+        }
+        self.watch_exprs = watch_exprs;
+    }
+
+    // adds the register writes of the cycle to the history and the trace file
+    fn record_cycle(&mut self, cycle: usize, pc: u32) -> Result<(), Error> {
+        // the signature covers every instruction, whatever is traced
+        let mut state_hash = match (&mut self.state_hash, self.fetched) {
+            (Some((HashMode::Commit, hash)), Some(raw_inst)) => {
+                hash.write_u32(self.hart as u32);
+                hash.write_u32(pc);
+                hash.write_u32(raw_inst);
+                Some(hash)
+            }
+            _ => None,
+        };
+        let fetched = self
+            .fetched
+            .take()
+            .filter(|_| self.run_to.is_none() && self.trace_filter.matches(pc));
+        let mut trace = self.trace.as_mut().filter(|_| fetched.is_some());
+        if let (Some(trace), Some(raw_inst)) = (trace.as_mut(), fetched) {
+            trace.begin(cycle, pc, raw_inst);
+        }
+        for change in self.regs.drain_changes() {
+            if let Some(history) = self.reg_history.as_mut() {
+                history.record(cycle, pc, change);
+            }
+            if let Some(trace) = trace.as_mut() {
+                trace.reg_write(&change);
+            }
+            if let Some(hash) = state_hash.as_mut() {
+                hash.write_u32(change.reg as u32);
+                hash.write_u32(change.new);
+            }
+        }
+        match trace {
+            Some(trace) => trace.end().map_err(Error::TraceIo),
+            None => Ok(()),
+        }
+    }
+
+    fn tracing(&self, pc: u32) -> bool {
+        self.print_debug && self.run_to.is_none() && self.trace_filter.matches(pc)
+    }
+
+    fn dump_state(&self, cycle_count: usize) {
+        if self.quiet {
+            return;
+        }
+        eprintln!("CPU dump at cycle {cycle_count}:");
+        eprintln!("PC: {}", self.pc.get());
+        for i in 0..32 {
+            eprintln!("R{i}: {}", self.regs.read(i) as i32);
+        }
+    }
+
+    pub fn print_history(&self) {
+        if self.quiet {
+            return;
+        }
+        self.inst_history.print();
+        if let Some(history) = &self.reg_history {
+            history.print();
+        }
+    }
+
+    // fetches next instruction from memory, page faults are returned as Error::Trap
+    fn fetch(&mut self) -> Result<u32, Error> {
+        let pc = self.pc.inc();
+        let physical = self.translate(pc, Access::Execute).map_err(Error::Trap)?;
+        if !self.mem.is_mapped(physical, Size::Word) {
+            return Err(Error::InvalidPC(pc, self.mem.ram_size()));
+        }
+        Ok(self.mem.read(Size::Word, physical, true))
+    }
+
+    // Ecalls are interpreted when they execute, depending on the environment: the built-in sbi
+    // firmware handles the ones of the kernel as sbi calls, otherwise the emulated linux syscalls (exit is also
+    // used by the official risc-v testsuite) are handled and everything else goes to the trap
+    // handler.
+    pub fn ecall(&mut self) -> Result<(), Exception> {
+        match self.env {
+            // ecalls of user processes go to the kernel
+            Env::Sbi if self.csrs.mode == Mode::User => {
+                return Err(Exception::EnvironmentCall(Mode::User))
+            }
+            Env::Sbi => {
+                if let Some(code) = sbi::handle_ecall(self) {
+                    self.request_stop(StopReason::Exit(code));
+                }
+                return Ok(());
+            }
+            // the program handles its ecalls itself
+            Env::Bare | Env::Htif => return Err(Exception::EnvironmentCall(self.csrs.mode)),
+            Env::Newlib | Env::Linux => (),
+        }
+        let syscall = if self.env.emulates(self.regs.get(Reg::A7)) {
+            syscall::handle(self)
+        } else {
+            None
+        };
+        if self.strace {
+            eprintln!("{}", syscall::strace(self, syscall.as_ref()));
+        }
+        match syscall {
+            Some(Syscall::Exit(code)) => self.request_stop(StopReason::Exit(code)),
+            Some(Syscall::Reset) => self.request_reset(),
+            Some(Syscall::Return(value)) => self.regs.set(Reg::A0, value),
+            None if self.csrs.mtvec != 0 || self.strict_syscalls => {
+                return Err(Exception::EnvironmentCall(self.csrs.mode))
+            }
+            // programs without a trap handler keep ignoring unknown syscalls
+            None => (),
+        }
+        Ok(())
+    }
+
+    // Enters the trap handler at mtvec, or at stvec if the exception is delegated to supervisor
+    // mode. Programs that never installed the handler (its address is zero) are stopped instead
+    // of jumping to address 0, with the given error if the instruction couldn't be decoded.
+    fn trap(
+        &mut self,
+        exception: Exception,
+        pc: u32,
+        err: Error,
+    ) -> Result<Option<StopReason>, Error> {
+        let tvec = if self.csrs.delegated(exception.cause()) {
+            self.csrs.stvec
+        } else {
+            self.csrs.mtvec
+        };
+        if tvec == 0 {
+            // the state shows the instruction that trapped, like a core dump or debugger expects
+            self.pc.set(pc);
+            return match (exception, err) {
+                (Exception::LoadAccessFault(address), _)
+                | (Exception::StoreAccessFault(address), _)
+                    if self.mem.in_guard(address) =>
+                {
+                    Err(Error::StackOverflow(address))
+                }
+                (Exception::Breakpoint(address), _) => Ok(Some(StopReason::Break(address))),
+                // only raised without handler in strict mode, environments without syscalls stop at the trap
+                (Exception::EnvironmentCall(_), _)
+                    if matches!(self.env, Env::Newlib | Env::Linux) =>
+                {
+                    Err(Error::UnimplementedSyscall(
+                        self.regs.get(Reg::A7),
+                        [Reg::A0, Reg::A1, Reg::A2, Reg::A3, Reg::A4, Reg::A5]
+                            .map(|reg| self.regs.get(reg)),
+                    ))
+                }
+                (_, Error::Trap(exception)) => Ok(Some(StopReason::Trap(exception))),
+                (_, err) => Err(err),
+            };
+        }
+        let tvec = self.enter_trap(exception.cause(), exception.tval(), pc);
+        // exceptions always go to the base address, even in vectored mode
+        self.pc.set(tvec & !0b11);
+        Ok(None)
+    }
+
+    // Takes the trap in supervisor mode if it's delegated, otherwise in machine mode. Returns the
+    // trap vector of that mode.
+    fn enter_trap(&mut self, cause: u32, tval: u32, pc: u32) -> u32 {
+        if self.csrs.delegated(cause) {
+            self.csrs.sepc = pc;
+            self.csrs.scause = cause;
+            self.csrs.stval = tval;
+            self.csrs.push_supervisor_interrupt_enable();
+            self.csrs.stvec
+        } else {
+            self.csrs.mepc = pc;
+            self.csrs.mcause = cause;
+            self.csrs.mtval = tval;
+            self.csrs.push_interrupt_enable();
+            self.csrs.mtvec
+        }
+    }
+
+    // Returns the number of the interrupt to take, if one is pending and enabled. Interrupts of
+    // machine mode are enabled below machine mode and by mstatus.MIE, delegated ones below
+    // supervisor mode and by sstatus.SIE in supervisor mode. External interrupts have the highest
+    // priority, then software and timer interrupts, the ones of machine mode first.
+    fn pending_interrupt(&self) -> Option<u32> {
+        let mode = self.csrs.mode;
+        let pending = self.csrs.mip & self.csrs.mie;
+        let mut enabled = 0;
+        if mode < Mode::Machine || self.csrs.mstatus & MSTATUS_MIE != 0 {
+            enabled |= pending & !self.csrs.mideleg;
+        }
+        if mode < Mode::Supervisor
+            || (mode == Mode::Supervisor && self.csrs.mstatus & MSTATUS_SIE != 0)
+        {
+            enabled |= pending & self.csrs.mideleg;
+        }
+        [MIP_MEIP, MIP_MSIP, MIP_MTIP, MIP_SEIP, MIP_SSIP, MIP_STIP]
+            .into_iter()
+            .find(|&bit| enabled & bit != 0)
+            .map(u32::trailing_zeros)
+    }
+
+    // Interrupts are taken before the instruction at pc executes, so mepc is the instruction
+    // to resume at. In vectored mode each interrupt has its own entry at base + 4 * cause.
+    fn interrupt(&mut self, code: u32) {
+        let tvec = self.enter_trap(INTERRUPT | code, 0, self.pc.get());
+        let base = tvec & !0b11;
+        let vectored = tvec & 0b11 == 1;
+        self.pc.set(if vectored {
+            base.wrapping_add(4 * code)
+        } else {
+            base
+        });
+    }
+
+    // Triggers fire before the instruction executes. Like on hardware without tcontrol they don't
+    // fire while mstatus.MIE is clear, so a trap handler can't trigger itself recursively.
+    fn trigger_fires(&mut self, access: Access, address: u32) -> bool {
+        self.csrs.mstatus & MSTATUS_MIE != 0 && self.triggers.check(access, address)
+    }
+
+    // Sleeps the host until the next device deadline instead of spinning in wfi. The slept time
+    // is skipped on the device clocks, so the guest sees it pass like it would on hardware.
+    fn idle(&mut self) {
+        // other harts keep executing, only sleep once none of them can make progress
+        if !self.others_blocked() {
+            return;
+        }
+        let cycles = [self.mem.next_event(), self.next_timer()]
+            .into_iter()
+            .flatten()
+            .min()
+            .map_or(MAX_IDLE_CYCLES, |cycles| cycles.min(MAX_IDLE_CYCLES));
+        if self.idle_sleep {
+            std::thread::sleep(Duration::from_nanos(
+                cycles * 1_000_000_000 / TIMEBASE_FREQUENCY as u64,
+            ));
+        }
+        self.mem.skip(cycles);
+        self.csrs.count_cycles(cycles);
+        self.clock.advance(cycles);
+    }
+
+    // cycles until the stimecmp of a hart that enabled it or a deadline set through the sbi is
+    // reached
+    fn next_timer(&self) -> Option<u64> {
+        let now = self.clock.now();
+        let others = self
+            .harts
+            .iter()
+            .enumerate()
+            .filter(|&(id, _)| id != self.hart);
+        others
+            .map(|(_, state)| &state.csrs)
+            .chain([&self.csrs])
+            .filter(|csrs| csrs.menvcfgh & MENVCFGH_STCE != 0)
+            .map(|csrs| csrs.stimecmp)
+            .chain(self.sbi.as_ref().and_then(Sbi::next_deadline))
+            .map(|deadline| deadline.saturating_sub(now))
+            .min()
+    }
+
+    fn emulate_cycle(&mut self) -> Result<Option<StopReason>, Error> {
+        self.mem.tick();
+        self.csrs.count_cycles(1);
+        self.clock.advance(1);
+        self.csrs.mip = (self.csrs.mip & !MIP_HARDWARE) | self.mem.interrupts(self.hart);
+        // with Sstc the supervisor timer interrupt is pending while time is past stimecmp
+        if self.csrs.menvcfgh & MENVCFGH_STCE != 0 {
+            self.csrs.mip &= !MIP_STIP;
+            if self.clock.now() >= self.csrs.stimecmp {
+                self.csrs.mip |= MIP_STIP;
+            }
+        }
+        // the sbi raises the timer interrupt once the deadline passed, the next set_timer clears it
+        let now = self.clock.now();
+        if let Some(sbi) = self.sbi.as_mut() {
+            if sbi.timer_due(self.hart, now) {
+                self.csrs.mip |= MIP_STIP;
+            }
+        }
+        if self.parked {
+            if self.csrs.mip & MIP_MSIP == 0 {
+                self.idle();
+                return Ok(None);
+            }
+            self.parked = false;
+        }
+        if let Some(code) = self.pending_interrupt() {
+            self.waiting = false;
+            self.interrupt(code);
+            return Ok(None);
+        }
+        if self.waiting {
+            // wfi also resumes on interrupts that are disabled in mstatus, they just aren't taken
+            if self.csrs.mip & self.csrs.mie == 0 {
+                self.idle();
+                return Ok(None);
+            }
+            self.waiting = false;
+        }
+
+        let pc = self.pc.get();
+        let resumed = match self.debug_stop {
+            Some((hart, stop_pc)) if hart == self.hart => {
+                self.debug_stop = None;
+                stop_pc == pc
+            }
+            _ => false,
+        };
+        let debug = !self.watchpoints.is_empty() && !resumed;
+        let stop = debug.then(|| self.watchpoints.check_execute(self, pc));
+        if let Some(stop) = stop.flatten() {
+            self.debug_stop = Some((self.hart, pc));
+            return Ok(Some(StopReason::Debug(stop)));
+        }
+        if self.trigger_fires(Access::Execute, pc) {
+            let exception = Exception::Breakpoint(pc);
+            return self.trap(exception, pc, Error::Trap(exception));
+        }
+        if let Some(mut stub) = self.stubs.remove(&pc) {
+            let result = stub(self);
+            // a stub that replaced itself while running stays replaced
+            self.stubs.entry(pc).or_insert(stub);
+            self.regs.set(Reg::A0, result);
+            self.pc.set(self.regs.get(Reg::Ra));
+            return Ok(self.stop.take());
+        }
+        // the byte order can differ between harts and change with every csr write, supervisor and
+        // user mode are always little-endian
+        self.mem.set_big_endian(
+            self.csrs.mode == Mode::Machine && self.csrs.mstatush & MSTATUSH_MBE != 0,
+        );
+        let raw_inst = match self.fetch() {
+            Ok(raw_inst) => raw_inst,
+            Err(Error::Trap(exception)) => return self.trap(exception, pc, Error::Trap(exception)),
+            Err(e) => return self.trap(Exception::InstructionAccessFault(pc), pc, e),
+        };
+        self.fetched = Some(raw_inst);
+        if raw_inst == 0 {
+            return Err(Error::EndOfInstructions);
+        }
+        if let Some(counts) = self.exec_counts.as_mut() {
+            *counts.entry(pc).or_default() += 1;
+        }
+        if let Some(allocations) = self.allocations.as_mut() {
+            allocations.observe(self.hart, pc, &self.regs);
+        }
+        if self.tracing(pc) {
+            eprintln!("Inst: {:032b}", raw_inst);
+        }
+
+        let decoded = decode(raw_inst);
+        let sources = decoded.as_ref().map_or([None, None], Inst::sources);
+        self.inst_history.record(pc, raw_inst, sources, &self.regs);
+        let inst = match decoded {
+            Ok(inst) => inst,
+            Err(e) => return self.trap(Exception::IllegalInstruction(raw_inst), pc, e),
+        };
+        if let Some(reported) = self.pedantic.as_mut() {
+            let ignored = inst.ignored_semantics(raw_inst);
+            if let Some(missing) = ignored.filter(|_| reported.insert(raw_inst)) {
+                eprintln!(
+                    "pedantic: {pc:#010x}: {raw_inst:#010x} {inst} executed as nop, {missing}"
+                );
+            }
+        }
+        // only classified when observed, most programs execute no hints at all
+        let hint = if self.hint_hooks.is_empty() {
+            None
+        } else {
+            inst.hint(raw_inst, self)
+        };
+        if let Some(hint) = hint {
+            let mut hooks = std::mem::take(&mut self.hint_hooks);
+            for hook in hooks.iter_mut() {
+                hook(self, pc, hint);
+            }
+            self.hint_hooks = hooks;
+        }
+        if let Some((access, address)) = inst.access(self) {
+            let watched = self
+                .watchpoints
+                .check_access(access, address, inst.access_size());
+            if let Some(stop) = watched.filter(|_| debug) {
+                // the instruction executes once resumed
+                self.pc.set(pc);
+                self.debug_stop = Some((self.hart, pc));
+                return Ok(Some(StopReason::Debug(stop)));
+            }
+            if self.trigger_fires(access, address) {
+                let exception = Exception::Breakpoint(address);
+                return self.trap(exception, pc, Error::Trap(exception));
+            }
+        }
+        let event = inst.event();
+        let link = self.stack_usage.as_ref().and_then(|_| stack::link(&inst));
+        let sp = self.regs.get(Reg::Sp);
+        let result = inst.execute(self);
+        // drained even if the instruction traps, the next cycle may run another hart
+        self.invalidate_reservations();
+        if let Err(exception) = result {
+            // only the decoded instruction is executed, so the instruction bits are added here
+            let exception = match exception {
+                Exception::IllegalInstruction(_) => Exception::IllegalInstruction(raw_inst),
+                exception => exception,
+            };
+            return self.trap(exception, pc, Error::Trap(exception));
+        }
+        if let Some(usages) = self.stack_usage.as_mut() {
+            let usage = usages
+                .entry(self.hart)
+                .or_insert_with(|| StackUsage::new(sp));
+            let sp = self.regs.get(Reg::Sp);
+            usage.update(sp);
+            match link {
+                Some(Link::Call) => usage.call(self.pc.get(), sp),
+                Some(Link::Return) => usage.ret(),
+                None => (),
+            }
+        }
+        self.csrs.retire();
+        self.retired += 1;
+        if let Some(event) = event {
+            self.csrs.count_event(event);
+        }
+        if let Some(reason) = self.stop.take() {
+            return Ok(Some(reason));
+        }
+        if let Some(code) = self.mem.take_exit() {
+            return Ok(Some(StopReason::Exit(code)));
+        }
+        if let Some(failure) = self.mem.take_failed_assert() {
+            return Err(Error::AssertFailed(failure, self.pc.get()));
+        }
+        if std::mem::take(&mut self.reset_requested) | self.mem.take_reset() {
+            self.reset();
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::access_log::LogFormat;
+    use crate::devices::{DebugConsole, RtcClock};
+    use crate::machine::Machine;
+    use crate::trace::TraceFormat;
+    use crate::watch::WatchKind;
+    use std::io::Write;
+    use std::path::Path;
+    use std::process::Command;
+
+    // NOTE: The testcases in tests/ terminate by running out of instructions.
+    // This is by design, as I don't want to exit each testcase using ecall.
+
+    fn file_to_bin(path: &'static str) -> Vec<u8> {
+        let mut current_path = std::env::current_dir().unwrap();
+        current_path.push("tests");
+        current_path.push(path);
+        create_bin(current_path.as_path())
+    }
+    fn asm_to_bin(asm: &'static str) -> Vec<u8> {
+        let mut asm_temp = tempfile::Builder::new()
+            .suffix(".s")
+            .tempfile()
+            .expect("tempfile create");
+        write!(asm_temp, ".global _start\n_start:\n{}", asm).expect("write asm to tempfile");
+        create_bin(asm_temp.path())
+    }
+
+    // builds a program from already encoded instructions, doesn't need the riscv toolchain
+    fn words_to_bin(insts: &[u32]) -> Vec<u8> {
+        insts.iter().flat_map(|inst| inst.to_le_bytes()).collect()
+    }
+
+    // whether riscv64-unknown-elf-gcc is installed, otherwise the built-in assembler is used
+    fn has_toolchain() -> bool {
+        Command::new("riscv64-unknown-elf-gcc")
+            .arg("--version")
+            .output()
+            .is_ok_and(|output| output.status.success())
+    }
+
+    // The program from tests/fixtures/<name>.bin if the source is one of the tests/*.s files,
+    // which are checked in so that the tests run without toolchain. Inline programs are
+    // assembled on the fly.
+    fn assemble_without_toolchain(asm_filepath: &Path) -> Vec<u8> {
+        let fixture = asm_filepath
+            .parent()
+            .unwrap()
+            .join("fixtures")
+            .join(asm_filepath.file_stem().unwrap())
+            .with_extension("bin");
+        if let Ok(program) = std::fs::read(fixture) {
+            return program;
+        }
+        let source = std::fs::read_to_string(asm_filepath).expect("can read asm");
+        match crate::asm::assemble(&source, 0) {
+            Ok(assembly) => assembly.bytes,
+            Err(e) => panic!("invalid asm: {e:?}"),
+        }
+    }
+
+    fn create_bin(asm_filepath: &Path) -> Vec<u8> {
+        if !has_toolchain() {
+            return assemble_without_toolchain(asm_filepath);
+        }
+        let executable = tempfile::NamedTempFile::new().expect("tempfile create");
+        assert!(
+            Command::new("riscv64-unknown-elf-gcc")
+                .args([
+                    "-Wl,-Ttext=0x0",
+                    "-nostdlib",
+                    "-o",
+                    executable.path().to_str().unwrap(),
+                    asm_filepath.to_str().unwrap(),
+                    "-march=rv32i",
+                    "-mabi=ilp32",
+                ])
+                .status()
+                .expect("invokes riscv gcc cross compiler")
+                .success(),
+            "invalid asm"
+        );
+
+        let binary = tempfile::NamedTempFile::new().expect("tempfile create");
+        assert!(
+            Command::new("riscv64-unknown-elf-objcopy")
+                .args([
+                    "-O",
+                    "binary",
+                    executable.path().to_str().unwrap(),
+                    binary.path().to_str().unwrap(),
+                ])
+                .status()
+                .expect("invokes riscv objcopy")
+                .success(),
+            "invalid elf"
+        );
+
+        std::fs::read(binary.path()).expect("can read binary")
+    }
+
+    // Regenerate the fixtures after changing a tests/*.s file or the assembler with
+    // `UPDATE_FIXTURES=1 cargo test fixtures_up_to_date`.
+    #[test]
+    fn fixtures_up_to_date() {
+        let tests = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests");
+        let update = std::env::var_os("UPDATE_FIXTURES").is_some();
+        for entry in std::fs::read_dir(&tests).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|extension| extension != "s") {
+                continue;
+            }
+            let source = std::fs::read_to_string(&path).unwrap();
+            let program = crate::asm::assemble(&source, 0).unwrap().bytes;
+            let fixture = tests
+                .join("fixtures")
+                .join(path.file_stem().unwrap())
+                .with_extension("bin");
+            if update {
+                std::fs::write(&fixture, &program).unwrap();
+            }
+            assert_eq!(
+                std::fs::read(&fixture).ok(),
+                Some(program),
+                "{} is outdated, run with UPDATE_FIXTURES=1",
+                fixture.display()
+            );
+        }
+    }
+
+    #[test]
+    fn x0_hardwired() {
+        let program = asm_to_bin("addi x0, x0, -127\n");
+        let mut cpu = Cpu::new(false);
+        cpu.mem.load_program(program);
+
+        assert!(cpu.emulate_cycle().is_ok());
+        assert_eq!(0, cpu.regs.read(0));
+    }
+
+    #[test]
+    fn negative_assign() {
+        let program = asm_to_bin("addi x31, x0, -127\n");
+        let mut cpu = Cpu::new(false);
+        cpu.mem.load_program(program);
+
+        assert!(cpu.emulate_cycle().is_ok());
+        let n = -127;
+        assert_eq!(n as u32, cpu.regs.read(31));
+        assert_eq!(0, cpu.regs.read(0));
+    }
+
+    #[test]
+    fn auipc_copy() {
+        let program = asm_to_bin("auipc x10, 0\n");
+        let mut cpu = Cpu::new(false);
+
+        assert!(matches!(cpu.run(program), Err(Error::EndOfInstructions)));
+        assert_eq!(cpu.regs.read(10), 0);
+    }
+
+    #[test]
+    fn auipc_offset() {
+        let program = asm_to_bin("addi x11, x0, 12\nauipc x10, 4\n");
+        let mut cpu = Cpu::new(false);
+
+        assert!(matches!(cpu.run(program), Err(Error::EndOfInstructions)));
+        assert_eq!(cpu.regs.read(10), 16388);
+    }
+
+    #[test]
+    fn arithmetic() {
+        let program = file_to_bin("arith.s");
+        let mut cpu = Cpu::new(false);
+
+        assert!(matches!(cpu.run(program), Err(Error::EndOfInstructions)));
+        assert_eq!(cpu.regs.read(27) as i32, -26);
+        assert_eq!(cpu.regs.read(28) as i32, -6);
+        assert_eq!(cpu.regs.read(29), 5);
+        assert_eq!(cpu.regs.read(30) as i32, -32);
+        assert_eq!(cpu.regs.read(31) as i32, 42);
+        assert_eq!(cpu.pc.get(), 28);
+    }
+
+    #[test]
+    fn bitops() {
+        let program = file_to_bin("bitops.s");
+        let mut cpu = Cpu::new(false);
+
+        assert!(matches!(cpu.run(program), Err(Error::EndOfInstructions)));
+        assert_eq!(cpu.regs.read(28) as i32, 1);
+        assert_eq!(cpu.regs.read(29), 5);
+        assert_eq!(cpu.regs.read(30) as i32, -123);
+        assert_eq!(cpu.regs.read(31), 0);
+        assert_eq!(cpu.pc.get(), 24);
+    }
+    #[test]
+    fn load() {
+        let program = file_to_bin("load.s");
+        let mut cpu = Cpu::new(false);
+
+        assert!(matches!(cpu.run(program), Err(Error::EndOfInstructions)));
+        assert_eq!(cpu.regs.read(27), 60);
+        assert_eq!(cpu.regs.read(30), 60);
+        assert_eq!(cpu.regs.read(29), 60);
+        assert_eq!(cpu.regs.read(28), 60);
+        assert_eq!(cpu.mem.read(Size::Byte, 64, true), 60);
+    }
+
+    #[test]
+    fn negative_load_imm() {
+        let program = file_to_bin("negative_load.s");
+        let mut cpu = Cpu::new(false);
+
+        assert!(matches!(cpu.run(program), Err(Error::EndOfInstructions)));
+        assert_eq!(cpu.regs.read(27), 21);
+        assert_eq!(cpu.regs.read(28), 60);
+        assert_eq!(cpu.regs.read(30), 60);
+        assert_eq!(cpu.mem.read(Size::Byte, 20, true), 60);
+    }
+
+    #[test]
+    fn negative_store_imm() {
+        let program = file_to_bin("negative_store.s");
+        let mut cpu = Cpu::new(false);
+
+        assert!(matches!(cpu.run(program), Err(Error::EndOfInstructions)));
+        assert_eq!(cpu.regs.read(22), 261);
+        assert_eq!(cpu.regs.read(27), 256);
+        assert_eq!(cpu.regs.read(28), 60);
+        assert_eq!(cpu.regs.read(30), 60);
+        assert_eq!(cpu.mem.read(Size::Byte, 256, true), 60);
+    }
+
+    #[test]
+    fn branch_eq() {
+        let program = file_to_bin("branch.s");
+        let mut cpu = Cpu::new(false);
+
+        assert!(matches!(cpu.run(program), Err(Error::EndOfInstructions)));
+        assert_eq!(cpu.regs.read(20) as i32, -2);
+        assert_eq!(cpu.regs.read(21), 1);
+    }
+
+    #[test]
+    fn branch_signed() {
+        let program = file_to_bin("signed_branch.s");
+        let mut cpu = Cpu::new(false);
+
+        assert!(matches!(cpu.run(program), Err(Error::EndOfInstructions)));
+        assert_eq!(cpu.regs.read(20) as i32, -1);
+        assert_eq!(cpu.regs.read(21), 1);
+    }
+    #[test]
+    fn branch_unsigned() {
+        let program = file_to_bin("unsigned_branch.s");
+        let mut cpu = Cpu::new(false);
+
+        assert!(matches!(cpu.run(program), Err(Error::EndOfInstructions)));
+        assert_eq!(cpu.regs.read(20), 100);
+        assert_eq!(cpu.regs.read(21), 100);
+    }
+
+    #[test]
+    fn fibonacci() {
+        let program = file_to_bin("fibs.s");
+        let mut cpu = Cpu::new(false);
+
+        // fibonacci terminates using exit syscall which is why result is Ok.
+        assert!(cpu.run(program).is_ok());
+        //  fibs(10) == a0 == r10 == 55
+        assert_eq!(cpu.regs.read(10), 55);
+    }
+
+    #[test]
+    fn sifive_test_finisher() {
+        let program = words_to_bin(&[
+            0x001002b7, // lui x5, 0x100
+            0x00005337, // lui x6, 0x5
+            0x55530313, // addi x6, x6, 0x555
+            0x0062a023, // sw x6, 0(x5)
+            0x00100393, // addi x7, x0, 1
+        ]);
+        let mut cpu = Cpu::new(false);
+        cpu.mem
+            .add_device(Box::new(crate::devices::SifiveTest::new()));
+
+        assert!(matches!(cpu.run(program), Ok(StopReason::Exit(0))));
+        // the instruction after the finisher write is never executed
+        assert_eq!(cpu.regs.read(7), 0);
+    }
+
+    #[test]
+    fn failed_assertion() {
+        let source = "
+            li t0, 0x104000
+            li a0, 5
+            sw a0, 0(t0)
+            sw a0, 4(t0)
+            li t1, 1
+            sw t1, 8(t0)
+            addi a0, a0, -6
+            sw a0, 0(t0)
+            li t1, 2
+            sw t1, 8(t0)
+            li t1, 3";
+        let mut cpu = Cpu::new(false);
+        cpu.set_quiet();
+        cpu.mem
+            .add_device(Box::new(crate::devices::TestAssert::new()));
+        let program = crate::asm::assemble(source, 0).unwrap().bytes;
+
+        let result = cpu.run(program);
+        let failure = crate::devices::AssertFailure {
+            id: 2,
+            actual: u32::MAX,
+            expected: 5,
+        };
+        assert!(matches!(result, Err(Error::AssertFailed(f, pc)) if f == failure && pc == 0x28));
+        assert_eq!(cpu.mem.read(Size::Word, 0x10400c, true), 1);
+        assert_eq!(cpu.regs.read(6), 2);
+    }
+
+    #[test]
+    fn dtb_passed_in_a1() {
+        let mut cpu = Cpu::new(false);
+        cpu.enable_dtb();
+
+        assert!(matches!(cpu.run(vec![]), Err(Error::EndOfInstructions)));
+        let dtb = cpu.regs.read(11);
+        assert_eq!(dtb % 16, 0);
+        assert_eq!(cpu.regs.read(2), dtb);
+        // fdt header is big-endian
+        assert_eq!(cpu.mem.read(Size::Word, dtb, true).swap_bytes(), 0xd00dfeed);
+    }
+
+    #[test]
+    fn bootrom_jumps_to_entry() {
+        let mut program = vec![0; 0x100];
+        program.extend(words_to_bin(&[
+            0x00100393, // addi x7, x0, 1
+        ]));
+        let mut cpu = Cpu::new(false);
+        cpu.enable_dtb();
+        cpu.enable_bootrom();
+        cpu.set_reset_pc(0x100);
+
+        assert!(matches!(cpu.run(program), Err(Error::EndOfInstructions)));
+        assert_eq!(cpu.regs.read(7), 1);
+        assert_eq!(cpu.regs.read(10), 0);
+        assert_eq!(cpu.regs.read(11), cpu.regs.read(2));
+        assert_eq!(cpu.regs.read(5), 0x100);
+    }
+
+    // installs the trap handler at 0x20
+    const SET_MTVEC: [u32; 2] = [
+        0x02000293, // addi x5, x0, 0x20
+        0x30529073, // csrrw x0, mtvec, x5
+    ];
+
+    #[test]
+    fn illegal_instruction_mtval() {
+        let mut program = SET_MTVEC.to_vec();
+        program.push(0xffffffff);
+        let mut cpu = Cpu::new(false);
+        cpu.mem.load_program(words_to_bin(&program));
+        for _ in 0..3 {
+            assert!(cpu.emulate_cycle().is_ok());
+        }
+
+        assert_eq!(cpu.csrs.mcause, 2);
+        assert_eq!(cpu.csrs.mtval, 0xffffffff);
+        assert_eq!(cpu.csrs.mepc, 8);
+        assert_eq!(cpu.pc.get(), 0x20);
+    }
+
+    #[test]
+    fn load_access_fault_mtval() {
+        let mut program = SET_MTVEC.to_vec();
+        program.extend([
+            0x40000337, // lui x6, 0x40000
+            0x00032383, // lw x7, 0(x6)
+        ]);
+        let mut cpu = Cpu::new(false);
+        cpu.mem.load_program(words_to_bin(&program));
+        for _ in 0..4 {
+            assert!(cpu.emulate_cycle().is_ok());
+        }
+
+        assert_eq!(cpu.csrs.mcause, 5);
+        assert_eq!(cpu.csrs.mtval, 0x40000000);
+        assert_eq!(cpu.csrs.mepc, 12);
+    }
+
+    #[test]
+    fn unhandled_access_fault() {
+        let program = words_to_bin(&[
+            0x40000337, // lui x6, 0x40000
+            0x00032383, // lw x7, 0(x6)
+        ]);
+        let mut cpu = Cpu::new(false);
+
+        assert!(matches!(
+            cpu.run(program),
+            Ok(StopReason::Trap(Exception::LoadAccessFault(0x40000000)))
+        ));
+    }
+
+    #[test]
+    fn ebreak_handler_returns() {
+        let mut program = SET_MTVEC.to_vec();
+        program.extend([
+            0x00100073, // ebreak
+            0x00100493, // addi x9, x0, 1
+            0, 0, 0, 0,          // handler skips the ebreak
+            0x34102473, // csrr x8, mepc
+            0x00440413, // addi x8, x8, 4
+            0x34141073, // csrw mepc, x8
+            0x30200073, // mret
+        ]);
+        let mut cpu = Cpu::new(false);
+
+        assert!(matches!(
+            cpu.run(words_to_bin(&program)),
+            Err(Error::EndOfInstructions)
+        ));
+        assert_eq!(cpu.regs.read(9), 1);
+        assert_eq!(cpu.csrs.mcause, 3);
+        assert_eq!(cpu.csrs.mtval, 8);
+    }
+
+    #[test]
+    fn atomics() {
+        let program = words_to_bin(&[
+            0x10000293, // addi x5, x0, 0x100
+            0x00700313, // addi x6, x0, 7
+            0x0062a023, // sw x6, 0(x5)
+            0x00500393, // addi x7, x0, 5
+            0x0072a42f, // amoadd.w x8, x7, (x5)
+            0x1002a4af, // lr.w x9, (x5)
+            0x1872a52f, // sc.w x10, x7, (x5)
+            0x1862a5af, // sc.w x11, x6, (x5)
+        ]);
+        let mut cpu = Cpu::new(false);
+
+        assert!(matches!(cpu.run(program), Err(Error::EndOfInstructions)));
+        assert_eq!(cpu.regs.read(8), 7);
+        assert_eq!(cpu.regs.read(9), 12);
+        assert_eq!(cpu.regs.read(10), 0);
+        // the reservation was consumed by the first sc
+        assert_eq!(cpu.regs.read(11), 1);
+        assert_eq!(cpu.mem.read(Size::Word, 0x100, true), 5);
+    }
+
+    #[test]
+    fn misaligned_amo() {
+        let program = words_to_bin(&[
+            0x00700313, // addi x6, x0, 7
+            0xa073262f, // amomax.w x12, x7, (x6)
+        ]);
+        let mut cpu = Cpu::new(false);
+
+        assert!(matches!(
+            cpu.run(program),
+            Ok(StopReason::Trap(Exception::StoreAddressMisaligned(7)))
+        ));
+    }
+
+    #[test]
+    fn stack_overflow() {
+        let program = words_to_bin(&[
+            0xff010113, // addi sp, sp, -16
+            0x00012023, // sw x0, 0(sp)
+            0xff9ff06f, // jal x0, -8
+        ]);
+        let mut cpu = Cpu::new(false);
+        cpu.set_stack_size(0x1000);
+
+        // the stack starts at the end of ram and the guard page is right below the 4KiB stack
+        let guard_top = MEMSIZE as u32 - 0x1000;
+        assert!(matches!(
+            cpu.run(program),
+            Err(Error::StackOverflow(address)) if address == guard_top - 16
+        ));
+        assert_eq!(cpu.regs.read(2), guard_top - 16);
+    }
+
+    // every fetch decodes the current memory contents, so patched code runs right away
+    #[test]
+    fn self_modifying_code() {
+        let program = words_to_bin(&[
+            0x005002b7, // lui x5, 0x500
+            0x41328293, // addi x5, x5, 0x413 (encodes addi x8, x0, 5)
+            0x00502823, // sw x5, 16(x0)
+            0x0000100f, // fence.i
+            0x00100413, // addi x8, x0, 1 (overwritten)
+        ]);
+        let mut cpu = Cpu::new(false);
+
+        assert!(matches!(cpu.run(program), Err(Error::EndOfInstructions)));
+        assert_eq!(cpu.regs.read(8), 5);
+    }
+
+    #[test]
+    fn exit_syscall() {
+        // a7 is only read once the ecall executes
+        let program = words_to_bin(&[
+            0x00300513, // addi a0, x0, 3
+            0x05d00893, // addi a7, x0, 93
+            0x00000073, // ecall
+        ]);
+        let mut cpu = Cpu::new(false);
+
+        assert!(matches!(cpu.run(program), Ok(StopReason::Exit(3))));
+        assert_eq!(cpu.csrs.minstret, 3);
+    }
+
+    #[test]
+    fn exit_hooks_and_stop_reasons() {
+        let program = words_to_bin(&[
+            0x00500293, // addi t0, x0, 5
+            0x00100073, // ebreak
+        ]);
+        let mut cpu = Cpu::new(false);
+        let stopped = std::rc::Rc::new(std::cell::Cell::new(None));
+        let hook_stopped = stopped.clone();
+        cpu.on_exit(move |cpu, reason| hook_stopped.set(Some((*reason, cpu.regs.get(Reg::T0)))));
+
+        assert_eq!(cpu.run(program).ok(), Some(StopReason::Break(4)));
+        assert_eq!(stopped.get(), Some((StopReason::Break(4), 5)));
+
+        let mut cpu = Cpu::new(false);
+        cpu.load(words_to_bin(&[0x0000006f])); // jal x0, 0
+        assert!(matches!(cpu.step(), Ok(None)));
+        cpu.stop_handle().store(true, Ordering::Relaxed);
+        assert!(matches!(cpu.step(), Ok(Some(StopReason::HostRequest))));
+        assert!(matches!(cpu.step(), Ok(None)));
+    }
+
+    #[test]
+    fn time_sliced_runs() {
+        // counts a0 up to 100 and exits with it
+        let program = words_to_bin(&[
+            0x06400593, // addi a1, x0, 100
+            0x00150513, // addi a0, a0, 1
+            0xfeb51ee3, // bne a0, a1, -4
+            0x05d00893, // addi a7, x0, 93
+            0x00000073, // ecall
+        ]);
+        let mut whole = Cpu::new(false);
+        assert_eq!(whole.run(program.clone()).ok(), Some(StopReason::Exit(100)));
+
+        // two cpus sharing a thread with different slices reach the same state as one run
+        let mut cpus = [Cpu::new(false), Cpu::new(false)];
+        for cpu in &mut cpus {
+            cpu.load(program.clone());
+        }
+        assert_eq!(cpus[0].run_for(0).ok(), Some(StopReason::Yield));
+        let mut slices = 0;
+        let mut reasons = [None, None];
+        while reasons.iter().any(Option::is_none) {
+            for (n, cpu) in cpus.iter_mut().enumerate() {
+                if reasons[n].is_none() {
+                    match cpu.run_for(7 + n as u64 * 10).unwrap() {
+                        StopReason::Yield => slices += 1,
+                        reason => reasons[n] = Some(reason),
+                    }
+                }
+            }
+        }
+        assert_eq!(reasons, [Some(StopReason::Exit(100)); 2]);
+        assert!(slices > 30);
+        for cpu in &cpus {
+            assert_eq!(cpu.retired(), whole.retired());
+            assert_eq!(cpu.pc.get(), whole.pc.get());
+        }
+    }
+
+    #[test]
+    fn reset_keeps_memory() {
+        // counts its boots in memory and reboots until the third one, registers and csrs have
+        // to be back at their power-on values every time
+        let source = "
+            csrr t1, mscratch
+            bnez t1, fail
+            bnez s0, fail
+            csrw mscratch, sp
+            li s0, 1
+            la t0, boots
+            lw a0, 0(t0)
+            addi a0, a0, 1
+            sw a0, 0(t0)
+            li t1, 3
+            bge a0, t1, done
+            li a0, 0xfee1dead
+            li a1, 672274793
+            li a2, 0x01234567
+            li a7, 142
+            ecall
+        fail:
+            li a0, 99
+        done:
+            li a7, 93
+            ecall
+        boots:
+            .word 0
+        ";
+        let mut cpu = Cpu::new(false);
+        cpu.load(crate::asm::assemble(source, 0).unwrap().bytes);
+        assert_eq!(cpu.run_loaded().ok(), Some(StopReason::Exit(3)));
+        let retired = cpu.retired();
+
+        cpu.reset();
+        assert_eq!(cpu.pc.get(), 0);
+        assert_eq!(cpu.regs.get(Reg::S0), 0);
+        assert_eq!(cpu.csrs.mscratch, 0);
+        assert_eq!(cpu.retired(), retired);
+        assert_eq!(cpu.run_loaded().ok(), Some(StopReason::Exit(4)));
+    }
+
+    #[test]
+    fn dma_completion_interrupt() {
+        // starts a copy, sleeps until the completion interrupt and exits with the last word
+        // copied and the claimed source in a1
+        let source = "
+            la t0, handler
+            csrw mtvec, t0
+            li t0, 0x0c000030
+            li t1, 1
+            sw t1, 0(t0)
+            li t0, 0x0c002000
+            li t1, 0x1000
+            sw t1, 0(t0)
+            li t1, 0x800
+            csrw mie, t1
+            csrsi mstatus, 8
+            li t0, 0x10007000
+            la t1, src
+            sw t1, 0(t0)
+            la t1, dst
+            sw t1, 4(t0)
+            li t1, 16
+            sw t1, 8(t0)
+            li t1, 3
+            sw t1, 12(t0)
+        wait:
+            wfi
+            j wait
+        handler:
+            li t0, 0x0c200004
+            lw a1, 0(t0)
+            la t1, dst
+            lw a0, 12(t1)
+            li a7, 93
+            ecall
+        src:
+            .word 1, 2, 3, 42
+        dst:
+            .word 0, 0, 0, 0
+        ";
+        let mut cpu = Cpu::new(false);
+        cpu.mem.add_device(Box::new(crate::devices::Plic::new()));
+        cpu.mem.add_device(Box::new(crate::devices::Dma::new()));
+        let program = crate::asm::assemble(source, 0).unwrap().bytes;
+        assert_eq!(cpu.run(program).ok(), Some(StopReason::Exit(42)));
+        assert_eq!(cpu.regs.get(Reg::A1), crate::devices::DMA_IRQ);
+    }
+
+    #[test]
+    fn supervisor_paging() {
+        // maps the megapage at 0x400000 to the program at 0, enters supervisor mode there and
+        // records the causes of the delegated traps at 0x800
+        let source = "
+            li t0, 0x1000
+            li t1, 0x0f
+            sw t1, 4(t0)
+            li t0, 0x80000001
+            csrw satp, t0
+            li t0, 0x2000
+            csrw medeleg, t0
+            li t0, 2
+            csrw mideleg, t0
+            li t1, 0x400000
+            la t0, s_trap
+            add t0, t0, t1
+            csrw stvec, t0
+            la t0, s_mode
+            add t0, t0, t1
+            csrw mepc, t0
+            li t0, 0x800
+            csrw mstatus, t0
+            li s1, 0x400800
+            mret
+        s_mode:
+            li a0, 0x1000000
+            lw a1, 0(a0)
+            li t0, 2
+            csrw sie, t0
+            csrsi sstatus, 2
+            csrsi sip, 2
+            li a0, 0
+            li a7, 93
+            ecall
+        s_trap:
+            csrr t2, scause
+            sw t2, 0(s1)
+            addi s1, s1, 4
+            bltz t2, 1f
+            csrr a2, stval
+            csrr t3, sepc
+            addi t3, t3, 4
+            csrw sepc, t3
+            sret
+        1:  csrci sip, 2
+            sret
+        ";
+        let mut cpu = Cpu::new(false);
+        let program = crate::asm::assemble(source, 0).unwrap().bytes;
+
+        assert_eq!(cpu.run(program).ok(), Some(StopReason::Exit(0)));
+        assert_eq!(cpu.csrs.mode, Mode::Supervisor);
+        assert_eq!(cpu.mem.read(Size::Word, 0x800, true), 13);
+        assert_eq!(cpu.mem.read(Size::Word, 0x804, true), INTERRUPT | 1);
+        assert_eq!(cpu.regs.get(Reg::A2), 0x100_0000);
+        // the walk marked the page as accessed and written
+        assert_eq!(cpu.mem.read(Size::Word, 0x1004, true), 0xcf);
+    }
+
+    #[test]
+    fn watchdog_reset() {
+        // arms the watchdog and hangs, exits with the cause register after the reset
+        let source = "
+            li t0, 0x10006000
+            lw a0, 8(t0)
+            bnez a0, done
+            li t1, 50
+            sw t1, 0(t0)
+        hang:
+            j hang
+        done:
+            li a7, 93
+            ecall
+        ";
+        let mut cpu = Cpu::new(false);
+        cpu.mem
+            .add_device(Box::new(crate::devices::Watchdog::new()));
+        let program = crate::asm::assemble(source, 0).unwrap().bytes;
+        assert_eq!(cpu.run(program).ok(), Some(StopReason::Exit(1)));
+    }
 
-        for cycle in 0.. {
-            match self.emulate_cycle() {
-                Ok(ProgState::Exit(code)) => {
-                    self.dump_state(cycle);
-                    return Ok(code);
-                }
-                Err(e) => {
-                    self.dump_state(cycle);
-                    return Err(e);
-                }
-                _ => (),
-            }
-            if self.print_debug {
-                self.dump_state(cycle);
-            }
-        }
+    #[test]
+    fn hint_hooks() {
+        let program = words_to_bin(&[
+            0x00001537, // lui a0, 0x1
+            0x04156013, // prefetch.r 64(a0)
+            0x0100000f, // pause
+            0x00150513, // addi a0, a0, 1
+            0x00100073, // ebreak
+        ]);
+        let mut cpu = Cpu::new(false);
+        cpu.set_quiet();
+        let hints = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let hook_hints = hints.clone();
+        cpu.on_hint(move |_, pc, hint| hook_hints.borrow_mut().push((pc, hint)));
 
-        unreachable!("Emulator should either run out of instructions or exit using syscall")
+        assert_eq!(cpu.run(program).ok(), Some(StopReason::Break(16)));
+        assert_eq!(
+            *hints.borrow(),
+            [(4, Hint::Prefetch(Access::Load, 0x1040)), (8, Hint::Pause)]
+        );
+        // hints execute as nops
+        assert_eq!(cpu.regs.get(Reg::A0), 0x1001);
     }
 
-    fn dump_state(&self, cycle_count: usize) {
-        eprintln!("CPU dump at cycle {cycle_count}:");
-        eprintln!("PC: {}", self.pc.get());
-        for i in 0..32 {
-            eprintln!("R{i}: {}", self.regs.read(i) as i32);
-        }
+    #[test]
+    fn captured_output() {
+        let program = words_to_bin(&[
+            0x100002b7, // lui t0, 0x10000 (uart)
+            0x06800313, // addi t1, x0, 'h'
+            0x00628023, // sb t1, 0(t0)
+            0x06900313, // addi t1, x0, 'i'
+            0x00628023, // sb t1, 0(t0)
+            0x001022b7, // lui t0, 0x102 (debug console)
+            0x02100313, // addi t1, x0, '!'
+            0x00628023, // sb t1, 0(t0)
+            0x00000513, // addi a0, x0, 0
+            0x05d00893, // addi a7, x0, 93
+            0x00000073, // ecall
+        ]);
+        let mut cpu = Cpu::new(false);
+        cpu.mem = Machine::FreertosDemo.memory(RtcClock::Frozen(0));
+        cpu.mem
+            .add_device(Box::new(DebugConsole::new(Box::new(std::io::sink()))));
+        cpu.set_reset_pc(Machine::FreertosDemo.reset_pc());
+        let output = cpu.mem.capture_output();
+
+        assert!(matches!(cpu.run(program), Ok(StopReason::Exit(0))));
+        assert_eq!(output.text(), "hi!");
     }
 
-    // fetches next instruction from memory
-    fn fetch(&mut self) -> Result<u32, Error> {
-        let pc = self.pc.inc()?;
-        Ok(self.mem.read(Size::Word, pc, true))
-    }
-
-    // parses raw byte instruction into correct format
-    // for decode information see: [riscv-ref](crate::docs/riscv-ref)
-    fn decode(&self, raw_inst: u32) -> Result<Inst, Error> {
-        // get the lowest 7 bits for the opcode
-        let opcode = get_bits!(raw_inst, 0, 6);
-        let inst = match opcode {
-            0b0110011 => {
-                let r_format = RFormat::new(raw_inst);
-                let inst = match (r_format.funct3, r_format.funct7) {
-                    (0x0, 0x00) => RInst::ADD,
-                    (0x0, 0x20) => RInst::SUB,
-                    (0x4, 0x00) => RInst::XOR,
-                    (0x6, 0x00) => RInst::OR,
-                    (0x7, 0x00) => RInst::AND,
-                    (0x1, 0x00) => RInst::SLL,
-                    (0x5, 0x00) => RInst::SRL,
-                    (0x5, 0x20) => RInst::SRA,
-                    (0x2, 0x00) => RInst::SLT,
-                    (0x3, 0x00) => RInst::SLTU,
-                    _ => return Err(Error::InvalidInstFormat(FormatError::R(r_format))),
-                };
+    #[test]
+    fn execution_counts() {
+        let program = words_to_bin(&[
+            0x00300513, // addi a0, x0, 3
+            0xfff50513, // addi a0, a0, -1
+            0xfe051ee3, // bne a0, x0, -4
+            0x05d00893, // addi a7, x0, 93
+            0x00000073, // ecall
+        ]);
+        let mut cpu = Cpu::new(false);
+        cpu.set_quiet();
+        cpu.enable_exec_counts();
 
-                Inst::R(inst, r_format)
-            }
-            0b0010011 => {
-                let i_format = IFormat::new(raw_inst);
-                let upper_imm = get_bits!(i_format.imm, 5, 11);
-                let inst = match (i_format.funct3, upper_imm) {
-                    (0x0, _) => ArithIInst::ADDI,
-                    (0x4, _) => ArithIInst::XORI,
-                    (0x6, _) => ArithIInst::ORI,
-                    (0x7, _) => ArithIInst::ANDI,
-                    (0x1, 0x00) => ArithIInst::SLLI,
-                    (0x5, 0x00) => ArithIInst::SRLI,
-                    (0x5, 0x20) => ArithIInst::SRAI,
-                    (0x2, _) => ArithIInst::SLTI,
-                    (0x3, _) => ArithIInst::SLTIU,
-                    _ => return Err(Error::InvalidInstFormat(FormatError::I(i_format))),
-                };
+        assert!(matches!(cpu.run(program), Ok(StopReason::Exit(0))));
+        let counts = cpu.exec_counts().unwrap();
+        assert_eq!(counts[&0], 1);
+        assert_eq!(counts[&4], 3);
+        assert_eq!(counts[&8], 3);
+        assert_eq!(counts[&16], 1);
+    }
 
-                Inst::I(IInst::Arith(inst), i_format)
-            }
-            0b0000011 => {
-                let i_format = IFormat::new(raw_inst);
-                let inst = match i_format.funct3 {
-                    0x0 => LoadIInst::LB,
-                    0x1 => LoadIInst::LH,
-                    0x2 => LoadIInst::LW,
-                    0x4 => LoadIInst::LBU,
-                    0x5 => LoadIInst::LHU,
-                    _ => return Err(Error::InvalidInstFormat(FormatError::I(i_format))),
-                };
+    #[test]
+    fn register_history() {
+        let program = words_to_bin(&[
+            0x00500293, // addi t0, x0, 5
+            0x00128293, // addi t0, t0, 1
+            0x00128293, // addi t0, t0, 1
+        ]);
+        let mut cpu = Cpu::new(false);
+        cpu.enable_reg_history(2);
 
-                Inst::I(IInst::Mem(inst), i_format)
-            }
-            0b1100111 => {
-                let i_format = IFormat::new(raw_inst);
-                if let 0x0 = i_format.funct3 {
-                    Inst::I(IInst::Jalr, i_format)
-                } else {
-                    return Err(Error::InvalidInstFormat(FormatError::I(i_format)));
-                }
-            }
-            0b0100011 => {
-                let s_format = SFormat::new(raw_inst);
-                let inst = match s_format.funct3 {
-                    0x0 => SInst::SB,
-                    0x1 => SInst::SH,
-                    0x2 => SInst::SW,
-                    _ => return Err(Error::InvalidInstFormat(FormatError::S(s_format))),
-                };
+        assert!(matches!(cpu.run(program), Err(Error::EndOfInstructions)));
+        let writes: Vec<_> = cpu
+            .reg_history
+            .as_ref()
+            .unwrap()
+            .writes()
+            .map(|write| (write.cycle, write.pc, write.change.old, write.change.new))
+            .collect();
+        assert_eq!(writes, [(1, 4, 5, 6), (2, 8, 6, 7)]);
+    }
 
-                Inst::S(inst, s_format)
-            }
-            0b1100011 => {
-                let b_format = BFormat::new(raw_inst);
-                let inst = match b_format.funct3 {
-                    0x0 => BInst::BEQ,
-                    0x1 => BInst::BNE,
-                    0x4 => BInst::BLT,
-                    0x5 => BInst::BGE,
-                    0x6 => BInst::BLTU,
-                    0x7 => BInst::BGEU,
-                    _ => return Err(Error::InvalidInstFormat(FormatError::B(b_format))),
-                };
+    #[test]
+    fn instruction_history() {
+        let program = words_to_bin(&[
+            0x00500293, // addi t0, x0, 5
+            0x00528333, // add t1, t0, t0
+            0xffffffff, // invalid
+        ]);
+        let mut cpu = Cpu::new(false);
 
-                Inst::B(inst, b_format)
-            }
-            0b1101111 => {
-                // jal instruction is the only J-Format instruction
-                Inst::J(JFormat::new(raw_inst))
-            }
-            0b0110111 => Inst::U(UInst::LUI, UFormat::new(raw_inst)),
-            0b0010111 => Inst::U(UInst::AUIPC, UFormat::new(raw_inst)),
-            0b1110011 => {
-                // ecall
-                let call = if self.regs.read(17) == 93 {
-                    // intercept exit syscall (a7 == 93) to check official risc-v testsuite
-                    SysCall::Exit(self.regs.read(10) as u8)
-                } else {
-                    SysCall::Nop
-                };
-                Inst::SysCall(call)
-            }
-            0b0001111 => {
-                // fence (also necessary for riscv-tests)
-                Inst::SysCall(SysCall::Nop)
-            }
-            _ => return Err(Error::InvalidOpcode(opcode)),
+        assert!(cpu.run(program).is_err());
+        let insts: Vec<_> = cpu
+            .inst_history
+            .insts()
+            .map(|inst| (inst.pc, inst.raw_inst, inst.operands))
+            .collect();
+        assert_eq!(insts[1], (4, 0x00528333, [Some((Reg::T0, 5)), None]));
+        assert_eq!(insts[2], (8, 0xffffffff, [None, None]));
+    }
+
+    #[test]
+    fn commit_trace() {
+        let program = words_to_bin(&[
+            0x00500293, // addi t0, x0, 5
+            0x00000013, // addi x0, x0, 0
+            0x05d00893, // addi a7, x0, 93
+            0x00000073, // ecall
+        ]);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trace.log");
+        let mut cpu = Cpu::new(false);
+        cpu.trace_filter.add_range(0..8);
+        cpu.enable_trace(TraceWriter::create(&path, TraceFormat::Commit, None).unwrap());
+
+        assert!(matches!(cpu.run(program), Ok(StopReason::Exit(0))));
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "0x00000000 (0x00500293) t0 0x00000005\n0x00000004 (0x00000013)\n"
+        );
+    }
+
+    #[test]
+    fn state_hashes() {
+        let hash = |mode, t0: u32| {
+            let program = words_to_bin(&[
+                0x00000293 | t0 << 20, // addi t0, x0, t0
+                0x00502023,            // sw t0, 0(x0)
+                0x05d00893,            // addi a7, x0, 93
+                0x00000073,            // ecall
+            ]);
+            let mut cpu = Cpu::new(false);
+            cpu.set_quiet();
+            cpu.enable_state_hash(mode);
+            assert!(matches!(cpu.run(program), Ok(StopReason::Exit(0))));
+            cpu.state_hash().unwrap()
         };
+        assert_eq!(hash(HashMode::Commit, 5), hash(HashMode::Commit, 5));
+        assert_ne!(hash(HashMode::Commit, 5), hash(HashMode::Commit, 6));
+        assert_eq!(hash(HashMode::Final, 5), hash(HashMode::Final, 5));
+        assert_ne!(hash(HashMode::Final, 5), hash(HashMode::Final, 6));
+        assert_ne!(hash(HashMode::Final, 5), hash(HashMode::Commit, 5));
+        assert_eq!(Cpu::new(false).state_hash(), None);
+    }
 
-        Ok(inst)
+    #[test]
+    fn run_to_address() {
+        let program = words_to_bin(&[
+            0x00500293, // addi t0, x0, 5
+            0xfff28293, // addi t0, t0, -1
+            0xfe029ee3, // bne t0, x0, -4
+            0x05d00893, // addi a7, x0, 93
+            0x00000073, // ecall
+        ]);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trace.log");
+        let mut cpu = Cpu::new(false);
+        cpu.set_run_to(12);
+        cpu.enable_trace(TraceWriter::create(&path, TraceFormat::Commit, None).unwrap());
+
+        assert!(matches!(cpu.run(program), Ok(StopReason::Exit(0))));
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "0x0000000c (0x05d00893) a7 0x0000005d\n0x00000010 (0x00000073)\n"
+        );
     }
 
-    fn emulate_cycle(&mut self) -> Result<ProgState, Error> {
-        let raw_inst = self.fetch()?;
-        if raw_inst == 0 {
-            return Err(Error::EndOfInstructions);
-        }
-        if self.print_debug {
-            eprintln!("Inst: {:032b}", raw_inst);
-        }
+    #[test]
+    fn nested_traps() {
+        let mut program = vec![
+            0x04000393, // addi t2, x0, 0x40
+            0x30539073, // csrw mtvec, t2
+            0x30046073, // csrsi mstatus, 8
+            0x00100073, // ebreak
+            0x30002973, // csrr s2, mstatus
+            0x05d00893, // addi a7, x0, 93
+            0x00000073, // ecall
+        ];
+        // handler at 0x40, the first trap re-enables interrupts and traps again
+        program.resize(0x10, 0);
+        program.extend([
+            0x02029863, // bne t0, x0, nested
+            0x00100293, // addi t0, x0, 1
+            0x34102473, // csrr s0, mepc
+            0x300024f3, // csrr s1, mstatus
+            0x30046073, // csrsi mstatus, 8
+            0x00100073, // ebreak
+            0x30002a73, // csrr s4, mstatus
+            0x30049073, // csrw mstatus, s1
+            0x00440413, // addi s0, s0, 4
+            0x34141073, // csrw mepc, s0
+            0x30200073, // mret
+        ]);
+        // nested: at 0x70
+        program.resize(0x1c, 0);
+        program.extend([
+            0x300029f3, // csrr s3, mstatus
+            0x34102373, // csrr t1, mepc
+            0x00430313, // addi t1, t1, 4
+            0x34131073, // csrw mepc, t1
+            0x30200073, // mret
+        ]);
+        let mut cpu = Cpu::new(false);
 
-        let inst = self.decode(raw_inst)?;
-        if let Inst::SysCall(SysCall::Exit(code)) = inst {
-            return Ok(ProgState::Exit(code));
-        }
+        assert!(matches!(
+            cpu.run(words_to_bin(&program)),
+            Ok(StopReason::Exit(0))
+        ));
+        // the outer handler runs with interrupts disabled, the outer ones were enabled
+        assert_eq!(cpu.regs.read(9), MSTATUS_MPIE | MSTATUS_MPP);
+        // the nested trap saved the re-enabled MIE
+        assert_eq!(cpu.regs.read(19), MSTATUS_MPIE | MSTATUS_MPP);
+        // which the nested mret restored, leaving user mode in mpp
+        assert_eq!(cpu.regs.read(20), MSTATUS_MIE | MSTATUS_MPIE);
+        assert_eq!(cpu.regs.read(18), MSTATUS_MIE | MSTATUS_MPIE);
+    }
+
+    #[test]
+    fn parked_hart_woken_by_msip() {
+        let mut program = vec![
+            0x800012b7, // lui t0, 0x80001
+            0x02800613, // addi a2, x0, 40
+            0x00c2a223, // sw a2, 4(t0)
+            0x020005b7, // lui a1, 0x2000
+            0x00100613, // addi a2, x0, 1
+            0x00c5a223, // sw a2, 4(a1) (msip of hart 1)
+            0x0002a303, // lw t1, 0(t0)
+            0xfe030ee3, // beq t1, x0, -4
+            0x00030513, // addi a0, t1, 0
+            0x05d00893, // addi a7, x0, 93
+            0x00000073, // ecall
+        ];
+        // hart 1 at 0x40
+        program.resize(0x10, 0);
+        program.extend([
+            0xf1402573, // csrr a0, mhartid
+            0x020005b7, // lui a1, 0x2000
+            0x0005a223, // sw x0, 4(a1)
+            0x800012b7, // lui t0, 0x80001
+            0x0042a603, // lw a2, 4(t0)
+            0x00c50533, // add a0, a0, a2
+            0x00150513, // addi a0, a0, 1
+            0x00a2a023, // sw a0, 0(t0)
+            0x0000006f, // jal x0, 0
+        ]);
+        let mut cpu = Cpu::new(false);
+        cpu.mem = Machine::FreertosDemo.memory(RtcClock::Frozen(0));
+        cpu.set_reset_pc(Machine::FreertosDemo.reset_pc());
+        cpu.set_harts(2);
+        cpu.configure_hart(
+            1,
+            HartConfig {
+                entry: Some(0x80000040),
+                sp: None,
+                parked: true,
+            },
+        );
+        cpu.set_cycle_limit(1000);
 
-        inst.execute(self);
-        Ok(ProgState::Continue)
+        // hart 1 only reads the value stored by hart 0 if it stayed parked until then
+        assert!(matches!(
+            cpu.run(words_to_bin(&program)),
+            Ok(StopReason::Exit(42))
+        ));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::io::Write;
-    use std::path::Path;
-    use std::process::Command;
+    #[test]
+    fn remote_store_invalidates_reservation() {
+        let mut program = vec![
+            0x800012b7, // lui t0, 0x80001
+            0x1002a32f, // lr.w t1, (t0)
+            0x00100393, // addi t2, x0, 1
+            0x0072a423, // sw t2, 8(t0)
+            0x0042a383, // lw t2, 4(t0)
+            0xfe038ee3, // beq t2, x0, -4
+            0x1862a52f, // sc.w a0, t1, (t0)
+            0x05d00893, // addi a7, x0, 93
+            0x00000073, // ecall
+        ];
+        // hart 1 at 0x40 stores to the reserved word once hart 0 holds the reservation
+        program.resize(0x10, 0);
+        program.extend([
+            0x800012b7, // lui t0, 0x80001
+            0x0082a383, // lw t2, 8(t0)
+            0xfe038ee3, // beq t2, x0, -4
+            0x00500313, // addi t1, x0, 5
+            0x0062a023, // sw t1, 0(t0)
+            0x0062a223, // sw t1, 4(t0)
+            0x0000006f, // jal x0, 0
+        ]);
+        let mut cpu = Cpu::new(false);
+        cpu.mem = Machine::FreertosDemo.memory(RtcClock::Frozen(0));
+        cpu.set_reset_pc(Machine::FreertosDemo.reset_pc());
+        cpu.set_harts(2);
+        cpu.configure_hart(
+            1,
+            HartConfig {
+                entry: Some(0x80000040),
+                ..HartConfig::default()
+            },
+        );
+        cpu.set_cycle_limit(1000);
 
-    // NOTE: The testcases in tests/ terminate by running out of instructions.
-    // This is by design, as I don't want to exit each testcase using ecall.
+        // sc fails and doesn't overwrite the value stored by hart 1
+        assert!(matches!(
+            cpu.run(words_to_bin(&program)),
+            Ok(StopReason::Exit(1))
+        ));
+        assert_eq!(cpu.mem.read(Size::Word, 0x80001000, true), 5);
+    }
 
-    fn file_to_bin(path: &'static str) -> Vec<u8> {
-        let mut current_path = std::env::current_dir().unwrap();
-        current_path.push("tests");
-        current_path.push(path);
-        create_bin(current_path.as_path())
+    #[test]
+    fn ecall_traps_with_handler() {
+        let mut program = SET_MTVEC.to_vec();
+        program.extend([
+            0x00000073, // ecall
+            0, 0, 0, 0, 0, 0x34202473, // csrr x8, mcause
+        ]);
+        let mut cpu = Cpu::new(false);
+
+        assert!(matches!(
+            cpu.run(words_to_bin(&program)),
+            Err(Error::EndOfInstructions)
+        ));
+        assert_eq!(cpu.regs.read(8), 11);
+        assert_eq!(cpu.csrs.mepc, 8);
     }
-    fn asm_to_bin(asm: &'static str) -> Vec<u8> {
-        let mut asm_temp = tempfile::Builder::new()
-            .suffix(".s")
-            .tempfile()
-            .expect("tempfile create");
-        write!(asm_temp, ".global _start\n_start:\n{}", asm).expect("write asm to tempfile");
-        create_bin(asm_temp.path())
+
+    #[test]
+    fn strict_syscalls() {
+        let program = words_to_bin(&[
+            0x00100513, // addi a0, x0, 1
+            0x0ac00893, // addi a7, x0, 172 (getpid)
+            0x00000073, // ecall
+            0x05d00893, // addi a7, x0, 93
+            0x00000073, // ecall
+        ]);
+        let mut cpu = Cpu::new(false);
+        cpu.set_quiet();
+        assert!(matches!(cpu.run(program.clone()), Ok(StopReason::Exit(1))));
+
+        let mut cpu = Cpu::new(false);
+        cpu.set_quiet();
+        cpu.enable_strict_syscalls();
+        assert!(matches!(
+            cpu.run(program),
+            Err(Error::UnimplementedSyscall(172, [1, _, _, _, _, _]))
+        ));
     }
 
-    fn create_bin(asm_filepath: &Path) -> Vec<u8> {
-        let executable = tempfile::NamedTempFile::new().expect("tempfile create");
-        assert!(
-            Command::new("riscv64-unknown-elf-gcc")
-                .args([
-                    "-Wl,-Ttext=0x0",
-                    "-nostdlib",
-                    "-o",
-                    executable.path().to_str().unwrap(),
-                    asm_filepath.to_str().unwrap(),
-                    "-march=rv32i",
-                    "-mabi=ilp32",
-                ])
-                .status()
-                .expect("invokes riscv gcc cross compiler")
-                .success(),
-            "invalid asm"
+    #[test]
+    fn environments() {
+        let program = words_to_bin(&[
+            0x00100513, // addi a0, x0, 1
+            0x11600893, // addi a7, x0, 278 (getrandom)
+            0x00000073, // ecall
+            0x05d00893, // addi a7, x0, 93
+            0x00000073, // ecall
+        ]);
+        // without environment the exit syscall is an ordinary ecall
+        let mut cpu = Cpu::new(false);
+        cpu.set_quiet();
+        cpu.set_env(Env::Bare);
+        assert_eq!(
+            cpu.run(program.clone()).ok(),
+            Some(StopReason::Trap(Exception::EnvironmentCall(Mode::Machine)))
         );
+        assert_eq!(cpu.regs.get(Reg::A0), 1);
 
-        let binary = tempfile::NamedTempFile::new().expect("tempfile create");
+        // getrandom isn't part of newlib and is ignored, 0 random bytes would be written otherwise
+        let mut cpu = Cpu::new(false);
+        cpu.set_quiet();
+        cpu.set_env(Env::Newlib);
+        assert_eq!(cpu.run(program.clone()).ok(), Some(StopReason::Exit(1)));
+        cpu.set_env(Env::Linux);
+        assert_eq!(cpu.run(program).ok(), Some(StopReason::Exit(0)));
+    }
+
+    // Two tasks increment their own counter and are preempted by the machine timer, whose
+    // handler swaps the task contexts like an rtos scheduler. Stops after 10 context switches.
+    #[test]
+    fn timer_preemption() {
+        let mut program = vec![
+            0x80001537, // lui a0, 0x80001 (data section)
+            0x800005b7, // lui a1, 0x80000
+            0x14058593, // addi a1, a1, 0x140
+            0x00b52823, // sw a1, 0x10(a0) (saved pc of task b)
+            0x800005b7, // lui a1, 0x80000
+            0x20058593, // addi a1, a1, 0x200
+            0x30559073, // csrw mtvec, a1
+            0x020045b7, // lui a1, 0x2004
+            0x06400613, // addi a2, x0, 100
+            0x00c5a023, // sw a2, 0(a1) (mtimecmp)
+            0x0005a223, // sw x0, 4(a1)
+            0x08000613, // addi a2, x0, 0x80
+            0x30461073, // csrw mie, a2
+            0x30046073, // csrsi mstatus, 8
+            0x0c80006f, // jal x0, task_a
+        ];
+        // task a at 0x100
+        program.resize(0x40, 0);
+        program.extend([
+            0x800012b7, // lui t0, 0x80001
+            0x0002a303, // lw t1, 0(t0)
+            0x00130313, // addi t1, t1, 1
+            0x0062a023, // sw t1, 0(t0)
+            0xff5ff06f, // jal x0, -12
+        ]);
+        // task b at 0x140
+        program.resize(0x50, 0);
+        program.extend([
+            0x800012b7, // lui t0, 0x80001
+            0x00428293, // addi t0, t0, 4
+            0x0002a303, // lw t1, 0(t0)
+            0x00130313, // addi t1, t1, 1
+            0x0062a023, // sw t1, 0(t0)
+            0xff5ff06f, // jal x0, -12
+        ]);
+        // timer handler at 0x200
+        program.resize(0x80, 0);
+        program.extend([
+            0x80001537, // lui a0, 0x80001
+            0x01052583, // lw a1, 0x10(a0)
+            0x01452603, // lw a2, 0x14(a0)
+            0x01852683, // lw a3, 0x18(a0)
+            0x34102773, // csrr a4, mepc
+            0x00e52823, // sw a4, 0x10(a0)
+            0x00552a23, // sw t0, 0x14(a0)
+            0x00652c23, // sw t1, 0x18(a0)
+            0x34159073, // csrw mepc, a1
+            0x00060293, // addi t0, a2, 0
+            0x00068313, // addi t1, a3, 0
+            0x0200c5b7, // lui a1, 0x200c
+            0xff85a603, // lw a2, -8(a1) (mtime)
+            0x06460613, // addi a2, a2, 100
+            0x020045b7, // lui a1, 0x2004
+            0x00c5a023, // sw a2, 0(a1) (mtimecmp)
+            0x02052603, // lw a2, 0x20(a0)
+            0x00160613, // addi a2, a2, 1
+            0x02c52023, // sw a2, 0x20(a0)
+            0x00a00693, // addi a3, x0, 10
+            0x00d61a63, // bne a2, a3, 20
+            0x001005b7, // lui a1, 0x100
+            0x00005637, // lui a2, 5
+            0x55560613, // addi a2, a2, 0x555
+            0x00c5a023, // sw a2, 0(a1) (test finisher)
+            0x30200073, // mret
+        ]);
+        let mut cpu = Cpu::new(false);
+        cpu.mem = Machine::FreertosDemo.memory(RtcClock::Frozen(0));
+        cpu.set_reset_pc(Machine::FreertosDemo.reset_pc());
+
+        assert!(matches!(
+            cpu.run(words_to_bin(&program)),
+            Ok(StopReason::Exit(0))
+        ));
+        assert_eq!(cpu.csrs.mcause, INTERRUPT | 7);
+        // interrupts stay disabled inside the handler
+        assert_eq!(cpu.csrs.mstatus & MSTATUS_MIE, 0);
+        assert_eq!(cpu.mem.read(Size::Word, 0x8000_1020, true), 10);
+        let task_a = cpu.mem.read(Size::Word, 0x8000_1000, true);
+        let task_b = cpu.mem.read(Size::Word, 0x8000_1004, true);
         assert!(
-            Command::new("riscv64-unknown-elf-objcopy")
-                .args([
-                    "-O",
-                    "binary",
-                    executable.path().to_str().unwrap(),
-                    binary.path().to_str().unwrap(),
-                ])
-                .status()
-                .expect("invokes riscv objcopy")
-                .success(),
-            "invalid elf"
+            task_a > 0 && task_b > 0,
+            "both tasks ran: {task_a}, {task_b}"
         );
-
-        crate::read_bin(binary.path().to_str().unwrap())
     }
 
+    // wfi skips ahead to the timer deadline instead of executing a cycle per tick, the timer
+    // interrupt isn't taken since mstatus.MIE is clear but still resumes execution
     #[test]
-    fn x0_hardwired() {
-        let program = asm_to_bin("addi x0, x0, -127\n");
+    fn wfi_until_timer() {
+        let program = words_to_bin(&[
+            0x020045b7, // lui a1, 0x2004
+            0x7d000613, // addi a2, x0, 2000
+            0x00c5a023, // sw a2, 0(a1) (mtimecmp)
+            0x0005a223, // sw x0, 4(a1)
+            0x08000613, // addi a2, x0, 0x80
+            0x30461073, // csrw mie, a2
+            0x10500073, // wfi
+            0x34402473, // csrr x8, mip
+            0x0200c5b7, // lui a1, 0x200c
+            0xff85a483, // lw x9, -8(a1) (mtime)
+        ]);
         let mut cpu = Cpu::new(false);
+        cpu.mem = Machine::FreertosDemo.memory(RtcClock::Frozen(0));
+        cpu.set_reset_pc(Machine::FreertosDemo.reset_pc());
         cpu.mem.load_program(program);
+        cpu.reset();
 
-        assert!(cpu.emulate_cycle().is_ok());
-        assert_eq!(0, cpu.regs.read(0));
+        let mut cycles = 0;
+        while cpu.emulate_cycle().is_ok() {
+            cycles += 1;
+        }
+        assert!(cycles < 20, "wfi spun for {cycles} cycles");
+        assert_eq!(cpu.regs.read(8), MIP_MTIP);
+        assert!(cpu.regs.read(9) >= 2000);
+        assert!(!cpu.waiting);
     }
 
+    // without an event to wait for, every wfi cycle skips the longest idle time
     #[test]
-    fn negative_assign() {
-        let program = asm_to_bin("addi x31, x0, -127\n");
+    fn idle_without_sleep() {
+        let program = words_to_bin(&[
+            0x00800293, // addi t0, x0, 8
+            0x30429073, // csrw mie, t0
+            0x10500073, // wfi
+            0xffdff06f, // j -4
+        ]);
         let mut cpu = Cpu::new(false);
-        cpu.mem.load_program(program);
+        cpu.set_quiet();
+        cpu.disable_idle_sleep();
+        cpu.set_cycle_limit(200);
 
-        assert!(cpu.emulate_cycle().is_ok());
-        let n = -127;
-        assert_eq!(n as u32, cpu.regs.read(31));
-        assert_eq!(0, cpu.regs.read(0));
+        let start = std::time::Instant::now();
+        assert!(matches!(cpu.run(program), Ok(StopReason::Limit(200))));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(cpu.read_csr(TIME).unwrap() as u64 >= 100 * MAX_IDLE_CYCLES);
     }
 
     #[test]
-    fn auipc_copy() {
-        let program = asm_to_bin("auipc x10, 0\n");
+    fn store_watchpoint() {
+        let mut program = SET_MTVEC.to_vec();
+        program.extend([
+            0x30046073, // csrsi mstatus, 8
+            0x10000293, // addi x5, x0, 0x100
+            0x7a229073, // csrw tdata2, x5
+            0x04200313, // addi x6, x0, 0x42 (m, store)
+            0x7a131073, // csrw tdata1, x6
+            0x0052a023, // sw x5, 0(x5)
+            0x34202473, // csrr x8, mcause
+            0x343024f3, // csrr x9, mtval
+            0x34102973, // csrr x18, mepc
+        ]);
         let mut cpu = Cpu::new(false);
 
-        assert!(matches!(cpu.run(program), Err(Error::EndOfInstructions)));
-        assert_eq!(cpu.regs.read(10), 0);
+        assert!(matches!(
+            cpu.run(words_to_bin(&program)),
+            Err(Error::EndOfInstructions)
+        ));
+        assert_eq!(cpu.regs.read(8), 3);
+        assert_eq!(cpu.regs.read(9), 0x100);
+        assert_eq!(cpu.regs.read(18), 0x1c);
+        // the store didn't happen
+        assert_eq!(cpu.mem.read(Size::Word, 0x100, true), 0);
     }
 
     #[test]
-    fn auipc_offset() {
-        let program = asm_to_bin("addi x11, x0, 12\nauipc x10, 4\n");
+    fn debugger_watchpoint() {
         let mut cpu = Cpu::new(false);
+        cpu.set_quiet();
+        cpu.load(words_to_bin(&[
+            0x00700513, // addi a0, zero, 7
+            0x20a02023, // sw a0, 512(zero)
+            0x20002583, // lw a1, 512(zero)
+        ]));
+        cpu.watchpoints.add_watchpoint(WatchKind::Write, 0x200, 4);
+        assert_eq!(
+            cpu.run_until_stop().ok(),
+            Some(StopReason::Debug(DebugStop::Watchpoint(
+                WatchKind::Write,
+                0x200
+            )))
+        );
+        // stopped before the store
+        assert_eq!(cpu.pc.get(), 4);
+        assert_eq!(cpu.mem.read(Size::Word, 0x200, true), 0);
 
-        assert!(matches!(cpu.run(program), Err(Error::EndOfInstructions)));
-        assert_eq!(cpu.regs.read(10), 16388);
+        // resuming executes the store instead of stopping again
+        assert!(matches!(
+            cpu.run_until_stop(),
+            Err(Error::EndOfInstructions)
+        ));
+        assert_eq!(cpu.regs.read(11), 7);
     }
 
     #[test]
-    fn arithmetic() {
-        let program = file_to_bin("arith.s");
+    fn tracepoint_log() {
         let mut cpu = Cpu::new(false);
+        cpu.set_quiet();
+        let log = crate::devices::Capture::new();
+        cpu.enable_tracepoints(Box::new(log.clone())).unwrap();
+        let program = words_to_bin(&[
+            0x02a00513, // addi a0, zero, 42
+            0x001032b7, // lui t0, 0x103
+            0x00700313, // addi t1, zero, 7
+            0x0062a023, // sw t1, 0(t0)
+            0x00150513, // addi a0, a0, 1
+            0x0062a223, // sw t1, 4(t0)
+            0x00a2a023, // sw a0, 0(t0)
+        ]);
 
         assert!(matches!(cpu.run(program), Err(Error::EndOfInstructions)));
-        assert_eq!(cpu.regs.read(27) as i32, -26);
-        assert_eq!(cpu.regs.read(28) as i32, -6);
-        assert_eq!(cpu.regs.read(29), 5);
-        assert_eq!(cpu.regs.read(30) as i32, -32);
-        assert_eq!(cpu.regs.read(31) as i32, 42);
-        assert_eq!(cpu.pc.get(), 28);
+        assert_eq!(log.text(), "cycle,hart,id,a0\n3,0,7,0x2a\n6,0,43,0x2b\n");
     }
 
     #[test]
-    fn bitops() {
-        let program = file_to_bin("bitops.s");
+    fn access_log() {
         let mut cpu = Cpu::new(false);
+        cpu.set_quiet();
+        let log = crate::devices::Capture::new();
+        let access_log = AccessLog::new(Box::new(log.clone()), LogFormat::Csv).unwrap();
+        cpu.enable_access_log(access_log, vec![0x200..0x204, 0x300..0x301]);
+        let program = words_to_bin(&[
+            0x02a00513, // addi a0, zero, 42
+            0x20a02023, // sw a0, 0x200(zero)
+            0x20a02223, // sw a0, 0x204(zero)
+            0x30004583, // lbu a1, 0x300(zero)
+        ]);
 
         assert!(matches!(cpu.run(program), Err(Error::EndOfInstructions)));
-        assert_eq!(cpu.regs.read(28) as i32, 1);
-        assert_eq!(cpu.regs.read(29), 5);
-        assert_eq!(cpu.regs.read(30) as i32, -123);
-        assert_eq!(cpu.regs.read(31), 0);
-        assert_eq!(cpu.pc.get(), 24);
+        assert_eq!(
+            log.text(),
+            "cycle,hart,pc,access,address,size,value
+1,0,0x4,w,0x200,4,0x2a
+3,0,0xc,r,0x300,1,0x0
+"
+        );
     }
+
     #[test]
-    fn load() {
-        let program = file_to_bin("load.s");
+    fn stack_usage() {
         let mut cpu = Cpu::new(false);
+        cpu.set_quiet();
+        cpu.enable_stack_usage();
+        let program = words_to_bin(&[
+            0xff010113, // addi sp, sp, -16
+            0x00c000ef, // jal ra, f
+            0x05d00893, // addi a7, zero, 93
+            0x00000073, // ecall
+            0xfe010113, // f: addi sp, sp, -32
+            0x02010113, // addi sp, sp, 32
+            0x00008067, // ret
+        ]);
 
-        assert!(matches!(cpu.run(program), Err(Error::EndOfInstructions)));
-        assert_eq!(cpu.regs.read(27), 60);
-        assert_eq!(cpu.regs.read(30), 60);
-        assert_eq!(cpu.regs.read(29), 60);
-        assert_eq!(cpu.regs.read(28), 60);
-        assert_eq!(cpu.mem.read(Size::Byte, 64, true), 60);
+        assert!(matches!(cpu.run(program), Ok(StopReason::Exit(0))));
+        let report = cpu.stack_report().unwrap();
+        assert!(report.starts_with("max stack usage of hart 0: 48 bytes"));
+        assert!(report.ends_with("including callees:\n        32  0x10\n"));
+        assert_eq!(Cpu::new(false).stack_report(), None);
     }
 
     #[test]
-    fn negative_load_imm() {
-        let program = file_to_bin("negative_load.s");
+    fn heap_usage() {
         let mut cpu = Cpu::new(false);
+        cpu.set_quiet();
+        cpu.enable_heap_usage();
+        let program = words_to_bin(&[
+            0x00000513, // addi a0, zero, 0
+            0x0d600893, // addi a7, zero, 214
+            0x00000073, // ecall (brk(0))
+            0x10050513, // addi a0, a0, 0x100
+            0x00000073, // ecall (brk(start + 0x100))
+            0xf8050513, // addi a0, a0, -0x80
+            0x00000073, // ecall (brk(start + 0x80))
+            0x00000513, // addi a0, zero, 0
+            0x05d00893, // addi a7, zero, 93
+            0x00000073, // ecall
+        ]);
 
-        assert!(matches!(cpu.run(program), Err(Error::EndOfInstructions)));
-        assert_eq!(cpu.regs.read(27), 21);
-        assert_eq!(cpu.regs.read(28), 60);
-        assert_eq!(cpu.regs.read(30), 60);
-        assert_eq!(cpu.mem.read(Size::Byte, 20, true), 60);
+        assert!(matches!(cpu.run(program), Ok(StopReason::Exit(0))));
+        assert_eq!(cpu.heap.size(), 0x80);
+        assert_eq!(cpu.heap.peak(), 0x100);
+        assert!(cpu
+            .heap_report()
+            .unwrap()
+            .starts_with("heap: 128 bytes at exit"));
     }
 
     #[test]
-    fn negative_store_imm() {
-        let program = file_to_bin("negative_store.s");
+    fn reset_pc_overrides_elf_entry() {
+        let code = [0x00000013, 0x00000013];
+        let elf = Elf::parse(&crate::elf::build_test_elf(0x8000_0000, &code, 0x8000_1000)).unwrap();
         let mut cpu = Cpu::new(false);
+        cpu.mem = Machine::FreertosDemo.memory(RtcClock::Frozen(0));
+        cpu.load_elf(&elf).unwrap();
+        assert_eq!(cpu.pc.get(), 0x8000_0000);
 
-        assert!(matches!(cpu.run(program), Err(Error::EndOfInstructions)));
-        assert_eq!(cpu.regs.read(22), 261);
-        assert_eq!(cpu.regs.read(27), 256);
-        assert_eq!(cpu.regs.read(28), 60);
-        assert_eq!(cpu.regs.read(30), 60);
-        assert_eq!(cpu.mem.read(Size::Byte, 256, true), 60);
+        cpu.set_reset_pc(0x8000_0004);
+        cpu.load_elf(&elf).unwrap();
+        assert_eq!(cpu.pc.get(), 0x8000_0004);
     }
 
     #[test]
-    fn branch_eq() {
-        let program = file_to_bin("branch.s");
+    fn stubbed_function() {
+        let program = words_to_bin(&[
+            0x00500513, // addi a0, zero, 5
+            0x00c000ef, // jal ra, double
+            0x05d00893, // addi a7, zero, 93
+            0x00000073, // ecall
+            0x00100513, // double: addi a0, zero, 1
+            0x00008067, // ret
+        ]);
         let mut cpu = Cpu::new(false);
+        cpu.set_quiet();
+        cpu.symbols.insert("double".to_string(), 0x10);
+        assert!(!cpu.stub_symbol("rand", |_| 4));
+        assert!(cpu.stub_symbol("double", |cpu| cpu.regs.get(Reg::A0) * 2));
 
-        assert!(matches!(cpu.run(program), Err(Error::EndOfInstructions)));
-        assert_eq!(cpu.regs.read(20) as i32, -2);
-        assert_eq!(cpu.regs.read(21), 1);
+        assert!(matches!(cpu.run(program), Ok(StopReason::Exit(10))));
+        assert!(cpu.fork().is_none());
     }
 
     #[test]
-    fn branch_signed() {
-        let program = file_to_bin("signed_branch.s");
+    fn fork_diverges() {
+        let program = words_to_bin(&[
+            0x10002503, // lw a0, 0x100(zero)
+            0x00150513, // addi a0, a0, 1
+            0x10a02023, // sw a0, 0x100(zero)
+        ]);
+        let mut cpu = Cpu::new(false);
+        cpu.set_quiet();
+        cpu.load(program);
+        cpu.mem.write(Size::Word, 0x100, 5);
+        assert!(matches!(cpu.step(), Ok(None)));
+
+        let mut fork = cpu.fork().unwrap();
+        assert_eq!(fork.regs.read(10), 5);
+        fork.regs.set(Reg::A0, 10);
+        assert!(matches!(
+            fork.run_until_stop(),
+            Err(Error::EndOfInstructions)
+        ));
+        assert!(matches!(
+            cpu.run_until_stop(),
+            Err(Error::EndOfInstructions)
+        ));
+        assert_eq!(fork.mem.read(Size::Word, 0x100, true), 11);
+        assert_eq!(cpu.mem.read(Size::Word, 0x100, true), 6);
+    }
+
+    #[test]
+    fn big_endian_data() {
+        let program = words_to_bin(&[
+            0x123455b7, // lui a1, 0x12345
+            0x67858593, // addi a1, a1, 0x678
+            0x02000293, // addi t0, zero, 32
+            0x3102a073, // csrs mstatush, t0
+            0x10b02023, // sw a1, 0x100(zero)
+            0x10004603, // lbu a2, 0x100(zero)
+            0x10201683, // lh a3, 0x102(zero)
+            0x10002703, // lw a4, 0x100(zero)
+        ]);
         let mut cpu = Cpu::new(false);
 
         assert!(matches!(cpu.run(program), Err(Error::EndOfInstructions)));
-        assert_eq!(cpu.regs.read(20) as i32, -1);
-        assert_eq!(cpu.regs.read(21), 1);
+        // the most significant byte is stored first
+        assert_eq!(cpu.mem.read(Size::Word, 0x100, true), 0x7856_3412);
+        assert_eq!(cpu.regs.read(12), 0x12);
+        assert_eq!(cpu.regs.read(13), 0x5678);
+        assert_eq!(cpu.regs.read(14), 0x1234_5678);
     }
+
     #[test]
-    fn branch_unsigned() {
-        let program = file_to_bin("unsigned_branch.s");
+    fn scalar_crypto() {
+        let program = words_to_bin(&[
+            0x123455b7, // lui a1, 0x12345
+            0x6985d613, // rev8 a2, a1
+            0x62b006b3, // aes32esi a3, x0, a1, 1
+            0x40b5c733, // xnor a4, a1, a1
+        ]);
         let mut cpu = Cpu::new(false);
 
         assert!(matches!(cpu.run(program), Err(Error::EndOfInstructions)));
-        assert_eq!(cpu.regs.read(20), 100);
-        assert_eq!(cpu.regs.read(21), 100);
+        assert_eq!(cpu.regs.read(12), 0x0050_3412);
+        // sbox[0x50] in the second byte
+        assert_eq!(cpu.regs.read(13), 0x5300);
+        assert_eq!(cpu.regs.read(14), u32::MAX);
     }
 
     #[test]
-    fn fibonacci() {
-        let program = file_to_bin("fibs.s");
+    fn vector_add_loop() {
+        let mut program = vec![
+            0x00600513, // addi a0, x0, 6
+            0x10000593, // addi a1, x0, 0x100
+            0x20000613, // addi a2, x0, 0x200
+            0x00a00693, // addi a3, x0, 10
+            0x0d0572d7, // loop: vsetvli t0, a0, e32, m1, ta, ma
+            0x0205e087, // vle32.v v1, (a1)
+            0x0216c0d7, // vadd.vx v1, v1, a3
+            0x020660a7, // vse32.v v1, (a2)
+            0x40550533, // sub a0, a0, t0
+            0x00229313, // slli t1, t0, 2
+            0x006585b3, // add a1, a1, t1
+            0x00660633, // add a2, a2, t1
+            0xfe0510e3, // bne a0, x0, loop
+            0x42102757, // vmv.x.s a4, v1
+        ];
+        program.resize(0x40, 0);
+        program.extend(1..=6);
         let mut cpu = Cpu::new(false);
 
-        // fibonacci terminates using exit syscall which is why result is Ok.
-        assert!(cpu.run(program).is_ok());
-        //  fibs(10) == a0 == r10 == 55
-        assert_eq!(cpu.regs.read(10), 55);
+        assert!(matches!(
+            cpu.run(words_to_bin(&program)),
+            Err(Error::EndOfInstructions)
+        ));
+        // vlen is 128, so the loop runs with a vector length of 4 and then 2
+        assert_eq!(cpu.regs.read(5), 2);
+        assert_eq!(cpu.regs.read(14), 15);
+        for i in 0..6 {
+            assert_eq!(cpu.mem.read(Size::Word, 0x200 + 4 * i, true), 11 + i);
+        }
+    }
+
+    #[test]
+    fn vector_unconfigured() {
+        let program = words_to_bin(&[
+            0x0216c0d7, // vadd.vx v1, v1, a3
+        ]);
+        let mut cpu = Cpu::new(false);
+
+        assert!(matches!(
+            cpu.run(program),
+            Ok(StopReason::Trap(Exception::IllegalInstruction(0x0216c0d7)))
+        ));
+    }
+
+    #[test]
+    fn misaligned_jump_target() {
+        let mut program = SET_MTVEC.to_vec();
+        program.extend([
+            0x006000ef, // jal x1, 6
+        ]);
+        let mut cpu = Cpu::new(false);
+
+        assert!(matches!(
+            cpu.run(words_to_bin(&program)),
+            Err(Error::EndOfInstructions)
+        ));
+        assert_eq!(cpu.csrs.mcause, 0);
+        assert_eq!(cpu.csrs.mepc, 8);
+        assert_eq!(cpu.csrs.mtval, 14);
+        // the jump doesn't link when it traps
+        assert_eq!(cpu.regs.read(1), 0);
+    }
+
+    #[test]
+    fn misaligned_branch_target() {
+        let program = words_to_bin(&[
+            0x00000563, // beq x0, x0, 10
+        ]);
+        let mut cpu = Cpu::new(false);
+
+        assert!(matches!(
+            cpu.run(program),
+            Ok(StopReason::Trap(Exception::InstructionAddressMisaligned(
+                10
+            )))
+        ));
+    }
+
+    #[test]
+    fn jalr_link_and_lsb() {
+        let mut program = vec![
+            0x03100293, // addi x5, x0, 0x31
+            0x000282e7, // jalr x5, 0(x5)
+        ];
+        program.resize(0x30 / 4, 0);
+        program.push(0x00100313); // addi x6, x0, 1
+        let mut cpu = Cpu::new(false);
+
+        assert!(matches!(
+            cpu.run(words_to_bin(&program)),
+            Err(Error::EndOfInstructions)
+        ));
+        assert_eq!(cpu.regs.read(5), 8);
+        assert_eq!(cpu.regs.read(6), 1);
+    }
+
+    #[test]
+    fn jalr_out_of_memory() {
+        let mut program = SET_MTVEC.to_vec();
+        program.extend([
+            0x400002b7, // lui x5, 0x40000
+            0x00028067, // jalr x0, 0(x5)
+        ]);
+        let mut cpu = Cpu::new(false);
+
+        assert!(matches!(
+            cpu.run(words_to_bin(&program)),
+            Err(Error::EndOfInstructions)
+        ));
+        assert_eq!(cpu.csrs.mcause, 1);
+        assert_eq!(cpu.csrs.mepc, 0x4000_0000);
+        assert_eq!(cpu.csrs.mtval, 0x4000_0000);
     }
 }
@@ -0,0 +1,140 @@
+// Helpers for the scalar cryptography extensions (Zbkb, Zbkx, Zknd, Zkne).
+
+// multiplication in GF(2^8) with the aes polynomial x^8 + x^4 + x^3 + x + 1
+const fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        a = (a << 1) ^ if a & 0x80 != 0 { 0x1b } else { 0 };
+        b >>= 1;
+    }
+    product
+}
+
+// the multiplicative inverse is a^254, zero maps to zero
+const fn gf_inv(a: u8) -> u8 {
+    let mut inverse = 1;
+    let mut i = 0;
+    while i < 254 {
+        inverse = gf_mul(inverse, a);
+        i += 1;
+    }
+    inverse
+}
+
+const fn fwd_sbox() -> [u8; 256] {
+    let mut sbox = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let b = gf_inv(i as u8);
+        sbox[i] =
+            b ^ b.rotate_left(1) ^ b.rotate_left(2) ^ b.rotate_left(3) ^ b.rotate_left(4) ^ 0x63;
+        i += 1;
+    }
+    sbox
+}
+
+const fn inv_sbox() -> [u8; 256] {
+    let mut inv = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        inv[FWD_SBOX[i] as usize] = i as u8;
+        i += 1;
+    }
+    inv
+}
+
+const FWD_SBOX: [u8; 256] = fwd_sbox();
+const INV_SBOX: [u8; 256] = inv_sbox();
+
+// Computes one column of an aes round from byte `bs` of rs2 and xors it into rs1.
+// The middle variants (esmi/dsmi) also apply the (inverse) MixColumns step.
+pub fn aes32(rs1: u32, rs2: u32, bs: u32, decrypt: bool, mix: bool) -> u32 {
+    let shamt = bs * 8;
+    let si = (rs2 >> shamt) as u8;
+    let so = if decrypt {
+        INV_SBOX[si as usize]
+    } else {
+        FWD_SBOX[si as usize]
+    };
+    let mixed = match (mix, decrypt) {
+        (false, _) => so as u32,
+        (true, false) => u32::from_le_bytes([gf_mul(so, 2), so, so, gf_mul(so, 3)]),
+        (true, true) => u32::from_le_bytes([
+            gf_mul(so, 0xe),
+            gf_mul(so, 0x9),
+            gf_mul(so, 0xd),
+            gf_mul(so, 0xb),
+        ]),
+    };
+    rs1 ^ mixed.rotate_left(shamt)
+}
+
+// reverses the bits within each byte
+pub fn brev8(rs1: u32) -> u32 {
+    u32::from_le_bytes(rs1.to_le_bytes().map(u8::reverse_bits))
+}
+
+// interleaves the lower and upper half, bit i of the lower half ends up at bit 2i
+pub fn zip(rs1: u32) -> u32 {
+    (0..16).fold(0, |rd, i| {
+        rd | ((rs1 >> i) & 1) << (2 * i) | ((rs1 >> (i + 16)) & 1) << (2 * i + 1)
+    })
+}
+
+// inverse of zip, the even bits end up in the lower half
+pub fn unzip(rs1: u32) -> u32 {
+    (0..16).fold(0, |rd, i| {
+        rd | ((rs1 >> (2 * i)) & 1) << i | ((rs1 >> (2 * i + 1)) & 1) << (i + 16)
+    })
+}
+
+// Looks up each element of rs2 in the table of `width`-bit elements in rs1,
+// out of range indices yield zero.
+pub fn xperm(rs1: u32, rs2: u32, width: u32) -> u32 {
+    let mask = (1 << width) - 1;
+    (0..32).step_by(width as usize).fold(0, |rd, pos| {
+        let index = (rs2 >> pos) & mask;
+        let element = if index * width < 32 {
+            (rs1 >> (index * width)) & mask
+        } else {
+            0
+        };
+        rd | element << pos
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sbox() {
+        assert_eq!(FWD_SBOX[0x00], 0x63);
+        assert_eq!(FWD_SBOX[0x53], 0xed);
+        assert_eq!(INV_SBOX[0x63], 0x00);
+    }
+
+    #[test]
+    fn aes32_round_trip() {
+        // substitutes every byte of a column and back, without mixing
+        let column = 0x1928_3746;
+        let encrypted = (0..4).fold(0, |rd, bs| aes32(rd, column, bs, false, false));
+        assert_eq!(encrypted.to_le_bytes()[0], FWD_SBOX[0x46]);
+        let decrypted = (0..4).fold(0, |rd, bs| aes32(rd, encrypted, bs, true, false));
+        assert_eq!(decrypted, column);
+        // a substituted byte of 1 gives the coefficients of the inverse mixcolumns matrix
+        assert_eq!(aes32(0, 0x7c, 0, true, true), 0x0b0d_090e);
+    }
+
+    #[test]
+    fn bit_permutations() {
+        assert_eq!(brev8(0x0102_80c0), 0x8040_0103);
+        assert_eq!(zip(0x0000_ffff), 0x5555_5555);
+        assert_eq!(unzip(zip(0x1234_5678)), 0x1234_5678);
+        assert_eq!(xperm(0x4433_2211, 0x0005_0100, 8), 0x1100_2211);
+        assert_eq!(xperm(0x7654_3210, 0x0000_0f17, 4), 0x0000_0017);
+    }
+}
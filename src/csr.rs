@@ -0,0 +1,619 @@
+use crate::trap::INTERRUPT;
+
+// supervisor-mode control and status registers, see riscv-privileged spec chapter 10
+pub const SSTATUS: u16 = 0x100;
+pub const SIE: u16 = 0x104;
+pub const STVEC: u16 = 0x105;
+pub const SCOUNTEREN: u16 = 0x106;
+pub const SSCRATCH: u16 = 0x140;
+pub const SEPC: u16 = 0x141;
+pub const SCAUSE: u16 = 0x142;
+pub const STVAL: u16 = 0x143;
+pub const SIP: u16 = 0x144;
+// supervisor timer compare of the Sstc extension
+pub const STIMECMP: u16 = 0x14d;
+pub const STIMECMPH: u16 = 0x15d;
+pub const SATP: u16 = 0x180;
+// machine-mode control and status registers, see riscv-privileged spec chapter 2 and 3
+pub const MSTATUS: u16 = 0x300;
+pub const MISA: u16 = 0x301;
+pub const MEDELEG: u16 = 0x302;
+pub const MIDELEG: u16 = 0x303;
+pub const MIE: u16 = 0x304;
+pub const MTVEC: u16 = 0x305;
+pub const MCOUNTEREN: u16 = 0x306;
+pub const MENVCFG: u16 = 0x30a;
+pub const MENVCFGH: u16 = 0x31a;
+// upper half of mstatus on rv32
+pub const MSTATUSH: u16 = 0x310;
+pub const MCOUNTINHIBIT: u16 = 0x320;
+pub const MHPMEVENT3: u16 = 0x323;
+pub const MHPMEVENT31: u16 = 0x33f;
+pub const MSCRATCH: u16 = 0x340;
+pub const MEPC: u16 = 0x341;
+pub const MCAUSE: u16 = 0x342;
+pub const MTVAL: u16 = 0x343;
+pub const MIP: u16 = 0x344;
+pub const PMPCFG0: u16 = 0x3a0;
+pub const PMPCFG3: u16 = 0x3a3;
+pub const PMPADDR0: u16 = 0x3b0;
+pub const PMPADDR15: u16 = 0x3bf;
+pub const MVENDORID: u16 = 0xf11;
+pub const MARCHID: u16 = 0xf12;
+pub const MIMPID: u16 = 0xf13;
+pub const MHARTID: u16 = 0xf14;
+pub const MCYCLE: u16 = 0xb00;
+pub const MINSTRET: u16 = 0xb02;
+pub const MCYCLEH: u16 = 0xb80;
+pub const MINSTRETH: u16 = 0xb82;
+pub const MHPMCOUNTER3: u16 = 0xb03;
+pub const MHPMCOUNTER31: u16 = 0xb1f;
+pub const MHPMCOUNTER3H: u16 = 0xb83;
+pub const MHPMCOUNTER31H: u16 = 0xb9f;
+// read-only user-level shadows of the machine counters (Zicntr)
+pub const CYCLE: u16 = 0xc00;
+pub const TIME: u16 = 0xc01;
+pub const INSTRET: u16 = 0xc02;
+pub const CYCLEH: u16 = 0xc80;
+pub const TIMEH: u16 = 0xc81;
+pub const INSTRETH: u16 = 0xc82;
+pub const HPMCOUNTER3: u16 = 0xc03;
+pub const HPMCOUNTER31: u16 = 0xc1f;
+pub const HPMCOUNTER3H: u16 = 0xc83;
+pub const HPMCOUNTER31H: u16 = 0xc9f;
+
+// mxl = 32-bit, the i, m, a, s and u extension
+const MISA_VALUE: u32 = (1 << 30) | (1 << 20) | (1 << 18) | (1 << 12) | (1 << 8) | (1 << 0);
+
+// Privilege modes, numbered like in the mpp and spp fields. Harts start in machine mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Mode {
+    User = 0,
+    Supervisor = 1,
+    Machine = 3,
+}
+
+impl Mode {
+    // the reserved encoding 2 is None
+    pub fn from_bits(bits: u32) -> Option<Self> {
+        match bits {
+            0 => Some(Mode::User),
+            1 => Some(Mode::Supervisor),
+            3 => Some(Mode::Machine),
+            _ => None,
+        }
+    }
+}
+
+// global interrupt-enable bits of supervisor and machine mode and the ones stacked on trap entry
+pub const MSTATUS_SIE: u32 = 1 << 1;
+pub const MSTATUS_MIE: u32 = 1 << 3;
+pub const MSTATUS_SPIE: u32 = 1 << 5;
+pub const MSTATUS_MPIE: u32 = 1 << 7;
+// previous privilege mode of supervisor traps, only user or supervisor mode
+pub const MSTATUS_SPP: u32 = 1 << 8;
+// previous privilege mode of machine traps
+pub const MSTATUS_MPP: u32 = 0b11 << 11;
+// loads and stores of machine mode are translated like the ones of the mode in mpp
+pub const MSTATUS_MPRV: u32 = 1 << 17;
+// supervisor mode may access user pages, and may load from executable-only pages
+pub const MSTATUS_SUM: u32 = 1 << 18;
+pub const MSTATUS_MXR: u32 = 1 << 19;
+// fields of mstatus that are visible in sstatus
+const SSTATUS_MASK: u32 = MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_SPP | MSTATUS_SUM | MSTATUS_MXR;
+// the remaining fields of unimplemented features read as zero
+const MSTATUS_WRITABLE: u32 = SSTATUS_MASK | MSTATUS_MIE | MSTATUS_MPIE | MSTATUS_MPRV;
+// loads and stores of machine mode are big-endian, instruction fetches stay little-endian
+pub const MSTATUSH_MBE: u32 = 1 << 5;
+// enables stimecmp (bit 63 of menvcfg)
+pub const MENVCFGH_STCE: u32 = 1 << 31;
+
+// exceptions that can be delegated to supervisor mode, ecalls from machine mode can't
+const MEDELEG_MASK: u32 = 0xb3ff;
+
+// the address translation mode in satp, the asid isn't implemented and reads as zero
+pub const SATP_SV32: u32 = 1 << 31;
+const SATP_MASK: u32 = SATP_SV32 | 0x3f_ffff;
+
+// counters stopped by mcountinhibit, there is no time bit since mtime lives in the clint
+pub const MCOUNTINHIBIT_CY: u32 = 1 << 0;
+pub const MCOUNTINHIBIT_IR: u32 = 1 << 2;
+// bits 3-31 inhibit the hpm counters
+const MCOUNTINHIBIT_HPM: u32 = !0b111;
+
+// mhpmcounter3 to mhpmcounter31
+const NUM_HPM_COUNTERS: usize = 29;
+
+// Events that can be selected in mhpmevent, counted for retired instructions only.
+// Selecting any other event stops the counter.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HpmEvent {
+    // conditional branches, whether they're taken or not
+    Branch = 1,
+    Load = 2,
+    Store = 3,
+}
+
+impl HpmEvent {
+    fn from_selector(selector: u32) -> Option<Self> {
+        match selector {
+            1 => Some(HpmEvent::Branch),
+            2 => Some(HpmEvent::Load),
+            3 => Some(HpmEvent::Store),
+            _ => None,
+        }
+    }
+}
+
+// interrupt-pending bits in mip/mie
+pub const MIP_SSIP: u32 = 1 << 1;
+pub const MIP_MSIP: u32 = 1 << 3;
+pub const MIP_STIP: u32 = 1 << 5;
+pub const MIP_MTIP: u32 = 1 << 7;
+pub const MIP_SEIP: u32 = 1 << 9;
+pub const MIP_MEIP: u32 = 1 << 11;
+// pending bits driven by the interrupt controllers, these can't be written by software
+pub const MIP_HARDWARE: u32 = MIP_MSIP | MIP_MTIP | MIP_SEIP | MIP_MEIP;
+// the interrupts of supervisor mode, only these can be delegated
+pub const MIP_SUPERVISOR: u32 = MIP_SSIP | MIP_STIP | MIP_SEIP;
+
+#[derive(Clone)]
+pub struct Csrs {
+    // id of the hart the csrs belong to
+    pub hartid: u32,
+    // privilege mode the hart executes in
+    pub mode: Mode,
+    pub mstatus: u32,
+    pub mstatush: u32,
+    pub medeleg: u32,
+    pub mideleg: u32,
+    pub mie: u32,
+    pub mtvec: u32,
+    pub mcounteren: u32,
+    pub menvcfgh: u32,
+    pub mscratch: u32,
+    pub mepc: u32,
+    pub mcause: u32,
+    pub mtval: u32,
+    pub mip: u32,
+    pub mcycle: u64,
+    pub minstret: u64,
+    pub mcountinhibit: u32,
+    mhpmevent: [Option<HpmEvent>; NUM_HPM_COUNTERS],
+    mhpmcounter: [u64; NUM_HPM_COUNTERS],
+    // the pmp registers only hold their values, accesses aren't checked against them
+    pmpcfg: [u32; 4],
+    pmpaddr: [u32; 16],
+    pub stvec: u32,
+    pub scounteren: u32,
+    pub sscratch: u32,
+    pub sepc: u32,
+    pub scause: u32,
+    pub stval: u32,
+    pub stimecmp: u64,
+    pub satp: u32,
+}
+
+impl Csrs {
+    pub fn new() -> Self {
+        Csrs {
+            hartid: 0,
+            mode: Mode::Machine,
+            mstatus: MSTATUS_MPP,
+            mstatush: 0,
+            medeleg: 0,
+            mideleg: 0,
+            mie: 0,
+            mtvec: 0,
+            mcounteren: 0,
+            menvcfgh: 0,
+            mscratch: 0,
+            mepc: 0,
+            mcause: 0,
+            mtval: 0,
+            mip: 0,
+            mcycle: 0,
+            minstret: 0,
+            mcountinhibit: 0,
+            mhpmevent: [None; NUM_HPM_COUNTERS],
+            mhpmcounter: [0; NUM_HPM_COUNTERS],
+            pmpcfg: [0; 4],
+            pmpaddr: [0; 16],
+            stvec: 0,
+            scounteren: 0,
+            sscratch: 0,
+            sepc: 0,
+            scause: 0,
+            stval: 0,
+            stimecmp: u64::MAX,
+            satp: 0,
+        }
+    }
+
+    // advances mcycle unless it's inhibited
+    pub fn count_cycles(&mut self, cycles: u64) {
+        if self.mcountinhibit & MCOUNTINHIBIT_CY == 0 {
+            self.mcycle = self.mcycle.wrapping_add(cycles);
+        }
+    }
+
+    // called once an instruction completed without raising an exception
+    pub fn retire(&mut self) {
+        if self.mcountinhibit & MCOUNTINHIBIT_IR == 0 {
+            self.minstret = self.minstret.wrapping_add(1);
+        }
+    }
+
+    // increments the hpm counters that selected the event
+    pub fn count_event(&mut self, event: HpmEvent) {
+        for (i, counter) in self.mhpmcounter.iter_mut().enumerate() {
+            let inhibited = self.mcountinhibit & (1 << (i + 3)) != 0;
+            if self.mhpmevent[i] == Some(event) && !inhibited {
+                *counter = counter.wrapping_add(1);
+            }
+        }
+    }
+
+    // the writing instruction still retires afterwards, so the next one sees the written value
+    fn write_minstret(&mut self, value: u64) {
+        self.minstret = if self.mcountinhibit & MCOUNTINHIBIT_IR == 0 {
+            value.wrapping_sub(1)
+        } else {
+            value
+        };
+    }
+
+    // On trap entry the interrupt-enable bit is saved in mpie and interrupts are disabled
+    // until the handler returns, mpp records the privilege mode the trap was taken from.
+    // The stack is only one level deep: handlers that re-enable interrupts to allow nested
+    // traps have to save mepc and mstatus first and restore them before returning.
+    pub fn push_interrupt_enable(&mut self) {
+        let mie = self.mstatus & MSTATUS_MIE != 0;
+        self.mstatus &= !(MSTATUS_MIE | MSTATUS_MPIE | MSTATUS_MPP);
+        if mie {
+            self.mstatus |= MSTATUS_MPIE;
+        }
+        self.mstatus |= (self.mode as u32) << 11;
+        self.mode = Mode::Machine;
+    }
+
+    // mret restores the interrupt-enable bit saved on trap entry, sets mpie and returns to the
+    // mode in mpp, which is then set to the least-privileged mode.
+    pub fn pop_interrupt_enable(&mut self) {
+        let mpie = self.mstatus & MSTATUS_MPIE != 0;
+        self.mode = Mode::from_bits((self.mstatus & MSTATUS_MPP) >> 11).unwrap();
+        self.mstatus &= !(MSTATUS_MIE | MSTATUS_MPP);
+        if mpie {
+            self.mstatus |= MSTATUS_MIE;
+        }
+        self.mstatus |= MSTATUS_MPIE;
+        if self.mode != Mode::Machine {
+            self.mstatus &= !MSTATUS_MPRV;
+        }
+    }
+
+    // the same for traps taken in supervisor mode, with spp recording whether the trap came from
+    // user or supervisor mode
+    pub fn push_supervisor_interrupt_enable(&mut self) {
+        let sie = self.mstatus & MSTATUS_SIE != 0;
+        self.mstatus &= !(MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_SPP);
+        if sie {
+            self.mstatus |= MSTATUS_SPIE;
+        }
+        if self.mode == Mode::Supervisor {
+            self.mstatus |= MSTATUS_SPP;
+        }
+        self.mode = Mode::Supervisor;
+    }
+
+    // sret, which always returns to a less privileged mode than machine mode
+    pub fn pop_supervisor_interrupt_enable(&mut self) {
+        let spie = self.mstatus & MSTATUS_SPIE != 0;
+        self.mode = if self.mstatus & MSTATUS_SPP != 0 {
+            Mode::Supervisor
+        } else {
+            Mode::User
+        };
+        self.mstatus &= !(MSTATUS_SIE | MSTATUS_SPP | MSTATUS_MPRV);
+        if spie {
+            self.mstatus |= MSTATUS_SIE;
+        }
+        self.mstatus |= MSTATUS_SPIE;
+    }
+
+    // Csrs can only be accessed from the privilege mode encoded in bits 8-9 of their number or a
+    // more privileged one. Below machine mode the counters additionally have to be made available
+    // in mcounteren, and for user mode also in scounteren.
+    pub fn accessible(&self, csr: u16) -> bool {
+        if (self.mode as u16) < (csr >> 8) & 0b11 {
+            return false;
+        }
+        let counter = 1 << (csr & 0x1f);
+        let is_counter =
+            (CYCLE..=HPMCOUNTER31).contains(&csr) || (CYCLEH..=HPMCOUNTER31H).contains(&csr);
+        match self.mode {
+            _ if !is_counter => true,
+            Mode::Machine => true,
+            Mode::Supervisor => self.mcounteren & counter != 0,
+            Mode::User => self.mcounteren & self.scounteren & counter != 0,
+        }
+    }
+
+    // the trap of an exception with the cause or a delegated interrupt is taken in supervisor
+    // mode, as long as the hart isn't executing in machine mode
+    pub fn delegated(&self, cause: u32) -> bool {
+        let delegation = if cause & INTERRUPT != 0 {
+            self.mideleg
+        } else {
+            self.medeleg
+        };
+        self.mode != Mode::Machine && delegation & (1 << (cause & 0x1f)) != 0
+    }
+
+    // returns None if the csr doesn't exist
+    pub fn read(&self, csr: u16) -> Option<u32> {
+        let value = match csr {
+            SSTATUS => self.mstatus & SSTATUS_MASK,
+            SIE => self.mie & self.mideleg,
+            STVEC => self.stvec,
+            SCOUNTEREN => self.scounteren,
+            SSCRATCH => self.sscratch,
+            SEPC => self.sepc,
+            SCAUSE => self.scause,
+            STVAL => self.stval,
+            SIP => self.mip & self.mideleg,
+            STIMECMP => self.stimecmp as u32,
+            STIMECMPH => (self.stimecmp >> 32) as u32,
+            SATP => self.satp,
+            MSTATUS => self.mstatus,
+            MSTATUSH => self.mstatush,
+            MISA => MISA_VALUE,
+            MEDELEG => self.medeleg,
+            MIDELEG => self.mideleg,
+            MIE => self.mie,
+            MTVEC => self.mtvec,
+            MCOUNTEREN => self.mcounteren,
+            MENVCFG => 0,
+            MENVCFGH => self.menvcfgh,
+            MSCRATCH => self.mscratch,
+            MEPC => self.mepc,
+            MCAUSE => self.mcause,
+            MTVAL => self.mtval,
+            MIP => self.mip,
+            PMPCFG0..=PMPCFG3 => self.pmpcfg[(csr - PMPCFG0) as usize],
+            PMPADDR0..=PMPADDR15 => self.pmpaddr[(csr - PMPADDR0) as usize],
+            MVENDORID | MARCHID | MIMPID => 0,
+            MHARTID => self.hartid,
+            MCOUNTINHIBIT => self.mcountinhibit,
+            MCYCLE | CYCLE => self.mcycle as u32,
+            MCYCLEH | CYCLEH => (self.mcycle >> 32) as u32,
+            MINSTRET | INSTRET => self.minstret as u32,
+            MINSTRETH | INSTRETH => (self.minstret >> 32) as u32,
+            MHPMEVENT3..=MHPMEVENT31 => {
+                self.mhpmevent[(csr - MHPMEVENT3) as usize].map_or(0, |event| event as u32)
+            }
+            MHPMCOUNTER3..=MHPMCOUNTER31 => self.mhpmcounter[(csr - MHPMCOUNTER3) as usize] as u32,
+            HPMCOUNTER3..=HPMCOUNTER31 => self.mhpmcounter[(csr - HPMCOUNTER3) as usize] as u32,
+            MHPMCOUNTER3H..=MHPMCOUNTER31H => {
+                (self.mhpmcounter[(csr - MHPMCOUNTER3H) as usize] >> 32) as u32
+            }
+            HPMCOUNTER3H..=HPMCOUNTER31H => {
+                (self.mhpmcounter[(csr - HPMCOUNTER3H) as usize] >> 32) as u32
+            }
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    // returns None if the csr doesn't exist or is read-only
+    pub fn write(&mut self, csr: u16, value: u32) -> Option<()> {
+        // the top two bits of the address encode read-only csrs
+        if csr >> 10 == 0b11 {
+            return None;
+        }
+        match csr {
+            SSTATUS => self.mstatus = self.mstatus & !SSTATUS_MASK | value & SSTATUS_MASK,
+            // only the delegated interrupts are visible to supervisor mode
+            SIE => self.mie = self.mie & !self.mideleg | value & self.mideleg,
+            STVEC if value & 0b11 < 2 => self.stvec = value,
+            STVEC => (),
+            SCOUNTEREN => self.scounteren = value,
+            SSCRATCH => self.sscratch = value,
+            SEPC => self.sepc = value & !0b11,
+            SCAUSE => self.scause = value,
+            STVAL => self.stval = value,
+            // supervisor mode can only raise and clear its software interrupt
+            SIP => {
+                let writable = MIP_SSIP & self.mideleg;
+                self.mip = self.mip & !writable | value & writable;
+            }
+            STIMECMP => self.stimecmp = (self.stimecmp & !0xffff_ffff) | value as u64,
+            STIMECMPH => self.stimecmp = (self.stimecmp & 0xffff_ffff) | (value as u64) << 32,
+            SATP => self.satp = value & SATP_MASK,
+            // fields of unimplemented features read as zero, the reserved mode 2 keeps mpp as is
+            MSTATUS => {
+                let mpp = match Mode::from_bits((value & MSTATUS_MPP) >> 11) {
+                    Some(_) => value & MSTATUS_MPP,
+                    None => self.mstatus & MSTATUS_MPP,
+                };
+                self.mstatus = value & MSTATUS_WRITABLE | mpp;
+            }
+            MSTATUSH => self.mstatush = value & MSTATUSH_MBE,
+            // misa is WARL and extensions can't be disabled, so writes are ignored
+            MISA => (),
+            MEDELEG => self.medeleg = value & MEDELEG_MASK,
+            MIDELEG => self.mideleg = value & MIP_SUPERVISOR,
+            MIE => self.mie = value,
+            // only direct and vectored mode are valid
+            MTVEC if value & 0b11 < 2 => self.mtvec = value,
+            MTVEC => (),
+            MCOUNTEREN => self.mcounteren = value,
+            // of the environment configuration only the enable of stimecmp is implemented
+            MENVCFG => (),
+            MENVCFGH => self.menvcfgh = value & MENVCFGH_STCE,
+            MSCRATCH => self.mscratch = value,
+            // instructions are always 4-byte aligned
+            MEPC => self.mepc = value & !0b11,
+            MCAUSE => self.mcause = value,
+            MTVAL => self.mtval = value,
+            MIP => self.mip = (self.mip & MIP_HARDWARE) | (value & !MIP_HARDWARE),
+            PMPCFG0..=PMPCFG3 => self.pmpcfg[(csr - PMPCFG0) as usize] = value,
+            PMPADDR0..=PMPADDR15 => self.pmpaddr[(csr - PMPADDR0) as usize] = value,
+            MCOUNTINHIBIT => {
+                self.mcountinhibit =
+                    value & (MCOUNTINHIBIT_CY | MCOUNTINHIBIT_IR | MCOUNTINHIBIT_HPM)
+            }
+            // unknown events are WARL and read back as zero
+            MHPMEVENT3..=MHPMEVENT31 => {
+                self.mhpmevent[(csr - MHPMEVENT3) as usize] = HpmEvent::from_selector(value)
+            }
+            MHPMCOUNTER3..=MHPMCOUNTER31 => {
+                let counter = &mut self.mhpmcounter[(csr - MHPMCOUNTER3) as usize];
+                *counter = (*counter & !0xffff_ffff) | value as u64;
+            }
+            MHPMCOUNTER3H..=MHPMCOUNTER31H => {
+                let counter = &mut self.mhpmcounter[(csr - MHPMCOUNTER3H) as usize];
+                *counter = (*counter & 0xffff_ffff) | (value as u64) << 32;
+            }
+            MCYCLE => self.mcycle = (self.mcycle & !0xffff_ffff) | value as u64,
+            MCYCLEH => self.mcycle = (self.mcycle & 0xffff_ffff) | (value as u64) << 32,
+            MINSTRET => self.write_minstret((self.minstret & !0xffff_ffff) | value as u64),
+            MINSTRETH => self.write_minstret((self.minstret & 0xffff_ffff) | (value as u64) << 32),
+            _ => return None,
+        }
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_only() {
+        let mut csrs = Csrs::new();
+        assert_eq!(csrs.write(MHARTID, 1), None);
+        assert_eq!(csrs.read(MHARTID), Some(0));
+        assert_eq!(csrs.read(0x7ff), None);
+        assert_eq!(csrs.write(0x7ff, 1), None);
+    }
+
+    #[test]
+    fn interrupt_enable_stack() {
+        let mut csrs = Csrs::new();
+        csrs.write(MSTATUS, MSTATUS_MIE | 1 << 2 | 2 << 11).unwrap();
+        assert_eq!(csrs.read(MSTATUS), Some(MSTATUS_MIE | MSTATUS_MPP));
+        csrs.push_interrupt_enable();
+        assert_eq!(csrs.mstatus, MSTATUS_MPIE | MSTATUS_MPP);
+        // mret returns to machine mode and leaves user mode in mpp
+        csrs.pop_interrupt_enable();
+        assert_eq!(csrs.mstatus, MSTATUS_MIE | MSTATUS_MPIE);
+        assert_eq!(csrs.mode, Mode::Machine);
+        // mret with interrupts disabled before the trap keeps them disabled
+        csrs.write(MSTATUS, 0).unwrap();
+        csrs.push_interrupt_enable();
+        csrs.pop_interrupt_enable();
+        assert_eq!(csrs.mstatus, MSTATUS_MPIE);
+    }
+
+    #[test]
+    fn privilege_modes() {
+        let mut csrs = Csrs::new();
+        // mret to supervisor mode with mprv set, which only applies to machine mode
+        csrs.write(MSTATUS, 1 << 11 | MSTATUS_MPRV).unwrap();
+        csrs.pop_interrupt_enable();
+        assert_eq!(csrs.mode, Mode::Supervisor);
+        assert_eq!(csrs.mstatus & MSTATUS_MPRV, 0);
+        assert!(!csrs.accessible(MSTATUS));
+        assert!(csrs.accessible(SSTATUS));
+        assert!(!csrs.accessible(TIME));
+
+        // a trap from supervisor mode records it in spp, sret returns there
+        csrs.mstatus |= MSTATUS_SIE;
+        csrs.push_supervisor_interrupt_enable();
+        assert_eq!(csrs.read(SSTATUS), Some(MSTATUS_SPIE | MSTATUS_SPP));
+        csrs.pop_supervisor_interrupt_enable();
+        assert_eq!(csrs.mode, Mode::Supervisor);
+        assert_eq!(csrs.read(SSTATUS), Some(MSTATUS_SIE | MSTATUS_SPIE));
+        csrs.pop_supervisor_interrupt_enable();
+        assert_eq!(csrs.mode, Mode::User);
+
+        csrs.mcounteren = 1 << 1;
+        assert!(!csrs.accessible(TIME));
+        csrs.scounteren = 1 << 1;
+        assert!(csrs.accessible(TIME));
+        assert!(!csrs.accessible(CYCLE));
+    }
+
+    #[test]
+    fn delegation() {
+        let mut csrs = Csrs::new();
+        csrs.write(MEDELEG, u32::MAX).unwrap();
+        csrs.write(MIDELEG, u32::MAX).unwrap();
+        assert_eq!(csrs.read(MEDELEG), Some(0xb3ff));
+        assert_eq!(csrs.read(MIDELEG), Some(MIP_SUPERVISOR));
+        // traps from machine mode are never delegated
+        assert!(!csrs.delegated(2));
+        csrs.mode = Mode::User;
+        assert!(csrs.delegated(2));
+        assert!(csrs.delegated(INTERRUPT | 5));
+        assert!(!csrs.delegated(INTERRUPT | 7));
+
+        // sie and sip only show the delegated interrupts, only ssip is writable
+        csrs.write(MIDELEG, MIP_SSIP | MIP_STIP).unwrap();
+        csrs.write(SIE, u32::MAX).unwrap();
+        assert_eq!(csrs.mie, MIP_SSIP | MIP_STIP);
+        csrs.mip = MIP_MTIP | MIP_STIP;
+        csrs.write(SIP, MIP_SSIP).unwrap();
+        assert_eq!(csrs.read(SIP), Some(MIP_SSIP | MIP_STIP));
+        assert_eq!(csrs.mip, MIP_MTIP | MIP_STIP | MIP_SSIP);
+    }
+
+    #[test]
+    fn counters() {
+        let mut csrs = Csrs::new();
+        csrs.write(MINSTRET, 10).unwrap();
+        csrs.retire();
+        assert_eq!(csrs.read(INSTRET), Some(10));
+        csrs.write(MCYCLEH, 1).unwrap();
+        csrs.count_cycles(3);
+        assert_eq!(csrs.read(CYCLE), Some(3));
+        assert_eq!(csrs.read(MCYCLEH), Some(1));
+
+        csrs.write(MCOUNTINHIBIT, u32::MAX).unwrap();
+        assert_eq!(csrs.read(MCOUNTINHIBIT), Some(!0b010));
+        csrs.count_cycles(1);
+        csrs.retire();
+        assert_eq!(csrs.read(MCYCLE), Some(3));
+        assert_eq!(csrs.read(MINSTRET), Some(10));
+        assert_eq!(csrs.write(CYCLE, 0), None);
+    }
+
+    #[test]
+    fn hpm_events() {
+        let mut csrs = Csrs::new();
+        csrs.write(MHPMEVENT3, HpmEvent::Load as u32).unwrap();
+        csrs.write(MHPMEVENT3 + 1, 0x1234).unwrap();
+        assert_eq!(csrs.read(MHPMEVENT3 + 1), Some(0));
+        csrs.write(MHPMCOUNTER3, u32::MAX).unwrap();
+
+        csrs.count_event(HpmEvent::Load);
+        csrs.count_event(HpmEvent::Store);
+        assert_eq!(csrs.read(HPMCOUNTER3), Some(0));
+        assert_eq!(csrs.read(HPMCOUNTER3H), Some(1));
+
+        csrs.write(MCOUNTINHIBIT, 1 << 3).unwrap();
+        csrs.count_event(HpmEvent::Load);
+        assert_eq!(csrs.read(MHPMCOUNTER3), Some(0));
+    }
+
+    #[test]
+    fn warl_fields() {
+        let mut csrs = Csrs::new();
+        csrs.write(MEPC, 0x103).unwrap();
+        assert_eq!(csrs.read(MEPC), Some(0x100));
+        csrs.write(MTVEC, 0x201).unwrap();
+        csrs.write(MTVEC, 0x302).unwrap();
+        assert_eq!(csrs.read(MTVEC), Some(0x201));
+    }
+}
@@ -0,0 +1,232 @@
+use crate::error::*;
+use crate::get_bits;
+use crate::inst::*;
+use crate::inst_format::*;
+use crate::vector::VInst;
+
+// Parses a raw instruction into its format and operation, independent of any machine state.
+// for decode information see: [riscv-ref](crate::docs/riscv-ref)
+pub fn decode(raw_inst: u32) -> Result<Inst, Error> {
+    // get the lowest 7 bits for the opcode
+    let opcode = get_bits!(raw_inst, 0, 6);
+    let inst = match opcode {
+        0b0110011 => {
+            let r_format = RFormat::new(raw_inst);
+            let inst = match (r_format.funct3, r_format.funct7) {
+                (0x0, 0x00) => RInst::ADD,
+                (0x0, 0x20) => RInst::SUB,
+                (0x4, 0x00) => RInst::XOR,
+                (0x6, 0x00) => RInst::OR,
+                (0x7, 0x00) => RInst::AND,
+                (0x1, 0x00) => RInst::SLL,
+                (0x5, 0x00) => RInst::SRL,
+                (0x5, 0x20) => RInst::SRA,
+                (0x2, 0x00) => RInst::SLT,
+                (0x3, 0x00) => RInst::SLTU,
+                (0x0, 0x01) => RInst::MUL,
+                (0x1, 0x01) => RInst::MULH,
+                (0x2, 0x01) => RInst::MULHSU,
+                (0x3, 0x01) => RInst::MULHU,
+                (0x4, 0x01) => RInst::DIV,
+                (0x5, 0x01) => RInst::DIVU,
+                (0x6, 0x01) => RInst::REM,
+                (0x7, 0x01) => RInst::REMU,
+                (0x5, 0x07) => RInst::CZEROEQZ,
+                (0x7, 0x07) => RInst::CZERONEZ,
+                (0x7, 0x20) => RInst::ANDN,
+                (0x6, 0x20) => RInst::ORN,
+                (0x4, 0x20) => RInst::XNOR,
+                (0x1, 0x30) => RInst::ROL,
+                (0x5, 0x30) => RInst::ROR,
+                (0x4, 0x04) => RInst::PACK,
+                (0x7, 0x04) => RInst::PACKH,
+                (0x2, 0x14) => RInst::XPERM4,
+                (0x4, 0x14) => RInst::XPERM8,
+                // aes instructions keep the byte select in the upper two bits of funct7
+                (0x0, funct7) if funct7 & 0x1f != 0 => {
+                    let inst = match funct7 & 0x1f {
+                        0b10001 => AesInst::ESI,
+                        0b10011 => AesInst::ESMI,
+                        0b10101 => AesInst::DSI,
+                        0b10111 => AesInst::DSMI,
+                        _ => return Err(Error::InvalidInstFormat(FormatError::R(r_format))),
+                    };
+                    return Ok(Inst::Aes(inst, r_format));
+                }
+                _ => return Err(Error::InvalidInstFormat(FormatError::R(r_format))),
+            };
+
+            Inst::R(inst, r_format)
+        }
+        0b0010011 => {
+            let i_format = IFormat::new(raw_inst);
+            let upper_imm = get_bits!(i_format.imm, 5, 11);
+            let shamt = get_bits!(i_format.imm, 0, 4);
+            let inst = match (i_format.funct3, upper_imm) {
+                // unary Zbkb instructions are encoded as shifts with a fixed amount
+                (0x1, 0x04) if shamt == 0x0f => ArithIInst::ZIP,
+                (0x5, 0x04) if shamt == 0x0f => ArithIInst::UNZIP,
+                (0x5, 0x34) if shamt == 0x07 => ArithIInst::BREV8,
+                (0x5, 0x34) if shamt == 0x18 => ArithIInst::REV8,
+                (0x5, 0x30) => ArithIInst::RORI,
+                (0x0, _) => ArithIInst::ADDI,
+                (0x4, _) => ArithIInst::XORI,
+                (0x6, _) => ArithIInst::ORI,
+                (0x7, _) => ArithIInst::ANDI,
+                (0x1, 0x00) => ArithIInst::SLLI,
+                (0x5, 0x00) => ArithIInst::SRLI,
+                (0x5, 0x20) => ArithIInst::SRAI,
+                (0x2, _) => ArithIInst::SLTI,
+                (0x3, _) => ArithIInst::SLTIU,
+                _ => return Err(Error::InvalidInstFormat(FormatError::I(i_format))),
+            };
+
+            Inst::I(IInst::Arith(inst), i_format)
+        }
+        0b0000011 => {
+            let i_format = IFormat::new(raw_inst);
+            let inst = match i_format.funct3 {
+                0x0 => LoadIInst::LB,
+                0x1 => LoadIInst::LH,
+                0x2 => LoadIInst::LW,
+                0x4 => LoadIInst::LBU,
+                0x5 => LoadIInst::LHU,
+                _ => return Err(Error::InvalidInstFormat(FormatError::I(i_format))),
+            };
+
+            Inst::I(IInst::Mem(inst), i_format)
+        }
+        0b1100111 => {
+            let i_format = IFormat::new(raw_inst);
+            if let 0x0 = i_format.funct3 {
+                Inst::I(IInst::Jalr, i_format)
+            } else {
+                return Err(Error::InvalidInstFormat(FormatError::I(i_format)));
+            }
+        }
+        0b0100011 => {
+            let s_format = SFormat::new(raw_inst);
+            let inst = match s_format.funct3 {
+                0x0 => SInst::SB,
+                0x1 => SInst::SH,
+                0x2 => SInst::SW,
+                _ => return Err(Error::InvalidInstFormat(FormatError::S(s_format))),
+            };
+
+            Inst::S(inst, s_format)
+        }
+        0b1100011 => {
+            let b_format = BFormat::new(raw_inst);
+            let inst = match b_format.funct3 {
+                0x0 => BInst::BEQ,
+                0x1 => BInst::BNE,
+                0x4 => BInst::BLT,
+                0x5 => BInst::BGE,
+                0x6 => BInst::BLTU,
+                0x7 => BInst::BGEU,
+                _ => return Err(Error::InvalidInstFormat(FormatError::B(b_format))),
+            };
+
+            Inst::B(inst, b_format)
+        }
+        0b1101111 => {
+            // jal instruction is the only J-Format instruction
+            Inst::J(JFormat::new(raw_inst))
+        }
+        0b0110111 => Inst::U(UInst::LUI, UFormat::new(raw_inst)),
+        0b0010111 => Inst::U(UInst::AUIPC, UFormat::new(raw_inst)),
+        0b1110011 => {
+            let i_format = IFormat::new(raw_inst);
+            let funct12 = get_bits!(i_format.imm, 0, 11);
+            let no_operands = i_format.rd == 0 && i_format.rs1 == 0;
+            match (i_format.funct3, funct12) {
+                // what an ecall does depends on the environment, so it's resolved by the cpu
+                (0x0, 0x000) if no_operands => Inst::Ecall,
+                (0x0, 0x001) if no_operands => Inst::Ebreak,
+                (0x0, 0x302) if no_operands => Inst::Mret,
+                (0x0, 0x102) if no_operands => Inst::Sret,
+                (0x0, 0x105) if no_operands => Inst::Wfi,
+                (0x0, _) if funct12 >> 5 == 0b0001001 && i_format.rd == 0 => {
+                    Inst::SfenceVma(RFormat::new(raw_inst))
+                }
+                (0x1, _) => Inst::Csr(CsrInst::CSRRW, i_format),
+                (0x2, _) => Inst::Csr(CsrInst::CSRRS, i_format),
+                (0x3, _) => Inst::Csr(CsrInst::CSRRC, i_format),
+                (0x5, _) => Inst::Csr(CsrInst::CSRRWI, i_format),
+                (0x6, _) => Inst::Csr(CsrInst::CSRRSI, i_format),
+                (0x7, _) => Inst::Csr(CsrInst::CSRRCI, i_format),
+                _ => return Err(Error::InvalidInstFormat(FormatError::I(i_format))),
+            }
+        }
+        0b0101111 => {
+            let r_format = RFormat::new(raw_inst);
+            // the lowest two bits of funct7 are the aq/rl ordering bits
+            let funct5 = r_format.funct7 >> 2;
+            let inst = match (r_format.funct3, funct5) {
+                (0x2, 0b00010) if r_format.rs2 == 0 => AmoInst::LR,
+                (0x2, 0b00011) => AmoInst::SC,
+                (0x2, 0b00001) => AmoInst::SWAP,
+                (0x2, 0b00000) => AmoInst::ADD,
+                (0x2, 0b00100) => AmoInst::XOR,
+                (0x2, 0b01100) => AmoInst::AND,
+                (0x2, 0b01000) => AmoInst::OR,
+                (0x2, 0b10000) => AmoInst::MIN,
+                (0x2, 0b10100) => AmoInst::MAX,
+                (0x2, 0b11000) => AmoInst::MINU,
+                (0x2, 0b11100) => AmoInst::MAXU,
+                _ => return Err(Error::InvalidInstFormat(FormatError::R(r_format))),
+            };
+
+            Inst::Amo(inst, r_format)
+        }
+        // vector loads/stores share their opcodes with the scalar floating-point ones
+        0b1010111 | 0b0000111 | 0b0100111 => match VInst::decode(raw_inst) {
+            Some(inst) => Inst::Vector(inst),
+            None => {
+                return Err(Error::InvalidInstFormat(FormatError::R(RFormat::new(
+                    raw_inst,
+                ))))
+            }
+        },
+        0b0001111 => {
+            let i_format = IFormat::new(raw_inst);
+            match i_format.funct3 {
+                // fence (also necessary for riscv-tests)
+                0x0 => Inst::Fence,
+                // fence.i, there is no instruction cache to flush
+                0x1 => Inst::Fence,
+                _ => return Err(Error::InvalidInstFormat(FormatError::I(i_format))),
+            }
+        }
+        _ => return Err(Error::InvalidOpcode(opcode)),
+    };
+
+    Ok(inst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn standalone_decode() {
+        // addi x5, x0, 0x20
+        assert!(matches!(
+            decode(0x02000293),
+            Ok(Inst::I(
+                IInst::Arith(ArithIInst::ADDI),
+                IFormat {
+                    rd: 5,
+                    imm: 0x20,
+                    ..
+                }
+            ))
+        ));
+        // ecall isn't interpreted at decode time
+        assert!(matches!(decode(0x00000073), Ok(Inst::Ecall)));
+        assert!(matches!(
+            decode(0x0000007f),
+            Err(Error::InvalidOpcode(0x7f))
+        ));
+    }
+}
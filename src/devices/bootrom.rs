@@ -0,0 +1,60 @@
+use super::{Device, BOOTROM_BASE};
+use crate::memory::Size;
+
+// offsets of the data words following the code
+const ENTRY_OFFSET: usize = 0x18;
+const DTB_OFFSET: usize = 0x1c;
+const ROM_SIZE: usize = 0x20;
+
+// Read-only boot rom containing the reset vector, modeled after the one in qemu's virt machine.
+// It sets up the boot convention registers and enters the loaded program through mret, so that it
+// runs in the mode the cpu put into mstatus.MPP, e.g. supervisor mode below the built-in sbi:
+//   auipc t0, 0
+//   csrr  a0, mhartid
+//   lw    a1, 28(t0)    # device tree address
+//   lw    t0, 24(t0)    # entry point
+//   csrw  mepc, t0
+//   mret
+//   .word entry
+//   .word dtb
+#[derive(Clone)]
+pub struct BootRom {
+    rom: [u8; ROM_SIZE],
+}
+
+impl BootRom {
+    pub fn new(entry: u32, dtb: u32) -> Self {
+        let code: [u32; 6] = [
+            0x00000297, 0xf1402573, 0x01c2a583, 0x0182a283, 0x34129073, 0x30200073,
+        ];
+        let mut rom = [0; ROM_SIZE];
+        for (i, inst) in code.iter().enumerate() {
+            rom[i * 4..i * 4 + 4].copy_from_slice(&inst.to_le_bytes());
+        }
+        rom[ENTRY_OFFSET..ENTRY_OFFSET + 4].copy_from_slice(&entry.to_le_bytes());
+        rom[DTB_OFFSET..DTB_OFFSET + 4].copy_from_slice(&dtb.to_le_bytes());
+
+        BootRom { rom }
+    }
+}
+
+impl Device for BootRom {
+    fn base(&self) -> u32 {
+        BOOTROM_BASE
+    }
+    fn size(&self) -> u32 {
+        ROM_SIZE as u32
+    }
+    fn read(&mut self, offset: u32, size: Size) -> u32 {
+        let offset = offset as usize;
+        let mut bytes = [0; 4];
+        let len = (size as usize).min(ROM_SIZE - offset);
+        bytes[..len].copy_from_slice(&self.rom[offset..offset + len]);
+        u32::from_le_bytes(bytes)
+    }
+    // writes to rom are ignored
+    fn write(&mut self, _offset: u32, _size: Size, _value: u32) {}
+    fn fork(&self) -> Option<Box<dyn Device>> {
+        Some(Box::new(self.clone()))
+    }
+}
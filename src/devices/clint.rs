@@ -0,0 +1,234 @@
+use super::{Device, CLINT_BASE};
+use crate::clock;
+use crate::csr::{MIP_MSIP, MIP_MTIP};
+use crate::fdt::{cpu_intc_phandle, Fdt};
+use crate::memory::Size;
+
+use std::time::Instant;
+
+// register offsets for hart 0, the registers of hart n follow at n * 4 (msip) and
+// n * 8 (mtimecmp)
+const MSIP: u32 = 0x0;
+const MTIMECMP: u32 = 0x4000;
+const MTIME: u32 = 0xbff8;
+
+// the register layout leaves room for 4095 harts, this many are implemented
+pub const MAX_HARTS: usize = 8;
+
+// interrupt numbers of the cpu-local interrupt controller
+const IRQ_M_SOFT: u32 = 3;
+const IRQ_M_TIMER: u32 = 7;
+
+// SiFive core-local interruptor, provides the machine timer and software interrupts of each
+// hart. mtime is incremented once per cycle, or follows the host's time at the timebase frequency.
+#[derive(Clone)]
+pub struct Clint {
+    msip: [bool; MAX_HARTS],
+    // with host time, the difference between mtime and the ticks since host_start
+    mtime: u64,
+    host_start: Option<Instant>,
+    mtimecmp: [u64; MAX_HARTS],
+}
+
+impl Clint {
+    pub fn new() -> Self {
+        Clint {
+            msip: [false; MAX_HARTS],
+            mtime: 0,
+            host_start: None,
+            // no timer interrupt until software sets a deadline
+            mtimecmp: [u64::MAX; MAX_HARTS],
+        }
+    }
+
+    fn mtime(&self) -> u64 {
+        match self.host_start {
+            Some(start) => self.mtime.wrapping_add(clock::ticks(start.elapsed())),
+            None => self.mtime,
+        }
+    }
+}
+
+// returns the upper or lower half of a 64-bit register depending on the offset
+fn read_half(value: u64, offset: u32) -> u32 {
+    if offset.is_multiple_of(8) {
+        value as u32
+    } else {
+        (value >> 32) as u32
+    }
+}
+
+fn write_half(reg: &mut u64, offset: u32, value: u32) {
+    *reg = if offset.is_multiple_of(8) {
+        (*reg & !0xffff_ffff) | value as u64
+    } else {
+        (*reg & 0xffff_ffff) | ((value as u64) << 32)
+    }
+}
+
+const MSIP_END: u32 = MSIP + 4 * MAX_HARTS as u32;
+const MTIMECMP_END: u32 = MTIMECMP + 8 * MAX_HARTS as u32;
+
+impl Device for Clint {
+    fn base(&self) -> u32 {
+        CLINT_BASE
+    }
+    fn size(&self) -> u32 {
+        0x1_0000
+    }
+    fn read(&mut self, offset: u32, _size: Size) -> u32 {
+        match offset {
+            MSIP..MSIP_END => self.msip[((offset - MSIP) / 4) as usize] as u32,
+            MTIMECMP..MTIMECMP_END => {
+                read_half(self.mtimecmp[((offset - MTIMECMP) / 8) as usize], offset)
+            }
+            MTIME | 0xbffc => read_half(self.mtime(), offset),
+            _ => 0,
+        }
+    }
+    fn write(&mut self, offset: u32, _size: Size, value: u32) {
+        match offset {
+            MSIP..MSIP_END => self.msip[((offset - MSIP) / 4) as usize] = value & 1 != 0,
+            MTIMECMP..MTIMECMP_END => write_half(
+                &mut self.mtimecmp[((offset - MTIMECMP) / 8) as usize],
+                offset,
+                value,
+            ),
+            MTIME | 0xbffc => {
+                let current = self.mtime();
+                let mut mtime = current;
+                write_half(&mut mtime, offset, value);
+                self.mtime = self.mtime.wrapping_add(mtime.wrapping_sub(current));
+            }
+            _ => (),
+        }
+    }
+    fn describe(&self, fdt: &mut Fdt) {
+        fdt.begin_node(&format!("clint@{:x}", CLINT_BASE));
+        fdt.property_strs("compatible", &["sifive,clint0", "riscv,clint0"]);
+        fdt.property_cells("reg", &[CLINT_BASE, self.size()]);
+        let interrupts: Vec<_> = (0..fdt.harts())
+            .flat_map(|hart| {
+                let intc = cpu_intc_phandle(hart);
+                [intc, IRQ_M_SOFT, intc, IRQ_M_TIMER]
+            })
+            .collect();
+        fdt.property_cells("interrupts-extended", &interrupts);
+        fdt.end_node();
+    }
+    fn tick(&mut self) {
+        if self.host_start.is_none() {
+            self.mtime = self.mtime.wrapping_add(1);
+        }
+    }
+    fn next_event(&self) -> Option<u64> {
+        let mtime = self.mtime();
+        self.mtimecmp
+            .iter()
+            .filter(|&&mtimecmp| mtimecmp != u64::MAX && mtime < mtimecmp)
+            .map(|mtimecmp| mtimecmp - mtime)
+            .min()
+    }
+    fn skip(&mut self, cycles: u64) {
+        if self.host_start.is_none() {
+            self.mtime = self.mtime.wrapping_add(cycles);
+        }
+    }
+    fn follow_host_time(&mut self, start: Instant) {
+        self.mtime = self.mtime();
+        self.host_start = Some(start);
+    }
+    fn interrupts(&self, hart: usize) -> u32 {
+        if hart >= MAX_HARTS {
+            return 0;
+        }
+        let soft = if self.msip[hart] { MIP_MSIP } else { 0 };
+        let timer = if self.mtime() >= self.mtimecmp[hart] {
+            MIP_MTIP
+        } else {
+            0
+        };
+        soft | timer
+    }
+    // mtime keeps running like the rtc, only the interrupts are cleared
+    fn reset(&mut self) {
+        self.msip = [false; MAX_HARTS];
+        self.mtimecmp = [u64::MAX; MAX_HARTS];
+    }
+    fn fork(&self) -> Option<Box<dyn Device>> {
+        Some(Box::new(self.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timer_interrupt() {
+        let mut clint = Clint::new();
+        clint.write(MTIMECMP + 4, Size::Word, 0);
+        clint.write(MTIMECMP, Size::Word, 3);
+        for _ in 0..2 {
+            clint.tick();
+        }
+        assert_eq!(clint.read(MTIME, Size::Word), 2);
+        assert_eq!(clint.interrupts(0), 0);
+
+        clint.tick();
+        assert_eq!(clint.interrupts(0), MIP_MTIP);
+    }
+
+    #[test]
+    fn skip_to_deadline() {
+        let mut clint = Clint::new();
+        assert_eq!(clint.next_event(), None);
+        clint.write(MTIMECMP + 4, Size::Word, 0);
+        clint.write(MTIMECMP, Size::Word, 1000);
+        clint.tick();
+        assert_eq!(clint.next_event(), Some(999));
+
+        clint.skip(999);
+        assert_eq!(clint.interrupts(0), MIP_MTIP);
+        assert_eq!(clint.next_event(), None);
+    }
+
+    #[test]
+    fn host_time() {
+        let mut clint = Clint::new();
+        clint.write(MTIME, Size::Word, 100);
+        clint.follow_host_time(Instant::now() - std::time::Duration::from_millis(1));
+        clint.skip(1_000_000_000);
+        let mtime = clint.read(MTIME, Size::Word);
+        assert!((10_100..20_000).contains(&mtime), "mtime {mtime}");
+
+        clint.write(MTIME + 4, Size::Word, 1);
+        assert_eq!(clint.read(MTIME + 4, Size::Word), 1);
+        assert!(clint.read(MTIME, Size::Word) >= mtime);
+    }
+
+    #[test]
+    fn software_interrupt() {
+        let mut clint = Clint::new();
+        clint.write(MSIP, Size::Word, 1);
+        assert_eq!(clint.interrupts(0), MIP_MSIP);
+        clint.write(MSIP, Size::Word, 0);
+        assert_eq!(clint.interrupts(0), 0);
+    }
+
+    #[test]
+    fn per_hart_registers() {
+        let mut clint = Clint::new();
+        clint.write(MSIP + 4, Size::Word, 1);
+        assert_eq!(clint.interrupts(0), 0);
+        assert_eq!(clint.interrupts(1), MIP_MSIP);
+
+        clint.write(MTIMECMP + 2 * 8 + 4, Size::Word, 0);
+        clint.write(MTIMECMP + 2 * 8, Size::Word, 5);
+        assert_eq!(clint.read(MTIMECMP + 2 * 8, Size::Word), 5);
+        assert_eq!(clint.next_event(), Some(5));
+        clint.skip(5);
+        assert_eq!(clint.interrupts(2), MIP_MTIP);
+        assert_eq!(clint.interrupts(0), 0);
+    }
+}
@@ -0,0 +1,75 @@
+use super::{Device, CONSOLE_BASE};
+use crate::fdt::Fdt;
+use crate::memory::Size;
+
+use std::io::Write;
+
+// Minimal output-only console for bare-metal programs that just need putchar: every byte
+// written to offset 0 is passed on to the sink right away. Reads return 0.
+pub struct DebugConsole {
+    sink: Box<dyn Write>,
+}
+
+impl DebugConsole {
+    pub fn new(sink: Box<dyn Write>) -> Self {
+        DebugConsole { sink }
+    }
+}
+
+impl Device for DebugConsole {
+    fn base(&self) -> u32 {
+        CONSOLE_BASE
+    }
+    fn size(&self) -> u32 {
+        0x1000
+    }
+    fn read(&mut self, _offset: u32, _size: Size) -> u32 {
+        0
+    }
+    fn write(&mut self, offset: u32, _size: Size, value: u32) {
+        if offset != 0 {
+            return;
+        }
+        // the guest can't do anything about a failing sink, so the byte is dropped
+        let _ = self
+            .sink
+            .write_all(&[value as u8])
+            .and_then(|_| self.sink.flush());
+    }
+    fn set_sink(&mut self, sink: Box<dyn Write>) -> bool {
+        self.sink = sink;
+        true
+    }
+    fn describe(&self, fdt: &mut Fdt) {
+        fdt.begin_node(&format!("console@{:x}", CONSOLE_BASE));
+        fdt.property_str("compatible", "ruscv,debug-console");
+        fdt.property_cells("reg", &[CONSOLE_BASE, self.size()]);
+        fdt.end_node();
+    }
+    fn fork(&self) -> Option<Box<dyn Device>> {
+        // the fork's output is dropped unless it gets a sink of its own
+        Some(Box::new(DebugConsole::new(Box::new(std::io::sink()))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Seek;
+
+    #[test]
+    fn writes_bytes_to_sink() {
+        let file = tempfile::tempfile().unwrap();
+        let mut console = DebugConsole::new(Box::new(file.try_clone().unwrap()));
+        for byte in b"hi\n" {
+            console.write(0, Size::Byte, *byte as u32);
+        }
+        console.write(4, Size::Byte, b'x' as u32);
+
+        let mut file = file;
+        let mut output = String::new();
+        file.rewind().unwrap();
+        std::io::Read::read_to_string(&mut file, &mut output).unwrap();
+        assert_eq!(output, "hi\n");
+    }
+}
@@ -0,0 +1,218 @@
+use super::{Device, DMA_BASE, DMA_IRQ};
+use crate::memory::Size;
+
+const SRC: u32 = 0x00;
+const DST: u32 = 0x04;
+const LEN: u32 = 0x08;
+const CONTROL: u32 = 0x0c;
+const STATUS: u32 = 0x10;
+// writing it to the control register starts a transfer unless one is running
+const CONTROL_START: u32 = 1;
+// raises the interrupt once a transfer completed
+const CONTROL_IRQ_ENABLE: u32 = 2;
+const STATUS_BUSY: u32 = 1;
+// done and error stay set until the program writes 1 to them
+const STATUS_DONE: u32 = 2;
+// a byte of the transfer wasn't mapped or the destination was read-only
+const STATUS_ERROR: u32 = 4;
+// the engine moves one word per cycle
+const BYTES_PER_CYCLE: u64 = 4;
+
+// A copy a bus master makes on the bus, performed by the memory between device cycles.
+pub struct Transfer {
+    pub src: u32,
+    pub dst: u32,
+    pub len: u32,
+}
+
+// Single-channel dma engine copying `len` bytes from `src` to `dst` while the harts keep
+// running. The copy advances with the cycles, ascending byte by byte like a forward memmove, so
+// the program sees it in progress until the busy bit clears.
+#[derive(Clone)]
+pub struct Dma {
+    src: u32,
+    dst: u32,
+    len: u32,
+    irq_enable: bool,
+    busy: bool,
+    done: bool,
+    error: bool,
+    // bytes already handed to the memory
+    copied: u32,
+    // bytes the cycles since the last transfer allow to move
+    allowed: u64,
+}
+
+impl Dma {
+    pub fn new() -> Self {
+        Dma {
+            src: 0,
+            dst: 0,
+            len: 0,
+            irq_enable: false,
+            busy: false,
+            done: false,
+            error: false,
+            copied: 0,
+            allowed: 0,
+        }
+    }
+
+    fn remaining(&self) -> u64 {
+        (self.len - self.copied) as u64
+    }
+
+    fn complete(&mut self, error: bool) {
+        self.busy = false;
+        self.done = true;
+        self.error |= error;
+    }
+}
+
+impl Device for Dma {
+    fn base(&self) -> u32 {
+        DMA_BASE
+    }
+    fn size(&self) -> u32 {
+        0x1000
+    }
+    fn read(&mut self, offset: u32, _size: Size) -> u32 {
+        match offset {
+            SRC => self.src,
+            DST => self.dst,
+            LEN => self.len,
+            CONTROL if self.irq_enable => CONTROL_IRQ_ENABLE,
+            STATUS => {
+                (if self.busy { STATUS_BUSY } else { 0 })
+                    | (if self.done { STATUS_DONE } else { 0 })
+                    | (if self.error { STATUS_ERROR } else { 0 })
+            }
+            _ => 0,
+        }
+    }
+    fn write(&mut self, offset: u32, _size: Size, value: u32) {
+        match offset {
+            // the running transfer keeps its addresses
+            SRC | DST | LEN if self.busy => (),
+            SRC => self.src = value,
+            DST => self.dst = value,
+            LEN => self.len = value,
+            CONTROL => {
+                self.irq_enable = value & CONTROL_IRQ_ENABLE != 0;
+                if value & CONTROL_START != 0 && !self.busy {
+                    self.busy = true;
+                    self.copied = 0;
+                    self.allowed = 0;
+                }
+            }
+            STATUS => {
+                self.done &= value & STATUS_DONE == 0;
+                self.error &= value & STATUS_ERROR == 0;
+            }
+            _ => (),
+        }
+    }
+    fn tick(&mut self) {
+        self.skip(1);
+    }
+    fn next_event(&self) -> Option<u64> {
+        let left = self.remaining().saturating_sub(self.allowed);
+        self.busy.then_some(left.div_ceil(BYTES_PER_CYCLE))
+    }
+    fn skip(&mut self, cycles: u64) {
+        if self.busy {
+            let allowed = self.allowed + cycles.saturating_mul(BYTES_PER_CYCLE);
+            self.allowed = allowed.min(self.remaining());
+        }
+    }
+    fn take_transfer(&mut self) -> Option<Transfer> {
+        if !self.busy {
+            return None;
+        }
+        if self.remaining() == 0 {
+            self.complete(false);
+            return None;
+        }
+        let len = std::mem::take(&mut self.allowed) as u32;
+        if len == 0 {
+            return None;
+        }
+        let transfer = Transfer {
+            src: self.src.wrapping_add(self.copied),
+            dst: self.dst.wrapping_add(self.copied),
+            len,
+        };
+        self.copied += len;
+        Some(transfer)
+    }
+    fn transfer_done(&mut self, ok: bool) {
+        if !ok {
+            self.complete(true);
+        } else if self.remaining() == 0 {
+            self.complete(false);
+        }
+    }
+    fn irq(&self) -> Option<u32> {
+        (self.irq_enable && self.done).then_some(DMA_IRQ)
+    }
+    // a running transfer is aborted, what it copied so far stays
+    fn reset(&mut self) {
+        *self = Dma::new();
+    }
+    fn fork(&self) -> Option<Box<dyn Device>> {
+        Some(Box::new(self.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::Memory;
+
+    fn start(mem: &mut Memory, src: u32, dst: u32, len: u32) {
+        mem.store(Size::Word, DMA_BASE + SRC, src).unwrap();
+        mem.store(Size::Word, DMA_BASE + DST, dst).unwrap();
+        mem.store(Size::Word, DMA_BASE + LEN, len).unwrap();
+        let control = CONTROL_START | CONTROL_IRQ_ENABLE;
+        mem.store(Size::Word, DMA_BASE + CONTROL, control).unwrap();
+    }
+
+    fn status(mem: &mut Memory) -> u32 {
+        mem.load(Size::Word, DMA_BASE + STATUS, true).unwrap()
+    }
+
+    #[test]
+    fn copies_over_cycles() {
+        let mut mem = Memory::new();
+        mem.add_device(Box::new(Dma::new()));
+        mem.write_bytes(0x100, b"direct memory access");
+        start(&mut mem, 0x100, 0x200, 20);
+        assert_eq!(mem.next_event(), Some(5));
+        mem.tick();
+        mem.tick();
+        assert_eq!(
+            &mem.peek(0x200, 20)[..],
+            b"direct m\0\0\0\0\0\0\0\0\0\0\0\0"
+        );
+        assert_eq!(status(&mut mem), STATUS_BUSY);
+
+        mem.skip(3);
+        assert_eq!(&mem.peek(0x200, 20)[..], b"direct memory access");
+        assert_eq!(status(&mut mem), STATUS_DONE);
+        assert_eq!(mem.next_event(), None);
+        mem.store(Size::Word, DMA_BASE + STATUS, STATUS_DONE)
+            .unwrap();
+        assert_eq!(status(&mut mem), 0);
+    }
+
+    #[test]
+    fn unmapped_bytes_abort() {
+        let mut mem = Memory::new();
+        mem.add_device(Box::new(Dma::new()));
+        let end = mem.ram_end() as u32;
+        start(&mut mem, 0x100, end - 2, 8);
+        mem.tick();
+        mem.tick();
+        assert_eq!(status(&mut mem), STATUS_DONE | STATUS_ERROR);
+    }
+}
@@ -0,0 +1,201 @@
+use super::{I2cSlave, SpiSlave};
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+// size of an eeprom backed by a new file, that of a 24c256/25lc256
+pub const DEFAULT_EEPROM_SIZE: usize = 32 * 1024;
+// largest size reachable with the 16-bit addresses
+const MAX_EEPROM_SIZE: usize = 64 * 1024;
+
+// spi instructions of the 25xx series
+const READ: u8 = 0x03;
+const WRITE: u8 = 0x02;
+
+enum Phase {
+    // waiting for the spi instruction
+    Instruction,
+    // receiving the high and then the low byte of the address
+    AddressHigh,
+    AddressLow,
+    Data,
+    // reading the status register, which always reports that no write is in progress
+    Status,
+}
+
+// Serial eeprom with 16-bit addresses backed by a host file, usable both as spi slave (25xx
+// protocol: read 0x03 and write 0x02 followed by the address) and as i2c slave (24xx protocol: a
+// write sets the address with its first two bytes, reads continue at the address). Stores are
+// written through to the file at once, the address wraps around at the end of the memory.
+pub struct Eeprom {
+    data: Vec<u8>,
+    file: File,
+    address: u16,
+    phase: Phase,
+    // whether the current spi instruction writes
+    writing: bool,
+}
+
+impl Eeprom {
+    // Opens or creates the file, new files are filled with erased bytes.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        if data.len() > MAX_EEPROM_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "file larger than 64 KiB",
+            ));
+        }
+        if data.is_empty() {
+            data = vec![0xff; DEFAULT_EEPROM_SIZE];
+            file.write_all(&data)?;
+        }
+        Ok(Eeprom {
+            data,
+            file,
+            address: 0,
+            phase: Phase::Instruction,
+            writing: false,
+        })
+    }
+
+    fn offset(&self) -> usize {
+        self.address as usize % self.data.len()
+    }
+
+    fn next_byte(&mut self) -> u8 {
+        let byte = self.data[self.offset()];
+        self.address = self.address.wrapping_add(1);
+        byte
+    }
+
+    fn store(&mut self, byte: u8) {
+        let offset = self.offset();
+        self.data[offset] = byte;
+        self.address = self.address.wrapping_add(1);
+        let written = self
+            .file
+            .seek(SeekFrom::Start(offset as u64))
+            .and_then(|_| self.file.write_all(&[byte]));
+        if let Err(e) = written {
+            eprintln!("can't write eeprom file: {e}");
+        }
+    }
+
+    // receives the address bytes, returns false once the address is complete
+    fn address_byte(&mut self, byte: u8) -> bool {
+        match self.phase {
+            Phase::AddressHigh => {
+                self.address = (byte as u16) << 8;
+                self.phase = Phase::AddressLow;
+            }
+            Phase::AddressLow => {
+                self.address |= byte as u16;
+                self.phase = Phase::Data;
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+impl SpiSlave for Eeprom {
+    fn select(&mut self) {
+        self.phase = Phase::Instruction;
+    }
+    fn transfer(&mut self, mosi: u8) -> u8 {
+        match self.phase {
+            Phase::Instruction => {
+                // unknown instructions read as the status register as well
+                self.phase = match mosi {
+                    READ | WRITE => Phase::AddressHigh,
+                    _ => Phase::Status,
+                };
+                self.writing = mosi == WRITE;
+                0xff
+            }
+            Phase::AddressHigh | Phase::AddressLow => {
+                self.address_byte(mosi);
+                0xff
+            }
+            Phase::Data if self.writing => {
+                self.store(mosi);
+                0xff
+            }
+            Phase::Data => self.next_byte(),
+            Phase::Status => 0,
+        }
+    }
+}
+
+impl I2cSlave for Eeprom {
+    fn start(&mut self, read: bool) -> bool {
+        // a write starts with the address, reads continue where the last access stopped
+        self.phase = if read {
+            Phase::Data
+        } else {
+            Phase::AddressHigh
+        };
+        true
+    }
+    fn write(&mut self, byte: u8) -> bool {
+        if !self.address_byte(byte) {
+            self.store(byte);
+        }
+        true
+    }
+    fn read(&mut self) -> u8 {
+        self.next_byte()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const READ_STATUS: u8 = 0x05;
+
+    #[test]
+    fn spi_and_i2c_protocols() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("eeprom.bin");
+        let mut eeprom = Eeprom::open(&path).unwrap();
+        assert_eq!(eeprom.data.len(), DEFAULT_EEPROM_SIZE);
+
+        // spi: write two bytes at 0x1234 and read them back
+        let mut spi = |bytes: &[u8]| -> Vec<u8> {
+            SpiSlave::select(&mut eeprom);
+            bytes.iter().map(|&b| eeprom.transfer(b)).collect()
+        };
+        spi(&[WRITE, 0x12, 0x34, 0xab, 0xcd]);
+        assert_eq!(
+            spi(&[READ, 0x12, 0x34, 0, 0, 0]),
+            [0xff, 0xff, 0xff, 0xab, 0xcd, 0xff]
+        );
+        assert_eq!(spi(&[READ_STATUS, 0]), [0xff, 0]);
+
+        // i2c: set the address, then read with a repeated start
+        assert!(eeprom.start(false));
+        eeprom.write(0x12);
+        eeprom.write(0x35);
+        assert!(eeprom.start(true));
+        assert_eq!(eeprom.read(), 0xcd);
+        eeprom.start(false);
+        for byte in [0x00, 0x10, 1, 2] {
+            eeprom.write(byte);
+        }
+        drop(eeprom);
+
+        let data = std::fs::read(&path).unwrap();
+        assert_eq!(&data[0x1234..0x1236], [0xab, 0xcd]);
+        assert_eq!(&data[0x10..0x12], [1, 2]);
+    }
+}
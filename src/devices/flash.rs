@@ -0,0 +1,162 @@
+use super::Device;
+use crate::memory::Size;
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::Path;
+
+// erase granularity
+pub const SECTOR_SIZE: u32 = 4096;
+// size of a flash backed by a new file
+pub const DEFAULT_FLASH_SIZE: u32 = 1024 * 1024;
+
+// registers on the page after the flash array
+const ERASE: u32 = 0x0;
+const STATUS: u32 = 0x4;
+const SECTOR: u32 = 0x8;
+const STATUS_READY: u32 = 1;
+const ERASED: u8 = 0xff;
+
+// NOR-like flash backed by a host file, so data stored by the program survives restarts. Reads
+// return the array, stores to it program the flash: like on hardware they can only clear bits,
+// setting bits requires erasing the whole sector by writing its offset to the erase register.
+// Programming and erasing complete instantly and are written through to the file right away.
+pub struct Flash {
+    base: u32,
+    data: Vec<u8>,
+    file: File,
+}
+
+impl Flash {
+    // Opens or creates the file, files that don't fill whole sectors are padded with erased bytes.
+    pub fn open(path: &Path, base: u32) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        let size = match data.len() {
+            0 => DEFAULT_FLASH_SIZE as usize,
+            len => len.next_multiple_of(SECTOR_SIZE as usize),
+        };
+        if size > u32::MAX as usize / 2 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "file too large",
+            ));
+        }
+        let len = data.len();
+        data.resize(size, ERASED);
+        let mut flash = Flash { base, data, file };
+        flash.flush(len..size)?;
+        Ok(flash)
+    }
+
+    fn flush(&mut self, range: Range<usize>) -> io::Result<()> {
+        if range.is_empty() {
+            return Ok(());
+        }
+        self.file.seek(SeekFrom::Start(range.start as u64))?;
+        self.file.write_all(&self.data[range])
+    }
+
+    fn write_through(&mut self, range: Range<usize>) {
+        if let Err(e) = self.flush(range) {
+            eprintln!("can't write flash file: {e}");
+        }
+    }
+
+    fn array_size(&self) -> u32 {
+        self.data.len() as u32
+    }
+}
+
+impl Device for Flash {
+    fn base(&self) -> u32 {
+        self.base
+    }
+    fn size(&self) -> u32 {
+        self.array_size() + SECTOR_SIZE
+    }
+    fn read(&mut self, offset: u32, size: Size) -> u32 {
+        if offset >= self.array_size() {
+            return match offset - self.array_size() {
+                STATUS => STATUS_READY,
+                SECTOR => SECTOR_SIZE,
+                _ => 0,
+            };
+        }
+        let offset = offset as usize;
+        let mut bytes = [0; 4];
+        let len = (size as usize).min(self.data.len() - offset);
+        bytes[..len].copy_from_slice(&self.data[offset..offset + len]);
+        u32::from_le_bytes(bytes)
+    }
+    fn write(&mut self, offset: u32, size: Size, value: u32) {
+        if offset >= self.array_size() {
+            if offset - self.array_size() == ERASE && value < self.array_size() {
+                let start = (value / SECTOR_SIZE * SECTOR_SIZE) as usize;
+                let sector = start..start + SECTOR_SIZE as usize;
+                self.data[sector.clone()].fill(ERASED);
+                self.write_through(sector);
+            }
+            return;
+        }
+        let offset = offset as usize;
+        let len = (size as usize).min(self.data.len() - offset);
+        for (byte, value) in self.data[offset..offset + len]
+            .iter_mut()
+            .zip(value.to_le_bytes())
+        {
+            *byte &= value;
+        }
+        self.write_through(offset..offset + len);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn program_and_erase() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("flash.bin");
+        std::fs::write(&path, [0x12, 0x34]).unwrap();
+        let mut flash = Flash::open(&path, 0x2200_0000).unwrap();
+        assert_eq!(flash.size(), 2 * SECTOR_SIZE);
+        assert_eq!(flash.read(0, Size::Word), 0xffff_3412);
+        assert_eq!(flash.read(SECTOR_SIZE + SECTOR, Size::Word), SECTOR_SIZE);
+
+        // programming only clears bits
+        flash.write(4, Size::Word, 0x0f0f_0f0f);
+        flash.write(4, Size::Byte, 0xf1);
+        assert_eq!(flash.read(4, Size::Word), 0x0f0f_0f01);
+        flash.write(SECTOR_SIZE + ERASE, Size::Word, 6);
+        assert_eq!(flash.read(0, Size::Word), 0xffff_ffff);
+        flash.write(8, Size::Byte, 0xaa);
+
+        // the contents persist across restarts
+        drop(flash);
+        let mut flash = Flash::open(&path, 0x2200_0000).unwrap();
+        assert_eq!(flash.read(8, Size::Word), 0xffff_ffaa);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), SECTOR_SIZE as u64);
+    }
+
+    #[test]
+    fn new_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("new.bin");
+        let mut flash = Flash::open(&path, 0).unwrap();
+        assert_eq!(flash.size(), DEFAULT_FLASH_SIZE + SECTOR_SIZE);
+        assert_eq!(flash.read(DEFAULT_FLASH_SIZE - 4, Size::Word), 0xffff_ffff);
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().len(),
+            DEFAULT_FLASH_SIZE as u64
+        );
+    }
+}
@@ -0,0 +1,230 @@
+use super::{Device, GPIO_BASE, GPIO_IRQ};
+use crate::memory::Size;
+
+use std::io::Write;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+// levels of all pins, outputs read back what they drive
+const INPUT: u32 = 0x00;
+const OUTPUT: u32 = 0x04;
+// pins with their bit set are outputs
+const DIRECTION: u32 = 0x08;
+// input pins that raise the interrupt on a rising edge
+const IRQ_ENABLE: u32 = 0x0c;
+// rising edges seen on enabled pins, writing a bit clears it
+const IRQ_PENDING: u32 = 0x10;
+
+pub const GPIO_PINS: u32 = 32;
+
+// The host's side of the pins: it drives the inputs, e.g. a button, and sees the levels the
+// program drives on the outputs, e.g. a led. Clones share the pins.
+#[derive(Clone)]
+pub struct GpioPins {
+    inputs: Arc<AtomicU32>,
+    outputs: Arc<AtomicU32>,
+}
+
+impl GpioPins {
+    pub fn set_input(&self, pin: u32, level: bool) {
+        let mask = 1 << pin;
+        if level {
+            self.inputs.fetch_or(mask, Ordering::Relaxed);
+        } else {
+            self.inputs.fetch_and(!mask, Ordering::Relaxed);
+        }
+    }
+    pub fn inputs(&self) -> u32 {
+        self.inputs.load(Ordering::Relaxed)
+    }
+    // levels of the pins configured as outputs, the others read as 0
+    pub fn outputs(&self) -> u32 {
+        self.outputs.load(Ordering::Relaxed)
+    }
+
+    // Runs a `gpio [<pin> <0|1>]` debugger command: sets the input if one is given and reports
+    // the state of all pins.
+    pub fn command(&self, input: Option<(u32, bool)>) -> String {
+        if let Some((pin, level)) = input {
+            self.set_input(pin, level);
+        }
+        format!(
+            "gpio outputs {:#010x} inputs {:#010x}\n",
+            self.outputs(),
+            self.inputs()
+        )
+    }
+}
+
+// parses the arguments of the gpio debugger command, None shows the pins without changing them
+pub fn parse_input(args: &str) -> Result<Option<(u32, bool)>, String> {
+    let args: Vec<_> = args.split_whitespace().collect();
+    match args[..] {
+        [] => Ok(None),
+        [pin, level] => {
+            let pin = pin
+                .parse()
+                .ok()
+                .filter(|&pin| pin < GPIO_PINS)
+                .ok_or(format!("invalid gpio pin '{pin}'"))?;
+            match level {
+                "0" => Ok(Some((pin, false))),
+                "1" => Ok(Some((pin, true))),
+                _ => Err(format!("invalid gpio level '{level}', expected 0 or 1")),
+            }
+        }
+        _ => Err("expected 'gpio [<pin> <0|1>]'".to_string()),
+    }
+}
+
+// A block of 32 general purpose pins. Every change of an output pin is logged as
+// `cycle,pin,level`, rising edges on input pins can raise an interrupt through the plic.
+pub struct Gpio {
+    pins: GpioPins,
+    output: u32,
+    direction: u32,
+    irq_enable: u32,
+    irq_pending: u32,
+    // the inputs at the last tick, to find the edges
+    last_inputs: u32,
+    cycle: u64,
+    log: Option<Box<dyn Write>>,
+}
+
+impl Gpio {
+    pub fn new(log: Option<Box<dyn Write>>) -> Self {
+        Gpio {
+            pins: GpioPins {
+                inputs: Arc::new(AtomicU32::new(0)),
+                outputs: Arc::new(AtomicU32::new(0)),
+            },
+            output: 0,
+            direction: 0,
+            irq_enable: 0,
+            irq_pending: 0,
+            last_inputs: 0,
+            cycle: 0,
+            log,
+        }
+    }
+
+    pub fn pins(&self) -> GpioPins {
+        self.pins.clone()
+    }
+
+    // publishes the driven levels and logs the pins that changed
+    fn update_outputs(&mut self) {
+        let levels = self.output & self.direction;
+        let changed = levels ^ self.pins.outputs.swap(levels, Ordering::Relaxed);
+        let Some(log) = self.log.as_mut() else {
+            return;
+        };
+        for pin in (0..GPIO_PINS).filter(|pin| changed >> pin & 1 == 1) {
+            let written = writeln!(log, "{},{pin},{}", self.cycle, levels >> pin & 1);
+            if let Err(e) = written.and_then(|_| log.flush()) {
+                eprintln!("can't write gpio log: {e}");
+                self.log = None;
+                return;
+            }
+        }
+    }
+
+    fn sample_inputs(&mut self) {
+        let inputs = self.pins.inputs() & !self.direction;
+        self.irq_pending |= inputs & !self.last_inputs & self.irq_enable;
+        self.last_inputs = inputs;
+    }
+}
+
+impl Device for Gpio {
+    fn base(&self) -> u32 {
+        GPIO_BASE
+    }
+    fn size(&self) -> u32 {
+        0x1000
+    }
+    fn read(&mut self, offset: u32, _size: Size) -> u32 {
+        match offset {
+            INPUT => self.pins.inputs() & !self.direction | self.output & self.direction,
+            OUTPUT => self.output,
+            DIRECTION => self.direction,
+            IRQ_ENABLE => self.irq_enable,
+            IRQ_PENDING => self.irq_pending,
+            _ => 0,
+        }
+    }
+    fn write(&mut self, offset: u32, _size: Size, value: u32) {
+        match offset {
+            OUTPUT => self.output = value,
+            DIRECTION => self.direction = value,
+            IRQ_ENABLE => self.irq_enable = value,
+            IRQ_PENDING => self.irq_pending &= !value,
+            _ => return,
+        }
+        self.update_outputs();
+    }
+    fn tick(&mut self) {
+        self.skip(1);
+    }
+    fn skip(&mut self, cycles: u64) {
+        self.cycle += cycles;
+        self.sample_inputs();
+    }
+    fn irq(&self) -> Option<u32> {
+        (self.irq_pending != 0).then_some(GPIO_IRQ)
+    }
+    // all pins become inputs again, which shows up in the log as outputs going low
+    fn reset(&mut self) {
+        self.output = 0;
+        self.direction = 0;
+        self.irq_enable = 0;
+        self.irq_pending = 0;
+        self.update_outputs();
+        self.sample_inputs();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::Capture;
+
+    #[test]
+    fn outputs_inputs_and_edges() {
+        let log = Capture::new();
+        let mut gpio = Gpio::new(Some(Box::new(log.clone())));
+        let pins = gpio.pins();
+        // pin 0 is a led, pin 4 a button
+        gpio.write(DIRECTION, Size::Word, 1);
+        gpio.write(IRQ_ENABLE, Size::Word, 1 << 4);
+        gpio.tick();
+        gpio.write(OUTPUT, Size::Word, 1 | 1 << 4);
+        gpio.skip(9);
+        gpio.write(OUTPUT, Size::Word, 0);
+        assert_eq!(log.text(), "1,0,1\n10,0,0\n");
+        assert_eq!(pins.outputs(), 0);
+
+        pins.set_input(4, true);
+        assert_eq!(gpio.irq(), None);
+        gpio.tick();
+        assert_eq!(gpio.irq(), Some(GPIO_IRQ));
+        assert_eq!(gpio.read(INPUT, Size::Word), 1 << 4);
+        gpio.write(IRQ_PENDING, Size::Word, 1 << 4);
+        gpio.tick();
+        // the level stays high, only edges are pending
+        assert_eq!(gpio.irq(), None);
+        assert_eq!(
+            pins.command(Some((4, false))),
+            "gpio outputs 0x00000000 inputs 0x00000000\n"
+        );
+    }
+
+    #[test]
+    fn command_arguments() {
+        assert_eq!(parse_input(""), Ok(None));
+        assert_eq!(parse_input("3 1"), Ok(Some((3, true))));
+        assert!(parse_input("32 1").is_err());
+        assert!(parse_input("3 high").is_err());
+        assert!(parse_input("3").is_err());
+    }
+}
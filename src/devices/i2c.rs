@@ -0,0 +1,148 @@
+use super::{Device, I2C_BASE};
+use crate::memory::Size;
+
+use std::collections::BTreeMap;
+
+const ADDRESS: u32 = 0x0;
+const DATA: u32 = 0x4;
+const STOP: u32 = 0x8;
+const STATUS: u32 = 0xc;
+// the last address or data byte wasn't acknowledged
+const STATUS_NACK: u32 = 1;
+// a transaction was started and not stopped yet
+const STATUS_BUSY: u32 = 2;
+const IDLE: u8 = 0xff;
+
+// A device on the i2c bus, addressed by its 7-bit address. Returning false from start or write
+// doesn't acknowledge the address or byte.
+pub trait I2cSlave {
+    fn start(&mut self, _read: bool) -> bool {
+        true
+    }
+    fn write(&mut self, byte: u8) -> bool;
+    fn read(&mut self) -> u8;
+    fn stop(&mut self) {}
+}
+
+// Polled i2c master. Writing `address << 1 | read` to the address register sends a (repeated)
+// start condition and the address, writing the data register sends a byte to the addressed
+// slave and reading it receives one. Writing the stop register ends the transaction. Every
+// transfer completes at once, the status register tells whether it was acknowledged.
+pub struct I2c {
+    slaves: BTreeMap<u8, Box<dyn I2cSlave>>,
+    // the addressed slave, None if no slave answered
+    target: Option<u8>,
+    busy: bool,
+    nack: bool,
+}
+
+impl I2c {
+    pub fn new() -> Self {
+        I2c {
+            slaves: BTreeMap::new(),
+            target: None,
+            busy: false,
+            nack: false,
+        }
+    }
+
+    pub fn add_slave(&mut self, address: u8, slave: Box<dyn I2cSlave>) {
+        self.slaves.insert(address, slave);
+    }
+
+    fn target(&mut self) -> Option<&mut Box<dyn I2cSlave>> {
+        self.slaves.get_mut(&self.target?)
+    }
+
+    fn start(&mut self, value: u32) {
+        let address = (value >> 1 & 0x7f) as u8;
+        self.busy = true;
+        self.target = None;
+        self.nack = true;
+        if let Some(slave) = self.slaves.get_mut(&address) {
+            if slave.start(value & 1 == 1) {
+                self.target = Some(address);
+                self.nack = false;
+            }
+        }
+    }
+
+    fn stop(&mut self) {
+        // every slave sees the stop condition, not just the addressed one
+        for slave in self.slaves.values_mut() {
+            slave.stop();
+        }
+        self.target = None;
+        self.busy = false;
+    }
+}
+
+impl Device for I2c {
+    fn base(&self) -> u32 {
+        I2C_BASE
+    }
+    fn size(&self) -> u32 {
+        0x1000
+    }
+    fn read(&mut self, offset: u32, _size: Size) -> u32 {
+        match offset {
+            DATA => self.target().map_or(IDLE, |slave| slave.read()) as u32,
+            STATUS => {
+                (if self.nack { STATUS_NACK } else { 0 })
+                    | (if self.busy { STATUS_BUSY } else { 0 })
+            }
+            _ => 0,
+        }
+    }
+    fn write(&mut self, offset: u32, _size: Size, value: u32) {
+        match offset {
+            ADDRESS => self.start(value),
+            DATA => self.nack = !self.target().is_some_and(|slave| slave.write(value as u8)),
+            STOP => self.stop(),
+            _ => (),
+        }
+    }
+    fn reset(&mut self) {
+        self.stop();
+        self.nack = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a single register that only accepts even values
+    struct Register(u8);
+
+    impl I2cSlave for Register {
+        fn write(&mut self, byte: u8) -> bool {
+            self.0 = byte;
+            byte.is_multiple_of(2)
+        }
+        fn read(&mut self) -> u8 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn addressing_and_acks() {
+        let mut i2c = I2c::new();
+        i2c.add_slave(0x50, Box::new(Register(7)));
+        i2c.write(ADDRESS, Size::Byte, 0x40 << 1);
+        assert_eq!(i2c.read(STATUS, Size::Word), STATUS_NACK | STATUS_BUSY);
+        assert_eq!(i2c.read(DATA, Size::Byte), 0xff);
+
+        i2c.write(ADDRESS, Size::Byte, 0x50 << 1 | 1);
+        assert_eq!(i2c.read(STATUS, Size::Word), STATUS_BUSY);
+        assert_eq!(i2c.read(DATA, Size::Byte), 7);
+        i2c.write(ADDRESS, Size::Byte, 0x50 << 1);
+        i2c.write(DATA, Size::Byte, 3);
+        assert_eq!(i2c.read(STATUS, Size::Word), STATUS_NACK | STATUS_BUSY);
+        i2c.write(DATA, Size::Byte, 4);
+        assert_eq!(i2c.read(STATUS, Size::Word), STATUS_BUSY);
+        i2c.write(STOP, Size::Word, 0);
+        assert_eq!(i2c.read(STATUS, Size::Word), 0);
+        assert_eq!(i2c.read(DATA, Size::Byte), 0xff);
+    }
+}
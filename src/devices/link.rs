@@ -0,0 +1,165 @@
+use super::reader;
+
+use std::io::{self, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::Receiver;
+
+// Host end of a serial line: the bytes arriving from the peer and the writer sending to it.
+pub struct SerialLink {
+    pub input: Receiver<u8>,
+    pub output: Box<dyn Write>,
+    // the peer's address or the path of the pseudo-terminal
+    pub name: String,
+}
+
+// Opens the host end of a uart link:
+//   tcp:<addr>          connects to a peer listening at addr, e.g. another ruscv
+//   tcp-listen:<addr>   waits for one peer to connect, e.g. another ruscv or `nc`
+//   pty                 creates a pseudo-terminal for terminal programs like screen or lrzsz
+pub fn open(spec: &str) -> io::Result<SerialLink> {
+    let stream = match spec.split_once(':') {
+        Some(("tcp", addr)) => TcpStream::connect(addr)?,
+        Some(("tcp-listen", addr)) => {
+            let listener = TcpListener::bind(addr)?;
+            eprintln!("uart link waiting on {}", listener.local_addr()?);
+            listener.accept()?.0
+        }
+        _ if spec == "pty" => return open_pty(),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "expected tcp:<addr>, tcp-listen:<addr> or pty",
+            ))
+        }
+    };
+    // every byte goes out on its own, like on the wire
+    stream.set_nodelay(true)?;
+    Ok(SerialLink {
+        input: reader(stream.try_clone()?),
+        name: stream.peer_addr()?.to_string(),
+        output: Box::new(stream),
+    })
+}
+
+#[cfg(unix)]
+fn open_pty() -> io::Result<SerialLink> {
+    use std::fs::{File, OpenOptions};
+    use std::os::fd::{AsRawFd, FromRawFd};
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let check = |result: libc::c_int| match result {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(result),
+    };
+    // SAFETY: posix_openpt returns a new descriptor that nothing else owns
+    let master = unsafe {
+        let fd = check(libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY))?;
+        File::from_raw_fd(fd)
+    };
+    // SAFETY: the descriptor is open, ptsname's static buffer is copied before the next call
+    let path = unsafe {
+        check(libc::grantpt(master.as_raw_fd()))?;
+        check(libc::unlockpt(master.as_raw_fd()))?;
+        let name = libc::ptsname(master.as_raw_fd());
+        if name.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        std::ffi::CStr::from_ptr(name)
+            .to_string_lossy()
+            .into_owned()
+    };
+    // Binary protocols like xmodem need the line raw, without echo or newline translation. The
+    // emulator keeps the slave open as well, so the link survives terminal programs coming and
+    // going instead of reading a hangup.
+    let slave = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(&path)?;
+    // SAFETY: termios is plain data, filled in by tcgetattr before it's used
+    unsafe {
+        let mut termios = std::mem::zeroed();
+        check(libc::tcgetattr(slave.as_raw_fd(), &mut termios))?;
+        libc::cfmakeraw(&mut termios);
+        check(libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios))?;
+    }
+    let input = reader(PtyMaster {
+        master: master.try_clone()?,
+        _slave: slave,
+    });
+    Ok(SerialLink {
+        input,
+        output: Box::new(master),
+        name: path,
+    })
+}
+
+#[cfg(not(unix))]
+fn open_pty() -> io::Result<SerialLink> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "pseudo-terminals are only supported on unix",
+    ))
+}
+
+// reading end of the pty, owning the slave for as long as the reader runs
+#[cfg(unix)]
+struct PtyMaster {
+    master: std::fs::File,
+    _slave: std::fs::File,
+}
+
+#[cfg(unix)]
+impl io::Read for PtyMaster {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.master.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::time::Duration;
+
+    fn receive(link: &SerialLink, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|_| link.input.recv_timeout(Duration::from_secs(5)).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn tcp_link() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut link = open(&format!("tcp:{addr}")).unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+        peer.write_all(b"\x01\x02").unwrap();
+        assert_eq!(receive(&link, 2), [1, 2]);
+        link.output.write_all(b"ok").unwrap();
+        let mut buf = [0; 2];
+        peer.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ok");
+        assert!(open("serial:/dev/ttyS0").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn raw_pty_link() {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        let mut link = open("pty").unwrap();
+        let mut terminal = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(&link.name)
+            .unwrap();
+        terminal.write_all(b"\x00\r\x7f").unwrap();
+        assert_eq!(receive(&link, 3), [0, b'\r', 0x7f]);
+        link.output.write_all(b"ok\n").unwrap();
+        let mut buf = [0; 3];
+        terminal.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ok\n");
+    }
+}
@@ -0,0 +1,63 @@
+use super::Device;
+use crate::memory::Size;
+
+use std::rc::Rc;
+
+const PAGE_SIZE: u32 = 4096;
+
+// A host file mapped read-only into the address space, so programs can access large data sets
+// without them being part of the binary. The mapping is padded with zeros to whole pages.
+#[derive(Clone)]
+pub struct MappedFile {
+    base: u32,
+    // shared by forks of the machine
+    data: Rc<[u8]>,
+}
+
+impl MappedFile {
+    pub fn new(base: u32, data: Vec<u8>) -> Self {
+        MappedFile {
+            base,
+            data: data.into(),
+        }
+    }
+}
+
+impl Device for MappedFile {
+    fn base(&self) -> u32 {
+        self.base
+    }
+    fn size(&self) -> u32 {
+        (self.data.len() as u32)
+            .next_multiple_of(PAGE_SIZE)
+            .max(PAGE_SIZE)
+    }
+    fn read(&mut self, offset: u32, size: Size) -> u32 {
+        let offset = (offset as usize).min(self.data.len());
+        let mut bytes = [0; 4];
+        let len = (size as usize).min(self.data.len() - offset);
+        bytes[..len].copy_from_slice(&self.data[offset..offset + len]);
+        u32::from_le_bytes(bytes)
+    }
+    fn write(&mut self, _offset: u32, _size: Size, _value: u32) {}
+    fn read_only(&self) -> bool {
+        true
+    }
+    fn fork(&self) -> Option<Box<dyn Device>> {
+        Some(Box::new(self.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_padded() {
+        let mut file = MappedFile::new(0x9000_0000, vec![1, 2, 3, 4, 5]);
+        assert_eq!(file.size(), PAGE_SIZE);
+        assert_eq!(file.read(0, Size::Word), 0x04030201);
+        assert_eq!(file.read(3, Size::Word), 0x0504);
+        assert_eq!(file.read(8, Size::HalfWord), 0);
+    }
+}
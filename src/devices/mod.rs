@@ -0,0 +1,274 @@
+mod bootrom;
+mod clint;
+mod console;
+mod dma;
+mod eeprom;
+mod flash;
+mod gpio;
+mod i2c;
+mod link;
+mod mapped_file;
+mod plic;
+mod rtc;
+#[cfg(feature = "scripting")]
+mod scripted;
+mod sifive_test;
+mod slip;
+mod spi;
+mod test_assert;
+mod tracepoint;
+mod uart;
+mod virtio;
+mod watchdog;
+
+pub use bootrom::BootRom;
+pub use clint::{Clint, MAX_HARTS};
+pub use console::DebugConsole;
+pub use dma::{Dma, Transfer};
+pub use eeprom::Eeprom;
+pub use flash::Flash;
+pub use gpio::{parse_input as parse_gpio_input, Gpio, GpioPins, GPIO_PINS};
+pub use i2c::{I2c, I2cSlave};
+pub use link::{open as open_serial_link, SerialLink};
+pub use mapped_file::MappedFile;
+pub use plic::Plic;
+pub use rtc::{GoldfishRtc, RtcClock};
+#[cfg(feature = "scripting")]
+pub use scripted::{ScriptedDevice, ScriptedSlave};
+pub use sifive_test::SifiveTest;
+pub use slip::SlipNet;
+pub use spi::{Spi, SpiSlave};
+pub use test_assert::{AssertFailure, TestAssert};
+pub use tracepoint::Tracepoint;
+pub use uart::Uart;
+pub use virtio::VirtioBlock;
+pub use watchdog::Watchdog;
+
+use crate::backend::MemoryBackend;
+use crate::fdt::Fdt;
+use crate::memory::Size;
+
+use std::borrow::Cow;
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+// Base addresses of the memory-mapped peripherals, loosely following qemu's virt machine.
+pub const SIFIVE_TEST_BASE: u32 = 0x0010_0000;
+pub const RTC_BASE: u32 = 0x0010_1000;
+pub const CONSOLE_BASE: u32 = 0x0010_2000;
+pub const TRACEPOINT_BASE: u32 = 0x0010_3000;
+pub const TEST_ASSERT_BASE: u32 = 0x0010_4000;
+pub const CLINT_BASE: u32 = 0x0200_0000;
+pub const PLIC_BASE: u32 = 0x0c00_0000;
+pub const UART_BASE: u32 = 0x1000_0000;
+pub const VIRTIO_BASE: u32 = 0x1000_1000;
+pub const SLIP_BASE: u32 = 0x1000_2000;
+pub const SPI_BASE: u32 = 0x1000_3000;
+pub const I2C_BASE: u32 = 0x1000_4000;
+pub const GPIO_BASE: u32 = 0x1000_5000;
+pub const WATCHDOG_BASE: u32 = 0x1000_6000;
+pub const DMA_BASE: u32 = 0x1000_7000;
+// ram starts at address 0, so the boot rom lives where qemu's virt machine maps its flash
+pub const BOOTROM_BASE: u32 = 0x2000_0000;
+// default address of the flash, where qemu's virt machine maps its second flash bank
+pub const FLASH_BASE: u32 = 0x2200_0000;
+
+// plic interrupt source numbers
+pub const VIRTIO_IRQ: u32 = 1;
+pub const UART_IRQ: u32 = 10;
+pub const GPIO_IRQ: u32 = 11;
+pub const DMA_IRQ: u32 = 12;
+
+// A peripheral mapped into the physical address space.
+// Offsets passed to read/write are relative to the device's base address.
+pub trait Device {
+    fn base(&self) -> u32;
+    // size of the register window in bytes
+    fn size(&self) -> u32;
+    fn read(&mut self, offset: u32, size: Size) -> u32;
+    fn write(&mut self, offset: u32, size: Size, value: u32);
+    // stores to read-only devices raise an access fault instead of being passed to write
+    fn read_only(&self) -> bool {
+        false
+    }
+    // redirects the bytes a console-like device outputs, returns false if the device has no output
+    fn set_sink(&mut self, _sink: Box<dyn Write>) -> bool {
+        false
+    }
+    // whether the device receives input from the host, like a uart reading stdin
+    fn has_input(&self) -> bool {
+        false
+    }
+    // replaces the host input of a device that has one
+    fn set_source(&mut self, _source: Receiver<u8>) {}
+    // copy of the device's state for a forked machine, None if it is tied to a host resource
+    fn fork(&self) -> Option<Box<dyn Device>> {
+        None
+    }
+    // devices that can power off the machine return the exit-code once they were told to do so
+    fn take_exit(&mut self) -> Option<u8> {
+        None
+    }
+    // devices that can reset the machine, e.g. a watchdog, return true once they were told to
+    fn take_reset(&mut self) -> bool {
+        false
+    }
+    // returns the registers to their power-on state when the machine resets, contents that
+    // persist on hardware like flash or a host connection are kept
+    fn reset(&mut self) {}
+    // bus masters like the dma engine return the copies they make on the bus, the memory
+    // performs them after each tick
+    fn take_transfer(&mut self) -> Option<Transfer> {
+        None
+    }
+    // tells a bus master whether its last transfer completed, false if it hit an unmapped or
+    // read-only byte and stopped there
+    fn transfer_done(&mut self, _ok: bool) {}
+    // bus masters that walk structures in ram, like the queues of a virtio device, are handed the
+    // ram after each tick
+    fn access_ram(&mut self, _ram: &mut Ram) {}
+    // the id the program wrote to a tracepoint register, if it did since the last call
+    fn take_tracepoint(&mut self) -> Option<u32> {
+        None
+    }
+    // the assertion of the program that failed, if one did since the last call
+    fn take_failed_assert(&mut self) -> Option<AssertFailure> {
+        None
+    }
+    // adds the device's node to the device tree passed to the guest
+    fn describe(&self, _fdt: &mut Fdt) {}
+    // called once every cycle
+    fn tick(&mut self) {}
+    // cycles until the device raises an interrupt on its own, lets an idle hart skip ahead
+    fn next_event(&self) -> Option<u64> {
+        None
+    }
+    // advances the device by several cycles at once, devices that count cycles override this
+    fn skip(&mut self, _cycles: u64) {
+        self.tick();
+    }
+    // external interrupt source number, if the device currently asserts its interrupt line
+    fn irq(&self) -> Option<u32> {
+        None
+    }
+    // makes a device with its own clock follow the host's time, starting at the given instant
+    fn follow_host_time(&mut self, _start: Instant) {}
+    // interrupt controllers receive the state of all interrupt lines as a bitmask every cycle
+    fn set_irq_lines(&mut self, _lines: u32) {}
+    // interrupt-pending bits (as in mip) that the device raises at the given hart
+    fn interrupts(&self, _hart: usize) -> u32 {
+        0
+    }
+}
+
+// The ram as seen by a bus master, addresses are physical and don't go through aliases or devices.
+pub struct Ram<'a> {
+    backend: &'a mut dyn MemoryBackend,
+    base: u32,
+}
+
+impl<'a> Ram<'a> {
+    pub fn new(backend: &'a mut dyn MemoryBackend, base: u32) -> Self {
+        Ram { backend, base }
+    }
+
+    // offsets of the bytes in the backend, None unless all of them are ram
+    fn range(&self, address: u64, len: usize) -> Option<Range<usize>> {
+        let start = usize::try_from(address.checked_sub(self.base as u64)?).ok()?;
+        let end = start.checked_add(len)?;
+        (end <= self.backend.size()).then_some(start..end)
+    }
+    pub fn read(&self, address: u64, len: usize) -> Option<Cow<'_, [u8]>> {
+        self.range(address, len)
+            .map(|range| self.backend.read(range))
+    }
+    // returns false without writing anything if a byte isn't ram
+    pub fn write(&mut self, address: u64, data: &[u8]) -> bool {
+        match self.range(address, data.len()) {
+            Some(range) => {
+                self.backend.write(range.start, data);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+// In-memory sink keeping everything written to it. Clones share the buffer, so the output of a
+// device that owns one clone can be read through another one, e.g. in tests.
+#[derive(Clone)]
+pub struct Capture(Arc<Mutex<Captured>>);
+
+#[derive(Default)]
+struct Captured {
+    bytes: Vec<u8>,
+    // bytes accepted over the capture's life, including the ones already taken
+    accepted: usize,
+    limit: Option<usize>,
+    // whether bytes past the limit were dropped
+    truncated: bool,
+}
+
+impl Capture {
+    pub fn new() -> Self {
+        Capture(Arc::new(Mutex::new(Captured::default())))
+    }
+    // keeps at most `limit` bytes over the capture's life, the writer isn't told about the rest
+    pub fn set_limit(&self, limit: usize) {
+        self.0.lock().unwrap().limit = Some(limit);
+    }
+    pub fn bytes(&self) -> Vec<u8> {
+        self.0.lock().unwrap().bytes.clone()
+    }
+    // the captured output, invalid utf-8 is replaced
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap().bytes).into_owned()
+    }
+    // removes and returns what was captured since the last call
+    pub fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut self.0.lock().unwrap().bytes)
+    }
+    pub fn truncated(&self) -> bool {
+        self.0.lock().unwrap().truncated
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut captured = self.0.lock().unwrap();
+        let room = captured
+            .limit
+            .map_or(buf.len(), |limit| limit.saturating_sub(captured.accepted))
+            .min(buf.len());
+        captured.bytes.extend_from_slice(&buf[..room]);
+        captured.accepted += room;
+        captured.truncated |= room < buf.len();
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// Spawns a thread forwarding the bytes read from stdin, so that devices can poll for input
+// without blocking the emulation.
+pub fn stdin_reader() -> Receiver<u8> {
+    reader(std::io::stdin())
+}
+
+// forwards the bytes read from the source like stdin_reader, until it ends or fails
+pub fn reader(mut source: impl Read + Send + 'static) -> Receiver<u8> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut buf = [0; 64];
+        while let Ok(len @ 1..) = source.read(&mut buf) {
+            if buf[..len].iter().any(|&byte| sender.send(byte).is_err()) {
+                break;
+            }
+        }
+    });
+    receiver
+}
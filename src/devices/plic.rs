@@ -0,0 +1,201 @@
+use super::{Device, PLIC_BASE};
+use crate::csr::{MIP_MEIP, MIP_SEIP};
+use crate::fdt::{cpu_intc_phandle, Fdt, PLIC_PHANDLE};
+use crate::memory::Size;
+
+// source 0 is reserved, so there are 31 usable interrupt sources
+const NUM_SOURCES: u32 = 32;
+// context 0 is hart 0 in m-mode, context 1 is hart 0 in s-mode
+const NUM_CONTEXTS: usize = 2;
+
+const PRIORITY: u32 = 0x0;
+const PENDING: u32 = 0x1000;
+const ENABLE: u32 = 0x2000;
+const ENABLE_STRIDE: u32 = 0x80;
+const CONTEXT: u32 = 0x20_0000;
+const CONTEXT_STRIDE: u32 = 0x1000;
+
+const IRQ_M_EXT: u32 = 11;
+const IRQ_S_EXT: u32 = 9;
+
+// Platform-level interrupt controller, routes the interrupt lines of devices to the hart.
+// Sources are level-triggered, a source stays pending until it's claimed and isn't
+// forwarded again until its handler signals completion.
+#[derive(Clone)]
+pub struct Plic {
+    priority: [u32; NUM_SOURCES as usize],
+    pending: u32,
+    // sources that were claimed but not completed yet
+    in_service: u32,
+    enable: [u32; NUM_CONTEXTS],
+    threshold: [u32; NUM_CONTEXTS],
+}
+
+impl Plic {
+    pub fn new() -> Self {
+        Plic {
+            priority: [0; NUM_SOURCES as usize],
+            pending: 0,
+            in_service: 0,
+            enable: [0; NUM_CONTEXTS],
+            threshold: [0; NUM_CONTEXTS],
+        }
+    }
+
+    // returns the enabled pending source with the highest priority (lowest id wins ties)
+    fn best_source(&self, context: usize) -> Option<u32> {
+        (1..NUM_SOURCES)
+            .filter(|&id| self.pending & self.enable[context] & (1 << id) != 0)
+            .filter(|&id| self.priority[id as usize] > self.threshold[context])
+            .max_by_key(|&id| (self.priority[id as usize], NUM_SOURCES - id))
+    }
+
+    fn claim(&mut self, context: usize) -> u32 {
+        match self.best_source(context) {
+            Some(id) => {
+                self.pending &= !(1 << id);
+                self.in_service |= 1 << id;
+                id
+            }
+            None => 0,
+        }
+    }
+}
+
+impl Device for Plic {
+    fn base(&self) -> u32 {
+        PLIC_BASE
+    }
+    fn size(&self) -> u32 {
+        0x400_0000
+    }
+    fn read(&mut self, offset: u32, _size: Size) -> u32 {
+        match offset {
+            _ if offset < PRIORITY + NUM_SOURCES * 4 => {
+                self.priority[((offset - PRIORITY) / 4) as usize]
+            }
+            PENDING => self.pending,
+            _ if (ENABLE..ENABLE + NUM_CONTEXTS as u32 * ENABLE_STRIDE).contains(&offset) => {
+                let context = (offset - ENABLE) / ENABLE_STRIDE;
+                if offset.is_multiple_of(ENABLE_STRIDE) {
+                    self.enable[context as usize]
+                } else {
+                    0
+                }
+            }
+            _ if (CONTEXT..CONTEXT + NUM_CONTEXTS as u32 * CONTEXT_STRIDE).contains(&offset) => {
+                let context = ((offset - CONTEXT) / CONTEXT_STRIDE) as usize;
+                match offset % CONTEXT_STRIDE {
+                    0 => self.threshold[context],
+                    4 => self.claim(context),
+                    _ => 0,
+                }
+            }
+            _ => 0,
+        }
+    }
+    fn write(&mut self, offset: u32, _size: Size, value: u32) {
+        match offset {
+            // the reserved source 0 has no priority
+            _ if offset == PRIORITY => (),
+            _ if offset < PRIORITY + NUM_SOURCES * 4 => {
+                self.priority[((offset - PRIORITY) / 4) as usize] = value & 0x7
+            }
+            _ if (ENABLE..ENABLE + NUM_CONTEXTS as u32 * ENABLE_STRIDE).contains(&offset) => {
+                let context = (offset - ENABLE) / ENABLE_STRIDE;
+                if offset.is_multiple_of(ENABLE_STRIDE) {
+                    self.enable[context as usize] = value & !1;
+                }
+            }
+            _ if (CONTEXT..CONTEXT + NUM_CONTEXTS as u32 * CONTEXT_STRIDE).contains(&offset) => {
+                let context = ((offset - CONTEXT) / CONTEXT_STRIDE) as usize;
+                match offset % CONTEXT_STRIDE {
+                    0 => self.threshold[context] = value & 0x7,
+                    // completion
+                    4 if value < NUM_SOURCES => self.in_service &= !(1 << value),
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+    fn describe(&self, fdt: &mut Fdt) {
+        fdt.begin_node(&format!("plic@{:x}", PLIC_BASE));
+        fdt.property_strs("compatible", &["sifive,plic-1.0.0", "riscv,plic0"]);
+        fdt.property_cells("reg", &[PLIC_BASE, self.size()]);
+        fdt.property_u32("#address-cells", 0);
+        fdt.property_u32("#interrupt-cells", 1);
+        fdt.property_empty("interrupt-controller");
+        fdt.property_cells(
+            "interrupts-extended",
+            &[
+                cpu_intc_phandle(0),
+                IRQ_M_EXT,
+                cpu_intc_phandle(0),
+                IRQ_S_EXT,
+            ],
+        );
+        fdt.property_u32("riscv,ndev", NUM_SOURCES - 1);
+        fdt.property_u32("phandle", PLIC_PHANDLE);
+        fdt.end_node();
+    }
+    fn set_irq_lines(&mut self, lines: u32) {
+        self.pending |= lines & !self.in_service & !1;
+    }
+    // both contexts belong to hart 0, external interrupts aren't routed to other harts
+    fn interrupts(&self, hart: usize) -> u32 {
+        if hart != 0 {
+            return 0;
+        }
+        let machine = self.best_source(0).map_or(0, |_| MIP_MEIP);
+        let supervisor = self.best_source(1).map_or(0, |_| MIP_SEIP);
+        machine | supervisor
+    }
+    fn reset(&mut self) {
+        *self = Plic::new();
+    }
+    fn fork(&self) -> Option<Box<dyn Device>> {
+        Some(Box::new(self.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn claim_complete() {
+        let mut plic = Plic::new();
+        plic.write(PRIORITY + 4 * 10, Size::Word, 1);
+        plic.write(PRIORITY + 4 * 3, Size::Word, 2);
+        plic.write(ENABLE, Size::Word, (1 << 10) | (1 << 3));
+
+        plic.set_irq_lines(1 << 10 | 1 << 3);
+        assert_eq!(plic.interrupts(0), MIP_MEIP);
+        // higher priority is claimed first
+        assert_eq!(plic.read(CONTEXT + 4, Size::Word), 3);
+        assert_eq!(plic.read(CONTEXT + 4, Size::Word), 10);
+        assert_eq!(plic.read(CONTEXT + 4, Size::Word), 0);
+        assert_eq!(plic.interrupts(0), 0);
+
+        // in-service sources aren't pending again until they are completed
+        plic.set_irq_lines(1 << 10);
+        assert_eq!(plic.interrupts(0), 0);
+        plic.write(CONTEXT + 4, Size::Word, 10);
+        plic.set_irq_lines(1 << 10);
+        assert_eq!(plic.interrupts(0), MIP_MEIP);
+    }
+
+    #[test]
+    fn threshold() {
+        let mut plic = Plic::new();
+        plic.write(PRIORITY + 4, Size::Word, 1);
+        plic.write(ENABLE + ENABLE_STRIDE, Size::Word, 1 << 1);
+        plic.write(CONTEXT + CONTEXT_STRIDE, Size::Word, 1);
+        plic.set_irq_lines(1 << 1);
+        assert_eq!(plic.interrupts(0), 0);
+
+        plic.write(CONTEXT + CONTEXT_STRIDE, Size::Word, 0);
+        assert_eq!(plic.interrupts(0), MIP_SEIP);
+    }
+}
@@ -0,0 +1,130 @@
+use super::{Device, RTC_BASE};
+use crate::fdt::Fdt;
+use crate::memory::Size;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// register offsets of the goldfish rtc, the alarm registers at 0x08-0x18 read as zero and ignore
+// writes since there is no alarm
+const TIME_LOW: u32 = 0x00;
+const TIME_HIGH: u32 = 0x04;
+
+#[derive(Clone, Copy)]
+pub enum RtcClock {
+    // follows the host's wall-clock
+    Host,
+    // always reports the same time (in ns since the unix epoch), useful for deterministic runs
+    Frozen(u64),
+}
+
+// Google Goldfish real-time clock as found in qemu's virt machine (and supported by linux).
+// Time is reported in nanoseconds since the unix epoch. Reading TIME_LOW latches the upper half,
+// so that a following read of TIME_HIGH returns a consistent 64-bit value.
+#[derive(Clone)]
+pub struct GoldfishRtc {
+    clock: RtcClock,
+    // difference between guest time and the clock, changes when the guest sets the time
+    offset: i64,
+    time_high: u32,
+}
+
+impl GoldfishRtc {
+    pub fn new(clock: RtcClock) -> Self {
+        GoldfishRtc {
+            clock,
+            offset: 0,
+            time_high: 0,
+        }
+    }
+
+    fn clock_ns(&self) -> u64 {
+        match self.clock {
+            RtcClock::Host => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_nanos() as u64),
+            RtcClock::Frozen(ns) => ns,
+        }
+    }
+
+    fn now(&self) -> u64 {
+        self.clock_ns().wrapping_add_signed(self.offset)
+    }
+
+    fn set_time(&mut self, ns: u64) {
+        self.offset = ns.wrapping_sub(self.clock_ns()) as i64;
+    }
+}
+
+impl Device for GoldfishRtc {
+    fn base(&self) -> u32 {
+        RTC_BASE
+    }
+    fn size(&self) -> u32 {
+        0x20
+    }
+    fn read(&mut self, offset: u32, _size: Size) -> u32 {
+        match offset {
+            TIME_LOW => {
+                let now = self.now();
+                self.time_high = (now >> 32) as u32;
+                now as u32
+            }
+            TIME_HIGH => self.time_high,
+            _ => 0,
+        }
+    }
+    fn write(&mut self, offset: u32, _size: Size, value: u32) {
+        match offset {
+            // like qemu the guest writes the high half first and commits by writing the low half
+            TIME_LOW => {
+                let time = ((self.time_high as u64) << 32) | value as u64;
+                self.set_time(time);
+            }
+            TIME_HIGH => self.time_high = value,
+            _ => (),
+        }
+    }
+    fn describe(&self, fdt: &mut Fdt) {
+        fdt.begin_node(&format!("rtc@{:x}", RTC_BASE));
+        fdt.property_str("compatible", "google,goldfish-rtc");
+        fdt.property_cells("reg", &[RTC_BASE, self.size()]);
+        fdt.end_node();
+    }
+    fn fork(&self) -> Option<Box<dyn Device>> {
+        Some(Box::new(self.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_time(rtc: &mut GoldfishRtc) -> u64 {
+        let low = rtc.read(TIME_LOW, Size::Word) as u64;
+        let high = rtc.read(TIME_HIGH, Size::Word) as u64;
+        (high << 32) | low
+    }
+
+    #[test]
+    fn frozen_time() {
+        let mut rtc = GoldfishRtc::new(RtcClock::Frozen(0x1234_5678_9abc_def0));
+        assert_eq!(read_time(&mut rtc), 0x1234_5678_9abc_def0);
+        assert_eq!(read_time(&mut rtc), 0x1234_5678_9abc_def0);
+    }
+
+    #[test]
+    fn host_time_advances() {
+        let mut rtc = GoldfishRtc::new(RtcClock::Host);
+        let first = read_time(&mut rtc);
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        assert!(read_time(&mut rtc) > first);
+    }
+
+    #[test]
+    fn guest_sets_time() {
+        let mut rtc = GoldfishRtc::new(RtcClock::Frozen(1000));
+        rtc.write(TIME_HIGH, Size::Word, 1);
+        rtc.write(TIME_LOW, Size::Word, 5);
+        assert_eq!(read_time(&mut rtc), (1 << 32) | 5);
+    }
+}
@@ -0,0 +1,332 @@
+// A device whose registers are implemented by a rhai script, so that the register map of a
+// custom board can be simulated without recompiling. The script's top level sets the window and
+// optionally the plic interrupt source, the device's behavior is defined by functions that keep
+// their state in `this`:
+//     let base = 0x1001_0000;
+//     let size = 0x100;
+//     let irq = 5;
+//     fn init() { this.reload = 0; }
+//     fn read(offset, size) { if offset == 0 { this.reload } else { 0 } }
+//     fn write(offset, size, value) { this.reload = value; timer(value); }
+//     fn timer() { irq(true); timer(this.reload); }
+// `timer(n)` calls `timer()` after n cycles (0 cancels it) and `irq(level)` sets the interrupt
+// line. All functions are optional, reads without a `read` function return 0.
+//
+// Spi and i2c slaves can be scripted the same way, with the optional functions `select()`,
+// `transfer(byte)` and `deselect()` on spi, and `start(read)`, `write(byte)`, `read()` and
+// `stop()` on i2c. `start` and `write` acknowledge unless they return false.
+use super::{Device, I2cSlave, SpiSlave};
+use crate::memory::Size;
+
+use rhai::{CallFnOptions, Dynamic, Engine, FuncArgs, Map, Scope, AST};
+use std::cell::Cell;
+use std::rc::Rc;
+
+// a compiled script with the state its functions share
+struct Script {
+    name: String,
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    // the object map the script functions see as `this`
+    state: Dynamic,
+}
+
+impl Script {
+    // compiles the script and runs its top level
+    fn load(name: &str, engine: Engine, source: &str) -> Result<Self, String> {
+        let ast = engine.compile(source).map_err(|e| e.to_string())?;
+        let mut scope = Scope::new();
+        engine
+            .run_ast_with_scope(&mut scope, &ast)
+            .map_err(|e| e.to_string())?;
+        Ok(Script {
+            name: name.to_string(),
+            engine,
+            ast,
+            scope,
+            state: Dynamic::from_map(Map::new()),
+        })
+    }
+
+    // a number the top level assigned to the variable
+    fn int(&self, var: &str) -> Result<Option<u32>, String> {
+        match self.scope.get_value::<Dynamic>(var) {
+            None => Ok(None),
+            Some(value) => match value.as_int() {
+                Ok(value @ 0..=0xffff_ffff) => Ok(Some(value as u32)),
+                _ => Err(format!("'{var}' isn't a 32-bit address or number")),
+            },
+        }
+    }
+
+    fn init(&mut self) -> Result<(), String> {
+        if self.defines("init") {
+            if let Err(e) = self.call("init", ()) {
+                return Err(e.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    fn defines(&self, function: &str) -> bool {
+        self.ast.iter_functions().any(|f| f.name == function)
+    }
+
+    fn call(
+        &mut self,
+        function: &str,
+        args: impl FuncArgs,
+    ) -> Result<Dynamic, Box<rhai::EvalAltResult>> {
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .bind_this_ptr(&mut self.state);
+        self.engine
+            .call_fn_with_options(options, &mut self.scope, &self.ast, function, args)
+    }
+
+    // errors in the script can't be passed to the program, they are printed and the call is
+    // treated like one to a function the script doesn't define
+    fn call_reporting(&mut self, function: &str, args: impl FuncArgs) -> Option<Dynamic> {
+        if !self.defines(function) {
+            return None;
+        }
+        self.call(function, args)
+            .map_err(|e| eprintln!("device script '{}': {e}", self.name))
+            .ok()
+    }
+
+    fn call_int(&mut self, function: &str, args: impl FuncArgs) -> Option<i64> {
+        self.call_reporting(function, args)
+            .and_then(|value| value.as_int().ok())
+    }
+
+    // false only if the function returned false
+    fn call_ack(&mut self, function: &str, args: impl FuncArgs) -> bool {
+        self.call_reporting(function, args)
+            .and_then(|value| value.as_bool().ok())
+            .unwrap_or(true)
+    }
+}
+
+pub struct ScriptedDevice {
+    script: Script,
+    base: u32,
+    size: u32,
+    irq_source: Option<u32>,
+    irq: Rc<Cell<bool>>,
+    // cycles until `timer()` is called, as set by the script
+    timer: Rc<Cell<Option<u64>>>,
+}
+
+impl ScriptedDevice {
+    // compiles the script, runs its top level and calls its `init` function
+    pub fn new(name: &str, source: &str) -> Result<Self, String> {
+        let irq = Rc::new(Cell::new(false));
+        let timer = Rc::new(Cell::new(None));
+        let mut engine = Engine::new();
+        let line = irq.clone();
+        engine.register_fn("irq", move |level: bool| line.set(level));
+        let deadline = timer.clone();
+        engine.register_fn("timer", move |cycles: i64| {
+            deadline.set((cycles > 0).then_some(cycles as u64))
+        });
+
+        let mut script = Script::load(name, engine, source)?;
+        let base = script.int("base")?.ok_or("the script doesn't set 'base'")?;
+        let size = script.int("size")?.ok_or("the script doesn't set 'size'")?;
+        if size == 0 || base as u64 + size as u64 > 1 << 32 {
+            return Err(format!("invalid register window {base:#x}+{size:#x}"));
+        }
+        let irq_source = script.int("irq")?;
+        if irq_source.is_some_and(|irq| !(1..32).contains(&irq)) {
+            return Err("'irq' must be an interrupt source between 1 and 31".to_string());
+        }
+        script.init()?;
+        Ok(ScriptedDevice {
+            script,
+            base,
+            size,
+            irq_source,
+            irq,
+            timer,
+        })
+    }
+
+    fn fire_timer(&mut self) {
+        self.timer.set(None);
+        self.script.call_reporting("timer", ());
+    }
+}
+
+impl Device for ScriptedDevice {
+    fn base(&self) -> u32 {
+        self.base
+    }
+    fn size(&self) -> u32 {
+        self.size
+    }
+    fn read(&mut self, offset: u32, size: Size) -> u32 {
+        self.script
+            .call_int("read", (offset as i64, size as i64))
+            .map_or(0, |value| value as u32)
+    }
+    fn write(&mut self, offset: u32, size: Size, value: u32) {
+        self.script
+            .call_reporting("write", (offset as i64, size as i64, value as i64));
+    }
+    fn tick(&mut self) {
+        self.skip(1);
+    }
+    fn next_event(&self) -> Option<u64> {
+        self.timer.get()
+    }
+    fn skip(&mut self, cycles: u64) {
+        match self.timer.get() {
+            Some(remaining) if remaining <= cycles => self.fire_timer(),
+            Some(remaining) => self.timer.set(Some(remaining - cycles)),
+            None => (),
+        }
+    }
+    fn irq(&self) -> Option<u32> {
+        self.irq_source.filter(|_| self.irq.get())
+    }
+    // the script starts over with an empty `this` and runs `init` again
+    fn reset(&mut self) {
+        self.irq.set(false);
+        self.timer.set(None);
+        self.script.state = Dynamic::from_map(Map::new());
+        if let Err(e) = self.script.init() {
+            eprintln!("device script '{}': {e}", self.script.name);
+        }
+    }
+}
+
+// A spi or i2c slave implemented by a script, it has no register window of its own.
+pub struct ScriptedSlave {
+    script: Script,
+}
+
+impl ScriptedSlave {
+    // compiles the script, runs its top level and calls its `init` function
+    pub fn new(name: &str, source: &str) -> Result<Self, String> {
+        let mut script = Script::load(name, Engine::new(), source)?;
+        script.init()?;
+        Ok(ScriptedSlave { script })
+    }
+}
+
+// bytes the slave sends if the script doesn't, like an idle bus
+const IDLE: i64 = 0xff;
+
+impl SpiSlave for ScriptedSlave {
+    fn select(&mut self) {
+        self.script.call_reporting("select", ());
+    }
+    fn transfer(&mut self, mosi: u8) -> u8 {
+        self.script
+            .call_int("transfer", (mosi as i64,))
+            .unwrap_or(IDLE) as u8
+    }
+    fn deselect(&mut self) {
+        self.script.call_reporting("deselect", ());
+    }
+}
+
+impl I2cSlave for ScriptedSlave {
+    fn start(&mut self, read: bool) -> bool {
+        self.script.call_ack("start", (read,))
+    }
+    fn write(&mut self, byte: u8) -> bool {
+        self.script.call_ack("write", (byte as i64,))
+    }
+    fn read(&mut self) -> u8 {
+        self.script.call_int("read", ()).unwrap_or(IDLE) as u8
+    }
+    fn stop(&mut self) {
+        self.script.call_reporting("stop", ());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMER: &str = "
+        let base = 0x1001_0000;
+        let size = 0x100;
+        let irq = 5;
+        fn init() { this.reload = 0; this.fired = 0; }
+        fn read(offset, size) {
+            if offset == 0 { this.reload } else if offset == 4 { this.fired } else { size }
+        }
+        fn write(offset, size, value) {
+            if offset == 0 { this.reload = value; timer(value); } else { irq(false); }
+        }
+        fn timer() { this.fired += 1; irq(true); timer(this.reload); }
+    ";
+
+    #[test]
+    fn registers_timers_and_interrupts() {
+        let mut device = ScriptedDevice::new("timer.rhai", TIMER).unwrap();
+        assert_eq!((device.base(), device.size()), (0x1001_0000, 0x100));
+        assert_eq!(device.read(8, Size::HalfWord), 2);
+        assert_eq!(device.next_event(), None);
+
+        device.write(0, Size::Word, 10);
+        assert_eq!(device.read(0, Size::Word), 10);
+        assert_eq!(device.next_event(), Some(10));
+        for _ in 0..9 {
+            device.tick();
+        }
+        assert_eq!(device.irq(), None);
+        device.tick();
+        assert_eq!(device.irq(), Some(5));
+        assert_eq!(device.read(4, Size::Word), 1);
+
+        // acknowledging lowers the line, skipping ahead fires the reloaded timer
+        device.write(4, Size::Word, 0);
+        assert_eq!(device.irq(), None);
+        device.skip(25);
+        assert_eq!(device.irq(), Some(5));
+        assert_eq!(device.read(4, Size::Word), 2);
+    }
+
+    #[test]
+    fn scripted_slaves() {
+        // a sensor answering its id on spi and counting the bytes written over i2c
+        let source = "
+            fn init() { this.written = 0; }
+            fn transfer(byte) { if byte == 0x9f { 0x42 } else { byte + 1 } }
+            fn write(byte) { this.written += 1; byte != 0 }
+            fn read() { this.written }
+        ";
+        let mut slave = ScriptedSlave::new("sensor.rhai", source).unwrap();
+        SpiSlave::select(&mut slave);
+        assert_eq!(slave.transfer(0x9f), 0x42);
+        assert_eq!(slave.transfer(7), 8);
+        assert!(slave.start(false));
+        assert!(I2cSlave::write(&mut slave, 1));
+        assert!(!I2cSlave::write(&mut slave, 0));
+        assert_eq!(I2cSlave::read(&mut slave), 2);
+
+        // a script without functions behaves like an idle bus that acknowledges everything
+        let mut idle = ScriptedSlave::new("idle.rhai", "").unwrap();
+        assert_eq!(idle.transfer(1), 0xff);
+        assert!(idle.start(true));
+        assert_eq!(I2cSlave::read(&mut idle), 0xff);
+    }
+
+    #[test]
+    fn invalid_scripts() {
+        assert!(ScriptedDevice::new("a", "let size = 4;").is_err());
+        assert!(ScriptedDevice::new("a", "let base = 0; let size = 0;").is_err());
+        assert!(ScriptedDevice::new("a", "let base = 0; let size = 4; let irq = 40;").is_err());
+        assert!(ScriptedDevice::new("a", "let base = ").is_err());
+        // runtime errors read as 0
+        let mut device =
+            ScriptedDevice::new("a", "let base = 0; let size = 4; fn read(o, s) { o / 0 }")
+                .unwrap();
+        assert_eq!(device.read(0, Size::Word), 0);
+    }
+}
@@ -0,0 +1,83 @@
+use super::{Device, SIFIVE_TEST_BASE};
+use crate::fdt::Fdt;
+use crate::memory::Size;
+
+const FINISHER_FAIL: u32 = 0x3333;
+const FINISHER_PASS: u32 = 0x5555;
+const FINISHER_RESET: u32 = 0x7777;
+
+// The "sifive,test0" finisher used by qemu's virt machine and OpenSBI to power off.
+// Writing 0x5555 ends the simulation successfully, writing 0x3333 | code << 16 ends it
+// with the given exit-code, writing 0x7777 resets the machine.
+#[derive(Clone)]
+pub struct SifiveTest {
+    exit: Option<u8>,
+    reset: bool,
+}
+
+impl SifiveTest {
+    pub fn new() -> Self {
+        SifiveTest {
+            exit: None,
+            reset: false,
+        }
+    }
+}
+
+impl Device for SifiveTest {
+    fn base(&self) -> u32 {
+        SIFIVE_TEST_BASE
+    }
+    fn size(&self) -> u32 {
+        0x1000
+    }
+    fn read(&mut self, _offset: u32, _size: Size) -> u32 {
+        0
+    }
+    fn write(&mut self, offset: u32, _size: Size, value: u32) {
+        if offset != 0 {
+            return;
+        }
+        match value & 0xffff {
+            FINISHER_PASS => self.exit = Some(0),
+            // exit-codes are truncated to a byte, just like they are by the host os
+            FINISHER_FAIL => self.exit = Some((value >> 16) as u8),
+            FINISHER_RESET => self.reset = true,
+            _ => (),
+        }
+    }
+    fn take_exit(&mut self) -> Option<u8> {
+        self.exit.take()
+    }
+    fn take_reset(&mut self) -> bool {
+        std::mem::take(&mut self.reset)
+    }
+    fn describe(&self, fdt: &mut Fdt) {
+        fdt.begin_node(&format!("test@{:x}", SIFIVE_TEST_BASE));
+        fdt.property_strs("compatible", &["sifive,test1", "sifive,test0", "syscon"]);
+        fdt.property_cells("reg", &[SIFIVE_TEST_BASE, self.size()]);
+        fdt.end_node();
+    }
+    fn fork(&self) -> Option<Box<dyn Device>> {
+        Some(Box::new(self.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pass_and_fail() {
+        let mut finisher = SifiveTest::new();
+        finisher.write(0, Size::Word, 0x1234);
+        assert_eq!(finisher.take_exit(), None);
+
+        finisher.write(0, Size::Word, FINISHER_PASS);
+        assert_eq!(finisher.take_exit(), Some(0));
+        assert_eq!(finisher.take_exit(), None);
+
+        finisher.write(0, Size::Word, (42 << 16) | FINISHER_FAIL);
+        assert_eq!(finisher.take_exit(), Some(42));
+    }
+}
@@ -0,0 +1,206 @@
+use super::{Device, SLIP_BASE};
+use crate::fdt::Fdt;
+use crate::memory::Size;
+
+use std::collections::VecDeque;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+
+// SLIP special characters (RFC 1055)
+const END: u8 = 0xc0;
+const ESC: u8 = 0xdb;
+const ESC_END: u8 = 0xdc;
+const ESC_ESC: u8 = 0xdd;
+
+// register offsets
+const DATA: u32 = 0x0;
+const STATUS: u32 = 0x4;
+
+const STATUS_RX_READY: u32 = 1 << 0;
+const STATUS_TX_READY: u32 = 1 << 1;
+
+// Largest datagram we accept from the host side.
+const MAX_PACKET: usize = 65536;
+// the receive fifo stops taking datagrams once it holds this many bytes, the rest waits in the
+// socket until the guest caught up (or the host drops it)
+const MAX_RX: usize = 4 * MAX_PACKET;
+
+// A serial line interface that speaks SLIP, bridged to a host UDP tunnel.
+// The guest network stack frames packets itself (like it would on a real serial line),
+// the device strips the framing and forwards every complete packet as one UDP datagram.
+// Datagrams arriving from the peer are framed again and queued into the receive fifo.
+//
+// Register layout:
+// 0x0 DATA:   write transmits a byte, read pops the next received byte (0 if empty)
+// 0x4 STATUS: bit 0 set if a byte can be read, bit 1 set if a byte can be written
+pub struct SlipNet {
+    socket: UdpSocket,
+    rx: VecDeque<u8>,
+    // receive buffer for a datagram, kept to not set up 64 KiB on every poll
+    packet: Box<[u8]>,
+    // decoded bytes of the frame the guest is currently transmitting
+    tx_frame: Vec<u8>,
+    tx_escaped: bool,
+}
+
+impl SlipNet {
+    // binds to `local` and sends all outgoing packets to `peer`
+    pub fn new(local: SocketAddr, peer: SocketAddr) -> io::Result<Self> {
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(peer)?;
+        socket.set_nonblocking(true)?;
+
+        Ok(SlipNet {
+            socket,
+            rx: VecDeque::new(),
+            packet: vec![0; MAX_PACKET].into_boxed_slice(),
+            tx_frame: Vec::new(),
+            tx_escaped: false,
+        })
+    }
+
+    // moves the pending datagrams from the host into the receive fifo until it is full
+    fn poll_rx(&mut self) {
+        while self.rx.len() < MAX_RX {
+            let Ok(len) = self.socket.recv(&mut self.packet) else {
+                break;
+            };
+            self.rx.push_back(END);
+            for &byte in &self.packet[..len] {
+                match byte {
+                    END => self.rx.extend([ESC, ESC_END]),
+                    ESC => self.rx.extend([ESC, ESC_ESC]),
+                    _ => self.rx.push_back(byte),
+                }
+            }
+            self.rx.push_back(END);
+        }
+    }
+
+    fn transmit(&mut self, byte: u8) {
+        match (self.tx_escaped, byte) {
+            (false, END) => {
+                // empty frames are used by senders to flush line noise, don't forward those
+                if !self.tx_frame.is_empty() {
+                    // the tunnel is best-effort just like a real link, so dropped packets are fine
+                    let _ = self.socket.send(&self.tx_frame);
+                    self.tx_frame.clear();
+                }
+            }
+            (false, ESC) => self.tx_escaped = true,
+            (true, ESC_END) => {
+                self.tx_frame.push(END);
+                self.tx_escaped = false;
+            }
+            (true, ESC_ESC) => {
+                self.tx_frame.push(ESC);
+                self.tx_escaped = false;
+            }
+            // protocol violation, RFC 1055 suggests just keeping the byte
+            (_, byte) => {
+                self.tx_frame.push(byte);
+                self.tx_escaped = false;
+            }
+        }
+    }
+}
+
+impl Device for SlipNet {
+    fn base(&self) -> u32 {
+        SLIP_BASE
+    }
+    fn size(&self) -> u32 {
+        0x8
+    }
+    fn read(&mut self, offset: u32, _size: Size) -> u32 {
+        match offset {
+            DATA => {
+                if self.rx.is_empty() {
+                    self.poll_rx();
+                }
+                self.rx.pop_front().unwrap_or(0) as u32
+            }
+            STATUS => {
+                if self.rx.is_empty() {
+                    self.poll_rx();
+                }
+                let rx_ready = if self.rx.is_empty() {
+                    0
+                } else {
+                    STATUS_RX_READY
+                };
+                rx_ready | STATUS_TX_READY
+            }
+            _ => 0,
+        }
+    }
+    fn write(&mut self, offset: u32, _size: Size, value: u32) {
+        if offset == DATA {
+            self.transmit(value as u8)
+        }
+    }
+    fn describe(&self, fdt: &mut Fdt) {
+        fdt.begin_node(&format!("slip@{:x}", SLIP_BASE));
+        fdt.property_str("compatible", "ruscv,slip");
+        fdt.property_cells("reg", &[SLIP_BASE, self.size()]);
+        fdt.end_node();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn tunnel() -> (SlipNet, UdpSocket) {
+        let host = UdpSocket::bind("127.0.0.1:0").unwrap();
+        host.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let slip =
+            SlipNet::new("127.0.0.1:0".parse().unwrap(), host.local_addr().unwrap()).unwrap();
+        host.connect(slip.socket.local_addr().unwrap()).unwrap();
+        (slip, host)
+    }
+
+    #[test]
+    fn guest_to_host() {
+        let (mut slip, host) = tunnel();
+        for byte in [END, 1, ESC, ESC_END, 2, ESC, ESC_ESC, END] {
+            slip.write(DATA, Size::Byte, byte as u32);
+        }
+
+        let mut buf = [0; 16];
+        let len = host.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], &[1, END, 2, ESC]);
+    }
+
+    #[test]
+    fn host_to_guest() {
+        let (mut slip, host) = tunnel();
+        host.send(&[7, END, ESC]).unwrap();
+
+        // wait for the datagram to arrive at the device socket
+        while slip.read(STATUS, Size::Word) & STATUS_RX_READY == 0 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let mut received = Vec::new();
+        while slip.read(STATUS, Size::Word) & STATUS_RX_READY != 0 {
+            received.push(slip.read(DATA, Size::Byte) as u8);
+        }
+        assert_eq!(received, [END, 7, ESC, ESC_END, ESC, ESC_ESC, END]);
+    }
+
+    #[test]
+    fn flood_is_bounded() {
+        let (mut slip, host) = tunnel();
+        let packet = vec![1; 60000];
+        for _ in 0..6 {
+            host.send(&packet).unwrap();
+        }
+
+        while slip.read(STATUS, Size::Word) & STATUS_RX_READY == 0 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        // stopped after the datagram that filled the fifo
+        assert!(slip.rx.len() < MAX_RX + packet.len() + 2);
+    }
+}
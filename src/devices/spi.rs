@@ -0,0 +1,137 @@
+use super::{Device, SPI_BASE};
+use crate::memory::Size;
+
+use std::collections::BTreeMap;
+
+const DATA: u32 = 0x0;
+const CS: u32 = 0x4;
+const STATUS: u32 = 0x8;
+const STATUS_READY: u32 = 1;
+// value of the chip-select register while no slave is selected
+const CS_NONE: u32 = 0xffff_ffff;
+// the data line is pulled up, reads without a selected slave see all ones
+const IDLE: u8 = 0xff;
+
+// A device on the spi bus, every byte the master shifts out shifts one byte in.
+pub trait SpiSlave {
+    fn select(&mut self) {}
+    fn transfer(&mut self, mosi: u8) -> u8;
+    fn deselect(&mut self) {}
+}
+
+// Polled spi master. Writing the index of a slave to the chip-select register selects it (and
+// releases the one selected before), writing CS_NONE releases the bus. Every byte written to the
+// data register is exchanged with the selected slave at once, reading the data register returns
+// the byte received last.
+pub struct Spi {
+    slaves: BTreeMap<u32, Box<dyn SpiSlave>>,
+    selected: u32,
+    received: u8,
+}
+
+impl Spi {
+    pub fn new() -> Self {
+        Spi {
+            slaves: BTreeMap::new(),
+            selected: CS_NONE,
+            received: IDLE,
+        }
+    }
+
+    pub fn add_slave(&mut self, cs: u32, slave: Box<dyn SpiSlave>) {
+        self.slaves.insert(cs, slave);
+    }
+
+    fn select(&mut self, cs: u32) {
+        if let Some(slave) = self.slaves.get_mut(&self.selected) {
+            slave.deselect();
+        }
+        self.selected = cs;
+        if let Some(slave) = self.slaves.get_mut(&cs) {
+            slave.select();
+        }
+    }
+}
+
+impl Device for Spi {
+    fn base(&self) -> u32 {
+        SPI_BASE
+    }
+    fn size(&self) -> u32 {
+        0x1000
+    }
+    fn read(&mut self, offset: u32, _size: Size) -> u32 {
+        match offset {
+            DATA => self.received as u32,
+            CS => self.selected,
+            STATUS => STATUS_READY,
+            _ => 0,
+        }
+    }
+    fn write(&mut self, offset: u32, _size: Size, value: u32) {
+        match offset {
+            DATA => {
+                self.received = match self.slaves.get_mut(&self.selected) {
+                    Some(slave) => slave.transfer(value as u8),
+                    None => IDLE,
+                }
+            }
+            CS => self.select(value),
+            _ => (),
+        }
+    }
+    fn reset(&mut self) {
+        self.select(CS_NONE);
+        self.received = IDLE;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // echoes the previous byte and logs the bus events
+    struct Echo {
+        last: u8,
+        log: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl SpiSlave for Echo {
+        fn select(&mut self) {
+            self.log.borrow_mut().push("select".to_string());
+        }
+        fn transfer(&mut self, mosi: u8) -> u8 {
+            std::mem::replace(&mut self.last, mosi)
+        }
+        fn deselect(&mut self) {
+            self.log.borrow_mut().push("deselect".to_string());
+        }
+    }
+
+    #[test]
+    fn transfers_with_selected_slave() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut spi = Spi::new();
+        spi.add_slave(
+            1,
+            Box::new(Echo {
+                last: 0,
+                log: log.clone(),
+            }),
+        );
+        spi.write(DATA, Size::Byte, 0x12);
+        assert_eq!(spi.read(DATA, Size::Byte), 0xff);
+
+        spi.write(CS, Size::Word, 1);
+        spi.write(DATA, Size::Byte, 0x12);
+        assert_eq!(spi.read(DATA, Size::Byte), 0);
+        spi.write(DATA, Size::Byte, 0x34);
+        assert_eq!(spi.read(DATA, Size::Byte), 0x12);
+        assert_eq!(spi.read(CS, Size::Word), 1);
+        spi.write(CS, Size::Word, CS_NONE);
+        assert_eq!(*log.borrow(), ["select", "deselect"]);
+        assert_eq!(spi.read(STATUS, Size::Word), STATUS_READY);
+    }
+}
@@ -0,0 +1,107 @@
+use super::{Device, TEST_ASSERT_BASE};
+use crate::memory::Size;
+
+const ACTUAL: u32 = 0x0;
+const EXPECTED: u32 = 0x4;
+const CHECK: u32 = 0x8;
+const PASSED: u32 = 0xc;
+
+// An assertion the guest made that didn't hold.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AssertFailure {
+    pub id: u32,
+    pub actual: u32,
+    pub expected: u32,
+}
+
+// Assertions for guest-side unit tests, an ASSERT_EQ(actual, expected, id) is three stores:
+//   sw actual, 0x0(base); sw expected, 0x4(base); sw id, 0x8(base)
+// Writing the id compares the two values, if they differ the emulation stops with the id and both
+// values printed. Reading 0xc returns the number of assertions that held.
+#[derive(Clone)]
+pub struct TestAssert {
+    actual: u32,
+    expected: u32,
+    passed: u32,
+    failure: Option<AssertFailure>,
+}
+
+impl TestAssert {
+    pub fn new() -> Self {
+        TestAssert {
+            actual: 0,
+            expected: 0,
+            passed: 0,
+            failure: None,
+        }
+    }
+}
+
+impl Device for TestAssert {
+    fn base(&self) -> u32 {
+        TEST_ASSERT_BASE
+    }
+    fn size(&self) -> u32 {
+        0x1000
+    }
+    fn read(&mut self, offset: u32, _size: Size) -> u32 {
+        match offset {
+            ACTUAL => self.actual,
+            EXPECTED => self.expected,
+            PASSED => self.passed,
+            _ => 0,
+        }
+    }
+    fn write(&mut self, offset: u32, _size: Size, value: u32) {
+        match offset {
+            ACTUAL => self.actual = value,
+            EXPECTED => self.expected = value,
+            CHECK if self.actual == self.expected => self.passed += 1,
+            CHECK => {
+                self.failure = Some(AssertFailure {
+                    id: value,
+                    actual: self.actual,
+                    expected: self.expected,
+                })
+            }
+            _ => (),
+        }
+    }
+    fn take_failed_assert(&mut self) -> Option<AssertFailure> {
+        self.failure.take()
+    }
+    fn reset(&mut self) {
+        *self = TestAssert::new();
+    }
+    fn fork(&self) -> Option<Box<dyn Device>> {
+        Some(Box::new(self.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assert_eq() {
+        let mut device = TestAssert::new();
+        device.write(ACTUAL, Size::Word, 7);
+        device.write(EXPECTED, Size::Word, 7);
+        device.write(CHECK, Size::Word, 1);
+        assert_eq!(device.take_failed_assert(), None);
+        assert_eq!(device.read(PASSED, Size::Word), 1);
+
+        device.write(EXPECTED, Size::Word, 8);
+        device.write(CHECK, Size::Word, 2);
+        assert_eq!(
+            device.take_failed_assert(),
+            Some(AssertFailure {
+                id: 2,
+                actual: 7,
+                expected: 8
+            })
+        );
+        assert_eq!(device.take_failed_assert(), None);
+        assert_eq!(device.read(PASSED, Size::Word), 1);
+    }
+}
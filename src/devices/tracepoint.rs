@@ -0,0 +1,39 @@
+use super::{Device, TRACEPOINT_BASE};
+use crate::memory::Size;
+
+// Instrumentation channel for the program: every write to the register is a tracepoint whose id
+// is the written value. The cpu logs it together with the cycle, the hart and a0, so firmware
+// can be timed without changes to the emulator.
+#[derive(Clone)]
+pub struct Tracepoint {
+    id: Option<u32>,
+}
+
+impl Tracepoint {
+    pub fn new() -> Self {
+        Tracepoint { id: None }
+    }
+}
+
+impl Device for Tracepoint {
+    fn base(&self) -> u32 {
+        TRACEPOINT_BASE
+    }
+    fn size(&self) -> u32 {
+        0x1000
+    }
+    fn read(&mut self, _offset: u32, _size: Size) -> u32 {
+        0
+    }
+    fn write(&mut self, offset: u32, _size: Size, value: u32) {
+        if offset == 0 {
+            self.id = Some(value);
+        }
+    }
+    fn take_tracepoint(&mut self) -> Option<u32> {
+        self.id.take()
+    }
+    fn fork(&self) -> Option<Box<dyn Device>> {
+        Some(Box::new(self.clone()))
+    }
+}
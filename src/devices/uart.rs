@@ -0,0 +1,252 @@
+use super::{stdin_reader, Device, UART_BASE, UART_IRQ};
+use crate::fdt::{Fdt, PLIC_PHANDLE};
+use crate::memory::Size;
+
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::mpsc::Receiver;
+
+// register offsets, RBR/THR and IER are the divisor latch while LCR.DLAB is set
+const RBR_THR: u32 = 0;
+const IER: u32 = 1;
+const IIR_FCR: u32 = 2;
+const LCR: u32 = 3;
+const MCR: u32 = 4;
+const LSR: u32 = 5;
+const MSR: u32 = 6;
+const SCR: u32 = 7;
+
+const IER_RX_AVAILABLE: u8 = 1 << 0;
+const IER_THR_EMPTY: u8 = 1 << 1;
+
+const IIR_NO_INTERRUPT: u8 = 0x01;
+const IIR_THR_EMPTY: u8 = 0x02;
+const IIR_RX_AVAILABLE: u8 = 0x04;
+const IIR_FIFO_ENABLED: u8 = 0xc0;
+
+const LCR_DLAB: u8 = 1 << 7;
+
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_THR_EMPTY: u8 = 1 << 5;
+const LSR_TRANSMITTER_EMPTY: u8 = 1 << 6;
+
+// NS16550A compatible uart connected to the host's stdin and stdout, the output can be redirected
+// with set_sink and the input with set_source, e.g. to link it to another uart. Transmitting is
+// instantaneous, so the transmitter is always empty.
+pub struct Uart {
+    sink: Box<dyn Write>,
+    // lazily spawned, so that stdin is only touched by programs that use the uart
+    input: Option<Receiver<u8>>,
+    rx: VecDeque<u8>,
+    ier: u8,
+    fcr: u8,
+    lcr: u8,
+    mcr: u8,
+    scr: u8,
+    divisor: u16,
+    // set whenever the transmitter becomes empty, cleared once the guest reads IIR
+    thr_empty_pending: bool,
+}
+
+impl Uart {
+    pub fn new() -> Self {
+        Uart {
+            sink: Box::new(std::io::stdout()),
+            input: None,
+            rx: VecDeque::new(),
+            ier: 0,
+            fcr: 0,
+            lcr: 0,
+            mcr: 0,
+            scr: 0,
+            divisor: 0,
+            thr_empty_pending: false,
+        }
+    }
+
+    fn poll_rx(&mut self) {
+        if let Some(input) = &self.input {
+            self.rx.extend(input.try_iter());
+        }
+    }
+
+    fn rx_interrupt(&self) -> bool {
+        self.ier & IER_RX_AVAILABLE != 0 && !self.rx.is_empty()
+    }
+
+    fn thr_interrupt(&self) -> bool {
+        self.ier & IER_THR_EMPTY != 0 && self.thr_empty_pending
+    }
+
+    fn transmit(&mut self, byte: u8) {
+        // a guest can't do anything about a closed stdout, so just drop the character
+        let _ = self.sink.write_all(&[byte]).and_then(|_| self.sink.flush());
+        self.thr_empty_pending = true;
+    }
+}
+
+impl Device for Uart {
+    fn base(&self) -> u32 {
+        UART_BASE
+    }
+    fn size(&self) -> u32 {
+        0x100
+    }
+    fn read(&mut self, offset: u32, _size: Size) -> u32 {
+        let dlab = self.lcr & LCR_DLAB != 0;
+        let value = match offset {
+            RBR_THR if dlab => self.divisor as u8,
+            IER if dlab => (self.divisor >> 8) as u8,
+            RBR_THR => {
+                self.input.get_or_insert_with(stdin_reader);
+                self.poll_rx();
+                self.rx.pop_front().unwrap_or(0)
+            }
+            IER => self.ier,
+            IIR_FCR => {
+                let fifo = if self.fcr & 1 != 0 {
+                    IIR_FIFO_ENABLED
+                } else {
+                    0
+                };
+                let id = if self.rx_interrupt() {
+                    IIR_RX_AVAILABLE
+                } else if self.thr_interrupt() {
+                    self.thr_empty_pending = false;
+                    IIR_THR_EMPTY
+                } else {
+                    IIR_NO_INTERRUPT
+                };
+                fifo | id
+            }
+            LCR => self.lcr,
+            MCR => self.mcr,
+            LSR => {
+                self.input.get_or_insert_with(stdin_reader);
+                self.poll_rx();
+                let ready = if self.rx.is_empty() {
+                    0
+                } else {
+                    LSR_DATA_READY
+                };
+                ready | LSR_THR_EMPTY | LSR_TRANSMITTER_EMPTY
+            }
+            MSR => 0,
+            SCR => self.scr,
+            _ => 0,
+        };
+        value as u32
+    }
+    fn write(&mut self, offset: u32, _size: Size, value: u32) {
+        let value = value as u8;
+        let dlab = self.lcr & LCR_DLAB != 0;
+        match offset {
+            RBR_THR if dlab => self.divisor = (self.divisor & 0xff00) | value as u16,
+            IER if dlab => self.divisor = (self.divisor & 0x00ff) | ((value as u16) << 8),
+            RBR_THR => self.transmit(value),
+            IER => {
+                // enabling the interrupt while the transmitter is empty raises it right away
+                if value & IER_THR_EMPTY != 0 && self.ier & IER_THR_EMPTY == 0 {
+                    self.thr_empty_pending = true;
+                }
+                self.ier = value & 0x0f;
+            }
+            IIR_FCR => self.fcr = value,
+            LCR => self.lcr = value,
+            MCR => self.mcr = value,
+            SCR => self.scr = value,
+            _ => (),
+        }
+    }
+    fn set_sink(&mut self, sink: Box<dyn Write>) -> bool {
+        self.sink = sink;
+        true
+    }
+    fn has_input(&self) -> bool {
+        true
+    }
+    fn set_source(&mut self, source: Receiver<u8>) {
+        self.input = Some(source);
+    }
+    fn describe(&self, fdt: &mut Fdt) {
+        fdt.begin_node(&format!("serial@{:x}", UART_BASE));
+        fdt.property_str("compatible", "ns16550a");
+        fdt.property_cells("reg", &[UART_BASE, self.size()]);
+        fdt.property_u32("clock-frequency", 3_686_400);
+        fdt.property_u32("interrupts", UART_IRQ);
+        fdt.property_u32("interrupt-parent", PLIC_PHANDLE);
+        fdt.end_node();
+    }
+    fn tick(&mut self) {
+        // only poll once the guest started using the uart
+        if self.rx.is_empty() && self.ier & IER_RX_AVAILABLE != 0 {
+            self.input.get_or_insert_with(stdin_reader);
+            self.poll_rx();
+        }
+    }
+    fn irq(&self) -> Option<u32> {
+        (self.rx_interrupt() || self.thr_interrupt()).then_some(UART_IRQ)
+    }
+    // input that already arrived stays in the receive buffer
+    fn reset(&mut self) {
+        self.ier = 0;
+        self.fcr = 0;
+        self.lcr = 0;
+        self.mcr = 0;
+        self.scr = 0;
+        self.divisor = 0;
+        self.thr_empty_pending = false;
+    }
+    fn fork(&self) -> Option<Box<dyn Device>> {
+        // the fork's output is dropped unless it gets a sink of its own, only the original reads stdin
+        Some(Box::new(Uart {
+            sink: Box::new(std::io::sink()),
+            input: None,
+            rx: self.rx.clone(),
+            ..*self
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn divisor_latch() {
+        let mut uart = Uart::new();
+        uart.write(LCR, Size::Byte, LCR_DLAB as u32);
+        uart.write(RBR_THR, Size::Byte, 0x03);
+        uart.write(IER, Size::Byte, 0x01);
+        assert_eq!(uart.divisor, 0x0103);
+        assert_eq!(uart.ier, 0);
+
+        uart.write(LCR, Size::Byte, 0x03);
+        assert_eq!(uart.read(LCR, Size::Byte), 0x03);
+    }
+
+    #[test]
+    fn rx_interrupt() {
+        let mut uart = Uart::new();
+        uart.rx.push_back(b'a');
+        assert_eq!(uart.irq(), None);
+
+        uart.write(IER, Size::Byte, IER_RX_AVAILABLE as u32);
+        assert_eq!(uart.irq(), Some(UART_IRQ));
+        assert_eq!(uart.read(IIR_FCR, Size::Byte), IIR_RX_AVAILABLE as u32);
+        assert_eq!(uart.read(LSR, Size::Byte) & LSR_DATA_READY as u32, 1);
+        assert_eq!(uart.read(RBR_THR, Size::Byte), b'a' as u32);
+        assert_eq!(uart.irq(), None);
+    }
+
+    #[test]
+    fn thr_empty_interrupt() {
+        let mut uart = Uart::new();
+        uart.write(IER, Size::Byte, IER_THR_EMPTY as u32);
+        assert_eq!(uart.irq(), Some(UART_IRQ));
+        // reading IIR acknowledges the interrupt
+        assert_eq!(uart.read(IIR_FCR, Size::Byte), IIR_THR_EMPTY as u32);
+        assert_eq!(uart.irq(), None);
+        assert_eq!(uart.read(IIR_FCR, Size::Byte), IIR_NO_INTERRUPT as u32);
+    }
+}
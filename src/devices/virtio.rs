@@ -0,0 +1,442 @@
+use super::{Device, Ram, VIRTIO_BASE, VIRTIO_IRQ};
+use crate::fdt::{Fdt, PLIC_PHANDLE};
+use crate::memory::Size;
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+// registers of the virtio-mmio transport, see the virtio spec 1.1 chapter 4.2.2
+const MAGIC_VALUE: u32 = 0x000;
+const VERSION: u32 = 0x004;
+const DEVICE_ID: u32 = 0x008;
+const VENDOR_ID: u32 = 0x00c;
+const DEVICE_FEATURES: u32 = 0x010;
+const DEVICE_FEATURES_SEL: u32 = 0x014;
+const DRIVER_FEATURES: u32 = 0x020;
+const DRIVER_FEATURES_SEL: u32 = 0x024;
+const QUEUE_SEL: u32 = 0x030;
+const QUEUE_NUM_MAX: u32 = 0x034;
+const QUEUE_NUM: u32 = 0x038;
+const QUEUE_READY: u32 = 0x044;
+const QUEUE_NOTIFY: u32 = 0x050;
+const INTERRUPT_STATUS: u32 = 0x060;
+const INTERRUPT_ACK: u32 = 0x064;
+const STATUS: u32 = 0x070;
+const QUEUE_DESC_LOW: u32 = 0x080;
+const QUEUE_DESC_HIGH: u32 = 0x084;
+const QUEUE_DRIVER_LOW: u32 = 0x090;
+const QUEUE_DRIVER_HIGH: u32 = 0x094;
+const QUEUE_DEVICE_LOW: u32 = 0x0a0;
+const QUEUE_DEVICE_HIGH: u32 = 0x0a4;
+const CONFIG_GENERATION: u32 = 0x0fc;
+// the block device's configuration, only the capacity in sectors
+const CONFIG: u32 = 0x100;
+
+const MAGIC: u32 = 0x7472_6976;
+const BLOCK_DEVICE: u32 = 2;
+// "QEMU", as drivers written against qemu don't care
+const VENDOR: u32 = 0x554d_4551;
+const F_VERSION_1: u64 = 1 << 32;
+const STATUS_NEEDS_RESET: u32 = 0x40;
+const INTERRUPT_USED_BUFFER: u32 = 1;
+const QUEUE_SIZE: u32 = 256;
+
+const DESC_F_NEXT: u16 = 1;
+const DESC_F_WRITE: u16 = 2;
+
+pub const SECTOR_SIZE: u64 = 512;
+const REQ_IN: u32 = 0;
+const REQ_OUT: u32 = 1;
+const REQ_FLUSH: u32 = 4;
+const REQ_OK: u8 = 0;
+const REQ_IOERR: u8 = 1;
+const REQ_UNSUPP: u8 = 2;
+
+#[derive(Clone, Copy, Default)]
+struct Queue {
+    num: u32,
+    ready: bool,
+    // addresses of the descriptor table and the available and used rings
+    desc: u64,
+    driver: u64,
+    device: u64,
+    // index into the available ring of the next request to serve
+    last_avail: u16,
+}
+
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+// Block device on the virtio-mmio transport (version 2, as qemu's virt machine with
+// force-legacy=false), backed by a host file that writes go through to. There is a single request
+// queue, requests are served on the tick after the driver notified the device and complete
+// instantly.
+pub struct VirtioBlock {
+    disk: Vec<u8>,
+    file: File,
+    device_features_sel: u32,
+    driver_features: u64,
+    driver_features_sel: u32,
+    status: u32,
+    queue_sel: u32,
+    queue: Queue,
+    interrupt_status: u32,
+    // set by a write to the notify register until the requests are served
+    notified: bool,
+}
+
+fn set_half(value: &mut u64, high: bool, half: u32) {
+    let shift = if high { 32 } else { 0 };
+    *value = *value & !(0xffff_ffff << shift) | (half as u64) << shift;
+}
+
+fn read_u16(ram: &Ram, address: u64) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        ram.read(address, 2)?[..].try_into().unwrap(),
+    ))
+}
+
+fn read_u32(ram: &Ram, address: u64) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        ram.read(address, 4)?[..].try_into().unwrap(),
+    ))
+}
+
+fn read_u64(ram: &Ram, address: u64) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        ram.read(address, 8)?[..].try_into().unwrap(),
+    ))
+}
+
+impl VirtioBlock {
+    // The capacity is the whole sectors in the file, a partial one at its end isn't accessible.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        let mut disk = Vec::new();
+        file.read_to_end(&mut disk)?;
+        Ok(VirtioBlock {
+            disk,
+            file,
+            device_features_sel: 0,
+            driver_features: 0,
+            driver_features_sel: 0,
+            status: 0,
+            queue_sel: 0,
+            queue: Queue::default(),
+            interrupt_status: 0,
+            notified: false,
+        })
+    }
+
+    fn capacity(&self) -> u64 {
+        self.disk.len() as u64 / SECTOR_SIZE
+    }
+
+    // the driver resets the device by writing 0 to the status register
+    fn reset_transport(&mut self) {
+        self.device_features_sel = 0;
+        self.driver_features = 0;
+        self.driver_features_sel = 0;
+        self.status = 0;
+        self.queue_sel = 0;
+        self.queue = Queue::default();
+        self.interrupt_status = 0;
+        self.notified = false;
+    }
+
+    fn descriptor(&self, ram: &Ram, index: u16) -> Option<Descriptor> {
+        let address = self.queue.desc + (index as u64 % self.queue.num as u64) * 16;
+        Some(Descriptor {
+            addr: read_u64(ram, address)?,
+            len: read_u32(ram, address + 8)?,
+            flags: read_u16(ram, address + 12)?,
+            next: read_u16(ram, address + 14)?,
+        })
+    }
+
+    // copies between the disk and the data buffers of the request, returns the status and the
+    // number of bytes written to ram
+    fn transfer(
+        &mut self,
+        ram: &mut Ram,
+        kind: u32,
+        sector: u64,
+        data: &[Descriptor],
+    ) -> (u8, u32) {
+        let len = data.iter().map(|desc| desc.len as u64).sum::<u64>();
+        let start = sector.saturating_mul(SECTOR_SIZE);
+        let in_range = start
+            .checked_add(len)
+            .is_some_and(|end| end <= self.capacity() * SECTOR_SIZE);
+        match kind {
+            REQ_IN | REQ_OUT if !in_range => (REQ_IOERR, 0),
+            REQ_IN => {
+                let mut offset = start as usize;
+                for desc in data {
+                    let bytes = &self.disk[offset..offset + desc.len as usize];
+                    if desc.flags & DESC_F_WRITE == 0 || !ram.write(desc.addr, bytes) {
+                        return (REQ_IOERR, 0);
+                    }
+                    offset += desc.len as usize;
+                }
+                (REQ_OK, len as u32)
+            }
+            REQ_OUT => {
+                let mut offset = start as usize;
+                for desc in data {
+                    let Some(bytes) = ram.read(desc.addr, desc.len as usize) else {
+                        return (REQ_IOERR, 0);
+                    };
+                    self.disk[offset..offset + bytes.len()].copy_from_slice(&bytes);
+                    offset += desc.len as usize;
+                }
+                let written = self
+                    .file
+                    .seek(SeekFrom::Start(start))
+                    .and_then(|_| self.file.write_all(&self.disk[start as usize..offset]));
+                match written {
+                    Ok(()) => (REQ_OK, 0),
+                    Err(e) => {
+                        eprintln!("can't write drive file: {e}");
+                        (REQ_IOERR, 0)
+                    }
+                }
+            }
+            REQ_FLUSH => (REQ_OK, 0),
+            _ => (REQ_UNSUPP, 0),
+        }
+    }
+
+    // Serves the request whose chain starts at the head descriptor: a header with the request type
+    // and sector, the data buffers and a byte the status is written to. Returns the length of the
+    // used buffer, None if the chain or the rings can't be accessed.
+    fn serve(&mut self, ram: &mut Ram, head: u16) -> Option<u32> {
+        let mut chain = Vec::new();
+        let mut index = head;
+        loop {
+            let desc = self.descriptor(ram, index)?;
+            index = desc.next;
+            let next = desc.flags & DESC_F_NEXT != 0;
+            chain.push(desc);
+            if !next {
+                break;
+            }
+            // loops in the chain
+            if chain.len() > self.queue.num as usize {
+                return None;
+            }
+        }
+        if chain.len() < 2 {
+            return None;
+        }
+        let status = chain.pop().unwrap();
+        let header = &chain[0];
+        let kind = read_u32(ram, header.addr)?;
+        let sector = read_u64(ram, header.addr + 8)?;
+        let (result, len) = self.transfer(ram, kind, sector, &chain[1..]);
+        ram.write(status.addr, &[result]).then_some(len + 1)
+    }
+
+    fn serve_queue(&mut self, ram: &mut Ram) -> Option<()> {
+        let queue = self.queue;
+        let avail_idx = read_u16(ram, queue.driver + 2)?;
+        let mut used_idx = read_u16(ram, queue.device + 2)?;
+        while self.queue.last_avail != avail_idx {
+            let slot = (self.queue.last_avail as u64 % queue.num as u64) * 2;
+            let head = read_u16(ram, queue.driver + 4 + slot)?;
+            let len = self.serve(ram, head)?;
+            let entry = queue.device + 4 + (used_idx as u64 % queue.num as u64) * 8;
+            let mut element = (head as u32).to_le_bytes().to_vec();
+            element.extend(len.to_le_bytes());
+            used_idx = used_idx.wrapping_add(1);
+            if !ram.write(entry, &element) || !ram.write(queue.device + 2, &used_idx.to_le_bytes())
+            {
+                return None;
+            }
+            self.queue.last_avail = self.queue.last_avail.wrapping_add(1);
+            self.interrupt_status |= INTERRUPT_USED_BUFFER;
+        }
+        Some(())
+    }
+}
+
+impl Device for VirtioBlock {
+    fn base(&self) -> u32 {
+        VIRTIO_BASE
+    }
+    fn size(&self) -> u32 {
+        0x1000
+    }
+    fn read(&mut self, offset: u32, size: Size) -> u32 {
+        if offset >= CONFIG {
+            let config = self.capacity().to_le_bytes();
+            let start = ((offset - CONFIG) as usize).min(config.len());
+            let mut bytes = [0; 4];
+            let len = (size as usize).min(config.len() - start);
+            bytes[..len].copy_from_slice(&config[start..start + len]);
+            return u32::from_le_bytes(bytes);
+        }
+        let device_features = F_VERSION_1;
+        match offset {
+            MAGIC_VALUE => MAGIC,
+            VERSION => 2,
+            DEVICE_ID => BLOCK_DEVICE,
+            VENDOR_ID => VENDOR,
+            DEVICE_FEATURES => match self.device_features_sel {
+                0 => device_features as u32,
+                1 => (device_features >> 32) as u32,
+                _ => 0,
+            },
+            QUEUE_NUM_MAX if self.queue_sel == 0 => QUEUE_SIZE,
+            QUEUE_READY if self.queue_sel == 0 => self.queue.ready as u32,
+            INTERRUPT_STATUS => self.interrupt_status,
+            STATUS => self.status,
+            CONFIG_GENERATION => 0,
+            _ => 0,
+        }
+    }
+    fn write(&mut self, offset: u32, _size: Size, value: u32) {
+        let high = matches!(
+            offset,
+            QUEUE_DESC_HIGH | QUEUE_DRIVER_HIGH | QUEUE_DEVICE_HIGH
+        );
+        match offset {
+            DEVICE_FEATURES_SEL => self.device_features_sel = value,
+            DRIVER_FEATURES if self.driver_features_sel < 2 => {
+                set_half(
+                    &mut self.driver_features,
+                    self.driver_features_sel == 1,
+                    value,
+                );
+            }
+            DRIVER_FEATURES_SEL => self.driver_features_sel = value,
+            QUEUE_SEL => self.queue_sel = value,
+            // the other registers of the queue are only written to queue 0, which is the only one
+            _ if self.queue_sel != 0 && (QUEUE_NUM..=QUEUE_DEVICE_HIGH).contains(&offset) => (),
+            QUEUE_NUM if value.is_power_of_two() && value <= QUEUE_SIZE => self.queue.num = value,
+            QUEUE_READY => self.queue.ready = value & 1 != 0,
+            QUEUE_DESC_LOW | QUEUE_DESC_HIGH => set_half(&mut self.queue.desc, high, value),
+            QUEUE_DRIVER_LOW | QUEUE_DRIVER_HIGH => set_half(&mut self.queue.driver, high, value),
+            QUEUE_DEVICE_LOW | QUEUE_DEVICE_HIGH => set_half(&mut self.queue.device, high, value),
+            QUEUE_NOTIFY if value == 0 => self.notified = true,
+            INTERRUPT_ACK => self.interrupt_status &= !value,
+            STATUS if value == 0 => self.reset_transport(),
+            STATUS => self.status = value,
+            _ => (),
+        }
+    }
+    fn access_ram(&mut self, ram: &mut Ram) {
+        if !std::mem::take(&mut self.notified) || !self.queue.ready || self.queue.num == 0 {
+            return;
+        }
+        // a request the device can't access leaves it broken until the driver resets it
+        if self.serve_queue(ram).is_none() {
+            self.status |= STATUS_NEEDS_RESET;
+            self.queue.ready = false;
+        }
+    }
+    fn irq(&self) -> Option<u32> {
+        (self.interrupt_status != 0).then_some(VIRTIO_IRQ)
+    }
+    // the contents of the disk persist
+    fn reset(&mut self) {
+        self.reset_transport();
+    }
+    fn describe(&self, fdt: &mut Fdt) {
+        fdt.begin_node(&format!("virtio_mmio@{:x}", VIRTIO_BASE));
+        fdt.property_str("compatible", "virtio,mmio");
+        fdt.property_cells("reg", &[VIRTIO_BASE, self.size()]);
+        fdt.property_u32("interrupts", VIRTIO_IRQ);
+        fdt.property_u32("interrupt-parent", PLIC_PHANDLE);
+        fdt.end_node();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::Memory;
+
+    const DESC: u32 = 0x1000;
+    const AVAIL: u32 = 0x2000;
+    const USED: u32 = 0x3000;
+    const HEADER: u32 = 0x4000;
+    const BUFFER: u32 = 0x5000;
+    const STATUS_BYTE: u32 = 0x6000;
+
+    fn store(mem: &mut Memory, offset: u32, value: u32) {
+        mem.store(Size::Word, VIRTIO_BASE + offset, value).unwrap();
+    }
+
+    fn descriptor(mem: &mut Memory, index: u32, addr: u32, len: u32, flags: u16, next: u16) {
+        let desc = DESC + index * 16;
+        mem.write(Size::Word, desc, addr);
+        mem.write(Size::Word, desc + 4, 0);
+        mem.write(Size::Word, desc + 8, len);
+        mem.write(Size::HalfWord, desc + 12, flags as u32);
+        mem.write(Size::HalfWord, desc + 14, next as u32);
+    }
+
+    // submits a request of the kind for the sector with a buffer of one sector at BUFFER
+    fn request(mem: &mut Memory, index: u16, kind: u32, sector: u32) {
+        mem.write(Size::Word, HEADER, kind);
+        mem.write(Size::Word, HEADER + 8, sector);
+        descriptor(mem, 0, HEADER, 16, DESC_F_NEXT, 1);
+        let buffer_flags = if kind == REQ_IN { DESC_F_WRITE } else { 0 };
+        descriptor(mem, 1, BUFFER, 512, DESC_F_NEXT | buffer_flags, 2);
+        descriptor(mem, 2, STATUS_BYTE, 1, DESC_F_WRITE, 0);
+        mem.write(Size::HalfWord, AVAIL + 4 + index as u32 % 8 * 2, 0);
+        mem.write(Size::HalfWord, AVAIL + 2, index as u32 + 1);
+        store(mem, QUEUE_NOTIFY, 0);
+        mem.tick();
+    }
+
+    #[test]
+    fn read_and_write_sectors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("disk.img");
+        let mut image = vec![0; 4 * 512];
+        image[512..517].copy_from_slice(b"xv6fs");
+        std::fs::write(&path, &image).unwrap();
+
+        let mut mem = Memory::with_layout(0, 0x10000);
+        mem.add_device(Box::new(VirtioBlock::open(&path).unwrap()));
+        let load = |mem: &mut Memory, offset| mem.load(Size::Word, VIRTIO_BASE + offset, true);
+        assert_eq!(load(&mut mem, MAGIC_VALUE), Ok(MAGIC));
+        assert_eq!(load(&mut mem, DEVICE_ID), Ok(BLOCK_DEVICE));
+        assert_eq!(load(&mut mem, CONFIG), Ok(4));
+        store(&mut mem, DEVICE_FEATURES_SEL, 1);
+        assert_eq!(load(&mut mem, DEVICE_FEATURES), Ok(1));
+        store(&mut mem, QUEUE_NUM, 8);
+        store(&mut mem, QUEUE_DESC_LOW, DESC);
+        store(&mut mem, QUEUE_DRIVER_LOW, AVAIL);
+        store(&mut mem, QUEUE_DEVICE_LOW, USED);
+        store(&mut mem, QUEUE_READY, 1);
+
+        request(&mut mem, 0, REQ_IN, 1);
+        assert_eq!(&mem.peek(BUFFER, 5)[..], b"xv6fs");
+        assert_eq!(mem.peek(STATUS_BYTE, 1)[0], REQ_OK);
+        assert_eq!(mem.read(Size::HalfWord, USED + 2, true), 1);
+        assert_eq!(mem.read(Size::Word, USED + 8, true), 513);
+        assert_eq!(load(&mut mem, INTERRUPT_STATUS), Ok(1));
+        store(&mut mem, INTERRUPT_ACK, 1);
+        assert_eq!(load(&mut mem, INTERRUPT_STATUS), Ok(0));
+
+        mem.write_bytes(BUFFER, b"written");
+        request(&mut mem, 1, REQ_OUT, 3);
+        assert_eq!(mem.peek(STATUS_BYTE, 1)[0], REQ_OK);
+        assert_eq!(
+            &std::fs::read(&path).unwrap()[3 * 512..3 * 512 + 7],
+            b"written"
+        );
+
+        request(&mut mem, 2, REQ_IN, 4);
+        assert_eq!(mem.peek(STATUS_BYTE, 1)[0], REQ_IOERR);
+        assert_eq!(mem.read(Size::HalfWord, USED + 2, true), 3);
+    }
+}
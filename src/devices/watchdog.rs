@@ -0,0 +1,111 @@
+use super::{Device, WATCHDOG_BASE};
+use crate::memory::Size;
+
+// writing a number of cycles arms the watchdog, 0 disarms it
+const TIMEOUT: u32 = 0x0;
+// any write restarts the countdown
+const FEED: u32 = 0x4;
+// reads 1 if the watchdog caused the last reset, so firmware can tell it from a power-on
+const CAUSE: u32 = 0x8;
+
+// Resets the machine unless the program feeds it within the armed number of cycles. The
+// timeout and countdown start over at the reset, only the cause survives it.
+#[derive(Clone)]
+pub struct Watchdog {
+    timeout: u32,
+    remaining: u64,
+    expired: bool,
+    caused_reset: bool,
+}
+
+impl Watchdog {
+    pub fn new() -> Self {
+        Watchdog {
+            timeout: 0,
+            remaining: 0,
+            expired: false,
+            caused_reset: false,
+        }
+    }
+
+    fn armed(&self) -> bool {
+        self.timeout != 0
+    }
+}
+
+impl Device for Watchdog {
+    fn base(&self) -> u32 {
+        WATCHDOG_BASE
+    }
+    fn size(&self) -> u32 {
+        0x1000
+    }
+    fn read(&mut self, offset: u32, _size: Size) -> u32 {
+        match offset {
+            TIMEOUT => self.timeout,
+            CAUSE => self.caused_reset as u32,
+            _ => 0,
+        }
+    }
+    fn write(&mut self, offset: u32, _size: Size, value: u32) {
+        match offset {
+            TIMEOUT => {
+                self.timeout = value;
+                self.remaining = value as u64;
+            }
+            FEED => self.remaining = self.timeout as u64,
+            _ => (),
+        }
+    }
+    fn tick(&mut self) {
+        self.skip(1);
+    }
+    fn next_event(&self) -> Option<u64> {
+        self.armed().then_some(self.remaining)
+    }
+    fn skip(&mut self, cycles: u64) {
+        if self.armed() {
+            self.remaining = self.remaining.saturating_sub(cycles);
+            self.expired |= self.remaining == 0;
+        }
+    }
+    fn take_reset(&mut self) -> bool {
+        std::mem::take(&mut self.expired)
+    }
+    fn reset(&mut self) {
+        let caused_reset = self.expired || self.remaining == 0 && self.armed();
+        *self = Watchdog::new();
+        self.caused_reset = caused_reset;
+    }
+    fn fork(&self) -> Option<Box<dyn Device>> {
+        Some(Box::new(self.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expires_unless_fed() {
+        let mut watchdog = Watchdog::new();
+        watchdog.skip(1000);
+        assert!(!watchdog.take_reset());
+
+        watchdog.write(TIMEOUT, Size::Word, 100);
+        assert_eq!(watchdog.next_event(), Some(100));
+        watchdog.skip(60);
+        watchdog.write(FEED, Size::Word, 0);
+        watchdog.skip(60);
+        assert!(!watchdog.take_reset());
+        watchdog.skip(40);
+        assert!(watchdog.take_reset());
+        assert!(!watchdog.take_reset());
+
+        watchdog.reset();
+        assert_eq!(watchdog.read(CAUSE, Size::Word), 1);
+        assert_eq!(watchdog.read(TIMEOUT, Size::Word), 0);
+        watchdog.reset();
+        assert_eq!(watchdog.read(CAUSE, Size::Word), 0);
+    }
+}
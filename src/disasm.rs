@@ -0,0 +1,533 @@
+// Disassembly in the syntax of the gnu assembler without pseudo-instructions, registers are
+// printed with their ABI names. Branch and jump targets are printed as offsets to the pc.
+use crate::decode::decode;
+use crate::error::Error;
+use crate::get_bits;
+use crate::inst::*;
+use crate::regs::Reg;
+use crate::vector::{Avl, Operand, VCmp, VInst, VOp, Vtype};
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+fn x(reg: usize) -> &'static str {
+    Reg::from_index(reg).name()
+}
+
+fn r_name(inst: &RInst) -> &'static str {
+    match inst {
+        RInst::ADD => "add",
+        RInst::SUB => "sub",
+        RInst::XOR => "xor",
+        RInst::OR => "or",
+        RInst::AND => "and",
+        RInst::SLL => "sll",
+        RInst::SRL => "srl",
+        RInst::SRA => "sra",
+        RInst::SLT => "slt",
+        RInst::SLTU => "sltu",
+        RInst::MUL => "mul",
+        RInst::MULH => "mulh",
+        RInst::MULHSU => "mulhsu",
+        RInst::MULHU => "mulhu",
+        RInst::DIV => "div",
+        RInst::DIVU => "divu",
+        RInst::REM => "rem",
+        RInst::REMU => "remu",
+        RInst::CZEROEQZ => "czero.eqz",
+        RInst::CZERONEZ => "czero.nez",
+        RInst::ANDN => "andn",
+        RInst::ORN => "orn",
+        RInst::XNOR => "xnor",
+        RInst::ROL => "rol",
+        RInst::ROR => "ror",
+        RInst::PACK => "pack",
+        RInst::PACKH => "packh",
+        RInst::XPERM4 => "xperm4",
+        RInst::XPERM8 => "xperm8",
+        RInst::BREV8 => "brev8",
+        RInst::REV8 => "rev8",
+        RInst::ZIP => "zip",
+        RInst::UNZIP => "unzip",
+    }
+}
+
+fn i_name(inst: &ArithIInst) -> &'static str {
+    match inst {
+        ArithIInst::ADDI => "addi",
+        ArithIInst::XORI => "xori",
+        ArithIInst::ORI => "ori",
+        ArithIInst::ANDI => "andi",
+        ArithIInst::SLLI => "slli",
+        ArithIInst::SRLI => "srli",
+        ArithIInst::SRAI => "srai",
+        ArithIInst::SLTI => "slti",
+        ArithIInst::SLTIU => "sltiu",
+        ArithIInst::RORI => "rori",
+        ArithIInst::BREV8 => "brev8",
+        ArithIInst::REV8 => "rev8",
+        ArithIInst::ZIP => "zip",
+        ArithIInst::UNZIP => "unzip",
+    }
+}
+
+fn load_name(inst: &LoadIInst) -> &'static str {
+    match inst {
+        LoadIInst::LB => "lb",
+        LoadIInst::LH => "lh",
+        LoadIInst::LW => "lw",
+        LoadIInst::LBU => "lbu",
+        LoadIInst::LHU => "lhu",
+    }
+}
+
+fn amo_name(inst: &AmoInst) -> &'static str {
+    match inst {
+        AmoInst::LR => "lr.w",
+        AmoInst::SC => "sc.w",
+        AmoInst::SWAP => "amoswap.w",
+        AmoInst::ADD => "amoadd.w",
+        AmoInst::XOR => "amoxor.w",
+        AmoInst::AND => "amoand.w",
+        AmoInst::OR => "amoor.w",
+        AmoInst::MIN => "amomin.w",
+        AmoInst::MAX => "amomax.w",
+        AmoInst::MINU => "amominu.w",
+        AmoInst::MAXU => "amomaxu.w",
+    }
+}
+
+fn vop_name(op: &VOp) -> &'static str {
+    match op {
+        VOp::Add => "vadd",
+        VOp::Sub => "vsub",
+        VOp::Rsub => "vrsub",
+        VOp::Minu => "vminu",
+        VOp::Min => "vmin",
+        VOp::Maxu => "vmaxu",
+        VOp::Max => "vmax",
+        VOp::And => "vand",
+        VOp::Or => "vor",
+        VOp::Xor => "vxor",
+        VOp::Sll => "vsll",
+        VOp::Srl => "vsrl",
+        VOp::Sra => "vsra",
+        VOp::Mul => "vmul",
+        VOp::Mulh => "vmulh",
+        VOp::Mulhu => "vmulhu",
+        VOp::Mulhsu => "vmulhsu",
+        VOp::Divu => "vdivu",
+        VOp::Div => "vdiv",
+        VOp::Remu => "vremu",
+        VOp::Rem => "vrem",
+    }
+}
+
+fn vcmp_name(cmp: &VCmp) -> &'static str {
+    match cmp {
+        VCmp::Eq => "vmseq",
+        VCmp::Ne => "vmsne",
+        VCmp::Ltu => "vmsltu",
+        VCmp::Lt => "vmslt",
+        VCmp::Leu => "vmsleu",
+        VCmp::Le => "vmsle",
+        VCmp::Gtu => "vmsgtu",
+        VCmp::Gt => "vmsgt",
+    }
+}
+
+// operand suffix (.vv, .vx, .vi) and the operand itself
+fn operand(src: &Operand) -> (&'static str, String) {
+    match *src {
+        Operand::Vector(vs1) => ("v", format!("v{vs1}")),
+        Operand::Scalar(rs1) => ("x", x(rs1).to_string()),
+        Operand::Imm(imm) => ("i", (imm as i32).to_string()),
+    }
+}
+
+// unmasked instructions have the vm bit set
+fn mask(vm: bool) -> &'static str {
+    if vm {
+        ""
+    } else {
+        ", v0.t"
+    }
+}
+
+impl fmt::Display for VInst {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VInst::SetVl { rd, avl, vtype } => match (avl, vtype) {
+                (Avl::Imm(avl), Vtype::Imm(vtype)) => {
+                    write!(f, "vsetivli {}, {avl}, {vtype:#x}", x(*rd))
+                }
+                (Avl::Reg(rs1), Vtype::Imm(vtype)) => {
+                    write!(f, "vsetvli {}, {}, {vtype:#x}", x(*rd), x(*rs1))
+                }
+                (Avl::Reg(rs1), Vtype::Reg(rs2)) => {
+                    write!(f, "vsetvl {}, {}, {}", x(*rd), x(*rs1), x(*rs2))
+                }
+                (Avl::Imm(avl), Vtype::Reg(rs2)) => {
+                    write!(f, "vsetvl {}, {avl}, {}", x(*rd), x(*rs2))
+                }
+            },
+            VInst::Load {
+                vd,
+                rs1,
+                stride,
+                eew,
+                vm,
+            } => match stride {
+                Some(rs2) => write!(
+                    f,
+                    "vlse{}.v v{vd}, ({}), {}{}",
+                    eew * 8,
+                    x(*rs1),
+                    x(*rs2),
+                    mask(*vm)
+                ),
+                None => write!(f, "vle{}.v v{vd}, ({}){}", eew * 8, x(*rs1), mask(*vm)),
+            },
+            VInst::Store {
+                vs3,
+                rs1,
+                stride,
+                eew,
+                vm,
+            } => match stride {
+                Some(rs2) => write!(
+                    f,
+                    "vsse{}.v v{vs3}, ({}), {}{}",
+                    eew * 8,
+                    x(*rs1),
+                    x(*rs2),
+                    mask(*vm)
+                ),
+                None => write!(f, "vse{}.v v{vs3}, ({}){}", eew * 8, x(*rs1), mask(*vm)),
+            },
+            VInst::Op {
+                op,
+                vd,
+                vs2,
+                src,
+                vm,
+            } => {
+                let (kind, src) = operand(src);
+                write!(
+                    f,
+                    "{}.v{kind} v{vd}, v{vs2}, {src}{}",
+                    vop_name(op),
+                    mask(*vm)
+                )
+            }
+            VInst::Cmp {
+                cmp,
+                vd,
+                vs2,
+                src,
+                vm,
+            } => {
+                let (kind, src) = operand(src);
+                write!(
+                    f,
+                    "{}.v{kind} v{vd}, v{vs2}, {src}{}",
+                    vcmp_name(cmp),
+                    mask(*vm)
+                )
+            }
+            VInst::Merge { vd, vs2, src, vm } => {
+                let (kind, src) = operand(src);
+                if *vm {
+                    write!(f, "vmv.v.{kind} v{vd}, {src}")
+                } else {
+                    write!(f, "vmerge.v{kind}m v{vd}, v{vs2}, {src}, v0")
+                }
+            }
+            VInst::RedSum { vd, vs2, vs1, vm } => {
+                write!(f, "vredsum.vs v{vd}, v{vs2}, v{vs1}{}", mask(*vm))
+            }
+            VInst::MvXS { rd, vs2 } => write!(f, "vmv.x.s {}, v{vs2}", x(*rd)),
+            VInst::MvSX { vd, rs1 } => write!(f, "vmv.s.x v{vd}, {}", x(*rs1)),
+        }
+    }
+}
+
+impl fmt::Display for Inst {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Inst::R(inst, format) => write!(
+                f,
+                "{} {}, {}, {}",
+                r_name(inst),
+                x(format.rd),
+                x(format.rs1),
+                x(format.rs2)
+            ),
+            Inst::I(IInst::Arith(inst), format) => {
+                let (rd, rs1) = (x(format.rd), x(format.rs1));
+                match inst {
+                    ArithIInst::BREV8 | ArithIInst::REV8 | ArithIInst::ZIP | ArithIInst::UNZIP => {
+                        write!(f, "{} {rd}, {rs1}", i_name(inst))
+                    }
+                    ArithIInst::SLLI | ArithIInst::SRLI | ArithIInst::SRAI | ArithIInst::RORI => {
+                        write!(f, "{} {rd}, {rs1}, {}", i_name(inst), format.imm & 0x1f)
+                    }
+                    _ => write!(f, "{} {rd}, {rs1}, {}", i_name(inst), format.imm as i32),
+                }
+            }
+            Inst::I(IInst::Mem(inst), format) => write!(
+                f,
+                "{} {}, {}({})",
+                load_name(inst),
+                x(format.rd),
+                format.imm as i32,
+                x(format.rs1)
+            ),
+            Inst::I(IInst::Jalr, format) => write!(
+                f,
+                "jalr {}, {}({})",
+                x(format.rd),
+                format.imm as i32,
+                x(format.rs1)
+            ),
+            Inst::S(inst, format) => {
+                let name = match inst {
+                    SInst::SB => "sb",
+                    SInst::SH => "sh",
+                    SInst::SW => "sw",
+                };
+                write!(
+                    f,
+                    "{name} {}, {}({})",
+                    x(format.rs2),
+                    format.imm as i32,
+                    x(format.rs1)
+                )
+            }
+            Inst::B(inst, format) => {
+                let name = match inst {
+                    BInst::BEQ => "beq",
+                    BInst::BNE => "bne",
+                    BInst::BLT => "blt",
+                    BInst::BGE => "bge",
+                    BInst::BLTU => "bltu",
+                    BInst::BGEU => "bgeu",
+                };
+                write!(
+                    f,
+                    "{name} {}, {}, {}",
+                    x(format.rs1),
+                    x(format.rs2),
+                    format.imm as i32
+                )
+            }
+            Inst::J(format) => write!(f, "jal {}, {}", x(format.rd), format.imm as i32),
+            Inst::U(inst, format) => {
+                let name = match inst {
+                    UInst::LUI => "lui",
+                    UInst::AUIPC => "auipc",
+                };
+                write!(f, "{name} {}, {:#x}", x(format.rd), format.imm & 0xfffff)
+            }
+            Inst::Csr(inst, format) => {
+                let csr = get_bits!(format.imm, 0, 11);
+                let (name, src) = match inst {
+                    CsrInst::CSRRW => ("csrrw", x(format.rs1).to_string()),
+                    CsrInst::CSRRS => ("csrrs", x(format.rs1).to_string()),
+                    CsrInst::CSRRC => ("csrrc", x(format.rs1).to_string()),
+                    CsrInst::CSRRWI => ("csrrwi", format.rs1.to_string()),
+                    CsrInst::CSRRSI => ("csrrsi", format.rs1.to_string()),
+                    CsrInst::CSRRCI => ("csrrci", format.rs1.to_string()),
+                };
+                write!(f, "{name} {}, {csr:#x}, {src}", x(format.rd))
+            }
+            Inst::Amo(inst, format) => {
+                let ordering = match format.funct7 & 0b11 {
+                    0b10 => ".aq",
+                    0b01 => ".rl",
+                    0b11 => ".aqrl",
+                    _ => "",
+                };
+                let (rd, rs1, rs2) = (x(format.rd), x(format.rs1), x(format.rs2));
+                match inst {
+                    AmoInst::LR => write!(f, "{}{ordering} {rd}, ({rs1})", amo_name(inst)),
+                    _ => write!(f, "{}{ordering} {rd}, {rs2}, ({rs1})", amo_name(inst)),
+                }
+            }
+            Inst::Aes(inst, format) => {
+                let name = match inst {
+                    AesInst::ESI => "aes32esi",
+                    AesInst::ESMI => "aes32esmi",
+                    AesInst::DSI => "aes32dsi",
+                    AesInst::DSMI => "aes32dsmi",
+                };
+                write!(
+                    f,
+                    "{name} {}, {}, {}, {}",
+                    x(format.rd),
+                    x(format.rs1),
+                    x(format.rs2),
+                    format.funct7 >> 5
+                )
+            }
+            Inst::Vector(inst) => write!(f, "{inst}"),
+            Inst::Mret => write!(f, "mret"),
+            Inst::Sret => write!(f, "sret"),
+            Inst::Wfi => write!(f, "wfi"),
+            Inst::SfenceVma(format) if format.rs1 == 0 && format.rs2 == 0 => {
+                write!(f, "sfence.vma")
+            }
+            Inst::SfenceVma(format) => {
+                write!(f, "sfence.vma {}, {}", x(format.rs1), x(format.rs2))
+            }
+            Inst::Ebreak => write!(f, "ebreak"),
+            Inst::Ecall => write!(f, "ecall"),
+            Inst::Fence => write!(f, "fence"),
+        }
+    }
+}
+
+// Disassembles an image loaded at `base` by linear sweep, yielding the address, the raw bits, the
+// decoded instruction and its disassembly for every instruction. Words that don't decode are
+// printed as `.word`. The C extension isn't implemented, so every instruction is 4 bytes and a
+// trailing partial word is skipped.
+pub fn iter(
+    bytes: &[u8],
+    base: u32,
+) -> impl Iterator<Item = (u32, u32, Result<Inst, Error>, String)> + '_ {
+    bytes.chunks_exact(4).enumerate().map(move |(i, word)| {
+        let address = base.wrapping_add(4 * i as u32);
+        let raw_inst = u32::from_le_bytes(word.try_into().unwrap());
+        let inst = decode(raw_inst);
+        let text = match &inst {
+            Ok(inst) => inst.to_string(),
+            Err(_) => format!(".word {raw_inst:#010x}"),
+        };
+        (address, raw_inst, inst, text)
+    })
+}
+
+// absolute target of a branch or jal at pc
+pub fn target(inst: &Inst, pc: u32) -> Option<u32> {
+    match inst {
+        Inst::B(_, format) => Some(pc.wrapping_add(format.imm)),
+        Inst::J(format) => Some(pc.wrapping_add(format.imm)),
+        _ => None,
+    }
+}
+
+// Addresses execution continues at after the instruction at pc, calls are assumed to return.
+// Indirect jumps (like returns), mret and sret end the path, their target isn't known statically.
+fn successors(inst: &Inst, pc: u32) -> [Option<u32>; 2] {
+    let next = Some(pc.wrapping_add(4));
+    match inst {
+        Inst::J(format) if format.rd == 0 => [target(inst, pc), None],
+        Inst::B(..) | Inst::J(_) => [next, target(inst, pc)],
+        Inst::I(IInst::Jalr, format) if format.rd == 0 => [None, None],
+        Inst::Mret | Inst::Sret => [None, None],
+        _ => [next, None],
+    }
+}
+
+// Follows the control flow from the entry points through branches, jumps and calls, so that
+// literal pools and padding between functions aren't mistaken for code. Returns the addresses of
+// all reachable instructions inside the image loaded at `base`.
+pub fn reachable(bytes: &[u8], base: u32, entries: &[u32]) -> BTreeSet<u32> {
+    let mut reached = BTreeSet::new();
+    let mut pending = entries.to_vec();
+    while let Some(pc) = pending.pop() {
+        let offset = pc.wrapping_sub(base) as usize;
+        let Some(word) = bytes
+            .get(offset..offset + 4)
+            .filter(|_| offset.is_multiple_of(4))
+        else {
+            continue;
+        };
+        if reached.contains(&pc) {
+            continue;
+        }
+        let Ok(inst) = decode(u32::from_le_bytes(word.try_into().unwrap())) else {
+            continue;
+        };
+        reached.insert(pc);
+        pending.extend(successors(&inst, pc).into_iter().flatten());
+    }
+    reached
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn disasm(raw_inst: u32) -> String {
+        decode(raw_inst).unwrap().to_string()
+    }
+
+    #[test]
+    fn scalar() {
+        assert_eq!(disasm(0x02000293), "addi t0, zero, 32");
+        assert_eq!(disasm(0x00c58533), "add a0, a1, a2");
+        assert_eq!(disasm(0xffc12503), "lw a0, -4(sp)");
+        assert_eq!(disasm(0x00b12423), "sw a1, 8(sp)");
+        assert_eq!(disasm(0xfeb50ce3), "beq a0, a1, -8");
+        assert_eq!(disasm(0x123452b7), "lui t0, 0x12345");
+        assert_eq!(disasm(0x30029573), "csrrw a0, 0x300, t0");
+        assert_eq!(disasm(0x0cc5a52f), "amoswap.w.aq a0, a2, (a1)");
+        assert_eq!(disasm(0x00000073), "ecall");
+    }
+
+    #[test]
+    fn vector() {
+        assert_eq!(disasm(0x0d0572d7), "vsetvli t0, a0, 0xd0");
+        assert_eq!(disasm(0x02056087), "vle32.v v1, (a0)");
+        assert_eq!(disasm(0x022180d7), "vadd.vv v1, v2, v3");
+        assert_eq!(disasm(0x0220b0d7), "vadd.vi v1, v2, 1");
+    }
+
+    #[test]
+    fn image() {
+        let bytes: Vec<u8> = [0x02000293u32, 0xffffffff, 0x00000073]
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .chain([0x13])
+            .collect();
+        let listing: Vec<_> = iter(&bytes, 0x8000_0000)
+            .map(|(address, raw_inst, inst, text)| (address, raw_inst, inst.is_ok(), text))
+            .collect();
+        assert_eq!(
+            listing,
+            [
+                (
+                    0x8000_0000,
+                    0x02000293,
+                    true,
+                    "addi t0, zero, 32".to_string()
+                ),
+                (
+                    0x8000_0004,
+                    0xffffffff,
+                    false,
+                    ".word 0xffffffff".to_string()
+                ),
+                (0x8000_0008, 0x00000073, true, "ecall".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn follows_control_flow() {
+        let bytes: Vec<u8> = [
+            0x00c000efu32, // jal ra, 12
+            0x0080006f,    // jal zero, 8
+            0x12345678,    // literal
+            0x00000463,    // beq zero, zero, 8
+            0x00000013,    // addi zero, zero, 0
+            0x00008067,    // jalr zero, 0(ra)
+        ]
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .collect();
+        let reached: Vec<_> = reachable(&bytes, 0x100, &[0x100]).into_iter().collect();
+        assert_eq!(reached, [0x100, 0x104, 0x10c, 0x110, 0x114]);
+    }
+}
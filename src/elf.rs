@@ -0,0 +1,260 @@
+// Minimal loader for statically linked 32-bit little-endian risc-v ELF executables,
+// see the System V ABI (chapter 4 and 5) and the risc-v ELF psABI.
+use crate::error::Error;
+
+use std::ops::Range;
+
+pub const ELF_MAGIC: [u8; 4] = *b"\x7fELF";
+
+const ELFCLASS32: u8 = 1;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_RISCV: u16 = 243;
+
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const STT_FUNC: u8 = 2;
+
+// a loadable segment, the memory past the file contents is zero-filled
+pub struct Segment {
+    pub address: u32,
+    pub data: Vec<u8>,
+    pub mem_size: u32,
+    // mapped executable, i.e. holds code
+    pub executable: bool,
+}
+
+struct Symbol {
+    name: String,
+    value: u32,
+    // size of the function or object, 0 if unknown
+    size: u32,
+    func: bool,
+}
+
+pub struct Elf {
+    pub entry: u32,
+    pub segments: Vec<Segment>,
+    symbols: Vec<Symbol>,
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> Result<u16, Error> {
+    bytes
+        .get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes(b.try_into().unwrap()))
+        .ok_or(Error::InvalidElf("truncated file"))
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Result<u32, Error> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+        .ok_or(Error::InvalidElf("truncated file"))
+}
+
+fn slice(bytes: &[u8], offset: u32, len: u32) -> Result<&[u8], Error> {
+    bytes
+        .get(offset as usize..offset as usize + len as usize)
+        .ok_or(Error::InvalidElf("section or segment outside of file"))
+}
+
+impl Elf {
+    pub fn is_elf(bytes: &[u8]) -> bool {
+        bytes.starts_with(&ELF_MAGIC)
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        if !Elf::is_elf(bytes) {
+            return Err(Error::InvalidElf("missing elf magic"));
+        }
+        if bytes.get(4) != Some(&ELFCLASS32) || bytes.get(5) != Some(&ELFDATA2LSB) {
+            return Err(Error::InvalidElf("not a 32-bit little-endian elf"));
+        }
+        if u16_at(bytes, 16)? != ET_EXEC || u16_at(bytes, 18)? != EM_RISCV {
+            return Err(Error::InvalidElf("not a risc-v executable"));
+        }
+        let entry = u32_at(bytes, 24)?;
+
+        let phoff = u32_at(bytes, 28)? as usize;
+        let phentsize = u16_at(bytes, 42)? as usize;
+        let mut segments = Vec::new();
+        for i in 0..u16_at(bytes, 44)? as usize {
+            let header = phoff + i * phentsize;
+            if u32_at(bytes, header)? != PT_LOAD {
+                continue;
+            }
+            let offset = u32_at(bytes, header + 4)?;
+            let address = u32_at(bytes, header + 12)?;
+            let file_size = u32_at(bytes, header + 16)?;
+            let mem_size = u32_at(bytes, header + 20)?;
+            let flags = u32_at(bytes, header + 24)?;
+            segments.push(Segment {
+                address,
+                data: slice(bytes, offset, file_size)?.to_vec(),
+                mem_size,
+                executable: flags & PF_X != 0,
+            });
+        }
+
+        Ok(Elf {
+            entry,
+            segments,
+            symbols: Elf::parse_symbols(bytes)?,
+        })
+    }
+
+    // reads the names, values and sizes of all symbols, stripped binaries just have none
+    fn parse_symbols(bytes: &[u8]) -> Result<Vec<Symbol>, Error> {
+        let shoff = u32_at(bytes, 32)? as usize;
+        let shentsize = u16_at(bytes, 46)? as usize;
+        let section = |i: usize| shoff + i * shentsize;
+        let mut symbols = Vec::new();
+        for i in 0..u16_at(bytes, 48)? as usize {
+            if u32_at(bytes, section(i) + 4)? != SHT_SYMTAB {
+                continue;
+            }
+            let table = slice(
+                bytes,
+                u32_at(bytes, section(i) + 16)?,
+                u32_at(bytes, section(i) + 20)?,
+            )?;
+            // sh_link is the string table holding the symbol names
+            let strtab = section(u32_at(bytes, section(i) + 24)? as usize);
+            let names = slice(
+                bytes,
+                u32_at(bytes, strtab + 16)?,
+                u32_at(bytes, strtab + 20)?,
+            )?;
+            for symbol in table.chunks_exact(16) {
+                let name = &names[(u32_at(symbol, 0)? as usize).min(names.len())..];
+                let name = name.split(|&b| b == 0).next().unwrap_or_default();
+                symbols.push(Symbol {
+                    name: String::from_utf8_lossy(name).into_owned(),
+                    value: u32_at(symbol, 4)?,
+                    size: u32_at(symbol, 8)?,
+                    func: symbol[12] & 0xf == STT_FUNC,
+                });
+            }
+        }
+        Ok(symbols)
+    }
+
+    fn find(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|symbol| symbol.name == name)
+    }
+
+    // names and values of all symbols
+    pub fn symbols(&self) -> impl Iterator<Item = (&str, u32)> {
+        self.symbols
+            .iter()
+            .map(|symbol| (symbol.name.as_str(), symbol.value))
+    }
+
+    // entry points of all functions in the symbol table
+    pub fn functions(&self) -> impl Iterator<Item = u32> + '_ {
+        self.symbols
+            .iter()
+            .filter(|symbol| symbol.func)
+            .map(|symbol| symbol.value)
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<u32> {
+        self.find(name).map(|symbol| symbol.value)
+    }
+
+    // addresses covered by the symbol, symbols without a size cover a single instruction
+    pub fn symbol_range(&self, name: &str) -> Option<Range<u32>> {
+        self.find(name)
+            .map(|symbol| symbol.value..symbol.value.saturating_add(symbol.size.max(4)))
+    }
+}
+
+// Builds an executable with a single segment holding `code` at `address` and a symbol table
+// containing `tohost`, the segment is 4 bytes larger than the code to test zero-filling.
+#[cfg(test)]
+pub fn build_test_elf(address: u32, code: &[u32], tohost: u32) -> Vec<u8> {
+    let words = |words: &[u32]| {
+        words
+            .iter()
+            .flat_map(|w| w.to_le_bytes())
+            .collect::<Vec<_>>()
+    };
+    let code_offset = 52 + 32;
+    let code_size = code.len() as u32 * 4;
+    let symtab_offset = code_offset + code_size;
+    let strtab_offset = symtab_offset + 32;
+    let shoff = strtab_offset + 8;
+
+    let mut elf = vec![0; 52];
+    elf[..4].copy_from_slice(&ELF_MAGIC);
+    elf[4] = ELFCLASS32;
+    elf[5] = ELFDATA2LSB;
+    elf[16..18].copy_from_slice(&ET_EXEC.to_le_bytes());
+    elf[18..20].copy_from_slice(&EM_RISCV.to_le_bytes());
+    elf[24..28].copy_from_slice(&address.to_le_bytes());
+    elf[28..32].copy_from_slice(&52u32.to_le_bytes());
+    elf[32..36].copy_from_slice(&shoff.to_le_bytes());
+    elf[42..44].copy_from_slice(&32u16.to_le_bytes());
+    elf[44..46].copy_from_slice(&1u16.to_le_bytes());
+    elf[46..48].copy_from_slice(&40u16.to_le_bytes());
+    elf[48..50].copy_from_slice(&3u16.to_le_bytes());
+    let size = code_size + 4;
+    elf.extend(words(&[
+        PT_LOAD,
+        code_offset,
+        address,
+        address,
+        code_size,
+        size,
+        PF_X,
+        0,
+    ]));
+    elf.extend(words(code));
+    // the null symbol and tohost
+    elf.extend(words(&[0, 0, 0, 0, 1, tohost, 8, 0]));
+    elf.extend(b"\0tohost\0");
+    // section headers: null, symtab, strtab
+    elf.extend(words(&[0; 10]));
+    elf.extend(words(&[
+        0,
+        SHT_SYMTAB,
+        0,
+        0,
+        symtab_offset,
+        32,
+        2,
+        0,
+        4,
+        16,
+    ]));
+    elf.extend(words(&[0, 3, 0, 0, strtab_offset, 8, 0, 0, 1, 0]));
+    elf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_executable() {
+        let elf = Elf::parse(&build_test_elf(0x8000_0000, &[0x13], 0x8000_1000)).unwrap();
+        assert_eq!(elf.entry, 0x8000_0000);
+        assert_eq!(elf.segments.len(), 1);
+        assert_eq!(elf.segments[0].address, 0x8000_0000);
+        assert_eq!(elf.segments[0].data, 0x13u32.to_le_bytes());
+        assert_eq!(elf.segments[0].mem_size, 8);
+        assert!(elf.segments[0].executable);
+        assert_eq!(elf.symbol("tohost"), Some(0x8000_1000));
+        assert_eq!(elf.symbol("fromhost"), None);
+        assert_eq!(elf.symbol_range("tohost"), Some(0x8000_1000..0x8000_1008));
+    }
+
+    #[test]
+    fn reject_non_riscv() {
+        let mut elf = build_test_elf(0x8000_0000, &[0x13], 0x8000_1000);
+        elf[18] = 62; // x86-64
+        assert!(matches!(Elf::parse(&elf), Err(Error::InvalidElf(_))));
+        assert!(matches!(Elf::parse(b"\x7fEL"), Err(Error::InvalidElf(_))));
+    }
+}
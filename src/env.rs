@@ -0,0 +1,65 @@
+// Execution environments, which give ecalls their meaning. Each environment recognizes its own
+// exit call, so programs written against another ecall abi aren't stopped by a stray a7 == 93.
+use crate::syscall::*;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Env {
+    // no environment, every ecall goes to the program's trap handler
+    Bare,
+    // the syscalls made by newlib's libgloss port
+    Newlib,
+    // every emulated linux syscall
+    Linux,
+    // sbi calls of a supervisor-mode kernel
+    Sbi,
+    // the riscv-tests environment: ecalls go to the trap handler, which exits through tohost
+    Htif,
+}
+
+impl Env {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "bare" => Some(Env::Bare),
+            "newlib" => Some(Env::Newlib),
+            "linux" => Some(Env::Linux),
+            "sbi" => Some(Env::Sbi),
+            "htif" => Some(Env::Htif),
+            _ => None,
+        }
+    }
+
+    // whether ecalls with the number in a7 are emulated as syscalls
+    pub fn emulates(self, number: u32) -> bool {
+        match self {
+            Env::Linux => true,
+            // libgloss has no clock_gettime, mmap or getrandom
+            Env::Newlib => matches!(
+                number,
+                SYS_OPENAT
+                    | SYS_CLOSE
+                    | SYS_LSEEK
+                    | SYS_READ
+                    | SYS_WRITE
+                    | SYS_EXIT
+                    | SYS_GETTIMEOFDAY
+                    | SYS_BRK
+            ),
+            Env::Bare | Env::Sbi | Env::Htif => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn emulated_syscalls() {
+        assert!(Env::Linux.emulates(SYS_GETRANDOM));
+        assert!(Env::Newlib.emulates(SYS_EXIT));
+        assert!(!Env::Newlib.emulates(SYS_MMAP));
+        assert!(!Env::Bare.emulates(SYS_EXIT));
+        assert_eq!(Env::from_name("htif"), Some(Env::Htif));
+        assert_eq!(Env::from_name("pk"), None);
+    }
+}
@@ -1,12 +1,47 @@
 use std::fmt;
 
+use crate::devices::AssertFailure;
 use crate::inst_format::{BFormat, IFormat, RFormat, SFormat};
+use crate::trap::Exception;
 
 pub enum Error {
     InvalidOpcode(usize),
     InvalidInstFormat(FormatError),
     InvalidPC(u32, usize),
     EndOfInstructions,
+    // exception raised while no trap handler was installed
+    Trap(Exception),
+    InvalidElf(&'static str),
+    // access to the guard page below the stack
+    StackOverflow(u32),
+    // an assertion made through the test assert device failed, with the pc after the store
+    AssertFailed(AssertFailure, u32),
+    // the program didn't finish within the given number of cycles
+    CycleLimit(usize),
+    // the host stopped the emulation before the program finished
+    Stopped,
+    // syscall number and arguments of an ecall that isn't emulated, only raised in strict mode
+    UnimplementedSyscall(u32, [u32; 6]),
+    // a mapped file or alias would overlap ram or a device
+    MappingOverlap(u32),
+    // writing the trace file failed
+    TraceIo(std::io::Error),
+    // the connection to gdb failed
+    Gdb(std::io::Error),
+    // the control socket couldn't be opened
+    Control(std::io::Error),
+    // line and description of an error in an assembly source
+    Assembly(usize, String),
+    // line and description of an error in a debug script
+    DebugScript(usize, String),
+    // writing the output of a debug script failed
+    ScriptIo(std::io::Error),
+    // drawing the terminal front end or reading its keys failed
+    Terminal(std::io::Error),
+    // the window of the graphical front end couldn't be opened
+    Gui(String),
+    // the playground server couldn't be opened
+    Serve(std::io::Error),
 }
 pub enum FormatError {
     R(RFormat),
@@ -40,7 +75,39 @@ impl fmt::Debug for Error {
                     ),
                 },
                 Error::InvalidPC(pc, memsize) => format!(
-                    "program counter (pc: {pc}) bigger than than memory (memsize: {memsize}B)"
+                    "program counter (pc: {pc}) outside of memory (memsize: {memsize}B) and devices"
+                ),
+                Error::Trap(exception) => format!("unhandled exception: {exception:?}"),
+                Error::StackOverflow(address) =>
+                    format!("stack overflow: access to guard page at {address:#x}"),
+                Error::TraceIo(e) => format!("can't write trace: {e}"),
+                Error::Gdb(e) => format!("gdb connection failed: {e}"),
+                Error::Control(e) => format!("can't open control socket: {e}"),
+                Error::Assembly(line, message) =>
+                    format!("assembly error on line {line}: {message}"),
+                Error::DebugScript(line, message) =>
+                    format!("debug script error on line {line}: {message}"),
+                Error::ScriptIo(e) => format!("can't write debug script output: {e}"),
+                Error::Terminal(e) => format!("terminal failed: {e}"),
+                Error::Gui(e) => format!("can't open the window: {e}"),
+                Error::Serve(e) => format!("can't open playground server: {e}"),
+                Error::MappingOverlap(address) =>
+                    format!("can't map region at {address:#x}: overlaps ram or a device"),
+                Error::InvalidElf(reason) => format!("invalid elf file: {reason}"),
+                Error::AssertFailed(failure, pc) => format!(
+                    "assertion {} failed before pc {pc:#x}: actual {} ({:#x}), expected {} ({:#x})",
+                    failure.id,
+                    failure.actual as i32,
+                    failure.actual,
+                    failure.expected as i32,
+                    failure.expected
+                ),
+                Error::CycleLimit(cycles) =>
+                    format!("program didn't finish within {cycles} cycles"),
+                Error::Stopped => "emulation stopped by the host".to_string(),
+                Error::UnimplementedSyscall(number, args) => format!(
+                    "unimplemented syscall (a7: {number}) with arguments a0-a5: {}",
+                    args.map(|arg| format!("{arg:#x}")).join(", ")
                 ),
                 Error::EndOfInstructions =>
                     "program ran out of instructions! Use exit syscall to terminate gracefully."
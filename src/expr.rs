@@ -0,0 +1,454 @@
+// A small C-like expression language for the debugging options, e.g. `a0 + 4`,
+// `*(u32*)(sp+8)` or `sym("buf")+16`. Registers, the pc and csrs are referred to by name, symbols
+// of the loaded program with sym("name"). Values are 64-bit so that sums don't wrap, registers
+// and loads are zero-extended unless cast to a signed type.
+use std::fmt;
+
+use crate::asm::csr_number;
+use crate::cpu::Cpu;
+use crate::regs::Reg;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Type {
+    U8,
+    I8,
+    U16,
+    I16,
+    U32,
+    I32,
+}
+
+impl Type {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "u8" => Type::U8,
+            "i8" => Type::I8,
+            "u16" => Type::U16,
+            "i16" => Type::I16,
+            "u32" => Type::U32,
+            "i32" => Type::I32,
+            _ => return None,
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Type::U8 | Type::I8 => 1,
+            Type::U16 | Type::I16 => 2,
+            Type::U32 | Type::I32 => 4,
+        }
+    }
+
+    // truncates the value to the type and extends it back
+    fn convert(self, value: i64) -> i64 {
+        match self {
+            Type::U8 => value as u8 as i64,
+            Type::I8 => value as i8 as i64,
+            Type::U16 => value as u16 as i64,
+            Type::I16 => value as i16 as i64,
+            Type::U32 => value as u32 as i64,
+            Type::I32 => value as i32 as i64,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BinOp {
+    Or,
+    And,
+    BitOr,
+    Xor,
+    BitAnd,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+// operators by their spelling and precedence, longer spellings first so that `<<` isn't read as `<`
+#[rustfmt::skip]
+const BINARY_OPS: &[(&str, BinOp, u8)] = &[
+    ("||", BinOp::Or, 1), ("&&", BinOp::And, 2), ("==", BinOp::Eq, 6), ("!=", BinOp::Ne, 6),
+    ("<=", BinOp::Le, 7), (">=", BinOp::Ge, 7), ("<<", BinOp::Shl, 8), (">>", BinOp::Shr, 8),
+    ("|", BinOp::BitOr, 3), ("^", BinOp::Xor, 4), ("&", BinOp::BitAnd, 5), ("<", BinOp::Lt, 7),
+    (">", BinOp::Gt, 7), ("+", BinOp::Add, 9), ("-", BinOp::Sub, 9), ("*", BinOp::Mul, 10),
+    ("/", BinOp::Div, 10), ("%", BinOp::Rem, 10),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Num(i64),
+    Reg(Reg),
+    Pc,
+    Csr(u16),
+    Sym(String),
+    Neg(Box<Node>),
+    Not(Box<Node>),
+    LogicalNot(Box<Node>),
+    Cast(Type, Box<Node>),
+    Deref(Type, Box<Node>),
+    Binary(BinOp, Box<Node>, Box<Node>),
+}
+
+#[derive(Clone, Debug)]
+pub struct Expr {
+    text: String,
+    node: Node,
+}
+
+impl Expr {
+    pub fn parse(text: &str) -> Result<Expr, String> {
+        let mut parser = Parser { text, pos: 0 };
+        let node = parser.binary(0)?;
+        parser.skip_space();
+        if parser.pos < text.len() {
+            return Err(format!("unexpected '{}'", parser.rest()));
+        }
+        Ok(Expr {
+            text: text.trim().to_string(),
+            node,
+        })
+    }
+
+    // the value in the current state of the running hart, fails on unknown symbols and reads
+    // outside of ram
+    pub fn eval(&self, cpu: &Cpu) -> Result<i64, String> {
+        eval(&self.node, cpu)
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+fn eval(node: &Node, cpu: &Cpu) -> Result<i64, String> {
+    Ok(match node {
+        Node::Num(value) => *value,
+        Node::Reg(reg) => cpu.regs.get(*reg) as i64,
+        Node::Pc => cpu.pc.get() as i64,
+        Node::Csr(csr) => cpu
+            .read_csr(*csr)
+            .ok_or_else(|| format!("csr {csr:#x} doesn't exist"))? as i64,
+        Node::Sym(name) => match cpu.symbols.get(name) {
+            Some(&address) => address as i64,
+            None => return Err(format!("unknown symbol '{name}'")),
+        },
+        Node::Neg(operand) => eval(operand, cpu)?.wrapping_neg(),
+        Node::Not(operand) => !eval(operand, cpu)?,
+        Node::LogicalNot(operand) => (eval(operand, cpu)? == 0) as i64,
+        Node::Cast(ty, operand) => ty.convert(eval(operand, cpu)?),
+        Node::Deref(ty, operand) => {
+            let address = eval(operand, cpu)? as u32;
+            let bytes = cpu.mem.peek(address, ty.size());
+            if bytes.len() < ty.size() {
+                return Err(format!("can't read {} bytes at {address:#x}", ty.size()));
+            }
+            let mut word = [0; 8];
+            word[..ty.size()].copy_from_slice(&bytes);
+            ty.convert(i64::from_le_bytes(word))
+        }
+        // the right side of && and || is only evaluated if needed, like in c
+        Node::Binary(BinOp::And, left, right) => {
+            (eval(left, cpu)? != 0 && eval(right, cpu)? != 0) as i64
+        }
+        Node::Binary(BinOp::Or, left, right) => {
+            (eval(left, cpu)? != 0 || eval(right, cpu)? != 0) as i64
+        }
+        Node::Binary(op, left, right) => {
+            let (a, b) = (eval(left, cpu)?, eval(right, cpu)?);
+            match op {
+                BinOp::BitOr => a | b,
+                BinOp::Xor => a ^ b,
+                BinOp::BitAnd => a & b,
+                BinOp::Eq => (a == b) as i64,
+                BinOp::Ne => (a != b) as i64,
+                BinOp::Lt => (a < b) as i64,
+                BinOp::Le => (a <= b) as i64,
+                BinOp::Gt => (a > b) as i64,
+                BinOp::Ge => (a >= b) as i64,
+                BinOp::Shl => a.wrapping_shl(b as u32),
+                BinOp::Shr => a.wrapping_shr(b as u32),
+                BinOp::Add => a.wrapping_add(b),
+                BinOp::Sub => a.wrapping_sub(b),
+                BinOp::Mul => a.wrapping_mul(b),
+                BinOp::Div | BinOp::Rem if b == 0 => return Err("division by zero".to_string()),
+                BinOp::Div => a.wrapping_div(b),
+                BinOp::Rem => a.wrapping_rem(b),
+                BinOp::And | BinOp::Or => unreachable!(),
+            }
+        }
+    })
+}
+
+// Formats `count` words starting at the address, four per line like gdb's x/<count>wx. Stops at
+// the end of ram.
+pub fn examine(cpu: &Cpu, address: u32, count: usize) -> String {
+    let bytes = cpu.mem.peek(address, count.saturating_mul(4));
+    if bytes.len() < 4 {
+        return format!("{address:#010x}: can't read memory\n");
+    }
+    let mut out = String::new();
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        out += &format!("{:#010x}:", address.wrapping_add(line as u32 * 16));
+        for word in chunk.chunks_exact(4) {
+            out += &format!(" {:#010x}", u32::from_le_bytes(word.try_into().unwrap()));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+// shows the value both as signed number and as the 32-bit pattern the hart sees
+pub fn format_value(value: i64) -> String {
+    format!("{value} ({:#x})", value as u32)
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn skip_space(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    // consumes the token if the remaining text starts with it
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_space();
+        let found = self.rest().starts_with(token);
+        if found {
+            self.pos += token.len();
+        }
+        found
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), String> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(format!("expected '{token}'"))
+        }
+    }
+
+    fn ident(&mut self) -> &'a str {
+        self.skip_space();
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(rest.len());
+        self.pos += len;
+        &rest[..len]
+    }
+
+    // precedence climbing, all binary operators are left associative
+    fn binary(&mut self, min_precedence: u8) -> Result<Node, String> {
+        let mut left = self.unary()?;
+        loop {
+            self.skip_space();
+            let rest = self.rest();
+            let Some(&(token, op, precedence)) =
+                BINARY_OPS.iter().find(|(token, _, precedence)| {
+                    rest.starts_with(token) && *precedence > min_precedence
+                })
+            else {
+                return Ok(left);
+            };
+            // `|` and `&` must not take the first character of `||` and `&&` of lower precedence
+            if BINARY_OPS
+                .iter()
+                .any(|(longer, _, _)| longer.len() > token.len() && rest.starts_with(longer))
+            {
+                return Ok(left);
+            }
+            self.pos += token.len();
+            let right = self.binary(precedence)?;
+            left = Node::Binary(op, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        if self.eat("-") {
+            return Ok(Node::Neg(Box::new(self.unary()?)));
+        }
+        if self.eat("~") {
+            return Ok(Node::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("!") {
+            return Ok(Node::LogicalNot(Box::new(self.unary()?)));
+        }
+        if self.eat("*") {
+            let start = self.pos;
+            // *(u16*)expr reads the type, a plain *expr a word
+            let ty = match self.cast(true) {
+                Some(ty) => ty,
+                None => {
+                    self.pos = start;
+                    Type::U32
+                }
+            };
+            return Ok(Node::Deref(ty, Box::new(self.unary()?)));
+        }
+        let start = self.pos;
+        if let Some(ty) = self.cast(false) {
+            return Ok(Node::Cast(ty, Box::new(self.unary()?)));
+        }
+        self.pos = start;
+        self.primary()
+    }
+
+    // a parenthesized type, with a trailing * if it is a pointer
+    fn cast(&mut self, pointer: bool) -> Option<Type> {
+        if !self.eat("(") {
+            return None;
+        }
+        let ty = Type::from_name(self.ident())?;
+        if pointer && !self.eat("*") {
+            return None;
+        }
+        self.eat(")").then_some(ty)
+    }
+
+    fn primary(&mut self) -> Result<Node, String> {
+        if self.eat("(") {
+            let node = self.binary(0)?;
+            self.expect(")")?;
+            return Ok(node);
+        }
+        let token = self.ident();
+        if token.is_empty() {
+            return match self.rest().chars().next() {
+                Some(c) => Err(format!("unexpected '{c}'")),
+                None => Err("missing operand".to_string()),
+            };
+        }
+        if token.starts_with(|c: char| c.is_ascii_digit()) {
+            return parse_number(token)
+                .map(Node::Num)
+                .ok_or_else(|| format!("invalid number '{token}'"));
+        }
+        if token == "sym" {
+            self.expect("(")?;
+            self.expect("\"")?;
+            let rest = self.rest();
+            let Some(len) = rest.find('"') else {
+                return Err("unterminated symbol name".to_string());
+            };
+            self.pos += len + 1;
+            self.expect(")")?;
+            return Ok(Node::Sym(rest[..len].to_string()));
+        }
+        if token == "pc" {
+            return Ok(Node::Pc);
+        }
+        if let Some(reg) = Reg::from_name(token) {
+            return Ok(Node::Reg(reg));
+        }
+        match csr_number(token) {
+            Some(csr) => Ok(Node::Csr(csr)),
+            None => Err(format!("unknown register '{token}'")),
+        }
+    }
+}
+
+fn parse_number(token: &str) -> Option<i64> {
+    let token = token.replace('_', "");
+    if let Some(hex) = token.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = token.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()
+    } else {
+        token.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csr::MSCRATCH;
+
+    fn eval_str(cpu: &Cpu, text: &str) -> Result<i64, String> {
+        Expr::parse(text)?.eval(cpu)
+    }
+
+    #[test]
+    fn arithmetic_and_precedence() {
+        let cpu = Cpu::new(false);
+        assert_eq!(eval_str(&cpu, "1 + 2 * 3"), Ok(7));
+        assert_eq!(eval_str(&cpu, "(1 + 2) * 3"), Ok(9));
+        assert_eq!(eval_str(&cpu, "10 - 4 - 3"), Ok(3));
+        assert_eq!(eval_str(&cpu, "1 << 4 | 0x3"), Ok(0x13));
+        assert_eq!(eval_str(&cpu, "1 < 2 && 3 >= 3 || 0"), Ok(1));
+        assert_eq!(eval_str(&cpu, "6 & 3 == 2"), Ok(0));
+        assert_eq!(eval_str(&cpu, "!0 + ~0 + -1"), Ok(-1));
+        assert_eq!(eval_str(&cpu, "(i8)0xff"), Ok(-1));
+        assert_eq!(eval_str(&cpu, "(u16)0x12345"), Ok(0x2345));
+        assert_eq!(eval_str(&cpu, "0b1010 % 4"), Ok(2));
+        assert_eq!(eval_str(&cpu, "1 / 0"), Err("division by zero".to_string()));
+        // the right side isn't evaluated once the result is known
+        assert_eq!(eval_str(&cpu, "0 && 1 / 0"), Ok(0));
+    }
+
+    #[test]
+    fn registers_memory_and_symbols() {
+        let mut cpu = Cpu::new(false);
+        cpu.load(vec![0x13, 0, 0, 0]);
+        let base = cpu.mem.ram_base();
+        cpu.regs.set(Reg::Sp, base + 0x100);
+        cpu.regs.set(Reg::A0, 0xffff_fffe);
+        cpu.mem.write_bytes(base + 0x108, &[0xfe, 0xff, 0x34, 0x12]);
+        cpu.symbols.insert("buf".to_string(), base + 0x100);
+        cpu.write_csr(MSCRATCH, 5);
+
+        assert_eq!(eval_str(&cpu, "a0 + 4"), Ok(0x1_0000_0002));
+        assert_eq!(eval_str(&cpu, "(i32)a0 + 4"), Ok(2));
+        assert_eq!(eval_str(&cpu, "x2 == sp && pc == 0x4"), Ok(0));
+        assert_eq!(eval_str(&cpu, "*(u32*)(sp+8)"), Ok(0x1234_fffe));
+        assert_eq!(eval_str(&cpu, "*(i16*)(sp+8)"), Ok(-2));
+        assert_eq!(eval_str(&cpu, "*(u8*)(sym(\"buf\")+11)"), Ok(0x12));
+        assert_eq!(eval_str(&cpu, "*(sym(\"buf\") + 8) >> 16"), Ok(0x1234));
+        assert_eq!(eval_str(&cpu, "mscratch * 2"), Ok(10));
+        assert_eq!(
+            eval_str(&cpu, "sym(\"missing\")"),
+            Err("unknown symbol 'missing'".to_string())
+        );
+        assert!(eval_str(&cpu, "*(u32*)0xfffffff0").is_err());
+        assert_eq!(
+            examine(&cpu, base + 0x104, 2),
+            format!("{:#010x}: 0x00000000 0x1234fffe\n", base + 0x104)
+        );
+    }
+
+    #[test]
+    fn syntax_errors() {
+        for text in [
+            "",
+            "1 +",
+            "(1",
+            "a0 a1",
+            "foo",
+            "sym(buf)",
+            "0xg",
+            "*(u64*)sp",
+        ] {
+            assert!(Expr::parse(text).is_err(), "{text}");
+        }
+        assert_eq!(Expr::parse(" a0+1 ").unwrap().to_string(), "a0+1");
+    }
+}
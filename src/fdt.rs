@@ -0,0 +1,240 @@
+use crate::memory::*;
+
+// Flattened device tree (devicetree specification v0.4, chapter 5)
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_VERSION: u32 = 17;
+const FDT_LAST_COMP_VERSION: u32 = 16;
+const FDT_HEADER_SIZE: u32 = 40;
+
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_END: u32 = 0x9;
+
+// frequency of the machine timer as reported to the guest
+pub const TIMEBASE_FREQUENCY: u32 = 10_000_000;
+
+pub const PLIC_PHANDLE: u32 = 2;
+
+// phandle of the interrupt-controller of a hart, referenced by device nodes
+pub fn cpu_intc_phandle(hart: u32) -> u32 {
+    0x100 + hart
+}
+
+// Builds the structure block and strings block of a device tree, all values are big-endian.
+pub struct Fdt {
+    structure: Vec<u8>,
+    strings: Vec<u8>,
+    // number of harts, devices connected to every hart describe one connection per hart
+    harts: u32,
+}
+
+impl Fdt {
+    pub fn new() -> Self {
+        Fdt {
+            structure: Vec::new(),
+            strings: Vec::new(),
+            harts: 1,
+        }
+    }
+
+    pub fn harts(&self) -> u32 {
+        self.harts
+    }
+
+    fn push_u32(&mut self, value: u32) {
+        self.structure.extend(value.to_be_bytes());
+    }
+
+    // all tokens are aligned to 4 bytes
+    fn align(&mut self) {
+        while !self.structure.len().is_multiple_of(4) {
+            self.structure.push(0);
+        }
+    }
+
+    // returns the offset of the property name in the strings block, names are deduplicated
+    fn string_offset(&mut self, name: &str) -> u32 {
+        let mut offset = 0;
+        for s in self.strings.split(|&b| b == 0) {
+            if s == name.as_bytes() && offset < self.strings.len() {
+                return offset as u32;
+            }
+            offset += s.len() + 1;
+        }
+        let offset = self.strings.len();
+        self.strings.extend(name.as_bytes());
+        self.strings.push(0);
+        offset as u32
+    }
+
+    pub fn begin_node(&mut self, name: &str) {
+        self.push_u32(FDT_BEGIN_NODE);
+        self.structure.extend(name.as_bytes());
+        self.structure.push(0);
+        self.align();
+    }
+
+    pub fn end_node(&mut self) {
+        self.push_u32(FDT_END_NODE);
+    }
+
+    pub fn property(&mut self, name: &str, value: &[u8]) {
+        let name_offset = self.string_offset(name);
+        self.push_u32(FDT_PROP);
+        self.push_u32(value.len() as u32);
+        self.push_u32(name_offset);
+        self.structure.extend(value);
+        self.align();
+    }
+
+    pub fn property_empty(&mut self, name: &str) {
+        self.property(name, &[]);
+    }
+
+    pub fn property_u32(&mut self, name: &str, value: u32) {
+        self.property(name, &value.to_be_bytes());
+    }
+
+    pub fn property_cells(&mut self, name: &str, cells: &[u32]) {
+        let value: Vec<u8> = cells.iter().flat_map(|cell| cell.to_be_bytes()).collect();
+        self.property(name, &value);
+    }
+
+    pub fn property_str(&mut self, name: &str, value: &str) {
+        self.property_strs(name, &[value]);
+    }
+
+    // string lists are concatenated null-terminated strings
+    pub fn property_strs(&mut self, name: &str, values: &[&str]) {
+        let mut value = Vec::new();
+        for s in values {
+            value.extend(s.as_bytes());
+            value.push(0);
+        }
+        self.property(name, &value);
+    }
+
+    // assembles header, memory reservation block, structure block and strings block
+    pub fn finish(mut self) -> Vec<u8> {
+        self.push_u32(FDT_END);
+
+        // the reservation map is terminated by an empty 16 byte entry
+        let off_mem_rsvmap = FDT_HEADER_SIZE;
+        let off_dt_struct = off_mem_rsvmap + 16;
+        let off_dt_strings = off_dt_struct + self.structure.len() as u32;
+        let totalsize = off_dt_strings + self.strings.len() as u32;
+
+        let header = [
+            FDT_MAGIC,
+            totalsize,
+            off_dt_struct,
+            off_dt_strings,
+            off_mem_rsvmap,
+            FDT_VERSION,
+            FDT_LAST_COMP_VERSION,
+            0, // boot_cpuid_phys
+            self.strings.len() as u32,
+            self.structure.len() as u32,
+        ];
+        let mut blob: Vec<u8> = header.iter().flat_map(|w| w.to_be_bytes()).collect();
+        blob.extend([0; 16]);
+        blob.extend(self.structure);
+        blob.extend(self.strings);
+        blob
+    }
+}
+
+// Describes the emulated machine: memory, the harts and all mapped devices.
+pub fn machine_fdt(mem: &Memory, isa: &str, harts: u32) -> Vec<u8> {
+    let mut fdt = Fdt::new();
+    fdt.harts = harts;
+    fdt.begin_node("");
+    fdt.property_u32("#address-cells", 1);
+    fdt.property_u32("#size-cells", 1);
+    fdt.property_str("compatible", "ruscv,virt");
+    fdt.property_str("model", "ruscv,virt");
+
+    fdt.begin_node("chosen");
+    fdt.end_node();
+
+    fdt.begin_node(&format!("memory@{:x}", mem.ram_base()));
+    fdt.property_str("device_type", "memory");
+    fdt.property_cells("reg", &[mem.ram_base(), mem.ram_size() as u32]);
+    fdt.end_node();
+
+    fdt.begin_node("cpus");
+    fdt.property_u32("#address-cells", 1);
+    fdt.property_u32("#size-cells", 0);
+    fdt.property_u32("timebase-frequency", TIMEBASE_FREQUENCY);
+    for hart in 0..harts {
+        fdt.begin_node(&format!("cpu@{hart}"));
+        fdt.property_str("device_type", "cpu");
+        fdt.property_u32("reg", hart);
+        fdt.property_str("status", "okay");
+        fdt.property_str("compatible", "riscv");
+        fdt.property_str("riscv,isa", isa);
+        fdt.property_str("mmu-type", "riscv,sv32");
+        fdt.begin_node("interrupt-controller");
+        fdt.property_u32("#interrupt-cells", 1);
+        fdt.property_empty("interrupt-controller");
+        fdt.property_str("compatible", "riscv,cpu-intc");
+        fdt.property_u32("phandle", cpu_intc_phandle(hart));
+        fdt.end_node();
+        fdt.end_node();
+    }
+    fdt.end_node();
+
+    fdt.begin_node("soc");
+    fdt.property_u32("#address-cells", 1);
+    fdt.property_u32("#size-cells", 1);
+    fdt.property_str("compatible", "simple-bus");
+    fdt.property_empty("ranges");
+    for dev in mem.devices() {
+        dev.describe(&mut fdt);
+    }
+    fdt.end_node();
+
+    fdt.end_node();
+    fdt.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_u32(blob: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes(blob[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn header() {
+        let mut fdt = Fdt::new();
+        fdt.begin_node("");
+        fdt.property_u32("#size-cells", 1);
+        fdt.end_node();
+        let blob = fdt.finish();
+
+        assert_eq!(read_u32(&blob, 0), FDT_MAGIC);
+        assert_eq!(read_u32(&blob, 4) as usize, blob.len());
+        let off_dt_struct = read_u32(&blob, 8) as usize;
+        let off_dt_strings = read_u32(&blob, 12) as usize;
+        // root node has an empty name which is padded to 4 bytes
+        assert_eq!(read_u32(&blob, off_dt_struct), FDT_BEGIN_NODE);
+        assert_eq!(read_u32(&blob, off_dt_struct + 8), FDT_PROP);
+        assert_eq!(read_u32(&blob, off_dt_struct + 12), 4);
+        assert_eq!(read_u32(&blob, off_dt_struct + 20), 1);
+        assert_eq!(read_u32(&blob, off_dt_struct + 24), FDT_END_NODE);
+        assert_eq!(read_u32(&blob, off_dt_struct + 28), FDT_END);
+        assert_eq!(&blob[off_dt_strings..], b"#size-cells\0");
+    }
+
+    #[test]
+    fn strings_deduplicated() {
+        let mut fdt = Fdt::new();
+        assert_eq!(fdt.string_offset("reg"), 0);
+        assert_eq!(fdt.string_offset("compatible"), 4);
+        assert_eq!(fdt.string_offset("reg"), 0);
+        assert_eq!(fdt.strings, b"reg\0compatible\0");
+    }
+}
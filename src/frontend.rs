@@ -0,0 +1,329 @@
+// State shared by the terminal and the graphical front end: stepping and running the program in
+// slices, breakpoints, the position of the memory viewer, the captured console and why the
+// program last stopped. The front ends draw it and map their keys or buttons to its methods.
+use crate::cpu::{Cpu, StopReason};
+use crate::devices::{Capture, SerialLink};
+use crate::disasm;
+use crate::error::Error;
+use crate::expr::Expr;
+use crate::watch::{BreakSpec, DebugStop};
+
+use std::sync::mpsc::{self, Sender};
+use std::time::{Duration, Instant};
+
+// instructions executed between two checks of the clock while running
+const RUN_CHUNK: u64 = 10_000;
+// bytes per line of the memory viewer
+pub const MEMORY_LINE: u32 = 16;
+
+// an instruction of the disassembly around the pc
+pub struct CodeLine {
+    pub address: u32,
+    pub text: String,
+    // the symbol starting at the address
+    pub label: Option<String>,
+    pub breakpoint: bool,
+    pub current: bool,
+}
+
+pub struct Frontend {
+    pub running: bool,
+    // set once the program stopped for another reason than a debug stop, with that reason
+    finished: Option<Result<StopReason, Error>>,
+    // why the program last stopped or what went wrong
+    pub message: String,
+    // registers before the last step or run
+    previous: [u32; 32],
+    // first address shown in the memory viewer
+    pub memory: u32,
+    console: Capture,
+    // feeds the uart, None if the machine has none
+    input: Option<Sender<u8>>,
+    // retired instructions and time of the last speed update
+    last_update: (u64, Instant),
+    // instructions per second while running
+    speed: f64,
+}
+
+impl Frontend {
+    // Captures everything the program prints and connects the uart's input, if there is a uart.
+    pub fn new(cpu: &mut Cpu) -> Self {
+        // state dumps would end up between the panes
+        cpu.set_quiet();
+        let console = cpu.capture_output();
+        let (input, receiver) = mpsc::channel();
+        let link = SerialLink {
+            input: receiver,
+            output: Box::new(console.clone()),
+            name: "front end".to_string(),
+        };
+        Frontend {
+            running: false,
+            finished: None,
+            message: String::new(),
+            previous: registers(cpu),
+            memory: cpu.mem.ram_base(),
+            console,
+            input: cpu.mem.link_serial(link).then_some(input),
+            last_update: (cpu.retired(), Instant::now()),
+            speed: 0.0,
+        }
+    }
+
+    pub fn step(&mut self, cpu: &mut Cpu) {
+        if self.can_continue() {
+            self.previous = registers(cpu);
+            let result = cpu.step().map(|reason| reason.unwrap_or(StopReason::Yield));
+            self.stopped(cpu, result);
+        }
+    }
+
+    // lets the program run in the slices given to `run_for`
+    pub fn run(&mut self, cpu: &Cpu) {
+        if !self.running && self.can_continue() {
+            self.previous = registers(cpu);
+            self.running = true;
+            self.message = "running".to_string();
+            self.last_update = (cpu.retired(), Instant::now());
+        }
+    }
+
+    pub fn pause(&mut self, cpu: &Cpu) {
+        if self.running {
+            self.running = false;
+            self.message = format!("paused at pc {:#x}", cpu.pc.get());
+        }
+    }
+
+    // runs the program for about the duration unless it stops earlier or isn't running
+    pub fn run_for(&mut self, cpu: &mut Cpu, duration: Duration) {
+        let end = Instant::now() + duration;
+        while self.running && Instant::now() < end {
+            let result = cpu.run_for(RUN_CHUNK);
+            self.stopped(cpu, result);
+        }
+    }
+
+    pub fn toggle_breakpoint(&mut self, cpu: &mut Cpu, address: u32) {
+        if cpu.watchpoints.remove_breakpoint(address) {
+            self.message = format!("breakpoint at {address:#x} removed");
+        } else {
+            cpu.watchpoints.add_breakpoint(address);
+            self.message = format!("breakpoint at {address:#x}");
+        }
+    }
+
+    // adds a breakpoint given like `--break`, e.g. `main if a0 == 5 hit 3`
+    pub fn add_breakpoint(&mut self, cpu: &mut Cpu, spec: &str) {
+        let result = BreakSpec::parse(spec).and_then(|spec| {
+            let address = spec.resolve(cpu)?;
+            cpu.watchpoints.add_break_spec(address, &spec);
+            Ok(address)
+        });
+        self.message = match result {
+            Ok(address) => format!("breakpoint at {address:#x}"),
+            Err(e) => e,
+        };
+    }
+
+    // moves the memory viewer to the line holding the expression's address
+    pub fn show_memory(&mut self, cpu: &Cpu, expr: &str) {
+        match Expr::parse(expr).and_then(|expr| expr.eval(cpu)) {
+            Ok(address) => {
+                self.memory = address as u32 & !(MEMORY_LINE - 1);
+                self.message = format!("memory at {address:#x}");
+            }
+            Err(e) => self.message = e,
+        }
+    }
+
+    pub fn scroll_memory(&mut self, lines: i32) {
+        let offset = lines.wrapping_mul(MEMORY_LINE as i32) as u32;
+        self.memory = self.memory.wrapping_add(offset);
+    }
+
+    // resets the machine, a program that stopped can run again
+    pub fn reset(&mut self, cpu: &mut Cpu) {
+        cpu.reset();
+        self.running = false;
+        self.finished = None;
+        self.previous = registers(cpu);
+        self.message = "reset".to_string();
+    }
+
+    pub fn has_uart(&self) -> bool {
+        self.input.is_some()
+    }
+
+    // sends the bytes to the uart's input
+    pub fn type_bytes(&self, bytes: &[u8]) {
+        if let Some(input) = &self.input {
+            for &byte in bytes {
+                // the uart is gone once the machine is
+                let _ = input.send(byte);
+            }
+        }
+    }
+
+    fn can_continue(&mut self) -> bool {
+        if self.finished.is_some() {
+            self.message = "the program stopped, reset the machine to run it again".to_string();
+        }
+        self.finished.is_none()
+    }
+
+    // takes note of why a step or run ended, a program that won't continue is finished
+    fn stopped(&mut self, cpu: &Cpu, result: Result<StopReason, Error>) {
+        let pc = cpu.pc.get();
+        self.message = match &result {
+            Ok(StopReason::Yield) => return,
+            Ok(StopReason::Debug(DebugStop::Breakpoint(pc))) => format!("breakpoint at {pc:#x}"),
+            Ok(StopReason::Debug(DebugStop::Watchpoint(kind, address))) => {
+                format!("{kind:?} watchpoint at {address:#x} hit at pc {pc:#x}")
+            }
+            Ok(StopReason::Debug(DebugStop::Condition(index))) => {
+                let condition = cpu.watchpoints.condition(*index).unwrap();
+                format!("'{condition}' became true at pc {pc:#x}")
+            }
+            Ok(StopReason::Exit(code)) => format!("program exited with code {code}"),
+            Ok(reason) => format!("program stopped at pc {pc:#x}: {reason:?}"),
+            Err(e) => format!("program failed at pc {pc:#x}: {e:?}"),
+        };
+        self.running = false;
+        if !matches!(result, Ok(StopReason::Debug(_))) {
+            self.finished = Some(result);
+        }
+    }
+
+    // Finishes the run, calling the exit hooks, and returns the program's exit code or 0 if it
+    // didn't exit.
+    pub fn end(&mut self, cpu: &mut Cpu) -> Result<u8, Error> {
+        let result = self.finished.take();
+        match cpu.finish(result.unwrap_or(Ok(StopReason::HostRequest)))? {
+            StopReason::Exit(code) => Ok(code),
+            _ => Ok(0),
+        }
+    }
+
+    pub fn state(&self) -> String {
+        match &self.finished {
+            Some(Ok(StopReason::Exit(code))) => format!("exited with {code}"),
+            Some(_) => "stopped".to_string(),
+            None if self.running => "running".to_string(),
+            None => "paused".to_string(),
+        }
+    }
+
+    // instructions per second since the last call while running
+    pub fn speed(&mut self, cpu: &Cpu) -> f64 {
+        let (retired, time) = self.last_update;
+        let elapsed = time.elapsed().as_secs_f64();
+        if self.running && elapsed > 0.0 {
+            self.speed = (cpu.retired() - retired) as f64 / elapsed;
+        }
+        self.last_update = (cpu.retired(), Instant::now());
+        self.speed
+    }
+
+    // true if the last step or run changed the register
+    pub fn changed(&self, cpu: &Cpu, n: usize) -> bool {
+        cpu.regs.read(n) != self.previous[n]
+    }
+
+    // The instructions around the pc, a third of them before it unless ram starts earlier. Empty if
+    // the pc isn't in ram.
+    pub fn code(&self, cpu: &Cpu, rows: u32) -> Vec<CodeLine> {
+        let pc = cpu.pc.get();
+        let before = (4 * (rows / 3)).min(pc.wrapping_sub(cpu.mem.ram_base()));
+        let start = pc - before;
+        let bytes = cpu.mem.peek(start, 4 * rows as usize);
+        disasm::iter(&bytes, start)
+            .map(|(address, _, _, text)| CodeLine {
+                address,
+                text,
+                label: cpu
+                    .symbols
+                    .iter()
+                    .find(|(_, &symbol)| symbol == address)
+                    .map(|(name, _)| name.clone()),
+                breakpoint: cpu.watchpoints.has_breakpoint(address),
+                current: address == pc,
+            })
+            .collect()
+    }
+
+    // hex dump lines of the memory viewer
+    pub fn memory_lines(&self, cpu: &Cpu, rows: u32) -> Vec<String> {
+        (0..rows)
+            .map(|row| {
+                let address = self.memory.wrapping_add(row * MEMORY_LINE);
+                let bytes = cpu.mem.peek(address, MEMORY_LINE as usize);
+                if bytes.is_empty() {
+                    return format!("{address:08x}  (not in ram)");
+                }
+                let hex: Vec<String> = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+                let text: String = bytes
+                    .iter()
+                    .map(|&byte| match byte {
+                        0x20..=0x7e => byte as char,
+                        _ => '.',
+                    })
+                    .collect();
+                format!("{address:08x}  {:<47}  {text}", hex.join(" "))
+            })
+            .collect()
+    }
+
+    // everything the program printed so far
+    pub fn console(&self) -> String {
+        self.console.text()
+    }
+}
+
+fn registers(cpu: &Cpu) -> [u32; 32] {
+    std::array::from_fn(|n| cpu.regs.read(n))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regs::Reg;
+
+    #[test]
+    fn steps_runs_and_stops() {
+        let source = "
+            li a0, 5
+            li a1, 7
+        done:
+            li a7, 93
+            ecall";
+        let assembly = crate::asm::assemble(source, 0).unwrap();
+        let mut cpu = Cpu::new(false);
+        cpu.load(assembly.bytes);
+        cpu.symbols = assembly.symbols;
+        let mut frontend = Frontend::new(&mut cpu);
+
+        frontend.step(&mut cpu);
+        assert!(frontend.changed(&cpu, Reg::A0.index()));
+        assert!(!frontend.changed(&cpu, Reg::A1.index()));
+        let code = frontend.code(&cpu, 6);
+        assert_eq!(code[1].text, "addi a1, zero, 7");
+        assert!(code[1].current);
+        assert_eq!(code[2].label.as_deref(), Some("done"));
+
+        frontend.add_breakpoint(&mut cpu, "done");
+        assert_eq!(frontend.message, "breakpoint at 0x8");
+        frontend.run(&cpu);
+        frontend.run_for(&mut cpu, Duration::from_secs(10));
+        assert_eq!(frontend.state(), "paused");
+        assert!(frontend.code(&cpu, 6)[2].breakpoint);
+
+        frontend.toggle_breakpoint(&mut cpu, 8);
+        frontend.run(&cpu);
+        frontend.run_for(&mut cpu, Duration::from_secs(10));
+        assert_eq!(frontend.message, "program exited with code 5");
+        frontend.step(&mut cpu);
+        assert_eq!(frontend.state(), "exited with 5");
+        assert_eq!(frontend.end(&mut cpu).ok(), Some(5));
+    }
+}
@@ -0,0 +1,249 @@
+// Host files exposed to the program's file syscalls. Nothing can be opened unless a root directory
+// or path mappings were given: guest paths are resolved against the longest mapped guest prefix,
+// everything else below the root directory, like a chroot. `..` never leaves the guest's root and
+// symlinks pointing out of the exposed directories are refused. /dev/urandom and /dev/random are
+// always available and read from the cpu's seeded entropy source.
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+
+// open flags of the generic linux syscall table
+const O_ACCMODE: u32 = 0o3;
+const O_WRONLY: u32 = 0o1;
+const O_RDWR: u32 = 0o2;
+const O_CREAT: u32 = 0o100;
+const O_EXCL: u32 = 0o200;
+const O_TRUNC: u32 = 0o1000;
+const O_APPEND: u32 = 0o2000;
+
+pub enum OpenFile {
+    Host(File),
+    Random,
+}
+
+pub struct FileSystem {
+    root: Option<PathBuf>,
+    // components of the guest path prefix and the host path it is mapped to
+    mappings: Vec<(Vec<String>, PathBuf)>,
+    // open files, the first one has the file descriptor passed to open
+    files: Vec<Option<OpenFile>>,
+}
+
+impl FileSystem {
+    pub fn new() -> Self {
+        FileSystem {
+            root: None,
+            mappings: Vec::new(),
+            files: Vec::new(),
+        }
+    }
+
+    // Copy for a forked machine. Like after a unix fork the descriptors refer to the same open
+    // files, so their offsets are shared, descriptors that can't be duplicated are closed.
+    pub fn fork(&self) -> Self {
+        let files = self.files.iter().map(|file| match file {
+            Some(OpenFile::Host(file)) => file.try_clone().ok().map(OpenFile::Host),
+            Some(OpenFile::Random) => Some(OpenFile::Random),
+            None => None,
+        });
+        FileSystem {
+            root: self.root.clone(),
+            mappings: self.mappings.clone(),
+            files: files.collect(),
+        }
+    }
+
+    // exposes the directory as the guest's `/`
+    pub fn set_root(&mut self, dir: PathBuf) {
+        self.root = Some(dir);
+    }
+
+    // exposes the host file or directory at the absolute guest path
+    pub fn map_path(&mut self, guest: &str, host: PathBuf) {
+        self.mappings.push((components(guest), host));
+        // the longest prefix is tried first
+        self.mappings
+            .sort_by_key(|(guest, _)| std::cmp::Reverse(guest.len()));
+    }
+
+    // Translates the guest path to a host path, None if it isn't exposed. Relative paths start at
+    // the guest's `/`.
+    pub fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let path = components(path);
+        let (base, rest) = match self
+            .mappings
+            .iter()
+            .find(|(guest, _)| path.starts_with(guest))
+        {
+            Some((guest, host)) => (host, &path[guest.len()..]),
+            None => (self.root.as_ref()?, path.as_slice()),
+        };
+        contained(base, rest)
+    }
+
+    // opens the guest path with linux' open flags, returns the file's index or an io error
+    pub fn open(&mut self, path: &str, flags: u32) -> io::Result<usize> {
+        let file = match components(path).as_slice() {
+            [dev, name] if dev == "dev" && (name == "urandom" || name == "random") => {
+                OpenFile::Random
+            }
+            _ => OpenFile::Host(self.open_host(path, flags)?),
+        };
+        let index = match self.files.iter().position(Option::is_none) {
+            Some(index) => index,
+            None => {
+                self.files.push(None);
+                self.files.len() - 1
+            }
+        };
+        self.files[index] = Some(file);
+        Ok(index)
+    }
+
+    fn open_host(&self, path: &str, flags: u32) -> io::Result<File> {
+        let Some(path) = self.resolve(path) else {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        };
+        let mut options = OpenOptions::new();
+        options
+            .read(flags & O_ACCMODE != O_WRONLY)
+            .write(flags & O_ACCMODE == O_WRONLY || flags & O_ACCMODE == O_RDWR)
+            .append(flags & O_APPEND != 0)
+            .truncate(flags & O_TRUNC != 0)
+            .create(flags & O_CREAT != 0)
+            .create_new(flags & O_CREAT != 0 && flags & O_EXCL != 0);
+        // the resolved path has no symlink at its end, one that appeared since isn't followed
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.custom_flags(libc::O_NOFOLLOW);
+        }
+        options.open(path)
+    }
+
+    pub fn file(&mut self, index: usize) -> Option<&mut OpenFile> {
+        self.files.get_mut(index)?.as_mut()
+    }
+
+    // returns false if the file wasn't open
+    pub fn close(&mut self, index: usize) -> bool {
+        self.files.get_mut(index).and_then(Option::take).is_some()
+    }
+}
+
+// the normalized components of a guest path, `..` at the root stays at the root
+fn components(path: &str) -> Vec<String> {
+    let mut components = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => (),
+            ".." => {
+                components.pop();
+            }
+            component => components.push(component.to_string()),
+        }
+    }
+    components
+}
+
+// Follows the components below base like the host would and returns the path they lead to, None
+// if a symlink on the way leaves base or is dangling, so that creating a file can't follow it out.
+// Components that don't exist yet are kept as they are.
+fn contained(base: &Path, components: &[String]) -> Option<PathBuf> {
+    let base = base.canonicalize().ok()?;
+    let mut path = base.clone();
+    for component in components {
+        let next = path.join(component);
+        path = match next.symlink_metadata() {
+            Ok(metadata) if metadata.file_type().is_symlink() => next
+                .canonicalize()
+                .ok()
+                .filter(|target| target.starts_with(&base))?,
+            _ => next,
+        };
+    }
+    Some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn path_translation() {
+        let root = tempfile::tempdir().unwrap();
+        let data = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("hello.txt"), "root").unwrap();
+        std::fs::write(data.path().join("hello.txt"), "data").unwrap();
+        let mut fs = FileSystem::new();
+        assert_eq!(fs.resolve("/hello.txt"), None);
+        fs.set_root(root.path().to_path_buf());
+        fs.map_path("/mnt/data", data.path().to_path_buf());
+
+        let read = |fs: &mut FileSystem, path| {
+            let index = fs.open(path, 0)?;
+            let mut text = String::new();
+            let Some(OpenFile::Host(file)) = fs.file(index) else {
+                panic!("{path} isn't a host file");
+            };
+            file.read_to_string(&mut text)?;
+            fs.close(index);
+            Ok::<_, io::Error>(text)
+        };
+        assert_eq!(read(&mut fs, "/hello.txt").unwrap(), "root");
+        assert_eq!(read(&mut fs, "../../hello.txt").unwrap(), "root");
+        assert_eq!(read(&mut fs, "/mnt/./data/hello.txt").unwrap(), "data");
+        assert_eq!(
+            fs.resolve("/mnt/data/../../etc/passwd"),
+            Some(root.path().canonicalize().unwrap().join("etc/passwd"))
+        );
+
+        let index = fs.open("/dev/../dev/urandom", 0).unwrap();
+        assert!(matches!(fs.file(index), Some(OpenFile::Random)));
+        fs.close(index);
+        let index = fs.open("/new.txt", O_WRONLY | O_CREAT).unwrap();
+        assert!(fs.close(index));
+        assert!(!fs.close(index));
+        assert!(root.path().join("new.txt").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_stay_inside() {
+        let root = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(outside.path().join("secret"), "").unwrap();
+        std::os::unix::fs::symlink(outside.path(), root.path().join("escape")).unwrap();
+        let mut fs = FileSystem::new();
+        fs.set_root(root.path().to_path_buf());
+
+        assert_eq!(fs.resolve("/escape/secret"), None);
+        assert_eq!(
+            fs.open("/escape/secret", 0).unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dangling_symlinks_refused() {
+        let root = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let target = outside.path().join("new");
+        std::os::unix::fs::symlink(&target, root.path().join("link")).unwrap();
+        std::fs::write(root.path().join("file"), "inside").unwrap();
+        std::os::unix::fs::symlink("file", root.path().join("alias")).unwrap();
+        let mut fs = FileSystem::new();
+        fs.set_root(root.path().to_path_buf());
+
+        assert_eq!(
+            fs.open("/link", O_WRONLY | O_CREAT).unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+        assert!(!target.exists());
+        // symlinks that stay inside still work
+        let index = fs.open("/alias", 0).unwrap();
+        assert!(fs.close(index));
+    }
+}
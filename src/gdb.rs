@@ -0,0 +1,587 @@
+// Stub for gdb's remote serial protocol, `target remote <addr>` attaches to the program before its
+// first instruction. Supports the general registers, pc and csrs, ram, breakpoints (Z0/Z1),
+// watchpoints (Z2-Z4), continuing, stepping and interrupting with ctrl-c. Every hart is a thread,
+// gdb's thread ids are the hart ids plus one. `monitor` commands evaluate expressions, see
+// `monitor`.
+use crate::cpu::{Cpu, StopReason};
+use crate::devices;
+use crate::error::Error;
+use crate::expr::{self, Expr};
+use crate::trap::Exception;
+use crate::watch::{BreakSpec, DebugStop, WatchKind, Watchpoints};
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+// signals reported in stop replies
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGBUS: u8 = 7;
+const SIGSEGV: u8 = 11;
+
+// register numbers of gdb's risc-v target description
+const PC_REGNUM: usize = 32;
+const FIRST_CSR_REGNUM: usize = 65;
+
+// cycles between checks for a ctrl-c while the program runs
+const POLL_INTERVAL: u64 = 4096;
+
+enum Packet {
+    Command(String),
+    // ctrl-c sent outside of a packet
+    Interrupt,
+}
+
+// what the program did after it was resumed
+enum Resumed {
+    // stopped with the stop reply for gdb, the program can be resumed again
+    Stopped(String),
+    Finished(Result<StopReason, Error>),
+}
+
+struct Stub {
+    stream: TcpStream,
+}
+
+struct Session {
+    // reply to `?`, the reason of the last stop
+    last_stop: String,
+    // hart whose registers are accessed, selected with Hg
+    general: usize,
+    // hart that is single-stepped, selected with Hc, the Hg one if any hart may be stepped
+    step: Option<usize>,
+}
+
+impl Session {
+    fn new() -> Self {
+        Session {
+            last_stop: format!("S{SIGTRAP:02x}"),
+            general: 0,
+            step: None,
+        }
+    }
+}
+
+// Waits for gdb to connect to the address and runs the loaded program under its control. The
+// program keeps running on its own once gdb detaches or disconnects.
+pub fn serve(cpu: &mut Cpu, address: &str) -> Result<StopReason, Error> {
+    let result = session(cpu, address);
+    cpu.finish(result)
+}
+
+fn session(cpu: &mut Cpu, address: &str) -> Result<StopReason, Error> {
+    let listener = TcpListener::bind(address).map_err(Error::Gdb)?;
+    eprintln!("waiting for gdb on {address}");
+    let (stream, _) = listener.accept().map_err(Error::Gdb)?;
+    stream.set_nodelay(true).map_err(Error::Gdb)?;
+    let mut stub = Stub { stream };
+    let mut session = Session::new();
+    loop {
+        let command = match stub.read_packet().map_err(Error::Gdb)? {
+            Some(Packet::Command(command)) => command,
+            Some(Packet::Interrupt) => continue,
+            None => break,
+        };
+        let reply = match command.as_bytes().first() {
+            Some(b'c') => stub.resume(cpu, &mut session, false),
+            Some(b's') => stub.resume(cpu, &mut session, true),
+            Some(b'k') => return Ok(StopReason::HostRequest),
+            Some(b'D') => {
+                stub.send("OK").map_err(Error::Gdb)?;
+                break;
+            }
+            _ => Resumed::Stopped(handle(cpu, &mut session, &command)),
+        };
+        match reply {
+            Resumed::Stopped(reply) => {
+                stub.send(&reply).map_err(Error::Gdb)?;
+                if command.starts_with(['c', 's']) {
+                    session.last_stop = reply;
+                }
+            }
+            Resumed::Finished(result) => {
+                let reply = match &result {
+                    Ok(StopReason::Exit(code)) => format!("W{code:02x}"),
+                    Ok(reason) => format!("X{:02x}", signal(reason)),
+                    Err(_) => format!("X{SIGSEGV:02x}"),
+                };
+                // the program is gone, gdb may have disconnected already
+                let _ = stub.send(&reply);
+                return result;
+            }
+        }
+    }
+    // without debugger the program must not stop at breakpoints no one handles
+    cpu.watchpoints = Watchpoints::new();
+    cpu.run_until_stop()
+}
+
+// answers the commands that don't resume the program
+fn handle(cpu: &mut Cpu, session: &mut Session, command: &str) -> String {
+    if !command.is_char_boundary(1) {
+        return String::new();
+    }
+    let (kind, args) = command.split_at(1);
+    let general = session.general;
+    let reply = match kind {
+        "?" => Some(session.last_stop.clone()),
+        "g" => Some(on_hart(cpu, general, |cpu| {
+            (0..=PC_REGNUM).map(|n| hex_reg(read_reg(cpu, n))).collect()
+        })),
+        "G" => on_hart(cpu, general, |cpu| write_regs(cpu, args)),
+        "p" => usize::from_str_radix(args, 16)
+            .ok()
+            .map(|n| hex_reg(on_hart(cpu, general, |cpu| read_reg(cpu, n)))),
+        "P" => args.split_once('=').and_then(|(n, value)| {
+            let n = usize::from_str_radix(n, 16).ok()?;
+            let value = parse_reg(value)?;
+            on_hart(cpu, general, |cpu| write_reg(cpu, n, value)).then(|| "OK".to_string())
+        }),
+        "m" => read_memory(cpu, args),
+        "M" => write_memory(cpu, args),
+        "Z" | "z" => set_point(cpu, kind == "Z", args),
+        "H" => select_thread(cpu, session, args),
+        "T" => thread(cpu, args).map(|_| "OK".to_string()),
+        "q" if args.starts_with("Supported") => Some("PacketSize=4000".to_string()),
+        "q" if args == "Attached" => Some("1".to_string()),
+        "q" if args == "C" => Some(format!("QC{:x}", cpu.hart() + 1)),
+        "q" if args == "fThreadInfo" => Some(
+            (1..=cpu.harts())
+                .map(|id| format!("{id:x}"))
+                .collect::<Vec<_>>()
+                .join(","),
+        )
+        .map(|ids| format!("m{ids}")),
+        "q" if args == "sThreadInfo" => Some("l".to_string()),
+        "q" if args.starts_with("Rcmd,") => {
+            Some(on_hart(cpu, general, |cpu| monitor(cpu, &args[5..])))
+        }
+        _ => Some(String::new()),
+    };
+    // an empty reply tells gdb that the command isn't supported
+    reply.unwrap_or_else(|| "E01".to_string())
+}
+
+// the hart of a thread id, None if there is no such thread
+fn thread(cpu: &Cpu, id: &str) -> Option<usize> {
+    let hart = usize::from_str_radix(id, 16).ok()?.checked_sub(1)?;
+    (hart < cpu.harts()).then_some(hart)
+}
+
+// Hg<id> selects the thread whose registers are accessed, Hc<id> the thread to step. -1 means all
+// threads and 0 any thread.
+fn select_thread(cpu: &Cpu, session: &mut Session, args: &str) -> Option<String> {
+    let (op, id) = args.split_at(args.len().min(1));
+    let hart = match id {
+        "-1" | "0" => None,
+        id => Some(thread(cpu, id)?),
+    };
+    match op {
+        "g" => session.general = hart.unwrap_or(session.general),
+        "c" => session.step = hart,
+        _ => return None,
+    }
+    Some("OK".to_string())
+}
+
+// runs f with the hart's state as the cpu's current one, without changing which hart runs next
+fn on_hart<T>(cpu: &mut Cpu, hart: usize, f: impl FnOnce(&mut Cpu) -> T) -> T {
+    let running = cpu.hart();
+    cpu.select_hart(hart);
+    let result = f(cpu);
+    cpu.select_hart(running);
+    result
+}
+
+// registers are sent in target byte order
+fn hex_reg(value: Option<u32>) -> String {
+    match value {
+        Some(value) => format!("{:08x}", value.swap_bytes()),
+        None => "xxxxxxxx".to_string(),
+    }
+}
+
+fn parse_reg(hex: &str) -> Option<u32> {
+    u32::from_str_radix(hex, 16).ok().map(u32::swap_bytes)
+}
+
+fn read_reg(cpu: &Cpu, n: usize) -> Option<u32> {
+    match n {
+        0..32 => Some(cpu.regs.read(n)),
+        PC_REGNUM => Some(cpu.pc.get()),
+        _ => cpu.read_csr(n.checked_sub(FIRST_CSR_REGNUM)?.try_into().ok()?),
+    }
+}
+
+// returns false if the register doesn't exist or is read-only
+fn write_reg(cpu: &mut Cpu, n: usize, value: u32) -> bool {
+    match n {
+        0..32 => cpu.regs.write(n, value),
+        PC_REGNUM => cpu.pc.set(value),
+        _ => {
+            let Some(csr) = n.checked_sub(FIRST_CSR_REGNUM) else {
+                return false;
+            };
+            return u16::try_from(csr).is_ok_and(|csr| cpu.write_csr(csr, value).is_some());
+        }
+    }
+    true
+}
+
+fn write_regs(cpu: &mut Cpu, hex: &str) -> Option<String> {
+    for n in 0..=PC_REGNUM {
+        let value = hex.get(n * 8..n * 8 + 8).and_then(parse_reg)?;
+        write_reg(cpu, n, value);
+    }
+    Some("OK".to_string())
+}
+
+fn parse_range(args: &str) -> Option<(u32, usize)> {
+    let (address, len) = args.split_once(',')?;
+    Some((
+        u32::from_str_radix(address, 16).ok()?,
+        usize::from_str_radix(len, 16).ok()?,
+    ))
+}
+
+// only ram is accessible, reading device registers could change their state
+fn read_memory(cpu: &Cpu, args: &str) -> Option<String> {
+    let (address, len) = parse_range(args)?;
+    let bytes = cpu.mem.peek(address, len);
+    if bytes.is_empty() && len > 0 {
+        return None;
+    }
+    Some(bytes.iter().map(|byte| format!("{byte:02x}")).collect())
+}
+
+fn write_memory(cpu: &mut Cpu, args: &str) -> Option<String> {
+    let (range, data) = args.split_once(':')?;
+    let (address, len) = parse_range(range)?;
+    let bytes: Vec<u8> = (0..len)
+        .map(|i| u8::from_str_radix(data.get(i * 2..i * 2 + 2)?, 16).ok())
+        .collect::<Option<_>>()?;
+    if cpu.mem.peek(address, len).len() < len {
+        return None;
+    }
+    cpu.mem.write_bytes(address, &bytes);
+    Some("OK".to_string())
+}
+
+// Z<type>,<addr>,<kind> inserts and z removes a breakpoint (type 0, 1) or a write, read or
+// access watchpoint (type 2, 3, 4) whose kind is the number of watched bytes
+fn set_point(cpu: &mut Cpu, insert: bool, args: &str) -> Option<String> {
+    let mut fields = args.split([',', ';']);
+    let kind = fields.next()?;
+    let address = u32::from_str_radix(fields.next()?, 16).ok()?;
+    let len = u32::from_str_radix(fields.next()?, 16).ok()?;
+    let watch = match kind {
+        "0" | "1" => {
+            if insert {
+                cpu.watchpoints.add_breakpoint(address);
+            } else {
+                cpu.watchpoints.remove_breakpoint(address);
+            }
+            return Some("OK".to_string());
+        }
+        "2" => WatchKind::Write,
+        "3" => WatchKind::Read,
+        "4" => WatchKind::Access,
+        _ => return Some(String::new()),
+    };
+    if insert {
+        cpu.watchpoints.add_watchpoint(watch, address, len);
+    } else {
+        cpu.watchpoints.remove_watchpoint(watch, address, len);
+    }
+    Some("OK".to_string())
+}
+
+// Runs a hex-encoded `monitor` command and replies with its hex-encoded output:
+// `print <expr>` shows the value, `x[/<count>] <expr>` the words at the address,
+// `break <loc> [if <expr>] [hit <n>]` adds a conditional or counted breakpoint,
+// `break-if <expr>` stops the program once the expression becomes nonzero and `reset` resets
+// the machine.
+fn monitor(cpu: &mut Cpu, hex: &str) -> String {
+    let bytes: Option<Vec<u8>> = (0..hex.len() / 2)
+        .map(|i| u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok())
+        .collect();
+    let command = String::from_utf8(bytes.unwrap_or_default()).unwrap_or_default();
+    let (name, text) = command
+        .trim()
+        .split_once(' ')
+        .unwrap_or((command.trim(), ""));
+    let output = match Expr::parse(text) {
+        _ if name == "reset" && text.is_empty() => {
+            cpu.reset();
+            "OK\n".to_string()
+        }
+        _ if name == "gpio" => match (devices::parse_gpio_input(text), cpu.gpio()) {
+            (Ok(input), Some(pins)) => pins.command(input),
+            (Err(e), _) => format!("{e}\n"),
+            (_, None) => "no gpio block\n".to_string(),
+        },
+        _ if name == "break" => {
+            match BreakSpec::parse(text).and_then(|spec| Ok((spec.resolve(cpu)?, spec))) {
+                Ok((address, spec)) => {
+                    cpu.watchpoints.add_break_spec(address, &spec);
+                    format!("breakpoint at {address:#x}\n")
+                }
+                Err(e) => format!("invalid breakpoint: {e}\n"),
+            }
+        }
+        Err(e) => format!("invalid expression: {e}\n"),
+        Ok(expr) => match name {
+            "print" | "p" => match expr.eval(cpu) {
+                Ok(value) => format!("{}\n", expr::format_value(value)),
+                Err(e) => format!("{e}\n"),
+            },
+            "x" => match expr.eval(cpu) {
+                Ok(address) => expr::examine(cpu, address as u32, 1),
+                Err(e) => format!("{e}\n"),
+            },
+            name if name.starts_with("x/") => match (name[2..].parse(), expr.eval(cpu)) {
+                (Ok(count), Ok(address)) => expr::examine(cpu, address as u32, count),
+                (Err(_), _) => format!("invalid count '{}'\n", &name[2..]),
+                (_, Err(e)) => format!("{e}\n"),
+            },
+            "break-if" => {
+                cpu.watchpoints.add_condition(expr);
+                "OK\n".to_string()
+            }
+            _ => format!("unknown monitor command '{name}'\n"),
+        },
+    };
+    output.bytes().map(|byte| format!("{byte:02x}")).collect()
+}
+
+// the stop reply names the hart that stopped, so gdb switches to its thread
+fn stop_reply(cpu: &Cpu, signal: u8, stop: Option<DebugStop>) -> String {
+    let watch = match stop {
+        Some(DebugStop::Watchpoint(kind, address)) => {
+            let name = match kind {
+                WatchKind::Write => "watch",
+                WatchKind::Read => "rwatch",
+                WatchKind::Access => "awatch",
+            };
+            format!("{name}:{address:x};")
+        }
+        Some(DebugStop::Breakpoint(_) | DebugStop::Condition(_)) | None => String::new(),
+    };
+    format!("T{signal:02x}{watch}thread:{:x};", cpu.hart() + 1)
+}
+
+pub(crate) fn signal(reason: &StopReason) -> u8 {
+    match reason {
+        StopReason::Trap(Exception::IllegalInstruction(_)) => SIGILL,
+        StopReason::Trap(
+            Exception::InstructionAddressMisaligned(_)
+            | Exception::LoadAddressMisaligned(_)
+            | Exception::StoreAddressMisaligned(_),
+        ) => SIGBUS,
+        StopReason::Trap(
+            Exception::InstructionAccessFault(_)
+            | Exception::LoadAccessFault(_)
+            | Exception::StoreAccessFault(_),
+        ) => SIGSEGV,
+        StopReason::Limit(_) | StopReason::HostRequest | StopReason::Yield => SIGINT,
+        _ => SIGTRAP,
+    }
+}
+
+impl Stub {
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(self.stream, "${data}#{checksum:02x}")?;
+        self.stream.flush()
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+        match self.stream.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    // the next packet, acknowledged, or None once gdb disconnected
+    fn read_packet(&mut self) -> io::Result<Option<Packet>> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(0x03) => return Ok(Some(Packet::Interrupt)),
+                Some(b'$') => break,
+                // acks of our replies and noise between packets
+                Some(_) => (),
+            }
+        }
+        let mut data = Vec::new();
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'#') => break,
+                Some(byte) => data.push(byte),
+            }
+        }
+        // the checksum isn't verified, tcp already is reliable
+        for _ in 0..2 {
+            if self.read_byte()?.is_none() {
+                return Ok(None);
+            }
+        }
+        self.stream.write_all(b"+")?;
+        Ok(Some(Packet::Command(
+            String::from_utf8_lossy(&data).into_owned(),
+        )))
+    }
+
+    // whether gdb sent a ctrl-c, doesn't block
+    fn interrupted(&mut self) -> bool {
+        let mut byte = [0];
+        if self.stream.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let read = self.stream.read(&mut byte);
+        let _ = self.stream.set_nonblocking(false);
+        matches!(read, Ok(1) if byte[0] == 0x03)
+    }
+
+    // Continues or single-steps the program until it stops. All harts keep running while one of
+    // them is stepped, a step ends once the stepped hart executed an instruction.
+    fn resume(&mut self, cpu: &mut Cpu, session: &mut Session, single: bool) -> Resumed {
+        let stepped = session.step.unwrap_or(session.general);
+        let start_pc = on_hart(cpu, stepped, |cpu| cpu.pc.get());
+        let stop = |cpu: &mut Cpu, session: &mut Session, signal, stop| {
+            session.general = cpu.hart();
+            Resumed::Stopped(stop_reply(cpu, signal, stop))
+        };
+        for cycle in 1.. {
+            let retired = cpu.retired();
+            match cpu.step() {
+                Ok(None) => (),
+                Ok(Some(StopReason::Debug(debug))) => {
+                    return stop(cpu, session, SIGTRAP, Some(debug))
+                }
+                Ok(Some(reason @ StopReason::Exit(_))) => return Resumed::Finished(Ok(reason)),
+                // gdb can look at the state of the program before it is finished
+                Ok(Some(reason)) => return stop(cpu, session, signal(&reason), None),
+                Err(e) => return Resumed::Finished(Err(e)),
+            }
+            // a trap entered the handler without retiring an instruction
+            let moved = cpu.retired() != retired || cpu.pc.get() != start_pc;
+            if single && cpu.hart() == stepped && moved {
+                break;
+            }
+            if cycle % POLL_INTERVAL == 0 && self.interrupted() {
+                return stop(cpu, session, SIGINT, None);
+            }
+        }
+        stop(cpu, session, SIGTRAP, None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regs::Reg;
+
+    #[test]
+    fn register_and_memory_packets() {
+        let mut cpu = Cpu::new(false);
+        let mut session = Session::new();
+        cpu.regs.set(Reg::A0, 0x1234_5678);
+        cpu.pc.set(0x100);
+        let regs = handle(&mut cpu, &mut session, "g");
+        assert_eq!(&regs[10 * 8..11 * 8], "78563412");
+        assert_eq!(&regs[32 * 8..], "00010000");
+        assert_eq!(handle(&mut cpu, &mut session, "Pb=efbeadde"), "OK");
+        assert_eq!(cpu.regs.get(Reg::A1), 0xdead_beef);
+        assert_eq!(handle(&mut cpu, &mut session, "p20"), "00010000");
+
+        assert_eq!(handle(&mut cpu, &mut session, "M200,3:616263"), "OK");
+        assert_eq!(handle(&mut cpu, &mut session, "m1ff,4"), "00616263");
+        assert_eq!(handle(&mut cpu, &mut session, "mfffffff0,4"), "E01");
+        assert_eq!(handle(&mut cpu, &mut session, "vMustReplyEmpty"), "");
+        assert_eq!(handle(&mut cpu, &mut session, ""), "");
+    }
+
+    #[test]
+    fn thread_packets() {
+        let mut cpu = Cpu::new(false);
+        cpu.set_harts(2);
+        cpu.load(vec![0x13, 0, 0, 0]);
+        let mut session = Session::new();
+        assert_eq!(handle(&mut cpu, &mut session, "qfThreadInfo"), "m1,2");
+        assert_eq!(handle(&mut cpu, &mut session, "qsThreadInfo"), "l");
+        assert_eq!(handle(&mut cpu, &mut session, "T2"), "OK");
+        assert_eq!(handle(&mut cpu, &mut session, "T3"), "E01");
+        assert_eq!(handle(&mut cpu, &mut session, "Hg3"), "E01");
+
+        // registers are per hart, the running hart stays the same
+        assert_eq!(handle(&mut cpu, &mut session, "Hg2"), "OK");
+        assert_eq!(handle(&mut cpu, &mut session, "Pa=01000000"), "OK");
+        assert_eq!(handle(&mut cpu, &mut session, "Hg1"), "OK");
+        assert_eq!(handle(&mut cpu, &mut session, "pa"), "00000000");
+        assert_eq!(cpu.hart(), 0);
+        assert_eq!(handle(&mut cpu, &mut session, "qC"), "QC1");
+        cpu.select_hart(1);
+        assert_eq!(cpu.regs.get(Reg::A0), 1);
+
+        assert_eq!(handle(&mut cpu, &mut session, "Hc-1"), "OK");
+        assert_eq!(session.step, None);
+        assert_eq!(handle(&mut cpu, &mut session, "Hc2"), "OK");
+        assert_eq!(session.step, Some(1));
+    }
+
+    #[test]
+    fn watchpoint_packets() {
+        let mut cpu = Cpu::new(false);
+        let mut session = Session::new();
+        assert_eq!(handle(&mut cpu, &mut session, "Z2,200,4"), "OK");
+        assert_eq!(handle(&mut cpu, &mut session, "Z0,8,4"), "OK");
+        assert_eq!(handle(&mut cpu, &mut session, "Z9,8,4"), "");
+        assert!(!cpu.watchpoints.is_empty());
+        assert_eq!(
+            stop_reply(
+                &cpu,
+                SIGTRAP,
+                Some(DebugStop::Watchpoint(WatchKind::Write, 0x202))
+            ),
+            "T05watch:202;thread:1;"
+        );
+        assert_eq!(handle(&mut cpu, &mut session, "z2,200,4"), "OK");
+        assert_eq!(handle(&mut cpu, &mut session, "z0,8,4"), "OK");
+        assert!(cpu.watchpoints.is_empty());
+    }
+
+    #[test]
+    fn monitor_commands() {
+        let mut cpu = Cpu::new(false);
+        let mut session = Session::new();
+        cpu.regs.set(Reg::A0, 0x200);
+        cpu.mem.write_bytes(0x200, &[0x78, 0x56, 0x34, 0x12]);
+        let mut run = |command: &str| {
+            let hex: String = command.bytes().map(|byte| format!("{byte:02x}")).collect();
+            let reply = handle(&mut cpu, &mut session, &format!("qRcmd,{hex}"));
+            let bytes: Vec<u8> = (0..reply.len() / 2)
+                .map(|i| u8::from_str_radix(&reply[i * 2..i * 2 + 2], 16).unwrap())
+                .collect();
+            String::from_utf8(bytes).unwrap()
+        };
+        assert_eq!(run("print a0 + 4"), "516 (0x204)\n");
+        assert_eq!(run("x/2 a0"), "0x00000200: 0x12345678 0x00000000\n");
+        assert_eq!(run("print (1"), "invalid expression: expected ')'\n");
+        assert_eq!(run("break-if *(u8*)a0 == 0x78"), "OK\n");
+        assert_eq!(
+            run("break a0 + 8 if a1 == 2 hit 10"),
+            "breakpoint at 0x208\n"
+        );
+        assert_eq!(
+            run("break nowhere"),
+            "invalid breakpoint: unknown register 'nowhere'\n"
+        );
+        assert_eq!(run("gpio"), "no gpio block\n");
+        assert_eq!(run("reset"), "OK\n");
+        assert!(!cpu.watchpoints.is_empty());
+    }
+}
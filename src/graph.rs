@@ -0,0 +1,298 @@
+// Control-flow and call graphs recovered from the disassembly, exported in graphviz' dot format.
+// Only direct jumps and calls are followed, indirect calls through jalr don't show up.
+use crate::disasm;
+use crate::inst::{IInst, Inst};
+use crate::listing::Image;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write;
+
+// Straight-line code that is only entered at its first instruction.
+pub struct Block {
+    pub start: u32,
+    // disassembly of the instructions, the first one is at `start`
+    pub insts: Vec<String>,
+    // start addresses of the blocks execution continues with
+    pub successors: Vec<u32>,
+    // address of every call instruction in the block and the function it calls
+    pub calls: Vec<(u32, u32)>,
+}
+
+pub struct Graph {
+    pub blocks: BTreeMap<u32, Block>,
+    // entry points, function symbols and call targets
+    pub functions: BTreeSet<u32>,
+}
+
+// whether the instruction ends its block, the blocks that follow it and the function it calls
+fn flow(inst: &Inst, pc: u32) -> (bool, Vec<u32>, Option<u32>) {
+    let next = pc.wrapping_add(4);
+    match (inst, disasm::target(inst, pc)) {
+        (Inst::J(format), Some(target)) if format.rd == 0 => (true, vec![target], None),
+        (Inst::J(_), Some(target)) => (false, Vec::new(), Some(target)),
+        (Inst::B(..), Some(target)) => (true, vec![next, target], None),
+        (Inst::I(IInst::Jalr, format), _) if format.rd == 0 => (true, Vec::new(), None),
+        (Inst::Mret | Inst::Sret, _) => (true, Vec::new(), None),
+        _ => (false, Vec::new(), None),
+    }
+}
+
+// Splits the code reachable from the image's entry points into basic blocks.
+pub fn build(image: &Image) -> Graph {
+    let mut insts = BTreeMap::new();
+    for &(base, bytes) in &image.regions {
+        let reached = disasm::reachable(bytes, base, &image.entries);
+        for (pc, _, inst, text) in disasm::iter(bytes, base) {
+            if let (Ok(inst), true) = (inst, reached.contains(&pc)) {
+                insts.insert(pc, (inst, text));
+            }
+        }
+    }
+
+    let mut functions: BTreeSet<u32> = image
+        .entries
+        .iter()
+        .copied()
+        .filter(|pc| insts.contains_key(pc))
+        .collect();
+    let mut leaders = functions.clone();
+    for (&pc, (inst, _)) in &insts {
+        let (ends, successors, call) = flow(inst, pc);
+        if ends {
+            leaders.insert(pc.wrapping_add(4));
+        }
+        leaders.extend(successors);
+        if let Some(target) = call.filter(|target| insts.contains_key(target)) {
+            leaders.insert(target);
+            functions.insert(target);
+        }
+    }
+
+    let mut blocks: BTreeMap<u32, Block> = BTreeMap::new();
+    // start and last instruction of the block that falls through to the next instruction
+    let mut open: Option<(u32, u32)> = None;
+    for (&pc, (inst, text)) in &insts {
+        let start = match open {
+            Some((start, last)) if last.wrapping_add(4) == pc && !leaders.contains(&pc) => start,
+            Some((start, last)) => {
+                if let Some(block) = blocks
+                    .get_mut(&start)
+                    .filter(|_| last.wrapping_add(4) == pc)
+                {
+                    block.successors.push(pc);
+                }
+                pc
+            }
+            None => pc,
+        };
+        let block = blocks.entry(start).or_insert_with(|| Block {
+            start,
+            insts: Vec::new(),
+            successors: Vec::new(),
+            calls: Vec::new(),
+        });
+        block.insts.push(text.clone());
+        let (ends, successors, call) = flow(inst, pc);
+        block.successors.extend(
+            successors
+                .into_iter()
+                .filter(|target| insts.contains_key(target)),
+        );
+        block.calls.extend(call.map(|target| (pc, target)));
+        open = (!ends).then_some((start, pc));
+    }
+    Graph { blocks, functions }
+}
+
+// Fill color of a node executed `count` times, from light yellow to dark red for the hottest one.
+fn heat(count: u64, max: u64) -> String {
+    match count {
+        0 => String::new(),
+        _ => {
+            let level = 1 + count * 8 / max.max(1);
+            format!(", style=filled, fillcolor=\"/ylorrd9/{level}\"")
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl Graph {
+    // the function containing the address, the closest function start at or before it
+    pub(crate) fn function(&self, address: u32) -> Option<u32> {
+        self.functions.range(..=address).next_back().copied()
+    }
+
+    pub(crate) fn name(image: &Image, function: u32) -> String {
+        match image.labels.get(&function) {
+            Some(label) => escape(label),
+            None => format!("{function:#x}"),
+        }
+    }
+
+    // The basic blocks of every function grouped into a cluster, with `counts` (executions per
+    // address) the blocks are annotated with how often they were entered and colored by heat.
+    pub fn cfg_dot(&self, image: &Image, counts: Option<&HashMap<u32, u64>>) -> String {
+        let count = |address| counts.map(|counts| counts.get(&address).copied().unwrap_or(0));
+        let max = self.blocks.keys().filter_map(|&start| count(start)).max();
+        let mut by_function: BTreeMap<Option<u32>, Vec<&Block>> = BTreeMap::new();
+        for block in self.blocks.values() {
+            by_function
+                .entry(self.function(block.start))
+                .or_default()
+                .push(block);
+        }
+
+        let mut out = String::from("digraph cfg {\n    node [shape=box, fontname=monospace];\n");
+        for (function, blocks) in by_function {
+            let indent = match function {
+                Some(function) => {
+                    let _ = writeln!(out, "    subgraph \"cluster_{function:x}\" {{");
+                    let _ = writeln!(out, "        label=\"{}\";", Self::name(image, function));
+                    "        "
+                }
+                None => "    ",
+            };
+            for block in blocks {
+                let mut label = format!("{:08x}:", block.start);
+                let mut style = String::new();
+                if let (Some(max), Some(count)) = (max, count(block.start)) {
+                    let _ = write!(label, " ({count}x)");
+                    style = heat(count, max);
+                }
+                label += "\\l";
+                for inst in &block.insts {
+                    let _ = write!(label, "{}\\l", escape(inst));
+                }
+                let _ = writeln!(
+                    out,
+                    "{indent}b{:x} [label=\"{label}\"{style}];",
+                    block.start
+                );
+            }
+            if function.is_some() {
+                out += "    }\n";
+            }
+        }
+        for block in self.blocks.values() {
+            for successor in &block.successors {
+                let _ = writeln!(out, "    b{:x} -> b{successor:x};", block.start);
+            }
+        }
+        out += "}\n";
+        out
+    }
+
+    // The functions and the direct calls between them, with `counts` functions are annotated
+    // with how often they were entered and calls with how often they were executed.
+    pub fn call_graph_dot(&self, image: &Image, counts: Option<&HashMap<u32, u64>>) -> String {
+        let count = |address| counts.map(|counts| counts.get(&address).copied().unwrap_or(0));
+        let max = self.functions.iter().filter_map(|&f| count(f)).max();
+        let mut calls: BTreeMap<(u32, u32), u64> = BTreeMap::new();
+        for block in self.blocks.values() {
+            let Some(caller) = self.function(block.start) else {
+                continue;
+            };
+            for &(site, callee) in &block.calls {
+                *calls.entry((caller, callee)).or_default() += count(site).unwrap_or(0);
+            }
+        }
+
+        let mut out = String::from("digraph calls {\n    node [shape=box, fontname=monospace];\n");
+        for &function in &self.functions {
+            let mut label = Self::name(image, function);
+            let mut style = String::new();
+            if let (Some(max), Some(count)) = (max, count(function)) {
+                let _ = write!(label, "\\n{count}x");
+                style = heat(count, max);
+            }
+            let _ = writeln!(out, "    f{function:x} [label=\"{label}\"{style}];");
+        }
+        for ((caller, callee), executed) in calls {
+            let _ = write!(out, "    f{caller:x} -> f{callee:x}");
+            if counts.is_some() {
+                let _ = write!(out, " [label=\"{executed}x\"]");
+            }
+            out += ";\n";
+        }
+        out += "}\n";
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program() -> Vec<u8> {
+        [
+            0x00300513u32, // addi a0, zero, 3
+            0x010000ef,    // jal ra, 16
+            0xfff50513,    // addi a0, a0, -1
+            0xfe051ee3,    // bne a0, zero, -4
+            0x00008067,    // jalr zero, 0(ra)
+            0x00158593,    // addi a1, a1, 1
+            0x00008067,    // jalr zero, 0(ra)
+        ]
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .collect()
+    }
+
+    #[test]
+    fn basic_blocks() {
+        let bytes = program();
+        let graph = build(&Image::raw(&bytes, 0x8000_0000));
+
+        let blocks: Vec<_> = graph
+            .blocks
+            .values()
+            .map(|block| (block.start, block.insts.len(), block.successors.clone()))
+            .collect();
+        assert_eq!(
+            blocks,
+            [
+                (0x8000_0000, 2, vec![0x8000_0008]),
+                (0x8000_0008, 2, vec![0x8000_0010, 0x8000_0008]),
+                (0x8000_0010, 1, vec![]),
+                (0x8000_0014, 2, vec![]),
+            ]
+        );
+        assert_eq!(
+            graph.blocks[&0x8000_0000].calls,
+            [(0x8000_0004, 0x8000_0014)]
+        );
+        assert_eq!(graph.functions, BTreeSet::from([0x8000_0000, 0x8000_0014]));
+    }
+
+    #[test]
+    fn dot_with_counts() {
+        let bytes = program();
+        let image = Image::raw(&bytes, 0x8000_0000);
+        let graph = build(&image);
+        let counts = HashMap::from([
+            (0x8000_0000, 1),
+            (0x8000_0004, 1),
+            (0x8000_0008, 3),
+            (0x8000_0014, 1),
+        ]);
+
+        assert_eq!(
+            graph.call_graph_dot(&image, Some(&counts)),
+            "digraph calls {\n    node [shape=box, fontname=monospace];\n    \
+             f80000000 [label=\"0x80000000\\n1x\", style=filled, fillcolor=\"/ylorrd9/9\"];\n    \
+             f80000014 [label=\"0x80000014\\n1x\", style=filled, fillcolor=\"/ylorrd9/9\"];\n    \
+             f80000000 -> f80000014 [label=\"1x\"];\n}\n"
+        );
+        let cfg = graph.cfg_dot(&image, Some(&counts));
+        assert!(cfg.contains("subgraph \"cluster_80000014\""));
+        assert!(cfg.contains(
+            "b80000008 [label=\"80000008: (3x)\\laddi a0, a0, -1\\lbne a0, zero, -4\\l\", \
+             style=filled, fillcolor=\"/ylorrd9/9\"];"
+        ));
+        assert!(cfg.contains("b80000010 [label=\"80000010: (0x)\\ljalr zero, 0(ra)\\l\"];"));
+        assert!(cfg.contains("    b80000008 -> b80000008;\n"));
+    }
+}
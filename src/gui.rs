@@ -0,0 +1,299 @@
+// Graphical front end for `ruscv gui`, with the panels of the terminal front end: the code around
+// the pc where clicking an instruction toggles its breakpoint, the registers with the ones changed
+// by the last step or run highlighted, a memory viewer, the console with a line to type into the
+// uart, and statistics. Given `--framebuffer`, a window shows a region of ram as a bitmap display
+// of 0x00rrggbb words, updated while the program runs.
+use crate::cpu::Cpu;
+use crate::error::Error;
+use crate::frontend::Frontend;
+use crate::regs::Reg;
+
+use eframe::egui::{self, Color32, RichText, TextureHandle, TextureOptions};
+use std::time::Duration;
+
+// the program runs this long between two frames
+const FRAME: Duration = Duration::from_millis(16);
+// rows of the code and memory panels
+const CODE_ROWS: u32 = 24;
+const MEMORY_ROWS: u32 = 16;
+// the framebuffer window shows every pixel this large
+const PIXEL_SCALE: f32 = 2.0;
+const CHANGED: Color32 = Color32::from_rgb(0xe0, 0xb0, 0x20);
+
+// A bitmap display backed by ram. Pixels are 32-bit words with red in bits 16-23, green in 8-15
+// and blue in 0-7, row by row.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Framebuffer {
+    pub address: u32,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Framebuffer {
+    // the pixels as rgba, black where the display isn't backed by ram
+    fn image(&self, cpu: &Cpu) -> egui::ColorImage {
+        let bytes = cpu.mem.peek(self.address, self.width * self.height * 4);
+        let mut rgba = vec![0; self.width * self.height * 4];
+        for (pixel, word) in rgba.chunks_exact_mut(4).zip(bytes.chunks_exact(4)) {
+            pixel.copy_from_slice(&[word[2], word[1], word[0], 0xff]);
+        }
+        for pixel in rgba.chunks_exact_mut(4).skip(bytes.len() / 4) {
+            pixel[3] = 0xff;
+        }
+        egui::ColorImage::from_rgba_unmultiplied([self.width, self.height], &rgba)
+    }
+}
+
+struct Gui<'a> {
+    cpu: &'a mut Cpu,
+    frontend: &'a mut Frontend,
+    framebuffer: Option<Framebuffer>,
+    texture: Option<TextureHandle>,
+    // contents of the text fields
+    breakpoint: String,
+    memory: String,
+    input: String,
+}
+
+// Opens the window and runs the loaded program from it until the window is closed, returns the
+// program's exit code or 0 if it didn't exit.
+pub fn run(cpu: &mut Cpu, framebuffer: Option<Framebuffer>) -> Result<u8, Error> {
+    let mut frontend = Frontend::new(cpu);
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default()
+            .with_title("ruscv")
+            .with_inner_size([1100.0, 760.0]),
+        ..Default::default()
+    };
+    let gui = Gui::new(cpu, &mut frontend, framebuffer);
+    let result = eframe::run_native("ruscv", options, Box::new(|_| Ok(Box::new(gui))));
+    result.map_err(|e| Error::Gui(e.to_string()))?;
+    frontend.end(cpu)
+}
+
+impl eframe::App for Gui<'_> {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.ui(ctx);
+    }
+}
+
+impl<'a> Gui<'a> {
+    fn new(cpu: &'a mut Cpu, frontend: &'a mut Frontend, framebuffer: Option<Framebuffer>) -> Self {
+        Gui {
+            cpu,
+            frontend,
+            framebuffer,
+            texture: None,
+            breakpoint: String::new(),
+            memory: String::new(),
+            input: String::new(),
+        }
+    }
+
+    fn ui(&mut self, ctx: &egui::Context) {
+        if self.frontend.running {
+            self.frontend.run_for(self.cpu, FRAME);
+            ctx.request_repaint();
+        }
+        egui::TopBottomPanel::top("controls").show(ctx, |ui| self.controls(ui));
+        egui::TopBottomPanel::bottom("console")
+            .resizable(true)
+            .default_height(160.0)
+            .show(ctx, |ui| self.console(ui));
+        egui::SidePanel::right("registers")
+            .resizable(false)
+            .show(ctx, |ui| {
+                self.registers(ui);
+                ui.separator();
+                self.stats(ui);
+            });
+        egui::CentralPanel::default().show(ctx, |ui| {
+            self.code(ui);
+            ui.separator();
+            self.memory_view(ui);
+        });
+        if let Some(framebuffer) = self.framebuffer {
+            let image = framebuffer.image(self.cpu);
+            let texture = match &mut self.texture {
+                Some(texture) => {
+                    texture.set(image, TextureOptions::NEAREST);
+                    texture
+                }
+                None => self.texture.insert(ctx.load_texture(
+                    "framebuffer",
+                    image,
+                    TextureOptions::NEAREST,
+                )),
+            };
+            let size = egui::vec2(framebuffer.width as f32, framebuffer.height as f32);
+            egui::Window::new("framebuffer").show(ctx, |ui| {
+                ui.image(egui::load::SizedTexture::new(
+                    texture.id(),
+                    size * PIXEL_SCALE,
+                ));
+            });
+        }
+    }
+
+    fn controls(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if ui.button("Step").clicked() {
+                self.frontend.step(self.cpu);
+            }
+            if self.frontend.running {
+                if ui.button("Pause").clicked() {
+                    self.frontend.pause(self.cpu);
+                }
+            } else if ui.button("Run").clicked() {
+                self.frontend.run(self.cpu);
+            }
+            if ui.button("Reset").clicked() {
+                self.frontend.reset(self.cpu);
+            }
+            ui.separator();
+            if entered(ui, &mut self.breakpoint, "break at, e.g. main if a0 == 5") {
+                self.frontend.add_breakpoint(self.cpu, &self.breakpoint);
+            }
+            if entered(ui, &mut self.memory, "memory at, e.g. sp + 16") {
+                self.frontend.show_memory(self.cpu, &self.memory);
+            }
+        });
+        ui.label(&self.frontend.message);
+    }
+
+    fn code(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Code");
+        let lines = self.frontend.code(self.cpu, CODE_ROWS);
+        if lines.is_empty() {
+            ui.monospace(format!("   {:08x}  (not in ram)", self.cpu.pc.get()));
+        }
+        for line in lines {
+            let marker = if line.breakpoint { "●" } else { " " };
+            let arrow = if line.current { "→" } else { " " };
+            let label = line
+                .label
+                .map_or(String::new(), |name| format!("  <{name}>"));
+            let text = format!("{marker}{arrow} {:08x}  {}{label}", line.address, line.text);
+            let mut text = RichText::new(text).monospace();
+            if line.current {
+                text = text.color(CHANGED).strong();
+            }
+            let response = ui.add(egui::Label::new(text).sense(egui::Sense::click()));
+            if response
+                .on_hover_text("click to toggle a breakpoint")
+                .clicked()
+            {
+                self.frontend.toggle_breakpoint(self.cpu, line.address);
+            }
+        }
+    }
+
+    fn memory_view(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.heading("Memory");
+            if ui.small_button("▲").clicked() {
+                self.frontend.scroll_memory(-(MEMORY_ROWS as i32));
+            }
+            if ui.small_button("▼").clicked() {
+                self.frontend.scroll_memory(MEMORY_ROWS as i32);
+            }
+        });
+        for line in self.frontend.memory_lines(self.cpu, MEMORY_ROWS) {
+            ui.monospace(line);
+        }
+    }
+
+    fn registers(&mut self, ui: &mut egui::Ui) {
+        ui.heading(format!("Registers, hart {}", self.cpu.hart()));
+        ui.monospace(format!("pc   {:#010x}", self.cpu.pc.get()));
+        egui::Grid::new("register grid").show(ui, |ui| {
+            for row in 0..16 {
+                for n in [row, row + 16] {
+                    let value = self.cpu.regs.read(n);
+                    let text = format!("{:<4} {value:#010x}", Reg::from_index(n).name());
+                    let mut text = RichText::new(text).monospace();
+                    if self.frontend.changed(self.cpu, n) {
+                        text = text.color(CHANGED).strong();
+                    }
+                    ui.label(text);
+                }
+                ui.end_row();
+            }
+        });
+    }
+
+    fn stats(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Stats");
+        let speed = self.frontend.speed(self.cpu);
+        egui::Grid::new("stats grid").show(ui, |ui| {
+            let rows = [
+                ("state", self.frontend.state()),
+                ("harts", self.cpu.harts().to_string()),
+                ("cycles", self.cpu.cycles().to_string()),
+                ("instructions", self.cpu.retired().to_string()),
+                ("speed", format!("{speed:.0} inst/s")),
+            ];
+            for (name, value) in rows {
+                ui.label(name);
+                ui.monospace(value);
+                ui.end_row();
+            }
+        });
+    }
+
+    fn console(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.heading("Console");
+            if self.frontend.has_uart() && entered(ui, &mut self.input, "type into the uart") {
+                self.frontend.type_bytes(self.input.as_bytes());
+                self.frontend.type_bytes(b"\n");
+                self.input.clear();
+            }
+        });
+        egui::ScrollArea::vertical()
+            .stick_to_bottom(true)
+            .auto_shrink(false)
+            .show(ui, |ui| ui.monospace(self.frontend.console()));
+    }
+}
+
+// a text field that returns true once enter was pressed in it
+fn entered(ui: &mut egui::Ui, text: &mut String, hint: &str) -> bool {
+    let field = egui::TextEdit::singleline(text).hint_text(hint);
+    let response = ui.add(field);
+    response.lost_focus() && ui.input(|input| input.key_pressed(egui::Key::Enter))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draws_the_panels() {
+        let source = "
+            li t0, 0x100
+            li t1, 0x00ff8000
+            sw t1, 4(t0)
+            li a0, 0
+            li a7, 93
+            ecall";
+        let mut cpu = Cpu::new(false);
+        cpu.load(crate::asm::assemble(source, 0).unwrap().bytes);
+        let mut frontend = Frontend::new(&mut cpu);
+        let framebuffer = Framebuffer {
+            address: 0x100,
+            width: 2,
+            height: 1,
+        };
+        let ctx = egui::Context::default();
+        let mut gui = Gui::new(&mut cpu, &mut frontend, Some(framebuffer));
+        gui.frontend.run(gui.cpu);
+        let _ = ctx.run(egui::RawInput::default(), |ctx| gui.ui(ctx));
+        assert_eq!(gui.frontend.state(), "exited with 0");
+
+        let image = framebuffer.image(gui.cpu);
+        assert_eq!(image.pixels[0], Color32::BLACK);
+        assert_eq!(image.pixels[1], Color32::from_rgb(0xff, 0x80, 0));
+        assert_eq!(gui.texture.as_ref().unwrap().size(), [2, 1]);
+    }
+}
@@ -0,0 +1,61 @@
+use crate::csr::{Csrs, MIP_HARDWARE, MIP_MSIP};
+use crate::pc::ProgramCounter;
+use crate::regs::Registers;
+use crate::trigger::Triggers;
+use crate::vector::VectorUnit;
+
+// Boot parameters of a single hart, unset values follow the boot convention of hart 0.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HartConfig {
+    pub entry: Option<u32>,
+    pub sp: Option<u32>,
+    // a parked hart doesn't execute until its software interrupt is raised through the clint
+    pub parked: bool,
+}
+
+// Architectural state of a hart that isn't running at the moment. The cpu executes one hart at a
+// time and swaps its state with the one stored here when switching to another hart.
+#[derive(Clone)]
+pub struct HartState {
+    pub pc: ProgramCounter,
+    pub regs: Registers,
+    pub csrs: Csrs,
+    pub vector: VectorUnit,
+    pub triggers: Triggers,
+    pub reservation: Option<u32>,
+    pub waiting: bool,
+    pub parked: bool,
+}
+
+impl HartState {
+    pub fn new(hartid: u32, vlen: u32) -> Self {
+        let mut csrs = Csrs::new();
+        csrs.hartid = hartid;
+        HartState {
+            pc: ProgramCounter::new(),
+            regs: Registers::new(),
+            csrs,
+            vector: VectorUnit::new(vlen),
+            triggers: Triggers::new(),
+            reservation: None,
+            waiting: false,
+            parked: false,
+        }
+    }
+
+    // whether the hart would execute given the interrupt lines asserted by the devices
+    pub fn runnable(&self, interrupts: u32) -> bool {
+        let mip = (self.csrs.mip & !MIP_HARDWARE) | interrupts;
+        resumes(self.waiting, self.parked, mip, self.csrs.mie)
+    }
+}
+
+// Parked harts only start once their software interrupt is pending, harts waiting in wfi resume
+// on any pending interrupt that is enabled in mie.
+fn resumes(waiting: bool, parked: bool, mip: u32, mie: u32) -> bool {
+    if parked {
+        mip & MIP_MSIP != 0
+    } else {
+        !waiting || mip & mie != 0
+    }
+}
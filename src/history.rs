@@ -0,0 +1,145 @@
+// Small ring buffers of recent execution state, printed when the emulation fails so that the
+// cause of a bad value can be found without rerunning with full tracing.
+use crate::decode::decode;
+use crate::regs::{Reg, RegChange, Registers};
+
+use std::collections::VecDeque;
+
+pub const DEFAULT_REG_HISTORY: usize = 16;
+pub const INST_HISTORY: usize = 32;
+
+pub struct RegWrite {
+    pub cycle: usize,
+    // address of the instruction that wrote the register
+    pub pc: u32,
+    pub change: RegChange,
+}
+
+// the last `capacity` register writes, oldest first
+pub struct RegHistory {
+    capacity: usize,
+    writes: VecDeque<RegWrite>,
+}
+
+impl RegHistory {
+    pub fn new(capacity: usize) -> Self {
+        RegHistory {
+            capacity,
+            writes: VecDeque::with_capacity(capacity),
+        }
+    }
+    pub fn record(&mut self, cycle: usize, pc: u32, change: RegChange) {
+        if self.writes.len() == self.capacity {
+            self.writes.pop_front();
+        }
+        self.writes.push_back(RegWrite { cycle, pc, change });
+    }
+    pub fn writes(&self) -> impl Iterator<Item = &RegWrite> {
+        self.writes.iter()
+    }
+    pub fn print(&self) {
+        eprintln!("Last {} register writes:", self.writes.len());
+        for write in &self.writes {
+            eprintln!(
+                "  cycle {:>8}  pc {:#010x}  {:<4} {:#010x} -> {:#010x}",
+                write.cycle,
+                write.pc,
+                write.change.reg.name(),
+                write.change.old,
+                write.change.new
+            );
+        }
+    }
+}
+
+pub struct ExecutedInst {
+    pub pc: u32,
+    pub raw_inst: u32,
+    // values of the source registers before the instruction executed
+    pub operands: [Option<(Reg, u32)>; 2],
+}
+
+// the last INST_HISTORY fetched instructions, including the one that failed
+pub struct InstHistory {
+    insts: VecDeque<ExecutedInst>,
+}
+
+impl InstHistory {
+    pub fn new() -> Self {
+        InstHistory {
+            insts: VecDeque::with_capacity(INST_HISTORY),
+        }
+    }
+    pub fn record(
+        &mut self,
+        pc: u32,
+        raw_inst: u32,
+        sources: [Option<usize>; 2],
+        regs: &Registers,
+    ) {
+        if self.insts.len() == INST_HISTORY {
+            self.insts.pop_front();
+        }
+        // an instruction reading the same register twice only shows it once
+        let sources = match sources {
+            [rs1, rs2] if rs1 == rs2 => [rs1, None],
+            sources => sources,
+        };
+        let operands = sources.map(|reg| {
+            reg.filter(|&reg| reg != 0)
+                .map(|reg| (Reg::from_index(reg), regs.read(reg)))
+        });
+        self.insts.push_back(ExecutedInst {
+            pc,
+            raw_inst,
+            operands,
+        });
+    }
+    pub fn insts(&self) -> impl Iterator<Item = &ExecutedInst> {
+        self.insts.iter()
+    }
+    pub fn print(&self) {
+        eprintln!("Last {} instructions:", self.insts.len());
+        for inst in &self.insts {
+            // instructions are decoded again, only their raw bits are kept
+            let disasm = match decode(inst.raw_inst) {
+                Ok(decoded) => decoded.to_string(),
+                Err(_) => "<invalid>".to_string(),
+            };
+            let operands: Vec<_> = inst
+                .operands
+                .iter()
+                .flatten()
+                .map(|(reg, value)| format!("{}={value:#x}", reg.name()))
+                .collect();
+            let line = format!(
+                "  {:#010x}: {:08x}  {disasm:<32} {}",
+                inst.pc,
+                inst.raw_inst,
+                operands.join(" ")
+            );
+            eprintln!("{}", line.trim_end());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regs::Reg;
+
+    #[test]
+    fn keeps_last_writes() {
+        let mut history = RegHistory::new(2);
+        for i in 0..3 {
+            let change = RegChange {
+                reg: Reg::T0,
+                old: i,
+                new: i + 1,
+            };
+            history.record(i as usize, 4 * i, change);
+        }
+        let cycles: Vec<_> = history.writes().map(|write| write.cycle).collect();
+        assert_eq!(cycles, [1, 2]);
+    }
+}
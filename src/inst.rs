@@ -1,12 +1,36 @@
 use crate::cpu::*;
+use crate::crypto;
+use crate::csr::{HpmEvent, Mode};
 use crate::get_bits;
 use crate::inst_format::*;
 use crate::memory::*;
+use crate::trap::Exception;
+use crate::trigger::Access;
+use crate::uop::{Uop, Uops};
+use crate::vector::VInst;
 
 use std::ops::BitAnd;
 use std::ops::BitOr;
 use std::ops::BitXor;
 
+// addi x0, x0, 0
+const NOP: u32 = 0x0000_0013;
+
+// The spec's hint encodings, which execute as nops but tell the hardware something about the
+// program. Passed to the hooks registered with Cpu::on_hint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hint {
+    // zihintpause, the hart is in a spin-wait loop
+    Pause,
+    // zicbop prefetch.i/r/w of the cache block at the address, encoded as ori to x0
+    Prefetch(Access, u32),
+    // zihintntl, the next memory access has no temporal locality at the level given by the
+    // register of add x0, x0, rs2: 2 is ntl.p1, 3 ntl.pall, 4 ntl.s1 and 5 ntl.all
+    NonTemporal(usize),
+    // any other integer instruction writing x0, reserved for future standard and custom hints
+    Reserved,
+}
+
 pub enum Inst {
     R(RInst, RFormat),
     I(IInst, IFormat),
@@ -14,17 +38,23 @@ pub enum Inst {
     B(BInst, BFormat),
     J(JFormat),
     U(UInst, UFormat),
-
-    // This isn't an official instruction but just so that the emulator doesn't crash on `ecall`.
-    // Only handles exit for now, every other syscall is ignored.
-    SysCall(SysCall),
-}
-
-pub enum SysCall {
-    Exit(u8),
-    Nop,
+    Csr(CsrInst, IFormat),
+    Amo(AmoInst, RFormat),
+    Aes(AesInst, RFormat),
+    Vector(VInst),
+    Mret,
+    Sret,
+    Wfi,
+    // there is no tlb to flush, see mmu.rs, the address and asid registers are kept for display
+    SfenceVma(RFormat),
+    Ebreak,
+    // interpreted by the cpu when it executes, see Cpu::ecall
+    Ecall,
+    // nop, memory is sequentially consistent and instructions are decoded on every fetch
+    Fence,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum RInst {
     ADD,
     SUB,
@@ -36,9 +66,36 @@ pub enum RInst {
     SRA,
     SLT,
     SLTU,
+    // M extension
+    MUL,
+    MULH,
+    MULHSU,
+    MULHU,
+    DIV,
+    DIVU,
+    REM,
+    REMU,
+    // Zicond extension
+    CZEROEQZ,
+    CZERONEZ,
+    // Zbkb and Zbkx extension
+    ANDN,
+    ORN,
+    XNOR,
+    ROL,
+    ROR,
+    PACK,
+    PACKH,
+    XPERM4,
+    XPERM8,
+    // unary Zbkb operations, these are only encoded as immediate instructions
+    BREV8,
+    REV8,
+    ZIP,
+    UNZIP,
 }
 impl RInst {
-    fn op(self) -> impl FnOnce(u32, u32) -> u32 {
+    pub(crate) fn op(self) -> impl FnOnce(u32, u32) -> u32 {
         match self {
             RInst::ADD => u32::wrapping_add,
             RInst::SUB => u32::wrapping_sub,
@@ -55,10 +112,41 @@ impl RInst {
             },
             RInst::SRA => |rs1, rs2| {
                 let amount = get_bits!(rs2, 0, 4, i32);
-                (rs1 as i32 >> amount as i32) as u32
+                (rs1 as i32 >> amount) as u32
             },
             RInst::SLT => |rs1, rs2| ((rs1 as i32) < (rs2 as i32)) as u32,
             RInst::SLTU => |rs1, rs2| (rs1 < rs2) as u32,
+            RInst::MUL => u32::wrapping_mul,
+            RInst::MULH => |rs1, rs2| ((rs1 as i32 as i64 * rs2 as i32 as i64) >> 32) as u32,
+            RInst::MULHSU => |rs1, rs2| ((rs1 as i32 as i64 * rs2 as i64) >> 32) as u32,
+            RInst::MULHU => |rs1, rs2| ((rs1 as u64 * rs2 as u64) >> 32) as u32,
+            // division by zero doesn't trap but returns all ones (the remainder the dividend)
+            // and signed overflow returns the dividend (the remainder zero)
+            RInst::DIV => |rs1, rs2| match rs2 {
+                0 => u32::MAX,
+                _ => (rs1 as i32).wrapping_div(rs2 as i32) as u32,
+            },
+            RInst::DIVU => |rs1: u32, rs2| rs1.checked_div(rs2).unwrap_or(u32::MAX),
+            RInst::REM => |rs1, rs2| match rs2 {
+                0 => rs1,
+                _ => (rs1 as i32).wrapping_rem(rs2 as i32) as u32,
+            },
+            RInst::REMU => |rs1: u32, rs2| rs1.checked_rem(rs2).unwrap_or(rs1),
+            RInst::CZEROEQZ => |rs1, rs2| if rs2 == 0 { 0 } else { rs1 },
+            RInst::CZERONEZ => |rs1, rs2| if rs2 != 0 { 0 } else { rs1 },
+            RInst::ANDN => |rs1, rs2: u32| rs1 & !rs2,
+            RInst::ORN => |rs1, rs2: u32| rs1 | !rs2,
+            RInst::XNOR => |rs1: u32, rs2: u32| !(rs1 ^ rs2),
+            RInst::ROL => |rs1: u32, rs2| rs1.rotate_left(rs2 & 0x1f),
+            RInst::ROR => |rs1: u32, rs2| rs1.rotate_right(rs2 & 0x1f),
+            RInst::PACK => |rs1, rs2| (rs2 << 16) | (rs1 & 0xffff),
+            RInst::PACKH => |rs1, rs2| ((rs2 & 0xff) << 8) | (rs1 & 0xff),
+            RInst::XPERM4 => |rs1, rs2| crypto::xperm(rs1, rs2, 4),
+            RInst::XPERM8 => |rs1, rs2| crypto::xperm(rs1, rs2, 8),
+            RInst::BREV8 => |rs1, _| crypto::brev8(rs1),
+            RInst::REV8 => |rs1: u32, _| rs1.swap_bytes(),
+            RInst::ZIP => |rs1, _| crypto::zip(rs1),
+            RInst::UNZIP => |rs1, _| crypto::unzip(rs1),
         }
     }
 }
@@ -74,10 +162,16 @@ impl From<ArithIInst> for RInst {
             ArithIInst::SRAI => RInst::SRA,
             ArithIInst::SLTI => RInst::SLT,
             ArithIInst::SLTIU => RInst::SLTU,
+            ArithIInst::RORI => RInst::ROR,
+            ArithIInst::BREV8 => RInst::BREV8,
+            ArithIInst::REV8 => RInst::REV8,
+            ArithIInst::ZIP => RInst::ZIP,
+            ArithIInst::UNZIP => RInst::UNZIP,
         }
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum ArithIInst {
     ADDI,
     XORI,
@@ -88,8 +182,14 @@ pub enum ArithIInst {
     SRAI,
     SLTI,
     SLTIU,
+    RORI,
+    BREV8,
+    REV8,
+    ZIP,
+    UNZIP,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum LoadIInst {
     LB,
     LH,
@@ -102,51 +202,20 @@ impl LoadIInst {
         matches!(self, LoadIInst::LBU | LoadIInst::LHU)
     }
 }
-impl LoadIInst {
-    fn op(self, mem: &Memory) -> impl FnOnce(u32, u32) -> u32 + '_ {
-        move |rs1, imm| {
-            let from = u32::wrapping_add(rs1, imm);
-            let is_unsigned = self.is_unsigned();
-            mem.read(Size::from(self), from, is_unsigned)
-        }
-    }
-}
 
 pub enum IInst {
     Arith(ArithIInst),
     Mem(LoadIInst),
     Jalr,
 }
-impl IInst {
-    fn op(self, cpu: &mut Cpu) -> Box<dyn FnOnce(u32, u32) -> u32 + '_> {
-        match self {
-            // Arithmetic operations are the same for R/I format, only the second operand differs.
-            IInst::Arith(inst) => Box::new(RInst::from(inst).op()),
-            IInst::Mem(inst) => Box::new(inst.op(&cpu.mem)),
-            IInst::Jalr => Box::new(|rs1, imm| {
-                let original_pc = cpu.pc.get();
-                cpu.pc.set(u32::wrapping_add(rs1, imm));
-                original_pc
-            }),
-        }
-    }
-}
-
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum SInst {
     SB,
     SH,
     SW,
 }
 
-impl SInst {
-    fn op(self, mem: &mut Memory) -> impl FnOnce(u32, u32, u32) + '_ {
-        move |rs1, rs2, imm| {
-            let address = u32::wrapping_add(rs1, imm);
-            mem.write(Size::from(self), address, rs2)
-        }
-    }
-}
-
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum BInst {
     BEQ,
     BNE,
@@ -155,72 +224,512 @@ pub enum BInst {
     BLTU,
     BGEU,
 }
+impl BInst {
+    pub(crate) fn taken(self, rs1: u32, rs2: u32) -> bool {
+        match self {
+            BInst::BEQ => rs1 == rs2,
+            BInst::BNE => rs1 != rs2,
+            BInst::BLT => (rs1 as i32) < rs2 as i32,
+            BInst::BLTU => rs1 < rs2,
+            BInst::BGE => rs1 as i32 >= rs2 as i32,
+            BInst::BGEU => rs1 >= rs2,
+        }
+    }
+}
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum UInst {
     LUI,
     AUIPC,
 }
-impl UInst {
-    fn op(self, pc: u32) -> impl FnOnce(u32) -> u32 {
-        move |imm| match self {
-            UInst::LUI => imm << 12,
-            UInst::AUIPC => u32::wrapping_add(pc - 4, imm << 12),
+
+// Zicsr extension, the immediate variants use the rs1 field as a 5-bit zero-extended immediate
+pub enum CsrInst {
+    CSRRW,
+    CSRRS,
+    CSRRC,
+    CSRRWI,
+    CSRRSI,
+    CSRRCI,
+}
+impl CsrInst {
+    // Atomically reads the csr into rd and writes the new value. csrrw doesn't read if rd is x0
+    // and csrrs/csrrc don't write if rs1 is x0, so side-effects only happen when intended.
+    // Accessing a csr that doesn't exist or writing a read-only one is an illegal instruction.
+    fn execute(self, cpu: &mut Cpu, format: IFormat) -> Result<(), Exception> {
+        // the caller fills in the instruction bits
+        let illegal = Exception::IllegalInstruction(0);
+        let csr = get_bits!(format.imm, 0, 11) as u16;
+        if !cpu.csrs.accessible(csr) {
+            return Err(illegal);
+        }
+        let src = match self {
+            CsrInst::CSRRW | CsrInst::CSRRS | CsrInst::CSRRC => cpu.regs.read(format.rs1),
+            CsrInst::CSRRWI | CsrInst::CSRRSI | CsrInst::CSRRCI => format.rs1 as u32,
+        };
+        let old = cpu.read_csr(csr).ok_or(illegal)?;
+        let new = match self {
+            CsrInst::CSRRW | CsrInst::CSRRWI => Some(src),
+            _ if format.rs1 == 0 => None,
+            CsrInst::CSRRS | CsrInst::CSRRSI => Some(old | src),
+            CsrInst::CSRRC | CsrInst::CSRRCI => Some(old & !src),
+        };
+        if let Some(new) = new {
+            cpu.write_csr(csr, new).ok_or(illegal)?;
         }
+        cpu.regs.write(format.rd, old);
+        Ok(())
     }
 }
 
-impl Inst {
-    pub fn execute(self, cpu: &mut Cpu) {
+// A extension, only word-sized operations exist on rv32. The aq/rl bits are ignored since harts
+// interleave whole instructions and every store is immediately visible to all of them.
+pub enum AmoInst {
+    LR,
+    SC,
+    SWAP,
+    ADD,
+    XOR,
+    AND,
+    OR,
+    MIN,
+    MAX,
+    MINU,
+    MAXU,
+}
+impl AmoInst {
+    fn op(self) -> impl FnOnce(u32, u32) -> u32 {
         match self {
-            Inst::R(inst, format) => {
-                let alu = inst.op();
-                let result = alu(cpu.regs.read(format.rs1), cpu.regs.read(format.rs2));
-                cpu.regs.write(format.rd, result);
+            AmoInst::SWAP => |_, rs2| rs2,
+            AmoInst::ADD => u32::wrapping_add,
+            AmoInst::XOR => u32::bitxor,
+            AmoInst::AND => u32::bitand,
+            AmoInst::OR => u32::bitor,
+            AmoInst::MIN => |mem, rs2| (mem as i32).min(rs2 as i32) as u32,
+            AmoInst::MAX => |mem, rs2| (mem as i32).max(rs2 as i32) as u32,
+            AmoInst::MINU => u32::min,
+            AmoInst::MAXU => u32::max,
+            AmoInst::LR | AmoInst::SC => unreachable!("lr/sc don't modify memory with an alu op"),
+        }
+    }
+
+    // Atomic memory operations must be naturally aligned. Since all of them write memory,
+    // faults are reported as store faults, except for lr which only loads. Reservations are held
+    // on physical addresses, like the stores that invalidate them.
+    fn execute(self, cpu: &mut Cpu, format: RFormat) -> Result<(), Exception> {
+        let address = cpu.regs.read(format.rs1);
+        let rs2 = cpu.regs.read(format.rs2);
+        if !address.is_multiple_of(4) {
+            return Err(match self {
+                AmoInst::LR => Exception::LoadAddressMisaligned(address),
+                _ => Exception::StoreAddressMisaligned(address),
+            });
+        }
+        let access = match self {
+            AmoInst::LR => Access::Load,
+            AmoInst::SC => Access::Store,
+            _ => Access::LoadStore,
+        };
+        let physical = cpu.translate_access(address, Size::Word, access)?;
+        let store_fault = |_| Exception::StoreAccessFault(address);
+        match self {
+            AmoInst::LR => {
+                let value = cpu
+                    .mem
+                    .load(Size::Word, physical, true)
+                    .map_err(|_| Exception::LoadAccessFault(address))?;
+                cpu.reservation = Some(physical);
+                cpu.regs.write(format.rd, value);
             }
-            Inst::I(inst, format) => {
-                let rs1 = cpu.regs.read(format.rs1);
-                let alu = inst.op(cpu);
-                let result = alu(rs1, format.imm);
+            AmoInst::SC => {
+                // sc always invalidates the reservation, whether it succeeds or not
+                let result = if cpu.reservation.take() == Some(physical) {
+                    cpu.mem
+                        .store(Size::Word, physical, rs2)
+                        .map_err(store_fault)?;
+                    0
+                } else {
+                    1
+                };
                 cpu.regs.write(format.rd, result);
             }
-            Inst::S(inst, format) => {
-                let rs1 = cpu.regs.read(format.rs1);
-                let rs2 = cpu.regs.read(format.rs2);
-                let alu = inst.op(&mut cpu.mem);
-                alu(rs1, rs2, format.imm);
+            inst => {
+                let old = cpu
+                    .mem
+                    .load(Size::Word, physical, true)
+                    .map_err(store_fault)?;
+                cpu.mem
+                    .store(Size::Word, physical, inst.op()(old, rs2))
+                    .map_err(store_fault)?;
+                cpu.regs.write(format.rd, old);
             }
-            Inst::B(inst, format) => {
-                let rs1 = cpu.regs.read(format.rs1);
-                let rs2 = cpu.regs.read(format.rs2);
-                let branch = match inst {
-                    BInst::BEQ => rs1 == rs2,
-                    BInst::BNE => rs1 != rs2,
-                    BInst::BLT => rs1 as i32 <= rs2 as i32,
-                    BInst::BLTU => rs1 <= rs2,
-                    BInst::BGE => rs1 as i32 >= rs2 as i32,
-                    BInst::BGEU => rs1 >= rs2,
+        }
+        Ok(())
+    }
+}
+
+// Zknd and Zkne extension, each instruction computes one byte (selected by bs) of an aes round
+pub enum AesInst {
+    ESI,
+    ESMI,
+    DSI,
+    DSMI,
+}
+impl AesInst {
+    fn op(self, bs: u32) -> impl FnOnce(u32, u32) -> u32 {
+        move |rs1, rs2| match self {
+            AesInst::ESI => crypto::aes32(rs1, rs2, bs, false, false),
+            AesInst::ESMI => crypto::aes32(rs1, rs2, bs, false, true),
+            AesInst::DSI => crypto::aes32(rs1, rs2, bs, true, false),
+            AesInst::DSMI => crypto::aes32(rs1, rs2, bs, true, true),
+        }
+    }
+}
+
+impl Inst {
+    // the performance monitor event counted once the instruction retires
+    pub fn event(&self) -> Option<HpmEvent> {
+        if let Some(uops) = self.lower(0) {
+            return uops.event();
+        }
+        match self {
+            Inst::Amo(AmoInst::LR, _) => Some(HpmEvent::Load),
+            Inst::Vector(VInst::Load { .. }) => Some(HpmEvent::Load),
+            // amos read and write memory but count as stores like sc does
+            Inst::Amo(..) => Some(HpmEvent::Store),
+            Inst::Vector(VInst::Store { .. }) => Some(HpmEvent::Store),
+            _ => None,
+        }
+    }
+
+    // What an instruction that is architecturally defined but executed as a nop would have done,
+    // None for instructions whose semantics are emulated in full.
+    pub fn ignored_semantics(&self, raw_inst: u32) -> Option<&'static str> {
+        if self.writes_x0(raw_inst) {
+            return Some("hint: the result written to x0 is discarded");
+        }
+        match self {
+            // funct3 tells fence.i apart, fm/pred/succ the pause hint and fence.tso
+            Inst::Fence => Some(match (raw_inst >> 12 & 0b111, raw_inst >> 20) {
+                (1, _) => "fence.i: there is no instruction cache to synchronize",
+                (_, 0x010) => "pause: spin-wait loops aren't slowed down",
+                (_, 0x833) => "fence.tso: memory ordering isn't modelled",
+                _ => "fence: memory ordering isn't modelled",
+            }),
+            _ => None,
+        }
+    }
+
+    // integer instructions whose result is discarded, the canonical nop aside
+    fn writes_x0(&self, raw_inst: u32) -> bool {
+        match self {
+            Inst::R(_, format) | Inst::Aes(_, format) => format.rd == 0,
+            Inst::I(IInst::Arith(_), format) => format.rd == 0 && raw_inst != NOP,
+            Inst::U(_, format) => format.rd == 0,
+            _ => false,
+        }
+    }
+
+    // The hint the encoding stands for, if any. The addresses of prefetches are computed from the
+    // registers before the instruction executes.
+    pub fn hint(&self, raw_inst: u32, cpu: &Cpu) -> Option<Hint> {
+        match self {
+            Inst::Fence if raw_inst >> 12 & 0b111 == 0 && raw_inst >> 20 == 0x010 => {
+                Some(Hint::Pause)
+            }
+            Inst::I(IInst::Arith(ArithIInst::ORI), format) if format.rd == 0 => {
+                let access = match format.imm & 0x1f {
+                    0 => Access::Execute,
+                    1 => Access::Load,
+                    3 => Access::Store,
+                    _ => return Some(Hint::Reserved),
                 };
-                if branch {
-                    cpu.pc.set(u32::wrapping_add(
-                        cpu.pc.get(),
-                        u32::wrapping_sub(format.imm, 4),
-                    ));
+                let address = cpu.regs.read(format.rs1).wrapping_add(format.imm & !0x1f);
+                Some(Hint::Prefetch(access, address))
+            }
+            Inst::R(RInst::ADD, format)
+                if format.rd == 0 && format.rs1 == 0 && (2..=5).contains(&format.rs2) =>
+            {
+                Some(Hint::NonTemporal(format.rs2))
+            }
+            _ => self.writes_x0(raw_inst).then_some(Hint::Reserved),
+        }
+    }
+
+    // the scalar registers the instruction reads
+    pub fn sources(&self) -> [Option<usize>; 2] {
+        if let Some(uops) = self.lower(0) {
+            let mut sources = uops.sources();
+            return [sources.next(), sources.next()];
+        }
+        match self {
+            Inst::Aes(_, format) => [Some(format.rs1), Some(format.rs2)],
+            Inst::Amo(AmoInst::LR, format) => [Some(format.rs1), None],
+            Inst::Amo(_, format) => [Some(format.rs1), Some(format.rs2)],
+            Inst::Csr(CsrInst::CSRRW | CsrInst::CSRRS | CsrInst::CSRRC, format) => {
+                [Some(format.rs1), None]
+            }
+            Inst::Vector(VInst::Load { rs1, stride, .. } | VInst::Store { rs1, stride, .. }) => {
+                [Some(*rs1), *stride]
+            }
+            _ => [None, None],
+        }
+    }
+
+    // the memory access the instruction performs, checked against the data triggers
+    pub fn access(&self, cpu: &Cpu) -> Option<(Access, u32)> {
+        if let Some(uops) = self.lower(0) {
+            return uops
+                .access(cpu)
+                .map(|(access, address, _)| (access, address));
+        }
+        match self {
+            Inst::Amo(AmoInst::LR, format) => Some((Access::Load, cpu.regs.read(format.rs1))),
+            Inst::Amo(AmoInst::SC, format) => Some((Access::Store, cpu.regs.read(format.rs1))),
+            Inst::Amo(_, format) => Some((Access::LoadStore, cpu.regs.read(format.rs1))),
+            _ => None,
+        }
+    }
+
+    // bytes accessed by the instruction at the address returned by `access`
+    pub fn access_size(&self) -> u32 {
+        let size = |uop: &Uop| match *uop {
+            Uop::Load { size, .. } | Uop::Store { size, .. } => Some(size as u32),
+            _ => None,
+        };
+        match self {
+            Inst::Amo(..) => 4,
+            inst => inst
+                .lower(0)
+                .and_then(|uops| uops.iter().find_map(size))
+                .unwrap_or(0),
+        }
+    }
+
+    // The micro-ops of the integer instructions for the instruction at pc, None for the ones
+    // executed directly. Each operand is read once, before anything is written, so rd may be
+    // one of the sources, and registers aren't written if a load, store or jump traps.
+    pub fn lower(&self, pc: u32) -> Option<Uops> {
+        let mut uops = Uops::new();
+        match *self {
+            Inst::R(op, ref format) => {
+                uops.push(Uop::ReadReg {
+                    t: 0,
+                    reg: format.rs1,
+                });
+                uops.push(Uop::ReadReg {
+                    t: 1,
+                    reg: format.rs2,
+                });
+                uops.push(Uop::Alu {
+                    op,
+                    t: 0,
+                    a: 0,
+                    b: 1,
+                });
+                uops.push(Uop::WriteReg {
+                    reg: format.rd,
+                    t: 0,
+                });
+            }
+            Inst::I(ref inst, ref format) => {
+                uops.push(Uop::ReadReg {
+                    t: 0,
+                    reg: format.rs1,
+                });
+                uops.push(Uop::Const {
+                    t: 1,
+                    value: format.imm,
+                });
+                match *inst {
+                    // arithmetic operations are the same for R/I format, only the second operand
+                    // differs
+                    IInst::Arith(inst) => {
+                        let op = RInst::from(inst);
+                        uops.push(Uop::Alu {
+                            op,
+                            t: 0,
+                            a: 0,
+                            b: 1,
+                        });
+                    }
+                    IInst::Mem(inst) => {
+                        uops.push(Uop::Alu {
+                            op: RInst::ADD,
+                            t: 0,
+                            a: 0,
+                            b: 1,
+                        });
+                        uops.push(Uop::Load {
+                            t: 0,
+                            address: 0,
+                            size: Size::from(inst),
+                            unsigned: inst.is_unsigned(),
+                        });
+                    }
+                    // The target has its lowest bit cleared. Targets outside of memory raise an
+                    // access fault when they're fetched.
+                    IInst::Jalr => {
+                        uops.push(Uop::Alu {
+                            op: RInst::ADD,
+                            t: 0,
+                            a: 0,
+                            b: 1,
+                        });
+                        uops.push(Uop::Const { t: 1, value: !1 });
+                        uops.push(Uop::Alu {
+                            op: RInst::AND,
+                            t: 0,
+                            a: 0,
+                            b: 1,
+                        });
+                        uops.push(Uop::Const {
+                            t: 1,
+                            value: pc.wrapping_add(4),
+                        });
+                        uops.push(Uop::Branch {
+                            cond: None,
+                            a: 0,
+                            b: 0,
+                            target: 0,
+                        });
+                        uops.push(Uop::WriteReg {
+                            reg: format.rd,
+                            t: 1,
+                        });
+                        return Some(uops);
+                    }
                 }
+                uops.push(Uop::WriteReg {
+                    reg: format.rd,
+                    t: 0,
+                });
+            }
+            Inst::S(inst, ref format) => {
+                uops.push(Uop::ReadReg {
+                    t: 0,
+                    reg: format.rs1,
+                });
+                uops.push(Uop::ReadReg {
+                    t: 1,
+                    reg: format.rs2,
+                });
+                uops.push(Uop::Const {
+                    t: 2,
+                    value: format.imm,
+                });
+                uops.push(Uop::Alu {
+                    op: RInst::ADD,
+                    t: 0,
+                    a: 0,
+                    b: 2,
+                });
+                uops.push(Uop::Store {
+                    address: 0,
+                    value: 1,
+                    size: Size::from(inst),
+                });
             }
-            Inst::J(format) => {
-                cpu.regs.write(format.rd, cpu.pc.get());
-                cpu.pc.set(u32::wrapping_add(
-                    cpu.pc.get(),
-                    u32::wrapping_sub(format.imm, 4),
-                ));
+            Inst::B(inst, ref format) => {
+                uops.push(Uop::ReadReg {
+                    t: 0,
+                    reg: format.rs1,
+                });
+                uops.push(Uop::ReadReg {
+                    t: 1,
+                    reg: format.rs2,
+                });
+                uops.push(Uop::Const {
+                    t: 2,
+                    value: pc.wrapping_add(format.imm),
+                });
+                uops.push(Uop::Branch {
+                    cond: Some(inst),
+                    a: 0,
+                    b: 1,
+                    target: 2,
+                });
             }
-            Inst::U(inst, format) => {
-                let alu = inst.op(cpu.pc.get());
-                let result = alu(format.imm);
+            Inst::J(ref format) => {
+                uops.push(Uop::Const {
+                    t: 0,
+                    value: pc.wrapping_add(format.imm),
+                });
+                uops.push(Uop::Const {
+                    t: 1,
+                    value: pc.wrapping_add(4),
+                });
+                uops.push(Uop::Branch {
+                    cond: None,
+                    a: 0,
+                    b: 0,
+                    target: 0,
+                });
+                uops.push(Uop::WriteReg {
+                    reg: format.rd,
+                    t: 1,
+                });
+            }
+            Inst::U(inst, ref format) => {
+                let value = match inst {
+                    UInst::LUI => format.imm << 12,
+                    UInst::AUIPC => pc.wrapping_add(format.imm << 12),
+                };
+                uops.push(Uop::Const { t: 0, value });
+                uops.push(Uop::WriteReg {
+                    reg: format.rd,
+                    t: 0,
+                });
+            }
+            _ => return None,
+        }
+        Some(uops)
+    }
+
+    pub fn execute(self, cpu: &mut Cpu) -> Result<(), Exception> {
+        // the pc already points at the next instruction
+        if let Some(uops) = self.lower(cpu.pc.get().wrapping_sub(4)) {
+            return uops.execute(cpu);
+        }
+        match self {
+            Inst::Csr(inst, format) => inst.execute(cpu, format)?,
+            Inst::Amo(inst, format) => inst.execute(cpu, format)?,
+            Inst::Vector(inst) => inst.execute(cpu)?,
+            Inst::Aes(inst, format) => {
+                // the byte select is stored in the upper two bits of funct7
+                let aes = inst.op(format.funct7 as u32 >> 5);
+                let result = aes(cpu.regs.read(format.rs1), cpu.regs.read(format.rs2));
                 cpu.regs.write(format.rd, result);
             }
-            Inst::SysCall(..) => {}
+            // the privileged instructions are illegal below the mode they belong to, user mode
+            // may not wait for interrupts
+            Inst::Mret if cpu.csrs.mode != Mode::Machine => {
+                return Err(Exception::IllegalInstruction(0))
+            }
+            Inst::Sret | Inst::Wfi | Inst::SfenceVma(_) if cpu.csrs.mode == Mode::User => {
+                return Err(Exception::IllegalInstruction(0))
+            }
+            Inst::Mret => {
+                cpu.csrs.pop_interrupt_enable();
+                cpu.pc.set(cpu.csrs.mepc);
+            }
+            Inst::Sret => {
+                cpu.csrs.pop_supervisor_interrupt_enable();
+                cpu.pc.set(cpu.csrs.sepc);
+            }
+            // with no interrupt enabled nothing could wake the hart, so wfi is a nop
+            Inst::Wfi => cpu.waiting = cpu.csrs.mie != 0,
+            Inst::SfenceVma(_) => {}
+            Inst::Ebreak => {
+                return Err(Exception::Breakpoint(cpu.pc.get().wrapping_sub(4)));
+            }
+            Inst::Ecall => cpu.ecall()?,
+            Inst::Fence => {}
+            Inst::R(..) | Inst::I(..) | Inst::S(..) | Inst::B(..) | Inst::J(_) | Inst::U(..) => {
+                unreachable!("integer instructions are lowered")
+            }
         }
+        Ok(())
     }
 }
 
@@ -228,6 +737,41 @@ impl Inst {
 mod tests {
     use super::*;
 
+    #[test]
+    fn nop_encodings() {
+        let ignored = |raw_inst| crate::decode(raw_inst).unwrap().ignored_semantics(raw_inst);
+        assert_eq!(ignored(NOP), None);
+        assert_eq!(ignored(0x00150513), None); // addi a0, a0, 1
+        assert!(ignored(0x00150013).unwrap().starts_with("hint")); // addi zero, a0, 1
+        assert!(ignored(0x00b50033).unwrap().starts_with("hint")); // add zero, a0, a1
+        assert!(ignored(0x0ff0000f).unwrap().starts_with("fence:"));
+        assert!(ignored(0x0000100f).unwrap().starts_with("fence.i"));
+        assert!(ignored(0x0100000f).unwrap().starts_with("pause"));
+        assert!(ignored(0x8330000f).unwrap().starts_with("fence.tso"));
+    }
+
+    #[test]
+    fn hint_encodings() {
+        let mut cpu = Cpu::new(false);
+        cpu.regs.write(10, 0x1000);
+        let hint = |raw_inst| crate::decode(raw_inst).unwrap().hint(raw_inst, &cpu);
+        assert_eq!(hint(NOP), None);
+        assert_eq!(hint(0x00150513), None); // addi a0, a0, 1
+        assert_eq!(hint(0x0ff0000f), None); // fence
+        assert_eq!(hint(0x0100000f), Some(Hint::Pause));
+        // prefetch.r 64(a0), prefetch.w -32(a0) and prefetch.i 0(a0)
+        assert_eq!(hint(0x04156013), Some(Hint::Prefetch(Access::Load, 0x1040)));
+        assert_eq!(hint(0xfe356013), Some(Hint::Prefetch(Access::Store, 0xfe0)));
+        assert_eq!(
+            hint(0x00056013),
+            Some(Hint::Prefetch(Access::Execute, 0x1000))
+        );
+        assert_eq!(hint(0x00500033), Some(Hint::NonTemporal(5))); // ntl.all
+        assert_eq!(hint(0x00256013), Some(Hint::Reserved)); // ori zero, a0, 2
+        assert_eq!(hint(0x00b50033), Some(Hint::Reserved)); // add zero, a0, a1
+        assert_eq!(hint(0x00001037), Some(Hint::Reserved)); // lui zero, 1
+    }
+
     #[test]
     fn store_assigns_byte() {
         let mut cpu = Cpu::new(false);
@@ -242,24 +786,57 @@ mod tests {
                 imm: 3,
             },
         );
-        inst.execute(&mut cpu);
+        inst.execute(&mut cpu).unwrap();
         assert_eq!(cpu.mem.read(Size::Byte, 3, true), 12);
     }
 
+    fn alu(inst: RInst, rs1: u32, rs2: u32) -> u32 {
+        inst.op()(rs1, rs2)
+    }
+
+    #[test]
+    fn multiply() {
+        assert_eq!(alu(RInst::MUL, -3i32 as u32, 7), -21i32 as u32);
+        assert_eq!(alu(RInst::MULH, -1i32 as u32, -1i32 as u32), 0);
+        assert_eq!(alu(RInst::MULHU, u32::MAX, u32::MAX), 0xffff_fffe);
+        assert_eq!(alu(RInst::MULHSU, -1i32 as u32, u32::MAX), u32::MAX);
+    }
+
+    #[test]
+    fn divide_edge_cases() {
+        assert_eq!(alu(RInst::DIV, -7i32 as u32, 2), -3i32 as u32);
+        assert_eq!(alu(RInst::REM, -7i32 as u32, 2), -1i32 as u32);
+        assert_eq!(alu(RInst::DIV, 5, 0), u32::MAX);
+        assert_eq!(alu(RInst::DIVU, 5, 0), u32::MAX);
+        assert_eq!(alu(RInst::REM, 5, 0), 5);
+        assert_eq!(alu(RInst::REMU, 5, 0), 5);
+        let min = i32::MIN as u32;
+        assert_eq!(alu(RInst::DIV, min, -1i32 as u32), min);
+        assert_eq!(alu(RInst::REM, min, -1i32 as u32), 0);
+    }
+
+    #[test]
+    fn conditional_zero() {
+        assert_eq!(alu(RInst::CZEROEQZ, 42, 0), 0);
+        assert_eq!(alu(RInst::CZEROEQZ, 42, 1), 42);
+        assert_eq!(alu(RInst::CZERONEZ, 42, 0), 42);
+        assert_eq!(alu(RInst::CZERONEZ, 42, u32::MAX), 0);
+    }
+
     #[test]
     fn lui() {
         let mut cpu = Cpu::new(false);
 
         let inst = Inst::U(UInst::LUI, UFormat { rd: 10, imm: 1 });
-        inst.execute(&mut cpu);
+        inst.execute(&mut cpu).unwrap();
         assert_eq!(cpu.regs.read(10), 4096);
 
         let inst = Inst::U(UInst::LUI, UFormat { rd: 10, imm: 3 });
-        inst.execute(&mut cpu);
+        inst.execute(&mut cpu).unwrap();
         assert_eq!(cpu.regs.read(10), 12288);
 
         let inst = Inst::U(UInst::LUI, UFormat { rd: 10, imm: 0x100 });
-        inst.execute(&mut cpu);
+        inst.execute(&mut cpu).unwrap();
         assert_eq!(cpu.regs.read(10), 1048576);
     }
 
@@ -273,7 +850,7 @@ mod tests {
                 imm: 0b1111_1111_1111_1111,
             },
         );
-        inst.execute(&mut cpu);
+        inst.execute(&mut cpu).unwrap();
         assert_eq!(cpu.regs.read(10), 0b1111_1111_1111_1111_0000_0000_0000);
     }
 
@@ -292,7 +869,7 @@ mod tests {
                 imm: 0x03000,
             },
         );
-        auipc_inst.execute(&mut cpu);
+        auipc_inst.execute(&mut cpu).unwrap();
         assert_eq!(cpu.regs.read(5), 0x43000000);
 
         // manually increment pc since no fetch phase
@@ -307,7 +884,7 @@ mod tests {
                 imm: -0x400i32 as u32,
             },
         );
-        jalr_inst.execute(&mut cpu);
+        jalr_inst.execute(&mut cpu).unwrap();
         assert_eq!(cpu.regs.read(10), 0x40000008);
         assert_eq!(cpu.pc.get(), 0x42fffc00);
     }
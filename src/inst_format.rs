@@ -42,6 +42,15 @@ impl RFormat {
             rs2,
         }
     }
+    // the inverse of new, only the lowest bits of each field are used
+    pub fn encode(&self, opcode: u32) -> u32 {
+        (self.funct7 as u32 & 0x7f) << 25
+            | (self.rs2 as u32 & 0x1f) << 20
+            | (self.rs1 as u32 & 0x1f) << 15
+            | (self.funct3 as u32 & 0x7) << 12
+            | (self.rd as u32 & 0x1f) << 7
+            | opcode & 0x7f
+    }
 }
 
 pub struct IFormat {
@@ -65,6 +74,13 @@ impl IFormat {
             imm,
         }
     }
+    pub fn encode(&self, opcode: u32) -> u32 {
+        (self.imm & 0xfff) << 20
+            | (self.rs1 as u32 & 0x1f) << 15
+            | (self.funct3 as u32 & 0x7) << 12
+            | (self.rd as u32 & 0x1f) << 7
+            | opcode & 0x7f
+    }
 }
 
 pub struct SFormat {
@@ -89,6 +105,14 @@ impl SFormat {
             imm,
         }
     }
+    pub fn encode(&self, opcode: u32) -> u32 {
+        get_bits!(self.imm, 5, 11, u32) << 25
+            | (self.rs2 as u32 & 0x1f) << 20
+            | (self.rs1 as u32 & 0x1f) << 15
+            | (self.funct3 as u32 & 0x7) << 12
+            | get_bits!(self.imm, 0, 4, u32) << 7
+            | opcode & 0x7f
+    }
 }
 
 pub struct BFormat {
@@ -122,6 +146,17 @@ impl BFormat {
             imm,
         }
     }
+    // the lowest bit of the offset is always zero and isn't encoded
+    pub fn encode(&self, opcode: u32) -> u32 {
+        get_bits!(self.imm, 12, 12, u32) << 31
+            | get_bits!(self.imm, 5, 10, u32) << 25
+            | (self.rs2 as u32 & 0x1f) << 20
+            | (self.rs1 as u32 & 0x1f) << 15
+            | (self.funct3 as u32 & 0x7) << 12
+            | get_bits!(self.imm, 1, 4, u32) << 8
+            | get_bits!(self.imm, 11, 11, u32) << 7
+            | opcode & 0x7f
+    }
 }
 
 pub struct JFormat {
@@ -140,6 +175,14 @@ impl JFormat {
 
         JFormat { rd, imm }
     }
+    pub fn encode(&self, opcode: u32) -> u32 {
+        get_bits!(self.imm, 20, 20, u32) << 31
+            | get_bits!(self.imm, 1, 10, u32) << 21
+            | get_bits!(self.imm, 11, 11, u32) << 20
+            | get_bits!(self.imm, 12, 19, u32) << 12
+            | (self.rd as u32 & 0x1f) << 7
+            | opcode & 0x7f
+    }
 }
 
 pub struct UFormat {
@@ -153,11 +196,16 @@ impl UFormat {
 
         UFormat { rd, imm }
     }
+    // imm holds the upper 20 bits shifted down
+    pub fn encode(&self, opcode: u32) -> u32 {
+        (self.imm & 0xf_ffff) << 12 | (self.rd as u32 & 0x1f) << 7 | opcode & 0x7f
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn get_bits_base() {
@@ -180,4 +228,86 @@ mod tests {
             -12
         );
     }
+
+    // Generates random fields, encodes them and checks that decoding yields the same fields.
+    // Immediates are generated as the sign-extended values the decoder produces.
+    proptest! {
+        #[test]
+        fn r_round_trip(
+            rd in 0..32usize,
+            funct3 in 0..8usize,
+            rs1 in 0..32usize,
+            rs2 in 0..32usize,
+            funct7 in 0..128usize,
+        ) {
+            let raw = RFormat { rd, funct3, rs1, rs2, funct7 }.encode(0x33);
+            let format = RFormat::new(raw);
+            prop_assert_eq!(
+                (format.rd, format.funct3, format.rs1, format.rs2, format.funct7),
+                (rd, funct3, rs1, rs2, funct7)
+            );
+        }
+
+        #[test]
+        fn i_round_trip(
+            rd in 0..32usize,
+            funct3 in 0..8usize,
+            rs1 in 0..32usize,
+            imm in -2048..2048i32,
+        ) {
+            let raw = IFormat { rd, funct3, rs1, imm: imm as u32 }.encode(0x13);
+            let format = IFormat::new(raw);
+            prop_assert_eq!(
+                (format.rd, format.funct3, format.rs1, format.imm as i32),
+                (rd, funct3, rs1, imm)
+            );
+        }
+
+        #[test]
+        fn s_round_trip(
+            funct3 in 0..8usize,
+            rs1 in 0..32usize,
+            rs2 in 0..32usize,
+            imm in -2048..2048i32,
+        ) {
+            let raw = SFormat { funct3, rs1, rs2, imm: imm as u32 }.encode(0x23);
+            let format = SFormat::new(raw);
+            prop_assert_eq!(
+                (format.funct3, format.rs1, format.rs2, format.imm as i32),
+                (funct3, rs1, rs2, imm)
+            );
+        }
+
+        // branch offsets are multiples of two
+        #[test]
+        fn b_round_trip(
+            funct3 in 0..8usize,
+            rs1 in 0..32usize,
+            rs2 in 0..32usize,
+            offset in -2048..2048i32,
+        ) {
+            let imm = offset * 2;
+            let raw = BFormat { funct3, rs1, rs2, imm: imm as u32 }.encode(0x63);
+            let format = BFormat::new(raw);
+            prop_assert_eq!(
+                (format.funct3, format.rs1, format.rs2, format.imm as i32),
+                (funct3, rs1, rs2, imm)
+            );
+        }
+
+        #[test]
+        fn j_round_trip(rd in 0..32usize, offset in -0x8_0000..0x8_0000i32) {
+            let imm = offset * 2;
+            let raw = JFormat { rd, imm: imm as u32 }.encode(0x6f);
+            let format = JFormat::new(raw);
+            prop_assert_eq!((format.rd, format.imm as i32), (rd, imm));
+        }
+
+        #[test]
+        fn u_round_trip(rd in 0..32usize, imm in -0x8_0000..0x8_0000i32) {
+            let raw = UFormat { rd, imm: imm as u32 }.encode(0x37);
+            let format = UFormat::new(raw);
+            prop_assert_eq!((format.rd, format.imm as i32), (rd, imm));
+        }
+    }
 }
@@ -0,0 +1,163 @@
+// Static check of a program for `--check-isa`: finds the encodings the emulated isa (see cpu::ISA)
+// can't execute before the program runs, so that a binary built for rv32imac or with floats is
+// recognized up front instead of trapping with an illegal instruction somewhere mid-run.
+// The code is swept linearly like objdump does, 16-bit encodings are compressed instructions and
+// zero halfwords are padding. Data between the code, like literal pools, may be reported as well.
+use crate::cpu::ISA;
+use crate::decode;
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+// addresses listed per extension, the rest are only counted
+const SHOWN_ADDRESSES: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Unsupported {
+    pub address: u32,
+    // the 16 or 32 bits of the encoding
+    pub raw_inst: u32,
+    // the extension the encoding most likely belongs to
+    pub extension: &'static str,
+}
+
+// Sweeps the code regions, given as start address and bytes, and returns the encodings that don't
+// decode in address order.
+pub fn scan(regions: &[(u32, &[u8])]) -> Vec<Unsupported> {
+    let mut found = Vec::new();
+    for &(base, bytes) in regions {
+        let mut offset = 0;
+        while let Some(half) = bytes.get(offset..offset + 2) {
+            let address = base.wrapping_add(offset as u32);
+            let half = u16::from_le_bytes(half.try_into().unwrap()) as u32;
+            if half == 0 {
+                offset += 2;
+                continue;
+            }
+            if half & 0b11 != 0b11 {
+                found.push(Unsupported {
+                    address,
+                    raw_inst: half,
+                    extension: "C",
+                });
+                offset += 2;
+                continue;
+            }
+            let Some(word) = bytes.get(offset..offset + 4) else {
+                break;
+            };
+            let raw_inst = u32::from_le_bytes(word.try_into().unwrap());
+            if decode(raw_inst).is_err() {
+                found.push(Unsupported {
+                    address,
+                    raw_inst,
+                    extension: extension(raw_inst),
+                });
+            }
+            offset += 4;
+        }
+    }
+    found
+}
+
+// Guesses the extension of a 32-bit encoding that doesn't decode from its major opcode and, for
+// floating point, its format or width field.
+fn extension(raw_inst: u32) -> &'static str {
+    let format = |fmt| match fmt {
+        0 => "F",
+        1 => "D",
+        2 => "Zfh",
+        _ => "Q",
+    };
+    match raw_inst & 0x7f {
+        // the widths not used by flw/fld/flq/flh are vector loads and stores
+        0x07 | 0x27 => match raw_inst >> 12 & 0b111 {
+            1 => "Zfh",
+            2 => "F",
+            3 => "D",
+            4 => "Q",
+            _ => "V",
+        },
+        0x43 | 0x47 | 0x4b | 0x4f | 0x53 => format(raw_inst >> 25 & 0b11),
+        // vector instructions beyond zve32x, e.g. floating-point ones
+        0x57 => "V",
+        // bit manipulation beyond zbkb and zbkx, e.g. zbb's clz or zbs's bset
+        0x13 | 0x33 => "B",
+        0x1b | 0x3b => "RV64",
+        0x2f if raw_inst >> 12 & 0b111 == 3 => "RV64",
+        // sret, sfence.vma and the hypervisor instructions
+        0x73 => "S",
+        0x0b | 0x2b | 0x5b | 0x7b => "custom",
+        _ => "unknown",
+    }
+}
+
+// Groups the encodings by extension, with their count and the first addresses.
+pub fn report(found: &[Unsupported]) -> String {
+    if found.is_empty() {
+        return format!("all instructions are supported by {ISA}\n");
+    }
+    let mut extensions: BTreeMap<&str, Vec<u32>> = BTreeMap::new();
+    for unsupported in found {
+        extensions
+            .entry(unsupported.extension)
+            .or_default()
+            .push(unsupported.address);
+    }
+    let mut out = format!("{} instructions can't be executed by {ISA}:\n", found.len());
+    for (extension, addresses) in extensions {
+        let shown: Vec<String> = addresses
+            .iter()
+            .take(SHOWN_ADDRESSES)
+            .map(|address| format!("{address:#x}"))
+            .collect();
+        let more = match addresses.len().saturating_sub(SHOWN_ADDRESSES) {
+            0 => String::new(),
+            more => format!(" and {more} more"),
+        };
+        let _ = writeln!(
+            out,
+            "  {extension:<8}{:>6} at {}{more}",
+            addresses.len(),
+            shown.join(", ")
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_other_extensions() {
+        let mut bytes = Vec::new();
+        // c.addi a0, 1 and padding
+        bytes.extend(0x0505u16.to_le_bytes());
+        bytes.extend(0u16.to_le_bytes());
+        // flw ft0, 0(a1), add a0, a1, a2, fadd.d ft0, ft1, ft2 and clz a0, a1
+        for word in [0x0005a007u32, 0x00c58533, 0x02208053, 0x60059513] {
+            bytes.extend(word.to_le_bytes());
+        }
+        let found = scan(&[(0x100, &bytes)]);
+        let extensions: Vec<_> = found.iter().map(|u| (u.address, u.extension)).collect();
+        assert_eq!(
+            extensions,
+            [(0x100, "C"), (0x104, "F"), (0x10c, "D"), (0x110, "B")]
+        );
+        assert_eq!(found[0].raw_inst, 0x0505);
+
+        let compressed = vec![0x01; 20];
+        assert_eq!(
+            report(&scan(&[(0, &compressed)])),
+            format!(
+                "10 instructions can't be executed by {ISA}:\n  C           10 at 0x0, 0x2, 0x4, \
+                 0x6, 0x8, 0xa, 0xc, 0xe and 2 more\n"
+            )
+        );
+        assert_eq!(
+            report(&[]),
+            format!("all instructions are supported by {ISA}\n")
+        );
+    }
+}
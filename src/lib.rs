@@ -0,0 +1,72 @@
+// A RISC-V (rv32i) emulator, also usable as a library to decode and run instructions.
+
+// instruction mnemonics are spelled like in the spec
+#![allow(clippy::upper_case_acronyms)]
+// emulator state is always set up through explicit constructors
+#![allow(clippy::new_without_default)]
+
+pub mod access_log;
+pub mod asm;
+pub mod backend;
+pub mod clock;
+pub mod control;
+pub mod coredump;
+pub mod cost;
+pub mod cpu;
+pub mod crypto;
+pub mod csr;
+pub mod decode;
+pub mod devices;
+pub mod disasm;
+pub mod elf;
+pub mod env;
+pub mod error;
+pub mod expr;
+pub mod fdt;
+#[cfg(any(feature = "tui", feature = "gui"))]
+pub mod frontend;
+pub mod fs;
+pub mod gdb;
+pub mod graph;
+#[cfg(feature = "gui")]
+pub mod gui;
+pub mod hart;
+pub mod history;
+pub mod inst;
+pub mod inst_format;
+pub mod isa;
+pub mod json;
+pub mod listing;
+pub mod machine;
+pub mod malloc;
+pub mod memory;
+pub mod mmu;
+pub mod pc;
+#[cfg(feature = "playground")]
+pub mod playground;
+pub mod progress;
+pub mod regs;
+pub mod repl;
+pub mod rng;
+pub mod sbi;
+pub mod scheduler;
+pub mod script;
+pub mod stack;
+pub mod state;
+pub mod state_hash;
+pub mod stats;
+pub mod syscall;
+pub mod test_suite;
+pub mod throttle;
+pub mod trace;
+pub mod trap;
+pub mod trigger;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod uop;
+pub mod vector;
+pub mod watch;
+
+pub use decode::decode;
+pub use error::Error;
+pub use inst::Inst;
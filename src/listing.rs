@@ -0,0 +1,213 @@
+// Listings printed by the `disasm` subcommand.
+use crate::disasm;
+use crate::elf::Elf;
+use crate::regs::Reg;
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+// ansi colors of the parts of a line
+const ADDRESS: &str = "\x1b[33m";
+const MNEMONIC: &str = "\x1b[1;32m";
+const REGISTER: &str = "\x1b[36m";
+const IMMEDIATE: &str = "\x1b[35m";
+const SYMBOL: &str = "\x1b[34m";
+const RESET: &str = "\x1b[0m";
+
+// width of the mnemonic column, fits the longest mnemonics like amomaxu.w.aqrl
+const MNEMONIC_WIDTH: usize = 14;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Mode {
+    // every word of the image is disassembled
+    Linear,
+    // only code reachable from the entry points is disassembled, the rest is listed as data
+    Recursive,
+}
+
+// The loaded bytes of a program together with its entry points and symbol names.
+pub struct Image<'a> {
+    pub(crate) regions: Vec<(u32, &'a [u8])>,
+    pub(crate) entries: Vec<u32>,
+    pub(crate) labels: BTreeMap<u32, &'a str>,
+}
+
+impl<'a> Image<'a> {
+    // a raw binary loaded and entered at base
+    pub fn raw(bytes: &'a [u8], base: u32) -> Self {
+        Image {
+            regions: vec![(base, bytes)],
+            entries: vec![base],
+            labels: BTreeMap::new(),
+        }
+    }
+
+    // the loadable segments, entered at the entry point and at every function symbol
+    pub fn elf(elf: &'a Elf) -> Self {
+        let mut labels = BTreeMap::new();
+        for (name, value) in elf.symbols().filter(|(name, _)| !name.is_empty()) {
+            labels.entry(value).or_insert(name);
+        }
+        Image {
+            regions: elf
+                .segments
+                .iter()
+                .map(|segment| (segment.address, segment.data.as_slice()))
+                .collect(),
+            entries: std::iter::once(elf.entry).chain(elf.functions()).collect(),
+            labels,
+        }
+    }
+
+    // the word loaded at the address, if it is part of the image
+    pub(crate) fn word(&self, address: u32) -> Option<u32> {
+        self.regions.iter().find_map(|&(base, bytes)| {
+            let offset = address.checked_sub(base)? as usize;
+            let word = bytes.get(offset..offset.checked_add(4)?)?;
+            Some(u32::from_le_bytes(word.try_into().unwrap()))
+        })
+    }
+
+    // the nearest label at or before the address, like objdump's <main+0x10>
+    fn location(&self, address: u32) -> Option<String> {
+        let (&start, label) = self.labels.range(..=address).next_back()?;
+        Some(match address - start {
+            0 => format!("<{label}>"),
+            offset => format!("<{label}+{offset:#x}>"),
+        })
+    }
+}
+
+struct Style {
+    color: bool,
+}
+
+impl Style {
+    fn paint(&self, color: &str, text: &str) -> String {
+        if self.color {
+            format!("{color}{text}{RESET}")
+        } else {
+            text.to_string()
+        }
+    }
+
+    // colors registers and immediates, the separators between them are kept as they are
+    fn operands(&self, operands: &str) -> String {
+        let mut out = String::new();
+        let mut token = String::new();
+        for c in operands.chars().chain(std::iter::once(',')) {
+            if !matches!(c, ',' | ' ' | '(' | ')') {
+                token.push(c);
+                continue;
+            }
+            if !token.is_empty() {
+                let is_register = Reg::from_name(&token).is_some()
+                    || token
+                        .strip_prefix('v')
+                        .is_some_and(|n| n.parse::<u8>().is_ok());
+                let color = if is_register { REGISTER } else { IMMEDIATE };
+                out += &self.paint(color, &std::mem::take(&mut token));
+            }
+            out.push(c);
+        }
+        // drop the separator that terminated the last token
+        out.pop();
+        out
+    }
+}
+
+// Lists the image in columns: address, raw bits, mnemonic and operands. Branch and jump targets
+// are annotated with their absolute address and symbol, `color` highlights the columns with ansi
+// escape codes.
+pub fn listing(image: &Image, mode: Mode, color: bool) -> String {
+    let style = Style { color };
+    let mut out = String::new();
+    for &(base, bytes) in &image.regions {
+        let code = match mode {
+            Mode::Linear => None,
+            Mode::Recursive => Some(disasm::reachable(bytes, base, &image.entries)),
+        };
+        for (address, raw_inst, inst, text) in disasm::iter(bytes, base) {
+            if let Some(label) = image.labels.get(&address) {
+                let address = style.paint(ADDRESS, &format!("{address:08x}"));
+                let label = style.paint(SYMBOL, &format!("<{label}>"));
+                let _ = write!(out, "\n{address} {label}:\n");
+            }
+            let is_code = code.as_ref().is_none_or(|code| code.contains(&address));
+            let (text, target) = match inst {
+                Ok(inst) if is_code => (text, disasm::target(&inst, address)),
+                _ => (format!(".word {raw_inst:#010x}"), None),
+            };
+            let (mnemonic, operands) = text.split_once(' ').unwrap_or((&text, ""));
+            let mut line = format!(
+                "{}: {raw_inst:08x}  {}",
+                style.paint(ADDRESS, &format!("{address:08x}")),
+                style.paint(MNEMONIC, mnemonic),
+            );
+            if !operands.is_empty() {
+                let padding = MNEMONIC_WIDTH.saturating_sub(mnemonic.len());
+                let _ = write!(line, "{:padding$} {}", "", style.operands(operands));
+            }
+            if let Some(target) = target {
+                let _ = write!(line, " # {}", style.paint(ADDRESS, &format!("{target:x}")));
+                if let Some(location) = image.location(target) {
+                    let _ = write!(line, " {}", style.paint(SYMBOL, &location));
+                }
+            }
+            let _ = writeln!(out, "{line}");
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn literal_pool_is_data() {
+        let bytes: Vec<u8> = [
+            0x0080006fu32, // jal zero, 8
+            0x00000513,    // literal that happens to decode as addi
+            0x00008067,    // jalr zero, 0(ra)
+        ]
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .collect();
+        let image = Image::raw(&bytes, 0x8000_0000);
+
+        assert_eq!(
+            listing(&image, Mode::Linear, false),
+            "80000000: 0080006f  jal            zero, 8 # 80000008\n\
+             80000004: 00000513  addi           a0, zero, 0\n\
+             80000008: 00008067  jalr           zero, 0(ra)\n"
+        );
+        assert_eq!(
+            listing(&image, Mode::Recursive, false),
+            "80000000: 0080006f  jal            zero, 8 # 80000008\n\
+             80000004: 00000513  .word          0x00000513\n\
+             80000008: 00008067  jalr           zero, 0(ra)\n"
+        );
+    }
+
+    #[test]
+    fn colored_operands() {
+        let style = Style { color: true };
+        assert_eq!(
+            style.operands("a0, -4(sp)"),
+            "\x1b[36ma0\x1b[0m, \x1b[35m-4\x1b[0m(\x1b[36msp\x1b[0m)"
+        );
+    }
+
+    #[test]
+    fn target_symbols() {
+        let image = Image {
+            regions: Vec::new(),
+            entries: Vec::new(),
+            labels: BTreeMap::from([(0x100, "main"), (0x200, "loop")]),
+        };
+        assert_eq!(image.location(0x100).as_deref(), Some("<main>"));
+        assert_eq!(image.location(0x110).as_deref(), Some("<main+0x10>"));
+        assert_eq!(image.location(0x80), None);
+    }
+}
@@ -0,0 +1,212 @@
+use crate::devices::{Clint, GoldfishRtc, Plic, RtcClock, SifiveTest, TestAssert, Uart};
+use crate::memory::{Alias, Memory};
+
+use std::path::PathBuf;
+
+// Presets of memory layout and peripherals selected with --machine.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Machine {
+    // ram at address 0, used by the testsuite and bare-metal programs
+    Default,
+    // mirrors the layout of qemu's virt machine as expected by the 32-bit xv6 port
+    Virt32,
+    // the layout of FreeRTOS' RISC-V qemu virt demo, only needs the timer and a console
+    FreertosDemo,
+}
+
+impl Machine {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "default" => Some(Machine::Default),
+            "virt32" => Some(Machine::Virt32),
+            "freertos-demo" => Some(Machine::FreertosDemo),
+            _ => None,
+        }
+    }
+
+    pub fn memory(self, clock: RtcClock) -> Memory {
+        let mut mem = match self {
+            Machine::Default => Memory::new(),
+            Machine::Virt32 | Machine::FreertosDemo => {
+                Memory::with_layout(0x8000_0000, 128 * 1024 * 1024)
+            }
+        };
+        mem.add_device(Box::new(GoldfishRtc::new(clock)));
+        mem.add_device(Box::new(SifiveTest::new()));
+        mem.add_device(Box::new(TestAssert::new()));
+        match self {
+            Machine::Default => (),
+            Machine::Virt32 => {
+                mem.add_device(Box::new(Clint::new()));
+                mem.add_device(Box::new(Plic::new()));
+                mem.add_device(Box::new(Uart::new()));
+            }
+            Machine::FreertosDemo => {
+                mem.add_device(Box::new(Clint::new()));
+                mem.add_device(Box::new(Uart::new()));
+            }
+        }
+        mem
+    }
+
+    // programs are loaded to the start of ram and entered there unless --reset-pc says otherwise
+    pub fn reset_pc(self) -> u32 {
+        match self {
+            Machine::Default => 0,
+            Machine::Virt32 | Machine::FreertosDemo => 0x8000_0000,
+        }
+    }
+}
+
+// Machine description read from a toml file with --machine-config, e.g.
+//
+//   machine = "virt32"
+//   [ram]
+//   size = 0x4000_0000
+//   file = "ram.img"
+//   [[alias]]
+//   base = 0x0000_0000
+//   size = 0x10_0000
+//   target = 0x8000_0000
+//
+// Only integers, strings and the `[ram]` and `[[alias]]` tables are understood, not the whole of
+// toml.
+#[derive(Default, PartialEq, Debug)]
+pub struct MachineConfig {
+    // preset the memory map starts from
+    pub machine: Option<Machine>,
+    // overrides the preset's ram size
+    pub ram_size: Option<u32>,
+    // host file backing ram instead of the heap
+    pub ram_file: Option<PathBuf>,
+    pub aliases: Vec<Alias>,
+}
+
+// table whose keys are being read
+enum Table {
+    Top,
+    Ram,
+    // base, size and target of the alias
+    Alias([Option<u32>; 3]),
+}
+
+impl MachineConfig {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut config = MachineConfig::default();
+        let mut table = Table::Top;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let context = |message: String| format!("line {}: {message}", number + 1);
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('[') {
+                config.finish(table).map_err(context)?;
+                table = match line {
+                    "[ram]" => Table::Ram,
+                    "[[alias]]" => Table::Alias([None; 3]),
+                    _ => return Err(context(format!("unknown table {line}"))),
+                };
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(context(format!("expected '<key> = <value>', got '{line}'")));
+            };
+            let (key, value) = (key.trim(), value.trim());
+            let string = value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'));
+            let integer = || {
+                parse_integer(value)
+                    .ok_or_else(|| context(format!("invalid value '{value}' of '{key}'")))
+            };
+            match (&mut table, key) {
+                (Table::Top, "machine") => {
+                    let name = string.unwrap_or(value);
+                    let machine = Machine::from_name(name)
+                        .ok_or_else(|| context(format!("unknown machine '{name}'")))?;
+                    config.machine = Some(machine);
+                }
+                (Table::Ram, "size") => config.ram_size = Some(integer()?),
+                (Table::Ram, "file") => {
+                    let path =
+                        string.ok_or_else(|| context(format!("expected a string for '{key}'")))?;
+                    config.ram_file = Some(PathBuf::from(path));
+                }
+                (Table::Alias(fields), "base") => fields[0] = Some(integer()?),
+                (Table::Alias(fields), "size") => fields[1] = Some(integer()?),
+                (Table::Alias(fields), "target") => fields[2] = Some(integer()?),
+                _ => return Err(context(format!("unknown key '{key}'"))),
+            }
+        }
+        config.finish(table)?;
+        Ok(config)
+    }
+
+    fn finish(&mut self, table: Table) -> Result<(), String> {
+        match table {
+            Table::Top | Table::Ram => Ok(()),
+            Table::Alias([Some(base), Some(size), Some(target)]) => {
+                self.aliases.push(Alias { base, size, target });
+                Ok(())
+            }
+            Table::Alias(_) => Err("an alias needs a base, size and target".to_string()),
+        }
+    }
+}
+
+// toml integers may be hexadecimal and contain underscores
+fn parse_integer(value: &str) -> Option<u32> {
+    let value = value.replace('_', "");
+    match value.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::Size;
+
+    #[test]
+    fn virt32_layout() {
+        let machine = Machine::from_name("virt32").unwrap();
+        let mem = machine.memory(RtcClock::Frozen(0));
+        assert_eq!(mem.ram_base(), machine.reset_pc());
+        assert_eq!(mem.ram_end(), 0x8800_0000);
+        assert!(mem.is_mapped(0x1000_0005, Size::Byte));
+        assert!(mem.is_mapped(0x0200_bff8, Size::Word));
+        assert_eq!(Machine::from_name("virt64"), None);
+    }
+
+    #[test]
+    fn machine_config() {
+        let config = MachineConfig::parse(
+            "machine = \"virt32\" # qemu-like\n\
+             [[alias]]\n\
+             base = 0x1000\n\
+             size = 0x100\n\
+             target = 0x8000_0000\n",
+        )
+        .unwrap();
+        assert_eq!(config.machine, Some(Machine::Virt32));
+        let mut mem = Machine::Virt32.memory(RtcClock::Frozen(0));
+        for alias in config.aliases {
+            mem.add_alias(alias);
+        }
+        mem.write(Size::Word, 0x1004, 0x1234_5678);
+        assert_eq!(mem.read(Size::Word, 0x8000_0004, true), 0x1234_5678);
+        assert!(mem.is_mapped(0x10fc, Size::Word));
+        assert!(!mem.is_mapped(0x1100, Size::Word));
+        assert!(mem.overlaps(0x1080, 4));
+
+        let config = MachineConfig::parse("[ram]\nsize = 1_048_576\nfile = \"ram.img\"\n").unwrap();
+        assert_eq!(config.ram_size, Some(0x10_0000));
+        assert_eq!(config.ram_file, Some(PathBuf::from("ram.img")));
+        assert!(MachineConfig::parse("[ram]\nfile = ram.img\n").is_err());
+        assert!(MachineConfig::parse("[[alias]]\nbase = 0\n").is_err());
+        assert!(MachineConfig::parse("[alias]\n").is_err());
+        assert!(MachineConfig::parse("ram = 4\n").is_err());
+    }
+}
@@ -1,50 +1,1047 @@
-mod cpu;
-mod error;
-mod inst;
-mod inst_format;
-mod memory;
-mod pc;
-mod regs;
-
-use cpu::Cpu;
-use error::Error;
+use ruscv::access_log::{AccessLog, LogFormat};
+use ruscv::asm;
+#[cfg(feature = "mmap")]
+use ruscv::backend::FileBackend;
+use ruscv::backend::{MemoryBackend, VecBackend};
+use ruscv::clock::TimeSource;
+use ruscv::control;
+use ruscv::coredump;
+use ruscv::cost::{self, CostTable};
+use ruscv::cpu::{Cpu, StopReason};
+use ruscv::devices::{
+    open_serial_link, DebugConsole, Device, Dma, Eeprom, Flash, I2c, I2cSlave, RtcClock, SlipNet,
+    Spi, SpiSlave, VirtioBlock, Watchdog, FLASH_BASE, MAX_HARTS, PLIC_BASE,
+};
+#[cfg(feature = "scripting")]
+use ruscv::devices::{ScriptedDevice, ScriptedSlave};
+use ruscv::elf::Elf;
+use ruscv::env::Env;
+use ruscv::error::Error;
+use ruscv::expr::{self, Expr};
+use ruscv::gdb;
+use ruscv::graph;
+use ruscv::hart::HartConfig;
+use ruscv::history::DEFAULT_REG_HISTORY;
+use ruscv::isa;
+use ruscv::listing::{self, Image};
+use ruscv::machine::{Machine, MachineConfig};
+use ruscv::memory::Alias;
+use ruscv::rng::Rng;
+use ruscv::scheduler::Scheduler;
+use ruscv::script::{self, Script};
+use ruscv::state_hash::HashMode;
+use ruscv::trace::{TraceFormat, TraceWriter};
+use ruscv::vector::{self, VectorUnit};
+use ruscv::watch::{BreakSpec, DebugStop};
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, BufWriter, IsTerminal, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const USAGE: &str = "Usage: ruscv [options] <file>
+       ruscv run [options] <file.s>          assembles and runs an assembly file, no toolchain needed
+       ruscv [options] --hex-inline <hex> | --stdin-bin
+                                             runs machine code given as hex bytes or read from stdin
+       ruscv debug [options] [--core <file>] <file>
+                                             reads debugger commands from stdin, e.g. to inspect a core dump
+       ruscv repl [options]                  executes assembly lines as they are typed, printing what they changed
+       ruscv tui [options] <file>            steps and runs the program in a terminal front end (feature tui)
+       ruscv gui [options] [--framebuffer <addr>,<w>x<h>] <file>
+                                             the same in a window, optionally showing ram as bitmap display (feature gui)
+       ruscv test-suite [--jobs <n>] <dir>   runs the riscv-tests elf binaries in dir on n threads
+       ruscv serve [--session-instructions <n>] [--session-memory <bytes>] [--sessions <n>] <address>
+                                             http and websocket backend for online playgrounds (feature playground)
+       ruscv disasm [--recursive] [--color] <file>
+                                             disassembles the file, --recursive follows the control flow
+       ruscv disasm --cfg|--call-graph <file>
+                                             prints the control-flow or call graph in graphviz dot format
+Options:
+  -debug                                prints emulator state after each cycle
+  --machine <name>                      memory layout and peripherals: default, virt32, freertos-demo
+  --machine-config <file.toml>          machine preset, ram size and backing file, aliased regions from a toml file
+  --net-udp <local-addr>,<peer-addr>    bridges the slip network device to a udp tunnel
+  --uart-link <link>                    connects the uart to tcp:<addr>, tcp-listen:<addr> or a new pty instead of stdin/stdout
+  --rtc-frozen <unix-secs>              real-time clock always reports the given time
+  --time <virtual|host>                 time of mtime, rdtime and the time syscalls: cycle-based (default) or host
+  --console <sink>                      debug console output: stdout (default), stderr, file:<path>, tcp:<addr>
+  --no-dtb                              doesn't pass a device tree to the program
+  --env <bare|newlib|linux|sbi|htif>    environment handling ecalls: none, libgloss or linux syscalls (default), sbi calls or riscv-tests
+  --sbi                                 same as --env sbi
+  --gdb <addr>                          waits for gdb to attach with 'target remote <addr>' before running
+  --control <addr>                      serves json-rpc requests (pause, registers, memory, interrupts, stats) on the address
+  --strace                              prints every syscall with its arguments and result
+  --pedantic                            reports fences and hints executed as nops the first time they execute
+  --check-isa                           reports the instructions the emulated isa can't execute (e.g. compressed or float ones) before running
+  --strict-syscalls                     stops at syscalls that aren't emulated instead of ignoring them
+  --reset-pc <addr>                     entry point of the program (default: start of ram)
+  --bootrom                             starts in a boot rom that sets up a0/a1 and jumps to the entry
+  --big-endian                          loads and stores are big-endian (sets mstatush.MBE)
+  --vlen <bits>                         width of the vector registers (default: 128)
+  --stack-size <bytes>                  places a guard page below a stack of the given size
+  --trace-filter <start>..<end>         only traces instructions in the address range
+  --trace-filter-sym <sym>,...          only traces instructions in the given functions (elf or .s)
+  --trace-file <path>                   writes a trace of the executed instructions, .gz/.zst are compressed
+  --trace-format <commit|json>          format of the trace file (default: commit)
+  --state-hash <commit|final>           prints a hash of the executed instructions and their register writes or of the final state, to compare runs
+  --stub <loc>=<value>                  returns the value instead of running the function at the address or symbol, e.g. 'rand=4', can be given several times
+  --stack-usage                         prints the lowest sp of each hart and the deepest stack of each function at exit
+  --heap-usage                          prints the program break and its peak at exit, and the allocations not freed by callsite if the elf has malloc
+  --tracepoint-log <path>               logs writes to the tracepoint register at 0x103000 as csv (cycle,hart,id,a0)
+  --access-log <path>                   logs the loads and stores to the access regions as csv (cycle,hart,pc,access,address,size,value), .json/.jsonl as json lines
+  --access-region <start>..<end>        address range logged by --access-log, e.g. a dma buffer, can be given several times
+  --trace-rotate <bytes>                starts a new trace file once the current one reaches the size
+  --run-to <addr|symbol>                disables tracing and -debug output until the pc reaches the target
+  --core-dump <path>                    writes the registers and ram as elf core file if the program crashes
+  --debug-script <file>                 runs debugger commands from the file instead of the whole program, see src/script.rs
+  --break <loc> [if <expr>] [hit <n>]   stops at the address or symbol once the condition held n times, e.g. 'main if a0 == 5'
+  --break-if <expr>                     stops once the expression becomes nonzero, e.g. 'a0 == 3 && pc == sym(\"main\")'
+  --watch-expr <expr>                   prints the expression's value whenever it changes, e.g. '*(u32*)(sp+8)'
+  --examine <expr>[,<count>]            prints count words (default: 1) at the address once the program stopped
+  --progress <millions>                 reports progress every given million instructions
+  --mips-limit <n>                      caps the speed at n million instructions per second, e.g. 0.001
+  --reg-history <n>                     prints the last n register writes on errors (default: 16)
+  --root <dir>                          directory the program's file syscalls see as /, nothing is exposed by default
+  --map-path <guest>=<host>             exposes a host file or directory at the guest path
+  --map <file>@<addr>                   maps a file read-only at addr, available to mmap as fd 3, 4, ...
+  --flash <file>[@<addr>]               persistent flash backed by the file (default addr: 0x22000000)
+  --drive <file>                        virtio block device backed by the disk image at 0x10001000, irq 1
+  --device-script <file.rhai>           mmio device with registers, timers and interrupt defined by the script
+  --dma                                 maps a dma engine at 0x10007000 copying 4 bytes per cycle, irq 12 signals completion
+  --watchdog                            maps a watchdog at 0x10006000 that resets the machine unless it is fed
+  --gpio <sink>                         maps 32 gpio pins at 0x10005000, logs output changes to a console sink or none
+  --spi <cs>=<slave>                    attaches a slave to the spi controller at 0x10003000, see below
+  --i2c <addr>=<slave>                  attaches a slave with the 7-bit address to the i2c controller at 0x10004000
+                                        slaves: eeprom:<file> (25xx/24xx eeprom) or script:<file.rhai>
+  --harts <n>                           number of harts sharing the memory (default: 1)
+  --hart <id>:<opt>,...                 boot parameters of a hart: entry=<addr>, sp=<addr>, parked
+  --schedule <round-robin|random>       order in which the harts execute (default: round-robin)
+  --seed <n>                            seed of the random schedule (printed if not given) and of getrandom
+  --quantum <n>                         instructions a hart executes before switching, at most for random
+  --cfg-dot <path>                      writes the control-flow graph with execution counts once the program stopped
+  --call-graph-dot <path>               writes the call graph with execution counts once the program stopped
+  --stats                               prints instruction and memory access statistics once the program stopped
+  --cost-table <path>                   prints the cost of each function, given a json/toml mnemonic -> cost table";
+
+// graphs of the program that can be exported in dot format
+#[derive(Clone, Copy)]
+enum GraphKind {
+    Cfg,
+    Calls,
+}
 
 struct CliArgs {
     print_debug: bool,
+    // runs the riscv-tests in the directory given as filename
+    test_suite: bool,
+    // threads running the test-suite, defaults to one per core
+    jobs: Option<usize>,
+    // serves playground sessions at the address given as filename
+    serve: bool,
+    // quotas of each playground session and the number of sessions, see playground::Limits
+    session_instructions: Option<u32>,
+    session_memory: Option<u32>,
+    sessions: Option<u32>,
+    // disassembles the file instead of running it
+    disasm: Option<listing::Mode>,
+    // highlights the disassembly with ansi colors
+    color: bool,
+    // prints a graph of the program instead of the disassembly
+    graph: Option<GraphKind>,
+    // graphs written after the program stopped, annotated with execution counts
+    graph_files: Vec<(GraphKind, String)>,
+    stats: bool,
+    // per-instruction costs accumulated per function and reported once the program stopped
+    cost_table: Option<String>,
+    machine: Machine,
+    // ram size and backing file overriding the preset, from the machine config
+    ram_size: Option<u32>,
+    ram_file: Option<PathBuf>,
+    // windows mirroring other regions, from the machine config
+    aliases: Vec<Alias>,
+    // local and peer address of the udp tunnel backing the slip network device
+    net_udp: Option<(SocketAddr, SocketAddr)>,
+    // host end the uart is connected to instead of stdin and stdout
+    uart_link: Option<String>,
+    // fixed time reported by the rtc instead of the host clock, in nanoseconds since the epoch
+    rtc_frozen: Option<u64>,
+    // whether the timer and time syscalls follow the executed cycles or the host's clock
+    time: TimeSource,
+    // where bytes written to the debug console go
+    console: String,
+    no_dtb: bool,
+    // what ecalls mean, only the environment's own exit call stops the program
+    env: Env,
+    strict_syscalls: bool,
+    // address gdb attaches to, the program only starts once it did
+    gdb: Option<String>,
+    // address of the json-rpc control socket
+    control: Option<String>,
+    strace: bool,
+    // report instructions that are executed as nops
+    pedantic: bool,
+    // scan the program for instructions of extensions that aren't emulated before running
+    check_isa: bool,
+    // defaults to the start of the machine's ram
+    reset_pc: Option<u32>,
+    bootrom: bool,
+    // harts start with big-endian data accesses
+    big_endian: bool,
+    vlen: u32,
+    // reserved stack size, a guard page below it catches overflows
+    stack_size: Option<u32>,
+    // address ranges and function names the debug output is limited to
+    trace_ranges: Vec<Range<u32>>,
+    trace_symbols: Vec<String>,
+    // address or symbol where tracing starts
+    run_to: Option<String>,
+    // reads debugger commands from stdin instead of running the program
+    debug: bool,
+    // executes assembly lines read from stdin instead of a program
+    repl: bool,
+    // shows the program in the terminal front end instead of running it
+    tui: bool,
+    // shows the program in a window instead of running it
+    gui: bool,
+    // address, width and height of the ram region the gui shows as bitmap display
+    framebuffer: Option<(u32, u32, u32)>,
+    // core dump restored before a debug session
+    core: Option<String>,
+    // where the state is dumped if the program crashes
+    core_dump: Option<PathBuf>,
+    // debugger commands controlling the run, the session's status becomes the exit-code
+    debug_script: Option<String>,
+    // breakpoints with optional condition and hit count
+    breaks: Vec<BreakSpec>,
+    // functions replaced by a constant result, by address or symbol
+    stubs: Vec<(String, u32)>,
+    // expressions of the debugging options, evaluated while the program runs
+    break_conditions: Vec<Expr>,
+    watch_exprs: Vec<Expr>,
+    // start address and number of words dumped once the program stopped
+    examine: Vec<(Expr, usize)>,
+    trace_file: Option<PathBuf>,
+    trace_format: TraceFormat,
+    // maximum size of a trace file before a new one is started
+    trace_rotate: Option<u64>,
+    // what the signature printed at exit covers
+    state_hash: Option<HashMode>,
+    // whether the stack usage is reported at exit
+    stack_usage: bool,
+    // whether the heap usage and the allocations not freed are reported at exit
+    heap_usage: bool,
+    // instructions between progress reports
+    progress: Option<u64>,
+    // maximum speed in millions of instructions per second
+    mips_limit: Option<f64>,
+    // number of register writes printed on errors, 0 disables the history
+    reg_history: usize,
+    // host files mapped read-only into the address space, in command-line order
+    maps: Vec<(String, u32)>,
+    // csv file receiving the tracepoints hit by the program
+    tracepoint_log: Option<String>,
+    // file receiving the loads and stores to the access regions
+    access_log: Option<String>,
+    access_regions: Vec<Range<u32>>,
+    // whether the dma engine and the watchdog are mapped
+    dma: bool,
+    watchdog: bool,
+    // sink logging the gpio outputs, the gpio block is only mapped if given
+    gpio: Option<String>,
+    // file backing the flash and the flash's address
+    flash: Option<(PathBuf, u32)>,
+    // disk image backing the virtio block device, which is only mapped if given
+    drive: Option<PathBuf>,
+    // rhai scripts defining mmio devices
+    device_scripts: Vec<String>,
+    // slaves on the spi bus by chip select and on the i2c bus by address
+    spi: Vec<(u32, String)>,
+    i2c: Vec<(u8, String)>,
+    // host directory and paths exposed to the file syscalls
+    root: Option<PathBuf>,
+    path_maps: Vec<(String, PathBuf)>,
+    harts: usize,
+    // boot parameters of individual harts
+    hart_configs: Vec<(usize, HartConfig)>,
+    random_schedule: bool,
+    seed: Option<u64>,
+    quantum: u64,
     filename: String,
+    // program bytes given on the command-line instead of a file
+    hex_inline: Option<Vec<u8>>,
+    // the program is read from stdin instead of a file, the program's own stdin is then empty
+    stdin_bin: bool,
 }
 impl CliArgs {
     fn new() -> Self {
         CliArgs {
             print_debug: false,
+            test_suite: false,
+            jobs: None,
+            serve: false,
+            session_instructions: None,
+            session_memory: None,
+            sessions: None,
+            disasm: None,
+            color: false,
+            graph: None,
+            graph_files: Vec::new(),
+            stats: false,
+            cost_table: None,
+            machine: Machine::Default,
+            ram_size: None,
+            ram_file: None,
+            aliases: Vec::new(),
+            net_udp: None,
+            uart_link: None,
+            rtc_frozen: None,
+            time: TimeSource::Virtual,
+            console: "stdout".to_string(),
+            no_dtb: false,
+            env: Env::Linux,
+            strict_syscalls: false,
+            gdb: None,
+            control: None,
+            strace: false,
+            pedantic: false,
+            check_isa: false,
+            reset_pc: None,
+            bootrom: false,
+            big_endian: false,
+            vlen: vector::DEFAULT_VLEN,
+            stack_size: None,
+            trace_ranges: Vec::new(),
+            trace_symbols: Vec::new(),
+            run_to: None,
+            debug: false,
+            repl: false,
+            tui: false,
+            gui: false,
+            framebuffer: None,
+            core: None,
+            core_dump: None,
+            debug_script: None,
+            breaks: Vec::new(),
+            stubs: Vec::new(),
+            break_conditions: Vec::new(),
+            watch_exprs: Vec::new(),
+            examine: Vec::new(),
+            trace_file: None,
+            trace_format: TraceFormat::Commit,
+            state_hash: None,
+            stack_usage: false,
+            heap_usage: false,
+            trace_rotate: None,
+            progress: None,
+            mips_limit: None,
+            reg_history: DEFAULT_REG_HISTORY,
+            maps: Vec::new(),
+            tracepoint_log: None,
+            access_log: None,
+            access_regions: Vec::new(),
+            dma: false,
+            watchdog: false,
+            gpio: None,
+            flash: None,
+            drive: None,
+            device_scripts: Vec::new(),
+            spi: Vec::new(),
+            i2c: Vec::new(),
+            root: None,
+            path_maps: Vec::new(),
+            harts: 1,
+            hart_configs: Vec::new(),
+            random_schedule: false,
+            seed: None,
+            quantum: 1,
             filename: String::new(),
+            hex_inline: None,
+            stdin_bin: false,
         }
     }
     fn parse() -> CliArgs {
         let mut cli_args = CliArgs::new();
-        let mut args = std::env::args().into_iter().skip(1);
+        let mut args = std::env::args().skip(1);
 
         while let Some(arg) = args.next() {
             match arg.as_str() {
                 "-debug" => cli_args.print_debug = true,
+                "test-suite" if cli_args.filename.is_empty() => cli_args.test_suite = true,
+                "--jobs" if cli_args.test_suite => {
+                    let jobs = args.next().unwrap_or_default();
+                    match parse_u32(&jobs) {
+                        Some(jobs) if jobs > 0 => cli_args.jobs = Some(jobs as usize),
+                        _ => usage_error(&format!("invalid number of jobs '{jobs}'")),
+                    }
+                }
+                "serve" if cli_args.filename.is_empty() => cli_args.serve = true,
+                "--session-instructions" | "--session-memory" | "--sessions" if cli_args.serve => {
+                    let value = args.next().unwrap_or_default();
+                    let Some(value) = parse_u32(&value).filter(|&value| value > 0) else {
+                        usage_error(&format!("invalid {} '{value}'", &arg[2..]))
+                    };
+                    match arg.as_str() {
+                        "--session-instructions" => cli_args.session_instructions = Some(value),
+                        "--session-memory" => cli_args.session_memory = Some(value),
+                        _ => cli_args.sessions = Some(value),
+                    }
+                }
+                // files are run by default, `run` only reads better in front of assembly files
+                "run" if cli_args.filename.is_empty() => (),
+                "debug" if cli_args.filename.is_empty() => cli_args.debug = true,
+                "repl" if cli_args.filename.is_empty() => cli_args.repl = true,
+                "tui" if cli_args.filename.is_empty() => cli_args.tui = true,
+                "gui" if cli_args.filename.is_empty() => cli_args.gui = true,
+                "--framebuffer" => {
+                    let spec = args.next().unwrap_or_default();
+                    let parsed = spec.split_once(',').and_then(|(address, size)| {
+                        let (width, height) = size.split_once('x')?;
+                        Some((parse_u32(address)?, parse_u32(width)?, parse_u32(height)?))
+                    });
+                    cli_args.framebuffer = match parsed {
+                        Some((_, width, height)) if width > 0 && height > 0 => parsed,
+                        _ => usage_error(&format!(
+                            "invalid framebuffer '{spec}', expected <addr>,<width>x<height>"
+                        )),
+                    };
+                }
+                "--core" => cli_args.core = args.next(),
+                "disasm" if cli_args.filename.is_empty() => {
+                    cli_args.disasm = Some(listing::Mode::Linear)
+                }
+                "--color" if cli_args.disasm.is_some() => cli_args.color = true,
+                "--cfg" if cli_args.disasm.is_some() => cli_args.graph = Some(GraphKind::Cfg),
+                "--call-graph" if cli_args.disasm.is_some() => {
+                    cli_args.graph = Some(GraphKind::Calls)
+                }
+                "--cfg-dot" => {
+                    let path = args.next().unwrap_or_default();
+                    cli_args.graph_files.push((GraphKind::Cfg, path));
+                }
+                "--stats" => cli_args.stats = true,
+                "--root" => {
+                    let dir = PathBuf::from(args.next().unwrap_or_default());
+                    if !dir.is_dir() {
+                        usage_error(&format!("root '{}' isn't a directory", dir.display()));
+                    }
+                    cli_args.root = Some(dir);
+                }
+                "--map-path" => {
+                    let rule = args.next().unwrap_or_default();
+                    match rule.split_once('=') {
+                        Some((guest, host)) if guest.starts_with('/') => {
+                            cli_args.path_maps.push((guest.to_string(), host.into()))
+                        }
+                        _ => usage_error(&format!(
+                            "invalid path mapping '{rule}', expected <absolute guest path>=<host path>"
+                        )),
+                    }
+                }
+                "--cost-table" => cli_args.cost_table = args.next(),
+                "--call-graph-dot" => {
+                    let path = args.next().unwrap_or_default();
+                    cli_args.graph_files.push((GraphKind::Calls, path));
+                }
+                "--recursive" if cli_args.disasm.is_some() => {
+                    cli_args.disasm = Some(listing::Mode::Recursive)
+                }
+                "--no-dtb" => cli_args.no_dtb = true,
+                "--sbi" => cli_args.env = Env::Sbi,
+                "--env" => {
+                    let name = args.next().unwrap_or_default();
+                    match Env::from_name(&name) {
+                        Some(env) => cli_args.env = env,
+                        None => usage_error(&format!("unknown environment '{name}'")),
+                    }
+                }
+                "--strict-syscalls" => cli_args.strict_syscalls = true,
+                "--strace" => cli_args.strace = true,
+                "--pedantic" => cli_args.pedantic = true,
+                "--check-isa" => cli_args.check_isa = true,
+                "--bootrom" => cli_args.bootrom = true,
+                "--big-endian" => cli_args.big_endian = true,
+                "--reset-pc" => {
+                    let addr = args.next().unwrap_or_default();
+                    match parse_u32(&addr) {
+                        Some(addr) => cli_args.reset_pc = Some(addr),
+                        None => usage_error(&format!("invalid address '{addr}'")),
+                    }
+                }
+                "--stack-size" => {
+                    let bytes = args.next().unwrap_or_default();
+                    match parse_u32(&bytes) {
+                        Some(bytes) => cli_args.stack_size = Some(bytes),
+                        None => usage_error(&format!("invalid stack size '{bytes}'")),
+                    }
+                }
+                "--trace-filter" => {
+                    let range = args.next().unwrap_or_default();
+                    let bounds = range.split_once("..");
+                    match bounds.map(|(start, end)| (parse_u32(start), parse_u32(end))) {
+                        Some((Some(start), Some(end))) => cli_args.trace_ranges.push(start..end),
+                        _ => usage_error(&format!("invalid address range '{range}'")),
+                    }
+                }
+                "--trace-filter-sym" => {
+                    let symbols = args.next().unwrap_or_default();
+                    cli_args
+                        .trace_symbols
+                        .extend(symbols.split(',').map(str::to_string));
+                }
+                "--run-to" => cli_args.run_to = args.next(),
+                "--core-dump" => cli_args.core_dump = args.next().map(PathBuf::from),
+                "--debug-script" => cli_args.debug_script = args.next(),
+                "--break" => {
+                    let spec = args.next().unwrap_or_default();
+                    match BreakSpec::parse(&spec) {
+                        Ok(spec) => cli_args.breaks.push(spec),
+                        Err(e) => usage_error(&format!("invalid breakpoint '{spec}': {e}")),
+                    }
+                }
+                "--break-if" => cli_args
+                    .break_conditions
+                    .push(parse_expr(&args.next().unwrap_or_default())),
+                "--watch-expr" => cli_args
+                    .watch_exprs
+                    .push(parse_expr(&args.next().unwrap_or_default())),
+                "--examine" => {
+                    let arg = args.next().unwrap_or_default();
+                    let (text, count) = match arg.rsplit_once(',') {
+                        Some((text, count)) => match count.trim().parse() {
+                            Ok(count) => (text, count),
+                            Err(_) => usage_error(&format!("invalid word count '{count}'")),
+                        },
+                        None => (arg.as_str(), 1),
+                    };
+                    cli_args.examine.push((parse_expr(text), count));
+                }
+                "--trace-file" => cli_args.trace_file = args.next().map(PathBuf::from),
+                "--trace-format" => {
+                    let name = args.next().unwrap_or_default();
+                    match TraceFormat::from_name(&name) {
+                        Some(format) => cli_args.trace_format = format,
+                        None => usage_error(&format!("unknown trace format '{name}'")),
+                    }
+                }
+                "--state-hash" => {
+                    let name = args.next().unwrap_or_default();
+                    match HashMode::from_name(&name) {
+                        Some(mode) => cli_args.state_hash = Some(mode),
+                        None => usage_error(&format!("unknown state hash mode '{name}'")),
+                    }
+                }
+                "--trace-rotate" => {
+                    let bytes = args.next().unwrap_or_default();
+                    match bytes.parse() {
+                        Ok(bytes) if bytes > 0 => cli_args.trace_rotate = Some(bytes),
+                        _ => usage_error(&format!("invalid trace file size '{bytes}'")),
+                    }
+                }
+                "--progress" => {
+                    let millions = args.next().unwrap_or_default();
+                    let interval = millions.parse::<u64>().ok().filter(|&m| m > 0);
+                    match interval.map(|m| m.checked_mul(1_000_000)) {
+                        Some(Some(interval)) => cli_args.progress = Some(interval),
+                        Some(None) => {
+                            usage_error(&format!("progress interval '{millions}' is too large"))
+                        }
+                        None => usage_error(&format!("invalid progress interval '{millions}'")),
+                    }
+                }
+                "--mips-limit" => {
+                    let mips = args.next().unwrap_or_default();
+                    match mips.parse::<f64>() {
+                        Ok(limit) if limit > 0.0 && limit.is_finite() => {
+                            cli_args.mips_limit = Some(limit)
+                        }
+                        _ => usage_error(&format!("invalid mips limit '{mips}'")),
+                    }
+                }
+                "--reg-history" => {
+                    let writes = args.next().unwrap_or_default();
+                    match writes.parse() {
+                        Ok(writes) => cli_args.reg_history = writes,
+                        Err(_) => usage_error(&format!("invalid number of writes '{writes}'")),
+                    }
+                }
+                "--map" => {
+                    let map = args.next().unwrap_or_default();
+                    match map.rsplit_once('@') {
+                        Some((file, addr)) => match parse_u32(addr) {
+                            Some(addr) => cli_args.maps.push((file.to_string(), addr)),
+                            None => usage_error(&format!("invalid address '{addr}'")),
+                        },
+                        None => usage_error("--map expects '<file>@<addr>'"),
+                    }
+                }
+                "--stub" => {
+                    let stub = args.next().unwrap_or_default();
+                    let parse_value =
+                        |v: &str| parse_u32(v).or_else(|| v.parse::<i32>().ok().map(|v| v as u32));
+                    match stub.split_once('=') {
+                        Some((location, value)) => match parse_value(value) {
+                            Some(value) => cli_args.stubs.push((location.to_string(), value)),
+                            None => usage_error(&format!("invalid stub value '{value}'")),
+                        },
+                        None => usage_error("--stub expects '<addr|symbol>=<value>'"),
+                    }
+                }
+                "--stack-usage" => cli_args.stack_usage = true,
+                "--heap-usage" => cli_args.heap_usage = true,
+                "--tracepoint-log" => cli_args.tracepoint_log = args.next(),
+                "--access-log" => cli_args.access_log = args.next(),
+                "--access-region" => {
+                    let range = args.next().unwrap_or_default();
+                    let bounds = range.split_once("..");
+                    match bounds.map(|(start, end)| (parse_u32(start), parse_u32(end))) {
+                        Some((Some(start), Some(end))) => cli_args.access_regions.push(start..end),
+                        _ => usage_error(&format!("invalid address range '{range}'")),
+                    }
+                }
+                "--dma" => cli_args.dma = true,
+                "--watchdog" => cli_args.watchdog = true,
+                "--gpio" => cli_args.gpio = Some(args.next().unwrap_or_default()),
+                "--device-script" => cli_args
+                    .device_scripts
+                    .push(args.next().unwrap_or_default()),
+                "--spi" | "--i2c" => {
+                    let slave = args.next().unwrap_or_default();
+                    let Some((id, spec)) = slave
+                        .split_once('=')
+                        .and_then(|(id, spec)| Some((parse_u32(id)?, spec.to_string())))
+                    else {
+                        usage_error(&format!("invalid bus slave '{slave}'"));
+                    };
+                    if arg == "--spi" {
+                        cli_args.spi.push((id, spec));
+                    } else if id < 0x80 {
+                        cli_args.i2c.push((id as u8, spec));
+                    } else {
+                        usage_error(&format!("i2c address {id:#x} doesn't fit into 7 bits"));
+                    }
+                }
+                "--flash" => {
+                    let flash = args.next().unwrap_or_default();
+                    let (file, addr) = match flash.rsplit_once('@') {
+                        Some((file, addr)) => match parse_u32(addr) {
+                            Some(addr) => (file, addr),
+                            None => usage_error(&format!("invalid address '{addr}'")),
+                        },
+                        None => (flash.as_str(), FLASH_BASE),
+                    };
+                    cli_args.flash = Some((file.into(), addr));
+                }
+                "--drive" => cli_args.drive = Some(args.next().unwrap_or_default().into()),
+                "--harts" => {
+                    let harts = args.next().unwrap_or_default();
+                    match harts.parse() {
+                        Ok(harts) if (1..=MAX_HARTS).contains(&harts) => cli_args.harts = harts,
+                        _ => usage_error(&format!(
+                            "number of harts '{harts}' isn't between 1 and {MAX_HARTS}"
+                        )),
+                    }
+                }
+                "--hart" => {
+                    let hart = args.next().unwrap_or_default();
+                    match parse_hart_config(&hart) {
+                        Some(config) => cli_args.hart_configs.push(config),
+                        None => usage_error(&format!("invalid hart configuration '{hart}'")),
+                    }
+                }
+                "--schedule" => match args.next().unwrap_or_default().as_str() {
+                    "round-robin" => cli_args.random_schedule = false,
+                    "random" => cli_args.random_schedule = true,
+                    schedule => usage_error(&format!("unknown schedule '{schedule}'")),
+                },
+                "--seed" => {
+                    let seed = args.next().unwrap_or_default();
+                    match seed.parse() {
+                        Ok(seed) => cli_args.seed = Some(seed),
+                        Err(_) => usage_error(&format!("invalid seed '{seed}'")),
+                    }
+                }
+                "--quantum" => {
+                    let quantum = args.next().unwrap_or_default();
+                    match quantum.parse() {
+                        Ok(quantum) if quantum > 0 => cli_args.quantum = quantum,
+                        _ => usage_error(&format!("invalid quantum '{quantum}'")),
+                    }
+                }
+                "--machine" => {
+                    let name = args.next().unwrap_or_default();
+                    match Machine::from_name(&name) {
+                        Some(machine) => cli_args.machine = machine,
+                        None => usage_error(&format!("unknown machine '{name}'")),
+                    }
+                }
+                "--machine-config" => {
+                    let path = args.next().unwrap_or_default();
+                    let config = std::fs::read_to_string(&path)
+                        .map_err(|e| e.to_string())
+                        .and_then(|text| MachineConfig::parse(&text));
+                    match config {
+                        Ok(config) => {
+                            if let Some(machine) = config.machine {
+                                cli_args.machine = machine;
+                            }
+                            cli_args.ram_size = config.ram_size.or(cli_args.ram_size);
+                            cli_args.ram_file = config.ram_file.or(cli_args.ram_file);
+                            cli_args.aliases.extend(config.aliases);
+                        }
+                        Err(e) => usage_error(&format!("invalid machine config '{path}': {e}")),
+                    }
+                }
+                "--vlen" => {
+                    let bits = args.next().unwrap_or_default();
+                    match bits.parse::<u32>() {
+                        Ok(bits) if bits.is_power_of_two() && (32..=65536).contains(&bits) => {
+                            cli_args.vlen = bits
+                        }
+                        _ => usage_error(&format!(
+                            "vector length '{bits}' isn't a power of two between 32 and 65536"
+                        )),
+                    }
+                }
+                "--uart-link" => cli_args.uart_link = Some(args.next().unwrap_or_default()),
+                "--net-udp" => {
+                    let addrs = args.next().unwrap_or_default();
+                    cli_args.net_udp = match addrs.split_once(',') {
+                        Some((local, peer)) => match (local.parse(), peer.parse()) {
+                            (Ok(local), Ok(peer)) => Some((local, peer)),
+                            _ => usage_error(&format!("invalid socket address in '{addrs}'")),
+                        },
+                        None => usage_error("--net-udp expects '<local-addr>,<peer-addr>'"),
+                    }
+                }
+                "--console" => cli_args.console = args.next().unwrap_or_default(),
+                "--rtc-frozen" => {
+                    let secs = args.next().unwrap_or_default();
+                    let nanos = secs
+                        .parse::<u64>()
+                        .ok()
+                        .map(|s| s.checked_mul(1_000_000_000));
+                    match nanos {
+                        Some(Some(nanos)) => cli_args.rtc_frozen = Some(nanos),
+                        Some(None) => usage_error(&format!("unix timestamp '{secs}' is too large")),
+                        None => usage_error(&format!("invalid unix timestamp '{secs}'")),
+                    }
+                }
+                "--gdb" => cli_args.gdb = Some(args.next().unwrap_or_default()),
+                "--control" => cli_args.control = Some(args.next().unwrap_or_default()),
+                "--hex-inline" => match parse_hex_bytes(&args.next().unwrap_or_default()) {
+                    Some(bytes) => cli_args.hex_inline = Some(bytes),
+                    None => usage_error("--hex-inline requires pairs of hex digits"),
+                },
+                "--stdin-bin" => cli_args.stdin_bin = true,
+                "--time" => {
+                    let name = args.next().unwrap_or_default();
+                    match TimeSource::from_name(&name) {
+                        Some(source) => cli_args.time = source,
+                        None => usage_error(&format!("unknown time source '{name}'")),
+                    }
+                }
                 file if cli_args.filename.is_empty() => cli_args.filename = file.to_string(),
                 _ => {
-                    eprintln!("Usage: ruscv [-debug] <file>");
+                    eprintln!("{USAGE}");
                     std::process::exit(1);
                 }
             }
         }
-        if cli_args.filename.is_empty() {
-            eprintln!("Error: ruscv requires exactly one binary input file");
-            eprintln!("Usage: ruscv [-debug] <file>");
-            std::process::exit(1);
+        let inputs = [
+            !cli_args.filename.is_empty(),
+            cli_args.hex_inline.is_some(),
+            cli_args.stdin_bin,
+        ];
+        let programs = inputs.iter().filter(|&&given| given).count();
+        if cli_args.repl && programs > 0 {
+            usage_error("repl starts without a program, the code is typed in");
+        }
+        if !cli_args.repl && programs != 1 {
+            usage_error("ruscv requires exactly one program: a file, --hex-inline or --stdin-bin");
+        }
+        if cli_args.debug_script.is_some() && cli_args.gdb.is_some() {
+            usage_error("--debug-script and --gdb both control the run, use only one");
+        }
+        let front_end = cli_args.tui || cli_args.gui;
+        let debuggers = [
+            cli_args.debug,
+            cli_args.tui,
+            cli_args.gui,
+            cli_args.debug_script.is_some(),
+            cli_args.gdb.is_some(),
+        ];
+        if front_end && debuggers.iter().filter(|&&given| given).count() > 1 {
+            usage_error(
+                "tui and gui can't be combined with another debug session, --debug-script or --gdb",
+            );
+        }
+        if cli_args.repl && (debuggers.contains(&true) || cli_args.control.is_some()) {
+            usage_error(
+                "repl can't be combined with a debug session, front end, --gdb or --control",
+            );
+        }
+        if cli_args.framebuffer.is_some() && !cli_args.gui {
+            usage_error("--framebuffer requires the graphical front end: ruscv gui");
+        }
+        if cli_args.control.is_some() && (cli_args.gdb.is_some() || cli_args.debug || front_end) {
+            usage_error("--control can't be combined with a debug session or --gdb");
+        }
+        if cli_args.core.is_some() && !cli_args.debug {
+            usage_error("--core requires a debug session: ruscv debug --core <file> <program>");
+        }
+        if cli_args.test_suite && cli_args.filename.is_empty() {
+            usage_error("test-suite requires a directory");
+        }
+        if let Some((id, _)) = cli_args
+            .hart_configs
+            .iter()
+            .find(|(id, _)| *id >= cli_args.harts)
+        {
+            usage_error(&format!("hart {id} doesn't exist, see --harts"));
         }
         cli_args
     }
 }
 
+// heap-allocated ram unless a host file backs it
+fn ram_backend(file: Option<&Path>, size: usize) -> Box<dyn MemoryBackend> {
+    match file {
+        None => Box::new(VecBackend::new(size)),
+        #[cfg(feature = "mmap")]
+        Some(path) => match FileBackend::open(path, size) {
+            Ok(backend) => Box::new(backend),
+            Err(e) => usage_error(&format!("can't map ram file '{}': {e}", path.display())),
+        },
+        #[cfg(not(feature = "mmap"))]
+        Some(_) => usage_error("file-backed ram requires the mmap feature"),
+    }
+}
+
+// the slaves given on the command line work on both buses
+trait BusSlave: SpiSlave + I2cSlave {}
+impl<T: SpiSlave + I2cSlave> BusSlave for T {}
+
+fn bus_slave(spec: &str) -> Box<dyn BusSlave> {
+    match spec.split_once(':') {
+        Some(("eeprom", path)) => match Eeprom::open(path.as_ref()) {
+            Ok(eeprom) => Box::new(eeprom),
+            Err(e) => usage_error(&format!("can't open eeprom file '{path}': {e}")),
+        },
+        Some(("script", path)) => scripted_slave(path),
+        _ => usage_error(&format!(
+            "invalid bus slave '{spec}', expected eeprom:<file> or script:<file>"
+        )),
+    }
+}
+
+#[cfg(feature = "scripting")]
+fn scripted_slave(path: &str) -> Box<dyn BusSlave> {
+    let slave = std::fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|source| ScriptedSlave::new(path, &source));
+    match slave {
+        Ok(slave) => Box::new(slave),
+        Err(e) => usage_error(&format!("can't load slave script '{path}': {e}")),
+    }
+}
+
+#[cfg(not(feature = "scripting"))]
+fn scripted_slave(_path: &str) -> Box<dyn BusSlave> {
+    usage_error("scripted slaves require the scripting feature")
+}
+
+#[cfg(feature = "tui")]
+fn run_tui(cpu: &mut Cpu) -> Result<u8, Error> {
+    ruscv::tui::run(cpu)
+}
+
+#[cfg(not(feature = "tui"))]
+fn run_tui(_cpu: &mut Cpu) -> Result<u8, Error> {
+    usage_error("the terminal front end requires the tui feature")
+}
+
+#[cfg(feature = "playground")]
+fn run_playground(cli_args: &CliArgs) -> Result<(), Error> {
+    let mut limits = ruscv::playground::Limits::new();
+    if let Some(instructions) = cli_args.session_instructions {
+        limits.instructions = instructions.into();
+    }
+    if let Some(memory) = cli_args.session_memory {
+        limits.memory = memory as usize;
+    }
+    if let Some(sessions) = cli_args.sessions {
+        limits.sessions = sessions as usize;
+    }
+    ruscv::playground::serve(&cli_args.filename, limits)
+}
+
+#[cfg(not(feature = "playground"))]
+fn run_playground(_cli_args: &CliArgs) -> Result<(), Error> {
+    usage_error("the playground server requires the playground feature")
+}
+
+#[cfg(feature = "gui")]
+fn run_gui(cpu: &mut Cpu, framebuffer: Option<(u32, u32, u32)>) -> Result<u8, Error> {
+    let framebuffer = framebuffer.map(|(address, width, height)| ruscv::gui::Framebuffer {
+        address,
+        width: width as usize,
+        height: height as usize,
+    });
+    ruscv::gui::run(cpu, framebuffer)
+}
+
+#[cfg(not(feature = "gui"))]
+fn run_gui(_cpu: &mut Cpu, _framebuffer: Option<(u32, u32, u32)>) -> Result<u8, Error> {
+    usage_error("the graphical front end requires the gui feature")
+}
+
+#[cfg(feature = "scripting")]
+fn scripted_device(path: &str) -> Box<dyn Device> {
+    let device = std::fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|source| ScriptedDevice::new(path, &source));
+    match device {
+        Ok(device) => Box::new(device),
+        Err(e) => usage_error(&format!("can't load device script '{path}': {e}")),
+    }
+}
+
+#[cfg(not(feature = "scripting"))]
+fn scripted_device(_path: &str) -> Box<dyn Device> {
+    usage_error("device scripts require the scripting feature")
+}
+
+fn usage_error(msg: &str) -> ! {
+    eprintln!("Error: {msg}");
+    eprintln!("{USAGE}");
+    std::process::exit(1);
+}
+
+fn parse_expr(text: &str) -> Expr {
+    Expr::parse(text).unwrap_or_else(|e| usage_error(&format!("invalid expression '{text}': {e}")))
+}
+
+// parses decimal or 0x-prefixed hexadecimal numbers
+fn parse_u32(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+// Parses bytes written as hex digits in memory order, e.g. "93 00 10 00" for addi x1, x0, 1.
+// Whitespace between the digits is ignored.
+fn parse_hex_bytes(s: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = s.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if digits.is_empty()
+        || !digits.len().is_multiple_of(2)
+        || !digits.iter().all(u8::is_ascii_hexdigit)
+    {
+        return None;
+    }
+    digits
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+// parses '<id>:entry=<addr>,sp=<addr>,parked', all options are optional
+fn parse_hart_config(s: &str) -> Option<(usize, HartConfig)> {
+    let (id, options) = s.split_once(':').unwrap_or((s, ""));
+    let mut config = HartConfig::default();
+    for option in options.split(',').filter(|option| !option.is_empty()) {
+        match option.split_once('=') {
+            Some(("entry", addr)) => config.entry = Some(parse_u32(addr)?),
+            Some(("sp", addr)) => config.sp = Some(parse_u32(addr)?),
+            None if option == "parked" => config.parked = true,
+            _ => return None,
+        }
+    }
+    Some((id.parse().ok()?, config))
+}
+
+fn open_console(sink: &str) -> io::Result<Box<dyn Write>> {
+    match sink.split_once(':') {
+        _ if sink == "stdout" => Ok(Box::new(io::stdout())),
+        _ if sink == "stderr" => Ok(Box::new(io::stderr())),
+        Some(("file", path)) => Ok(Box::new(File::create(path)?)),
+        Some(("tcp", addr)) => Ok(Box::new(TcpStream::connect(addr)?)),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "unknown sink")),
+    }
+}
+
+// calls f with the image of the program, raw binaries are placed at base
+fn with_image<T>(program: &[u8], base: u32, f: impl FnOnce(&Image) -> T) -> Result<T, Error> {
+    let elf = Elf::is_elf(program)
+        .then(|| Elf::parse(program))
+        .transpose()?;
+    Ok(match &elf {
+        Some(elf) => f(&Image::elf(elf)),
+        None => f(&Image::raw(program, base)),
+    })
+}
+
+// the report of `--check-isa`, elf files are scanned in their executable segments
+fn check_isa(program: &[u8], base: u32) -> Result<String, Error> {
+    let found = if Elf::is_elf(program) {
+        let elf = Elf::parse(program)?;
+        let code: Vec<(u32, &[u8])> = elf
+            .segments
+            .iter()
+            .filter(|segment| segment.executable)
+            .map(|segment| (segment.address, segment.data.as_slice()))
+            .collect();
+        isa::scan(&code)
+    } else {
+        isa::scan(&[(base, program)])
+    };
+    Ok(isa::report(&found))
+}
+
+fn graph_dot(
+    program: &[u8],
+    base: u32,
+    kind: GraphKind,
+    counts: Option<&HashMap<u32, u64>>,
+) -> Result<String, Error> {
+    with_image(program, base, |image| {
+        let graph = graph::build(image);
+        match kind {
+            GraphKind::Cfg => graph.cfg_dot(image, counts),
+            GraphKind::Calls => graph.call_graph_dot(image, counts),
+        }
+    })
+}
+
+// runs the loaded program, under the control of gdb or the control socket if given, and dumps its
+// state if it crashed
+fn run(
+    cpu: &mut Cpu,
+    gdb: Option<&str>,
+    control: Option<&str>,
+    core_dump: Option<&Path>,
+) -> Result<u8, Error> {
+    let result = match (gdb, control) {
+        (Some(address), _) => gdb::serve(cpu, address),
+        (None, Some(address)) => control::serve(cpu, address),
+        (None, None) => cpu.run_loaded(),
+    };
+    let crashed = !matches!(
+        result,
+        Ok(StopReason::Exit(_) | StopReason::Debug(_) | StopReason::HostRequest)
+    );
+    if let Some(path) = core_dump.filter(|_| crashed) {
+        let core = coredump::write(cpu, coredump::signal(&result));
+        match std::fs::write(path, core) {
+            Ok(()) => eprintln!("core dump written to {}", path.display()),
+            Err(e) => eprintln!("can't write core dump to '{}': {e}", path.display()),
+        }
+    }
+    result?.into_result()
+}
+
 fn read_bin(path: &str) -> Vec<u8> {
     let mut file = File::open(path).expect("valid binary input file");
     let mut program = Vec::new();
@@ -53,14 +1050,511 @@ fn read_bin(path: &str) -> Vec<u8> {
     program
 }
 
+// assembles the file to the start of ram, returns the image and the addresses of its labels
+fn assemble_file(path: &str, base: u32) -> Result<(Vec<u8>, HashMap<String, u32>), Error> {
+    let source = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| usage_error(&format!("can't read '{path}': {e}")));
+    let assembly = asm::assemble(&source, base)?;
+    Ok((assembly.bytes, assembly.symbols))
+}
+
+// Labels have no size, so a label is taken to cover the code up to the next one or the end of the
+// program.
+fn label_range(labels: &HashMap<String, u32>, name: &str, end: u32) -> Option<Range<u32>> {
+    let start = *labels.get(name)?;
+    let next = labels
+        .values()
+        .copied()
+        .filter(|&address| address > start && address < end)
+        .min()
+        .unwrap_or(end);
+    Some(start..next.max(start.saturating_add(4)))
+}
+
+// the program from the file, the command-line or stdin
+fn read_program(cli_args: &CliArgs) -> Vec<u8> {
+    if let Some(bytes) = &cli_args.hex_inline {
+        return bytes.clone();
+    }
+    if !cli_args.stdin_bin {
+        return read_bin(&cli_args.filename);
+    }
+    let mut program = Vec::new();
+    io::stdin()
+        .read_to_end(&mut program)
+        .expect("can read binary from stdin");
+    program
+}
+
 fn main() -> Result<(), Error> {
     let cli_args = CliArgs::parse();
+    if cli_args.test_suite {
+        let jobs = cli_args
+            .jobs
+            .unwrap_or_else(ruscv::test_suite::default_jobs);
+        let passed = ruscv::test_suite::run(cli_args.filename.as_ref(), jobs)
+            .unwrap_or_else(|e| usage_error(&format!("can't read test directory: {e}")));
+        std::process::exit(if passed { 0 } else { 1 });
+    }
+    if cli_args.serve {
+        return run_playground(&cli_args);
+    }
 
-    let program = read_bin(&cli_args.filename);
-    Cpu::new(cli_args.print_debug)
-        .run(program)
-        .and_then(|code| {
-            eprintln!("Emulated program finished at exit syscall with exit-code: {code}");
-            Ok(())
-        })
+    let ram_base = cli_args.machine.reset_pc();
+    let (program, symbols) = if cli_args.repl {
+        (Vec::new(), HashMap::new())
+    } else if cli_args.filename.ends_with(".s") {
+        assemble_file(&cli_args.filename, ram_base)?
+    } else {
+        (read_program(&cli_args), HashMap::new())
+    };
+    let entry = symbols.get("_start").copied();
+    let base = cli_args.reset_pc.unwrap_or(ram_base);
+    if let Some(mode) = cli_args.disasm {
+        match cli_args.graph {
+            Some(kind) => print!("{}", graph_dot(&program, base, kind, None)?),
+            None => print!(
+                "{}",
+                with_image(&program, base, |image| listing::listing(
+                    image,
+                    mode,
+                    cli_args.color
+                ))?
+            ),
+        }
+        return Ok(());
+    }
+    let mut cpu = Cpu::new(cli_args.print_debug);
+    let clock = match cli_args.rtc_frozen {
+        Some(nanos) => RtcClock::Frozen(nanos),
+        None => RtcClock::Host,
+    };
+    cpu.mem = cli_args.machine.memory(clock);
+    if cli_args.ram_size.is_some() || cli_args.ram_file.is_some() {
+        let size = cli_args
+            .ram_size
+            .map_or(cpu.mem.ram_size(), |size| size as usize);
+        let base = cpu.mem.ram_base();
+        let end = base as u64 + size as u64;
+        let covered = cpu
+            .mem
+            .devices()
+            .find(|dev| (dev.base() as u64) < end && base < dev.base() + dev.size());
+        if let Some(dev) = covered {
+            return Err(Error::MappingOverlap(dev.base()));
+        }
+        if end > 1 << 32 {
+            usage_error(&format!("{size:#x} bytes of ram don't fit above {base:#x}"));
+        }
+        cpu.mem
+            .set_backend(ram_backend(cli_args.ram_file.as_deref(), size));
+    }
+    cpu.set_time_source(cli_args.time);
+    cpu.vector = VectorUnit::new(cli_args.vlen);
+    if let Some(spec) = &cli_args.uart_link {
+        // checked first, so that nobody waits for a peer in vain
+        if !cpu.mem.devices().any(|dev| dev.has_input()) {
+            usage_error("--uart-link needs a machine with a uart, e.g. --machine virt32");
+        }
+        match open_serial_link(spec) {
+            Ok(link) => {
+                eprintln!("uart linked to {}", link.name);
+                cpu.mem.link_serial(link);
+            }
+            Err(e) => usage_error(&format!("can't open uart link '{spec}': {e}")),
+        }
+    }
+    if let Some((local, peer)) = cli_args.net_udp {
+        let slip = SlipNet::new(local, peer).unwrap_or_else(|e| {
+            usage_error(&format!(
+                "can't bind udp tunnel {local} for the network device: {e}"
+            ))
+        });
+        cpu.mem.add_device(Box::new(slip));
+    }
+    match open_console(&cli_args.console) {
+        Ok(sink) => cpu.mem.add_device(Box::new(DebugConsole::new(sink))),
+        Err(e) => usage_error(&format!("can't open console '{}': {e}", cli_args.console)),
+    }
+    if !cli_args.no_dtb {
+        cpu.enable_dtb();
+    }
+    // htif also needs the tohost symbol of the elf file
+    if cli_args.env != Env::Htif {
+        cpu.set_env(cli_args.env);
+    }
+    if cli_args.strict_syscalls {
+        cpu.enable_strict_syscalls();
+    }
+    if cli_args.strace {
+        cpu.enable_strace();
+    }
+    if cli_args.pedantic {
+        cpu.enable_pedantic();
+    }
+    if cli_args.bootrom {
+        cpu.enable_bootrom();
+    }
+    if cli_args.big_endian {
+        cpu.set_big_endian();
+    }
+    if let Some(interval) = cli_args.progress {
+        cpu.enable_progress(interval);
+    }
+    if let Some(mips) = cli_args.mips_limit {
+        cpu.enable_throttle(mips);
+    }
+    if cli_args.reg_history > 0 {
+        cpu.enable_reg_history(cli_args.reg_history);
+    }
+    for (file, addr) in &cli_args.maps {
+        let data = std::fs::read(file)
+            .unwrap_or_else(|e| usage_error(&format!("can't read mapped file '{file}': {e}")));
+        cpu.map_file(*addr, data)?;
+    }
+    if let Some(path) = &cli_args.tracepoint_log {
+        let log = File::create(path)
+            .and_then(|file| cpu.enable_tracepoints(Box::new(BufWriter::new(file))));
+        if let Err(e) = log {
+            usage_error(&format!("can't write tracepoint log '{path}': {e}"));
+        }
+    }
+    match (&cli_args.access_log, cli_args.access_regions.is_empty()) {
+        (Some(path), false) => {
+            let log = File::create(path).and_then(|file| {
+                AccessLog::new(Box::new(BufWriter::new(file)), LogFormat::from_path(path))
+            });
+            match log {
+                Ok(log) => cpu.enable_access_log(log, cli_args.access_regions.clone()),
+                Err(e) => usage_error(&format!("can't write access log '{path}': {e}")),
+            }
+        }
+        (Some(_), true) => usage_error("--access-log requires at least one --access-region"),
+        (None, false) => usage_error("--access-region requires --access-log"),
+        (None, true) => (),
+    }
+    if let Some((file, addr)) = &cli_args.flash {
+        let flash = Flash::open(file, *addr).unwrap_or_else(|e| {
+            usage_error(&format!("can't open flash file '{}': {e}", file.display()))
+        });
+        if cpu.mem.overlaps(*addr, flash.size()) {
+            return Err(Error::MappingOverlap(*addr));
+        }
+        cpu.mem.add_device(Box::new(flash));
+    }
+    if let Some(path) = &cli_args.drive {
+        if !cpu.mem.devices().any(|dev| dev.base() == PLIC_BASE) {
+            usage_error("--drive needs a machine with a plic, e.g. --machine virt32");
+        }
+        let drive = VirtioBlock::open(path).unwrap_or_else(|e| {
+            usage_error(&format!("can't open drive '{}': {e}", path.display()))
+        });
+        if cpu.mem.overlaps(drive.base(), drive.size()) {
+            return Err(Error::MappingOverlap(drive.base()));
+        }
+        cpu.mem.add_device(Box::new(drive));
+    }
+    for path in &cli_args.device_scripts {
+        let device = scripted_device(path);
+        if cpu.mem.overlaps(device.base(), device.size()) {
+            return Err(Error::MappingOverlap(device.base()));
+        }
+        cpu.mem.add_device(device);
+    }
+    if cli_args.dma {
+        cpu.mem.add_device(Box::new(Dma::new()));
+    }
+    if cli_args.watchdog {
+        cpu.mem.add_device(Box::new(Watchdog::new()));
+    }
+    if let Some(sink) = &cli_args.gpio {
+        let log = match sink.as_str() {
+            "none" => Ok(None),
+            sink => open_console(sink).map(Some),
+        };
+        if let Err(e) = log.and_then(|log| cpu.enable_gpio(log)) {
+            usage_error(&format!("can't open gpio log '{sink}': {e}"));
+        }
+    }
+    if !cli_args.spi.is_empty() {
+        let mut spi = Spi::new();
+        for (cs, spec) in &cli_args.spi {
+            spi.add_slave(*cs, bus_slave(spec));
+        }
+        cpu.mem.add_device(Box::new(spi));
+    }
+    if !cli_args.i2c.is_empty() {
+        let mut i2c = I2c::new();
+        for (address, spec) in &cli_args.i2c {
+            i2c.add_slave(*address, bus_slave(spec));
+        }
+        cpu.mem.add_device(Box::new(i2c));
+    }
+    for alias in &cli_args.aliases {
+        if cpu.mem.overlaps(alias.base, alias.size) {
+            return Err(Error::MappingOverlap(alias.base));
+        }
+        cpu.mem.add_alias(*alias);
+    }
+    if let Some(dir) = cli_args.root {
+        cpu.files.set_root(dir);
+    }
+    for (guest, host) in cli_args.path_maps {
+        cpu.files.map_path(&guest, host);
+    }
+    if let Some(bytes) = cli_args.stack_size {
+        cpu.set_stack_size(bytes);
+    }
+    cpu.set_harts(cli_args.harts);
+    for (id, config) in cli_args.hart_configs {
+        cpu.configure_hart(id, config);
+    }
+    // Without a seed every random schedule explores a different interleaving, otherwise the guest's
+    // entropy is deterministic. The one seed drives both, so that the printed seed replays the run.
+    let seed = match cli_args.seed {
+        Some(seed) => seed,
+        None if cli_args.random_schedule => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            now.as_nanos() as u64
+        }
+        None => 0,
+    };
+    cpu.entropy = Rng::new(seed);
+    if cli_args.random_schedule {
+        eprintln!("scheduler seed {seed} (replay with --schedule random --seed {seed})");
+        cpu.set_scheduler(Scheduler::random(seed, cli_args.quantum));
+    } else {
+        cpu.set_scheduler(Scheduler::round_robin(cli_args.quantum));
+    }
+    // assembly programs start at _start and elf files at their entry unless --reset-pc says otherwise
+    if let Some(address) = cli_args.reset_pc.or(entry) {
+        cpu.set_reset_pc(address);
+    }
+    // elf files replace them with their symbol table once loaded
+    cpu.symbols = symbols;
+    for condition in cli_args.break_conditions {
+        cpu.watchpoints.add_condition(condition);
+    }
+    if !cli_args.breaks.is_empty() || cpu.watchpoints.condition(0).is_some() {
+        cpu.on_exit(|cpu, reason| match reason {
+            StopReason::Debug(DebugStop::Breakpoint(pc)) => eprintln!("breakpoint at pc {pc:#x}"),
+            StopReason::Debug(DebugStop::Condition(index)) => {
+                let condition = cpu.watchpoints.condition(*index).unwrap();
+                eprintln!("'{condition}' became true at pc {:#x}", cpu.pc.get());
+            }
+            _ => (),
+        });
+    }
+    for expr in cli_args.watch_exprs {
+        cpu.add_watch_expr(expr);
+    }
+    if !cli_args.examine.is_empty() {
+        cpu.on_exit(move |cpu, _| {
+            for (expr, count) in &cli_args.examine {
+                match expr.eval(cpu) {
+                    Ok(address) => eprint!("{}", expr::examine(cpu, address as u32, *count)),
+                    Err(e) => eprintln!("can't examine '{expr}': {e}"),
+                }
+            }
+        });
+    }
+    if cli_args.stats {
+        cpu.mem.enable_stats();
+        cpu.on_exit(|cpu, _| {
+            eprintln!("{} instructions retired", cpu.retired());
+            if let Some(stats) = cpu.mem.stats() {
+                eprint!("{}", stats.report());
+            }
+        });
+    }
+    if let Some(mode) = cli_args.state_hash {
+        cpu.enable_state_hash(mode);
+        cpu.on_exit(|cpu, _| {
+            if let Some(hash) = cpu.state_hash() {
+                eprintln!("state hash: {hash:016x}");
+            }
+        });
+    }
+    if cli_args.stack_usage {
+        cpu.enable_stack_usage();
+        cpu.on_exit(|cpu, _| {
+            if let Some(report) = cpu.stack_report() {
+                eprint!("{report}");
+            }
+        });
+    }
+    if cli_args.heap_usage {
+        cpu.enable_heap_usage();
+        cpu.on_exit(|cpu, _| {
+            if let Some(report) = cpu.heap_report() {
+                eprint!("{report}");
+            }
+        });
+    }
+    if let Some(path) = &cli_args.cost_table {
+        let table = std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| CostTable::parse(&text))
+            .unwrap_or_else(|e| usage_error(&format!("invalid cost table '{path}': {e}")));
+        cpu.enable_exec_counts();
+        let program = program.clone();
+        cpu.on_exit(move |cpu, _| {
+            let counts = cpu.exec_counts().cloned().unwrap_or_default();
+            match with_image(&program, base, |image| {
+                cost::profile(&table, image, &counts)
+            }) {
+                Ok(profile) => eprint!("{}", cost::report(&profile)),
+                Err(e) => eprintln!("can't build cost profile: {e:?}"),
+            }
+        });
+    }
+    if !cli_args.graph_files.is_empty() {
+        cpu.enable_exec_counts();
+        let program = program.clone();
+        cpu.on_exit(move |cpu, _| {
+            for (kind, path) in &cli_args.graph_files {
+                let written = graph_dot(&program, base, *kind, cpu.exec_counts())
+                    .map(|dot| std::fs::write(path, dot));
+                match written {
+                    Ok(Ok(())) => (),
+                    Ok(Err(e)) => eprintln!("can't write graph to '{path}': {e}"),
+                    Err(e) => eprintln!("can't build graph: {e:?}"),
+                }
+            }
+        });
+    }
+    if let Some(path) = &cli_args.trace_file {
+        let trace = TraceWriter::create(path, cli_args.trace_format, cli_args.trace_rotate)
+            .unwrap_or_else(|e| usage_error(&format!("can't create trace file: {e}")));
+        cpu.enable_trace(trace);
+    }
+    for range in cli_args.trace_ranges {
+        cpu.trace_filter.add_range(range);
+    }
+
+    let run_to = cli_args
+        .run_to
+        .as_deref()
+        .map(|target| (target, parse_u32(target)));
+    if let Some((_, Some(address))) = run_to {
+        cpu.set_run_to(address);
+    }
+
+    if cli_args.check_isa {
+        eprint!("{}", check_isa(&program, base)?);
+    }
+    let elf = if Elf::is_elf(&program) {
+        Some(Elf::parse(&program)?)
+    } else {
+        None
+    };
+    // elf files bring their symbol table and assembly files their labels, raw binaries have neither
+    let no_symbols = elf.is_none() && cpu.symbols.is_empty();
+    let unknown = |name: &str| -> ! {
+        if no_symbols {
+            usage_error(&format!(
+                "'{name}' isn't an address and there is no symbol table"
+            ))
+        }
+        usage_error(&format!("unknown symbol '{name}'"))
+    };
+    let symbol = |name: &str| match &elf {
+        Some(elf) => elf.symbol(name),
+        None => cpu.symbols.get(name).copied(),
+    };
+    let program_end = ram_base.wrapping_add(program.len() as u32);
+    let symbol_range = |name: &str| match &elf {
+        Some(elf) => elf.symbol_range(name),
+        None => label_range(&cpu.symbols, name, program_end),
+    };
+    let run_to_symbol = match run_to {
+        Some((target, None)) => Some(symbol(target).unwrap_or_else(|| unknown(target))),
+        _ => None,
+    };
+    let trace_ranges: Vec<_> = cli_args
+        .trace_symbols
+        .iter()
+        .map(|name| symbol_range(name).unwrap_or_else(|| unknown(name)))
+        .collect();
+    let tohost = match cli_args.env {
+        Env::Htif => match symbol("tohost") {
+            Some(address) => Some(address),
+            None => usage_error("--env htif requires a program with a tohost symbol"),
+        },
+        _ => None,
+    };
+    if let Some(address) = run_to_symbol {
+        cpu.set_run_to(address);
+    }
+    for range in trace_ranges {
+        cpu.trace_filter.add_range(range);
+    }
+    if let Some(address) = tohost {
+        cpu.enable_htif(address);
+    }
+    match elf {
+        Some(elf) => cpu.load_elf(&elf)?,
+        None => cpu.load(program),
+    }
+    // breakpoint locations can name symbols, which are only known once the program is loaded
+    for spec in &cli_args.breaks {
+        match spec.resolve(&cpu) {
+            Ok(address) => cpu.watchpoints.add_break_spec(address, spec),
+            Err(e) => usage_error(&format!(
+                "invalid breakpoint location '{}': {e}",
+                spec.location
+            )),
+        }
+    }
+    // like breakpoints, stubs can name symbols
+    for (location, value) in &cli_args.stubs {
+        let value = *value;
+        let address = parse_u32(location).or_else(|| cpu.symbols.get(location).copied());
+        match address {
+            Some(address) => cpu.stub(address, move |_| value),
+            None => usage_error(&format!("unknown symbol '{location}'")),
+        }
+    }
+    if let Some(path) = &cli_args.core {
+        let core = std::fs::read(path)
+            .unwrap_or_else(|e| usage_error(&format!("can't read '{path}': {e}")));
+        coredump::load(&mut cpu, &core)?;
+    }
+    if let Some(path) = &cli_args.debug_script {
+        let text = std::fs::read_to_string(path)
+            .unwrap_or_else(|e| usage_error(&format!("can't read '{path}': {e}")));
+        let status = Script::parse(&text)?.run(&mut cpu, &mut io::stdout())?;
+        std::process::exit(status.into())
+    }
+    if cli_args.repl {
+        let stdin = io::stdin();
+        let prompt = stdin.is_terminal();
+        let status = ruscv::repl::run(&mut cpu, &mut stdin.lock(), &mut io::stdout(), prompt)?;
+        std::process::exit(status.into())
+    }
+    if cli_args.tui {
+        let status = run_tui(&mut cpu)?;
+        std::process::exit(status.into())
+    }
+    if cli_args.gui {
+        let status = run_gui(&mut cpu, cli_args.framebuffer)?;
+        std::process::exit(status.into())
+    }
+    if cli_args.debug && cli_args.gdb.is_none() {
+        let stdin = io::stdin();
+        let prompt = stdin.is_terminal();
+        let status = script::interactive(&mut cpu, &mut stdin.lock(), &mut io::stdout(), prompt)?;
+        std::process::exit(status.into())
+    }
+    let code = run(
+        &mut cpu,
+        cli_args.gdb.as_deref(),
+        cli_args.control.as_deref(),
+        cli_args.core_dump.as_deref(),
+    )?;
+    eprintln!("Emulated program finished at exit syscall with exit-code: {code}");
+    // forward the guest's exit-code so that test harnesses can rely on it
+    std::process::exit(code.into())
 }
@@ -0,0 +1,265 @@
+// Allocation profile for `--heap-usage`: the calls into the c library's allocator are observed at
+// the addresses of newlib's malloc, calloc, realloc and free and their reentrant _r variants, to
+// count the allocations of each callsite and find the blocks that were never freed. Calls nested
+// in another one, like malloc calling _malloc_r or realloc calling _free_r, belong to the outer
+// call.
+use crate::regs::{Reg, Registers};
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+// callsites listed in the report, the ones leaking most first
+const SHOWN_CALLSITES: usize = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Function {
+    Malloc,
+    Calloc,
+    Realloc,
+    Free,
+}
+
+// the allocator's entry points and whether they take the reentrancy struct as first argument
+const FUNCTIONS: [(&str, Function, bool); 8] = [
+    ("malloc", Function::Malloc, false),
+    ("_malloc_r", Function::Malloc, true),
+    ("calloc", Function::Calloc, false),
+    ("_calloc_r", Function::Calloc, true),
+    ("realloc", Function::Realloc, false),
+    ("_realloc_r", Function::Realloc, true),
+    ("free", Function::Free, false),
+    ("_free_r", Function::Free, true),
+];
+
+// an allocating call of a hart, completed once it returns to ra with the sp it was called with
+#[derive(Clone)]
+struct Pending {
+    function: Function,
+    callsite: u32,
+    sp: u32,
+    size: u32,
+    // the block passed to realloc
+    old: u32,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Callsite {
+    pub allocations: u32,
+    pub bytes: u64,
+    // blocks still allocated
+    pub live: u32,
+    pub live_bytes: u64,
+}
+
+#[derive(Clone)]
+pub struct Allocations {
+    entries: HashMap<u32, (Function, bool)>,
+    pending: HashMap<usize, Pending>,
+    // the allocated blocks with their callsite and size
+    live: HashMap<u32, (u32, u32)>,
+    callsites: HashMap<u32, Callsite>,
+}
+
+impl Allocations {
+    pub fn new() -> Self {
+        Allocations {
+            entries: HashMap::new(),
+            pending: HashMap::new(),
+            live: HashMap::new(),
+            callsites: HashMap::new(),
+        }
+    }
+
+    // looks up the allocator in the loaded program's symbols
+    pub fn set_symbols(&mut self, symbols: &HashMap<String, u32>) {
+        self.entries = FUNCTIONS
+            .iter()
+            .filter_map(|&(name, function, reentrant)| {
+                Some((*symbols.get(name)?, (function, reentrant)))
+            })
+            .collect();
+    }
+
+    // whether the program has an allocator to observe
+    pub fn found(&self) -> bool {
+        !self.entries.is_empty()
+    }
+
+    // the blocks don't survive a reset of the machine, the counts of the callsites do
+    pub fn reset(&mut self) {
+        self.pending.clear();
+        self.live.clear();
+    }
+
+    // called before the hart executes the instruction at pc
+    pub fn observe(&mut self, hart: usize, pc: u32, regs: &Registers) {
+        if let Some(pending) = self.pending.get(&hart) {
+            let ret = pending.callsite.wrapping_add(4);
+            if pc == ret && regs.get(Reg::Sp) == pending.sp {
+                let pending = self.pending.remove(&hart).unwrap();
+                self.complete(pending, regs.get(Reg::A0));
+            }
+            return;
+        }
+        let Some(&(function, reentrant)) = self.entries.get(&pc) else {
+            return;
+        };
+        let arg = |n| regs.read(Reg::A0.index() + reentrant as usize + n);
+        let (size, old) = match function {
+            Function::Malloc => (arg(0), 0),
+            Function::Calloc => (arg(0).saturating_mul(arg(1)), 0),
+            Function::Realloc => (arg(1), arg(0)),
+            Function::Free => {
+                self.live.remove(&arg(0));
+                return;
+            }
+        };
+        let pending = Pending {
+            function,
+            // the call before the return address
+            callsite: regs.get(Reg::Ra).wrapping_sub(4),
+            sp: regs.get(Reg::Sp),
+            size,
+            old,
+        };
+        self.pending.insert(hart, pending);
+    }
+
+    fn complete(&mut self, pending: Pending, block: u32) {
+        // realloc frees the old block unless it fails, and with size 0 even then
+        if pending.function == Function::Realloc && (block != 0 || pending.size == 0) {
+            self.live.remove(&pending.old);
+        }
+        if block == 0 {
+            return;
+        }
+        let callsite = self.callsites.entry(pending.callsite).or_default();
+        callsite.allocations += 1;
+        callsite.bytes += pending.size as u64;
+        self.live.insert(block, (pending.callsite, pending.size));
+    }
+
+    // the counts of each callsite, with the blocks that are still allocated
+    pub fn callsites(&self) -> HashMap<u32, Callsite> {
+        let mut callsites = self.callsites.clone();
+        for &(callsite, size) in self.live.values() {
+            let callsite = callsites.entry(callsite).or_default();
+            callsite.live += 1;
+            callsite.live_bytes += size as u64;
+        }
+        callsites
+    }
+
+    // the totals and the callsites leaking most, named by the symbols where known
+    pub fn report(&self, symbols: &HashMap<String, u32>) -> String {
+        let callsites = self.callsites();
+        let total = |field: fn(&Callsite) -> u64| callsites.values().map(field).sum::<u64>();
+        let mut out = format!(
+            "malloc: {} allocations of {} bytes, {} blocks of {} bytes not freed\n",
+            total(|c| c.allocations as u64),
+            total(|c| c.bytes),
+            total(|c| c.live as u64),
+            total(|c| c.live_bytes)
+        );
+        if callsites.is_empty() {
+            return out;
+        }
+        let mut labels: BTreeMap<u32, &str> = BTreeMap::new();
+        for (name, &address) in symbols.iter().filter(|(name, _)| !name.is_empty()) {
+            let label = labels.entry(address).or_insert(name.as_str());
+            *label = (*label).min(name.as_str());
+        }
+        let location = |address: u32| match labels.range(..=address).next_back() {
+            Some((&start, label)) if start == address => format!("<{label}>"),
+            Some((&start, label)) => format!("<{label}+{:#x}>", address - start),
+            None => String::new(),
+        };
+        let mut sorted: Vec<_> = callsites.into_iter().collect();
+        sorted.sort_by_key(|&(address, c)| (std::cmp::Reverse((c.live_bytes, c.bytes)), address));
+        out.push_str("  allocations       bytes  not freed  callsite\n");
+        for (address, c) in sorted.into_iter().take(SHOWN_CALLSITES) {
+            let _ = writeln!(
+                out,
+                "{:>13}{:>12}{:>11}  {address:#x} {}",
+                c.allocations,
+                c.bytes,
+                c.live,
+                location(address)
+            );
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // enters the function at pc from the call at callsite with the arguments in a0..
+    fn call(allocations: &mut Allocations, pc: u32, callsite: u32, args: &[u32]) {
+        let mut regs = Registers::new();
+        regs.set(Reg::Sp, 0x1000);
+        regs.set(Reg::Ra, callsite + 4);
+        for (n, &arg) in args.iter().enumerate() {
+            regs.write(Reg::A0.index() + n, arg);
+        }
+        allocations.observe(0, pc, &regs);
+    }
+
+    fn ret(allocations: &mut Allocations, callsite: u32, block: u32) {
+        let mut regs = Registers::new();
+        regs.set(Reg::Sp, 0x1000);
+        regs.set(Reg::A0, block);
+        allocations.observe(0, callsite + 4, &regs);
+    }
+
+    #[test]
+    fn leaks_by_callsite() {
+        let symbols = HashMap::from([
+            ("main".to_string(), 0x100),
+            ("malloc".to_string(), 0x400),
+            ("_malloc_r".to_string(), 0x420),
+            ("realloc".to_string(), 0x500),
+            ("_free_r".to_string(), 0x600),
+        ]);
+        let mut allocations = Allocations::new();
+        allocations.set_symbols(&symbols);
+        assert!(allocations.found());
+
+        // malloc(16) calls _malloc_r, which is part of the call
+        call(&mut allocations, 0x400, 0x110, &[16]);
+        call(&mut allocations, 0x420, 0x404, &[0, 16]);
+        ret(&mut allocations, 0x110, 0x2000);
+        call(&mut allocations, 0x420, 0x120, &[0, 32]);
+        ret(&mut allocations, 0x120, 0x3000);
+        // the block of 32 grows to 64 and is freed, the block of 16 leaks
+        call(&mut allocations, 0x500, 0x130, &[0x3000, 64]);
+        ret(&mut allocations, 0x130, 0x4000);
+        call(&mut allocations, 0x600, 0x140, &[0, 0x4000]);
+        // failing allocations aren't counted
+        call(&mut allocations, 0x400, 0x150, &[1 << 30]);
+        ret(&mut allocations, 0x150, 0);
+
+        let callsites = allocations.callsites();
+        assert_eq!(callsites.len(), 3);
+        assert_eq!(
+            callsites[&0x110],
+            Callsite {
+                allocations: 1,
+                bytes: 16,
+                live: 1,
+                live_bytes: 16
+            }
+        );
+        assert_eq!(callsites[&0x130].live, 0);
+        assert_eq!(
+            allocations.report(&symbols),
+            "malloc: 3 allocations of 112 bytes, 1 blocks of 16 bytes not freed
+  allocations       bytes  not freed  callsite
+            1          16          1  0x110 <main+0x10>
+            1          64          0  0x130 <main+0x30>
+            1          32          0  0x120 <main+0x20>
+"
+        );
+    }
+}
@@ -1,11 +1,21 @@
+use crate::access_log::{AccessRecorder, LoggedAccess};
+use crate::backend::{CowBackend, MemoryBackend, VecBackend};
+use crate::devices::{AssertFailure, Capture, Device, Ram, SerialLink, Transfer};
 use crate::inst::*;
+use crate::stats::MemStats;
+use crate::trap::Exception;
+
+use std::borrow::Cow;
+use std::ops::Range;
+use std::rc::Rc;
+use std::time::Instant;
 
 // Don't want to use too much memory for emulator
 pub const MEMSIZE: usize = 1024 * 128;
 // Start address of dram section
 // pub const MEM_START: u32 = 0x8000_0000;
 
-#[derive(Clone)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Size {
     Byte = 1,
     HalfWord = 2,
@@ -31,41 +41,490 @@ impl From<SInst> for Size {
     }
 }
 
+// A window at `base` through which the `size` bytes at `target` are accessed as well, like the
+// mirrored memory regions of some SoCs.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Alias {
+    pub base: u32,
+    pub size: u32,
+    pub target: u32,
+}
+
 macro_rules! read_mem {
     ($ty:ty,$mem:expr,$from:expr,$to:expr) => {
-        <$ty>::from_le_bytes($mem[$from as usize..$to as usize].try_into().unwrap()) as u32
+        <$ty>::from_le_bytes(
+            $mem.read($from as usize..$to as usize)[..]
+                .try_into()
+                .unwrap(),
+        ) as u32
     };
 }
-pub struct Memory([u8; MEMSIZE]);
+pub struct Memory {
+    ram: Box<dyn MemoryBackend>,
+    // physical address at which ram starts
+    ram_base: u32,
+    // memory-mapped peripherals, accesses outside of ram are routed to these
+    devices: Vec<Box<dyn Device>>,
+    // set once a device requested to power off the machine
+    exit: Option<u8>,
+    // set once a device requested to reset the machine
+    reset: bool,
+    // id written to the tracepoint device by the last store
+    tracepoint: Option<u32>,
+    // assertion reported as failed by the test assert device
+    failed_assert: Option<AssertFailure>,
+    // inaccessible page below the stack, accesses to it fault
+    guard: Option<Range<u32>>,
+    // address of the htif tohost word used by riscv-tests to report the result
+    tohost: Option<u32>,
+    // words written by stores since the log was last drained, only recorded once enabled
+    stores: Option<Vec<u32>>,
+    // statistics of the program's loads and stores, only recorded once enabled
+    stats: Option<MemStats>,
+    // loads and stores to the regions of the access log, taken by the cpu
+    accesses: Option<AccessRecorder>,
+    // byte order of loads and stores, fetches and the host's accesses are always little-endian
+    big_endian: bool,
+    // windows that mirror other regions, resolved before an address reaches ram or a device
+    aliases: Vec<Alias>,
+    // interrupt lines raised from the host for the next cycle, e.g. by the control socket
+    injected: u32,
+}
 impl Memory {
     pub fn new() -> Self {
-        Memory([0; MEMSIZE])
+        Memory::with_layout(0, MEMSIZE)
+    }
+    pub fn with_layout(ram_base: u32, ram_size: usize) -> Self {
+        Memory {
+            ram: Box::new(VecBackend::new(ram_size)),
+            ram_base,
+            devices: Vec::new(),
+            exit: None,
+            reset: false,
+            tracepoint: None,
+            failed_assert: None,
+            guard: None,
+            tohost: None,
+            stores: None,
+            stats: None,
+            accesses: None,
+            big_endian: false,
+            aliases: Vec::new(),
+            injected: 0,
+        }
+    }
+    pub fn ram_base(&self) -> u32 {
+        self.ram_base
+    }
+    pub fn ram_size(&self) -> usize {
+        self.ram.size()
+    }
+    // first address after ram, as u64 since ram may extend up to the end of the address space
+    pub fn ram_end(&self) -> u64 {
+        self.ram_base as u64 + self.ram.size() as u64
+    }
+    // replaces the storage of ram, its size follows the new backend
+    pub fn set_backend(&mut self, backend: Box<dyn MemoryBackend>) {
+        self.ram = backend;
+    }
+    // Copy of the memory and its devices that shares ram copy-on-write, None if a device is tied to
+    // a host resource and can't be copied.
+    pub fn fork(&mut self) -> Option<Memory> {
+        let devices = self
+            .devices
+            .iter()
+            .map(|dev| dev.fork())
+            .collect::<Option<Vec<_>>>()?;
+        Some(Memory {
+            ram: self.fork_ram(),
+            ram_base: self.ram_base,
+            devices,
+            exit: None,
+            reset: false,
+            tracepoint: None,
+            failed_assert: None,
+            guard: self.guard.clone(),
+            tohost: self.tohost,
+            stores: self.stores.as_ref().map(|_| Vec::new()),
+            stats: None,
+            accesses: None,
+            big_endian: self.big_endian,
+            aliases: self.aliases.clone(),
+            injected: 0,
+        })
+    }
+    // Splits ram into two copies that share the current contents until either side writes to it,
+    // returns the second copy.
+    pub fn fork_ram(&mut self) -> Box<dyn MemoryBackend> {
+        if let Some(copy) = self.ram.fork() {
+            return copy;
+        }
+        let frozen = std::mem::replace(&mut self.ram, Box::new(VecBackend::new(0)));
+        let snapshot: Rc<dyn MemoryBackend> = Rc::from(frozen);
+        self.ram = Box::new(CowBackend::new(snapshot.clone()));
+        Box::new(CowBackend::new(snapshot))
+    }
+    pub fn set_guard(&mut self, guard: Range<u32>) {
+        self.guard = Some(guard);
+    }
+    pub fn guard(&self) -> Option<&Range<u32>> {
+        self.guard.as_ref()
+    }
+    pub fn in_guard(&self, address: u32) -> bool {
+        let address = self.resolve(address);
+        self.guard
+            .as_ref()
+            .is_some_and(|guard| guard.contains(&address))
+    }
+    fn touches_guard(&self, address: u32, size: Size) -> bool {
+        self.in_guard(address) || self.in_guard(address.wrapping_add(size as u32 - 1))
+    }
+    pub fn set_tohost(&mut self, address: u32) {
+        self.tohost = Some(address);
+    }
+    pub fn add_device(&mut self, device: Box<dyn Device>) {
+        self.devices.push(device);
+    }
+    pub fn add_alias(&mut self, alias: Alias) {
+        self.aliases.push(alias);
+    }
+    // the address an access through an alias ends up at
+    fn resolve(&self, address: u32) -> u32 {
+        self.aliases
+            .iter()
+            .find(|alias| address >= alias.base && address - alias.base < alias.size)
+            .map_or(address, |alias| {
+                alias.target.wrapping_add(address - alias.base)
+            })
+    }
+    pub fn devices(&self) -> impl Iterator<Item = &dyn Device> {
+        self.devices.iter().map(|dev| dev.as_ref())
+    }
+    // Redirects the output of the uart and the debug console into the returned buffer instead of
+    // stdout. Devices added later keep their own sink.
+    pub fn capture_output(&mut self) -> Capture {
+        let capture = Capture::new();
+        for dev in self.devices.iter_mut() {
+            dev.set_sink(Box::new(capture.clone()));
+        }
+        capture
     }
-    pub fn read(&self, size: Size, from: u32, is_unsigned: bool) -> u32 {
-        let to = from + size.clone() as u32;
+    // Connects the first device taking input, i.e. the uart, to the link instead of stdin and
+    // stdout. Returns false if there is no such device.
+    pub fn link_serial(&mut self, link: SerialLink) -> bool {
+        let Some(dev) = self.devices.iter_mut().find(|dev| dev.has_input()) else {
+            return false;
+        };
+        dev.set_source(link.input);
+        dev.set_sink(link.output);
+        true
+    }
+    // lets devices with their own clock follow the host's time instead of counting cycles
+    pub fn follow_host_time(&mut self, start: Instant) {
+        for dev in self.devices.iter_mut() {
+            dev.follow_host_time(start);
+        }
+    }
+    fn in_ram(&self, address: u32, size: usize) -> bool {
+        address >= self.ram_base && address as u64 + size as u64 <= self.ram_end()
+    }
+    // up to `len` bytes of ram starting at the address, looking at them doesn't affect any device
+    pub fn peek(&self, address: u32, len: usize) -> Cow<'_, [u8]> {
+        let address = self.resolve(address);
+        if !self.in_ram(address, 0) {
+            return Cow::Borrowed(&[]);
+        }
+        let start = address.wrapping_sub(self.ram_base) as usize;
+        self.ram
+            .read(start..start.saturating_add(len).min(self.ram.size()))
+    }
+    // checks whether the whole access hits either ram or a device
+    pub fn is_mapped(&self, address: u32, size: Size) -> bool {
+        let address = self.resolve(address);
+        let end = address as u64 + size as u64;
+        self.in_ram(address, size as usize)
+            || self
+                .devices
+                .iter()
+                .any(|dev| address >= dev.base() && end <= dev.base() as u64 + dev.size() as u64)
+    }
+    // whether any byte of the range is taken by ram or a device
+    pub fn overlaps(&self, start: u32, size: u32) -> bool {
+        let end = start as u64 + size as u64;
+        let overlap = |base: u64, len: u64| (start as u64) < base + len && base < end;
+        overlap(self.ram_base as u64, self.ram_size() as u64)
+            || self
+                .aliases
+                .iter()
+                .any(|alias| overlap(alias.base as u64, alias.size as u64))
+            || self
+                .devices
+                .iter()
+                .any(|dev| overlap(dev.base() as u64, dev.size() as u64))
+    }
+    fn is_read_only(&self, address: u32) -> bool {
+        let address = self.resolve(address);
+        !self.in_ram(address, 1)
+            && self.devices.iter().any(|dev| {
+                address >= dev.base() && address - dev.base() < dev.size() && dev.read_only()
+            })
+    }
+    // returns the device mapped at address together with the offset into its register window
+    fn device_at(&mut self, address: u32) -> Option<(&mut Box<dyn Device>, u32)> {
+        self.devices
+            .iter_mut()
+            .find(|dev| address >= dev.base() && address - dev.base() < dev.size())
+            .map(|dev| {
+                let offset = address - dev.base();
+                (dev, offset)
+            })
+    }
+    pub fn read(&mut self, size: Size, from: u32, is_unsigned: bool) -> u32 {
+        let from = self.resolve(from);
+        if !self.in_ram(from, size as usize) {
+            if let Some((dev, offset)) = self.device_at(from) {
+                let value = dev.read(offset, size);
+                return match (size, is_unsigned) {
+                    (Size::Byte, false) => value as i8 as u32,
+                    (Size::HalfWord, false) => value as i16 as u32,
+                    _ => value,
+                };
+            }
+        }
+        let from = from.wrapping_sub(self.ram_base);
+        let to = from as usize + size as usize;
         match (size, is_unsigned) {
-            (Size::Byte, true) => read_mem!(u8, self.0, from, to),
-            (Size::HalfWord, true) => read_mem!(u16, self.0, from, to),
-            (Size::Byte, false) => read_mem!(i8, self.0, from, to),
-            (Size::HalfWord, false) => read_mem!(i16, self.0, from, to),
-            (Size::Word, _) => read_mem!(u32, self.0, from, to),
+            (Size::Byte, true) => read_mem!(u8, self.ram, from, to),
+            (Size::HalfWord, true) => read_mem!(u16, self.ram, from, to),
+            (Size::Byte, false) => read_mem!(i8, self.ram, from, to),
+            (Size::HalfWord, false) => read_mem!(i16, self.ram, from, to),
+            (Size::Word, _) => read_mem!(u32, self.ram, from, to),
         }
     }
     pub fn write(&mut self, size: Size, address: u32, value: u32) {
+        let address = self.resolve(address);
+        if !self.in_ram(address, size as usize) {
+            if let Some((dev, offset)) = self.device_at(address) {
+                dev.write(offset, size, value);
+                let (exit, tracepoint) = (dev.take_exit(), dev.take_tracepoint());
+                let reset = dev.take_reset();
+                let failed_assert = dev.take_failed_assert();
+                if exit.is_some() {
+                    self.exit = exit;
+                }
+                self.reset |= reset;
+                if tracepoint.is_some() {
+                    self.tracepoint = tracepoint;
+                }
+                if failed_assert.is_some() {
+                    self.failed_assert = failed_assert;
+                }
+                return;
+            }
+        }
+        // 1 signals success, otherwise the failed test number is stored shifted by one
+        if self.tohost == Some(address) && value != 0 {
+            self.exit = Some(if value == 1 {
+                0
+            } else {
+                (value >> 1).clamp(1, 255) as u8
+            });
+        }
         let slice = value.to_le_bytes();
-        let address = address as usize;
-        match size {
-            Size::Byte => self.0[address..address + size as usize].copy_from_slice(&slice[0..1]),
-            Size::HalfWord => {
-                self.0[address..address + size as usize].copy_from_slice(&slice[0..2])
+        let address = address.wrapping_sub(self.ram_base) as usize;
+        self.ram.write(address, &slice[..size as usize]);
+    }
+
+    // advances all devices by one cycle and routes their interrupt lines
+    pub fn tick(&mut self) {
+        let mut irq_lines = std::mem::take(&mut self.injected);
+        for n in 0..self.devices.len() {
+            self.devices[n].tick();
+            self.serve_transfers(n);
+            self.devices[n].access_ram(&mut Ram::new(&mut *self.ram, self.ram_base));
+            let dev = &mut self.devices[n];
+            self.reset |= dev.take_reset();
+            if let Some(irq) = dev.irq() {
+                irq_lines |= 1 << irq;
             }
-            Size::Word => self.0[address..address + size as usize].copy_from_slice(&slice[0..4]),
         }
+        for dev in self.devices.iter_mut() {
+            dev.set_irq_lines(irq_lines);
+        }
+    }
+
+    // raises the interrupt source for one cycle, like a device pulsing its line
+    pub fn inject_irq(&mut self, source: u32) {
+        self.injected |= 1 << source;
+    }
+
+    // cycles until the next device deadline, if any device has one
+    pub fn next_event(&self) -> Option<u64> {
+        self.devices.iter().filter_map(|dev| dev.next_event()).min()
+    }
+
+    pub fn skip(&mut self, cycles: u64) {
+        for n in 0..self.devices.len() {
+            self.devices[n].skip(cycles);
+            self.serve_transfers(n);
+            self.devices[n].access_ram(&mut Ram::new(&mut *self.ram, self.ram_base));
+            self.reset |= self.devices[n].take_reset();
+        }
+    }
+
+    // performs the copies the device made as a bus master
+    fn serve_transfers(&mut self, n: usize) {
+        while let Some(transfer) = self.devices[n].take_transfer() {
+            let ok = self.copy(transfer);
+            self.devices[n].transfer_done(ok);
+        }
+    }
+
+    // Copies byte by byte through the bus, so devices can be source or destination as well. Stops
+    // at the first byte that isn't mapped or can't be written and returns false then.
+    fn copy(&mut self, transfer: Transfer) -> bool {
+        for n in 0..transfer.len {
+            let src = transfer.src.wrapping_add(n);
+            let dst = transfer.dst.wrapping_add(n);
+            if !self.is_mapped(src, Size::Byte)
+                || !self.is_mapped(dst, Size::Byte)
+                || self.is_read_only(dst)
+            {
+                return false;
+            }
+            let byte = self.read(Size::Byte, src, true);
+            self.write(Size::Byte, dst, byte);
+        }
+        true
+    }
+
+    // interrupt-pending bits (as in mip) asserted by interrupt controllers at the hart
+    pub fn interrupts(&self, hart: usize) -> u32 {
+        self.devices
+            .iter()
+            .fold(0, |pending, dev| pending | dev.interrupts(hart))
+    }
+
+    // Loads and stores performed by the program, unmapped addresses and the stack guard page raise
+    // an access fault.
+    // Misaligned accesses are supported, so they never raise a misaligned exception.
+    pub fn load(&mut self, size: Size, from: u32, is_unsigned: bool) -> Result<u32, Exception> {
+        if !self.is_mapped(from, size) || self.touches_guard(from, size) {
+            return Err(Exception::LoadAccessFault(from));
+        }
+        if let Some(stats) = self.stats.as_mut() {
+            stats.record(from, size as u32, false);
+        }
+        let value = if !self.big_endian {
+            self.read(size, from, is_unsigned)
+        } else {
+            let value = self.read(size, from, true);
+            match (size, is_unsigned) {
+                (Size::Byte, false) => value as i8 as u32,
+                (Size::HalfWord, true) => (value as u16).swap_bytes() as u32,
+                (Size::HalfWord, false) => (value as u16).swap_bytes() as i16 as u32,
+                (Size::Word, _) => value.swap_bytes(),
+                _ => value,
+            }
+        };
+        if let Some(accesses) = self.accesses.as_mut() {
+            accesses.record(from, size, false, value);
+        }
+        Ok(value)
+    }
+    pub fn store(&mut self, size: Size, address: u32, value: u32) -> Result<(), Exception> {
+        if !self.is_mapped(address, size)
+            || self.touches_guard(address, size)
+            || self.is_read_only(address)
+        {
+            return Err(Exception::StoreAccessFault(address));
+        }
+        if let Some(stores) = self.stores.as_mut() {
+            let last = address.wrapping_add(size as u32 - 1) & !3;
+            stores.push(address & !3);
+            if last != address & !3 {
+                stores.push(last);
+            }
+        }
+        if let Some(stats) = self.stats.as_mut() {
+            stats.record(address, size as u32, true);
+        }
+        if let Some(accesses) = self.accesses.as_mut() {
+            accesses.record(address, size, true, value);
+        }
+        let value = match size {
+            Size::HalfWord if self.big_endian => (value as u16).swap_bytes() as u32,
+            Size::Word if self.big_endian => value.swap_bytes(),
+            _ => value,
+        };
+        self.write(size, address, value);
+        Ok(())
+    }
+    pub fn set_big_endian(&mut self, big_endian: bool) {
+        self.big_endian = big_endian;
+    }
+
+    pub fn enable_stats(&mut self) {
+        self.stats.get_or_insert_with(MemStats::new);
+    }
+    pub fn stats(&self) -> Option<&MemStats> {
+        self.stats.as_ref()
+    }
+
+    // records the loads and stores touching the regions, see `drain_accesses`
+    pub fn enable_access_log(&mut self, regions: Vec<Range<u32>>) {
+        self.accesses = Some(AccessRecorder::new(regions));
+    }
+    pub fn drain_accesses(&mut self) -> impl Iterator<Item = LoggedAccess> + '_ {
+        self.accesses.iter_mut().flat_map(AccessRecorder::drain)
+    }
+
+    pub fn enable_store_log(&mut self) {
+        self.stores.get_or_insert_with(Vec::new);
+    }
+    // removes the logged word addresses, keeping the log's allocation
+    pub fn drain_stores(&mut self) -> impl Iterator<Item = u32> + '_ {
+        self.stores.iter_mut().flat_map(|stores| stores.drain(..))
+    }
+
+    // copies raw bytes into ram, used to place boot data like the device tree
+    pub fn write_bytes(&mut self, address: u32, bytes: &[u8]) {
+        let address = self.resolve(address);
+        let address = address.wrapping_sub(self.ram_base) as usize;
+        self.ram.write(address, bytes);
+    }
+
+    pub fn take_exit(&mut self) -> Option<u8> {
+        self.exit.take()
+    }
+
+    pub fn take_reset(&mut self) -> bool {
+        std::mem::take(&mut self.reset)
+    }
+
+    // returns all devices to their power-on state, ram keeps its contents
+    pub fn reset_devices(&mut self) {
+        for dev in self.devices.iter_mut() {
+            dev.reset();
+        }
+        self.exit = None;
+        self.reset = false;
+        self.tracepoint = None;
+        self.failed_assert = None;
+    }
+
+    pub fn take_tracepoint(&mut self) -> Option<u32> {
+        self.tracepoint.take()
+    }
+
+    pub fn take_failed_assert(&mut self) -> Option<AssertFailure> {
+        self.failed_assert.take()
     }
 
-    // loads program to start of the memory
-    pub fn load_program(&mut self, mut program: Vec<u8>) {
-        program.resize_with(MEMSIZE, || 0);
-        self.0 = program.as_slice().try_into().unwrap()
+    // Loads program to start of the memory, the rest of ram keeps its contents which are zero unless
+    // the backend was filled before, e.g. a file from an earlier run.
+    pub fn load_program(&mut self, program: Vec<u8>) {
+        let len = program.len().min(self.ram.size());
+        self.ram.write(0, &program[..len]);
     }
 }
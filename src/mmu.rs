@@ -0,0 +1,185 @@
+// Sv32 address translation, see riscv-privileged spec chapter 10.3. There is no tlb, every access
+// walks the page table, so sfence.vma has nothing to flush and changes to the page table take
+// effect right away. The walk sets the accessed and dirty bits itself like qemu does, instead of
+// raising page faults for the kernel to set them.
+use crate::csr::{Csrs, Mode, MSTATUS_MPP, MSTATUS_MPRV, MSTATUS_MXR, MSTATUS_SUM, SATP_SV32};
+use crate::memory::{Memory, Size};
+use crate::trap::Exception;
+use crate::trigger::Access;
+
+const PAGE_SIZE: u64 = 4096;
+// physical page number of the root table in satp
+const SATP_PPN: u32 = 0x3f_ffff;
+
+const PTE_V: u32 = 1 << 0;
+const PTE_R: u32 = 1 << 1;
+const PTE_W: u32 = 1 << 2;
+const PTE_X: u32 = 1 << 3;
+const PTE_U: u32 = 1 << 4;
+const PTE_A: u32 = 1 << 6;
+const PTE_D: u32 = 1 << 7;
+
+// the privilege mode whose translation applies, with mprv loads and stores of machine mode are
+// translated like the ones of the mode in mpp
+fn effective_mode(csrs: &Csrs, access: Access) -> Mode {
+    if access != Access::Execute && csrs.mode == Mode::Machine && csrs.mstatus & MSTATUS_MPRV != 0 {
+        Mode::from_bits((csrs.mstatus & MSTATUS_MPP) >> 11).unwrap()
+    } else {
+        csrs.mode
+    }
+}
+
+// whether the accesses are translated, machine mode always uses physical addresses
+pub fn enabled(csrs: &Csrs, access: Access) -> bool {
+    csrs.satp & SATP_SV32 != 0 && effective_mode(csrs, access) != Mode::Machine
+}
+
+// amos are reported as stores, like their access faults
+fn page_fault(access: Access, address: u32) -> Exception {
+    match access {
+        Access::Execute => Exception::InstructionPageFault(address),
+        Access::Load => Exception::LoadPageFault(address),
+        Access::Store | Access::LoadStore => Exception::StorePageFault(address),
+    }
+}
+
+fn access_fault(access: Access, address: u32) -> Exception {
+    match access {
+        Access::Execute => Exception::InstructionAccessFault(address),
+        Access::Load => Exception::LoadAccessFault(address),
+        Access::Store | Access::LoadStore => Exception::StoreAccessFault(address),
+    }
+}
+
+// User pages can't be accessed by supervisor mode unless sum is set, and are never executed by
+// it. With mxr executable pages can also be read.
+fn permitted(csrs: &Csrs, mode: Mode, pte: u32, access: Access) -> bool {
+    let privilege = match mode {
+        Mode::User => pte & PTE_U != 0,
+        _ => pte & PTE_U == 0 || (access != Access::Execute && csrs.mstatus & MSTATUS_SUM != 0),
+    };
+    let readable = pte & PTE_R != 0 || (pte & PTE_X != 0 && csrs.mstatus & MSTATUS_MXR != 0);
+    let writable = pte & PTE_W != 0;
+    privilege
+        && match access {
+            Access::Execute => pte & PTE_X != 0,
+            Access::Load => readable,
+            Access::Store => writable,
+            Access::LoadStore => readable && writable,
+        }
+}
+
+// Returns the physical address the virtual address of the access maps to. Page tables outside of
+// memory and physical addresses beyond the 32-bit address space raise access faults.
+pub fn translate(
+    mem: &mut Memory,
+    csrs: &Csrs,
+    address: u32,
+    access: Access,
+) -> Result<u32, Exception> {
+    if !enabled(csrs, access) {
+        return Ok(address);
+    }
+    let mode = effective_mode(csrs, access);
+    let fault = page_fault(access, address);
+    let mut table = (csrs.satp & SATP_PPN) as u64 * PAGE_SIZE;
+    for level in [1, 0] {
+        let vpn = (address >> (12 + 10 * level)) & 0x3ff;
+        let pte_address = u32::try_from(table + vpn as u64 * 4)
+            .ok()
+            .filter(|&pte_address| mem.is_mapped(pte_address, Size::Word))
+            .ok_or(access_fault(access, address))?;
+        let pte = mem.read(Size::Word, pte_address, true);
+        if pte & PTE_V == 0 || (pte & PTE_R == 0 && pte & PTE_W != 0) {
+            return Err(fault);
+        }
+        let ppn = (pte >> 10) as u64;
+        // a pointer to the next level of the table
+        if pte & (PTE_R | PTE_X) == 0 {
+            table = ppn * PAGE_SIZE;
+            continue;
+        }
+        // megapages have to be aligned to their size
+        if !permitted(csrs, mode, pte, access) || (level == 1 && ppn & 0x3ff != 0) {
+            return Err(fault);
+        }
+        let written = matches!(access, Access::Store | Access::LoadStore);
+        let updated = pte | PTE_A | if written { PTE_D } else { 0 };
+        if updated != pte {
+            mem.write(Size::Word, pte_address, updated);
+        }
+        let offset = (1 << (12 + 10 * level)) - 1;
+        let physical = (ppn * PAGE_SIZE) & !offset | address as u64 & offset;
+        return u32::try_from(physical).map_err(|_| access_fault(access, address));
+    }
+    // the last level has to be a leaf
+    Err(fault)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // root table at 0x1000 with a megapage at 0x0040_0000 mapped to 0x0, a second level table at
+    // 0x2000 for the 4 MiB from 0x0080_0000 with a user page at 0x0080_1000 mapped to 0x3000
+    fn page_table() -> (Memory, Csrs) {
+        let mut mem = Memory::new();
+        mem.write(Size::Word, 0x1004, PTE_V | PTE_R | PTE_W | PTE_X);
+        mem.write(Size::Word, 0x1008, (0x2 << 10) | PTE_V);
+        mem.write(
+            Size::Word,
+            0x2004,
+            (0x3 << 10) | PTE_V | PTE_R | PTE_W | PTE_U,
+        );
+        let mut csrs = Csrs::new();
+        csrs.satp = SATP_SV32 | 0x1;
+        csrs.mode = Mode::Supervisor;
+        (mem, csrs)
+    }
+
+    #[test]
+    fn walk() {
+        let (mut mem, mut csrs) = page_table();
+        let mut walk = |csrs: &Csrs, address, access| translate(&mut mem, csrs, address, access);
+        assert_eq!(walk(&csrs, 0x0040_0123, Access::Execute), Ok(0x123));
+        assert_eq!(
+            walk(&csrs, 0x0080_0000, Access::Load),
+            Err(Exception::LoadPageFault(0x0080_0000))
+        );
+        // supervisor mode needs sum to access user pages
+        assert_eq!(
+            walk(&csrs, 0x0080_1008, Access::Store),
+            Err(Exception::StorePageFault(0x0080_1008))
+        );
+        csrs.mstatus |= MSTATUS_SUM;
+        assert_eq!(walk(&csrs, 0x0080_1008, Access::Store), Ok(0x3008));
+        assert_eq!(
+            walk(&csrs, 0x0080_1008, Access::Execute),
+            Err(Exception::InstructionPageFault(0x0080_1008))
+        );
+        csrs.mode = Mode::User;
+        assert_eq!(
+            walk(&csrs, 0x0040_0000, Access::Load),
+            Err(Exception::LoadPageFault(0x0040_0000))
+        );
+        // machine mode isn't translated unless mprv selects another mode in mpp
+        csrs.mode = Mode::Machine;
+        assert_eq!(walk(&csrs, 0x0080_1008, Access::Load), Ok(0x0080_1008));
+        csrs.mstatus = MSTATUS_MPRV;
+        assert_eq!(walk(&csrs, 0x0080_1008, Access::Load), Ok(0x3008));
+        assert_eq!(walk(&csrs, 0x0080_1008, Access::Execute), Ok(0x0080_1008));
+    }
+
+    #[test]
+    fn accessed_and_dirty() {
+        let (mut mem, mut csrs) = page_table();
+        csrs.mode = Mode::User;
+        translate(&mut mem, &csrs, 0x0080_1000, Access::Load).unwrap();
+        assert_eq!(mem.read(Size::Word, 0x2004, true) & (PTE_A | PTE_D), PTE_A);
+        translate(&mut mem, &csrs, 0x0080_1000, Access::LoadStore).unwrap();
+        assert_eq!(
+            mem.read(Size::Word, 0x2004, true) & (PTE_A | PTE_D),
+            PTE_A | PTE_D
+        );
+    }
+}
@@ -1,6 +1,4 @@
-use crate::error::*;
-use crate::memory::*;
-
+#[derive(Clone)]
 pub struct ProgramCounter(u32);
 impl ProgramCounter {
     pub fn new() -> Self {
@@ -14,12 +12,9 @@ impl ProgramCounter {
     }
     // Increments the program counter and returns the pc before it was incremented.
     // Basically a poor mans i++;
-    pub fn inc(&mut self) -> Result<u32, Error> {
+    pub fn inc(&mut self) -> u32 {
         let pc = self.0;
-        self.0 += 4;
-        if pc > MEMSIZE as u32 - 4 {
-            return Err(Error::InvalidPC(pc, MEMSIZE));
-        }
-        Ok(pc)
+        self.0 = self.0.wrapping_add(4);
+        pc
     }
 }
@@ -0,0 +1,814 @@
+// Backend for online playgrounds, started with `ruscv serve <address>`: an http server to which a
+// web page submits programs and then steps through them, each program in a session with a
+// machine of its own. Replies are json, steps and runs reply with what they changed, so that the
+// page only has to redraw the differences:
+//   POST   /sessions               {"assembly": "li a0, 1 ..."} or {"binary": "<hex of raw or elf>"}
+//                                  and optionally "memory", the ram size in bytes; replies the id
+//                                  and the state of the new session
+//   GET    /sessions/<id>          state: status, pc, all registers, instructions, quota, console
+//   POST   /sessions/<id>/step     {"count"} executes instructions (default: 1), replies the diff
+//   POST   /sessions/<id>/run      {"count"} like step, but by default until the program stops
+//   POST   /sessions/<id>/reset    resets the machine, ram keeps its contents
+//   GET    /sessions/<id>/memory?address=<addr>&length=<n>
+//   DELETE /sessions/<id>
+//   GET    /sessions/<id>/ws       websocket taking {"id", "method", "params"} messages for the
+//                                  methods state, step, run, reset and read_memory, answered with
+//                                  {"id", "result"} or {"id", "error"}
+// A diff holds the status, pc and instruction count, the registers that changed by name, the
+// written ram words as {"address", "data"} ranges of hex and the console output since the last
+// reply. Sessions execute at most their instruction quota over their whole life, get at most the
+// configured ram, keep at most the configured console output (replies carry
+// "console_truncated": true once more was dropped) and are dropped after being idle for
+// IDLE_TIMEOUT. Their programs can't read the
+// host's stdin and only open /dev/urandom.
+use crate::asm;
+use crate::control::{finished_status, optional_number, parse_hex};
+use crate::cpu::{Cpu, StopReason};
+use crate::devices::Capture;
+use crate::elf::Elf;
+use crate::error::Error;
+use crate::json::Json;
+use crate::memory::{Memory, MEMSIZE};
+use crate::regs::Reg;
+use crate::rng::Rng;
+
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeSet, HashMap};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use tungstenite::handshake::derive_accept_key;
+use tungstenite::protocol::{Role, WebSocket};
+use tungstenite::Message;
+
+// sessions nobody called for this long are dropped
+const IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+// clients have this long to send their request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+// longest request line or header and largest body, a binary is sent as hex of twice its size
+const MAX_LINE: u64 = 8 << 10;
+const MAX_BODY: usize = 8 << 20;
+// instructions between two drains of the store log
+const RUN_CHUNK: u64 = 10_000;
+// largest read_memory reply, in bytes of memory
+const MAX_READ: u64 = 1 << 16;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    // instructions a session may execute over its life
+    pub instructions: u64,
+    // largest ram of a session in bytes
+    pub memory: usize,
+    // sessions open at the same time
+    pub sessions: usize,
+    // console output a session keeps over its life in bytes, the rest is dropped
+    pub console: usize,
+}
+
+impl Limits {
+    pub fn new() -> Self {
+        Limits {
+            instructions: 10_000_000,
+            memory: 1 << 20,
+            sessions: 64,
+            console: 1 << 20,
+        }
+    }
+}
+
+// Serves playground sessions at the address until the process is ended.
+pub fn serve(address: &str, limits: Limits) -> Result<(), Error> {
+    let listener = TcpListener::bind(address).map_err(Error::Serve)?;
+    eprintln!(
+        "playground server on http://{}",
+        listener.local_addr().map_err(Error::Serve)?
+    );
+    listen(listener, limits);
+    Ok(())
+}
+
+// serves every client on a thread of its own
+fn listen(listener: TcpListener, limits: Limits) {
+    let server = Arc::new(Server::new(limits));
+    for stream in listener.incoming().flatten() {
+        let server = server.clone();
+        std::thread::spawn(move || server.client(stream));
+    }
+}
+
+// a method call for the thread running a session
+struct Call {
+    method: String,
+    params: Json,
+    reply: Sender<Reply>,
+}
+
+type Reply = Result<Json, String>;
+
+struct Entry {
+    calls: Sender<Call>,
+    // ends once the session was deleted or idle for too long
+    thread: JoinHandle<()>,
+}
+
+struct Server {
+    limits: Limits,
+    sessions: Mutex<HashMap<String, Entry>>,
+    // draws the session ids, knowing one is all it takes to control its session
+    ids: Mutex<Rng>,
+}
+
+impl Server {
+    fn new(limits: Limits) -> Self {
+        let seed = RandomState::new().build_hasher().finish();
+        Server {
+            limits,
+            sessions: Mutex::new(HashMap::new()),
+            ids: Mutex::new(Rng::new(seed)),
+        }
+    }
+
+    // answers one http request or serves a websocket until it's closed
+    fn client(&self, stream: TcpStream) {
+        // the timeout only fails if it is zero
+        let _ = stream.set_read_timeout(Some(REQUEST_TIMEOUT));
+        let Ok(reader) = stream.try_clone() else {
+            return;
+        };
+        let request = match Request::read(&mut BufReader::new(reader)) {
+            Ok(request) => request,
+            Err(e) => return respond(&stream, 400, Some(&error(e))),
+        };
+        let upgrade = request.header("upgrade");
+        if upgrade.is_some_and(|protocol| protocol.eq_ignore_ascii_case("websocket")) {
+            return self.websocket(stream, &request);
+        }
+        let (status, body) = self.route(&request);
+        respond(&stream, status, body.as_ref());
+    }
+
+    fn route(&self, request: &Request) -> (u16, Option<Json>) {
+        let body = match std::str::from_utf8(&request.body) {
+            _ if request.body.is_empty() => Json::Null,
+            Ok(text) => match Json::parse(text) {
+                Ok(body) => body,
+                Err(e) => return (400, Some(error(format!("invalid json: {e}")))),
+            },
+            Err(_) => return (400, Some(error("the body isn't utf-8"))),
+        };
+        let path: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+        let (id, method, params) = match (request.method.as_str(), path.as_slice()) {
+            // preflight requests of pages served from another origin
+            ("OPTIONS", _) => return (204, None),
+            ("POST", ["sessions"]) => {
+                return match self.create(&body) {
+                    Ok(state) => (201, Some(state)),
+                    Err((status, e)) => (status, Some(error(e))),
+                }
+            }
+            ("DELETE", ["sessions", id]) => {
+                return match self.sessions.lock().unwrap().remove(*id) {
+                    Some(_) => (204, None),
+                    None => (404, Some(no_session(id))),
+                }
+            }
+            ("GET", ["sessions", id]) => (id, "state", Json::Null),
+            ("POST", ["sessions", id, method @ ("step" | "run" | "reset")]) => (id, *method, body),
+            ("GET", ["sessions", id, "memory"]) => (id, "read_memory", query(&request.query)),
+            _ => return (404, Some(error("not found"))),
+        };
+        match self.call(id, method, params) {
+            Some(Ok(result)) => (200, Some(result)),
+            Some(Err(e)) => (400, Some(error(e))),
+            None => (404, Some(no_session(id))),
+        }
+    }
+
+    // Starts a session for the program in the params, returns its state with its id.
+    fn create(&self, params: &Json) -> Result<Json, (u16, String)> {
+        let (program, symbols) = program(params).map_err(|e| (400, e))?;
+        let memory = match params.get("memory") {
+            None => MEMSIZE.min(self.limits.memory),
+            Some(memory) => memory
+                .as_u64()
+                .filter(|&memory| (4..=self.limits.memory as u64).contains(&memory))
+                .ok_or((400, format!("memory is 4 to {} bytes", self.limits.memory)))?
+                as usize,
+        };
+        let quota = self.limits.instructions;
+        let console = self.limits.console;
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, entry| !entry.thread.is_finished());
+        if sessions.len() >= self.limits.sessions {
+            return Err((503, "too many sessions, try again later".to_string()));
+        }
+        let (calls, receiver) = mpsc::channel();
+        let (started, start) = mpsc::channel();
+        // a cpu stays on the thread it was created on
+        let thread = std::thread::spawn(move || {
+            match Session::new(program, symbols, memory, quota, console) {
+                Ok(mut session) => {
+                    let _ = started.send(Ok(session.state()));
+                    session.serve(receiver);
+                }
+                Err(e) => {
+                    let _ = started.send(Err(e));
+                }
+            }
+        });
+        let mut state = start
+            .recv()
+            .unwrap_or(Err("the session failed to start".to_string()))
+            .map_err(|e| (400, e))?;
+        let id = format!("{:016x}", self.ids.lock().unwrap().next_u64());
+        if let Json::Object(members) = &mut state {
+            members.insert(0, ("id".to_string(), id.as_str().into()));
+        }
+        sessions.insert(id, Entry { calls, thread });
+        Ok(state)
+    }
+
+    // runs the method on the session's thread, None if there is no such session
+    fn call(&self, id: &str, method: &str, params: Json) -> Option<Reply> {
+        let calls = self.sessions.lock().unwrap().get(id)?.calls.clone();
+        let (reply, replies) = mpsc::channel();
+        let method = method.to_string();
+        calls
+            .send(Call {
+                method,
+                params,
+                reply,
+            })
+            .ok()?;
+        replies.recv().ok()
+    }
+
+    fn websocket(&self, mut stream: TcpStream, request: &Request) {
+        let path: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+        let ["sessions", id, "ws"] = path.as_slice() else {
+            return respond(&stream, 404, Some(&error("not found")));
+        };
+        let Some(key) = request.header("sec-websocket-key") else {
+            return respond(&stream, 400, Some(&error("missing Sec-WebSocket-Key")));
+        };
+        if !self.sessions.lock().unwrap().contains_key(*id) {
+            return respond(&stream, 404, Some(&no_session(id)));
+        }
+        let accept = derive_accept_key(key.as_bytes());
+        let handshake = write!(
+            stream,
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Accept: {accept}\r\n\r\n"
+        );
+        if handshake.is_err() || stream.set_read_timeout(Some(IDLE_TIMEOUT)).is_err() {
+            return;
+        }
+        let mut socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+        // pings are answered while reading, other frames than text ones are ignored
+        while let Ok(message) = socket.read() {
+            let text = match message {
+                Message::Text(text) => text,
+                Message::Close(_) => break,
+                _ => continue,
+            };
+            let reply = match Json::parse(&text) {
+                Err(e) => Json::object([("id", Json::Null), ("error", e.as_str().into())]),
+                Ok(message) => {
+                    let call = message.get("id").cloned().unwrap_or(Json::Null);
+                    let method = message.get("method").and_then(Json::as_str);
+                    let params = message.get("params").cloned().unwrap_or(Json::Null);
+                    let body = match method.map(|method| self.call(id, method, params)) {
+                        None => ("error", "missing method".into()),
+                        Some(Some(Ok(result))) => ("result", result),
+                        Some(Some(Err(e))) => ("error", Json::String(e)),
+                        Some(None) => ("error", format!("session '{id}' ended").as_str().into()),
+                    };
+                    Json::object([("id", call), body])
+                }
+            };
+            if socket.send(Message::Text(reply.to_string())).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+// the image and labels of the program given as assembly or as hex of a raw binary or elf file
+fn program(params: &Json) -> Result<(Vec<u8>, HashMap<String, u32>), String> {
+    if let Some(source) = params.get("assembly").and_then(Json::as_str) {
+        // sessions' ram starts at 0
+        let assembly = asm::assemble(source, 0).map_err(|e| format!("{e:?}"))?;
+        Ok((assembly.bytes, assembly.symbols))
+    } else if let Some(hex) = params.get("binary").and_then(Json::as_str) {
+        let binary = parse_hex(hex).ok_or("binary isn't a hex string")?;
+        Ok((binary, HashMap::new()))
+    } else {
+        Err("expected assembly or binary".to_string())
+    }
+}
+
+struct Session {
+    cpu: Cpu,
+    console: Capture,
+    // console output taken from the capture already, the state replies all of it
+    output: Vec<u8>,
+    // instructions the session may still execute
+    quota: u64,
+    // the status once the program stopped, it doesn't continue until the machine is reset
+    finished: Option<Json>,
+}
+
+impl Session {
+    fn new(
+        program: Vec<u8>,
+        symbols: HashMap<String, u32>,
+        memory: usize,
+        quota: u64,
+        console_limit: usize,
+    ) -> Result<Self, String> {
+        let mut cpu = Cpu::new(false);
+        cpu.mem = Memory::with_layout(0, memory);
+        cpu.set_quiet();
+        // the quota counts cycles, so wfi must not hold the server thread for wall-clock time
+        cpu.disable_idle_sleep();
+        let console = cpu.capture_output();
+        console.set_limit(console_limit);
+        if Elf::is_elf(&program) {
+            let elf = Elf::parse(&program).map_err(|e| format!("{e:?}"))?;
+            cpu.load_elf(&elf).map_err(|e| format!("{e:?}"))?;
+        } else if program.len() > memory {
+            return Err(format!(
+                "the program doesn't fit into {memory} bytes of ram"
+            ));
+        } else {
+            cpu.load(program);
+            cpu.symbols = symbols;
+        }
+        // the logged stores make up the memory of a diff
+        cpu.mem.enable_store_log();
+        Ok(Session {
+            cpu,
+            console,
+            output: Vec::new(),
+            quota,
+            finished: None,
+        })
+    }
+
+    // answers calls until the session is deleted or idle for too long
+    fn serve(&mut self, calls: Receiver<Call>) {
+        while let Ok(call) = calls.recv_timeout(IDLE_TIMEOUT) {
+            // the client may have disconnected in the meantime
+            let _ = call.reply.send(self.call(&call.method, &call.params));
+        }
+    }
+
+    fn call(&mut self, method: &str, params: &Json) -> Reply {
+        let number = |key| optional_number(&self.cpu, params, key).map_err(|(_, e)| e);
+        match method {
+            "state" => Ok(self.state()),
+            "step" => {
+                let count = number("count")?.unwrap_or(1);
+                self.execute(count)
+            }
+            "run" => {
+                let count = number("count")?.unwrap_or(u64::MAX);
+                self.execute(count)
+            }
+            "reset" => {
+                self.cpu.reset();
+                self.cpu.mem.drain_stores().for_each(drop);
+                self.finished = None;
+                Ok(self.state())
+            }
+            "read_memory" => {
+                let address = number("address")?.ok_or("missing address")? as u32;
+                let length = number("length")?.ok_or("missing length")?;
+                if length > MAX_READ {
+                    return Err(format!("can read at most {MAX_READ} bytes"));
+                }
+                let bytes = self.cpu.mem.peek(address, length as usize);
+                if (bytes.len() as u64) < length {
+                    return Err(format!("{address:#x} isn't in ram"));
+                }
+                Ok(Json::object([("data", hex(&bytes).into())]))
+            }
+            _ => Err(format!("unknown method '{method}'")),
+        }
+    }
+
+    // executes up to count instructions of the quota and replies what changed
+    fn execute(&mut self, count: u64) -> Reply {
+        if self.finished.is_some() {
+            return Err("the program stopped, reset the session to run it again".to_string());
+        }
+        if self.quota == 0 {
+            return Err("the session used up its instructions".to_string());
+        }
+        let registers: [u32; 32] = std::array::from_fn(|n| self.cpu.regs.read(n));
+        let mut stores = BTreeSet::new();
+        let budget = count.min(self.quota);
+        let start = self.cpu.cycles();
+        let mut executed = 0;
+        while executed < budget {
+            let result = self.cpu.run_for((budget - executed).min(RUN_CHUNK));
+            stores.extend(self.cpu.mem.drain_stores());
+            executed = self.cpu.cycles() - start;
+            if !matches!(result, Ok(StopReason::Yield)) {
+                self.finished = Some(finished_status(&result));
+                break;
+            }
+        }
+        self.quota -= executed.min(self.quota);
+
+        let mut diff = self.status();
+        let changed = (0..32)
+            .filter(|&n| self.cpu.regs.read(n) != registers[n])
+            .map(|n| (register(n), self.cpu.regs.read(n).into()));
+        diff.push(("registers".to_string(), Json::Object(changed.collect())));
+        diff.push(("memory".to_string(), self.written(&stores)));
+        let console = self.console_output();
+        diff.extend(self.console_members(console));
+        Ok(Json::Object(diff))
+    }
+
+    // the status and everything else a page needs to draw the session
+    fn state(&mut self) -> Json {
+        let mut state = self.status();
+        let registers = (0..32).map(|n| (register(n), self.cpu.regs.read(n).into()));
+        state.push(("registers".to_string(), Json::Object(registers.collect())));
+        state.push((
+            "memory_size".to_string(),
+            (self.cpu.mem.ram_size() as u64).into(),
+        ));
+        self.console_output();
+        let console = String::from_utf8_lossy(&self.output).into_owned();
+        state.extend(self.console_members(console));
+        Json::Object(state)
+    }
+
+    // whether the program stopped, the pc and the instructions executed and left
+    fn status(&self) -> Vec<(String, Json)> {
+        let mut status = match &self.finished {
+            Some(Json::Object(members)) => members.clone(),
+            _ => vec![("state".to_string(), "paused".into())],
+        };
+        status.push(("pc".to_string(), self.cpu.pc.get().into()));
+        status.push(("instructions".to_string(), self.cpu.cycles().into()));
+        status.push(("quota".to_string(), self.quota.into()));
+        status
+    }
+
+    // contiguous runs of the stored words as {"address", "data"} objects
+    fn written(&self, words: &BTreeSet<u32>) -> Json {
+        let mut ranges: Vec<(u32, u32)> = Vec::new();
+        for &word in words {
+            match ranges.last_mut() {
+                Some((start, length)) if start.wrapping_add(*length) == word => *length += 4,
+                _ => ranges.push((word, 4)),
+            }
+        }
+        let ranges = ranges.into_iter().filter_map(|(address, length)| {
+            // stores to devices aren't part of the diff
+            let bytes = self.cpu.mem.peek(address, length as usize);
+            (!bytes.is_empty())
+                .then(|| Json::object([("address", address.into()), ("data", hex(&bytes).into())]))
+        });
+        Json::Array(ranges.collect())
+    }
+
+    // the output since the last reply
+    fn console_output(&mut self) -> String {
+        let bytes = self.console.take();
+        let output = String::from_utf8_lossy(&bytes).into_owned();
+        self.output.extend(bytes);
+        output
+    }
+
+    // the console output of a reply, flagged once output was dropped past the limit
+    fn console_members(&self, console: String) -> Vec<(String, Json)> {
+        let mut members = vec![("console".to_string(), console.into())];
+        if self.console.truncated() {
+            members.push(("console_truncated".to_string(), Json::Bool(true)));
+        }
+        members
+    }
+}
+
+fn register(n: usize) -> String {
+    Reg::from_index(n).name().to_string()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn error(message: impl Into<String>) -> Json {
+    Json::object([("error", Json::String(message.into()))])
+}
+
+fn no_session(id: &str) -> Json {
+    error(format!("there is no session '{id}'"))
+}
+
+// the parts of an http request the server looks at
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    // names are lowercase
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn read(reader: &mut impl BufRead) -> Result<Request, String> {
+        let mut read_line = |line: &mut String| {
+            line.clear();
+            match (&mut *reader).take(MAX_LINE).read_line(line) {
+                Ok(_) if line.ends_with('\n') => Ok(()),
+                Ok(_) => Err("request line or header too long or cut off".to_string()),
+                Err(e) => Err(e.to_string()),
+            }
+        };
+        let mut line = String::new();
+        read_line(&mut line)?;
+        let mut words = line.split_whitespace();
+        let (Some(method), Some(target)) = (words.next(), words.next()) else {
+            return Err("invalid request line".to_string());
+        };
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let (method, path, query) = (method.to_string(), path.to_string(), query.to_string());
+        let mut headers = Vec::new();
+        loop {
+            read_line(&mut line)?;
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            let (name, value) = header.split_once(':').ok_or("invalid header")?;
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+        let mut request = Request {
+            method,
+            path,
+            query,
+            headers,
+            body: Vec::new(),
+        };
+        let length = match request.header("content-length") {
+            Some(length) => length.parse().map_err(|_| "invalid Content-Length")?,
+            None => 0,
+        };
+        if length > MAX_BODY {
+            return Err(format!("the body is larger than {MAX_BODY} bytes"));
+        }
+        request.body.resize(length, 0);
+        reader
+            .read_exact(&mut request.body)
+            .map_err(|e| e.to_string())?;
+        Ok(request)
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        let header = self.headers.iter().find(|(key, _)| key == name);
+        header.map(|(_, value)| value.as_str())
+    }
+}
+
+// the query's parameters as json object of strings, which numbers are read from like expressions
+fn query(query: &str) -> Json {
+    let params = query
+        .split('&')
+        .filter_map(|param| param.split_once('='))
+        .map(|(key, value)| (decode(key), Json::String(decode(value))));
+    Json::Object(params.collect())
+}
+
+// undoes the percent-encoding of a query component
+fn decode(text: &str) -> String {
+    let mut bytes = Vec::new();
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = tail
+            .get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        rest = tail;
+        match (byte, escaped) {
+            (b'%', Some(escaped)) => {
+                bytes.push(escaped);
+                rest = &tail[2..];
+            }
+            (b'+', _) => bytes.push(b' '),
+            _ => bytes.push(byte),
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn respond(mut stream: &TcpStream, status: u16, body: Option<&Json>) {
+    let reason = match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        _ => "Service Unavailable",
+    };
+    let body = body.map_or(String::new(), Json::to_string);
+    // the client may be gone already
+    let _ = write!(
+        stream,
+        "HTTP/1.1 {status} {reason}\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nAccess-Control-Allow-Origin: *\r\n\
+         Access-Control-Allow-Methods: GET, POST, DELETE\r\n\
+         Access-Control-Allow-Headers: Content-Type\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(source: &str, quota: u64) -> Session {
+        let (program, symbols) = program(&Json::object([("assembly", source.into())])).unwrap();
+        Session::new(program, symbols, MEMSIZE, quota, Limits::new().console).unwrap()
+    }
+
+    fn member<'a>(json: &'a Json, path: &[&str]) -> &'a Json {
+        path.iter()
+            .fold(json, |json, key| json.get(key).unwrap_or(&Json::Null))
+    }
+
+    #[test]
+    fn diffs_and_quota() {
+        let source = "
+            li a0, 5
+            li t0, 0x100
+            sw a0, 0(t0)
+            sw a0, 4(t0)
+            li a0, 1
+            li a1, 0x100
+            li a2, 1
+            li a7, 64
+            ecall
+            li a0, 3
+            li a7, 93
+            ecall";
+        let mut session = session(source, 1000);
+        let state = session.state();
+        assert_eq!(member(&state, &["state"]).as_str(), Some("paused"));
+        assert_eq!(member(&state, &["registers", "a0"]).as_u64(), Some(0));
+
+        let diff = session.call("step", &Json::Null).unwrap();
+        assert_eq!(member(&diff, &["pc"]).as_u64(), Some(4));
+        assert_eq!(member(&diff, &["registers"]).to_string(), r#"{"a0":5}"#);
+        let diff = session
+            .call("step", &Json::object([("count", "2 + 1".into())]))
+            .unwrap();
+        assert_eq!(
+            member(&diff, &["memory"]).to_string(),
+            r#"[{"address":256,"data":"0500000005000000"}]"#
+        );
+        let diff = session.call("run", &Json::Null).unwrap();
+        assert_eq!(member(&diff, &["state"]).as_str(), Some("exited"));
+        assert_eq!(member(&diff, &["exit_code"]).as_u64(), Some(3));
+        assert_eq!(member(&diff, &["console"]).as_str(), Some("\u{5}"));
+        assert_eq!(member(&diff, &["quota"]).as_u64(), Some(1000 - 12));
+        assert!(session.call("step", &Json::Null).is_err());
+
+        let params = Json::object([("address", "0x100".into()), ("length", 4u32.into())]);
+        let memory = session.call("read_memory", &params).unwrap();
+        assert_eq!(member(&memory, &["data"]).as_str(), Some("05000000"));
+        let state = session.call("reset", &Json::Null).unwrap();
+        assert_eq!(member(&state, &["pc"]).as_u64(), Some(0));
+
+        let mut session = session_loop(10);
+        let diff = session.call("run", &Json::Null).unwrap();
+        assert_eq!(member(&diff, &["state"]).as_str(), Some("paused"));
+        assert_eq!(member(&diff, &["instructions"]).as_u64(), Some(10));
+        assert_eq!(member(&diff, &["quota"]).as_u64(), Some(0));
+        assert!(session.call("run", &Json::Null).is_err());
+    }
+
+    #[test]
+    fn wfi_doesnt_sleep() {
+        let mut session = session("li t0, 8\ncsrw mie, t0\nloop:\nwfi\nj loop", 1000);
+        let start = std::time::Instant::now();
+        let diff = session.call("run", &Json::Null).unwrap();
+        assert_eq!(member(&diff, &["quota"]).as_u64(), Some(0));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn console_limit() {
+        // writes the 6 bytes "hello\n" four times
+        let source = "
+            li a0, 0x68
+            li t0, 0x100
+            sb a0, 0(t0)
+            li s0, 4
+        loop:
+            li a0, 1
+            li a1, 0x100
+            li a2, 6
+            li a7, 64
+            ecall
+            addi s0, s0, -1
+            bnez s0, loop
+            li a7, 93
+            ecall";
+        let (program, symbols) = program(&Json::object([("assembly", source.into())])).unwrap();
+        let mut session = Session::new(program, symbols, MEMSIZE, 1000, 16).unwrap();
+        let diff = session.call("run", &Json::Null).unwrap();
+        assert_eq!(member(&diff, &["state"]).as_str(), Some("exited"));
+        assert_eq!(member(&diff, &["console"]).as_str().map(str::len), Some(16));
+        assert_eq!(member(&diff, &["console_truncated"]), &Json::Bool(true));
+        // the state has all of the kept output, the next diff none of it
+        let state = session.state();
+        assert_eq!(
+            member(&state, &["console"]).as_str().map(str::len),
+            Some(16)
+        );
+        session.call("reset", &Json::Null).unwrap();
+        let diff = session.call("step", &Json::Null).unwrap();
+        assert_eq!(member(&diff, &["console"]).as_str(), Some(""));
+    }
+
+    fn session_loop(quota: u64) -> Session {
+        session("loop:\naddi a0, a0, 1\nj loop", quota)
+    }
+
+    // sends the request and returns the status and the body of the response
+    fn http(address: &str, request: &str) -> (u16, Json) {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        let body = if body.is_empty() {
+            Json::Null
+        } else {
+            Json::parse(body).unwrap()
+        };
+        (status, body)
+    }
+
+    #[test]
+    fn http_and_websocket() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let mut limits = Limits::new();
+        limits.sessions = 1;
+        std::thread::spawn(move || listen(listener, limits));
+
+        let body = r#"{"assembly": "li a0, 7\nli a7, 93\necall", "memory": 4096}"#;
+        let post = format!(
+            "POST /sessions HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        let (status, state) = http(&address, &post);
+        assert_eq!(status, 201);
+        assert_eq!(member(&state, &["memory_size"]).as_u64(), Some(4096));
+        assert_eq!(member(&state, &["registers", "sp"]).as_u64(), Some(4096));
+        let id = member(&state, &["id"]).as_str().unwrap().to_string();
+        let (status, _) = http(&address, &post);
+        assert_eq!(status, 503);
+
+        let step = format!("POST /sessions/{id}/step HTTP/1.1\r\n\r\n");
+        let (status, diff) = http(&address, &step);
+        assert_eq!(status, 200);
+        assert_eq!(member(&diff, &["registers", "a0"]).as_u64(), Some(7));
+        let read =
+            format!("GET /sessions/{id}/memory?address=0%20%2B%204&length=4 HTTP/1.1\r\n\r\n");
+        let (_, memory) = http(&address, &read);
+        assert_eq!(member(&memory, &["data"]).as_str(), Some("9308d005"));
+
+        let stream = TcpStream::connect(&address).unwrap();
+        let url = format!("ws://{address}/sessions/{id}/ws");
+        let (mut socket, _) = tungstenite::client(url, stream).unwrap();
+        let request = r#"{"id": 1, "method": "run"}"#;
+        socket.send(Message::Text(request.to_string())).unwrap();
+        let reply = Json::parse(socket.read().unwrap().to_text().unwrap()).unwrap();
+        assert_eq!(member(&reply, &["id"]).as_u64(), Some(1));
+        assert_eq!(member(&reply, &["result", "exit_code"]).as_u64(), Some(7));
+        socket
+            .send(Message::Text(r#"{"id": 2, "method": "fly"}"#.to_string()))
+            .unwrap();
+        let reply = Json::parse(socket.read().unwrap().to_text().unwrap()).unwrap();
+        assert!(member(&reply, &["error"]).as_str().is_some());
+        socket.close(None).unwrap();
+
+        let delete = format!("DELETE /sessions/{id} HTTP/1.1\r\n\r\n");
+        assert_eq!(http(&address, &delete).0, 204);
+        assert_eq!(http(&address, &step).0, 404);
+        assert_eq!(http(&address, "GET /elsewhere HTTP/1.1\r\n\r\n").0, 404);
+    }
+}
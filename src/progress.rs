@@ -0,0 +1,77 @@
+// Periodic heartbeat on stderr for long runs, so that it's visible the emulator isn't hung.
+use crate::elf::Elf;
+
+use std::time::Instant;
+
+pub struct Progress {
+    // instructions between two reports
+    interval: u64,
+    next: u64,
+    // time and retired instructions at the last report
+    last: (Instant, u64),
+    // start addresses and names of the program's symbols, sorted by address
+    symbols: Vec<(u32, String)>,
+}
+
+impl Progress {
+    pub fn new(interval: u64) -> Self {
+        Progress {
+            interval,
+            next: interval,
+            last: (Instant::now(), 0),
+            symbols: Vec::new(),
+        }
+    }
+
+    pub fn set_symbols(&mut self, elf: &Elf) {
+        self.symbols = elf
+            .symbols()
+            .filter(|(name, _)| !name.is_empty())
+            .map(|(name, address)| (address, name.to_string()))
+            .collect();
+        self.symbols.sort();
+    }
+
+    // the closest symbol at or below the address, e.g. memcpy+0x10
+    fn location(&self, pc: u32) -> String {
+        let index = self.symbols.partition_point(|&(address, _)| address <= pc);
+        match index.checked_sub(1).map(|i| &self.symbols[i]) {
+            Some((address, name)) if *address == pc => format!(" <{name}>"),
+            Some((address, name)) => format!(" <{name}+{:#x}>", pc - address),
+            None => String::new(),
+        }
+    }
+
+    // prints a report once another interval of instructions retired
+    pub fn update(&mut self, retired: u64, pc: u32) {
+        if retired < self.next {
+            return;
+        }
+        let now = Instant::now();
+        let (last_time, last_retired) = self.last;
+        let seconds = now.duration_since(last_time).as_secs_f64();
+        let mips = (retired - last_retired) as f64 / seconds.max(f64::EPSILON) / 1e6;
+        eprintln!(
+            "[progress] {}M instructions, {mips:.1} MIPS, pc {pc:#010x}{}",
+            retired / 1_000_000,
+            self.location(pc)
+        );
+        self.last = (now, retired);
+        self.next = retired + self.interval;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nearest_symbol() {
+        let mut progress = Progress::new(1);
+        progress.symbols = vec![(0x100, "main".to_string()), (0x200, "memcpy".to_string())];
+        assert_eq!(progress.location(0x80), "");
+        assert_eq!(progress.location(0x100), " <main>");
+        assert_eq!(progress.location(0x1fc), " <main+0xfc>");
+        assert_eq!(progress.location(0x210), " <memcpy+0x10>");
+    }
+}
@@ -1,27 +1,205 @@
-use crate::memory::*;
+// ABI names of the integer registers, see the risc-v calling convention
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reg {
+    Zero,
+    Ra,
+    Sp,
+    Gp,
+    Tp,
+    T0,
+    T1,
+    T2,
+    S0,
+    S1,
+    A0,
+    A1,
+    A2,
+    A3,
+    A4,
+    A5,
+    A6,
+    A7,
+    S2,
+    S3,
+    S4,
+    S5,
+    S6,
+    S7,
+    S8,
+    S9,
+    S10,
+    S11,
+    T3,
+    T4,
+    T5,
+    T6,
+}
+
+const NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+impl Reg {
+    pub const ALL: [Reg; 32] = [
+        Reg::Zero,
+        Reg::Ra,
+        Reg::Sp,
+        Reg::Gp,
+        Reg::Tp,
+        Reg::T0,
+        Reg::T1,
+        Reg::T2,
+        Reg::S0,
+        Reg::S1,
+        Reg::A0,
+        Reg::A1,
+        Reg::A2,
+        Reg::A3,
+        Reg::A4,
+        Reg::A5,
+        Reg::A6,
+        Reg::A7,
+        Reg::S2,
+        Reg::S3,
+        Reg::S4,
+        Reg::S5,
+        Reg::S6,
+        Reg::S7,
+        Reg::S8,
+        Reg::S9,
+        Reg::S10,
+        Reg::S11,
+        Reg::T3,
+        Reg::T4,
+        Reg::T5,
+        Reg::T6,
+    ];
+
+    pub fn from_index(reg_idx: usize) -> Reg {
+        Reg::ALL[reg_idx]
+    }
+    pub fn index(self) -> usize {
+        self as usize
+    }
+    pub fn name(self) -> &'static str {
+        NAMES[self.index()]
+    }
+    // accepts both ABI names and x0-x31, fp is an alias of s0
+    pub fn from_name(name: &str) -> Option<Reg> {
+        if name == "fp" {
+            return Some(Reg::S0);
+        }
+        let index = match name.strip_prefix('x') {
+            Some(index) => index.parse().ok().filter(|&index: &usize| index < 32)?,
+            None => NAMES.iter().position(|&abi| abi == name)?,
+        };
+        Some(Reg::from_index(index))
+    }
+}
+
+// a register write that changed the register's value
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RegChange {
+    pub reg: Reg,
+    pub old: u32,
+    pub new: u32,
+}
+
+// The integer register file. x0 is hardwired to zero by never storing to its slot, so reads and
+// the raw view need no special case.
+#[derive(Clone)]
+pub struct Registers {
+    regs: [u32; 32],
+    // writes since the changes were last drained, only recorded once logging is enabled
+    changes: Option<Vec<RegChange>>,
+}
 
-pub struct Registers([u32; 32]);
 impl Registers {
+    // the stack pointer is set up at reset, once the memory layout is known
     pub fn new() -> Self {
-        let mut regs = Registers([0; 32]);
-        // initializes stack pointer to top of stack
-        regs.0[2] = MEMSIZE as u32;
-        regs
+        Registers {
+            regs: [0; 32],
+            changes: None,
+        }
     }
     pub fn read(&self, reg_idx: usize) -> u32 {
-        assert!(reg_idx < 32, "rv32i only has 32 registers");
-        if reg_idx == 0 {
-            0
-        } else {
-            self.0[reg_idx]
-        }
+        self.regs[reg_idx]
     }
     pub fn write(&mut self, reg_idx: usize, value: u32) {
-        assert!(reg_idx < 32, "rv32i only has 32 registers");
         if reg_idx == 0 {
             return;
         }
+        let old = std::mem::replace(&mut self.regs[reg_idx], value);
+        if let Some(changes) = self.changes.as_mut().filter(|_| old != value) {
+            changes.push(RegChange {
+                reg: Reg::from_index(reg_idx),
+                old,
+                new: value,
+            });
+        }
+    }
+    pub fn get(&self, reg: Reg) -> u32 {
+        self.read(reg.index())
+    }
+    pub fn set(&mut self, reg: Reg, value: u32) {
+        self.write(reg.index(), value)
+    }
+    // all registers indexed by their number, x0 reads as zero
+    pub fn raw(&self) -> &[u32; 32] {
+        &self.regs
+    }
+    pub fn enable_change_log(&mut self) {
+        self.changes.get_or_insert_with(Vec::new);
+    }
+    // removes the logged changes in the order they were made, keeping the log's allocation
+    pub fn drain_changes(&mut self) -> impl Iterator<Item = RegChange> + '_ {
+        self.changes
+            .iter_mut()
+            .flat_map(|changes| changes.drain(..))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn x0_hardwired() {
+        let mut regs = Registers::new();
+        regs.enable_change_log();
+        regs.write(0, 5);
+        regs.set(Reg::A0, 7);
+        regs.set(Reg::A0, 7);
+        regs.set(Reg::Sp, 0x100);
+        assert_eq!(regs.get(Reg::Zero), 0);
+        assert_eq!(regs.raw()[0], 0);
+        assert_eq!(regs.read(10), 7);
+        assert_eq!(
+            regs.drain_changes().collect::<Vec<_>>(),
+            [
+                RegChange {
+                    reg: Reg::A0,
+                    old: 0,
+                    new: 7
+                },
+                RegChange {
+                    reg: Reg::Sp,
+                    old: 0,
+                    new: 0x100
+                },
+            ]
+        );
+        assert_eq!(regs.drain_changes().count(), 0);
+    }
 
-        self.0[reg_idx] = value;
+    #[test]
+    fn names() {
+        assert_eq!(Reg::from_name("a7"), Some(Reg::A7));
+        assert_eq!(Reg::from_name("x31"), Some(Reg::T6));
+        assert_eq!(Reg::from_name("fp"), Some(Reg::S0));
+        assert_eq!(Reg::from_name("x32"), None);
+        assert_eq!(Reg::S11.name(), "s11");
     }
 }
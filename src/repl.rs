@@ -0,0 +1,306 @@
+// Assembly repl for `ruscv repl`: every line typed is assembled behind the code typed before and
+// executed right away against the machine's state, which persists between lines. After each line
+// the registers and memory words it changed are printed, like in the online sandboxes people
+// learn assembly with. Labels are remembered, so a branch can go back to a label typed earlier and
+// the code runs until it falls through to the end of everything typed so far. Lines starting with
+// `:` are commands:
+//   :regs                        shows the pc and all registers
+//   :print <expr> | :x <expr> [<n>]   shows a value or n words at an address
+//   :reset                       resets the machine and starts over with no code
+//   :help                        lists the commands
+//   :quit [<status>]             ends the repl with the status
+use std::collections::BTreeSet;
+use std::io::{BufRead, Write};
+
+use crate::asm;
+use crate::cpu::{Cpu, StopReason};
+use crate::error::Error;
+use crate::expr::{self, Expr};
+use crate::regs::Reg;
+
+// a line that runs longer stops, e.g. an endless loop, the next line continues behind the code
+const STEP_LIMIT: u64 = 1_000_000;
+// written words printed after a line, the rest are only counted
+const SHOWN_WORDS: usize = 16;
+
+const HELP: &str =
+    "type an instruction or label to execute it, e.g. `li a0, 5` or `loop: addi a0, a0, -1`
+:regs                        shows the pc and all registers
+:print <expr> | :x <expr> [<n>]   shows a value or n words at an address
+:reset                       resets the machine and starts over with no code
+:help                        shows this help
+:quit [<status>]             ends the repl";
+
+struct Repl<'a> {
+    cpu: &'a mut Cpu,
+    out: &'a mut dyn Write,
+    // where the code typed so far ends and the next line goes
+    end: u32,
+}
+
+// Reads lines from the input until `:quit` or its end, showing a prompt if asked to, and returns
+// the exit status. Mistakes are reported without ending the repl.
+pub fn run(
+    cpu: &mut Cpu,
+    input: &mut dyn BufRead,
+    out: &mut dyn Write,
+    prompt: bool,
+) -> Result<u8, Error> {
+    // state dumps would bury the changes
+    cpu.set_quiet();
+    cpu.mem.enable_store_log();
+    let end = cpu.pc.get();
+    let mut repl = Repl { cpu, out, end };
+    if prompt {
+        repl.say("ruscv repl, type assembly to execute it or :help for the commands")?;
+    }
+    let status = loop {
+        if prompt {
+            write!(repl.out, "> ")
+                .and_then(|_| repl.out.flush())
+                .map_err(Error::ScriptIo)?;
+        }
+        let mut line = String::new();
+        if input.read_line(&mut line).map_err(Error::ScriptIo)? == 0 {
+            break 0;
+        }
+        let line = line.trim();
+        let result = match line.strip_prefix(':') {
+            Some(command) => match repl.command(command) {
+                Ok(Some(status)) => break status,
+                result => result.map(|_| ()),
+            },
+            None => repl.execute(line),
+        };
+        if let Err(e) = result {
+            repl.say(&e)?;
+        }
+    };
+    repl.cpu.finish(Ok(StopReason::HostRequest))?;
+    Ok(status)
+}
+
+impl Repl<'_> {
+    // assembles the line behind the code so far and runs until the pc reaches the new end
+    fn execute(&mut self, line: &str) -> Result<(), String> {
+        if line.is_empty() {
+            return Ok(());
+        }
+        // the labels typed earlier are defined relative to `.`, so that branches treat them as
+        // addresses and not as offsets
+        let end = self.end;
+        let mut source: String = self
+            .cpu
+            .symbols
+            .iter()
+            .map(|(name, &value)| match value.checked_sub(end) {
+                Some(ahead) => format!(".equ {name}, . + {ahead:#x}\n"),
+                None => format!(".equ {name}, . - {:#x}\n", end - value),
+            })
+            .collect();
+        source.push_str(line);
+        let assembly = asm::assemble(&source, self.end).map_err(|e| match e {
+            Error::Assembly(_, message) => message,
+            e => format!("{e:?}"),
+        })?;
+        let start = self.end;
+        let size = assembly.bytes.len();
+        if self.cpu.mem.peek(start, size).len() < size {
+            return Err(format!("no ram left for code at {start:#x}"));
+        }
+        self.cpu.mem.write_bytes(start, &assembly.bytes);
+        self.cpu.symbols.extend(assembly.symbols);
+        self.end = start.wrapping_add(size as u32);
+        if size == 0 {
+            return Ok(());
+        }
+
+        let registers: [u32; 32] = std::array::from_fn(|n| self.cpu.regs.read(n));
+        self.cpu.mem.drain_stores().for_each(drop);
+        self.cpu.pc.set(start);
+        let mut stopped = None;
+        for _ in 0..STEP_LIMIT {
+            if self.cpu.pc.get() == self.end {
+                break;
+            }
+            match self.cpu.step() {
+                Ok(None) => (),
+                Ok(Some(StopReason::Exit(code))) => {
+                    stopped = Some(format!("program exited with code {code}"));
+                    break;
+                }
+                Ok(Some(reason)) => {
+                    stopped = Some(format!("{reason:?}"));
+                    break;
+                }
+                Err(e) => {
+                    stopped = Some(format!("{e:?}"));
+                    break;
+                }
+            }
+        }
+        let pc = self.cpu.pc.get();
+        if stopped.is_none() && pc != self.end {
+            stopped = Some(format!("still running after {STEP_LIMIT} instructions"));
+        }
+        let stores: BTreeSet<u32> = self.cpu.mem.drain_stores().collect();
+        self.changes(&registers, &stores)
+            .map_err(|e| format!("{e:?}"))?;
+        match stopped {
+            Some(reason) => Err(format!(
+                "stopped at pc {pc:#x}: {reason}, the next line runs behind the code again"
+            )),
+            None => Ok(()),
+        }
+    }
+
+    // prints the registers and memory words that changed
+    fn changes(&mut self, registers: &[u32; 32], stores: &BTreeSet<u32>) -> Result<(), Error> {
+        for (n, &before) in registers.iter().enumerate() {
+            let value = self.cpu.regs.read(n);
+            if value != before {
+                let name = Reg::from_index(n).name();
+                self.say(&format!(
+                    "{name} = {}",
+                    expr::format_value(value as i32 as i64)
+                ))?;
+            }
+        }
+        for &address in stores.iter().take(SHOWN_WORDS) {
+            let bytes = self.cpu.mem.peek(address, 4);
+            // stores to devices aren't shown
+            if let Ok(word) = <[u8; 4]>::try_from(&bytes[..]) {
+                self.say(&format!(
+                    "[{address:#x}] = {:#010x}",
+                    u32::from_le_bytes(word)
+                ))?;
+            }
+        }
+        if stores.len() > SHOWN_WORDS {
+            self.say(&format!("and {} more words", stores.len() - SHOWN_WORDS))?;
+        }
+        Ok(())
+    }
+
+    // runs the command, returns the status if it ends the repl
+    fn command(&mut self, command: &str) -> Result<Option<u8>, String> {
+        let (name, args) = command
+            .trim()
+            .split_once(char::is_whitespace)
+            .unwrap_or((command.trim(), ""));
+        let args = args.trim();
+        let io = |e: Error| format!("{e:?}");
+        match name {
+            "regs" => self.regs().map_err(io)?,
+            "print" | "p" => {
+                let expr = Expr::parse(args)?;
+                let value = expr.eval(self.cpu)?;
+                self.say(&format!("{expr} = {}", expr::format_value(value)))
+                    .map_err(io)?;
+            }
+            "x" => {
+                let (address, count) = match args.rsplit_once(char::is_whitespace) {
+                    Some((address, count)) if count.parse::<usize>().is_ok() => {
+                        (address, count.parse().unwrap())
+                    }
+                    _ => (args, 1),
+                };
+                let address = Expr::parse(address)?.eval(self.cpu)? as u32;
+                let dump = expr::examine(self.cpu, address, count);
+                self.out
+                    .write_all(dump.as_bytes())
+                    .map_err(|e| format!("{e}"))?;
+            }
+            "reset" => {
+                self.cpu.reset();
+                self.cpu.symbols.clear();
+                self.end = self.cpu.pc.get();
+                self.say("reset").map_err(io)?;
+            }
+            "help" | "h" => self.say(HELP).map_err(io)?,
+            "quit" | "q" if args.is_empty() => return Ok(Some(0)),
+            "quit" | "q" => match args.parse() {
+                Ok(status) => return Ok(Some(status)),
+                Err(_) => return Err(format!("invalid exit status '{args}'")),
+            },
+            _ => return Err(format!("unknown command ':{name}', :help lists them")),
+        }
+        Ok(None)
+    }
+
+    fn say(&mut self, line: &str) -> Result<(), Error> {
+        writeln!(self.out, "{line}").map_err(Error::ScriptIo)
+    }
+
+    fn regs(&mut self) -> Result<(), Error> {
+        self.say(&format!("pc   {:#010x}", self.cpu.pc.get()))?;
+        for row in 0..8 {
+            let line: Vec<String> = (0..4)
+                .map(|column| {
+                    let reg = Reg::from_index(row * 4 + column);
+                    format!("{:<4} {:#010x}", reg.name(), self.cpu.regs.get(reg))
+                })
+                .collect();
+            self.say(line.join("  ").trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repl(input: &str) -> (u8, String) {
+        let mut cpu = Cpu::new(false);
+        cpu.set_quiet();
+        cpu.load(Vec::new());
+        let mut out = Vec::new();
+        let status = run(&mut cpu, &mut input.as_bytes(), &mut out, false).unwrap();
+        (status, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn lines_run_against_the_same_state() {
+        let input = "li a0, 3
+            li t0, 0x1000
+            loop: addi a1, a1, 2
+            addi a0, a0, -1
+            bnez a0, loop
+            sw a1, 4(t0)
+            bogus a0
+            :print a1 + 1
+            :x 0x1004 1
+            :quit 4
+            li a0, 9";
+        let (status, out) = repl(input);
+        assert_eq!(status, 4);
+        assert_eq!(
+            out,
+            "a0 = 3 (0x3)
+t0 = 4096 (0x1000)
+a1 = 2 (0x2)
+a0 = 2 (0x2)
+a0 = 0 (0x0)
+a1 = 6 (0x6)
+[0x1004] = 0x00000006
+unknown instruction 'bogus'
+a1 + 1 = 7 (0x7)
+0x00001004: 0x00000006
+"
+        );
+    }
+
+    #[test]
+    fn endless_loops_stop() {
+        let (_, out) = repl("spin: j spin\nli a0, 1\n:reset\n:print pc");
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(
+            lines[0],
+            "stopped at pc 0x0: still running after 1000000 instructions, the next line runs \
+             behind the code again"
+        );
+        assert_eq!(lines[1], "a0 = 1 (0x1)");
+        assert_eq!(lines[2..], ["reset", "pc = 0 (0x0)"]);
+    }
+}
@@ -0,0 +1,48 @@
+// Pseudo-random numbers that only depend on the seed, so that runs using them (random schedules,
+// the guest's entropy) can be replayed on any machine. splitmix64, accepts any seed including 0.
+#[derive(Clone)]
+pub struct Rng {
+    seed: u64,
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { seed, state: seed }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    pub fn fill(&mut self, bytes: &mut [u8]) {
+        for chunk in bytes.chunks_mut(8) {
+            let random = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&random[..chunk.len()]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fill_replays_seed() {
+        let mut first = [0; 13];
+        let mut second = [0; 13];
+        Rng::new(7).fill(&mut first);
+        Rng::new(7).fill(&mut second);
+        assert_eq!(first, second);
+        Rng::new(8).fill(&mut second);
+        assert_ne!(first, second);
+    }
+}
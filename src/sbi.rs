@@ -0,0 +1,349 @@
+use crate::cpu::*;
+use crate::csr::{Mode, MIP_STIP, MSTATUS_SIE};
+use crate::devices::stdin_reader;
+use crate::regs::Reg;
+
+use std::io::Write;
+use std::sync::mpsc::Receiver;
+
+// extension ids, the legacy extensions (0x00-0x0f) return their result only in a0
+const EXT_SET_TIMER: u32 = 0x00;
+const EXT_CONSOLE_PUTCHAR: u32 = 0x01;
+const EXT_CONSOLE_GETCHAR: u32 = 0x02;
+const EXT_SHUTDOWN: u32 = 0x08;
+const EXT_BASE: u32 = 0x10;
+const EXT_TIME: u32 = 0x5449_4d45;
+const EXT_HSM: u32 = 0x0048_534d;
+const EXT_SRST: u32 = 0x5352_5354;
+
+const SUPPORTED_EXTENSIONS: [u32; 8] = [
+    EXT_SET_TIMER,
+    EXT_CONSOLE_PUTCHAR,
+    EXT_CONSOLE_GETCHAR,
+    EXT_SHUTDOWN,
+    EXT_BASE,
+    EXT_TIME,
+    EXT_HSM,
+    EXT_SRST,
+];
+
+const SBI_SUCCESS: i32 = 0;
+const SBI_ERR_NOT_SUPPORTED: i32 = -2;
+const SBI_ERR_INVALID_PARAM: i32 = -3;
+const SBI_ERR_ALREADY_AVAILABLE: i32 = -6;
+
+// implemented spec version 0.2
+const SPEC_VERSION: u32 = 2;
+// not an officially assigned implementation id
+const IMPL_ID: u32 = 0x7275_7363;
+
+const HSM_STATE_STARTED: u32 = 0;
+const HSM_STATE_STOPPED: u32 = 1;
+
+// reset types of system_reset besides the shutdown
+const SRST_COLD_REBOOT: u32 = 1;
+const SRST_WARM_REBOOT: u32 = 2;
+const SRST_REASON_SYSTEM_FAILURE: u32 = 1;
+
+// Firmware state of the emulated SBI implementation.
+pub struct Sbi {
+    // lazily spawned reader, so that stdin is only touched by programs that actually read from it
+    stdin: Option<Receiver<u8>>,
+    // deadlines set via set_timer by each hart that didn't pass yet, in ticks of the timebase
+    timer_deadlines: Vec<Option<u64>>,
+}
+
+impl Sbi {
+    pub fn new() -> Self {
+        Sbi {
+            stdin: None,
+            timer_deadlines: Vec::new(),
+        }
+    }
+
+    // state for a forked machine, only the original reads stdin
+    pub fn fork(&self) -> Self {
+        Sbi {
+            stdin: None,
+            timer_deadlines: self.timer_deadlines.clone(),
+        }
+    }
+
+    pub fn timer_deadline(&self, hart: usize) -> Option<u64> {
+        self.timer_deadlines.get(hart).copied().flatten()
+    }
+
+    fn set_timer(&mut self, hart: usize, deadline: u64) {
+        if self.timer_deadlines.len() <= hart {
+            self.timer_deadlines.resize(hart + 1, None);
+        }
+        self.timer_deadlines[hart] = Some(deadline);
+    }
+
+    // Removes the hart's deadline once the time reached it, returns whether it did. The timer
+    // interrupt it raises stays pending until the next set_timer.
+    pub fn timer_due(&mut self, hart: usize, now: u64) -> bool {
+        let due = self
+            .timer_deadline(hart)
+            .is_some_and(|deadline| now >= deadline);
+        if due {
+            self.timer_deadlines[hart] = None;
+        }
+        due
+    }
+
+    // the earliest deadline of all harts
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.timer_deadlines.iter().flatten().copied().min()
+    }
+
+    pub fn clear_timers(&mut self) {
+        self.timer_deadlines.clear();
+    }
+
+    // returns -1 if no character is available like the legacy getchar does
+    fn getchar(&mut self) -> i32 {
+        let stdin = self.stdin.get_or_insert_with(stdin_reader);
+        stdin.try_recv().map_or(-1, |byte| byte as i32)
+    }
+}
+
+enum SbiResult {
+    // legacy extensions only return a single value in a0
+    Legacy(i32),
+    Ret(i32, u32),
+    Exit(u8),
+    Reset,
+}
+
+// Starts a stopped hart in supervisor mode at the address, with its hartid in a0 and the opaque
+// value in a1 as hart_start specifies.
+fn hart_start(cpu: &mut Cpu, hartid: u32, start: u32, opaque: u32) -> SbiResult {
+    let started = cpu.with_hart(hartid as usize, |cpu| {
+        if !cpu.parked() {
+            return false;
+        }
+        cpu.pc.set(start);
+        cpu.regs.set(Reg::A0, hartid);
+        cpu.regs.set(Reg::A1, opaque);
+        cpu.csrs.mode = Mode::Supervisor;
+        cpu.csrs.satp = 0;
+        cpu.csrs.mstatus &= !MSTATUS_SIE;
+        cpu.set_parked(false);
+        true
+    });
+    match started {
+        Some(true) => SbiResult::Ret(SBI_SUCCESS, 0),
+        Some(false) => SbiResult::Ret(SBI_ERR_ALREADY_AVAILABLE, 0),
+        None => SbiResult::Ret(SBI_ERR_INVALID_PARAM, 0),
+    }
+}
+
+// Handles an ecall made by a supervisor-mode kernel, the extension id is passed in a7, the function
+// id in a6 and arguments in a0-a5. Returns the exit-code if the call shut down the machine.
+pub fn handle_ecall(cpu: &mut Cpu) -> Option<u8> {
+    let eid = cpu.regs.get(Reg::A7);
+    let fid = cpu.regs.get(Reg::A6);
+    let args: Vec<u32> = (10..16).map(|reg| cpu.regs.read(reg)).collect();
+    let hart = cpu.hart();
+    let sbi = cpu
+        .sbi
+        .as_mut()
+        .expect("sbi ecall requires the sbi environment");
+
+    let result = match (eid, fid) {
+        (EXT_SET_TIMER, _) | (EXT_TIME, 0) => {
+            sbi.set_timer(hart, ((args[1] as u64) << 32) | args[0] as u64);
+            cpu.csrs.mip &= !MIP_STIP;
+            if eid == EXT_SET_TIMER {
+                SbiResult::Legacy(0)
+            } else {
+                SbiResult::Ret(SBI_SUCCESS, 0)
+            }
+        }
+        (EXT_CONSOLE_PUTCHAR, _) if cpu.console.is_some() => {
+            let _ = cpu.console.as_mut().unwrap().write_all(&[args[0] as u8]);
+            SbiResult::Legacy(0)
+        }
+        (EXT_CONSOLE_PUTCHAR, _) => {
+            let mut stdout = std::io::stdout();
+            // a guest can't do anything about a closed stdout, so just drop the character
+            let _ = stdout
+                .write_all(&[args[0] as u8])
+                .and_then(|_| stdout.flush());
+            SbiResult::Legacy(0)
+        }
+        (EXT_CONSOLE_GETCHAR, _) => SbiResult::Legacy(sbi.getchar()),
+        (EXT_SHUTDOWN, _) => SbiResult::Exit(0),
+        (EXT_BASE, 0) => SbiResult::Ret(SBI_SUCCESS, SPEC_VERSION),
+        (EXT_BASE, 1) => SbiResult::Ret(SBI_SUCCESS, IMPL_ID),
+        (EXT_BASE, 2) => SbiResult::Ret(SBI_SUCCESS, 1),
+        (EXT_BASE, 3) => {
+            let available = SUPPORTED_EXTENSIONS.contains(&args[0]);
+            SbiResult::Ret(SBI_SUCCESS, available as u32)
+        }
+        // mvendorid, marchid and mimpid are all zero
+        (EXT_BASE, 4..=6) => SbiResult::Ret(SBI_SUCCESS, 0),
+        (EXT_HSM, 0) => hart_start(cpu, args[0], args[1], args[2]),
+        // the stopped hart doesn't return until it is started again
+        (EXT_HSM, 1) => {
+            cpu.set_parked(true);
+            SbiResult::Ret(SBI_SUCCESS, 0)
+        }
+        (EXT_HSM, 2) => match cpu.with_hart(args[0] as usize, |cpu| cpu.parked()) {
+            Some(true) => SbiResult::Ret(SBI_SUCCESS, HSM_STATE_STOPPED),
+            Some(false) => SbiResult::Ret(SBI_SUCCESS, HSM_STATE_STARTED),
+            None => SbiResult::Ret(SBI_ERR_INVALID_PARAM, 0),
+        },
+        (EXT_SRST, 0) if matches!(args[0], SRST_COLD_REBOOT | SRST_WARM_REBOOT) => SbiResult::Reset,
+        (EXT_SRST, 0) => {
+            let reason = args[1];
+            SbiResult::Exit((reason == SRST_REASON_SYSTEM_FAILURE) as u8)
+        }
+        _ => SbiResult::Ret(SBI_ERR_NOT_SUPPORTED, 0),
+    };
+
+    match result {
+        SbiResult::Legacy(value) => cpu.regs.set(Reg::A0, value as u32),
+        SbiResult::Ret(error, value) => {
+            cpu.regs.set(Reg::A0, error as u32);
+            cpu.regs.set(Reg::A1, value);
+        }
+        SbiResult::Exit(code) => return Some(code),
+        SbiResult::Reset => cpu.request_reset(),
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sbi_cpu() -> Cpu {
+        let mut cpu = Cpu::new(false);
+        cpu.enable_sbi();
+        cpu
+    }
+
+    fn call(cpu: &mut Cpu, eid: u32, fid: u32, args: &[u32]) -> Option<u8> {
+        cpu.regs.write(17, eid);
+        cpu.regs.write(16, fid);
+        for (i, arg) in args.iter().enumerate() {
+            cpu.regs.write(10 + i, *arg);
+        }
+        handle_ecall(cpu)
+    }
+
+    #[test]
+    fn probe_extension() {
+        let mut cpu = sbi_cpu();
+        assert_eq!(call(&mut cpu, EXT_BASE, 3, &[EXT_HSM]), None);
+        assert_eq!(cpu.regs.read(10), 0);
+        assert_eq!(cpu.regs.read(11), 1);
+
+        call(&mut cpu, EXT_BASE, 3, &[0x4442_434e]);
+        assert_eq!(cpu.regs.read(11), 0);
+    }
+
+    #[test]
+    fn set_timer() {
+        let mut cpu = sbi_cpu();
+        call(&mut cpu, EXT_TIME, 0, &[0x10, 0x1]);
+        assert_eq!(
+            cpu.sbi.as_ref().unwrap().timer_deadline(0),
+            Some(0x1_0000_0010)
+        );
+        assert_eq!(cpu.regs.read(10), SBI_SUCCESS as u32);
+    }
+
+    #[test]
+    fn timer_tick() {
+        // the kernel asks for a timer interrupt 1000 ticks from now and waits for it in wfi
+        let source = "
+            la t0, trap
+            csrw stvec, t0
+            li t0, 0x20
+            csrw sie, t0
+            csrsi sstatus, 2
+            rdtime a0
+            addi a0, a0, 1000
+            li a1, 0
+            li a6, 0
+            li a7, 0x54494d45
+            ecall
+        1:  wfi
+            j 1b
+        trap:
+            csrr s1, scause
+            li a0, 0
+            li a1, 0
+            li a6, 0
+            li a7, 0x53525354
+            ecall
+        ";
+        let mut cpu = sbi_cpu();
+        cpu.set_cycle_limit(10_000);
+        let program = crate::asm::assemble(source, 0).unwrap().bytes;
+
+        assert_eq!(cpu.run(program).ok(), Some(StopReason::Exit(0)));
+        assert_eq!(cpu.csrs.mode, Mode::Supervisor);
+        assert_eq!(cpu.regs.get(Reg::S1), crate::trap::INTERRUPT | 5);
+        // wfi skipped right to the deadline
+        assert!(
+            (1000..1100).contains(&cpu.clock.now()),
+            "{}",
+            cpu.clock.now()
+        );
+        assert_eq!(cpu.sbi.as_ref().unwrap().timer_deadline(0), None);
+    }
+
+    #[test]
+    fn hart_start() {
+        let mut cpu = sbi_cpu();
+        cpu.set_harts(2);
+        cpu.load(vec![0; 4]);
+        // only hart 0 starts, the others wait in the stopped state
+        call(&mut cpu, EXT_HSM, 2, &[1]);
+        assert_eq!(cpu.regs.read(11), HSM_STATE_STOPPED);
+        call(&mut cpu, EXT_HSM, 0, &[1, 0x1000, 0x55]);
+        assert_eq!(cpu.regs.read(10) as i32, SBI_SUCCESS);
+        call(&mut cpu, EXT_HSM, 2, &[1]);
+        assert_eq!(cpu.regs.read(11), HSM_STATE_STARTED);
+        call(&mut cpu, EXT_HSM, 0, &[1, 0x1000, 0]);
+        assert_eq!(cpu.regs.read(10) as i32, SBI_ERR_ALREADY_AVAILABLE);
+        call(&mut cpu, EXT_HSM, 0, &[0, 0x1000, 0]);
+        assert_eq!(cpu.regs.read(10) as i32, SBI_ERR_ALREADY_AVAILABLE);
+        call(&mut cpu, EXT_HSM, 0, &[2, 0x1000, 0]);
+        assert_eq!(cpu.regs.read(10) as i32, SBI_ERR_INVALID_PARAM);
+
+        assert!(cpu.select_hart(1));
+        assert_eq!(cpu.pc.get(), 0x1000);
+        assert_eq!(cpu.regs.read(10), 1);
+        assert_eq!(cpu.regs.read(11), 0x55);
+        assert_eq!(cpu.csrs.mode, Mode::Supervisor);
+        // hart_stop parks the calling hart again
+        call(&mut cpu, EXT_HSM, 1, &[]);
+        assert!(cpu.select_hart(0));
+        call(&mut cpu, EXT_HSM, 2, &[1]);
+        assert_eq!(cpu.regs.read(11), HSM_STATE_STOPPED);
+    }
+
+    #[test]
+    fn unknown_extension() {
+        let mut cpu = sbi_cpu();
+        assert_eq!(call(&mut cpu, 0x0a00_0000, 0, &[]), None);
+        assert_eq!(cpu.regs.read(10) as i32, SBI_ERR_NOT_SUPPORTED);
+    }
+
+    #[test]
+    fn shutdown() {
+        let mut cpu = sbi_cpu();
+        assert_eq!(call(&mut cpu, EXT_SRST, 0, &[0, 0]), Some(0));
+        assert_eq!(
+            call(&mut cpu, EXT_SRST, 0, &[0, SRST_REASON_SYSTEM_FAILURE]),
+            Some(1)
+        );
+        assert_eq!(call(&mut cpu, EXT_SHUTDOWN, 0, &[]), Some(0));
+        assert_eq!(call(&mut cpu, EXT_SRST, 0, &[SRST_WARM_REBOOT, 0]), None);
+    }
+}
@@ -0,0 +1,81 @@
+use crate::rng::Rng;
+
+// Decides which hart executes the next instruction. Round-robin switches to the next hart after
+// every `quantum` instructions. The random scheduler runs a random hart for a random number of
+// up to `quantum` instructions, the choices only depend on the seed, so a run that exposed a
+// concurrency bug can be replayed by passing the same seed again.
+#[derive(Clone)]
+pub struct Scheduler {
+    quantum: u64,
+    // instructions left until the next scheduling decision
+    remaining: u64,
+    rng: Option<Rng>,
+}
+
+impl Scheduler {
+    pub fn round_robin(quantum: u64) -> Self {
+        Scheduler {
+            quantum: quantum.max(1),
+            remaining: quantum.max(1),
+            rng: None,
+        }
+    }
+
+    pub fn random(seed: u64, quantum: u64) -> Self {
+        Scheduler {
+            rng: Some(Rng::new(seed)),
+            ..Scheduler::round_robin(quantum)
+        }
+    }
+
+    pub fn seed(&self) -> Option<u64> {
+        self.rng.as_ref().map(Rng::seed)
+    }
+
+    // returns the hart that executes the next instruction
+    pub fn next(&mut self, current: usize, harts: usize) -> usize {
+        self.remaining -= 1;
+        if self.remaining > 0 {
+            return current;
+        }
+        match self.rng.as_mut() {
+            Some(rng) => {
+                self.remaining = 1 + rng.next_u64() % self.quantum;
+                (rng.next_u64() % harts as u64) as usize
+            }
+            None => {
+                self.remaining = self.quantum;
+                (current + 1) % harts
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(scheduler: &mut Scheduler, harts: usize, cycles: usize) -> Vec<usize> {
+        let mut hart = 0;
+        (0..cycles)
+            .map(|_| {
+                hart = scheduler.next(hart, harts);
+                hart
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_robin_quantum() {
+        let mut scheduler = Scheduler::round_robin(2);
+        assert_eq!(schedule(&mut scheduler, 3, 7), [0, 1, 1, 2, 2, 0, 0]);
+    }
+
+    #[test]
+    fn random_replays_seed() {
+        let first = schedule(&mut Scheduler::random(42, 8), 4, 1000);
+        assert_eq!(first, schedule(&mut Scheduler::random(42, 8), 4, 1000));
+        assert_ne!(first, schedule(&mut Scheduler::random(43, 8), 4, 1000));
+        assert!((0..4).all(|hart| first.contains(&hart)));
+    }
+}
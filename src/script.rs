@@ -0,0 +1,418 @@
+// Debugger sessions read from a file for `--debug-script`, one command per line, `#` starts a
+// comment:
+//   break <loc> [if <expr>] [hit <n>]   adds a breakpoint, see `BreakSpec`
+//   break-if <expr>                     stops once the expression becomes nonzero
+//   watch <expr>                        prints the expression whenever its value changes
+//   run | continue                      runs until the next stop
+//   step [<n>]                          executes n instructions (default: 1)
+//   print <expr> | x[/<count>] <expr>   shows a value or the words at an address
+//   regs                                shows the pc and the general registers
+//   assert <expr>                       fails the session with status 1 if the expression is 0
+//   gpio [<pin> <0|1>]                  drives the gpio input and shows the pins
+//   reset                               resets the machine, the program starts over
+//   quit [<status>]                     ends the session with the status
+// A session that doesn't quit explicitly ends with the program's exit code, or 0 if the program
+// didn't exit. `ruscv debug` reads the same commands interactively.
+use std::io::{BufRead, Write};
+
+use crate::cpu::{Cpu, StopReason};
+use crate::devices;
+use crate::error::Error;
+use crate::expr::{self, Expr};
+use crate::regs::Reg;
+use crate::watch::{BreakSpec, DebugStop};
+
+#[derive(Debug)]
+enum Command {
+    Break(BreakSpec),
+    BreakIf(Expr),
+    Watch(Expr),
+    Run,
+    Step(u64),
+    Print(Expr),
+    Examine(Expr, usize),
+    Regs,
+    Assert(Expr),
+    Gpio(Option<(u32, bool)>),
+    Reset,
+    Quit(Option<u8>),
+}
+
+impl Command {
+    fn parse(line: &str) -> Result<Option<Command>, String> {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            return Ok(None);
+        }
+        let (name, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let args = args.trim();
+        let command = match name {
+            "break" | "b" => Command::Break(BreakSpec::parse(args)?),
+            "break-if" => Command::BreakIf(Expr::parse(args)?),
+            "watch" => Command::Watch(Expr::parse(args)?),
+            "run" | "continue" | "c" if args.is_empty() => Command::Run,
+            "step" | "s" if args.is_empty() => Command::Step(1),
+            "step" | "s" => match args.parse() {
+                Ok(count) => Command::Step(count),
+                Err(_) => return Err(format!("invalid step count '{args}'")),
+            },
+            "print" | "p" => Command::Print(Expr::parse(args)?),
+            "x" => Command::Examine(Expr::parse(args)?, 1),
+            name if name.starts_with("x/") => match name[2..].parse() {
+                Ok(count) => Command::Examine(Expr::parse(args)?, count),
+                Err(_) => return Err(format!("invalid count '{}'", &name[2..])),
+            },
+            "regs" if args.is_empty() => Command::Regs,
+            "assert" => Command::Assert(Expr::parse(args)?),
+            "gpio" => Command::Gpio(devices::parse_gpio_input(args)?),
+            "reset" if args.is_empty() => Command::Reset,
+            "quit" | "q" if args.is_empty() => Command::Quit(None),
+            "quit" | "q" => match args.parse() {
+                Ok(status) => Command::Quit(Some(status)),
+                Err(_) => return Err(format!("invalid exit status '{args}'")),
+            },
+            _ => return Err(format!("unknown command '{line}'")),
+        };
+        Ok(Some(command))
+    }
+}
+
+pub struct Script {
+    // commands with their line numbers
+    commands: Vec<(usize, Command)>,
+}
+
+impl Script {
+    // checks the whole script before anything runs, so that typos don't cut a long session short
+    pub fn parse(text: &str) -> Result<Script, Error> {
+        let mut commands = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            match Command::parse(line) {
+                Ok(Some(command)) => commands.push((index + 1, command)),
+                Ok(None) => (),
+                Err(e) => return Err(Error::DebugScript(index + 1, e)),
+            }
+        }
+        Ok(Script { commands })
+    }
+
+    // Runs the session on the loaded program and returns its exit status. The run is finished,
+    // calling the exit hooks, once the program stopped for good or the session ends.
+    pub fn run(&self, cpu: &mut Cpu, out: &mut dyn Write) -> Result<u8, Error> {
+        let mut session = Session {
+            cpu,
+            out,
+            stopped: None,
+        };
+        let status = session.execute(&self.commands);
+        session.end(status)
+    }
+}
+
+// Reads commands from the input until `quit` or its end, showing a prompt if asked to. Unlike in
+// scripts, mistakes and failed asserts are reported without ending the session.
+pub fn interactive(
+    cpu: &mut Cpu,
+    input: &mut dyn BufRead,
+    out: &mut dyn Write,
+    prompt: bool,
+) -> Result<u8, Error> {
+    let mut session = Session {
+        cpu,
+        out,
+        stopped: None,
+    };
+    let mut line = 0;
+    let status = loop {
+        if prompt {
+            write!(session.out, "(ruscv) ")
+                .and_then(|_| session.out.flush())
+                .map_err(Error::ScriptIo)?;
+        }
+        let mut text = String::new();
+        if input.read_line(&mut text).map_err(Error::ScriptIo)? == 0 {
+            break Ok(session.exit_code());
+        }
+        line += 1;
+        let command = match Command::parse(&text) {
+            Ok(Some(command)) => command,
+            Ok(None) => continue,
+            Err(e) => {
+                session.say(format!("{:?}", Error::DebugScript(line, e)))?;
+                continue;
+            }
+        };
+        match session.execute_one(line, &command) {
+            Ok(Some(status)) if matches!(command, Command::Quit(_)) => break Ok(status),
+            Ok(_) => (),
+            Err(e) => session.say(format!("{e:?}"))?,
+        }
+    };
+    session.end(status)
+}
+
+struct Session<'a> {
+    cpu: &'a mut Cpu,
+    out: &'a mut dyn Write,
+    // set once the program stopped for another reason than a debug stop
+    stopped: Option<StopReason>,
+}
+
+impl Session<'_> {
+    // runs the commands until one ends the session, returns the exit status
+    fn execute(&mut self, commands: &[(usize, Command)]) -> Result<u8, Error> {
+        for (line, command) in commands {
+            if let Some(status) = self.execute_one(*line, command)? {
+                return Ok(status);
+            }
+        }
+        Ok(self.exit_code())
+    }
+
+    // the exit status if the command ends the session
+    fn execute_one(&mut self, line: usize, command: &Command) -> Result<Option<u8>, Error> {
+        let error = |message: String| Error::DebugScript(line, message);
+        match command {
+            Command::Break(spec) => {
+                let address = spec.resolve(self.cpu).map_err(error)?;
+                self.cpu.watchpoints.add_break_spec(address, spec);
+            }
+            Command::BreakIf(condition) => self.cpu.watchpoints.add_condition(condition.clone()),
+            Command::Watch(expr) => self.cpu.add_watch_expr(expr.clone()),
+            Command::Run | Command::Step(_) if self.stopped.is_some() => {
+                return Err(error("the program already stopped".to_string()));
+            }
+            Command::Run => {
+                let reason = self.cpu.run_until_stop()?;
+                self.stop(reason)?;
+            }
+            Command::Step(count) => {
+                for _ in 0..*count {
+                    if let Some(reason) = self.cpu.step()? {
+                        self.stop(reason)?;
+                        break;
+                    }
+                }
+            }
+            Command::Print(expr) => {
+                let value = expr.eval(self.cpu).map_err(error)?;
+                self.say(format!("{expr} = {}", expr::format_value(value)))?;
+            }
+            Command::Examine(expr, count) => {
+                let address = expr.eval(self.cpu).map_err(error)? as u32;
+                let dump = expr::examine(self.cpu, address, *count);
+                self.out
+                    .write_all(dump.as_bytes())
+                    .map_err(Error::ScriptIo)?;
+            }
+            Command::Regs => self.regs()?,
+            Command::Assert(expr) => {
+                let value = expr.eval(self.cpu).map_err(error)?;
+                if value == 0 {
+                    self.say(format!("line {line}: assertion '{expr}' failed"))?;
+                    return Ok(Some(1));
+                }
+            }
+            Command::Gpio(input) => {
+                let pins = self.cpu.gpio().ok_or(error("no gpio block".to_string()))?;
+                let state = pins.command(*input);
+                self.out
+                    .write_all(state.as_bytes())
+                    .map_err(Error::ScriptIo)?;
+            }
+            // a program that exited runs again after the reset
+            Command::Reset => {
+                self.cpu.reset();
+                self.stopped = None;
+                self.say("reset".to_string())?;
+            }
+            Command::Quit(status) => return Ok(Some(status.unwrap_or(self.exit_code()))),
+        }
+        Ok(None)
+    }
+
+    // finishes the run unless the program already stopped for good
+    fn end(self, status: Result<u8, Error>) -> Result<u8, Error> {
+        if self.stopped.is_none() {
+            self.cpu.finish(Ok(StopReason::HostRequest))?;
+        }
+        status
+    }
+
+    fn exit_code(&self) -> u8 {
+        match self.stopped {
+            Some(StopReason::Exit(code)) => code,
+            _ => 0,
+        }
+    }
+
+    // reports why the program stopped, a program that won't continue is finished
+    fn stop(&mut self, reason: StopReason) -> Result<(), Error> {
+        let pc = self.cpu.pc.get();
+        match reason {
+            StopReason::Debug(DebugStop::Breakpoint(pc)) => {
+                self.say(format!("breakpoint at pc {pc:#x}"))?
+            }
+            StopReason::Debug(DebugStop::Watchpoint(kind, address)) => self.say(format!(
+                "{kind:?} watchpoint at {address:#x} hit at pc {pc:#x}"
+            ))?,
+            StopReason::Debug(DebugStop::Condition(index)) => {
+                let condition = self.cpu.watchpoints.condition(index).unwrap();
+                self.say(format!("'{condition}' became true at pc {pc:#x}"))?
+            }
+            StopReason::Exit(code) => self.say(format!("program exited with code {code}"))?,
+            reason => self.say(format!("program stopped at pc {pc:#x}: {reason:?}"))?,
+        }
+        if !matches!(reason, StopReason::Debug(_)) {
+            self.stopped = Some(self.cpu.finish(Ok(reason))?);
+        }
+        Ok(())
+    }
+
+    fn say(&mut self, line: String) -> Result<(), Error> {
+        writeln!(self.out, "{line}").map_err(Error::ScriptIo)
+    }
+
+    fn regs(&mut self) -> Result<(), Error> {
+        self.say(format!("pc   {:#010x}", self.cpu.pc.get()))?;
+        for row in 0..8 {
+            let line: Vec<String> = (0..4)
+                .map(|column| {
+                    let reg = Reg::from_index(row * 4 + column);
+                    format!("{:<4} {:#010x}", reg.name(), self.cpu.regs.get(reg))
+                })
+                .collect();
+            self.say(line.join("  ").trim_end().to_string())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+
+    fn loaded(source: &str) -> Cpu {
+        let assembly = asm::assemble(source, 0).unwrap();
+        let mut cpu = Cpu::new(false);
+        cpu.set_quiet();
+        cpu.load(assembly.bytes);
+        cpu.symbols = assembly.symbols;
+        cpu
+    }
+
+    fn session(source: &str, script: &str) -> (Result<u8, Error>, String) {
+        let mut cpu = loaded(source);
+        let mut out = Vec::new();
+        let status = Script::parse(script).and_then(|script| script.run(&mut cpu, &mut out));
+        (status, String::from_utf8(out).unwrap())
+    }
+
+    const LOOP: &str = "
+        li a0, 0
+    loop:
+        addi a0, a0, 1
+        li t0, 10
+        blt a0, t0, loop
+        li a7, 93
+        ecall
+    ";
+
+    #[test]
+    fn breakpoints_and_asserts() {
+        let script = "
+            # stop in the fifth iteration
+            break loop if a0 == 4
+            run
+            assert a0 == 4
+            print a0 * 2
+            step 2
+            print t0
+            run
+        ";
+        let (status, out) = session(LOOP, script);
+        assert_eq!(status.unwrap(), 10);
+        assert_eq!(
+            out,
+            "breakpoint at pc 0x4\na0 * 2 = 8 (0x8)\nt0 = 10 (0xa)\nprogram exited with code 10\n"
+        );
+
+        let (status, out) = session(LOOP, "break loop hit 3\nrun\nassert a0 == 2\nquit 7");
+        assert_eq!(status.unwrap(), 7);
+        assert_eq!(out, "breakpoint at pc 0x4\n");
+
+        let (status, out) = session(LOOP, "run\nassert a0 == 9");
+        assert_eq!(status.unwrap(), 1);
+        assert!(out.ends_with("line 2: assertion 'a0 == 9' failed\n"));
+    }
+
+    #[test]
+    fn gpio_button() {
+        // waits for the button on pin 4, then lights the led on pin 0
+        let source = "
+            li t0, 0x10005000
+            li a0, 1
+            sw a0, 8(t0)
+        wait:
+            lw a1, 0(t0)
+            andi a1, a1, 16
+            beqz a1, wait
+            sw a0, 4(t0)
+            ebreak
+        ";
+        let mut cpu = loaded(source);
+        let pins = cpu.enable_gpio(None).unwrap();
+        let mut out = Vec::new();
+        let script = Script::parse("step 50\ngpio 4 1\nrun\ngpio").unwrap();
+        script.run(&mut cpu, &mut out).unwrap();
+        assert_eq!(pins.outputs(), 1);
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .ends_with("gpio outputs 0x00000001 inputs 0x00000010\n"));
+
+        let (status, _) = session(LOOP, "gpio");
+        assert!(matches!(status, Err(Error::DebugScript(1, _))));
+        assert!(Script::parse("gpio 4 on").is_err());
+    }
+
+    #[test]
+    fn reset_restarts_the_program() {
+        let script = "run\nreset\nprint pc\nprint a0\nbreak loop hit 3\nrun\nprint a0";
+        let (status, out) = session(LOOP, script);
+        assert_eq!(status.unwrap(), 0);
+        assert_eq!(
+            out,
+            "program exited with code 10\nreset\npc = 0 (0x0)\na0 = 0 (0x0)\n\
+             breakpoint at pc 0x4\na0 = 2 (0x2)\n"
+        );
+    }
+
+    #[test]
+    fn script_errors() {
+        let (status, _) = session(LOOP, "run\nbogus");
+        assert!(matches!(status, Err(Error::DebugScript(2, _))));
+        let (status, _) = session(LOOP, "run\nrun");
+        assert!(matches!(status, Err(Error::DebugScript(2, _))));
+        let (status, _) = session(LOOP, "break nowhere");
+        assert!(matches!(status, Err(Error::DebugScript(1, _))));
+        let (status, _) = session(LOOP, "step x");
+        assert!(matches!(status, Err(Error::DebugScript(1, _))));
+    }
+
+    #[test]
+    fn interactive_session() {
+        let mut cpu = loaded(LOOP);
+        let mut input = "bogus\nstep 2\nassert a0 == 5\nprint a0\nquit 3\nprint a0\n".as_bytes();
+        let mut out = Vec::new();
+        let status = interactive(&mut cpu, &mut input, &mut out, false);
+        assert_eq!(status.unwrap(), 3);
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(
+            out,
+            "debug script error on line 1: unknown command 'bogus'\n\
+             line 3: assertion 'a0 == 5' failed\n\
+             a0 = 1 (0x1)\n"
+        );
+    }
+}
@@ -0,0 +1,180 @@
+// Stack usage analysis for `--stack-usage`, to size the stacks of embedded programs. The lowest
+// sp of each hart is tracked, and a shadow call stack follows the calls and returns marked as such
+// by the calling convention (jal/jalr linking to ra or t0, jalr x0 through ra or t0), so that the
+// deepest stack below each function's entry sp, including its callees, can be reported.
+use crate::inst::{IInst, Inst};
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+// functions listed in the report, the deepest first
+const SHOWN_FUNCTIONS: usize = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Link {
+    Call,
+    Return,
+}
+
+// whether the instruction is a call or a return by the calling convention's link registers
+pub fn link(inst: &Inst) -> Option<Link> {
+    let is_link = |reg| reg == 1 || reg == 5;
+    match inst {
+        Inst::J(format) if is_link(format.rd) => Some(Link::Call),
+        Inst::I(IInst::Jalr, format) if is_link(format.rd) => Some(Link::Call),
+        Inst::I(IInst::Jalr, format) if format.rd == 0 && is_link(format.rs1) => Some(Link::Return),
+        _ => None,
+    }
+}
+
+// an active call: the function's address, sp at its entry and the lowest sp since
+#[derive(Clone)]
+struct Frame {
+    function: u32,
+    entry_sp: u32,
+    min_sp: u32,
+}
+
+#[derive(Clone)]
+pub struct StackUsage {
+    initial_sp: u32,
+    min_sp: u32,
+    frames: Vec<Frame>,
+    // deepest stack of each function that returned, in bytes below its entry sp
+    deepest: HashMap<u32, u32>,
+}
+
+impl StackUsage {
+    pub fn new(sp: u32) -> Self {
+        StackUsage {
+            initial_sp: sp,
+            min_sp: sp,
+            frames: Vec::new(),
+            deepest: HashMap::new(),
+        }
+    }
+
+    // called after every instruction with the current sp
+    pub fn update(&mut self, sp: u32) {
+        self.min_sp = self.min_sp.min(sp);
+        if let Some(frame) = self.frames.last_mut() {
+            frame.min_sp = frame.min_sp.min(sp);
+        }
+    }
+
+    pub fn call(&mut self, function: u32, sp: u32) {
+        self.frames.push(Frame {
+            function,
+            entry_sp: sp,
+            min_sp: sp,
+        });
+    }
+
+    // the callee's lowest sp is the caller's as well, returns without a call are ignored
+    pub fn ret(&mut self) {
+        let Some(frame) = self.frames.pop() else {
+            return;
+        };
+        let depth = frame.entry_sp.saturating_sub(frame.min_sp);
+        let deepest = self.deepest.entry(frame.function).or_default();
+        *deepest = (*deepest).max(depth);
+        if let Some(caller) = self.frames.last_mut() {
+            caller.min_sp = caller.min_sp.min(frame.min_sp);
+        }
+    }
+
+    // bytes between the sp at the start and the lowest sp reached
+    pub fn max_usage(&self) -> u32 {
+        self.initial_sp.saturating_sub(self.min_sp)
+    }
+
+    // The deepest stack of each function, the deepest first. Functions still running are
+    // counted as if they returned now.
+    pub fn functions(&self) -> Vec<(u32, u32)> {
+        let mut usage = self.clone();
+        while !usage.frames.is_empty() {
+            usage.ret();
+        }
+        let mut functions: Vec<(u32, u32)> = usage.deepest.into_iter().collect();
+        functions.sort_by_key(|&(function, depth)| (std::cmp::Reverse(depth), function));
+        functions
+    }
+
+    // the usage and the deepest functions, named by the symbols where known
+    pub fn report(&self, hart: usize, symbols: &HashMap<String, u32>) -> String {
+        let mut out = format!(
+            "max stack usage of hart {hart}: {} bytes (sp {:#x} down to {:#x})\n",
+            self.max_usage(),
+            self.initial_sp,
+            self.min_sp
+        );
+        let functions = self.functions();
+        if functions.is_empty() {
+            return out;
+        }
+        let names: HashMap<u32, &str> = symbols
+            .iter()
+            .map(|(name, &address)| (address, name.as_str()))
+            .collect();
+        out.push_str("deepest stack below the entry of each function, including callees:\n");
+        for &(function, depth) in functions.iter().take(SHOWN_FUNCTIONS) {
+            let name = match names.get(&function) {
+                Some(name) => name.to_string(),
+                None => format!("{function:#x}"),
+            };
+            let _ = writeln!(out, "{depth:>10}  {name}");
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_calls() {
+        let mut usage = StackUsage::new(0x1000);
+        // main pushes 16 bytes and calls f twice, f pushes 32 and calls g which pushes 64
+        usage.call(0x100, 0x1000);
+        usage.update(0xff0);
+        for _ in 0..2 {
+            usage.call(0x200, 0xff0);
+            usage.update(0xfd0);
+            usage.call(0x300, 0xfd0);
+            usage.update(0xf90);
+            usage.update(0xfd0);
+            usage.ret();
+            usage.update(0xff0);
+            usage.ret();
+        }
+        usage.ret();
+        usage.ret();
+        assert_eq!(usage.max_usage(), 0x70);
+        assert_eq!(
+            usage.functions(),
+            [(0x100, 0x70), (0x200, 0x60), (0x300, 0x40)]
+        );
+
+        let symbols = HashMap::from([("main".to_string(), 0x100), ("g".to_string(), 0x300)]);
+        assert_eq!(
+            usage.report(0, &symbols),
+            "max stack usage of hart 0: 112 bytes (sp 0x1000 down to 0xf90)
+deepest stack below the entry of each function, including callees:
+       112  main
+        96  0x200
+        64  g
+"
+        );
+    }
+
+    #[test]
+    fn running_functions() {
+        let mut usage = StackUsage::new(0x1000);
+        usage.call(0x100, 0x1000);
+        usage.update(0xff8);
+        usage.call(0x200, 0xff8);
+        usage.update(0xfe8);
+        assert_eq!(usage.functions(), [(0x100, 0x18), (0x200, 0x10)]);
+    }
+}
@@ -0,0 +1,177 @@
+// Snapshots of the architectural state of the running hart for tests: the general registers, the
+// csrs and ram. Comparing the state after running a program with the one before shows every side
+// effect, and `assert_only` fails on any change outside of an explicit set:
+//     let before = ArchState::capture(&cpu);
+//     cpu.step()?;
+//     ArchState::capture(&cpu).assert_only(&before, &Allowed::new().reg(Reg::A2));
+// The pc and the counters change with every instruction and aren't compared.
+use std::fmt;
+use std::ops::Range;
+
+use crate::cpu::Cpu;
+use crate::csr::*;
+use crate::regs::Reg;
+
+// csrs that count cycles, time or instructions
+const COUNTERS: &[u16] = &[
+    MCYCLE, MINSTRET, MCYCLEH, MINSTRETH, CYCLE, TIME, INSTRET, CYCLEH, TIMEH, INSTRETH,
+];
+
+// a difference between two snapshots, with the old and the new value
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Change {
+    Reg(Reg, u32, u32),
+    Csr(u16, u32, u32),
+    Mem(u32, u8, u8),
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Reg(reg, old, new) => write!(f, "{}: {old:#x} -> {new:#x}", reg.name()),
+            Change::Csr(csr, old, new) => write!(f, "csr {csr:#x}: {old:#x} -> {new:#x}"),
+            Change::Mem(address, old, new) => write!(f, "mem {address:#x}: {old:#x} -> {new:#x}"),
+        }
+    }
+}
+
+pub struct ArchState {
+    regs: [u32; 32],
+    // the value of every implemented csr
+    csrs: Vec<(u16, u32)>,
+    ram_base: u32,
+    ram: Vec<u8>,
+}
+
+impl ArchState {
+    pub fn capture(cpu: &Cpu) -> Self {
+        let ram_base = cpu.mem.ram_base();
+        ArchState {
+            regs: std::array::from_fn(|n| cpu.regs.read(n)),
+            csrs: (0..4096)
+                .filter(|csr| !COUNTERS.contains(csr))
+                .filter_map(|csr| Some((csr, cpu.read_csr(csr)?)))
+                .collect(),
+            ram_base,
+            ram: cpu.mem.peek(ram_base, cpu.mem.ram_size()).into_owned(),
+        }
+    }
+
+    // everything that differs from the earlier snapshot, registers first, then csrs and memory
+    pub fn changes_since(&self, before: &ArchState) -> Vec<Change> {
+        let regs = (0..32)
+            .filter(|&n| self.regs[n] != before.regs[n])
+            .map(|n| Change::Reg(Reg::from_index(n), before.regs[n], self.regs[n]));
+        let csrs = before
+            .csrs
+            .iter()
+            .zip(&self.csrs)
+            .filter(|((_, old), (_, new))| old != new)
+            .map(|(&(csr, old), &(_, new))| Change::Csr(csr, old, new));
+        let mem = before
+            .ram
+            .iter()
+            .zip(&self.ram)
+            .enumerate()
+            .filter(|(_, (old, new))| old != new)
+            .map(|(offset, (&old, &new))| Change::Mem(self.ram_base + offset as u32, old, new));
+        regs.chain(csrs).chain(mem).collect()
+    }
+
+    // panics listing every change since the earlier snapshot that isn't allowed
+    pub fn assert_only(&self, before: &ArchState, allowed: &Allowed) {
+        let unexpected: Vec<String> = self
+            .changes_since(before)
+            .into_iter()
+            .filter(|change| !allowed.permits(change))
+            .map(|change| change.to_string())
+            .collect();
+        assert!(
+            unexpected.is_empty(),
+            "unexpected state changes:\n  {}",
+            unexpected.join("\n  ")
+        );
+    }
+}
+
+// the registers, csrs and memory ranges a program may change
+pub struct Allowed {
+    regs: Vec<Reg>,
+    csrs: Vec<u16>,
+    memory: Vec<Range<u32>>,
+}
+
+impl Allowed {
+    pub fn new() -> Self {
+        Allowed {
+            regs: Vec::new(),
+            csrs: Vec::new(),
+            memory: Vec::new(),
+        }
+    }
+
+    pub fn reg(mut self, reg: Reg) -> Self {
+        self.regs.push(reg);
+        self
+    }
+
+    pub fn csr(mut self, csr: u16) -> Self {
+        self.csrs.push(csr);
+        self
+    }
+
+    pub fn memory(mut self, range: Range<u32>) -> Self {
+        self.memory.push(range);
+        self
+    }
+
+    pub fn permits(&self, change: &Change) -> bool {
+        match change {
+            Change::Reg(reg, _, _) => self.regs.contains(reg),
+            Change::Csr(csr, _, _) => self.csrs.contains(csr),
+            Change::Mem(address, _, _) => self.memory.iter().any(|range| range.contains(address)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_and_allowed_sets() {
+        let mut cpu = Cpu::new(false);
+        cpu.load(vec![0x13, 0, 0, 0]);
+        let before = ArchState::capture(&cpu);
+        cpu.regs.set(Reg::A2, 5);
+        cpu.write_csr(MSCRATCH, 7);
+        cpu.mem.write_bytes(0x101, &[0x2a]);
+        cpu.pc.set(0x40);
+        let after = ArchState::capture(&cpu);
+        assert_eq!(
+            after.changes_since(&before),
+            [
+                Change::Reg(Reg::A2, 0, 5),
+                Change::Csr(MSCRATCH, 0, 7),
+                Change::Mem(0x101, 0, 0x2a)
+            ]
+        );
+        let allowed = Allowed::new()
+            .reg(Reg::A2)
+            .csr(MSCRATCH)
+            .memory(0x100..0x104);
+        after.assert_only(&before, &allowed);
+        assert!(!Allowed::new()
+            .reg(Reg::A2)
+            .permits(&Change::Mem(0x101, 0, 0x2a)));
+    }
+
+    #[test]
+    #[should_panic(expected = "mem 0x101: 0x0 -> 0x2a")]
+    fn unexpected_changes_panic() {
+        let mut cpu = Cpu::new(false);
+        let before = ArchState::capture(&cpu);
+        cpu.mem.write_bytes(0x101, &[0x2a]);
+        ArchState::capture(&cpu).assert_only(&before, &Allowed::new().memory(0..0x100));
+    }
+}
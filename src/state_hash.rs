@@ -0,0 +1,68 @@
+// Signature of a run for `--state-hash`: a 64-bit fnv-1a hash over what the commit trace would
+// contain or over the final state, so that two emulator versions can be compared for regressions
+// without storing huge traces. The hash only depends on the values fed in, in little-endian byte
+// order, so it is the same on every host.
+
+const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HashMode {
+    // every retired or trapping instruction: hart, pc, raw instruction and the registers written
+    Commit,
+    // the pcs and registers of all harts and the contents of ram once the program stopped
+    Final,
+}
+
+impl HashMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "commit" => Some(HashMode::Commit),
+            "final" => Some(HashMode::Final),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct StateHash {
+    hash: u64,
+}
+
+impl StateHash {
+    pub fn new() -> Self {
+        StateHash { hash: OFFSET_BASIS }
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.hash = (self.hash ^ byte as u64).wrapping_mul(PRIME);
+        }
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.write(&value.to_le_bytes());
+    }
+
+    pub fn value(&self) -> u64 {
+        self.hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fnv1a() {
+        let mut hash = StateHash::new();
+        assert_eq!(hash.value(), 0xcbf29ce484222325);
+        hash.write(b"a");
+        assert_eq!(hash.value(), 0xaf63dc4c8601ec8c);
+        let mut words = StateHash::new();
+        words.write_u32(0x0403_0201);
+        let mut bytes = StateHash::new();
+        bytes.write(&[1, 2, 3, 4]);
+        assert_eq!(words.value(), bytes.value());
+    }
+}
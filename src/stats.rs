@@ -0,0 +1,123 @@
+// Statistics about the guest's loads and stores, to spot cache-unfriendly access patterns without
+// simulating a cache.
+use std::collections::HashMap;
+use std::fmt::Write;
+
+// granularity of the hottest-address report, the line size of common caches
+const LINE_SIZE: u32 = 64;
+// entries listed in the stride and hottest-line tables
+const TOP: usize = 8;
+
+#[derive(Default)]
+pub struct MemStats {
+    pub loads: u64,
+    pub stores: u64,
+    pub bytes_loaded: u64,
+    pub bytes_stored: u64,
+    // distance of each load (store) to the previous load (store) and how often it occurred
+    strides: HashMap<i64, u64>,
+    // address of the previous load and store
+    last: [Option<u32>; 2],
+    // accesses per cache line
+    lines: HashMap<u32, u64>,
+}
+
+impl MemStats {
+    pub fn new() -> Self {
+        MemStats::default()
+    }
+
+    pub fn record(&mut self, address: u32, bytes: u32, store: bool) {
+        if store {
+            self.stores += 1;
+            self.bytes_stored += bytes as u64;
+        } else {
+            self.loads += 1;
+            self.bytes_loaded += bytes as u64;
+        }
+        if let Some(last) = self.last[store as usize].replace(address) {
+            *self
+                .strides
+                .entry(address as i64 - last as i64)
+                .or_default() += 1;
+        }
+        *self
+            .lines
+            .entry(address / LINE_SIZE * LINE_SIZE)
+            .or_default() += 1;
+    }
+
+    // the most frequent strides with their count, most frequent first
+    pub fn strides(&self) -> Vec<(i64, u64)> {
+        top(&self.strides)
+    }
+
+    // the most accessed cache lines with their number of accesses, most accessed first
+    pub fn hottest_lines(&self) -> Vec<(u32, u64)> {
+        top(&self.lines)
+    }
+
+    pub fn report(&self) -> String {
+        let mut out = String::from("memory accesses:\n");
+        let _ = writeln!(
+            out,
+            "  loads  {:>12} ({} bytes)",
+            self.loads, self.bytes_loaded
+        );
+        let _ = writeln!(
+            out,
+            "  stores {:>12} ({} bytes)",
+            self.stores, self.bytes_stored
+        );
+        if self.stores > 0 {
+            let ratio = self.loads as f64 / self.stores as f64;
+            let _ = writeln!(out, "  read/write ratio {ratio:.2}");
+        }
+        let accesses: u64 = self.strides.values().sum();
+        if accesses > 0 {
+            out += "  strides to the previous access of the same kind:\n";
+            for (stride, count) in self.strides() {
+                let share = count as f64 / accesses as f64 * 100.0;
+                let _ = writeln!(out, "    {stride:>+12} {count:>12} {share:>5.1}%");
+            }
+        }
+        if !self.lines.is_empty() {
+            let _ = writeln!(out, "  hottest {LINE_SIZE}-byte lines:");
+            for (line, count) in self.hottest_lines() {
+                let _ = writeln!(out, "    {line:#010x} {count:>12}");
+            }
+        }
+        out
+    }
+}
+
+fn top<K: Copy + Ord>(counts: &HashMap<K, u64>) -> Vec<(K, u64)> {
+    let mut entries: Vec<_> = counts.iter().map(|(&key, &count)| (key, count)).collect();
+    // ties are broken by the key, so the report doesn't depend on the hash order
+    entries.sort_by_key(|&(key, count)| (std::cmp::Reverse(count), key));
+    entries.truncate(TOP);
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn access_patterns() {
+        let mut stats = MemStats::new();
+        for i in 0..32 {
+            stats.record(0x1000 + i * 4, 4, false);
+        }
+        stats.record(0x2000, 1, true);
+        stats.record(0x1ff0, 2, true);
+
+        assert_eq!((stats.loads, stats.bytes_loaded), (32, 128));
+        assert_eq!((stats.stores, stats.bytes_stored), (2, 3));
+        assert_eq!(stats.strides(), [(4, 31), (-16, 1)]);
+        assert_eq!(
+            stats.hottest_lines(),
+            [(0x1000, 16), (0x1040, 16), (0x1fc0, 1), (0x2000, 1)]
+        );
+    }
+}
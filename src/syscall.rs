@@ -0,0 +1,651 @@
+// Linux system calls as made by newlib's libgloss port for risc-v, the syscall number is passed
+// in a7, arguments in a0-a5 and the result is returned in a0.
+use crate::cpu::Cpu;
+use crate::fs::OpenFile;
+use crate::regs::Reg;
+
+use std::fmt::Write;
+use std::io::{self, Read, Seek, SeekFrom, Write as _};
+
+pub const SYS_OPENAT: u32 = 56;
+pub const SYS_CLOSE: u32 = 57;
+pub const SYS_LSEEK: u32 = 62;
+pub const SYS_READ: u32 = 63;
+pub const SYS_WRITE: u32 = 64;
+pub const SYS_EXIT: u32 = 93;
+pub const SYS_CLOCK_GETTIME: u32 = 113;
+pub const SYS_REBOOT: u32 = 142;
+pub const SYS_GETTIMEOFDAY: u32 = 169;
+pub const SYS_BRK: u32 = 214;
+pub const SYS_MMAP: u32 = 222;
+pub const SYS_GETRANDOM: u32 = 278;
+pub const SYS_CLOCK_GETTIME64: u32 = 403;
+
+// file descriptor of the first file mapped on the command-line, following stdin/out/err
+const FIRST_MAPPED_FD: u32 = 3;
+const PROT_WRITE: u32 = 0x2;
+const PAGE_SIZE: u32 = 4096;
+// dirfd of openat that refers to the current directory, which is always the guest's root
+const AT_FDCWD: u32 = -100i32 as u32;
+const PATH_MAX: usize = 4096;
+// clocks of clock_gettime following the wall-clock, all others are treated as monotonic
+const CLOCK_REALTIME: u32 = 0;
+const CLOCK_REALTIME_COARSE: u32 = 5;
+const CLOCK_BOOTTIME: u32 = 7;
+
+// errors are returned as negated errno values
+const ENOENT: u32 = 2;
+const EIO: u32 = 5;
+const EBADF: u32 = 9;
+const EACCES: u32 = 13;
+const EFAULT: u32 = 14;
+const EEXIST: u32 = 17;
+const EINVAL: u32 = 22;
+const ESPIPE: u32 = 29;
+const ENAMETOOLONG: u32 = 36;
+
+// magic numbers and commands of reboot
+const REBOOT_MAGIC1: u32 = 0xfee1_dead;
+const REBOOT_MAGIC2: u32 = 672274793;
+const REBOOT_CMD_RESTART: u32 = 0x0123_4567;
+const REBOOT_CMD_HALT: u32 = 0xcdef_0123;
+const REBOOT_CMD_POWER_OFF: u32 = 0x4321_fedc;
+
+// bytes of strings and buffers shown by strace
+const STRACE_LEN: usize = 32;
+
+// The program break, the end of the heap which starts right after the loaded program.
+// sbrk is implemented by the c library on top of brk.
+#[derive(Clone)]
+pub struct Heap {
+    start: u32,
+    brk: u32,
+    // highest break of the run, resets of the machine included
+    peak: u32,
+}
+
+impl Heap {
+    pub fn new(start: u32) -> Self {
+        // malloc expects an aligned heap
+        let start = start.next_multiple_of(16);
+        Heap {
+            start,
+            brk: start,
+            peak: start,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.brk = self.start;
+    }
+
+    // bytes between the start of the heap and the program break
+    pub fn size(&self) -> u32 {
+        self.brk - self.start
+    }
+
+    pub fn peak(&self) -> u32 {
+        self.peak - self.start
+    }
+
+    pub fn report(&self) -> String {
+        format!(
+            "heap: {} bytes at exit (program break {:#x}), at most {} bytes\n",
+            self.size(),
+            self.brk,
+            self.peak()
+        )
+    }
+}
+
+pub enum Syscall {
+    Return(u32),
+    Exit(u8),
+    // the machine restarts from its power-on state
+    Reset,
+}
+
+// Returns None for syscalls that aren't emulated, these go to the program's trap handler.
+pub fn handle(cpu: &mut Cpu) -> Option<Syscall> {
+    let a0 = cpu.regs.get(Reg::A0);
+    match cpu.regs.get(Reg::A7) {
+        SYS_OPENAT => Some(Syscall::Return(openat(
+            cpu,
+            a0,
+            cpu.regs.get(Reg::A1),
+            cpu.regs.get(Reg::A2),
+        ))),
+        SYS_CLOSE => Some(Syscall::Return(close(cpu, a0))),
+        SYS_LSEEK => Some(Syscall::Return(lseek(
+            cpu,
+            a0,
+            cpu.regs.get(Reg::A1) as i32,
+            cpu.regs.get(Reg::A2),
+        ))),
+        SYS_READ => Some(Syscall::Return(read(
+            cpu,
+            a0,
+            cpu.regs.get(Reg::A1),
+            cpu.regs.get(Reg::A2),
+        ))),
+        SYS_WRITE => Some(Syscall::Return(write(
+            cpu,
+            a0,
+            cpu.regs.get(Reg::A1),
+            cpu.regs.get(Reg::A2),
+        ))),
+        SYS_GETRANDOM => Some(Syscall::Return(getrandom(cpu, a0, cpu.regs.get(Reg::A1)))),
+        SYS_CLOCK_GETTIME | SYS_CLOCK_GETTIME64 => Some(Syscall::Return(clock_gettime(
+            cpu,
+            a0,
+            cpu.regs.get(Reg::A1),
+        ))),
+        SYS_GETTIMEOFDAY => Some(Syscall::Return(gettimeofday(cpu, a0))),
+        SYS_EXIT => Some(Syscall::Exit(a0 as u8)),
+        SYS_REBOOT => Some(reboot(a0, cpu.regs.get(Reg::A1), cpu.regs.get(Reg::A2))),
+        SYS_BRK => Some(Syscall::Return(brk(cpu, a0))),
+        SYS_MMAP => Some(Syscall::Return(mmap(
+            cpu,
+            cpu.regs.get(Reg::A1),
+            cpu.regs.get(Reg::A2),
+            cpu.regs.get(Reg::A4),
+            cpu.regs.get(Reg::A5),
+        ))),
+        _ => None,
+    }
+}
+
+// how strace shows an argument
+#[derive(Clone, Copy)]
+enum Arg {
+    Int,
+    Hex,
+    // nul-terminated string
+    Str,
+    // buffer whose length is the argument with the given index
+    Buf(usize),
+}
+
+// name and arguments of the syscalls known to strace, numbers of the generic linux syscall table
+fn signature(number: u32) -> Option<(&'static str, &'static [Arg])> {
+    use Arg::*;
+    Some(match number {
+        17 => ("getcwd", &[Hex, Int]),
+        29 => ("ioctl", &[Int, Hex, Hex]),
+        35 => ("unlinkat", &[Int, Str, Hex]),
+        SYS_OPENAT => ("openat", &[Int, Str, Hex, Hex]),
+        SYS_CLOSE => ("close", &[Int]),
+        SYS_LSEEK => ("lseek", &[Int, Int, Int]),
+        SYS_READ => ("read", &[Int, Hex, Int]),
+        SYS_WRITE => ("write", &[Int, Buf(2), Int]),
+        66 => ("writev", &[Int, Hex, Int]),
+        80 => ("fstat", &[Int, Hex]),
+        SYS_EXIT => ("exit", &[Int]),
+        94 => ("exit_group", &[Int]),
+        SYS_CLOCK_GETTIME => ("clock_gettime", &[Int, Hex]),
+        SYS_REBOOT => ("reboot", &[Hex, Int, Hex]),
+        160 => ("uname", &[Hex]),
+        SYS_GETTIMEOFDAY => ("gettimeofday", &[Hex, Hex]),
+        172 => ("getpid", &[]),
+        SYS_BRK => ("brk", &[Hex]),
+        215 => ("munmap", &[Hex, Int]),
+        SYS_MMAP => ("mmap", &[Hex, Int, Hex, Hex, Int, Hex]),
+        SYS_GETRANDOM => ("getrandom", &[Hex, Int, Hex]),
+        SYS_CLOCK_GETTIME64 => ("clock_gettime64", &[Int, Hex]),
+        _ => return None,
+    })
+}
+
+fn errno_name(errno: u32) -> Option<&'static str> {
+    Some(match errno {
+        ENOENT => "ENOENT",
+        EIO => "EIO",
+        EBADF => "EBADF",
+        EACCES => "EACCES",
+        EFAULT => "EFAULT",
+        EEXIST => "EEXIST",
+        EINVAL => "EINVAL",
+        ESPIPE => "ESPIPE",
+        ENAMETOOLONG => "ENAMETOOLONG",
+        _ => return None,
+    })
+}
+
+// quotes the bytes like strace, `truncated` adds an ellipsis
+fn quote(bytes: &[u8], truncated: bool) -> String {
+    let mut out = String::from("\"");
+    for &byte in bytes {
+        match byte {
+            b'\n' => out += "\\n",
+            b'\t' => out += "\\t",
+            b'"' => out += "\\\"",
+            b'\\' => out += "\\\\",
+            0x20..=0x7e => out.push(byte as char),
+            _ => {
+                let _ = write!(out, "\\x{byte:02x}");
+            }
+        }
+    }
+    out.push('"');
+    if truncated {
+        out += "...";
+    }
+    out
+}
+
+fn format_arg(cpu: &Cpu, kind: Arg, args: &[u32; 6], value: u32) -> String {
+    match kind {
+        Arg::Int => (value as i32).to_string(),
+        Arg::Hex if value == 0 => "NULL".to_string(),
+        Arg::Hex => format!("{value:#x}"),
+        Arg::Str => {
+            let bytes = cpu.mem.peek(value, STRACE_LEN + 1);
+            match bytes.iter().position(|&byte| byte == 0) {
+                Some(len) => quote(&bytes[..len], false),
+                None if bytes.len() > STRACE_LEN => quote(&bytes[..STRACE_LEN], true),
+                None => format_arg(cpu, Arg::Hex, args, value),
+            }
+        }
+        Arg::Buf(len) => {
+            let len = args[len] as usize;
+            let bytes = cpu.mem.peek(value, len.min(STRACE_LEN));
+            if bytes.len() < len.min(STRACE_LEN) {
+                return format_arg(cpu, Arg::Hex, args, value);
+            }
+            quote(&bytes, len > STRACE_LEN)
+        }
+    }
+}
+
+// Describes the syscall the program is making like strace does, e.g. `write(1, "hi\n", 3) = 3`.
+// `result` is how the emulator handled it, None if the syscall isn't emulated.
+pub fn strace(cpu: &Cpu, result: Option<&Syscall>) -> String {
+    let number = cpu.regs.get(Reg::A7);
+    let args = [Reg::A0, Reg::A1, Reg::A2, Reg::A3, Reg::A4, Reg::A5].map(|reg| cpu.regs.get(reg));
+    let (name, kinds) = match signature(number) {
+        Some((name, kinds)) => (name.to_string(), kinds),
+        None => (format!("syscall_{number}"), [Arg::Hex; 6].as_slice()),
+    };
+    let args: Vec<_> = kinds
+        .iter()
+        .zip(args)
+        .map(|(&kind, value)| format_arg(cpu, kind, &args, value))
+        .collect();
+    let result = match result {
+        Some(Syscall::Return(value)) => match errno_name(value.wrapping_neg()) {
+            Some(errno) => format!("-1 {errno}"),
+            None if matches!(number, SYS_BRK | SYS_MMAP) => format!("{value:#x}"),
+            None => (*value as i32).to_string(),
+        },
+        Some(Syscall::Exit(code)) => format!("?\n+++ exited with {code} +++"),
+        Some(Syscall::Reset) => "?\n+++ reset +++".to_string(),
+        None => "? (not emulated)".to_string(),
+    };
+    format!("{name}({}) = {result}", args.join(", "))
+}
+
+fn errno(error: io::Error) -> u32 {
+    let errno = match error.kind() {
+        io::ErrorKind::NotFound => ENOENT,
+        io::ErrorKind::PermissionDenied => EACCES,
+        io::ErrorKind::AlreadyExists => EEXIST,
+        io::ErrorKind::InvalidInput => EINVAL,
+        _ => EIO,
+    };
+    errno.wrapping_neg()
+}
+
+// Files opened by the program get the file descriptors after stdin/out/err and the mapped files,
+// returns the index of the open file.
+fn open_file(cpu: &Cpu, fd: u32) -> Option<usize> {
+    let first = FIRST_MAPPED_FD as usize + cpu.mapped_files.len();
+    (fd as usize).checked_sub(first)
+}
+
+// Opens the host file the guest path is translated to by the sandbox. The current directory is
+// always the guest's root, other directory file descriptors aren't supported.
+fn openat(cpu: &mut Cpu, dirfd: u32, path: u32, flags: u32) -> u32 {
+    let bytes = cpu.mem.peek(path, PATH_MAX);
+    let Some(len) = bytes.iter().position(|&byte| byte == 0) else {
+        return match bytes.len() {
+            PATH_MAX => ENAMETOOLONG.wrapping_neg(),
+            _ => EFAULT.wrapping_neg(),
+        };
+    };
+    let path = String::from_utf8_lossy(&bytes[..len]).into_owned();
+    if dirfd != AT_FDCWD && !path.starts_with('/') {
+        return EBADF.wrapping_neg();
+    }
+    match cpu.files.open(&path, flags) {
+        Ok(index) => (FIRST_MAPPED_FD as usize + cpu.mapped_files.len() + index) as u32,
+        Err(e) => errno(e),
+    }
+}
+
+fn close(cpu: &mut Cpu, fd: u32) -> u32 {
+    match open_file(cpu, fd) {
+        // stdin/out/err stay usable by the emulator
+        _ if fd <= 2 => 0,
+        Some(index) if cpu.files.close(index) => 0,
+        _ => EBADF.wrapping_neg(),
+    }
+}
+
+fn lseek(cpu: &mut Cpu, fd: u32, offset: i32, whence: u32) -> u32 {
+    let position = match whence {
+        0 if offset >= 0 => SeekFrom::Start(offset as u64),
+        1 => SeekFrom::Current(offset as i64),
+        2 => SeekFrom::End(offset as i64),
+        _ => return EINVAL.wrapping_neg(),
+    };
+    let file = open_file(cpu, fd).and_then(|index| cpu.files.file(index));
+    let seek = file.map(|file| match file {
+        OpenFile::Host(file) => file.seek(position),
+        OpenFile::Random => Ok(0),
+    });
+    match seek {
+        Some(Ok(position)) => position as u32,
+        Some(Err(e)) => errno(e),
+        None if fd <= 2 => ESPIPE.wrapping_neg(),
+        None => EBADF.wrapping_neg(),
+    }
+}
+
+// stdin is read from the host's stdin, unless the output is captured for a front end or a
+// playground session, which own the host's stdin, then it is at its end
+fn read(cpu: &mut Cpu, fd: u32, buf: u32, count: u32) -> u32 {
+    if cpu.mem.peek(buf, count as usize).len() < count as usize {
+        return EFAULT.wrapping_neg();
+    }
+    let mut data = vec![0; count as usize];
+    let read = match (fd, open_file(cpu, fd)) {
+        (0, _) if cpu.console.is_some() => Ok(0),
+        (0, _) => io::stdin().read(&mut data),
+        (_, Some(index)) => match cpu.files.file(index) {
+            Some(OpenFile::Host(file)) => file.read(&mut data),
+            Some(OpenFile::Random) => {
+                cpu.entropy.fill(&mut data);
+                Ok(data.len())
+            }
+            None => return EBADF.wrapping_neg(),
+        },
+        _ => return EBADF.wrapping_neg(),
+    };
+    match read {
+        Ok(len) => {
+            cpu.mem.write_bytes(buf, &data[..len]);
+            len as u32
+        }
+        Err(e) => errno(e),
+    }
+}
+
+// stdout and stderr are written to the host's stdout and stderr, unless the output is captured
+fn write(cpu: &mut Cpu, fd: u32, buf: u32, count: u32) -> u32 {
+    let data = cpu.mem.peek(buf, count as usize).to_vec();
+    if data.len() < count as usize {
+        return EFAULT.wrapping_neg();
+    }
+    let written = match (fd, open_file(cpu, fd)) {
+        (1 | 2, _) if cpu.console.is_some() => cpu.console.as_mut().unwrap().write_all(&data),
+        (1, _) => io::stdout()
+            .write_all(&data)
+            .and_then(|_| io::stdout().flush()),
+        (2, _) => io::stderr().write_all(&data),
+        (_, Some(index)) => match cpu.files.file(index) {
+            Some(OpenFile::Host(file)) => file.write_all(&data),
+            // like linux, writing to the entropy source is allowed but doesn't change its output
+            Some(OpenFile::Random) => Ok(()),
+            None => return EBADF.wrapping_neg(),
+        },
+        _ => return EBADF.wrapping_neg(),
+    };
+    match written {
+        Ok(()) => count,
+        Err(e) => errno(e),
+    }
+}
+
+// fills the buffer from the seeded entropy source, flags like GRND_NONBLOCK don't matter
+fn getrandom(cpu: &mut Cpu, buf: u32, len: u32) -> u32 {
+    if cpu.mem.peek(buf, len as usize).len() < len as usize {
+        return EFAULT.wrapping_neg();
+    }
+    let mut data = vec![0; len as usize];
+    cpu.entropy.fill(&mut data);
+    cpu.mem.write_bytes(buf, &data);
+    len
+}
+
+// Stores a timespec or timeval as laid out by newlib on rv32: a 64-bit time_t followed by the
+// fraction in a 32-bit long and padding. Returns false if the struct isn't in ram.
+fn write_time(cpu: &mut Cpu, address: u32, secs: u64, fraction: u32) -> bool {
+    if cpu.mem.peek(address, 16).len() < 16 {
+        return false;
+    }
+    let mut time = [0; 16];
+    time[..8].copy_from_slice(&secs.to_le_bytes());
+    time[8..12].copy_from_slice(&fraction.to_le_bytes());
+    cpu.mem.write_bytes(address, &time);
+    true
+}
+
+// the time is virtual or the host's, depending on the cpu's clock
+fn clock_gettime(cpu: &mut Cpu, clock: u32, timespec: u32) -> u32 {
+    let time = match clock {
+        CLOCK_REALTIME | CLOCK_REALTIME_COARSE => cpu.clock.realtime(),
+        1..=CLOCK_BOOTTIME => cpu.clock.monotonic(),
+        _ => return EINVAL.wrapping_neg(),
+    };
+    if !write_time(cpu, timespec, time.as_secs(), time.subsec_nanos()) {
+        return EFAULT.wrapping_neg();
+    }
+    0
+}
+
+// the timezone argument is obsolete and ignored, the timeval may be NULL
+fn gettimeofday(cpu: &mut Cpu, timeval: u32) -> u32 {
+    let time = cpu.clock.realtime();
+    if timeval != 0 && !write_time(cpu, timeval, time.as_secs(), time.subsec_micros()) {
+        return EFAULT.wrapping_neg();
+    }
+    0
+}
+
+// restarting resets the machine, halting and powering off end the program successfully
+fn reboot(magic1: u32, magic2: u32, cmd: u32) -> Syscall {
+    if magic1 != REBOOT_MAGIC1 || magic2 != REBOOT_MAGIC2 {
+        return Syscall::Return(EINVAL.wrapping_neg());
+    }
+    match cmd {
+        REBOOT_CMD_RESTART => Syscall::Reset,
+        REBOOT_CMD_HALT | REBOOT_CMD_POWER_OFF => Syscall::Exit(0),
+        _ => Syscall::Return(EINVAL.wrapping_neg()),
+    }
+}
+
+// Like linux' brk, returns the new break on success and the current one if it can't be moved.
+// The heap can't grow into the stack, which ends at the guard page or the stack pointer.
+fn brk(cpu: &mut Cpu, address: u32) -> u32 {
+    let current = cpu.heap.brk;
+    if address < cpu.heap.start || address as u64 > cpu.mem.ram_end() {
+        return current;
+    }
+    let stack_limit = cpu
+        .mem
+        .guard()
+        .map_or(cpu.regs.get(Reg::Sp), |guard| guard.start);
+    if address > stack_limit {
+        eprintln!(
+            "heap collides with stack: can't move the program break to {address:#x} \
+             (stack ends at {stack_limit:#x})"
+        );
+        return current;
+    }
+    // memory returned to the system reads as zero once the heap grows again
+    if address > current {
+        cpu.mem
+            .write_bytes(current, &vec![0; (address - current) as usize]);
+    }
+    cpu.heap.brk = address;
+    cpu.heap.peak = cpu.heap.peak.max(address);
+    address
+}
+
+// Only files that were mapped by the host can be mmap'ed, their contents are already in the
+// address space, so this returns where the requested part of the file is. The address hint and
+// flags are ignored.
+fn mmap(cpu: &Cpu, len: u32, prot: u32, fd: u32, offset: u32) -> u32 {
+    let Some(&(base, size)) = fd
+        .checked_sub(FIRST_MAPPED_FD)
+        .and_then(|i| cpu.mapped_files.get(i as usize))
+    else {
+        return EBADF.wrapping_neg();
+    };
+    if prot & PROT_WRITE != 0 {
+        return EACCES.wrapping_neg();
+    }
+    if len == 0 || !offset.is_multiple_of(PAGE_SIZE) || offset as u64 + len as u64 > size as u64 {
+        return EINVAL.wrapping_neg();
+    }
+    base + offset
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use crate::fdt::TIMEBASE_FREQUENCY;
+    use crate::memory::Size;
+    use crate::rng::Rng;
+
+    #[test]
+    fn heap_stack_collision() {
+        let mut cpu = Cpu::new(false);
+        cpu.heap = Heap::new(0x1001);
+        cpu.regs.set(Reg::Sp, 0x8000);
+        assert_eq!(brk(&mut cpu, 0), 0x1010);
+        assert_eq!(brk(&mut cpu, 0x2000), 0x2000);
+        assert_eq!(brk(&mut cpu, 0x8001), 0x2000);
+        assert_eq!(brk(&mut cpu, 0x1800), 0x1800);
+    }
+
+    #[test]
+    fn file_syscalls() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("in.txt"), "hello").unwrap();
+        let mut cpu = Cpu::new(false);
+        cpu.map_file(0x9000_0000, vec![0; 16]).unwrap();
+        cpu.files.set_root(root.path().to_path_buf());
+        cpu.mem.write_bytes(0x100, b"/in.txt\0/missing\0");
+
+        let fd = openat(&mut cpu, AT_FDCWD, 0x100, 0);
+        assert_eq!(fd, 4);
+        assert_eq!(lseek(&mut cpu, fd, 1, 0), 1);
+        assert_eq!(read(&mut cpu, fd, 0x200, 16), 4);
+        assert_eq!(cpu.mem.peek(0x200, 4)[..], *b"ello");
+        assert_eq!(close(&mut cpu, fd), 0);
+        assert_eq!(read(&mut cpu, fd, 0x200, 16), EBADF.wrapping_neg());
+        assert_eq!(lseek(&mut cpu, 1, 0, 0), ESPIPE.wrapping_neg());
+        assert_eq!(openat(&mut cpu, AT_FDCWD, 0x108, 0), ENOENT.wrapping_neg());
+        assert_eq!(read(&mut cpu, 0, 0xffff_fff0, 4), EFAULT.wrapping_neg());
+    }
+
+    #[test]
+    fn seeded_entropy() {
+        let random = |seed| {
+            let mut cpu = Cpu::new(false);
+            cpu.entropy = Rng::new(seed);
+            cpu.mem.write_bytes(0x100, b"/dev/urandom\0");
+            assert_eq!(getrandom(&mut cpu, 0x200, 8), 8);
+            let fd = openat(&mut cpu, AT_FDCWD, 0x100, 0);
+            assert_eq!(read(&mut cpu, fd, 0x208, 8), 8);
+            cpu.mem.peek(0x200, 16).to_vec()
+        };
+        assert_eq!(random(1), random(1));
+        assert_ne!(random(1), random(2));
+        assert_ne!(random(1)[..8], random(1)[8..]);
+    }
+
+    #[test]
+    fn virtual_time() {
+        let mut cpu = Cpu::new(false);
+        cpu.clock.advance(TIMEBASE_FREQUENCY as u64 * 5 / 4);
+        assert_eq!(clock_gettime(&mut cpu, 1, 0x100), 0);
+        assert_eq!(gettimeofday(&mut cpu, 0x110), 0);
+        assert_eq!(
+            cpu.mem.peek(0x100, 16)[..12],
+            [1, 0, 0, 0, 0, 0, 0, 0, 0x80, 0xb2, 0xe6, 0x0e]
+        );
+        assert_eq!(
+            cpu.mem.peek(0x110, 16)[..12],
+            [1, 0, 0, 0, 0, 0, 0, 0, 0x90, 0xd0, 0x03, 0]
+        );
+        assert_eq!(clock_gettime(&mut cpu, 99, 0x100), EINVAL.wrapping_neg());
+        assert_eq!(
+            clock_gettime(&mut cpu, 0, 0xffff_fff8),
+            EFAULT.wrapping_neg()
+        );
+        assert_eq!(gettimeofday(&mut cpu, 0), 0);
+    }
+
+    #[test]
+    fn strace_lines() {
+        let mut cpu = Cpu::new(false);
+        cpu.mem.write_bytes(0x100, b"hi\n\x01\0");
+        let mut call = |number: u32, args: &[u32], result: Option<Syscall>| {
+            cpu.regs.set(Reg::A7, number);
+            for (reg, &arg) in [Reg::A0, Reg::A1, Reg::A2].into_iter().zip(args) {
+                cpu.regs.set(reg, arg);
+            }
+            strace(&cpu, result.as_ref())
+        };
+
+        assert_eq!(
+            call(64, &[1, 0x100, 4], Some(Syscall::Return(4))),
+            r#"write(1, "hi\n\x01", 4) = 4"#
+        );
+        assert_eq!(
+            call(56, &[-100i32 as u32, 0x100, 0], None),
+            r#"openat(-100, "hi\n\x01", NULL, NULL) = ? (not emulated)"#
+        );
+        assert_eq!(
+            call(SYS_BRK, &[0], Some(Syscall::Return(0x1010))),
+            "brk(NULL) = 0x1010"
+        );
+        assert_eq!(
+            call(57, &[7], Some(Syscall::Return(EBADF.wrapping_neg()))),
+            "close(7) = -1 EBADF"
+        );
+        assert_eq!(
+            call(SYS_EXIT, &[3], Some(Syscall::Exit(3))),
+            "exit(3) = ?\n+++ exited with 3 +++"
+        );
+        assert!(call(999, &[], None).starts_with("syscall_999(0x3, 0x100, NULL, "));
+        assert_eq!(
+            call(
+                SYS_REBOOT,
+                &[0xfee1dead, 672274793, 0x01234567],
+                Some(Syscall::Reset)
+            ),
+            "reboot(0xfee1dead, 672274793, 0x1234567) = ?\n+++ reset +++"
+        );
+    }
+
+    #[test]
+    fn mmap_mapped_file() {
+        let mut cpu = Cpu::new(false);
+        cpu.map_file(0x9000_0000, vec![7; 0x1800]).unwrap();
+        assert_eq!(mmap(&cpu, 0x1800, 1, 3, 0), 0x9000_0000);
+        assert_eq!(mmap(&cpu, 0x800, 1, 3, 0x1000), 0x9000_1000);
+        assert_eq!(mmap(&cpu, 0x1000, 1, 4, 0), EBADF.wrapping_neg());
+        assert_eq!(mmap(&cpu, 0x1000, 3, 3, 0), EACCES.wrapping_neg());
+        assert_eq!(mmap(&cpu, 0x1000, 1, 3, 0x1800), EINVAL.wrapping_neg());
+        assert!(cpu.mem.store(Size::Word, 0x9000_0000, 0).is_err());
+        assert_eq!(cpu.mem.load(Size::Byte, 0x9000_17ff, true).ok(), Some(7));
+        assert!(matches!(
+            cpu.map_file(0x9000_1000, vec![0]),
+            Err(Error::MappingOverlap(0x9000_1000))
+        ));
+    }
+}
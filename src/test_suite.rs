@@ -0,0 +1,189 @@
+// Runs the prebuilt binaries of the official riscv-tests (rv32ui-p-*, rv32mi-p-*, ...), see
+// https://github.com/riscv-software-src/riscv-tests. The tests report their result by writing
+// to the `tohost` symbol, 1 means success, otherwise the number of the failed test case is
+// stored shifted left by one.
+use crate::cpu::{Cpu, StopReason};
+use crate::devices::RtcClock;
+use crate::elf::Elf;
+use crate::error::Error;
+use crate::machine::Machine;
+
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+// the longest tests finish within a few thousand cycles, anything beyond this is stuck
+const CYCLE_LIMIT: usize = 1_000_000;
+
+pub enum Outcome {
+    Pass,
+    // number of the failed test case
+    Fail(u8),
+    Error(Error),
+}
+
+pub struct TestResult {
+    pub name: String,
+    pub outcome: Outcome,
+    // pc at which the test terminated
+    pub pc: u32,
+}
+
+// runs a single test binary on the virt32 machine, which has ram where the tests are linked
+pub fn run_test(name: &str, bytes: &[u8]) -> TestResult {
+    let mut cpu = Cpu::new(false);
+    cpu.mem = Machine::Virt32.memory(RtcClock::Frozen(0));
+    cpu.set_quiet();
+    cpu.set_cycle_limit(CYCLE_LIMIT);
+    let result = Elf::parse(bytes).and_then(|elf| {
+        let tohost = elf
+            .symbol("tohost")
+            .ok_or(Error::InvalidElf("no tohost symbol"))?;
+        cpu.enable_htif(tohost);
+        cpu.run_elf(&elf).and_then(StopReason::into_result)
+    });
+    let outcome = match result {
+        Ok(0) => Outcome::Pass,
+        Ok(test_case) => Outcome::Fail(test_case),
+        Err(e) => Outcome::Error(e),
+    };
+    TestResult {
+        name: name.to_string(),
+        outcome,
+        pc: cpu.pc.get(),
+    }
+}
+
+// Runs the named test binaries on `jobs` threads, each test gets its own cpu. The results are
+// in the order of the tests, no matter which thread finished first.
+pub fn run_all(tests: &[(String, Vec<u8>)], jobs: usize) -> Vec<TestResult> {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::with_capacity(tests.len()));
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, tests.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some((name, bytes)) = tests.get(index) else {
+                    break;
+                };
+                let result = run_test(name, bytes);
+                results.lock().unwrap().push((index, result));
+            });
+        }
+    });
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+// the number of threads used when none is given, one per available core
+pub fn default_jobs() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+// Runs every elf file in the directory on `jobs` threads and prints the results in alphabetical
+// order. Returns whether all tests passed.
+pub fn run(dir: &Path, jobs: usize) -> std::io::Result<bool> {
+    let mut paths: Vec<_> = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    paths.sort();
+
+    let mut tests = Vec::new();
+    for path in paths.iter().filter(|path| path.is_file()) {
+        let bytes = std::fs::read(path)?;
+        // the testsuite also contains the disassembly of each test
+        if !Elf::is_elf(&bytes) {
+            continue;
+        }
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        tests.push((name.into_owned(), bytes));
+    }
+    let results = run_all(&tests, jobs);
+
+    let width = results
+        .iter()
+        .map(|result| result.name.len())
+        .max()
+        .unwrap_or(0);
+    for result in &results {
+        let status = match &result.outcome {
+            Outcome::Pass => "pass".to_string(),
+            Outcome::Fail(test_case) => {
+                format!("FAIL  test case {test_case} (pc: {:#010x})", result.pc)
+            }
+            Outcome::Error(e) => format!("ERROR {e:?} (pc: {:#010x})", result.pc),
+        };
+        println!("{:width$}  {status}", result.name);
+    }
+    let passed = results
+        .iter()
+        .filter(|result| matches!(result.outcome, Outcome::Pass))
+        .count();
+    println!("{passed}/{} tests passed", results.len());
+    Ok(passed == results.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elf::build_test_elf;
+
+    const TOHOST: u32 = 0x8000_1000;
+
+    // stores the value to tohost
+    fn report(value: u32) -> Vec<u32> {
+        vec![
+            0x80001537,               // lui a0, 0x80001
+            0x00000593 | value << 20, // addi a1, x0, value
+            0x00b52023,               // sw a1, 0(a0)
+            0x0000006f,               // jal x0, 0
+        ]
+    }
+
+    #[test]
+    fn pass_and_fail() {
+        let pass = build_test_elf(0x8000_0000, &report(1), TOHOST);
+        assert!(matches!(run_test("pass", &pass).outcome, Outcome::Pass));
+
+        // test case 3 failed
+        let fail = build_test_elf(0x8000_0000, &report(7), TOHOST);
+        let result = run_test("fail", &fail);
+        assert!(matches!(result.outcome, Outcome::Fail(3)));
+        assert_eq!(result.pc, 0x8000_000c);
+    }
+
+    #[test]
+    fn parallel_results_keep_their_order() {
+        let tests: Vec<_> = (0..20)
+            .map(|n| {
+                // odd tests fail test case n
+                let value = if n % 2 == 0 { 1 } else { n << 1 | 1 };
+                let elf = build_test_elf(0x8000_0000, &report(value), TOHOST);
+                (format!("test-{n}"), elf)
+            })
+            .collect();
+        for jobs in [1, 4, 64] {
+            let results = run_all(&tests, jobs);
+            assert_eq!(results.len(), 20);
+            for (n, result) in results.iter().enumerate() {
+                assert_eq!(result.name, format!("test-{n}"));
+                match result.outcome {
+                    Outcome::Pass => assert!(n % 2 == 0),
+                    Outcome::Fail(test_case) => assert_eq!(test_case as usize, n),
+                    Outcome::Error(_) => panic!("test-{n} errored"),
+                }
+            }
+        }
+        assert!(run_all(&[], 4).is_empty());
+    }
+
+    #[test]
+    fn stuck_test() {
+        let stuck = build_test_elf(0x8000_0000, &[0x0000006f], TOHOST);
+        assert!(matches!(
+            run_test("stuck", &stuck).outcome,
+            Outcome::Error(Error::CycleLimit(CYCLE_LIMIT))
+        ));
+    }
+}
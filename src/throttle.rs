@@ -0,0 +1,69 @@
+// Caps the emulation speed by sleeping whenever the program runs ahead of the given instruction
+// rate, e.g. to watch a demo at human speed or to not flood a peer on the network.
+use std::time::{Duration, Instant};
+
+// how often per second the pace is checked, keeps the sleeps short enough to not be noticed
+const CHECKS_PER_SECOND: f64 = 100.0;
+
+pub struct Throttle {
+    // instructions per second
+    rate: f64,
+    // instructions between two checks
+    interval: u64,
+    next: u64,
+    // time and retired instructions the pace is measured from
+    origin: (Instant, u64),
+}
+
+impl Throttle {
+    pub fn new(mips: f64) -> Self {
+        let rate = mips * 1e6;
+        let interval = (rate / CHECKS_PER_SECOND).max(1.0) as u64;
+        Throttle {
+            rate,
+            interval,
+            next: interval,
+            origin: (Instant::now(), 0),
+        }
+    }
+
+    // how long to sleep to get back to the rate, None once the emulation fell behind it
+    fn ahead(&self, retired: u64, now: Instant) -> Option<Duration> {
+        let (start, start_retired) = self.origin;
+        let due = Duration::from_secs_f64((retired - start_retired) as f64 / self.rate);
+        due.checked_sub(now.duration_since(start))
+    }
+
+    pub fn update(&mut self, retired: u64) {
+        if retired < self.next {
+            return;
+        }
+        self.next = retired + self.interval;
+        let now = Instant::now();
+        match self.ahead(retired, now) {
+            Some(sleep) => std::thread::sleep(sleep),
+            // time spent waiting or in a slow stretch isn't caught up with a burst afterwards
+            None => self.origin = (now, retired),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paces_instructions() {
+        let mut throttle = Throttle::new(0.001);
+        assert_eq!(throttle.interval, 10);
+        let start = throttle.origin.0;
+        assert_eq!(throttle.ahead(500, start), Some(Duration::from_millis(500)));
+        assert_eq!(throttle.ahead(500, start + Duration::from_secs(1)), None);
+
+        let begin = Instant::now();
+        for retired in 0..=20 {
+            throttle.update(retired);
+        }
+        assert!(begin.elapsed() >= Duration::from_millis(19));
+    }
+}
@@ -0,0 +1,277 @@
+// Traces of the executed instructions written to files, and the filter that restricts the
+// per-instruction output of all tracing modes to the code regions of interest.
+use crate::decode::decode;
+use crate::regs::RegChange;
+
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+pub struct TraceFilter {
+    // an empty filter traces everything
+    ranges: Vec<Range<u32>>,
+}
+
+impl TraceFilter {
+    pub fn new() -> Self {
+        TraceFilter { ranges: Vec::new() }
+    }
+    pub fn add_range(&mut self, range: Range<u32>) {
+        self.ranges.push(range);
+    }
+    // whether the instruction at pc is traced
+    pub fn matches(&self, pc: u32) -> bool {
+        self.ranges.is_empty() || self.ranges.iter().any(|range| range.contains(&pc))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    // one line per instruction: pc, raw instruction and the registers it wrote, like spike's
+    // commit log
+    Commit,
+    // one json object per line
+    Json,
+}
+
+impl TraceFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "commit" => Some(TraceFormat::Commit),
+            "json" => Some(TraceFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+// trace files are compressed depending on their extension
+enum Output {
+    Plain(BufWriter<File>),
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzEncoder<BufWriter<File>>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::Encoder<'static, BufWriter<File>>),
+}
+
+impl Output {
+    fn create(path: &Path) -> io::Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(match path.extension().and_then(|ext| ext.to_str()) {
+            #[cfg(feature = "gzip")]
+            Some("gz") => Output::Gzip(flate2::write::GzEncoder::new(
+                file,
+                flate2::Compression::default(),
+            )),
+            #[cfg(feature = "zstd")]
+            Some("zst") => Output::Zstd(zstd::Encoder::new(file, 0)?),
+            _ => Output::Plain(file),
+        })
+    }
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            Output::Plain(file) => file,
+            #[cfg(feature = "gzip")]
+            Output::Gzip(encoder) => encoder,
+            #[cfg(feature = "zstd")]
+            Output::Zstd(encoder) => encoder,
+        }
+    }
+    // compressed streams are only complete once their trailer is written
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Output::Plain(mut file) => file.flush(),
+            #[cfg(feature = "gzip")]
+            Output::Gzip(encoder) => encoder.finish()?.flush(),
+            #[cfg(feature = "zstd")]
+            Output::Zstd(encoder) => encoder.finish()?.flush(),
+        }
+    }
+}
+
+// Writes a trace of the executed instructions to a file. With a size limit the trace is split
+// into several files, trace.log, trace.1.log, trace.2.log and so on, each at most about
+// max_size bytes before compression.
+pub struct TraceWriter {
+    path: PathBuf,
+    format: TraceFormat,
+    max_size: Option<u64>,
+    // number of the current file and the bytes written to it
+    part: u32,
+    written: u64,
+    out: Option<Output>,
+    line: String,
+}
+
+impl TraceWriter {
+    pub fn create(path: &Path, format: TraceFormat, max_size: Option<u64>) -> io::Result<Self> {
+        Ok(TraceWriter {
+            path: path.to_path_buf(),
+            format,
+            max_size,
+            part: 0,
+            written: 0,
+            out: Some(Output::create(path)?),
+            line: String::new(),
+        })
+    }
+
+    // the first part keeps the given name, the part number is inserted before the extensions
+    fn part_path(&self, part: u32) -> PathBuf {
+        let name = self.path.file_name().unwrap_or_default().to_string_lossy();
+        let name = match name.split_once('.') {
+            Some((stem, extensions)) => format!("{stem}.{part}.{extensions}"),
+            None => format!("{name}.{part}"),
+        };
+        self.path.with_file_name(name)
+    }
+
+    // starts the line of an executed instruction
+    pub fn begin(&mut self, cycle: usize, pc: u32, raw_inst: u32) {
+        self.line.clear();
+        match self.format {
+            TraceFormat::Commit => {
+                let _ = write!(self.line, "{pc:#010x} ({raw_inst:#010x})");
+            }
+            TraceFormat::Json => {
+                let disasm = match decode(raw_inst) {
+                    Ok(inst) => inst.to_string(),
+                    Err(_) => String::new(),
+                };
+                let _ = write!(
+                    self.line,
+                    r#"{{"cycle":{cycle},"pc":{pc},"inst":{raw_inst},"disasm":"{disasm}","writes":["#
+                );
+            }
+        }
+    }
+
+    pub fn reg_write(&mut self, change: &RegChange) {
+        match self.format {
+            TraceFormat::Commit => {
+                let _ = write!(self.line, " {} {:#010x}", change.reg.name(), change.new);
+            }
+            TraceFormat::Json => {
+                if !self.line.ends_with('[') {
+                    self.line.push(',');
+                }
+                let _ = write!(
+                    self.line,
+                    r#"{{"reg":"{}","value":{}}}"#,
+                    change.reg.name(),
+                    change.new
+                );
+            }
+        }
+    }
+
+    // writes the line and starts the next file once the current one is full
+    pub fn end(&mut self) -> io::Result<()> {
+        if self.format == TraceFormat::Json {
+            self.line.push_str("]}");
+        }
+        self.line.push('\n');
+        if self.max_size.is_some_and(|max_size| {
+            self.written > 0 && self.written + self.line.len() as u64 > max_size
+        }) {
+            self.part += 1;
+            if let Some(out) = self.out.take() {
+                out.finish()?;
+            }
+            self.out = Some(Output::create(&self.part_path(self.part))?);
+            self.written = 0;
+        }
+        if let Some(out) = self.out.as_mut() {
+            out.writer().write_all(self.line.as_bytes())?;
+        }
+        self.written += self.line.len() as u64;
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        match self.out.take() {
+            Some(out) => out.finish(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regs::Reg;
+
+    #[test]
+    fn ranges() {
+        let mut filter = TraceFilter::new();
+        assert!(filter.matches(0x1234));
+        filter.add_range(0x100..0x200);
+        filter.add_range(0x400..0x404);
+        assert!(filter.matches(0x100));
+        assert!(!filter.matches(0x200));
+        assert!(filter.matches(0x400));
+        assert!(!filter.matches(0x1234));
+    }
+
+    fn trace_lines(writer: &mut TraceWriter, count: u32) {
+        for i in 0..count {
+            writer.begin(i as usize, 4 * i, 0x00500293);
+            writer.reg_write(&RegChange {
+                reg: Reg::T0,
+                old: 0,
+                new: 5,
+            });
+            writer.end().unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trace.log");
+        let mut writer = TraceWriter::create(&path, TraceFormat::Commit, Some(64)).unwrap();
+        trace_lines(&mut writer, 3);
+
+        let line = "0x00000000 (0x00500293) t0 0x00000005\n";
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), line);
+        assert!(dir.path().join("trace.1.log").exists());
+        assert!(dir.path().join("trace.2.log").exists());
+        assert!(!dir.path().join("trace.3.log").exists());
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip_json() {
+        use std::io::Read;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trace.jsonl.gz");
+        let mut writer = TraceWriter::create(&path, TraceFormat::Json, None).unwrap();
+        trace_lines(&mut writer, 1);
+
+        let mut json = String::new();
+        flate2::read::GzDecoder::new(File::open(&path).unwrap())
+            .read_to_string(&mut json)
+            .unwrap();
+        assert_eq!(
+            json,
+            r#"{"cycle":0,"pc":0,"inst":5243539,"disasm":"addi t0, zero, 5","writes":[{"reg":"t0","value":5}]}"#
+                .to_string()
+                + "\n"
+        );
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trace.log.zst");
+        let mut writer = TraceWriter::create(&path, TraceFormat::Commit, None).unwrap();
+        trace_lines(&mut writer, 2);
+
+        let trace = zstd::decode_all(File::open(&path).unwrap()).unwrap();
+        assert_eq!(String::from_utf8(trace).unwrap().lines().count(), 2);
+    }
+}
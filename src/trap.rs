@@ -0,0 +1,86 @@
+use crate::csr::Mode;
+
+use std::fmt;
+
+// set in mcause if the trap was caused by an interrupt, the remaining bits are the interrupt number
+pub const INTERRUPT: u32 = 1 << 31;
+
+// Synchronous exceptions, the payload is the value written to mtval.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Exception {
+    InstructionAddressMisaligned(u32),
+    InstructionAccessFault(u32),
+    IllegalInstruction(u32),
+    Breakpoint(u32),
+    LoadAddressMisaligned(u32),
+    LoadAccessFault(u32),
+    StoreAddressMisaligned(u32),
+    StoreAccessFault(u32),
+    // the cause tells which privilege mode the ecall came from
+    EnvironmentCall(Mode),
+    // raised by the address translation, the payload is the virtual address
+    InstructionPageFault(u32),
+    LoadPageFault(u32),
+    StorePageFault(u32),
+}
+
+impl Exception {
+    // exception code written to mcause
+    pub fn cause(&self) -> u32 {
+        match self {
+            Exception::InstructionAddressMisaligned(_) => 0,
+            Exception::InstructionAccessFault(_) => 1,
+            Exception::IllegalInstruction(_) => 2,
+            Exception::Breakpoint(_) => 3,
+            Exception::LoadAddressMisaligned(_) => 4,
+            Exception::LoadAccessFault(_) => 5,
+            Exception::StoreAddressMisaligned(_) => 6,
+            Exception::StoreAccessFault(_) => 7,
+            Exception::EnvironmentCall(mode) => 8 + *mode as u32,
+            Exception::InstructionPageFault(_) => 12,
+            Exception::LoadPageFault(_) => 13,
+            Exception::StorePageFault(_) => 15,
+        }
+    }
+
+    // For faulting accesses and misaligned jumps this is the faulting address, for illegal
+    // instructions the instruction bits and for breakpoints the pc of the ebreak.
+    pub fn tval(&self) -> u32 {
+        match self {
+            Exception::InstructionAddressMisaligned(tval)
+            | Exception::InstructionAccessFault(tval)
+            | Exception::IllegalInstruction(tval)
+            | Exception::Breakpoint(tval)
+            | Exception::LoadAddressMisaligned(tval)
+            | Exception::LoadAccessFault(tval)
+            | Exception::StoreAddressMisaligned(tval)
+            | Exception::StoreAccessFault(tval)
+            | Exception::InstructionPageFault(tval)
+            | Exception::LoadPageFault(tval)
+            | Exception::StorePageFault(tval) => *tval,
+            Exception::EnvironmentCall(_) => 0,
+        }
+    }
+}
+
+impl fmt::Debug for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Exception::InstructionAddressMisaligned(_) => "instruction address misaligned",
+            Exception::InstructionAccessFault(_) => "instruction access fault",
+            Exception::IllegalInstruction(_) => "illegal instruction",
+            Exception::Breakpoint(_) => "breakpoint",
+            Exception::LoadAddressMisaligned(_) => "load address misaligned",
+            Exception::LoadAccessFault(_) => "load access fault",
+            Exception::StoreAddressMisaligned(_) => "store address misaligned",
+            Exception::StoreAccessFault(_) => "store access fault",
+            Exception::EnvironmentCall(Mode::User) => "environment call from u-mode",
+            Exception::EnvironmentCall(Mode::Supervisor) => "environment call from s-mode",
+            Exception::EnvironmentCall(Mode::Machine) => "environment call from m-mode",
+            Exception::InstructionPageFault(_) => "instruction page fault",
+            Exception::LoadPageFault(_) => "load page fault",
+            Exception::StorePageFault(_) => "store page fault",
+        };
+        write!(f, "{name} (mtval: {:#010x})", self.tval())
+    }
+}
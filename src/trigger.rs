@@ -0,0 +1,172 @@
+// Sdtrig debug triggers, see riscv-debug spec chapter 5. Only address/data match triggers
+// (mcontrol) are implemented and they always raise a breakpoint exception, there is no
+// external debugger to enter debug mode for.
+pub const TSELECT: u16 = 0x7a0;
+pub const TDATA1: u16 = 0x7a1;
+pub const TDATA2: u16 = 0x7a2;
+pub const TDATA3: u16 = 0x7a3;
+pub const TINFO: u16 = 0x7a4;
+
+const NUM_TRIGGERS: usize = 4;
+
+// the type field in the top four bits of tdata1
+const TYPE_MCONTROL: u32 = 2;
+
+// mcontrol fields, the remaining ones (dmode, select, timing, size, action, chain) are
+// hardwired to zero: only address matches firing before the access with a breakpoint
+const MCONTROL_HIT: u32 = 1 << 20;
+const MCONTROL_MATCH: u32 = 0xf << 7;
+const MCONTROL_M: u32 = 1 << 6;
+const MCONTROL_EXECUTE: u32 = 1 << 2;
+const MCONTROL_STORE: u32 = 1 << 1;
+const MCONTROL_LOAD: u32 = 1 << 0;
+
+const MATCH_EQUAL: u32 = 0;
+const MATCH_GREATER_EQUAL: u32 = 2;
+const MATCH_LESS: u32 = 3;
+
+// the kind of access a trigger is checked against
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Execute,
+    Load,
+    Store,
+    // amos read and write memory
+    LoadStore,
+}
+
+impl Access {
+    fn mask(self) -> u32 {
+        match self {
+            Access::Execute => MCONTROL_EXECUTE,
+            Access::Load => MCONTROL_LOAD,
+            Access::Store => MCONTROL_STORE,
+            Access::LoadStore => MCONTROL_LOAD | MCONTROL_STORE,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Trigger {
+    // tdata1 without the type field
+    control: u32,
+    // tdata2, the address to compare against
+    address: u32,
+}
+
+impl Trigger {
+    fn matches(&self, access: Access, address: u32) -> bool {
+        // there is only m-mode, so triggers without the m bit never fire
+        if self.control & MCONTROL_M == 0 || self.control & access.mask() == 0 {
+            return false;
+        }
+        match (self.control & MCONTROL_MATCH) >> 7 {
+            MATCH_GREATER_EQUAL => address >= self.address,
+            MATCH_LESS => address < self.address,
+            _ => address == self.address,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Triggers {
+    select: usize,
+    triggers: [Trigger; NUM_TRIGGERS],
+}
+
+impl Triggers {
+    pub fn new() -> Self {
+        Triggers {
+            select: 0,
+            triggers: [Trigger {
+                control: 0,
+                address: 0,
+            }; NUM_TRIGGERS],
+        }
+    }
+
+    pub fn is_csr(csr: u16) -> bool {
+        (TSELECT..=TINFO).contains(&csr)
+    }
+
+    pub fn read_csr(&self, csr: u16) -> Option<u32> {
+        let trigger = &self.triggers[self.select];
+        let value = match csr {
+            TSELECT => self.select as u32,
+            TDATA1 => TYPE_MCONTROL << 28 | trigger.control,
+            TDATA2 => trigger.address,
+            TDATA3 => 0,
+            // only mcontrol triggers are supported
+            TINFO => 1 << TYPE_MCONTROL,
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    pub fn write_csr(&mut self, csr: u16, value: u32) -> Option<()> {
+        let trigger = &mut self.triggers[self.select];
+        match csr {
+            // selecting a trigger that doesn't exist keeps the current one, as debuggers
+            // probe the number of triggers like this
+            TSELECT if (value as usize) < NUM_TRIGGERS => self.select = value as usize,
+            TSELECT => (),
+            TDATA1 => {
+                let mut control = value
+                    & (MCONTROL_HIT
+                        | MCONTROL_MATCH
+                        | MCONTROL_M
+                        | MCONTROL_EXECUTE
+                        | MCONTROL_STORE
+                        | MCONTROL_LOAD);
+                if !matches!(
+                    (control & MCONTROL_MATCH) >> 7,
+                    MATCH_EQUAL | MATCH_GREATER_EQUAL | MATCH_LESS
+                ) {
+                    control &= !MCONTROL_MATCH;
+                }
+                trigger.control = control;
+            }
+            TDATA2 => trigger.address = value,
+            TDATA3 | TINFO => (),
+            _ => return None,
+        }
+        Some(())
+    }
+
+    // Returns whether any trigger fires on the access and marks the ones that did as hit.
+    pub fn check(&mut self, access: Access, address: u32) -> bool {
+        let mut fired = false;
+        for trigger in self.triggers.iter_mut() {
+            if trigger.matches(access, address) {
+                trigger.control |= MCONTROL_HIT;
+                fired = true;
+            }
+        }
+        fired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn address_match() {
+        let mut triggers = Triggers::new();
+        triggers.write_csr(TSELECT, 1).unwrap();
+        triggers.write_csr(TDATA2, 0x100).unwrap();
+        triggers
+            .write_csr(TDATA1, MCONTROL_M | MCONTROL_STORE | MATCH_LESS << 7)
+            .unwrap();
+        triggers.write_csr(TSELECT, NUM_TRIGGERS as u32).unwrap();
+        assert_eq!(triggers.read_csr(TSELECT), Some(1));
+
+        assert!(!triggers.check(Access::Load, 0x80));
+        assert!(!triggers.check(Access::Store, 0x100));
+        assert!(triggers.check(Access::LoadStore, 0x80));
+        assert_eq!(
+            triggers.read_csr(TDATA1),
+            Some(TYPE_MCONTROL << 28 | MCONTROL_HIT | MCONTROL_M | MCONTROL_STORE | 3 << 7)
+        );
+    }
+}
@@ -0,0 +1,325 @@
+// Terminal front end for `ruscv tui`: panes with the disassembly around the pc, the registers
+// with the ones changed by the last step or run highlighted, a memory viewer, the program's
+// console output and statistics. Keys:
+//   s          executes one instruction       c, r      runs until a breakpoint or the end
+//   p          pauses the running program     b         toggles a breakpoint at the pc
+//   B          adds a breakpoint at a location like `main if a0 == 5 hit 3`
+//   g          shows the memory at an expression, up/down and pgup/pgdn scroll it
+//   i          types into the uart until esc  R         resets the machine
+//   q          quits
+// Everything the program prints ends up in the console pane, input only reaches it through the
+// uart.
+use crate::cpu::Cpu;
+use crate::error::Error;
+use crate::frontend::Frontend;
+use crate::regs::Reg;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::time::Duration;
+
+// the screen is redrawn and keys are read this often while running
+const FRAME: Duration = Duration::from_millis(50);
+const HELP: &str =
+    "s step  c run  p pause  b breakpoint  B break at  g memory  i type  R reset  q quit";
+
+// what the status line reads from the user
+#[derive(Clone, Copy, PartialEq)]
+enum Prompt {
+    Break,
+    Memory,
+}
+
+struct Tui {
+    frontend: Frontend,
+    typing: bool,
+    prompt: Option<(Prompt, String)>,
+    quit: bool,
+}
+
+// Shows the loaded program in the terminal until the user quits, returns the program's exit code
+// or 0 if it didn't exit. The run is finished, calling the exit hooks, after the terminal is
+// restored.
+pub fn run(cpu: &mut Cpu) -> Result<u8, Error> {
+    let mut tui = Tui::new(cpu);
+    let mut terminal = ratatui::init();
+    let result = tui.event_loop(cpu, &mut terminal);
+    ratatui::restore();
+    result?;
+    tui.frontend.end(cpu)
+}
+
+impl Tui {
+    fn new(cpu: &mut Cpu) -> Self {
+        Tui {
+            frontend: Frontend::new(cpu),
+            typing: false,
+            prompt: None,
+            quit: false,
+        }
+    }
+
+    fn event_loop(&mut self, cpu: &mut Cpu, terminal: &mut DefaultTerminal) -> Result<(), Error> {
+        while !self.quit {
+            terminal
+                .draw(|frame| self.draw(cpu, frame))
+                .map_err(Error::Terminal)?;
+            if self.frontend.running {
+                self.frontend.run_for(cpu, FRAME);
+                while event::poll(Duration::ZERO).map_err(Error::Terminal)? {
+                    self.event(cpu, event::read().map_err(Error::Terminal)?);
+                }
+            } else {
+                self.event(cpu, event::read().map_err(Error::Terminal)?);
+            }
+        }
+        Ok(())
+    }
+
+    fn event(&mut self, cpu: &mut Cpu, event: Event) {
+        if let Event::Key(key) = event {
+            if key.kind == KeyEventKind::Press {
+                self.key(cpu, key);
+            }
+        }
+    }
+
+    fn key(&mut self, cpu: &mut Cpu, key: KeyEvent) {
+        if let Some((_, text)) = &mut self.prompt {
+            match key.code {
+                KeyCode::Char(c) => text.push(c),
+                KeyCode::Backspace => drop(text.pop()),
+                KeyCode::Enter => match self.prompt.take().unwrap() {
+                    (Prompt::Break, text) => self.frontend.add_breakpoint(cpu, &text),
+                    (Prompt::Memory, text) => self.frontend.show_memory(cpu, &text),
+                },
+                KeyCode::Esc => self.prompt = None,
+                _ => (),
+            }
+            return;
+        }
+        if self.typing {
+            let mut bytes = [0; 4];
+            let bytes: &[u8] = match key.code {
+                KeyCode::Char(c) => c.encode_utf8(&mut bytes).as_bytes(),
+                KeyCode::Enter => b"\n",
+                KeyCode::Backspace => b"\x7f",
+                KeyCode::Tab => b"\t",
+                KeyCode::Esc => {
+                    self.typing = false;
+                    return;
+                }
+                _ => return,
+            };
+            self.frontend.type_bytes(bytes);
+            return;
+        }
+        match key.code {
+            KeyCode::Char('q') => self.quit = true,
+            KeyCode::Char('s') => self.frontend.step(cpu),
+            KeyCode::Char('c' | 'r') => self.frontend.run(cpu),
+            KeyCode::Char('p') => self.frontend.pause(cpu),
+            KeyCode::Char('b') => self.frontend.toggle_breakpoint(cpu, cpu.pc.get()),
+            KeyCode::Char('B') => self.prompt = Some((Prompt::Break, String::new())),
+            KeyCode::Char('g') => self.prompt = Some((Prompt::Memory, String::new())),
+            KeyCode::Char('i') if !self.frontend.has_uart() => {
+                self.frontend.message = "the machine has no uart".to_string()
+            }
+            KeyCode::Char('i') => self.typing = true,
+            KeyCode::Char('R') => self.frontend.reset(cpu),
+            KeyCode::Up => self.frontend.scroll_memory(-1),
+            KeyCode::Down => self.frontend.scroll_memory(1),
+            KeyCode::PageUp => self.frontend.scroll_memory(-16),
+            KeyCode::PageDown => self.frontend.scroll_memory(16),
+            _ => (),
+        }
+    }
+
+    fn draw(&mut self, cpu: &Cpu, frame: &mut Frame) {
+        let [top, middle, console, status] = Layout::vertical([
+            Constraint::Length(19),
+            Constraint::Min(6),
+            Constraint::Length(8),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [code, regs] =
+            Layout::horizontal([Constraint::Min(30), Constraint::Length(38)]).areas(top);
+        let [memory, stats] =
+            Layout::horizontal([Constraint::Min(30), Constraint::Length(38)]).areas(middle);
+        frame.render_widget(self.code(cpu, code), code);
+        frame.render_widget(self.registers(cpu), regs);
+        frame.render_widget(self.memory(cpu, memory), memory);
+        frame.render_widget(self.stats(cpu), stats);
+        frame.render_widget(self.console(console), console);
+        let message = &self.frontend.message;
+        let line = match &self.prompt {
+            Some((Prompt::Break, text)) => format!("break at: {text}_"),
+            Some((Prompt::Memory, text)) => format!("memory at: {text}_"),
+            None if self.typing => "typing into the uart, esc ends".to_string(),
+            None if message.is_empty() => HELP.to_string(),
+            None => format!("{message}  |  {HELP}"),
+        };
+        frame.render_widget(Paragraph::new(line), status);
+    }
+
+    fn code(&self, cpu: &Cpu, area: Rect) -> Paragraph<'static> {
+        let rows = area.height.saturating_sub(2) as u32;
+        let mut lines: Vec<Line> = self
+            .frontend
+            .code(cpu, rows)
+            .into_iter()
+            .map(|line| {
+                let marker = if line.breakpoint { "●" } else { " " };
+                let arrow = if line.current { "→" } else { " " };
+                let label = line
+                    .label
+                    .map_or(String::new(), |name| format!("  <{name}>"));
+                let text = format!("{marker}{arrow} {:08x}  {}{label}", line.address, line.text);
+                match line.current {
+                    true => Line::styled(
+                        text,
+                        Style::new().fg(Color::Yellow).add_modifier(Modifier::BOLD),
+                    ),
+                    false => Line::raw(text),
+                }
+            })
+            .collect();
+        if lines.is_empty() {
+            lines.push(Line::raw(format!("   {:08x}  (not in ram)", cpu.pc.get())));
+        }
+        Paragraph::new(lines).block(Block::bordered().title(" code "))
+    }
+
+    fn registers(&self, cpu: &Cpu) -> Paragraph<'static> {
+        let changed = Style::new().fg(Color::Yellow).add_modifier(Modifier::BOLD);
+        let mut lines = vec![Line::raw(format!("pc   {:#010x}", cpu.pc.get()))];
+        for row in 0..16 {
+            let spans: Vec<Span> = [row, row + 16]
+                .into_iter()
+                .map(|n| {
+                    let value = cpu.regs.read(n);
+                    let text = format!("{:<4} {value:#010x}   ", Reg::from_index(n).name());
+                    match self.frontend.changed(cpu, n) {
+                        true => Span::styled(text, changed),
+                        false => Span::raw(text),
+                    }
+                })
+                .collect();
+            lines.push(Line::from(spans));
+        }
+        let title = format!(" registers, hart {} ", cpu.hart());
+        Paragraph::new(lines).block(Block::bordered().title(title))
+    }
+
+    fn memory(&self, cpu: &Cpu, area: Rect) -> Paragraph<'static> {
+        let rows = area.height.saturating_sub(2) as u32;
+        let lines: Vec<Line> = self
+            .frontend
+            .memory_lines(cpu, rows)
+            .into_iter()
+            .map(Line::raw)
+            .collect();
+        Paragraph::new(lines).block(Block::bordered().title(" memory "))
+    }
+
+    fn stats(&mut self, cpu: &Cpu) -> Paragraph<'static> {
+        let lines = vec![
+            Line::raw(format!("state         {}", self.frontend.state())),
+            Line::raw(format!("harts         {}", cpu.harts())),
+            Line::raw(format!("cycles        {}", cpu.cycles())),
+            Line::raw(format!("instructions  {}", cpu.retired())),
+            Line::raw(format!(
+                "speed         {:.0} inst/s",
+                self.frontend.speed(cpu)
+            )),
+        ];
+        Paragraph::new(lines).block(Block::bordered().title(" stats "))
+    }
+
+    fn console(&self, area: Rect) -> Paragraph<'static> {
+        let text = self.frontend.console();
+        let rows = area.height.saturating_sub(2) as usize;
+        let lines: Vec<&str> = text.lines().collect();
+        let shown: Vec<Line> = lines[lines.len().saturating_sub(rows)..]
+            .iter()
+            .map(|line| Line::raw(line.to_string()))
+            .collect();
+        let title = match self.typing {
+            true => " console (typing) ",
+            false => " console ",
+        };
+        Paragraph::new(shown).block(Block::bordered().title(title))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    fn press(tui: &mut Tui, cpu: &mut Cpu, keys: &str) {
+        for c in keys.chars() {
+            tui.key(cpu, KeyEvent::from(KeyCode::Char(c)));
+        }
+    }
+
+    fn screen(tui: &mut Tui, cpu: &Cpu) -> String {
+        let mut terminal = Terminal::new(TestBackend::new(110, 40)).unwrap();
+        terminal.draw(|frame| tui.draw(cpu, frame)).unwrap();
+        let buffer = terminal.backend().buffer();
+        let cells: Vec<&str> = buffer.content().iter().map(|cell| cell.symbol()).collect();
+        cells
+            .chunks(buffer.area.width as usize)
+            .map(|row| row.concat())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn keys_and_panes() {
+        let source = "
+            li t0, 0x10000000
+            li t1, 111
+            sb t1, 0(t0)
+            li t1, 107
+            sb t1, 0(t0)
+        done:
+            li a0, 0
+            li a7, 93
+            ecall";
+        let assembly = crate::asm::assemble(source, 0).unwrap();
+        let mut cpu = Cpu::new(false);
+        cpu.mem = crate::machine::Machine::FreertosDemo.memory(crate::devices::RtcClock::Frozen(0));
+        cpu.set_reset_pc(crate::machine::Machine::FreertosDemo.reset_pc());
+        cpu.load(assembly.bytes);
+        let mut tui = Tui::new(&mut cpu);
+
+        press(&mut tui, &mut cpu, "s");
+        let shown = screen(&mut tui, &cpu);
+        assert!(shown.contains("→ 80000004  addi t1, zero, 111"), "{shown}");
+        assert!(shown.contains("t0   0x10000000"));
+
+        press(&mut tui, &mut cpu, "B0x80000014");
+        tui.key(&mut cpu, KeyEvent::from(KeyCode::Enter));
+        press(&mut tui, &mut cpu, "c");
+        tui.frontend.run_for(&mut cpu, Duration::from_secs(10));
+        let shown = screen(&mut tui, &cpu);
+        assert!(shown.contains("●→ 80000014"), "{shown}");
+        assert!(shown.contains("│ok"), "{shown}");
+
+        press(&mut tui, &mut cpu, "gpc - 4");
+        tui.key(&mut cpu, KeyEvent::from(KeyCode::Enter));
+        assert!(screen(&mut tui, &cpu).contains("80000010  23 80 62 00"));
+        press(&mut tui, &mut cpu, "c");
+        tui.frontend.run_for(&mut cpu, Duration::from_secs(10));
+        assert!(screen(&mut tui, &cpu).contains("exited with 0"));
+        press(&mut tui, &mut cpu, "q");
+        assert!(tui.quit);
+    }
+}
@@ -0,0 +1,231 @@
+// Micro-op IR of the integer instructions. `Inst::lower` turns an instruction into a short
+// straight-line sequence over a few temporaries: registers are read into temporaries, combined by
+// alu ops, loaded from or stored to memory and written back, and a branch or jump may end it. The
+// interpreter executes the lowering, and the watchpoints, triggers and performance counters
+// derive what an instruction reads, accesses and counts as from it, so that they can't drift
+// apart from what executes. A jit or timing model would consume the same sequences.
+// Instructions with more involved semantics (csrs, atomics, aes, vectors, system instructions)
+// aren't lowered and execute directly.
+use crate::cpu::Cpu;
+use crate::csr::HpmEvent;
+use crate::inst::{BInst, RInst};
+use crate::memory::Size;
+use crate::trap::Exception;
+use crate::trigger::Access;
+
+use std::ops::Deref;
+
+// temporaries of a lowering
+pub const TEMPS: usize = 4;
+// longest lowering, jalr's
+const MAX_UOPS: usize = 8;
+
+// t, a, b and the other temporaries index the lowering's temporaries
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Uop {
+    // t = x[reg]
+    ReadReg {
+        t: usize,
+        reg: usize,
+    },
+    // t = value, immediates and addresses computed from the instruction's pc
+    Const {
+        t: usize,
+        value: u32,
+    },
+    // t = op(a, b), unary ops ignore b
+    Alu {
+        op: RInst,
+        t: usize,
+        a: usize,
+        b: usize,
+    },
+    // t = the value of the size at the address in temporary address
+    Load {
+        t: usize,
+        address: usize,
+        size: Size,
+        unsigned: bool,
+    },
+    Store {
+        address: usize,
+        value: usize,
+        size: Size,
+    },
+    // x[reg] = t, writes to x0 are discarded
+    WriteReg {
+        reg: usize,
+        t: usize,
+    },
+    // jumps to the target if there is no condition or it holds for a and b, targets that aren't
+    // 4-byte aligned raise an exception before anything after the branch happens
+    Branch {
+        cond: Option<BInst>,
+        a: usize,
+        b: usize,
+        target: usize,
+    },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Uops {
+    uops: [Uop; MAX_UOPS],
+    len: usize,
+}
+
+impl Uops {
+    pub fn new() -> Self {
+        Uops {
+            uops: [Uop::Const { t: 0, value: 0 }; MAX_UOPS],
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, uop: Uop) {
+        self.uops[self.len] = uop;
+        self.len += 1;
+    }
+
+    // Runs the micro-ops on the cpu. Everything before a trapping load, store or jump has
+    // happened, the lowerings don't write registers before those.
+    pub fn execute(&self, cpu: &mut Cpu) -> Result<(), Exception> {
+        let mut temps = [0; TEMPS];
+        for &uop in self.iter() {
+            match uop {
+                Uop::ReadReg { t, reg } => temps[t] = cpu.regs.read(reg),
+                Uop::Const { t, value } => temps[t] = value,
+                Uop::Alu { op, t, a, b } => temps[t] = op.op()(temps[a], temps[b]),
+                Uop::Load {
+                    t,
+                    address,
+                    size,
+                    unsigned,
+                } => temps[t] = cpu.load_virtual(size, temps[address], unsigned)?,
+                Uop::Store {
+                    address,
+                    value,
+                    size,
+                } => cpu.store_virtual(size, temps[address], temps[value])?,
+                Uop::WriteReg { reg, t } => cpu.regs.write(reg, temps[t]),
+                Uop::Branch { cond, a, b, target } => {
+                    if cond.is_none_or(|cond| cond.taken(temps[a], temps[b])) {
+                        jump(cpu, temps[target])?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // the registers read, in order
+    pub fn sources(&self) -> impl Iterator<Item = usize> + '_ {
+        self.iter().filter_map(|uop| match *uop {
+            Uop::ReadReg { reg, .. } => Some(reg),
+            _ => None,
+        })
+    }
+
+    // The memory access and its size in bytes, with the address computed from the registers as
+    // they are before the micro-ops execute.
+    pub fn access(&self, cpu: &Cpu) -> Option<(Access, u32, u32)> {
+        let mut temps = [0; TEMPS];
+        for &uop in self.iter() {
+            match uop {
+                Uop::ReadReg { t, reg } => temps[t] = cpu.regs.read(reg),
+                Uop::Const { t, value } => temps[t] = value,
+                Uop::Alu { op, t, a, b } => temps[t] = op.op()(temps[a], temps[b]),
+                Uop::Load { address, size, .. } => {
+                    return Some((Access::Load, temps[address], size as u32))
+                }
+                Uop::Store { address, size, .. } => {
+                    return Some((Access::Store, temps[address], size as u32))
+                }
+                Uop::WriteReg { .. } | Uop::Branch { .. } => (),
+            }
+        }
+        None
+    }
+
+    // the performance monitor event counted once the micro-ops retire
+    pub fn event(&self) -> Option<HpmEvent> {
+        self.iter().find_map(|uop| match uop {
+            Uop::Branch { cond: Some(_), .. } => Some(HpmEvent::Branch),
+            Uop::Load { .. } => Some(HpmEvent::Load),
+            Uop::Store { .. } => Some(HpmEvent::Store),
+            _ => None,
+        })
+    }
+}
+
+impl Deref for Uops {
+    type Target = [Uop];
+
+    fn deref(&self) -> &[Uop] {
+        &self.uops[..self.len]
+    }
+}
+
+// Sets the pc to the target of a taken branch or jump. There are no compressed instructions,
+// so targets that aren't 4-byte aligned raise an exception on the jump itself.
+fn jump(cpu: &mut Cpu, target: u32) -> Result<(), Exception> {
+    if !target.is_multiple_of(4) {
+        return Err(Exception::InstructionAddressMisaligned(target));
+    }
+    cpu.pc.set(target);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decode;
+
+    #[test]
+    fn lowerings() {
+        // jalr ra, 8(a0)
+        let uops = decode(0x008500e7).unwrap().lower(0x100).unwrap();
+        assert_eq!(
+            *uops,
+            [
+                Uop::ReadReg { t: 0, reg: 10 },
+                Uop::Const { t: 1, value: 8 },
+                Uop::Alu {
+                    op: RInst::ADD,
+                    t: 0,
+                    a: 0,
+                    b: 1
+                },
+                Uop::Const { t: 1, value: !1 },
+                Uop::Alu {
+                    op: RInst::AND,
+                    t: 0,
+                    a: 0,
+                    b: 1
+                },
+                Uop::Const { t: 1, value: 0x104 },
+                Uop::Branch {
+                    cond: None,
+                    a: 0,
+                    b: 0,
+                    target: 0
+                },
+                Uop::WriteReg { reg: 1, t: 1 },
+            ]
+        );
+
+        let mut cpu = Cpu::new(false);
+        cpu.regs.write(2, 0x40);
+        // sh a1, -2(sp)
+        let store = decode(0xfeb11f23).unwrap().lower(0).unwrap();
+        assert_eq!(store.sources().collect::<Vec<_>>(), [2, 11]);
+        assert_eq!(store.access(&cpu), Some((Access::Store, 0x3e, 2)));
+        assert_eq!(store.event(), Some(HpmEvent::Store));
+        // bne a0, a1, -8 at 0x20
+        let branch = decode(0xfeb51ce3).unwrap().lower(0x20).unwrap();
+        assert_eq!(branch[2], Uop::Const { t: 2, value: 0x18 });
+        assert_eq!(branch.access(&cpu), None);
+        assert_eq!(branch.event(), Some(HpmEvent::Branch));
+        // csrrw zero, mscratch, a0
+        assert!(decode(0x34051073).unwrap().lower(0).is_none());
+    }
+}
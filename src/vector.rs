@@ -0,0 +1,687 @@
+// Subset of the vector extension (RVV 1.0) for the embedded Zve32x profile: integer elements of
+// up to 32 bits, unit-stride and strided loads/stores and the common integer arithmetic.
+// Tail and inactive elements are always left undisturbed, which is allowed for both policies.
+use crate::cpu::Cpu;
+use crate::get_bits;
+use crate::memory::Size;
+use crate::trap::Exception;
+
+pub const VSTART: u16 = 0x008;
+pub const VXSAT: u16 = 0x009;
+pub const VXRM: u16 = 0x00a;
+pub const VCSR: u16 = 0x00f;
+pub const VL: u16 = 0xc20;
+pub const VTYPE: u16 = 0xc21;
+pub const VLENB: u16 = 0xc22;
+
+pub const DEFAULT_VLEN: u32 = 128;
+
+const VTYPE_VILL: u32 = 1 << 31;
+// widest element in bits
+const ELEN: u32 = 32;
+
+#[derive(Clone)]
+pub struct VectorUnit {
+    // register i occupies the bytes i * vlenb..(i + 1) * vlenb
+    regs: Vec<u8>,
+    vlenb: u32,
+    vl: u32,
+    vtype: u32,
+    vstart: u32,
+    vxrm: u32,
+    vxsat: u32,
+}
+
+impl VectorUnit {
+    pub fn vlen(&self) -> u32 {
+        self.vlenb * 8
+    }
+
+    // vlen is the register width in bits, a power of two of at least 32
+    pub fn new(vlen: u32) -> Self {
+        let vlenb = vlen / 8;
+        VectorUnit {
+            regs: vec![0; 32 * vlenb as usize],
+            vlenb,
+            vl: 0,
+            // the vector unit is unconfigured until the first vsetvl
+            vtype: VTYPE_VILL,
+            vstart: 0,
+            vxrm: 0,
+            vxsat: 0,
+        }
+    }
+
+    pub fn is_csr(csr: u16) -> bool {
+        matches!(csr, VSTART | VXSAT | VXRM | VCSR | VL | VTYPE | VLENB)
+    }
+
+    pub fn read_csr(&self, csr: u16) -> Option<u32> {
+        let value = match csr {
+            VSTART => self.vstart,
+            VXSAT => self.vxsat,
+            VXRM => self.vxrm,
+            VCSR => (self.vxrm << 1) | self.vxsat,
+            VL => self.vl,
+            VTYPE => self.vtype,
+            VLENB => self.vlenb,
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    // vl, vtype and vlenb can only be changed by vsetvl
+    pub fn write_csr(&mut self, csr: u16, value: u32) -> Option<()> {
+        match csr {
+            VSTART => self.vstart = value,
+            VXSAT => self.vxsat = value & 1,
+            VXRM => self.vxrm = value & 0b11,
+            VCSR => {
+                self.vxsat = value & 1;
+                self.vxrm = (value >> 1) & 0b11;
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+
+    // Returns the maximum vector length for the given vtype or None if the vtype isn't supported.
+    // LMUL is handled in eighths, so that fractional group sizes are integers as well.
+    fn vlmax(&self, vtype: u32) -> Option<u32> {
+        let sew = 8 << get_bits!(vtype, 3, 5, u32);
+        let lmul8 = lmul_eighths(vtype)?;
+        let reserved = vtype & !0xff != 0;
+        // fractional groups must at least hold one element of the widest type
+        if reserved || sew > ELEN || lmul8 * ELEN < sew * 8 {
+            return None;
+        }
+        Some(self.vlenb * 8 * lmul8 / (sew * 8))
+    }
+
+    // element width in bytes
+    fn sew(&self) -> u32 {
+        1 << get_bits!(self.vtype, 3, 5, u32)
+    }
+
+    fn lmul8(&self) -> u32 {
+        lmul_eighths(self.vtype).unwrap_or(8)
+    }
+
+    // Register groups occupy lmul consecutive registers starting at a multiple of lmul.
+    fn valid_group(reg: usize, lmul8: u32) -> bool {
+        let group = lmul8.div_ceil(8) as usize;
+        reg.is_multiple_of(group) && reg + group <= 32
+    }
+
+    fn elem(&self, reg: usize, index: u32, width: u32) -> u32 {
+        let start = reg * self.vlenb as usize + (index * width) as usize;
+        let mut bytes = [0; 4];
+        bytes[..width as usize].copy_from_slice(&self.regs[start..start + width as usize]);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn set_elem(&mut self, reg: usize, index: u32, width: u32, value: u32) {
+        let start = reg * self.vlenb as usize + (index * width) as usize;
+        self.regs[start..start + width as usize]
+            .copy_from_slice(&value.to_le_bytes()[..width as usize]);
+    }
+
+    fn mask_bit(&self, reg: usize, index: u32) -> bool {
+        let byte = self.regs[reg * self.vlenb as usize + index as usize / 8];
+        byte >> (index % 8) & 1 != 0
+    }
+
+    fn set_mask_bit(&mut self, reg: usize, index: u32, value: bool) {
+        let byte = &mut self.regs[reg * self.vlenb as usize + index as usize / 8];
+        *byte = (*byte & !(1 << (index % 8))) | ((value as u8) << (index % 8));
+    }
+
+    // elements are active if the instruction is unmasked or their bit in v0 is set
+    fn active(&self, vm: bool, index: u32) -> bool {
+        vm || self.mask_bit(0, index)
+    }
+}
+
+fn lmul_eighths(vtype: u32) -> Option<u32> {
+    match get_bits!(vtype, 0, 2, u32) {
+        vlmul @ 0..=3 => Some(8 << vlmul),
+        5 => Some(1),
+        6 => Some(2),
+        7 => Some(4),
+        _ => None,
+    }
+}
+
+fn sign_extend(value: u32, bits: u32) -> i64 {
+    ((value << (32 - bits)) as i32 >> (32 - bits)) as i64
+}
+
+pub enum Avl {
+    // rs1, x0 either selects the maximum vector length or keeps the current one
+    Reg(usize),
+    Imm(u32),
+}
+
+pub enum Vtype {
+    Imm(u32),
+    Reg(usize),
+}
+
+#[derive(Clone, Copy)]
+pub enum Operand {
+    Vector(usize),
+    Scalar(usize),
+    // sign-extended 5-bit immediate
+    Imm(u32),
+}
+
+pub enum VOp {
+    Add,
+    Sub,
+    Rsub,
+    Minu,
+    Min,
+    Maxu,
+    Max,
+    And,
+    Or,
+    Xor,
+    Sll,
+    Srl,
+    Sra,
+    Mul,
+    Mulh,
+    Mulhu,
+    Mulhsu,
+    Divu,
+    Div,
+    Remu,
+    Rem,
+}
+impl VOp {
+    // a is the element of vs2, b the second operand, both are zero-extended to bits
+    fn apply(&self, a: u32, b: u32, bits: u32) -> u32 {
+        // wide enough for the full product of two 32-bit operands
+        let (sa, sb) = (sign_extend(a, bits) as i128, sign_extend(b, bits) as i128);
+        let (ua, ub) = (a as i128, b as i128);
+        let shamt = b & (bits - 1);
+        let result = match self {
+            VOp::Add => ua + ub,
+            VOp::Sub => ua - ub,
+            VOp::Rsub => ub - ua,
+            VOp::Minu => ua.min(ub),
+            VOp::Min => sa.min(sb),
+            VOp::Maxu => ua.max(ub),
+            VOp::Max => sa.max(sb),
+            VOp::And => ua & ub,
+            VOp::Or => ua | ub,
+            VOp::Xor => ua ^ ub,
+            VOp::Sll => ua << shamt,
+            VOp::Srl => ua >> shamt,
+            VOp::Sra => sa >> shamt,
+            VOp::Mul => ua * ub,
+            VOp::Mulh => (sa * sb) >> bits,
+            VOp::Mulhu => (ua * ub) >> bits,
+            VOp::Mulhsu => (sa * ub) >> bits,
+            // division by zero gives all ones and the dividend as remainder like the scalar ones
+            VOp::Divu | VOp::Div if b == 0 => -1,
+            VOp::Remu | VOp::Rem if b == 0 => ua,
+            VOp::Divu => ua / ub,
+            VOp::Div => sa / sb,
+            VOp::Remu => ua % ub,
+            VOp::Rem => sa % sb,
+        };
+        (result as u64 & (u64::MAX >> (64 - bits))) as u32
+    }
+}
+
+pub enum VCmp {
+    Eq,
+    Ne,
+    Ltu,
+    Lt,
+    Leu,
+    Le,
+    Gtu,
+    Gt,
+}
+impl VCmp {
+    fn apply(&self, a: u32, b: u32, bits: u32) -> bool {
+        let (sa, sb) = (sign_extend(a, bits), sign_extend(b, bits));
+        match self {
+            VCmp::Eq => a == b,
+            VCmp::Ne => a != b,
+            VCmp::Ltu => a < b,
+            VCmp::Lt => sa < sb,
+            VCmp::Leu => a <= b,
+            VCmp::Le => sa <= sb,
+            VCmp::Gtu => a > b,
+            VCmp::Gt => sa > sb,
+        }
+    }
+}
+
+pub enum VInst {
+    // vsetvli, vsetivli and vsetvl
+    SetVl {
+        rd: usize,
+        avl: Avl,
+        vtype: Vtype,
+    },
+    // unit-stride if there is no stride register, eew is the element width in bytes
+    Load {
+        vd: usize,
+        rs1: usize,
+        stride: Option<usize>,
+        eew: u32,
+        vm: bool,
+    },
+    Store {
+        vs3: usize,
+        rs1: usize,
+        stride: Option<usize>,
+        eew: u32,
+        vm: bool,
+    },
+    Op {
+        op: VOp,
+        vd: usize,
+        vs2: usize,
+        src: Operand,
+        vm: bool,
+    },
+    // writes the results as mask bits into vd
+    Cmp {
+        cmp: VCmp,
+        vd: usize,
+        vs2: usize,
+        src: Operand,
+        vm: bool,
+    },
+    // vmerge if masked, otherwise vmv.v.*
+    Merge {
+        vd: usize,
+        vs2: usize,
+        src: Operand,
+        vm: bool,
+    },
+    RedSum {
+        vd: usize,
+        vs2: usize,
+        vs1: usize,
+        vm: bool,
+    },
+    // vmv.x.s
+    MvXS {
+        rd: usize,
+        vs2: usize,
+    },
+    // vmv.s.x
+    MvSX {
+        vd: usize,
+        rs1: usize,
+    },
+}
+
+impl VInst {
+    // Decodes the OP-V opcode and the vector forms of LOAD-FP/STORE-FP,
+    // returns None for encodings outside of the implemented subset.
+    pub fn decode(raw_inst: u32) -> Option<VInst> {
+        let opcode = get_bits!(raw_inst, 0, 6);
+        let vd = get_bits!(raw_inst, 7, 11);
+        let funct3 = get_bits!(raw_inst, 12, 14);
+        let rs1 = get_bits!(raw_inst, 15, 19);
+        let vs2 = get_bits!(raw_inst, 20, 24);
+        let vm = get_bits!(raw_inst, 25, 25) == 1;
+        let funct6 = get_bits!(raw_inst, 26, 31);
+
+        if opcode == 0b0000111 || opcode == 0b0100111 {
+            // scalar floating-point loads/stores share the opcode but use other widths
+            let eew = match funct3 {
+                0 => 1,
+                5 => 2,
+                6 => 4,
+                _ => return None,
+            };
+            // only single-field unit-stride (without the whole register or mask variants)
+            // and strided accesses are supported
+            let stride = match (get_bits!(raw_inst, 26, 31), vs2) {
+                (0b000000, 0) => None,
+                (0b000010, rs2) => Some(rs2),
+                _ => return None,
+            };
+            return Some(if opcode == 0b0000111 {
+                VInst::Load {
+                    vd,
+                    rs1,
+                    stride,
+                    eew,
+                    vm,
+                }
+            } else {
+                VInst::Store {
+                    vs3: vd,
+                    rs1,
+                    stride,
+                    eew,
+                    vm,
+                }
+            });
+        }
+        if opcode != 0b1010111 {
+            return None;
+        }
+
+        let src = match funct3 {
+            0 | 2 => Operand::Vector(rs1),
+            4 | 6 => Operand::Scalar(rs1),
+            3 => Operand::Imm(((rs1 as i32) << 27 >> 27) as u32),
+            7 => {
+                let (avl, vtype) = match get_bits!(raw_inst, 30, 31) {
+                    0b00 | 0b01 => (Avl::Reg(rs1), Vtype::Imm(get_bits!(raw_inst, 20, 30, u32))),
+                    0b11 => (
+                        Avl::Imm(rs1 as u32),
+                        Vtype::Imm(get_bits!(raw_inst, 20, 29, u32)),
+                    ),
+                    _ if get_bits!(raw_inst, 25, 29) == 0 => (Avl::Reg(rs1), Vtype::Reg(vs2)),
+                    _ => return None,
+                };
+                return Some(VInst::SetVl { rd: vd, avl, vtype });
+            }
+            _ => return None,
+        };
+        let op = |op| {
+            Some(VInst::Op {
+                op,
+                vd,
+                vs2,
+                src,
+                vm,
+            })
+        };
+        let cmp = |cmp| {
+            Some(VInst::Cmp {
+                cmp,
+                vd,
+                vs2,
+                src,
+                vm,
+            })
+        };
+
+        match (funct3, funct6) {
+            // integer instructions (OPIVV, OPIVI, OPIVX)
+            (0 | 3 | 4, 0b000000) => op(VOp::Add),
+            (0 | 4, 0b000010) => op(VOp::Sub),
+            (3 | 4, 0b000011) => op(VOp::Rsub),
+            (0 | 4, 0b000100) => op(VOp::Minu),
+            (0 | 4, 0b000101) => op(VOp::Min),
+            (0 | 4, 0b000110) => op(VOp::Maxu),
+            (0 | 4, 0b000111) => op(VOp::Max),
+            (0 | 3 | 4, 0b001001) => op(VOp::And),
+            (0 | 3 | 4, 0b001010) => op(VOp::Or),
+            (0 | 3 | 4, 0b001011) => op(VOp::Xor),
+            (0 | 3 | 4, 0b100101) => op(VOp::Sll),
+            (0 | 3 | 4, 0b101000) => op(VOp::Srl),
+            (0 | 3 | 4, 0b101001) => op(VOp::Sra),
+            // vmv.v.* has no source vector
+            (0 | 3 | 4, 0b010111) if !vm || vs2 == 0 => Some(VInst::Merge { vd, vs2, src, vm }),
+            (0 | 3 | 4, 0b011000) => cmp(VCmp::Eq),
+            (0 | 3 | 4, 0b011001) => cmp(VCmp::Ne),
+            (0 | 4, 0b011010) => cmp(VCmp::Ltu),
+            (0 | 4, 0b011011) => cmp(VCmp::Lt),
+            (0 | 3 | 4, 0b011100) => cmp(VCmp::Leu),
+            (0 | 3 | 4, 0b011101) => cmp(VCmp::Le),
+            (3 | 4, 0b011110) => cmp(VCmp::Gtu),
+            (3 | 4, 0b011111) => cmp(VCmp::Gt),
+            // multiply/divide instructions (OPMVV, OPMVX)
+            (2, 0b000000) => Some(VInst::RedSum {
+                vd,
+                vs2,
+                vs1: rs1,
+                vm,
+            }),
+            (2, 0b010000) if rs1 == 0 && vm => Some(VInst::MvXS { rd: vd, vs2 }),
+            (6, 0b010000) if vs2 == 0 && vm => Some(VInst::MvSX { vd, rs1 }),
+            (2 | 6, 0b100000) => op(VOp::Divu),
+            (2 | 6, 0b100001) => op(VOp::Div),
+            (2 | 6, 0b100010) => op(VOp::Remu),
+            (2 | 6, 0b100011) => op(VOp::Rem),
+            (2 | 6, 0b100100) => op(VOp::Mulhu),
+            (2 | 6, 0b100101) => op(VOp::Mul),
+            (2 | 6, 0b100110) => op(VOp::Mulhsu),
+            (2 | 6, 0b100111) => op(VOp::Mulh),
+            _ => None,
+        }
+    }
+
+    pub fn execute(self, cpu: &mut Cpu) -> Result<(), Exception> {
+        // the caller fills in the instruction bits
+        let illegal = Exception::IllegalInstruction(0);
+        if let VInst::SetVl { rd, avl, vtype } = self {
+            let vtype = match vtype {
+                Vtype::Imm(vtype) => vtype,
+                Vtype::Reg(rs2) => cpu.regs.read(rs2),
+            };
+            let v = &mut cpu.vector;
+            match v.vlmax(vtype) {
+                Some(vlmax) => {
+                    let avl = match avl {
+                        Avl::Imm(avl) => avl,
+                        Avl::Reg(rs1) if rs1 != 0 => cpu.regs.read(rs1),
+                        Avl::Reg(_) if rd != 0 => u32::MAX,
+                        Avl::Reg(_) => v.vl,
+                    };
+                    v.vl = avl.min(vlmax);
+                    v.vtype = vtype;
+                }
+                None => {
+                    v.vl = 0;
+                    v.vtype = VTYPE_VILL;
+                }
+            }
+            v.vstart = 0;
+            cpu.regs.write(rd, v.vl);
+            return Ok(());
+        }
+
+        let v = &cpu.vector;
+        if v.vtype & VTYPE_VILL != 0 {
+            return Err(illegal);
+        }
+        let (sew, lmul8, vl) = (v.sew(), v.lmul8(), v.vl);
+        let bits = sew * 8;
+        let operand = |cpu: &Cpu, src: &Operand, i| match *src {
+            Operand::Vector(vs1) => cpu.vector.elem(vs1, i, sew),
+            Operand::Scalar(rs1) => cpu.regs.read(rs1) & (u32::MAX >> (32 - bits)),
+            Operand::Imm(imm) => imm & (u32::MAX >> (32 - bits)),
+        };
+        let valid_src = |src: &Operand| match *src {
+            Operand::Vector(vs1) => VectorUnit::valid_group(vs1, lmul8),
+            _ => true,
+        };
+
+        match self {
+            VInst::SetVl { .. } => unreachable!("handled above"),
+            VInst::Load {
+                vd,
+                rs1,
+                stride,
+                eew,
+                vm,
+            }
+            | VInst::Store {
+                vs3: vd,
+                rs1,
+                stride,
+                eew,
+                vm,
+            } => {
+                let is_load = matches!(self, VInst::Load { .. });
+                // the effective group size scales with the ratio of element widths
+                let emul8 = lmul8 * eew / sew;
+                if !(1..=64).contains(&emul8) || !VectorUnit::valid_group(vd, emul8) {
+                    return Err(illegal);
+                }
+                if is_load && !vm && vd == 0 {
+                    return Err(illegal);
+                }
+                let base = cpu.regs.read(rs1);
+                let stride = stride.map_or(eew, |rs2| cpu.regs.read(rs2));
+                let size = match eew {
+                    1 => Size::Byte,
+                    2 => Size::HalfWord,
+                    _ => Size::Word,
+                };
+                for i in cpu.vector.vstart..vl {
+                    if !cpu.vector.active(vm, i) {
+                        continue;
+                    }
+                    let address = base.wrapping_add(i.wrapping_mul(stride));
+                    // faulting accesses are resumed at the failing element
+                    let result = if is_load {
+                        cpu.load_virtual(size, address, true)
+                            .map(|value| cpu.vector.set_elem(vd, i, eew, value))
+                    } else {
+                        let value = cpu.vector.elem(vd, i, eew);
+                        cpu.store_virtual(size, address, value)
+                    };
+                    if let Err(exception) = result {
+                        cpu.vector.vstart = i;
+                        return Err(exception);
+                    }
+                }
+            }
+            VInst::Op {
+                op,
+                vd,
+                vs2,
+                src,
+                vm,
+            } => {
+                let groups_valid = VectorUnit::valid_group(vd, lmul8)
+                    && VectorUnit::valid_group(vs2, lmul8)
+                    && valid_src(&src);
+                if !groups_valid || (!vm && vd == 0) {
+                    return Err(illegal);
+                }
+                for i in cpu.vector.vstart..vl {
+                    if cpu.vector.active(vm, i) {
+                        let a = cpu.vector.elem(vs2, i, sew);
+                        let b = operand(cpu, &src, i);
+                        cpu.vector.set_elem(vd, i, sew, op.apply(a, b, bits));
+                    }
+                }
+            }
+            VInst::Cmp {
+                cmp,
+                vd,
+                vs2,
+                src,
+                vm,
+            } => {
+                if !VectorUnit::valid_group(vs2, lmul8) || !valid_src(&src) {
+                    return Err(illegal);
+                }
+                // all sources are read before the mask is written, since vd may overlap them
+                let results: Vec<_> = (cpu.vector.vstart..vl)
+                    .filter(|&i| cpu.vector.active(vm, i))
+                    .map(|i| {
+                        let a = cpu.vector.elem(vs2, i, sew);
+                        (i, cmp.apply(a, operand(cpu, &src, i), bits))
+                    })
+                    .collect();
+                for (i, result) in results {
+                    cpu.vector.set_mask_bit(vd, i, result);
+                }
+            }
+            VInst::Merge { vd, vs2, src, vm } => {
+                let groups_valid = VectorUnit::valid_group(vd, lmul8)
+                    && VectorUnit::valid_group(vs2, lmul8)
+                    && valid_src(&src);
+                if !groups_valid || (!vm && vd == 0) {
+                    return Err(illegal);
+                }
+                for i in cpu.vector.vstart..vl {
+                    let value = if cpu.vector.active(vm, i) {
+                        operand(cpu, &src, i)
+                    } else {
+                        cpu.vector.elem(vs2, i, sew)
+                    };
+                    cpu.vector.set_elem(vd, i, sew, value);
+                }
+            }
+            VInst::RedSum { vd, vs2, vs1, vm } => {
+                if !VectorUnit::valid_group(vs2, lmul8) || cpu.vector.vstart != 0 {
+                    return Err(illegal);
+                }
+                if vl > 0 {
+                    let sum = (0..vl)
+                        .filter(|&i| cpu.vector.active(vm, i))
+                        .fold(cpu.vector.elem(vs1, 0, sew), |sum, i| {
+                            VOp::Add.apply(sum, cpu.vector.elem(vs2, i, sew), bits)
+                        });
+                    cpu.vector.set_elem(vd, 0, sew, sum);
+                }
+            }
+            VInst::MvXS { rd, vs2 } => {
+                let value = sign_extend(cpu.vector.elem(vs2, 0, sew), bits);
+                cpu.regs.write(rd, value as u32);
+            }
+            VInst::MvSX { vd, rs1 } => {
+                if cpu.vector.vstart < vl {
+                    cpu.vector.set_elem(vd, 0, sew, cpu.regs.read(rs1));
+                }
+            }
+        }
+        cpu.vector.vstart = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // vtype with the given log2 of sew in bytes and vlmul encoding
+    fn vtype(vsew: u32, vlmul: u32) -> u32 {
+        (vsew << 3) | vlmul
+    }
+
+    #[test]
+    fn vlmax() {
+        let v = VectorUnit::new(128);
+        assert_eq!(v.vlmax(vtype(2, 0)), Some(4));
+        assert_eq!(v.vlmax(vtype(0, 3)), Some(128));
+        assert_eq!(v.vlmax(vtype(0, 7)), Some(8));
+        // e32 with lmul=1/2 can't hold a single element of the widest type
+        assert_eq!(v.vlmax(vtype(2, 7)), None);
+        // elen is 32 and the lmul encoding 4 is reserved
+        assert_eq!(v.vlmax(vtype(3, 0)), None);
+        assert_eq!(v.vlmax(vtype(0, 4)), None);
+    }
+
+    #[test]
+    fn element_ops() {
+        assert_eq!(VOp::Add.apply(0xff, 1, 8), 0);
+        assert_eq!(VOp::Min.apply(0x80, 1, 8), 0x80);
+        assert_eq!(VOp::Minu.apply(0x80, 1, 8), 1);
+        assert_eq!(VOp::Sra.apply(0x8000, 4, 16), 0xf800);
+        assert_eq!(VOp::Mulh.apply(0xff, 0xff, 8), 0);
+        assert_eq!(VOp::Div.apply(0x80, 0xff, 8), 0x80);
+        assert_eq!(VOp::Divu.apply(5, 0, 16), 0xffff);
+        assert!(VCmp::Lt.apply(0xffff_ffff, 0, 32));
+        assert!(!VCmp::Ltu.apply(0xffff_ffff, 0, 32));
+    }
+
+    #[test]
+    fn register_groups() {
+        let mut v = VectorUnit::new(64);
+        // elements past the first register continue in the next one
+        v.set_elem(2, 3, 4, 0xdead_beef);
+        assert_eq!(v.elem(3, 1, 4), 0xdead_beef);
+        v.set_mask_bit(0, 9, true);
+        assert!(v.active(false, 9) && !v.active(false, 8));
+        assert!(VectorUnit::valid_group(4, 32) && !VectorUnit::valid_group(2, 32));
+    }
+}
@@ -0,0 +1,306 @@
+// Breakpoints and watchpoints set by an external debugger. Unlike the guest's debug triggers they
+// don't raise an exception but stop the emulation before the instruction executes, like gdb
+// expects from risc-v targets whose watchpoints fire before the access.
+use std::cell::Cell;
+
+use crate::cpu::Cpu;
+use crate::expr::Expr;
+use crate::trigger::Access;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum WatchKind {
+    Write,
+    Read,
+    // reads and writes
+    Access,
+}
+
+impl WatchKind {
+    fn matches(self, access: Access) -> bool {
+        matches!(
+            (self, access),
+            (_, Access::LoadStore)
+                | (WatchKind::Access, _)
+                | (WatchKind::Write, Access::Store)
+                | (WatchKind::Read, Access::Load)
+        )
+    }
+}
+
+// why the debugger stopped the emulation
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DebugStop {
+    Breakpoint(u32),
+    // the kind of the watchpoint that fired and the accessed address
+    Watchpoint(WatchKind, u32),
+    // index of the condition that became true
+    Condition(usize),
+}
+
+// A breakpoint given as `<location> [if <expr>] [hit <n>]`, e.g. `main if a0 == 5` or
+// `0x80000040 hit 100`. The location is a symbol or an expression evaluated once the program is
+// loaded.
+#[derive(Clone, Debug)]
+pub struct BreakSpec {
+    pub location: String,
+    pub condition: Option<Expr>,
+    // the breakpoint only stops from the n-th time its condition held on
+    pub count: Option<u64>,
+}
+
+impl BreakSpec {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (rest, count) = match spec.rsplit_once(" hit ") {
+            Some((rest, count)) => match count.trim().parse() {
+                Ok(count) => (rest, Some(count)),
+                Err(_) => return Err(format!("invalid hit count '{}'", count.trim())),
+            },
+            None => (spec, None),
+        };
+        let (location, condition) = match rest.split_once(" if ") {
+            Some((location, condition)) => (location, Some(Expr::parse(condition)?)),
+            None => (rest, None),
+        };
+        let location = location.trim();
+        if location.is_empty() {
+            return Err("missing breakpoint location".to_string());
+        }
+        Ok(BreakSpec {
+            location: location.to_string(),
+            condition,
+            count,
+        })
+    }
+
+    // the address of the location in the loaded program
+    pub fn resolve(&self, cpu: &Cpu) -> Result<u32, String> {
+        match cpu.symbols.get(&self.location) {
+            Some(&address) => Ok(address),
+            None => Ok(Expr::parse(&self.location)?.eval(cpu)? as u32),
+        }
+    }
+}
+
+// a breakpoint with a condition or hit count, `hits` counts the times its condition held
+struct CountedBreakpoint {
+    address: u32,
+    condition: Option<Expr>,
+    count: u64,
+    hits: Cell<u64>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct Watchpoint {
+    kind: WatchKind,
+    address: u32,
+    len: u32,
+}
+
+pub struct Watchpoints {
+    breakpoints: Vec<u32>,
+    counted_breakpoints: Vec<CountedBreakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    // expressions that stop the emulation when they become nonzero, with their last result
+    conditions: Vec<(Expr, Cell<bool>)>,
+}
+
+impl Watchpoints {
+    pub fn new() -> Self {
+        Watchpoints {
+            breakpoints: Vec::new(),
+            counted_breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            conditions: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.breakpoints.is_empty()
+            && self.counted_breakpoints.is_empty()
+            && self.watchpoints.is_empty()
+            && self.conditions.is_empty()
+    }
+
+    pub fn add_breakpoint(&mut self, address: u32) {
+        if !self.breakpoints.contains(&address) {
+            self.breakpoints.push(address);
+        }
+    }
+
+    // returns false if there was no breakpoint at the address
+    pub fn remove_breakpoint(&mut self, address: u32) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|&breakpoint| breakpoint != address);
+        self.breakpoints.len() != len
+    }
+
+    // true if a breakpoint, with or without condition, is set at the address
+    pub fn has_breakpoint(&self, address: u32) -> bool {
+        self.breakpoints.contains(&address)
+            || self
+                .counted_breakpoints
+                .iter()
+                .any(|breakpoint| breakpoint.address == address)
+    }
+
+    pub fn add_watchpoint(&mut self, kind: WatchKind, address: u32, len: u32) {
+        let watchpoint = Watchpoint { kind, address, len };
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+    }
+
+    // returns false if there was no such watchpoint
+    pub fn remove_watchpoint(&mut self, kind: WatchKind, address: u32, len: u32) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints
+            .retain(|&watchpoint| watchpoint != Watchpoint { kind, address, len });
+        self.watchpoints.len() != count
+    }
+
+    // a breakpoint without condition or count is a plain breakpoint
+    pub fn add_break_spec(&mut self, address: u32, spec: &BreakSpec) {
+        if spec.condition.is_none() && spec.count.is_none() {
+            return self.add_breakpoint(address);
+        }
+        self.counted_breakpoints.push(CountedBreakpoint {
+            address,
+            condition: spec.condition.clone(),
+            count: spec.count.unwrap_or(1),
+            hits: Cell::new(0),
+        });
+    }
+
+    pub fn add_condition(&mut self, condition: Expr) {
+        self.conditions.push((condition, Cell::new(false)));
+    }
+
+    pub fn condition(&self, index: usize) -> Option<&Expr> {
+        self.conditions.get(index).map(|(condition, _)| condition)
+    }
+
+    // whether a breakpoint or condition stops the emulation before the instruction at the pc
+    pub fn check_execute(&self, cpu: &Cpu, pc: u32) -> Option<DebugStop> {
+        let counted = self.check_counted(cpu, pc);
+        let condition = self.check_conditions(cpu);
+        self.breakpoints
+            .contains(&pc)
+            .then_some(DebugStop::Breakpoint(pc))
+            .or(counted)
+            .or(condition)
+    }
+
+    // counts the hits of all breakpoints at the pc whose condition holds
+    fn check_counted(&self, cpu: &Cpu, pc: u32) -> Option<DebugStop> {
+        let mut stop = None;
+        for breakpoint in self.counted_breakpoints.iter().filter(|b| b.address == pc) {
+            let holds = breakpoint
+                .condition
+                .as_ref()
+                .is_none_or(|condition| condition.eval(cpu).is_ok_and(|value| value != 0));
+            if holds {
+                breakpoint.hits.set(breakpoint.hits.get() + 1);
+                if breakpoint.hits.get() >= breakpoint.count {
+                    stop = Some(DebugStop::Breakpoint(pc));
+                }
+            }
+        }
+        stop
+    }
+
+    // The first condition that became true since the last check. Conditions that stay true
+    // don't stop again, conditions that can't be evaluated, e.g. reads outside of ram, are false.
+    fn check_conditions(&self, cpu: &Cpu) -> Option<DebugStop> {
+        let mut stop = None;
+        for (index, (condition, was_true)) in self.conditions.iter().enumerate() {
+            let holds = condition.eval(cpu).is_ok_and(|value| value != 0);
+            if holds && !was_true.replace(holds) {
+                stop = stop.or(Some(DebugStop::Condition(index)));
+            }
+            was_true.set(holds);
+        }
+        stop
+    }
+
+    // the first watchpoint overlapping the `size` bytes accessed at the address
+    pub fn check_access(&self, access: Access, address: u32, size: u32) -> Option<DebugStop> {
+        let end = address as u64 + size as u64;
+        self.watchpoints
+            .iter()
+            .find(|watchpoint| {
+                watchpoint.kind.matches(access)
+                    && (address as u64) < watchpoint.address as u64 + watchpoint.len as u64
+                    && (watchpoint.address as u64) < end
+            })
+            .map(|watchpoint| DebugStop::Watchpoint(watchpoint.kind, address))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regs::Reg;
+
+    #[test]
+    fn watch_ranges() {
+        let mut points = Watchpoints::new();
+        assert!(points.is_empty());
+        points.add_watchpoint(WatchKind::Write, 0x100, 4);
+        points.add_watchpoint(WatchKind::Read, 0x200, 1);
+
+        assert_eq!(points.check_access(Access::Store, 0xfc, 4), None);
+        assert_eq!(
+            points.check_access(Access::Store, 0xfe, 4),
+            Some(DebugStop::Watchpoint(WatchKind::Write, 0xfe))
+        );
+        assert_eq!(points.check_access(Access::Load, 0x100, 4), None);
+        assert_eq!(
+            points.check_access(Access::LoadStore, 0x200, 4),
+            Some(DebugStop::Watchpoint(WatchKind::Read, 0x200))
+        );
+        assert!(points.remove_watchpoint(WatchKind::Read, 0x200, 1));
+        assert!(!points.remove_watchpoint(WatchKind::Read, 0x200, 1));
+        assert_eq!(points.check_access(Access::Load, 0x200, 4), None);
+
+        let cpu = Cpu::new(false);
+        points.add_breakpoint(0x40);
+        assert_eq!(
+            points.check_execute(&cpu, 0x40),
+            Some(DebugStop::Breakpoint(0x40))
+        );
+        assert!(points.remove_breakpoint(0x40));
+        assert_eq!(points.check_execute(&cpu, 0x40), None);
+    }
+
+    #[test]
+    fn conditional_and_counted_breakpoints() {
+        let mut cpu = Cpu::new(false);
+        cpu.symbols.insert("main".to_string(), 0x40);
+        let mut points = Watchpoints::new();
+        let spec = BreakSpec::parse("main if a0 == 5").unwrap();
+        points.add_break_spec(spec.resolve(&cpu).unwrap(), &spec);
+        let spec = BreakSpec::parse("0x20 + 0x60 hit 3").unwrap();
+        assert_eq!(spec.count, Some(3));
+        points.add_break_spec(spec.resolve(&cpu).unwrap(), &spec);
+
+        assert_eq!(points.check_execute(&cpu, 0x40), None);
+        cpu.regs.set(Reg::A0, 5);
+        assert_eq!(
+            points.check_execute(&cpu, 0x40),
+            Some(DebugStop::Breakpoint(0x40))
+        );
+        assert_eq!(points.check_execute(&cpu, 0x44), None);
+
+        assert_eq!(points.check_execute(&cpu, 0x80), None);
+        assert_eq!(points.check_execute(&cpu, 0x80), None);
+        assert_eq!(
+            points.check_execute(&cpu, 0x80),
+            Some(DebugStop::Breakpoint(0x80))
+        );
+
+        assert!(BreakSpec::parse(" if a0").is_err());
+        assert!(BreakSpec::parse("main hit x").is_err());
+        assert!(BreakSpec::parse("main if a0 ==").is_err());
+        assert!(BreakSpec::parse("missing").unwrap().resolve(&cpu).is_err());
+    }
+}
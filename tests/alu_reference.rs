@@ -0,0 +1,240 @@
+// Differential tests of the register-register and register-immediate arithmetic instructions.
+// Every instruction is executed by the cpu over a grid of edge-case and random operands and the
+// result is compared against the reference semantics below, which are written from the spec
+// without looking at the emulator's implementation.
+use ruscv::cpu::{Cpu, StopReason};
+use ruscv::rng::Rng;
+
+// operands live in a0/a1, the result is written to a2
+const RS1: u32 = 10;
+const RS2: u32 = 11;
+const RD: u32 = 12;
+
+type Reference = fn(u32, u32) -> u32;
+type UnaryReference = fn(u32) -> u32;
+
+#[rustfmt::skip]
+const R_TYPE: &[(&str, u32, Reference)] = &[
+    // rv32i
+    ("add", 0x00000033, |a, b| (a as u64 + b as u64) as u32),
+    ("sub", 0x40000033, |a, b| (a as i64 - b as i64) as u32),
+    ("sll", 0x00001033, |a, b| ((a as u64) << (b % 32)) as u32),
+    ("slt", 0x00002033, |a, b| ((a as i32 as i64) < (b as i32 as i64)) as u32),
+    ("sltu", 0x00003033, |a, b| ((a as u64) < (b as u64)) as u32),
+    ("xor", 0x00004033, |a, b| a ^ b),
+    ("srl", 0x00005033, |a, b| ((a as u64) >> (b % 32)) as u32),
+    ("sra", 0x40005033, |a, b| ((a as i32 as i64) >> (b % 32)) as u32),
+    ("or", 0x00006033, |a, b| a | b),
+    ("and", 0x00007033, |a, b| a & b),
+    // m
+    ("mul", 0x02000033, |a, b| (a as i128 * b as i128) as u32),
+    ("mulh", 0x02001033, |a, b| ((a as i32 as i128 * b as i32 as i128) >> 32) as u32),
+    ("mulhsu", 0x02002033, |a, b| ((a as i32 as i128 * b as i128) >> 32) as u32),
+    ("mulhu", 0x02003033, |a, b| ((a as i128 * b as i128) >> 32) as u32),
+    ("div", 0x02004033, |a, b| match (a as i32 as i64, b as i32 as i64) {
+        (_, 0) => u32::MAX,
+        // the only overflowing division wraps around to the dividend
+        (a, b) => (a / b) as i32 as u32,
+    }),
+    ("divu", 0x02005033, |a, b| a.checked_div(b).unwrap_or(u32::MAX)),
+    ("rem", 0x02006033, |a, b| match (a as i32 as i64, b as i32 as i64) {
+        (a, 0) => a as u32,
+        (a, b) => (a % b) as u32,
+    }),
+    ("remu", 0x02007033, |a, b| a.checked_rem(b).unwrap_or(a)),
+    // zicond
+    ("czero.eqz", 0x0e005033, |a, b| if b == 0 { 0 } else { a }),
+    ("czero.nez", 0x0e007033, |a, b| if b != 0 { 0 } else { a }),
+    // zbkb
+    ("andn", 0x40007033, |a, b| a & !b),
+    ("orn", 0x40006033, |a, b| a | !b),
+    ("xnor", 0x40004033, |a, b| !(a ^ b)),
+    ("rol", 0x60001033, |a, b| rotate_left(a, b % 32)),
+    ("ror", 0x60005033, |a, b| rotate_left(a, (32 - b % 32) % 32)),
+    ("pack", 0x08004033, |a, b| (a & 0xffff) | (b << 16)),
+    ("packh", 0x08007033, |a, b| (a & 0xff) | ((b & 0xff) << 8)),
+];
+
+// the immediate is passed sign-extended as the second operand
+#[rustfmt::skip]
+const I_TYPE: &[(&str, u32, Reference)] = &[
+    ("addi", 0x00000013, |a, imm| (a as u64 + imm as u64) as u32),
+    ("slti", 0x00002013, |a, imm| ((a as i32 as i64) < (imm as i32 as i64)) as u32),
+    ("sltiu", 0x00003013, |a, imm| ((a as u64) < (imm as u64)) as u32),
+    ("xori", 0x00004013, |a, imm| a ^ imm),
+    ("ori", 0x00006013, |a, imm| a | imm),
+    ("andi", 0x00007013, |a, imm| a & imm),
+];
+
+// the shift amount is passed as the second operand
+#[rustfmt::skip]
+const SHIFT_IMM: &[(&str, u32, Reference)] = &[
+    ("slli", 0x00001013, |a, shamt| ((a as u64) << shamt) as u32),
+    ("srli", 0x00005013, |a, shamt| ((a as u64) >> shamt) as u32),
+    ("srai", 0x40005013, |a, shamt| ((a as i32 as i64) >> shamt) as u32),
+    ("rori", 0x60005013, |a, shamt| rotate_left(a, (32 - shamt) % 32)),
+];
+
+// bit permutations without register operand besides rs1
+#[rustfmt::skip]
+const UNARY: &[(&str, u32, UnaryReference)] = &[
+    ("rev8", 0x69805013, |a| {
+        (0..4).fold(0, |rev, byte| rev | ((a >> (8 * byte)) & 0xff) << (24 - 8 * byte))
+    }),
+    ("brev8", 0x68705013, |a| {
+        (0..32).fold(0, |rev, bit| rev | ((a >> bit) & 1) << ((bit / 8) * 8 + 7 - bit % 8))
+    }),
+    // even bits come from the lower half, odd bits from the upper half
+    ("zip", 0x08f01013, |a| {
+        (0..16).fold(0, |zip, i| zip | ((a >> i) & 1) << (2 * i) | ((a >> (i + 16)) & 1) << (2 * i + 1))
+    }),
+    ("unzip", 0x08f05013, |a| {
+        (0..16).fold(0, |unzip, i| unzip | ((a >> (2 * i)) & 1) << i | ((a >> (2 * i + 1)) & 1) << (i + 16))
+    }),
+];
+
+// the bits shifted out of the low word come back in from the high word
+fn rotate_left(value: u32, amount: u32) -> u32 {
+    let wide = (value as u64) << amount;
+    wide as u32 | (wide >> 32) as u32
+}
+
+// seeded, so that failures are reproducible
+fn random_words(seed: u64) -> impl Iterator<Item = u32> {
+    let mut rng = Rng::new(seed);
+    std::iter::repeat_with(move || rng.next_u64() as u32)
+}
+
+// values around the boundaries of signed and unsigned arithmetic plus random ones
+fn operands() -> Vec<u32> {
+    let edges = [
+        0,
+        1,
+        2,
+        u32::MAX,
+        u32::MAX - 1,
+        i32::MIN as u32,
+        i32::MIN as u32 + 1,
+        i32::MAX as u32,
+        i32::MAX as u32 - 1,
+        0xffff,
+        0x1_0000,
+        0x8000,
+        0xffff_8000,
+        0x5555_5555,
+        0xaaaa_aaaa,
+        31,
+        32,
+        33,
+    ];
+    edges
+        .into_iter()
+        .chain(random_words(0x2545_f491).take(24))
+        .collect()
+}
+
+// every 12-bit immediate that is an edge case, sign-extended
+fn immediates() -> Vec<u32> {
+    [
+        0, 1, 2, -1, -2, 2047, -2048, 2046, -2047, 0x555, -0x556, 31, 32,
+    ]
+    .into_iter()
+    .chain(
+        random_words(0x9e37_79b9)
+            .take(16)
+            .map(|word| word as i32 >> 20),
+    )
+    .map(|imm| imm as u32)
+    .collect()
+}
+
+// Executes the instruction with the given operand registers and returns the value written to rd.
+fn execute(cpu: &mut Cpu, raw_inst: u32, a: u32, b: u32) -> u32 {
+    cpu.pc.set(0);
+    cpu.regs.write(RS1 as usize, a);
+    cpu.regs.write(RS2 as usize, b);
+    cpu.regs.write(RD as usize, 0xdead_beef);
+    cpu.mem.write_bytes(0, &raw_inst.to_le_bytes());
+    let result = cpu.step();
+    assert!(
+        matches!(result, Ok(None)),
+        "{raw_inst:#010x} didn't execute: {result:?}"
+    );
+    assert_eq!(cpu.pc.get(), 4);
+    cpu.regs.read(RD as usize)
+}
+
+fn new_cpu() -> Cpu {
+    let mut cpu = Cpu::new(false);
+    cpu.set_quiet();
+    cpu.load(vec![0; 4]);
+    cpu
+}
+
+fn check(name: &str, raw_inst: u32, a: u32, b: u32, actual: u32, expected: u32) {
+    assert_eq!(
+        actual, expected,
+        "{name} ({raw_inst:#010x}) with {a:#010x}, {b:#010x}: got {actual:#010x}, expected {expected:#010x}"
+    );
+}
+
+#[test]
+fn register_register() {
+    let mut cpu = new_cpu();
+    let operands = operands();
+    for &(name, matches, reference) in R_TYPE {
+        let raw_inst = matches | RD << 7 | RS1 << 15 | RS2 << 20;
+        for &a in &operands {
+            for &b in &operands {
+                let actual = execute(&mut cpu, raw_inst, a, b);
+                check(name, raw_inst, a, b, actual, reference(a, b));
+            }
+        }
+    }
+}
+
+#[test]
+fn register_immediate() {
+    let mut cpu = new_cpu();
+    let operands = operands();
+    for &(name, matches, reference) in I_TYPE {
+        for imm in immediates() {
+            let raw_inst = matches | RD << 7 | RS1 << 15 | (imm & 0xfff) << 20;
+            for &a in &operands {
+                let actual = execute(&mut cpu, raw_inst, a, 0);
+                check(name, raw_inst, a, imm, actual, reference(a, imm));
+            }
+        }
+    }
+    for &(name, matches, reference) in SHIFT_IMM {
+        for shamt in 0..32 {
+            let raw_inst = matches | RD << 7 | RS1 << 15 | shamt << 20;
+            for &a in &operands {
+                let actual = execute(&mut cpu, raw_inst, a, 0);
+                check(name, raw_inst, a, shamt, actual, reference(a, shamt));
+            }
+        }
+    }
+    for &(name, matches, reference) in UNARY {
+        let raw_inst = matches | RD << 7 | RS1 << 15;
+        for &a in &operands {
+            let actual = execute(&mut cpu, raw_inst, a, 0);
+            check(name, raw_inst, a, 0, actual, reference(a));
+        }
+    }
+}
+
+// writes to x0 are discarded, whatever the instruction computes
+#[test]
+fn zero_register() {
+    let mut cpu = new_cpu();
+    for &(name, matches, _) in R_TYPE {
+        let raw_inst = matches | RS1 << 15 | RS2 << 20;
+        cpu.pc.set(0);
+        cpu.regs.write(RS1 as usize, u32::MAX);
+        cpu.regs.write(RS2 as usize, 3);
+        cpu.mem.write_bytes(0, &raw_inst.to_le_bytes());
+        assert!(matches!(cpu.step(), Ok(None::<StopReason>)), "{name}");
+        assert_eq!(cpu.regs.read(0), 0, "{name} wrote x0");
+    }
+}
@@ -0,0 +1,58 @@
+// Runs prebuilt rv32im benchmark binaries to completion, these are ignored by default since
+// the binaries aren't part of the repository. Build them for qemu's virt machine (ns16550a uart
+// at 0x10000000, ram at 0x80000000), convert them with `objcopy -O binary` and make them
+// terminate through the sifive_test finisher or the exit syscall, then run:
+// $ COREMARK_BIN=<coremark.bin> DHRYSTONE_BIN=<dhrystone.bin> cargo test --release -- --ignored
+use std::process::Command;
+
+// runs the binary on the virt32 machine and returns what it printed to the uart
+fn run_benchmark(env_var: &str) -> String {
+    let path = std::env::var(env_var).unwrap_or_else(|_| panic!("{env_var} is not set"));
+    let output = Command::new(env!("CARGO_BIN_EXE_ruscv"))
+        .args(["--machine", "virt32", &path])
+        .output()
+        .expect("runs ruscv");
+    assert!(
+        output.status.success(),
+        "benchmark failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+// returns the number following `label` on the first line containing it
+fn parse_score(output: &str, label: &str) -> Option<f64> {
+    let line = output.lines().find(|line| line.contains(label))?;
+    let (_, rest) = line.split_once(label)?;
+    rest.trim_start_matches([' ', ':', '\t'])
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+#[test]
+#[ignore = "requires COREMARK_BIN"]
+fn coremark() {
+    let output = run_benchmark("COREMARK_BIN");
+    assert!(output.contains("Correct operation validated"), "{output}");
+    let score = parse_score(&output, "Iterations/Sec");
+    assert!(score.is_some_and(|score| score > 0.0), "{output}");
+}
+
+#[test]
+#[ignore = "requires DHRYSTONE_BIN"]
+fn dhrystone() {
+    let output = run_benchmark("DHRYSTONE_BIN");
+    let score = parse_score(&output, "Dhrystones per Second");
+    assert!(score.is_some_and(|score| score > 0.0), "{output}");
+}
+
+#[test]
+fn score_parsing() {
+    let output = "CoreMark Size    : 666\nIterations/Sec   : 123.45\n";
+    assert_eq!(parse_score(output, "Iterations/Sec"), Some(123.45));
+    let output = "Dhrystones per Second:                      5000 \n";
+    assert_eq!(parse_score(output, "Dhrystones per Second"), Some(5000.0));
+    assert_eq!(parse_score(output, "Microseconds"), None);
+}
@@ -0,0 +1,134 @@
+// Conformance tests of the conditional branches. The reference table states the comparison of
+// every branch as the spec defines it, strictly-less for blt/bltu and greater-or-equal for
+// bge/bgeu, and each branch is executed over edge-case operands and offsets to check that a taken
+// branch lands exactly on its target and an untaken one on the next instruction.
+use ruscv::cpu::{Cpu, StopReason};
+use ruscv::trap::Exception;
+
+const RS1: u32 = 10;
+const RS2: u32 = 11;
+// branches execute from the middle of ram, so that backward offsets stay inside of it
+const PC: u32 = 0x8000;
+
+type Condition = fn(u32, u32) -> bool;
+
+#[rustfmt::skip]
+const BRANCHES: &[(&str, u32, Condition)] = &[
+    ("beq", 0x00000063, |a, b| a == b),
+    ("bne", 0x00001063, |a, b| a != b),
+    ("blt", 0x00004063, |a, b| (a as i32) < (b as i32)),
+    ("bge", 0x00005063, |a, b| (a as i32) >= (b as i32)),
+    ("bltu", 0x00006063, |a, b| a < b),
+    ("bgeu", 0x00007063, |a, b| a >= b),
+];
+
+// forward, backward, to the next instruction and to the ends of the 13-bit range
+const OFFSETS: [i32; 5] = [8, -16, 4, 0xffc, -0x1000];
+
+// equal operands are where `<` and `<=` disagree, the others flip between signed and unsigned
+const OPERANDS: [u32; 10] = [
+    0,
+    1,
+    2,
+    u32::MAX,
+    u32::MAX - 1,
+    i32::MIN as u32,
+    i32::MIN as u32 + 1,
+    i32::MAX as u32,
+    0x8000,
+    0xffff_8000,
+];
+
+// b-type immediates are scrambled into imm[12|10:5] and imm[4:1|11]
+fn encode(matches: u32, offset: i32) -> u32 {
+    let imm = offset as u32;
+    matches
+        | RS1 << 15
+        | RS2 << 20
+        | (imm >> 12 & 1) << 31
+        | (imm >> 5 & 0x3f) << 25
+        | (imm >> 1 & 0xf) << 8
+        | (imm >> 11 & 1) << 7
+}
+
+// executes the branch at PC with the given operands
+fn step(cpu: &mut Cpu, raw_inst: u32, a: u32, b: u32) -> Result<Option<StopReason>, String> {
+    cpu.pc.set(PC);
+    cpu.regs.write(RS1 as usize, a);
+    cpu.regs.write(RS2 as usize, b);
+    cpu.mem.write_bytes(PC, &raw_inst.to_le_bytes());
+    cpu.step().map_err(|e| format!("{e:?}"))
+}
+
+fn new_cpu() -> Cpu {
+    let mut cpu = Cpu::new(false);
+    cpu.set_quiet();
+    cpu.load(vec![0; 4]);
+    cpu
+}
+
+#[test]
+fn reference_table() {
+    // the comparisons themselves, written out so that a wrong row in the table stands out
+    let cases = [
+        ("blt", 1, 1, false),
+        ("blt", u32::MAX, 0, true),
+        ("bltu", 1, 1, false),
+        ("bltu", 0, u32::MAX, true),
+        ("bge", 1, 1, true),
+        ("bge", 0, u32::MAX, true),
+        ("bgeu", 1, 1, true),
+        ("bgeu", 0, u32::MAX, false),
+    ];
+    for (name, a, b, taken) in cases {
+        let &(_, _, condition) = BRANCHES.iter().find(|branch| branch.0 == name).unwrap();
+        assert_eq!(condition(a, b), taken, "{name} {a:#x}, {b:#x}");
+    }
+}
+
+#[test]
+fn taken_and_fall_through() {
+    let mut cpu = new_cpu();
+    for &(name, matches, condition) in BRANCHES {
+        for offset in OFFSETS {
+            let raw_inst = encode(matches, offset);
+            let target = PC.wrapping_add(offset as u32);
+            for a in OPERANDS {
+                for b in OPERANDS {
+                    let result = step(&mut cpu, raw_inst, a, b);
+                    assert_eq!(result, Ok(None), "{name} ({raw_inst:#010x})");
+                    let expected = if condition(a, b) { target } else { PC + 4 };
+                    assert_eq!(
+                        cpu.pc.get(),
+                        expected,
+                        "{name} {a:#x}, {b:#x} with offset {offset} ({raw_inst:#010x})"
+                    );
+                }
+            }
+        }
+    }
+}
+
+// only taken branches to addresses that aren't 4-byte aligned raise the exception
+#[test]
+fn misaligned_target() {
+    let mut cpu = new_cpu();
+    for &(name, matches, condition) in BRANCHES {
+        let raw_inst = encode(matches, 6);
+        for a in OPERANDS {
+            let b = 1;
+            let result = step(&mut cpu, raw_inst, a, b);
+            if condition(a, b) {
+                let exception = Exception::InstructionAddressMisaligned(PC + 6);
+                assert_eq!(
+                    result,
+                    Ok(Some(StopReason::Trap(exception))),
+                    "{name} {a:#x}"
+                );
+            } else {
+                assert_eq!(result, Ok(None), "{name} {a:#x}");
+                assert_eq!(cpu.pc.get(), PC + 4);
+            }
+        }
+    }
+}
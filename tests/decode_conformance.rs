@@ -0,0 +1,166 @@
+// Cross-checks the decoder against the mask/match pairs of every implemented instruction, as
+// listed in the official riscv-opcodes repository (https://github.com/riscv/riscv-opcodes).
+// An encoding is valid if `raw & mask == match`, every other encoding has to be rejected.
+use ruscv::decode;
+
+// the vector extension has too many encodings to list here, see vector.rs for its decoder
+const VECTOR_OPCODES: [u32; 3] = [0x57, 0x07, 0x27];
+
+#[rustfmt::skip]
+const ENCODINGS: &[(&str, u32, u32)] = &[
+    // rv32i
+    ("lui", 0x0000007f, 0x00000037),
+    ("auipc", 0x0000007f, 0x00000017),
+    ("jal", 0x0000007f, 0x0000006f),
+    ("jalr", 0x0000707f, 0x00000067),
+    ("beq", 0x0000707f, 0x00000063),
+    ("bne", 0x0000707f, 0x00001063),
+    ("blt", 0x0000707f, 0x00004063),
+    ("bge", 0x0000707f, 0x00005063),
+    ("bltu", 0x0000707f, 0x00006063),
+    ("bgeu", 0x0000707f, 0x00007063),
+    ("lb", 0x0000707f, 0x00000003),
+    ("lh", 0x0000707f, 0x00001003),
+    ("lw", 0x0000707f, 0x00002003),
+    ("lbu", 0x0000707f, 0x00004003),
+    ("lhu", 0x0000707f, 0x00005003),
+    ("sb", 0x0000707f, 0x00000023),
+    ("sh", 0x0000707f, 0x00001023),
+    ("sw", 0x0000707f, 0x00002023),
+    ("addi", 0x0000707f, 0x00000013),
+    ("slti", 0x0000707f, 0x00002013),
+    ("sltiu", 0x0000707f, 0x00003013),
+    ("xori", 0x0000707f, 0x00004013),
+    ("ori", 0x0000707f, 0x00006013),
+    ("andi", 0x0000707f, 0x00007013),
+    ("slli", 0xfe00707f, 0x00001013),
+    ("srli", 0xfe00707f, 0x00005013),
+    ("srai", 0xfe00707f, 0x40005013),
+    ("add", 0xfe00707f, 0x00000033),
+    ("sub", 0xfe00707f, 0x40000033),
+    ("sll", 0xfe00707f, 0x00001033),
+    ("slt", 0xfe00707f, 0x00002033),
+    ("sltu", 0xfe00707f, 0x00003033),
+    ("xor", 0xfe00707f, 0x00004033),
+    ("srl", 0xfe00707f, 0x00005033),
+    ("sra", 0xfe00707f, 0x40005033),
+    ("or", 0xfe00707f, 0x00006033),
+    ("and", 0xfe00707f, 0x00007033),
+    ("fence", 0x0000707f, 0x0000000f),
+    ("ecall", 0xffffffff, 0x00000073),
+    ("ebreak", 0xffffffff, 0x00100073),
+    // zifencei
+    ("fence.i", 0x0000707f, 0x0000100f),
+    // zicsr
+    ("csrrw", 0x0000707f, 0x00001073),
+    ("csrrs", 0x0000707f, 0x00002073),
+    ("csrrc", 0x0000707f, 0x00003073),
+    ("csrrwi", 0x0000707f, 0x00005073),
+    ("csrrsi", 0x0000707f, 0x00006073),
+    ("csrrci", 0x0000707f, 0x00007073),
+    // privileged
+    ("mret", 0xffffffff, 0x30200073),
+    ("sret", 0xffffffff, 0x10200073),
+    ("wfi", 0xffffffff, 0x10500073),
+    ("sfence.vma", 0xfe007fff, 0x12000073),
+    // m
+    ("mul", 0xfe00707f, 0x02000033),
+    ("mulh", 0xfe00707f, 0x02001033),
+    ("mulhsu", 0xfe00707f, 0x02002033),
+    ("mulhu", 0xfe00707f, 0x02003033),
+    ("div", 0xfe00707f, 0x02004033),
+    ("divu", 0xfe00707f, 0x02005033),
+    ("rem", 0xfe00707f, 0x02006033),
+    ("remu", 0xfe00707f, 0x02007033),
+    // a
+    ("lr.w", 0xf9f0707f, 0x1000202f),
+    ("sc.w", 0xf800707f, 0x1800202f),
+    ("amoswap.w", 0xf800707f, 0x0800202f),
+    ("amoadd.w", 0xf800707f, 0x0000202f),
+    ("amoxor.w", 0xf800707f, 0x2000202f),
+    ("amoand.w", 0xf800707f, 0x6000202f),
+    ("amoor.w", 0xf800707f, 0x4000202f),
+    ("amomin.w", 0xf800707f, 0x8000202f),
+    ("amomax.w", 0xf800707f, 0xa000202f),
+    ("amominu.w", 0xf800707f, 0xc000202f),
+    ("amomaxu.w", 0xf800707f, 0xe000202f),
+    // zicond
+    ("czero.eqz", 0xfe00707f, 0x0e005033),
+    ("czero.nez", 0xfe00707f, 0x0e007033),
+    // zbkb
+    ("andn", 0xfe00707f, 0x40007033),
+    ("orn", 0xfe00707f, 0x40006033),
+    ("xnor", 0xfe00707f, 0x40004033),
+    ("rol", 0xfe00707f, 0x60001033),
+    ("ror", 0xfe00707f, 0x60005033),
+    ("rori", 0xfe00707f, 0x60005013),
+    ("pack", 0xfe00707f, 0x08004033),
+    ("packh", 0xfe00707f, 0x08007033),
+    ("brev8", 0xfff0707f, 0x68705013),
+    ("rev8", 0xfff0707f, 0x69805013),
+    ("zip", 0xfff0707f, 0x08f01013),
+    ("unzip", 0xfff0707f, 0x08f05013),
+    // zbkx
+    ("xperm4", 0xfe00707f, 0x28002033),
+    ("xperm8", 0xfe00707f, 0x28004033),
+    // zknd, zkne
+    ("aes32dsi", 0x3e00707f, 0x2a000033),
+    ("aes32dsmi", 0x3e00707f, 0x2e000033),
+    ("aes32esi", 0x3e00707f, 0x22000033),
+    ("aes32esmi", 0x3e00707f, 0x26000033),
+];
+
+// xorshift32, deterministic so that failures are reproducible
+fn random_words(mut state: u32) -> impl Iterator<Item = u32> {
+    std::iter::repeat_with(move || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state
+    })
+}
+
+fn is_listed(raw: u32) -> bool {
+    ENCODINGS
+        .iter()
+        .any(|&(_, mask, matches)| raw & mask == matches)
+}
+
+#[test]
+fn listed_encodings_decode() {
+    for &(name, mask, matches) in ENCODINGS {
+        // all operand bits cleared, all set and random ones in between
+        let operands = [0, u32::MAX]
+            .into_iter()
+            .chain(random_words(matches | 1).take(64));
+        for operand in operands {
+            let raw = matches | (operand & !mask);
+            assert!(decode(raw).is_ok(), "{name} ({raw:#010x}) is rejected");
+        }
+    }
+}
+
+#[test]
+fn unlisted_encodings_rejected() {
+    for raw in random_words(0x1234_5678).take(1_000_000) {
+        if is_listed(raw) || VECTOR_OPCODES.contains(&(raw & 0x7f)) {
+            continue;
+        }
+        assert!(decode(raw).is_err(), "{raw:#010x} isn't a valid encoding");
+    }
+}
+
+// random words rarely hit the sparse system and unary encodings, so their neighbours are
+// checked by flipping each bit of every listed encoding
+#[test]
+fn neighbouring_encodings_rejected() {
+    for &(_, _, matches) in ENCODINGS {
+        for bit in 0..32 {
+            let raw = matches ^ (1 << bit);
+            if is_listed(raw) || VECTOR_OPCODES.contains(&(raw & 0x7f)) {
+                continue;
+            }
+            assert!(decode(raw).is_err(), "{raw:#010x} isn't a valid encoding");
+        }
+    }
+}
@@ -0,0 +1,35 @@
+// Boots FreeRTOS' RISC-V-Qemu-virt_GCC blinky demo on the freertos-demo machine, ignored by
+// default since the demo isn't part of the repository. Build the default (blinky) configuration
+// with `make` in FreeRTOS/Demo/RISC-V-Qemu-virt_GCC, then run:
+// $ FREERTOS_DEMO_ELF=<build/RTOSDemo.axf> cargo test --release --test freertos -- --ignored
+// The receiving task only prints once the sending task woke up from its delay and passed it a
+// value through the queue, so the output shows that timer interrupts and context switches work.
+use ruscv::cpu::{Cpu, StopReason};
+use ruscv::devices::RtcClock;
+use ruscv::elf::Elf;
+use ruscv::env::Env;
+use ruscv::machine::Machine;
+
+// the demo sends a value every second, which takes 10 million cycles at the 10 MHz timebase
+const CYCLES: usize = 35_000_000;
+
+#[test]
+#[ignore = "requires FREERTOS_DEMO_ELF"]
+fn blinky() {
+    let path = std::env::var("FREERTOS_DEMO_ELF").expect("FREERTOS_DEMO_ELF is not set");
+    let bytes = std::fs::read(&path).unwrap_or_else(|e| panic!("can't read '{path}': {e}"));
+    let elf = Elf::parse(&bytes).unwrap();
+    let mut cpu = Cpu::new(false);
+    cpu.set_quiet();
+    cpu.mem = Machine::FreertosDemo.memory(RtcClock::Host);
+    // the port yields with ecall, whatever a7 holds
+    cpu.set_env(Env::Bare);
+    cpu.set_cycle_limit(CYCLES);
+    let output = cpu.mem.capture_output();
+
+    let result = cpu.run_elf(&elf);
+    assert!(matches!(result, Ok(StopReason::Limit(_))), "{result:?}");
+    let text = output.text();
+    assert!(text.matches("Blink").count() >= 3, "{text}");
+    assert!(!text.contains("Unexpected value"), "{text}");
+}
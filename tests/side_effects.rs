@@ -0,0 +1,75 @@
+// Checks that instructions change nothing but their documented destinations: every instruction
+// executes once from the start of ram with prepared operands, and the architectural state after
+// it may only differ from the one before in the listed registers, csrs and memory bytes.
+use ruscv::asm;
+use ruscv::cpu::Cpu;
+use ruscv::csr::{MSCRATCH, MSTATUS};
+use ruscv::regs::Reg;
+use ruscv::state::{Allowed, ArchState};
+
+// address of the word the memory instructions access, held in a0
+const DATA: u32 = 0x1000;
+
+fn check(source: &str, allowed: Allowed) {
+    let assembly = asm::assemble(source, 0).unwrap();
+    let mut cpu = Cpu::new(false);
+    cpu.set_quiet();
+    cpu.load(assembly.bytes);
+    cpu.regs.set(Reg::A0, DATA);
+    cpu.regs.set(Reg::A1, 0x8765_4321);
+    cpu.regs.set(Reg::A2, 0xdead_beef);
+    cpu.mem.write_bytes(DATA, &0x1234_5678u32.to_le_bytes());
+    let before = ArchState::capture(&cpu);
+    let result = cpu.step();
+    assert!(matches!(result, Ok(None)), "{source}: {result:?}");
+    ArchState::capture(&cpu).assert_only(&before, &allowed);
+}
+
+fn only(reg: Reg) -> Allowed {
+    Allowed::new().reg(reg)
+}
+
+#[test]
+fn arithmetic_writes_only_rd() {
+    for op in [
+        "add", "sub", "sll", "slt", "sltu", "xor", "srl", "sra", "or", "and", "mul", "mulh",
+        "mulhsu", "mulhu", "div", "divu", "rem", "remu",
+    ] {
+        check(&format!("{op} a3, a1, a2"), only(Reg::A3));
+    }
+    for op in ["addi", "slti", "sltiu", "xori", "ori", "andi"] {
+        check(&format!("{op} a3, a1, -7"), only(Reg::A3));
+    }
+    check("lui a3, 0x12345", only(Reg::A3));
+    check("auipc a3, 1", only(Reg::A3));
+    // writes to x0 are discarded
+    check("add zero, a1, a2", Allowed::new());
+}
+
+#[test]
+fn memory_accesses() {
+    for op in ["lb", "lbu", "lh", "lhu", "lw"] {
+        check(&format!("{op} a3, 0(a0)"), only(Reg::A3));
+    }
+    check("sb a1, 1(a0)", Allowed::new().memory(DATA + 1..DATA + 2));
+    check("sh a1, 2(a0)", Allowed::new().memory(DATA + 2..DATA + 4));
+    check("sw a1, 0(a0)", Allowed::new().memory(DATA..DATA + 4));
+    check("lr.w a3, (a0)", only(Reg::A3));
+    for op in ["amoswap.w", "amoadd.w", "amoxor.w", "amoor.w", "amomaxu.w"] {
+        check(
+            &format!("{op} a3, a1, (a0)"),
+            only(Reg::A3).memory(DATA..DATA + 4),
+        );
+    }
+}
+
+#[test]
+fn control_flow_and_csrs() {
+    check("jal ra, 8", only(Reg::Ra));
+    check("jalr t0, 16(zero)", only(Reg::T0));
+    check("beq a1, a1, 8", Allowed::new());
+    check("csrrw a3, mscratch, a1", only(Reg::A3).csr(MSCRATCH));
+    check("csrrs a3, mstatus, zero", only(Reg::A3));
+    check("csrrsi zero, mstatus, 8", Allowed::new().csr(MSTATUS));
+    check("fence", Allowed::new());
+}
@@ -0,0 +1,42 @@
+// Boots the 32-bit xv6 port on the virt32 machine, ignored by default since the kernel and its file
+// system image aren't part of the repository. Build `kernel/kernel` and `fs.img` of an rv32 xv6
+// port that expects qemu's virt machine with a virtio-mmio version 2 disk, then run:
+// $ XV6_KERNEL=<kernel/kernel> XV6_FS_IMG=<fs.img> cargo test --release --test xv6 -- --ignored
+// The kernel enters supervisor mode with paging enabled, reads init from the disk and runs it in
+// user mode, so the shell starting shows that traps, Sv32 and the virtio disk work.
+use ruscv::cpu::{Cpu, StopReason};
+use ruscv::devices::{RtcClock, VirtioBlock};
+use ruscv::elf::Elf;
+use ruscv::env::Env;
+use ruscv::machine::Machine;
+
+// xv6 is up in well under a second of virtual time
+const CYCLES: usize = 200_000_000;
+
+#[test]
+#[ignore = "requires XV6_KERNEL and XV6_FS_IMG"]
+fn boots_to_shell() {
+    let kernel = std::env::var("XV6_KERNEL").expect("XV6_KERNEL is not set");
+    let fs_img = std::env::var("XV6_FS_IMG").expect("XV6_FS_IMG is not set");
+    let bytes = std::fs::read(&kernel).unwrap_or_else(|e| panic!("can't read '{kernel}': {e}"));
+    let elf = Elf::parse(&bytes).unwrap();
+    // the kernel writes to the file system, so it runs on a copy of the image
+    let dir = tempfile::tempdir().unwrap();
+    let disk = dir.path().join("fs.img");
+    std::fs::copy(&fs_img, &disk).unwrap_or_else(|e| panic!("can't copy '{fs_img}': {e}"));
+
+    let mut cpu = Cpu::new(false);
+    cpu.set_quiet();
+    cpu.mem = Machine::Virt32.memory(RtcClock::Frozen(0));
+    cpu.mem
+        .add_device(Box::new(VirtioBlock::open(&disk).unwrap()));
+    // the kernel handles the ecalls of its processes itself
+    cpu.set_env(Env::Bare);
+    cpu.set_cycle_limit(CYCLES);
+    let output = cpu.mem.capture_output();
+
+    let result = cpu.run_elf(&elf);
+    assert!(matches!(result, Ok(StopReason::Limit(_))), "{result:?}");
+    let text = output.text();
+    assert!(text.contains("init: starting sh"), "{text}");
+}